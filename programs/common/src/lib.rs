@@ -11,6 +11,14 @@ pub const FEE_BPS_DEFAULT: u16 = 5;
 pub const FEATURE_PRIVATE_TRANSFER_ENABLED: u8 = 0x01;
 /// Feature flag enabling hook CPIs.
 pub const FEATURE_HOOKS_ENABLED: u8 = 0x02;
+/// Feature flag enforcing the vault supply invariant at runtime.
+pub const FEATURE_INVARIANT_CHECKS: u8 = 0x04;
+/// Feature flag writing a leaf-index lookup PDA for every appended commitment.
+pub const FEATURE_LEAF_INDEX: u8 = 0x08;
+/// Feature bits the protocol assigns. Pools store only the low byte.
+pub const FEATURE_PROTOCOL_MASK: u64 = 0x0000_0000_ffff_ffff;
+/// Feature bits left to integrators. The programs record them but never read them.
+pub const FEATURE_INTEGRATOR_MASK: u64 = 0xffff_ffff_0000_0000;
 /// Maximum basis points value accepted by the protocol (100%).
 pub const MAX_BPS: u16 = 10_000;
 
@@ -30,6 +38,10 @@ pub mod hooks {
         pub commitment: [u8; 32],
         pub amount_commit: [u8; 32],
         pub amount: u64,
+        /// Leaf the note was appended at, and the pool root and slot afterwards.
+        pub leaf_index: u64,
+        pub root: [u8; 32],
+        pub slot: u64,
     }
 
     /// Payload dispatched after a successful unshield.
@@ -41,6 +53,41 @@ pub mod hooks {
         pub mode: u8,
         pub amount: u64,
         pub fee: u64,
+        /// Leaves the change notes were appended at, in `output_commitments` order;
+        /// empty for emergency exits and lightweight builds.
+        pub change_leaf_indices: Vec<u64>,
+        pub root: [u8; 32],
+        pub slot: u64,
+    }
+
+    /// Payload dispatched before a shield deposits funds; an error aborts the shield.
+    #[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+    pub struct PreShieldHook {
+        pub origin_mint: Pubkey,
+        pub pool: Pubkey,
+        pub depositor: Pubkey,
+        pub amount: u64,
+    }
+
+    /// Payload dispatched before an unshield releases funds; an error aborts the unshield.
+    #[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+    pub struct PreUnshieldHook {
+        pub origin_mint: Pubkey,
+        pub pool: Pubkey,
+        pub destination: Pubkey,
+        pub mode: u8,
+        pub amount: u64,
+        pub fee: u64,
+    }
+
+    /// Payload dispatched after a successful private transfer.
+    #[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+    pub struct PostTransferHook {
+        pub origin_mint: Pubkey,
+        pub pool: Pubkey,
+        pub nullifiers: Vec<[u8; 32]>,
+        pub output_commitments: Vec<[u8; 32]>,
+        pub new_root: [u8; 32],
     }
 
     /// Serialized instruction discriminant for hook dispatch.
@@ -48,6 +95,9 @@ pub mod hooks {
     pub enum HookInstruction {
         PostShield(PostShieldHook),
         PostUnshield(PostUnshieldHook),
+        PreShield(PreShieldHook),
+        PreUnshield(PreUnshieldHook),
+        PostTransfer(PostTransferHook),
     }
 }
 
@@ -62,14 +112,36 @@ pub mod seeds {
     pub const HOOKS: &[u8] = b"hooks";
     pub const VERIFIER: &[u8] = b"vk";
     pub const NULLIFIERS: &[u8] = b"nulls";
+    pub const NULLIFIER: &[u8] = b"null";
     pub const TREE: &[u8] = b"tree";
     pub const TIMELOCK: &[u8] = b"timelock";
+    pub const TIMELOCK_INDEX: &[u8] = b"timelock-index";
     pub const ALLOWANCE: &[u8] = b"allow";
+    pub const ARITY_VERIFIER: &[u8] = b"arity";
+    pub const CONSOLIDATION_VERIFIER: &[u8] = b"consolidate";
+    pub const AGGREGATION_VERIFIER: &[u8] = b"aggregate";
+    pub const SCHEDULED_VERIFIER: &[u8] = b"scheduled";
+    pub const DISCLOSURE_VERIFIER: &[u8] = b"disclosure";
+    pub const RECEIPT: &[u8] = b"receipt";
+    pub const ESCROW: &[u8] = b"escrow";
+    pub const LEAF: &[u8] = b"leaf";
+    pub const CHECKPOINT: &[u8] = b"checkpoint";
+    pub const STATS: &[u8] = b"stats";
+    pub const RATE_LIMIT: &[u8] = b"rate";
+    pub const DESTINATIONS: &[u8] = b"destinations";
+    pub const REFERRAL: &[u8] = b"referral";
+    pub const COUNCIL: &[u8] = b"council";
+    pub const PROPOSAL: &[u8] = b"proposal";
+    pub const PTKN_MINT: &[u8] = b"ptkn-mint";
+    pub const PROTOCOL_CONFIG: &[u8] = b"protocol-config";
+    pub const TEMPLATE: &[u8] = b"template";
+    pub const REGISTRATION_BOND: &[u8] = b"bond";
+    pub const UNWRAP: &[u8] = b"unwrap";
 }
 
 /// Runtime feature flags represented as a bit field.
 #[derive(Clone, Copy, Debug, Default, AnchorSerialize, AnchorDeserialize, Eq, PartialEq)]
-pub struct FeatureFlags(u8);
+pub struct FeatureFlags(u64);
 
 impl FeatureFlags {
     /// Constructs an empty set of flags.
@@ -78,15 +150,20 @@ impl FeatureFlags {
     }
 
     /// Creates flags from raw bits.
-    pub const fn from_bits(bits: u8) -> Self {
+    pub const fn from_bits(bits: u64) -> Self {
         Self(bits)
     }
 
     /// Returns the raw bits.
-    pub const fn bits(self) -> u8 {
+    pub const fn bits(self) -> u64 {
         self.0
     }
 
+    /// Returns the integrator-defined bits, shifted down.
+    pub const fn integrator_bits(self) -> u32 {
+        ((self.0 & FEATURE_INTEGRATOR_MASK) >> 32) as u32
+    }
+
     /// Returns `true` if all bits in `other` are contained in `self`.
     pub const fn contains(self, other: FeatureFlags) -> bool {
         (self.0 & other.0) == other.0
@@ -105,19 +182,25 @@ impl FeatureFlags {
 
 impl From<u8> for FeatureFlags {
     fn from(value: u8) -> FeatureFlags {
+        FeatureFlags::from_bits(value as u64)
+    }
+}
+
+impl From<u64> for FeatureFlags {
+    fn from(value: u64) -> FeatureFlags {
         FeatureFlags::from_bits(value)
     }
 }
 
-impl From<FeatureFlags> for u8 {
-    fn from(value: FeatureFlags) -> u8 {
+impl From<FeatureFlags> for u64 {
+    fn from(value: FeatureFlags) -> u64 {
         value.bits()
     }
 }
 
 impl core::fmt::Display for FeatureFlags {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(f, "0x{:02x}", self.0)
+        write!(f, "0x{:016x}", self.0)
     }
 }
 
@@ -141,11 +224,20 @@ mod tests {
 
     #[test]
     fn feature_flag_combinations() {
-        let mut flags = FeatureFlags::from_bits(FEATURE_PRIVATE_TRANSFER_ENABLED);
-        assert!(flags.contains(FeatureFlags::from_bits(FEATURE_PRIVATE_TRANSFER_ENABLED)));
-        flags.insert(FeatureFlags::from_bits(FEATURE_HOOKS_ENABLED));
-        assert!(flags.contains(FeatureFlags::from_bits(FEATURE_HOOKS_ENABLED)));
-        flags.remove(FeatureFlags::from_bits(FEATURE_HOOKS_ENABLED));
-        assert!(!flags.contains(FeatureFlags::from_bits(FEATURE_HOOKS_ENABLED)));
+        let mut flags = FeatureFlags::from(FEATURE_PRIVATE_TRANSFER_ENABLED);
+        assert!(flags.contains(FeatureFlags::from(FEATURE_PRIVATE_TRANSFER_ENABLED)));
+        flags.insert(FeatureFlags::from(FEATURE_HOOKS_ENABLED));
+        assert!(flags.contains(FeatureFlags::from(FEATURE_HOOKS_ENABLED)));
+        flags.remove(FeatureFlags::from(FEATURE_HOOKS_ENABLED));
+        assert!(!flags.contains(FeatureFlags::from(FEATURE_HOOKS_ENABLED)));
+    }
+
+    #[test]
+    fn integrator_bits_sit_above_protocol_bits() {
+        assert_eq!(FEATURE_PROTOCOL_MASK & FEATURE_INTEGRATOR_MASK, 0);
+        assert_eq!(FEATURE_PROTOCOL_MASK | FEATURE_INTEGRATOR_MASK, u64::MAX);
+        let flags = FeatureFlags::from_bits((0xabcd << 32) | FEATURE_HOOKS_ENABLED as u64);
+        assert_eq!(flags.integrator_bits(), 0xabcd);
+        assert!(flags.contains(FeatureFlags::from(FEATURE_HOOKS_ENABLED)));
     }
 }
@@ -1,18 +1,59 @@
 use anchor_lang::prelude::*;
+use anchor_spl::associated_token::{self, AssociatedToken};
 use anchor_spl::token_interface::{
     self as token_interface,
-    spl_token_2022::{self, instruction::AuthorityType},
-    Mint, MintTo, SetAuthority, TokenAccount, TokenInterface,
+    spl_pod::optional_keys::OptionalNonZeroPubkey,
+    spl_token_2022::{
+        self,
+        extension::{BaseStateWithExtensions, ExtensionType, StateWithExtensions},
+        instruction::AuthorityType,
+    },
+    spl_token_metadata_interface::state::{Field, TokenMetadata},
+    Burn, MetadataPointerInitialize, Mint, MintTo, NonTransferableMintInitialize, SetAuthority,
+    TokenAccount, TokenInterface, TokenMetadataInitialize, TokenMetadataUpdateField,
 };
+use solana_program::instruction::{AccountMeta, Instruction};
 use solana_program::program_option::COption;
 use solana_program::program_pack::Pack as Token2022Pack;
-use solana_program::{hash::hashv, program::invoke, system_instruction, system_program};
+use solana_program::{
+    hash::hashv,
+    program::{invoke, invoke_signed},
+    system_instruction, system_program,
+};
 use spl_token_2022::state::Mint as Token2022Mint;
 
-use ptf_common::{seeds, FeatureFlags, MAX_BPS};
+use ptf_common::{seeds, FeatureFlags, FEATURE_HOOKS_ENABLED, MAX_BPS};
+use ptf_vault::program::PtfVault;
+use ptf_verifier_groth16::{program::PtfVerifierGroth16, VerifyingKeyAccount};
 use solana_program::pubkey;
 
 const PTF_POOL_PROGRAM_ID: Pubkey = pubkey!("7kbUWzeTPY6qb1mFJC1ZMRmTZAdaHC27yukc3Czj7fKh");
+/// SPL Governance, whose governance native treasuries may hold the factory authority.
+pub const SPL_GOVERNANCE_PROGRAM_ID: Pubkey =
+    pubkey!("GovER5Lthms3bLBqWub97yVrMmEogzX7xNjdXpPPCVZw");
+/// Metaplex Token Metadata, which wallets read token names and symbols from.
+pub const TOKEN_METADATA_PROGRAM_ID: Pubkey =
+    pubkey!("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
+/// Seed prefix of a Metaplex metadata account, `["metadata", program, mint]`.
+const TOKEN_METADATA_SEED: &[u8] = b"metadata";
+/// `ptkn_extensions` bit: create the pTKN mint under token-2022 with its metadata stored in
+/// the mint, behind a metadata pointer to itself.
+pub const PTKN_EXTENSION_METADATA: u8 = 0x01;
+/// `ptkn_extensions` bit: create the pTKN mint under token-2022 as non-transferable, so
+/// holders can only receive it from the pool and burn it back.
+pub const PTKN_EXTENSION_NON_TRANSFERABLE: u8 = 0x02;
+const PTKN_EXTENSIONS_ALL: u8 = PTKN_EXTENSION_METADATA | PTKN_EXTENSION_NON_TRANSFERABLE;
+/// `paused_scopes` bit: halts `register_mint` and `register_and_deploy`.
+pub const PAUSE_REGISTRATION: u8 = 0x01;
+/// `paused_scopes` bit: halts `update_mint`, direct or through the timelock.
+pub const PAUSE_MINT_UPDATES: u8 = 0x02;
+/// `paused_scopes` bit: halts `mint_ptkn`. Burning stays open so holders can exit.
+pub const PAUSE_PTKN_MINTING: u8 = 0x04;
+const PAUSE_SCOPES_ALL: u8 = PAUSE_REGISTRATION | PAUSE_MINT_UPDATES | PAUSE_PTKN_MINTING;
+/// Largest origin mint decimals a factory accepts until `set_max_decimals` changes it.
+pub const DEFAULT_MAX_DECIMALS: u8 = 12;
+/// Seed prefix of an SPL Governance native treasury, `["native-treasury", governance]`.
+const GOVERNANCE_NATIVE_TREASURY: &[u8] = b"native-treasury";
 
 declare_id!("4z618BY2dXGqAUiegqDt8omo3e81TSdXRHt64ikX1bTy");
 
@@ -36,6 +77,8 @@ pub mod ptf_factory {
         state.timelock_seconds = timelock_seconds;
         state.bump = ctx.bumps.factory_state;
         state.last_updated_slot = Clock::get()?.slot;
+        state.pool_programs[0] = PTF_POOL_PROGRAM_ID;
+        state.pool_programs_len = 1;
 
         emit!(FactoryInitialized {
             authority,
@@ -47,7 +90,7 @@ pub mod ptf_factory {
 
     pub fn set_default_features(
         ctx: Context<UpdateFactoryAuthority>,
-        default_features: u8,
+        default_features: u64,
     ) -> Result<()> {
         let state = &mut ctx.accounts.factory_state;
         ensure_direct_update_allowed(state)?;
@@ -60,78 +103,528 @@ pub mod ptf_factory {
         Ok(())
     }
 
+    /// Creates or replaces registration template `template_id`, a named parameter bundle
+    /// `register_mint` and `register_and_deploy` apply in place of their own parameters.
+    pub fn set_registration_template(
+        ctx: Context<SetRegistrationTemplate>,
+        template_id: u32,
+        name: [u8; 32],
+        params: RegistrationTemplateParams,
+    ) -> Result<()> {
+        params.validate()?;
+        let template = &mut ctx.accounts.template;
+        template.factory = ctx.accounts.factory_state.key();
+        template.template_id = template_id;
+        template.name = name;
+        template.params = params;
+        template.bump = ctx.bumps.template;
+        emit!(RegistrationTemplateSet {
+            template: template.key(),
+            template_id,
+            name,
+        });
+        Ok(())
+    }
+
+    /// Removes a registration template and refunds its rent to the authority. Mints
+    /// registered from it keep their parameters.
+    pub fn close_registration_template(ctx: Context<CloseRegistrationTemplate>) -> Result<()> {
+        emit!(RegistrationTemplateClosed {
+            template: ctx.accounts.template.key(),
+            template_id: ctx.accounts.template.template_id,
+        });
+        Ok(())
+    }
+
+    /// Registers `origin_mint`. With the optional `template` account, the template's
+    /// parameters replace `enable_ptkn`, `feature_flags`, `fee_bps_override` and
+    /// `ptkn_extensions`.
     pub fn register_mint(
         ctx: Context<RegisterMint>,
         decimals: u8,
         enable_ptkn: bool,
-        feature_flags: Option<u8>,
+        feature_flags: Option<u64>,
         fee_bps_override: Option<u16>,
+        ptkn_extensions: u8,
     ) -> Result<()> {
-        let state = &mut ctx.accounts.factory_state;
-        require!(!state.paused, FactoryError::Paused);
-        require!(decimals <= 12, FactoryError::InvalidDecimals);
-        if let Some(fee) = fee_bps_override {
-            require!(fee <= MAX_BPS, FactoryError::InvalidFeeBps);
+        // `decimals` stays in the signature for existing clients but must agree with the
+        // mint itself.
+        require!(
+            decimals == ctx.accounts.origin_mint.decimals,
+            FactoryError::InvalidDecimals
+        );
+        let (enable_ptkn, feature_flags, fee_bps_override, ptkn_extensions, template) =
+            match ctx.accounts.template.as_ref() {
+                Some(template) => (
+                    template.params.enable_ptkn,
+                    template.params.feature_flags,
+                    template.params.fee_bps_override,
+                    template.params.ptkn_extensions,
+                    template.key(),
+                ),
+                None => (
+                    enable_ptkn,
+                    feature_flags,
+                    fee_bps_override,
+                    ptkn_extensions,
+                    Pubkey::default(),
+                ),
+            };
+        let creates_ptkn_mint = enable_ptkn
+            && ctx
+                .accounts
+                .ptkn_mint
+                .as_ref()
+                .is_some_and(|mint| *mint.owner == system_program::ID && mint.data_is_empty());
+        let origin_metadata = ctx
+            .accounts
+            .origin_metadata
+            .as_ref()
+            .map(|account| read_token_metadata(account, &ctx.accounts.origin_mint.key()))
+            .transpose()?;
+        let metadata = PtknMetadata::for_origin(&ctx.accounts.origin_mint.key(), origin_metadata);
+        register_mapping(
+            &ctx.accounts.factory_state,
+            &mut ctx.accounts.mint_mapping,
+            ctx.accounts.origin_mint.key(),
+            ctx.bumps.mint_mapping,
+            RegisterMintParams {
+                decimals,
+                enable_ptkn,
+                feature_flags,
+                fee_bps_override,
+                template,
+            },
+            ctx.accounts.ptkn_mint.as_ref(),
+            ctx.accounts.token_program.as_ref(),
+            &ctx.accounts.rent,
+            &ctx.accounts.payer,
+            &ctx.accounts.authority,
+            Some(&PtknMintExtensions {
+                bits: ptkn_extensions,
+                metadata: metadata.clone(),
+            }),
+        )?;
+        emit_cpi!(mint_registered_event(
+            &ctx.accounts.factory_state,
+            &ctx.accounts.mint_mapping,
+            ctx.accounts.origin_mint.to_account_info().owner,
+            ctx.accounts.authority.key(),
+        ));
+        if let Some(event) = collect_registration_fee(
+            &mut ctx.accounts.factory_state,
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+            ctx.accounts.origin_mint.key(),
+        )? {
+            emit_cpi!(event);
+        }
+
+        // A freshly created twin gets wallet metadata when the caller passes the
+        // metadata accounts; older clients that omit them register as before. Twins
+        // that carry their metadata in the mint already have it.
+        if let (true, Some(ptkn_metadata), Some(metadata_program)) = (
+            creates_ptkn_mint && ptkn_extensions & PTKN_EXTENSION_METADATA == 0,
+            ctx.accounts.ptkn_metadata.as_ref(),
+            ctx.accounts.token_metadata_program.as_ref(),
+        ) {
+            let ptkn_mint = ctx
+                .accounts
+                .ptkn_mint
+                .as_ref()
+                .ok_or(FactoryError::PtknMintMissing)?;
+            write_ptkn_metadata(
+                &ctx.accounts.factory_state,
+                ptkn_mint,
+                ptkn_metadata,
+                metadata_program,
+                &ctx.accounts.payer,
+                &ctx.accounts.system_program,
+                &ctx.accounts.mint_mapping.origin_mint,
+                &metadata,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Registers a mint without the authority by posting `registration_bond_lamports`
+    /// into the mint's bond PDA. The mapping takes the factory defaults, or the
+    /// parameters of the optional template, and never gets a pTKN twin here. It stays
+    /// frozen until the authority refunds the bond.
+    pub fn register_mint_bonded(ctx: Context<RegisterMintBonded>) -> Result<()> {
+        let bond_lamports = ctx.accounts.factory_state.registration_bond_lamports;
+        require!(
+            bond_lamports > 0,
+            FactoryError::PermissionlessRegistrationDisabled
+        );
+        let origin_mint = ctx.accounts.origin_mint.key();
+        let (feature_flags, fee_bps_override, template) = match ctx.accounts.template.as_ref() {
+            Some(template) => (
+                template.params.feature_flags,
+                template.params.fee_bps_override,
+                template.key(),
+            ),
+            None => (None, None, Pubkey::default()),
+        };
+        register_mapping(
+            &ctx.accounts.factory_state,
+            &mut ctx.accounts.mint_mapping,
+            origin_mint,
+            ctx.bumps.mint_mapping,
+            RegisterMintParams {
+                decimals: ctx.accounts.origin_mint.decimals,
+                enable_ptkn: false,
+                feature_flags,
+                fee_bps_override,
+                template,
+            },
+            None,
+            None,
+            &ctx.accounts.rent,
+            &ctx.accounts.registrant,
+            &ctx.accounts.registrant,
+            None,
+        )?;
+        ctx.accounts.mint_mapping.status = MintStatus::Frozen as u8;
+        emit_cpi!(mint_registered_event(
+            &ctx.accounts.factory_state,
+            &ctx.accounts.mint_mapping,
+            ctx.accounts.origin_mint.to_account_info().owner,
+            ctx.accounts.registrant.key(),
+        ));
+        if let Some(event) = collect_registration_fee(
+            &mut ctx.accounts.factory_state,
+            &ctx.accounts.registrant,
+            &ctx.accounts.system_program,
+            origin_mint,
+        )? {
+            emit_cpi!(event);
         }
 
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.registrant.to_account_info(),
+                    to: ctx.accounts.bond.to_account_info(),
+                },
+            ),
+            bond_lamports,
+        )?;
+        let bond = &mut ctx.accounts.bond;
+        bond.factory = ctx.accounts.factory_state.key();
+        bond.origin_mint = origin_mint;
+        bond.registrant = ctx.accounts.registrant.key();
+        bond.lamports = bond_lamports;
+        bond.posted_at = Clock::get()?.unix_timestamp;
+        bond.bump = ctx.bumps.bond;
+        emit_cpi!(RegistrationBondPosted {
+            origin_mint,
+            registrant: ctx.accounts.registrant.key(),
+            lamports: bond_lamports,
+        });
+        Ok(())
+    }
+
+    /// Returns a reviewed registration's bond, and the bond account's rent, to the
+    /// registrant, and thaws the mapping.
+    pub fn refund_registration_bond(ctx: Context<ResolveRegistrationBond>) -> Result<()> {
         let mapping = &mut ctx.accounts.mint_mapping;
-        mapping.origin_mint = ctx.accounts.origin_mint.key();
         mapping.status = MintStatus::Active as u8;
-        mapping.decimals = decimals;
-        mapping.features =
-            FeatureFlags::from(feature_flags.unwrap_or_else(|| state.default_features.bits()));
-        mapping.has_fee_override = fee_bps_override.is_some();
-        mapping.fee_bps_override = fee_bps_override.unwrap_or_default();
-        mapping.bump = ctx.bumps.mint_mapping;
-        mapping.has_ptkn = false;
-        mapping.ptkn_mint = Pubkey::default();
+        emit!(MintThawed {
+            origin_mint: mapping.origin_mint,
+            authority: ctx.accounts.authority.key(),
+        });
+        let bond = &ctx.accounts.bond;
+        emit!(RegistrationBondRefunded {
+            origin_mint: bond.origin_mint,
+            registrant: bond.registrant,
+            lamports: bond.lamports,
+        });
+        Ok(())
+    }
+
+    /// Moves a rejected registration's bond into the collected fees and freezes the
+    /// mapping, which `close_mint_mapping` can then remove. The bond account's rent still
+    /// goes back to the registrant.
+    pub fn slash_registration_bond(ctx: Context<ResolveRegistrationBond>) -> Result<()> {
+        let lamports = ctx.accounts.bond.lamports;
+        let bond_info = ctx.accounts.bond.to_account_info();
+        let state_info = ctx.accounts.factory_state.to_account_info();
+        **bond_info.try_borrow_mut_lamports()? = bond_info
+            .lamports()
+            .checked_sub(lamports)
+            .ok_or(FactoryError::AmountOverflow)?;
+        **state_info.try_borrow_mut_lamports()? = state_info
+            .lamports()
+            .checked_add(lamports)
+            .ok_or(FactoryError::AmountOverflow)?;
+        let state = &mut ctx.accounts.factory_state;
+        state.treasury_balance = state
+            .treasury_balance
+            .checked_add(lamports)
+            .ok_or(FactoryError::AmountOverflow)?;
+        let mapping = &mut ctx.accounts.mint_mapping;
+        mapping.status = MintStatus::Frozen as u8;
+        emit!(MintFrozen {
+            origin_mint: mapping.origin_mint,
+            authority: ctx.accounts.authority.key(),
+        });
+        emit!(RegistrationBondSlashed {
+            origin_mint: ctx.accounts.bond.origin_mint,
+            registrant: ctx.accounts.bond.registrant,
+            lamports,
+        });
+        Ok(())
+    }
 
-        let effective_fee_bps = fee_bps_override.unwrap_or(state.default_fee_bps);
+    /// Sets the pTKN mint's Metaplex name, symbol and URI, creating the metadata account
+    /// for twins registered without one.
+    pub fn update_ptkn_metadata(
+        ctx: Context<UpdatePtknMetadata>,
+        metadata: PtknMetadata,
+    ) -> Result<()> {
+        metadata.validate()?;
+        require!(
+            ctx.accounts.mint_mapping.has_ptkn,
+            FactoryError::PtknMintDisabled
+        );
+        if ctx.accounts.mint_mapping.ptkn_extensions & PTKN_EXTENSION_METADATA != 0 {
+            return write_in_mint_metadata(
+                &ctx.accounts.factory_state,
+                &ctx.accounts.ptkn_mint,
+                &ctx.accounts.ptkn_metadata,
+                &ctx.accounts.token_metadata_program,
+                &ctx.accounts.payer,
+                &ctx.accounts.system_program,
+                &ctx.accounts.mint_mapping.origin_mint,
+                &metadata,
+            );
+        }
+        require_keys_eq!(
+            ctx.accounts.token_metadata_program.key(),
+            TOKEN_METADATA_PROGRAM_ID,
+            FactoryError::PtknMetadataInvalid
+        );
+        write_ptkn_metadata(
+            &ctx.accounts.factory_state,
+            &ctx.accounts.ptkn_mint,
+            &ctx.accounts.ptkn_metadata,
+            &ctx.accounts.token_metadata_program,
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+            &ctx.accounts.mint_mapping.origin_mint,
+            &metadata,
+        )
+    }
 
-        if enable_ptkn {
-            let mint_key = prepare_ptkn_mint(
-                state,
-                ctx.accounts.ptkn_mint.as_ref(),
-                ctx.accounts.token_program.as_ref(),
-                Some(&ctx.accounts.rent),
-                Some(&ctx.accounts.payer),
-                decimals,
-                Some(&ctx.accounts.authority),
-            )?;
-            mapping.has_ptkn = true;
-            mapping.ptkn_mint = mint_key;
+    /// Registers `origin_mint` and deploys its vault, vault token account and pool in one
+    /// transaction, in place of sequencing `register_mint`, `ptf_vault::initialize_vault`,
+    /// the vault ATA and `ptf_pool::initialize_pool` by hand. The factory authority becomes
+    /// the pool authority, and the mapping takes the origin mint's decimals.
+    pub fn register_and_deploy(
+        ctx: Context<RegisterAndDeploy>,
+        params: RegisterAndDeployParams,
+    ) -> Result<()> {
+        let origin_mint = ctx.accounts.origin_mint.key();
+        let template = ctx
+            .accounts
+            .template
+            .as_ref()
+            .map(|template| (template.key(), template.params.clone()));
+        let params = match &template {
+            Some((_, template)) => params.with_template(template),
+            None => params,
+        };
+        let ptkn_extensions = template
+            .as_ref()
+            .map_or(0, |(_, template)| template.ptkn_extensions);
+        let (expected_pool, _) = Pubkey::find_program_address(
+            &[seeds::POOL, origin_mint.as_ref()],
+            &PTF_POOL_PROGRAM_ID,
+        );
+        require_keys_eq!(
+            ctx.accounts.pool_state.key(),
+            expected_pool,
+            FactoryError::PoolAddressMismatch
+        );
+
+        register_mapping(
+            &ctx.accounts.factory_state,
+            &mut ctx.accounts.mint_mapping,
+            origin_mint,
+            ctx.bumps.mint_mapping,
+            RegisterMintParams {
+                decimals: ctx.accounts.origin_mint.decimals,
+                enable_ptkn: params.enable_ptkn,
+                feature_flags: params.feature_flags,
+                fee_bps_override: params.fee_bps_override,
+                template: template
+                    .as_ref()
+                    .map_or_else(Pubkey::default, |(key, _)| *key),
+            },
+            ctx.accounts.ptkn_mint.as_ref(),
+            Some(&ctx.accounts.token_program),
+            &ctx.accounts.rent,
+            &ctx.accounts.payer,
+            &ctx.accounts.authority,
+            (ptkn_extensions != 0)
+                .then(|| PtknMintExtensions {
+                    bits: ptkn_extensions,
+                    metadata: PtknMetadata::for_origin(&origin_mint, None),
+                })
+                .as_ref(),
+        )?;
+        if let Some(event) = collect_registration_fee(
+            &mut ctx.accounts.factory_state,
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+            origin_mint,
+        )? {
+            emit_cpi!(event);
         }
+        record_pool_accounts(
+            &mut ctx.accounts.mint_mapping,
+            expected_pool,
+            &PTF_POOL_PROGRAM_ID,
+        );
+        record_verifying_key(&mut ctx.accounts.mint_mapping, &ctx.accounts.verifying_key);
+        // The pool reads the mapping during its own initialization, so it must be
+        // written back before the CPI rather than when this instruction exits.
+        ctx.accounts.mint_mapping.exit(&crate::ID)?;
 
-        emit!(MintRegistered {
-            origin_mint: ctx.accounts.origin_mint.key(),
-            ptkn_mint: mapping.ptkn_mint,
-            decimals,
-            features: mapping.features.bits(),
-            fee_bps: effective_fee_bps,
+        ptf_vault::cpi::initialize_vault(
+            CpiContext::new(
+                ctx.accounts.vault_program.to_account_info(),
+                ptf_vault::cpi::accounts::InitializeVault {
+                    vault_state: ctx.accounts.vault_state.to_account_info(),
+                    origin_mint: ctx.accounts.origin_mint.to_account_info(),
+                    payer: ctx.accounts.payer.to_account_info(),
+                    system_program: ctx.accounts.system_program.to_account_info(),
+                },
+            ),
+            expected_pool,
+        )?;
+        associated_token::create(CpiContext::new(
+            ctx.accounts.associated_token_program.to_account_info(),
+            associated_token::Create {
+                payer: ctx.accounts.payer.to_account_info(),
+                associated_token: ctx.accounts.vault_token_account.to_account_info(),
+                authority: ctx.accounts.vault_state.to_account_info(),
+                mint: ctx.accounts.origin_mint.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                token_program: ctx.accounts.token_program.to_account_info(),
+            },
+        ))?;
+
+        invoke_initialize_pool(ctx.accounts, &params)?;
+        if let Some((_, template)) = &template {
+            apply_template_to_pool(ctx.accounts, &params, template)?;
+        }
+
+        // Emitted once the pool exists, so the event carries its accounts.
+        emit_cpi!(mint_registered_event(
+            &ctx.accounts.factory_state,
+            &ctx.accounts.mint_mapping,
+            ctx.accounts.origin_mint.to_account_info().owner,
+            ctx.accounts.authority.key(),
+        ));
+        emit_cpi!(PoolDeployed {
+            origin_mint,
+            pool: expected_pool,
+            vault: ctx.accounts.vault_state.key(),
+            vault_token_account: ctx.accounts.vault_token_account.key(),
+            ptkn_mint: ctx.accounts.mint_mapping.ptkn_mint,
+            commitment_tree: ctx.accounts.commitment_tree.key(),
+            nullifier_set: ctx.accounts.nullifier_set.key(),
+            note_ledger: ctx.accounts.note_ledger.key(),
+            hook_config: ctx.accounts.hook_config.key(),
+            token_program: ctx.accounts.token_program.key(),
+            verifying_key: ctx.accounts.verifying_key.key(),
+            pool_authority: if params.factory_governed {
+                ctx.accounts.factory_state.key()
+            } else {
+                ctx.accounts.authority.key()
+            },
+            shield_fee_bps: params.shield_fee_bps,
+            pool_features: params.pool_features,
+            merkle_depth: params.merkle_depth,
+            template: ctx.accounts.mint_mapping.template,
+        });
+        Ok(())
+    }
+
+    /// Records the accounts of the mint's existing pool on its mapping, for mints
+    /// registered without `register_and_deploy` or before the mapping stored them.
+    /// Linking again moves the mapping to a pool under another allowlisted program.
+    pub fn link_pool(ctx: Context<LinkPool>) -> Result<()> {
+        let pool_state = &ctx.accounts.pool_state;
+        let pool_program = *pool_state.owner;
+        require!(
+            ctx.accounts.factory_state.is_pool_program(&pool_program),
+            FactoryError::PoolProgramNotAllowed
+        );
+        let mapping = &mut ctx.accounts.mint_mapping;
+        let (expected_pool, _) = Pubkey::find_program_address(
+            &[seeds::POOL, mapping.origin_mint.as_ref()],
+            &pool_program,
+        );
+        require_keys_eq!(
+            pool_state.key(),
+            expected_pool,
+            FactoryError::PoolAddressMismatch
+        );
+        require!(!pool_state.data_is_empty(), FactoryError::PoolNotDeployed);
+
+        record_pool_accounts(mapping, expected_pool, &pool_program);
+        emit!(PoolLinked {
+            origin_mint: mapping.origin_mint,
+            pool: mapping.pool,
+            vault: mapping.vault,
+            commitment_tree: mapping.commitment_tree,
+            nullifier_set: mapping.nullifier_set,
         });
         Ok(())
     }
 
-    pub fn update_mint(ctx: Context<UpdateMint>, params: UpdateMintParams) -> Result<()> {
+    /// `ptkn_extensions` applies when this call creates the pTKN mint.
+    pub fn update_mint(
+        ctx: Context<UpdateMint>,
+        params: UpdateMintParams,
+        ptkn_extensions: u8,
+    ) -> Result<()> {
         let mapping = &mut ctx.accounts.mint_mapping;
         let state = &ctx.accounts.factory_state;
-        require!(!state.paused, FactoryError::Paused);
+        require!(!state.is_paused(PAUSE_MINT_UPDATES), FactoryError::Paused);
         require_keys_eq!(
             ctx.accounts.authority.key(),
             state.authority,
             FactoryError::Unauthorized
         );
         ensure_direct_update_allowed(state)?;
+        let origin_metadata = ctx
+            .accounts
+            .origin_metadata
+            .as_ref()
+            .map(|account| read_token_metadata(account, &mapping.origin_mint))
+            .transpose()?;
+        let metadata = PtknMetadata::for_origin(&mapping.origin_mint, origin_metadata);
 
         apply_mint_update(
             &ctx.accounts.factory_state,
             mapping,
             &params,
-            ctx.accounts.ptkn_mint.as_ref(),
-            ctx.accounts.token_program.as_ref(),
-            Some(&ctx.accounts.rent),
-            Some(&ctx.accounts.authority),
-            Some(&ctx.accounts.authority),
+            PtknMintAccounts {
+                ptkn_mint: ctx.accounts.ptkn_mint.as_ref(),
+                token_program: ctx.accounts.token_program.as_ref(),
+                rent: Some(&ctx.accounts.rent),
+                payer: Some(&ctx.accounts.authority),
+                authority: Some(&ctx.accounts.authority),
+            },
+            Some(&PtknMintExtensions {
+                bits: ptkn_extensions,
+                metadata,
+            }),
         )?;
 
         emit!(MintUpdated {
@@ -147,7 +640,9 @@ pub mod ptf_factory {
         Ok(())
     }
 
-    pub fn freeze_mapping(ctx: Context<MutationMintState>) -> Result<()> {
+    /// Freezes a mapping. The operator may call this and `thaw_mapping` as well as the
+    /// authority.
+    pub fn freeze_mapping(ctx: Context<OperateMintState>) -> Result<()> {
         let mapping = &mut ctx.accounts.mint_mapping;
         mapping.status = MintStatus::Frozen as u8;
         emit!(MintFrozen {
@@ -157,7 +652,7 @@ pub mod ptf_factory {
         Ok(())
     }
 
-    pub fn thaw_mapping(ctx: Context<MutationMintState>) -> Result<()> {
+    pub fn thaw_mapping(ctx: Context<OperateMintState>) -> Result<()> {
         let mapping = &mut ctx.accounts.mint_mapping;
         mapping.status = MintStatus::Active as u8;
         emit!(MintThawed {
@@ -167,7 +662,161 @@ pub mod ptf_factory {
         Ok(())
     }
 
-    pub fn pause(ctx: Context<UpdateFactoryAuthority>) -> Result<()> {
+    /// Caps the pTKN `mint_ptkn` may leave outstanding for this mapping; 0 removes the
+    /// cap. Timelocked factories go through `TimelockAction::SetPtknSupplyCap`.
+    pub fn set_ptkn_supply_cap(
+        ctx: Context<MutationMintState>,
+        max_ptkn_supply: u64,
+    ) -> Result<()> {
+        ensure_direct_update_allowed(&ctx.accounts.factory_state)?;
+        let mapping = &mut ctx.accounts.mint_mapping;
+        mapping.max_ptkn_supply = max_ptkn_supply;
+        emit!(PtknSupplyCapUpdated {
+            origin_mint: mapping.origin_mint,
+            max_ptkn_supply,
+        });
+        Ok(())
+    }
+
+    /// Registers a verifying key with `ptf_verifier_groth16` under the factory state PDA
+    /// as its authority. This alone changes no mint; `pin_verifying_key` attaches it.
+    pub fn register_verifying_key(
+        ctx: Context<RegisterVerifyingKey>,
+        circuit_tag: [u8; 32],
+        verifying_key_id: [u8; 32],
+        hash: [u8; 32],
+        version: u8,
+        verifying_key_data: Vec<u8>,
+    ) -> Result<()> {
+        let bump_seed = &[ctx.accounts.factory_state.bump];
+        let signer_seeds: [&[u8]; 3] = [seeds::FACTORY, crate::ID.as_ref(), bump_seed];
+        let signer_seeds_slice: &[&[u8]] = &signer_seeds;
+        let signer_seeds_for_cpi = [signer_seeds_slice];
+        ptf_verifier_groth16::cpi::initialize_verifying_key(
+            CpiContext::new_with_signer(
+                ctx.accounts.verifier_program.to_account_info(),
+                ptf_verifier_groth16::cpi::accounts::InitializeVerifyingKey {
+                    verifier_state: ctx.accounts.verifier_state.to_account_info(),
+                    authority: ctx.accounts.factory_state.to_account_info(),
+                    payer: ctx.accounts.payer.to_account_info(),
+                    system_program: ctx.accounts.system_program.to_account_info(),
+                },
+                &signer_seeds_for_cpi,
+            ),
+            circuit_tag,
+            verifying_key_id,
+            hash,
+            version,
+            verifying_key_data,
+        )
+    }
+
+    /// Pins the circuit a mint's pools must verify against. Timelocked factories go
+    /// through `TimelockAction::PinVerifyingKey`.
+    pub fn pin_verifying_key(ctx: Context<PinVerifyingKey>) -> Result<()> {
+        ensure_direct_update_allowed(&ctx.accounts.factory_state)?;
+        let mapping = &mut ctx.accounts.mint_mapping;
+        record_verifying_key(mapping, &ctx.accounts.verifying_key);
+        emit!(VerifyingKeyPinned {
+            origin_mint: mapping.origin_mint,
+            verifying_key: mapping.verifying_key,
+            circuit_tag: mapping.circuit_tag,
+        });
+        Ok(())
+    }
+
+    /// Grows a mint mapping created under an older layout. New fields start at 0, so the
+    /// supply cap is off and the mint and burn totals count from here.
+    pub fn migrate_mint_mapping(
+        ctx: Context<MigrateMintMapping>,
+        _origin_mint: Pubkey,
+    ) -> Result<()> {
+        let info = ctx.accounts.mint_mapping.to_account_info();
+        require_keys_eq!(*info.owner, crate::ID, FactoryError::Unauthorized);
+        let old_len = info.data_len();
+        if old_len >= MintMapping::SPACE {
+            return Ok(());
+        }
+        require!(
+            old_len >= MintMapping::LEGACY_SPACE
+                && info.try_borrow_data()?[..8] == *MintMapping::DISCRIMINATOR,
+            FactoryError::Unauthorized
+        );
+        let required_lamports = Rent::get()?.minimum_balance(MintMapping::SPACE);
+        let top_up = required_lamports.saturating_sub(info.lamports());
+        if top_up > 0 {
+            anchor_lang::system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    anchor_lang::system_program::Transfer {
+                        from: ctx.accounts.payer.to_account_info(),
+                        to: info.clone(),
+                    },
+                ),
+                top_up,
+            )?;
+        }
+        #[allow(deprecated)]
+        info.realloc(MintMapping::SPACE, true)?;
+        if old_len <= MintMapping::NARROW_FEATURES_SPACE {
+            widen_features(
+                &mut info.try_borrow_mut_data()?,
+                MintMapping::FEATURES_OFFSET,
+                old_len,
+            );
+        }
+        Ok(())
+    }
+
+    /// Removes a frozen registration and refunds its rent to the authority. The mint's pool
+    /// must never have been created or must have been closed, every pTKN minted must have
+    /// been burned, and no registration bond may be pending.
+    pub fn close_mint_mapping<'info>(
+        ctx: Context<'_, '_, '_, 'info, CloseMintMapping<'info>>,
+    ) -> Result<()> {
+        let mapping = &ctx.accounts.mint_mapping;
+        require!(
+            mapping.status == MintStatus::Frozen as u8,
+            FactoryError::MintNotFrozen
+        );
+        // A pool may live under any allowlisted program, so each one's PDA must be empty.
+        let pool_states = core::iter::once(ctx.accounts.pool_state.as_ref())
+            .chain(ctx.remaining_accounts.iter())
+            .collect::<Vec<_>>();
+        let programs = ctx.accounts.factory_state.allowed_pool_programs();
+        require!(
+            pool_states.len() >= programs.len(),
+            FactoryError::PoolAccountsMissing
+        );
+        for (program, pool_state) in programs.iter().zip(pool_states) {
+            let (expected_pool, _) =
+                Pubkey::find_program_address(&[seeds::POOL, mapping.origin_mint.as_ref()], program);
+            require_keys_eq!(
+                expected_pool,
+                pool_state.key(),
+                FactoryError::PoolAddressMismatch
+            );
+            require!(pool_state.data_is_empty(), FactoryError::PoolStillOpen);
+        }
+        require!(
+            mapping.total_minted == mapping.total_burned,
+            FactoryError::PtknSupplyOutstanding
+        );
+        require!(
+            ctx.accounts.registration_bond.data_is_empty(),
+            FactoryError::RegistrationBondOutstanding
+        );
+
+        emit!(MintDeregistered {
+            origin_mint: mapping.origin_mint,
+            ptkn_mint: mapping.ptkn_mint,
+            authority: ctx.accounts.authority.key(),
+        });
+        Ok(())
+    }
+
+    /// Pauses the factory. The guardian may call this as well as the authority.
+    pub fn pause(ctx: Context<PauseFactory>) -> Result<()> {
         let state = &mut ctx.accounts.factory_state;
         state.paused = true;
         emit!(FactoryPaused {
@@ -176,8 +825,11 @@ pub mod ptf_factory {
         Ok(())
     }
 
+    /// Timelocked factories go through `TimelockAction::UnpauseFactory`, which can be
+    /// queued while paused. Pausing stays immediate.
     pub fn unpause(ctx: Context<UpdateFactoryAuthority>) -> Result<()> {
         let state = &mut ctx.accounts.factory_state;
+        ensure_direct_update_allowed(state)?;
         state.paused = false;
         emit!(FactoryUnpaused {
             authority: ctx.accounts.authority.key(),
@@ -185,71 +837,548 @@ pub mod ptf_factory {
         Ok(())
     }
 
-    pub fn queue_timelock_action(
-        ctx: Context<QueueTimelockAction>,
-        salt: [u8; 32],
-        action: TimelockAction,
-    ) -> Result<()> {
-        let state = &ctx.accounts.factory_state;
-        require!(!state.paused, FactoryError::Paused);
-
-        let clock = Clock::get()?;
-        let execute_after = clock
-            .unix_timestamp
-            .checked_add(state.timelock_seconds)
-            .ok_or_else(|| error!(FactoryError::TimelockOverflow))?;
-
-        let action_bytes = action
-            .try_to_vec()
-            .map_err(|_| error!(FactoryError::SerializationError))?;
-        let expected_hash = hashv(&[
-            state.key().as_ref(),
-            &action_bytes,
-            &execute_after.to_le_bytes(),
-        ]);
+    /// Pauses the `PAUSE_*` scopes in `scopes`, leaving the rest of the factory running.
+    /// The guardian may call this as well as the authority.
+    pub fn pause_scopes(ctx: Context<PauseFactory>, scopes: u8) -> Result<()> {
+        validate_pause_scopes(scopes)?;
+        let factory_key = ctx.accounts.factory_state.key();
+        let state = &mut ctx.accounts.factory_state;
+        state.paused_scopes |= scopes;
+        emit!(PauseScopesUpdated {
+            factory: factory_key,
+            paused_scopes: state.paused_scopes,
+        });
+        Ok(())
+    }
 
-        if let TimelockAction::UpdateMint { origin_mint, .. } = &action {
-            let mapping = ctx
-                .accounts
-                .mint_mapping
-                .as_ref()
-                .ok_or(FactoryError::TimelockMissingMapping)?;
-            require_keys_eq!(
-                mapping.origin_mint,
-                *origin_mint,
-                FactoryError::OriginMintMismatch
-            );
-        }
+    /// Timelocked factories go through `TimelockAction::UnpauseScopes`.
+    pub fn unpause_scopes(ctx: Context<UpdateFactoryAuthority>, scopes: u8) -> Result<()> {
+        validate_pause_scopes(scopes)?;
+        let factory_key = ctx.accounts.factory_state.key();
+        let state = &mut ctx.accounts.factory_state;
+        ensure_direct_update_allowed(state)?;
+        state.paused_scopes &= !scopes;
+        emit!(PauseScopesUpdated {
+            factory: factory_key,
+            paused_scopes: state.paused_scopes,
+        });
+        Ok(())
+    }
 
-        let entry = &mut ctx.accounts.timelock_entry;
-        entry.factory = state.key();
-        entry.salt = salt;
-        entry.action_hash = expected_hash.to_bytes();
-        entry.queued_at = clock.unix_timestamp;
-        entry.execute_after = execute_after;
-        entry.executed = false;
-        entry.action = action;
-        entry.bump = ctx.bumps.timelock_entry;
+    /// Sets where `withdraw_treasury` sends collected fees and the lamport fee
+    /// `register_mint` charges. Timelocked factories go through
+    /// `TimelockAction::SetTreasury`.
+    pub fn set_treasury(
+        ctx: Context<UpdateFactoryAuthority>,
+        treasury: Pubkey,
+        registration_fee_lamports: u64,
+    ) -> Result<()> {
+        let factory_key = ctx.accounts.factory_state.key();
+        let state = &mut ctx.accounts.factory_state;
+        ensure_direct_update_allowed(state)?;
+        state.treasury = treasury;
+        state.registration_fee_lamports = registration_fee_lamports;
+        state.last_updated_slot = Clock::get()?.slot;
+        emit!(TreasuryUpdated {
+            factory: factory_key,
+            treasury,
+            registration_fee_lamports,
+        });
+        Ok(())
+    }
 
-        emit!(TimelockQueued {
-            factory: state.key(),
-            action_hash: entry.action_hash,
-            queued_at: clock.unix_timestamp,
-            execute_after,
+    /// Sets the largest origin mint decimals registration accepts; 0 restores
+    /// `DEFAULT_MAX_DECIMALS`. Timelocked factories go through
+    /// `TimelockAction::SetMaxDecimals`.
+    pub fn set_max_decimals(ctx: Context<UpdateFactoryAuthority>, max_decimals: u8) -> Result<()> {
+        let factory_key = ctx.accounts.factory_state.key();
+        let state = &mut ctx.accounts.factory_state;
+        ensure_direct_update_allowed(state)?;
+        state.max_decimals = max_decimals;
+        state.last_updated_slot = Clock::get()?.slot;
+        emit!(MaxDecimalsUpdated {
+            factory: factory_key,
+            max_decimals: state.decimals_limit(),
         });
         Ok(())
     }
 
-    pub fn execute_timelock_action(ctx: Context<ExecuteTimelockAction>) -> Result<()> {
+    /// Sets the lamport bond `register_mint_bonded` takes from a permissionless
+    /// registrant; 0 turns permissionless registration off. Timelocked factories go
+    /// through `TimelockAction::SetRegistrationBond`.
+    pub fn set_registration_bond(
+        ctx: Context<UpdateFactoryAuthority>,
+        bond_lamports: u64,
+    ) -> Result<()> {
+        let factory_key = ctx.accounts.factory_state.key();
         let state = &mut ctx.accounts.factory_state;
-        let entry = &mut ctx.accounts.timelock_entry;
-        require!(!entry.executed, FactoryError::TimelockConsumed);
+        ensure_direct_update_allowed(state)?;
+        state.registration_bond_lamports = bond_lamports;
+        state.last_updated_slot = Clock::get()?.slot;
+        emit!(RegistrationBondUpdated {
+            factory: factory_key,
+            bond_lamports,
+        });
+        Ok(())
+    }
 
-        let clock = Clock::get()?;
+    /// Sends `amount` of the collected fees to the treasury. Timelocked factories go
+    /// through `TimelockAction::WithdrawTreasury`.
+    pub fn withdraw_treasury(ctx: Context<WithdrawTreasury>, amount: u64) -> Result<()> {
+        ensure_direct_update_allowed(&ctx.accounts.factory_state)?;
+        pay_from_treasury(
+            &mut ctx.accounts.factory_state,
+            &ctx.accounts.treasury.to_account_info(),
+            amount,
+        )
+    }
+
+    /// Sets the guardian, which may cancel queued timelock actions and pause the factory
+    /// but nothing else. `Pubkey::default()` removes it. Timelocked factories go through
+    /// `TimelockAction::SetGuardian`.
+    pub fn set_guardian(ctx: Context<UpdateFactoryAuthority>, guardian: Pubkey) -> Result<()> {
+        let factory_key = ctx.accounts.factory_state.key();
+        let state = &mut ctx.accounts.factory_state;
+        ensure_direct_update_allowed(state)?;
+        state.guardian = guardian;
+        state.last_updated_slot = Clock::get()?.slot;
+        emit!(GuardianUpdated {
+            factory: factory_key,
+            guardian,
+        });
+        Ok(())
+    }
+
+    /// Sets the operator, who may only freeze and thaw mappings; `Pubkey::default()`
+    /// removes it. Timelocked factories go through `TimelockAction::SetOperator`.
+    pub fn set_operator(ctx: Context<UpdateFactoryAuthority>, operator: Pubkey) -> Result<()> {
+        let factory_key = ctx.accounts.factory_state.key();
+        let state = &mut ctx.accounts.factory_state;
+        ensure_direct_update_allowed(state)?;
+        state.operator = operator;
+        state.last_updated_slot = Clock::get()?.slot;
+        emit!(OperatorUpdated {
+            factory: factory_key,
+            operator,
+        });
+        Ok(())
+    }
+
+    /// Adds `program` to, or removes it from, the pool programs whose PDAs may mint and
+    /// burn pTKN. Timelocked factories go through `TimelockAction::SetPoolProgram`.
+    pub fn set_pool_program(
+        ctx: Context<UpdateFactoryAuthority>,
+        program: Pubkey,
+        allowed: bool,
+    ) -> Result<()> {
+        let factory_key = ctx.accounts.factory_state.key();
+        let state = &mut ctx.accounts.factory_state;
+        ensure_direct_update_allowed(state)?;
+        state.set_pool_program(program, allowed)?;
+        state.last_updated_slot = Clock::get()?.slot;
+        emit!(PoolProgramUpdated {
+            factory: factory_key,
+            program,
+            allowed,
+        });
+        Ok(())
+    }
+
+    /// Creates the protocol config every pool reads on shield and unshield, with no fee
+    /// bound below `MAX_BPS`, the kill switch off and the Groth16 verifier as the only
+    /// allowed verifier program.
+    pub fn initialize_protocol_config(ctx: Context<InitializeProtocolConfig>) -> Result<()> {
+        let config = &mut ctx.accounts.protocol_config;
+        config.factory = ctx.accounts.factory_state.key();
+        config.max_fee_bps = MAX_BPS;
+        config.kill_switch = false;
+        config.verifier_programs[0] = ptf_verifier_groth16::ID;
+        config.verifier_programs_len = 1;
+        config.bump = ctx.bumps.protocol_config;
+        emit!(ProtocolConfigInitialized {
+            factory: config.factory,
+            max_fee_bps: config.max_fee_bps,
+        });
+        Ok(())
+    }
+
+    /// Engages or releases the kill switch, which halts every shield and unshield in
+    /// every pool. Timelocked factories go through `TimelockAction::SetKillSwitch`.
+    pub fn set_kill_switch(ctx: Context<UpdateProtocolConfig>, engaged: bool) -> Result<()> {
+        ensure_direct_update_allowed(&ctx.accounts.factory_state)?;
+        let config = &mut ctx.accounts.protocol_config;
+        config.kill_switch = engaged;
+        emit!(KillSwitchUpdated {
+            factory: config.factory,
+            engaged,
+        });
+        Ok(())
+    }
+
+    /// Sets the largest shield or unshield rate a pool may charge; pools above it reject
+    /// shields and unshields until their fee is lowered. Timelocked factories go through
+    /// `TimelockAction::SetProtocolMaxFee`.
+    pub fn set_protocol_max_fee(
+        ctx: Context<UpdateProtocolConfig>,
+        max_fee_bps: u16,
+    ) -> Result<()> {
+        require!(max_fee_bps <= MAX_BPS, FactoryError::InvalidFeeBps);
+        ensure_direct_update_allowed(&ctx.accounts.factory_state)?;
+        let config = &mut ctx.accounts.protocol_config;
+        config.max_fee_bps = max_fee_bps;
+        emit!(ProtocolMaxFeeUpdated {
+            factory: config.factory,
+            max_fee_bps,
+        });
+        Ok(())
+    }
+
+    /// Adds `program` to, or removes it from, the verifier programs pools may call.
+    /// Timelocked factories go through `TimelockAction::SetVerifierProgram`.
+    pub fn set_verifier_program(
+        ctx: Context<UpdateProtocolConfig>,
+        program: Pubkey,
+        allowed: bool,
+    ) -> Result<()> {
+        ensure_direct_update_allowed(&ctx.accounts.factory_state)?;
+        let config = &mut ctx.accounts.protocol_config;
+        config.set_verifier_program(program, allowed)?;
+        emit!(VerifierProgramUpdated {
+            factory: config.factory,
+            program,
+            allowed,
+        });
+        Ok(())
+    }
+
+    /// Restricts `execute_timelock_action` to the authority and guardian, or opens it to
+    /// any signer again. Timelocked factories go through
+    /// `TimelockAction::SetRestrictedExecution`.
+    pub fn set_restricted_execution(
+        ctx: Context<UpdateFactoryAuthority>,
+        restricted: bool,
+    ) -> Result<()> {
+        let factory_key = ctx.accounts.factory_state.key();
+        let state = &mut ctx.accounts.factory_state;
+        ensure_direct_update_allowed(state)?;
+        state.restricted_execution = restricted;
+        state.last_updated_slot = Clock::get()?.slot;
+        emit!(RestrictedExecutionUpdated {
+            factory: factory_key,
+            restricted,
+        });
+        Ok(())
+    }
+
+    /// Hands the factory to an M-of-N council: the authority becomes the council PDA, so
+    /// every privileged instruction must go through an approved council proposal. Called
+    /// through a proposal, it replaces the members and threshold. Timelocked factories go
+    /// through `TimelockAction::SetCouncil`.
+    pub fn set_council(
+        ctx: Context<UpdateFactoryAuthority>,
+        members: Vec<Pubkey>,
+        threshold: u8,
+    ) -> Result<()> {
+        validate_council(&members, threshold)?;
+        let factory_key = ctx.accounts.factory_state.key();
+        let state = &mut ctx.accounts.factory_state;
+        ensure_direct_update_allowed(state)?;
+        install_council(state, factory_key, members, threshold);
+        state.last_updated_slot = Clock::get()?.slot;
+        Ok(())
+    }
+
+    /// Leaves council mode, handing the factory to `new_authority`. Only the council
+    /// itself can call this, through an approved proposal. Timelocked factories go
+    /// through `TimelockAction::DissolveCouncil`.
+    pub fn dissolve_council(
+        ctx: Context<UpdateFactoryAuthority>,
+        new_authority: Pubkey,
+    ) -> Result<()> {
+        let factory_key = ctx.accounts.factory_state.key();
+        let state = &mut ctx.accounts.factory_state;
+        ensure_direct_update_allowed(state)?;
+        remove_council(state, factory_key, new_authority)?;
+        state.last_updated_slot = Clock::get()?.slot;
+        Ok(())
+    }
+
+    /// Hands the factory authority to the native treasury of an SPL Governance
+    /// `governance` account, so privileged instructions, including queueing and executing
+    /// timelock actions, run as governance-executed proposal transactions. Clears any
+    /// council. `governance_program` must be `SPL_GOVERNANCE_PROGRAM_ID`. Timelocked
+    /// factories go through `TimelockAction::SetGovernanceAuthority`.
+    pub fn set_governance_authority(
+        ctx: Context<UpdateFactoryAuthority>,
+        governance_program: Pubkey,
+        governance: Pubkey,
+    ) -> Result<()> {
+        validate_governance_program(&governance_program)?;
+        let factory_key = ctx.accounts.factory_state.key();
+        let state = &mut ctx.accounts.factory_state;
+        ensure_direct_update_allowed(state)?;
+        install_governance_authority(state, factory_key, governance_program, governance);
+        state.last_updated_slot = Clock::get()?.slot;
+        Ok(())
+    }
+
+    /// Proposes one factory instruction for the council to execute. `data` is the full
+    /// instruction data and `metas` its accounts; the council PDA signs as the authority
+    /// when the proposal executes. The proposer's approval is recorded immediately.
+    pub fn create_council_proposal(
+        ctx: Context<CreateCouncilProposal>,
+        metas: Vec<ProposalAccountMeta>,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        let proposer = ctx.accounts.proposer.key();
+        let factory_key = ctx.accounts.factory_state.key();
+        let state = &mut ctx.accounts.factory_state;
+        let member_index = state
+            .council_index(&proposer)
+            .ok_or(FactoryError::NotCouncilMember)?;
+        require!(
+            metas.len() <= CouncilProposal::MAX_ACCOUNTS && data.len() <= CouncilProposal::MAX_DATA,
+            FactoryError::CouncilProposalTooLarge
+        );
+
+        let proposal = &mut ctx.accounts.proposal;
+        proposal.factory = factory_key;
+        proposal.id = state.council_proposals;
+        proposal.proposer = proposer;
+        proposal.council_epoch = state.council_epoch;
+        proposal.approvals = 1 << member_index;
+        proposal.executed = false;
+        proposal.metas = metas;
+        proposal.data = data;
+        proposal.bump = ctx.bumps.proposal;
+        state.council_proposals = state
+            .council_proposals
+            .checked_add(1)
+            .ok_or(FactoryError::TimelockOverflow)?;
+
+        emit!(CouncilProposalCreated {
+            factory: factory_key,
+            proposal: proposal.key(),
+            id: proposal.id,
+            proposer,
+        });
+        Ok(())
+    }
+
+    pub fn approve_council_proposal(ctx: Context<ApproveCouncilProposal>) -> Result<()> {
+        let member = ctx.accounts.member.key();
+        let state = &ctx.accounts.factory_state;
+        let member_index = state
+            .council_index(&member)
+            .ok_or(FactoryError::NotCouncilMember)?;
+        let proposal = &mut ctx.accounts.proposal;
+        require!(!proposal.executed, FactoryError::CouncilProposalExecuted);
+        require!(
+            proposal.council_epoch == state.council_epoch,
+            FactoryError::CouncilProposalStale
+        );
+        proposal.approvals |= 1 << member_index;
+
+        emit!(CouncilProposalApproved {
+            proposal: proposal.key(),
+            member,
+            approvals: proposal.approvals.count_ones() as u8,
+        });
+        Ok(())
+    }
+
+    /// Executes a proposal that has reached the threshold by calling back into this program
+    /// with the council PDA signing. Every proposal account other than the council must be
+    /// supplied in the remaining accounts; any other signer must sign this transaction.
+    pub fn execute_council_proposal<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ExecuteCouncilProposal<'info>>,
+    ) -> Result<()> {
+        let state = &ctx.accounts.factory_state;
+        let proposal = &mut ctx.accounts.proposal;
+        require!(!proposal.executed, FactoryError::CouncilProposalExecuted);
+        require!(
+            proposal.council_epoch == state.council_epoch,
+            FactoryError::CouncilProposalStale
+        );
+        require!(
+            proposal.approvals.count_ones() >= state.council_threshold as u32,
+            FactoryError::CouncilThresholdNotMet
+        );
+
+        let council_key = ctx.accounts.council.key();
+        let mut metas = Vec::with_capacity(proposal.metas.len());
+        let mut infos = Vec::with_capacity(proposal.metas.len() + 1);
+        for meta in proposal.metas.iter() {
+            let info = if meta.pubkey == council_key {
+                ctx.accounts.council.to_account_info()
+            } else {
+                ctx.remaining_accounts
+                    .iter()
+                    .find(|info| info.key() == meta.pubkey)
+                    .ok_or(FactoryError::CouncilAccountMissing)?
+                    .clone()
+            };
+            metas.push(AccountMeta {
+                pubkey: meta.pubkey,
+                is_signer: meta.is_signer || meta.pubkey == council_key,
+                is_writable: meta.is_writable,
+            });
+            infos.push(info);
+        }
+        infos.push(ctx.accounts.factory_program.to_account_info());
+        let ix = Instruction {
+            program_id: crate::ID,
+            accounts: metas,
+            data: proposal.data.clone(),
+        };
+
+        // Persist the executed flag before calling back in, so the proposal cannot be
+        // replayed from inside its own execution.
+        proposal.executed = true;
+        proposal.exit(&crate::ID)?;
+        emit!(CouncilProposalExecuted {
+            proposal: proposal.key(),
+            executor: ctx.accounts.executor.key(),
+        });
+
+        let factory_key = state.key();
+        let bump = [ctx.bumps.council];
+        let council_seeds: [&[u8]; 3] = [seeds::COUNCIL, factory_key.as_ref(), &bump];
+        invoke_signed(&ix, &infos, &[&council_seeds[..]])?;
+        Ok(())
+    }
+
+    /// Grows a factory state created before the council fields existed. The new fields
+    /// start zeroed, which is an empty council.
+    pub fn migrate_factory_state(ctx: Context<MigrateFactoryState>) -> Result<()> {
+        let info = ctx.accounts.factory_state.to_account_info();
+        require_keys_eq!(*info.owner, crate::ID, FactoryError::Unauthorized);
+        let old_len = info.data_len();
+        if old_len >= FactoryState::SPACE {
+            return Ok(());
+        }
+        let required_lamports = Rent::get()?.minimum_balance(FactoryState::SPACE);
+        let top_up = required_lamports.saturating_sub(info.lamports());
+        if top_up > 0 {
+            anchor_lang::system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    anchor_lang::system_program::Transfer {
+                        from: ctx.accounts.payer.to_account_info(),
+                        to: info.clone(),
+                    },
+                ),
+                top_up,
+            )?;
+        }
+        #[allow(deprecated)]
+        info.realloc(FactoryState::SPACE, true)?;
+        if old_len <= FactoryState::NARROW_FEATURES_SPACE {
+            widen_features(
+                &mut info.try_borrow_mut_data()?,
+                FactoryState::FEATURES_OFFSET,
+                old_len,
+            );
+        }
+        Ok(())
+    }
+
+    pub fn queue_timelock_action(
+        ctx: Context<QueueTimelockAction>,
+        salt: [u8; 32],
+        action: TimelockAction,
+    ) -> Result<()> {
+        let state = &ctx.accounts.factory_state;
+        // A timelocked factory can only be unpaused through the queue.
+        require!(
+            !state.paused || matches!(action, TimelockAction::UnpauseFactory),
+            FactoryError::Paused
+        );
+
+        action.validate()?;
+
+        let clock = Clock::get()?;
+        let execute_after = clock
+            .unix_timestamp
+            .checked_add(state.timelock_delay(&action))
+            .ok_or_else(|| error!(FactoryError::TimelockOverflow))?;
+
+        let expires_after = execute_after
+            .checked_add(TimelockEntry::EXECUTION_WINDOW)
+            .ok_or_else(|| error!(FactoryError::TimelockOverflow))?;
+
+        let action_bytes = action
+            .try_to_vec()
+            .map_err(|_| error!(FactoryError::SerializationError))?;
+        let expected_hash = hashv(&[
+            state.key().as_ref(),
+            &action_bytes,
+            &execute_after.to_le_bytes(),
+        ]);
+
+        if let TimelockAction::UpdateMint { origin_mint, .. } = &action {
+            let mapping = ctx
+                .accounts
+                .mint_mapping
+                .as_ref()
+                .ok_or(FactoryError::TimelockMissingMapping)?;
+            require_keys_eq!(
+                mapping.origin_mint,
+                *origin_mint,
+                FactoryError::OriginMintMismatch
+            );
+        }
+
+        let entry = &mut ctx.accounts.timelock_entry;
+        entry.factory = state.key();
+        entry.salt = salt;
+        entry.action_hash = expected_hash.to_bytes();
+        entry.queued_at = clock.unix_timestamp;
+        entry.execute_after = execute_after;
+        entry.executed = false;
+        entry.action = action;
+        entry.bump = ctx.bumps.timelock_entry;
+        entry.expires_after = expires_after;
+
+        let index = &mut ctx.accounts.timelock_index;
+        index.factory = state.key();
+        index.bump = ctx.bumps.timelock_index;
+        index.insert(PendingTimelock {
+            entry: entry.key(),
+            action_hash: entry.action_hash,
+            execute_after,
+        })?;
+
+        emit!(TimelockQueued {
+            factory: state.key(),
+            action_hash: entry.action_hash,
+            queued_at: clock.unix_timestamp,
+            execute_after,
+            expires_after,
+        });
+        Ok(())
+    }
+
+    pub fn execute_timelock_action(ctx: Context<ExecuteTimelockAction>) -> Result<()> {
+        let state = &mut ctx.accounts.factory_state;
+        if state.restricted_execution {
+            require!(
+                state.is_authority_or_guardian(&ctx.accounts.executor.key()),
+                FactoryError::Unauthorized
+            );
+        }
+        let entry = &mut ctx.accounts.timelock_entry;
+        require!(!entry.executed, FactoryError::TimelockConsumed);
+
+        let clock = Clock::get()?;
         require!(
             clock.unix_timestamp >= entry.execute_after,
             FactoryError::TimelockNotReady
         );
+        require!(
+            !entry.is_expired(clock.unix_timestamp),
+            FactoryError::TimelockExpired
+        );
 
         match &entry.action {
             TimelockAction::SetDefaultFeatures { features } => {
@@ -264,6 +1393,7 @@ pub mod ptf_factory {
                 origin_mint,
                 params,
             } => {
+                require!(!state.is_paused(PAUSE_MINT_UPDATES), FactoryError::Paused);
                 let mapping = ctx
                     .accounts
                     .mint_mapping
@@ -278,10 +1408,13 @@ pub mod ptf_factory {
                     state,
                     mapping,
                     params,
-                    ctx.accounts.ptkn_mint.as_ref(),
-                    ctx.accounts.token_program.as_ref(),
-                    Some(&ctx.accounts.rent),
-                    Some(&ctx.accounts.executor),
+                    PtknMintAccounts {
+                        ptkn_mint: ctx.accounts.ptkn_mint.as_ref(),
+                        token_program: ctx.accounts.token_program.as_ref(),
+                        rent: Some(&ctx.accounts.rent),
+                        payer: Some(&ctx.accounts.executor),
+                        authority: None,
+                    },
                     None,
                 )?;
                 emit!(MintUpdated {
@@ -307,10 +1440,289 @@ pub mod ptf_factory {
                     authority: state.authority,
                 });
             }
+            TimelockAction::PauseScopes { scopes } => {
+                state.paused_scopes |= *scopes;
+                emit!(PauseScopesUpdated {
+                    factory: state.key(),
+                    paused_scopes: state.paused_scopes,
+                });
+            }
+            TimelockAction::UnpauseScopes { scopes } => {
+                state.paused_scopes &= !*scopes;
+                emit!(PauseScopesUpdated {
+                    factory: state.key(),
+                    paused_scopes: state.paused_scopes,
+                });
+            }
+            TimelockAction::SetGuardian { guardian } => {
+                state.guardian = *guardian;
+                emit!(GuardianUpdated {
+                    factory: state.key(),
+                    guardian: *guardian,
+                });
+            }
+            TimelockAction::SetOperator { operator } => {
+                state.operator = *operator;
+                emit!(OperatorUpdated {
+                    factory: state.key(),
+                    operator: *operator,
+                });
+            }
+            TimelockAction::SetAuthority { new_authority } => {
+                let previous_authority = state.authority;
+                if state.council_len > 0 {
+                    state.council_members = [Pubkey::default(); FactoryState::MAX_COUNCIL_MEMBERS];
+                    state.council_len = 0;
+                    state.council_threshold = 0;
+                    state.council_epoch = state.council_epoch.wrapping_add(1);
+                }
+                state.governance_program = Pubkey::default();
+                state.governance = Pubkey::default();
+                state.authority = *new_authority;
+                emit!(AuthorityUpdated {
+                    factory: state.key(),
+                    previous_authority,
+                    new_authority: *new_authority,
+                });
+            }
+            TimelockAction::SetTimelockSeconds { seconds } => {
+                state.timelock_seconds = *seconds;
+                emit!(TimelockSecondsUpdated {
+                    factory: state.key(),
+                    timelock_seconds: *seconds,
+                });
+            }
+            TimelockAction::SetDefaultFee { fee_bps } => {
+                state.default_fee_bps = *fee_bps;
+                emit!(DefaultFeeUpdated {
+                    factory: state.key(),
+                    fee_bps: *fee_bps,
+                });
+            }
+            TimelockAction::SetPoolProgram { program, allowed } => {
+                state.set_pool_program(*program, *allowed)?;
+                emit!(PoolProgramUpdated {
+                    factory: state.key(),
+                    program: *program,
+                    allowed: *allowed,
+                });
+            }
+            TimelockAction::SetPtknSupplyCap {
+                origin_mint,
+                max_ptkn_supply,
+            } => {
+                let mapping = ctx
+                    .accounts
+                    .mint_mapping
+                    .as_mut()
+                    .ok_or(FactoryError::TimelockMissingMapping)?;
+                require_keys_eq!(
+                    mapping.origin_mint,
+                    *origin_mint,
+                    FactoryError::OriginMintMismatch
+                );
+                mapping.max_ptkn_supply = *max_ptkn_supply;
+                emit!(PtknSupplyCapUpdated {
+                    origin_mint: *origin_mint,
+                    max_ptkn_supply: *max_ptkn_supply,
+                });
+            }
+            TimelockAction::PinVerifyingKey {
+                origin_mint,
+                verifying_key,
+            } => {
+                let mapping = ctx
+                    .accounts
+                    .mint_mapping
+                    .as_mut()
+                    .ok_or(FactoryError::TimelockMissingMapping)?;
+                require_keys_eq!(
+                    mapping.origin_mint,
+                    *origin_mint,
+                    FactoryError::OriginMintMismatch
+                );
+                let account = ctx
+                    .accounts
+                    .verifying_key
+                    .as_ref()
+                    .ok_or(FactoryError::VerifyingKeyMissing)?;
+                require_keys_eq!(
+                    account.key(),
+                    *verifying_key,
+                    FactoryError::VerifyingKeyMismatch
+                );
+                record_verifying_key(mapping, account);
+                emit!(VerifyingKeyPinned {
+                    origin_mint: *origin_mint,
+                    verifying_key: *verifying_key,
+                    circuit_tag: mapping.circuit_tag,
+                });
+            }
+            TimelockAction::SetPoolFee {
+                origin_mint,
+                shield_fee_bps,
+                unshield_fee_bps,
+                shield_flat_fee,
+                unshield_flat_fee,
+            } => {
+                execute_pool_admin(
+                    state,
+                    ctx.accounts.pool_state.as_ref(),
+                    ctx.accounts.nullifier_set.as_ref(),
+                    ctx.accounts.pool_program.as_ref(),
+                    origin_mint,
+                    "set_fee",
+                    (
+                        *shield_fee_bps,
+                        *unshield_fee_bps,
+                        *shield_flat_fee,
+                        *unshield_flat_fee,
+                    ),
+                )?;
+            }
+            TimelockAction::SetPoolFeatures {
+                origin_mint,
+                features,
+            } => {
+                execute_pool_admin(
+                    state,
+                    ctx.accounts.pool_state.as_ref(),
+                    ctx.accounts.nullifier_set.as_ref(),
+                    ctx.accounts.pool_program.as_ref(),
+                    origin_mint,
+                    "set_features",
+                    *features,
+                )?;
+            }
+            TimelockAction::PausePool { origin_mint } => {
+                execute_pool_admin(
+                    state,
+                    ctx.accounts.pool_state.as_ref(),
+                    ctx.accounts.nullifier_set.as_ref(),
+                    ctx.accounts.pool_program.as_ref(),
+                    origin_mint,
+                    "pause",
+                    (),
+                )?;
+            }
+            TimelockAction::UnpausePool { origin_mint } => {
+                execute_pool_admin(
+                    state,
+                    ctx.accounts.pool_state.as_ref(),
+                    ctx.accounts.nullifier_set.as_ref(),
+                    ctx.accounts.pool_program.as_ref(),
+                    origin_mint,
+                    "unpause",
+                    (),
+                )?;
+            }
+            TimelockAction::SetKillSwitch { engaged } => {
+                let config = ctx
+                    .accounts
+                    .protocol_config
+                    .as_mut()
+                    .ok_or(FactoryError::ProtocolConfigMissing)?;
+                config.kill_switch = *engaged;
+                emit!(KillSwitchUpdated {
+                    factory: config.factory,
+                    engaged: *engaged,
+                });
+            }
+            TimelockAction::SetProtocolMaxFee { max_fee_bps } => {
+                let config = ctx
+                    .accounts
+                    .protocol_config
+                    .as_mut()
+                    .ok_or(FactoryError::ProtocolConfigMissing)?;
+                config.max_fee_bps = *max_fee_bps;
+                emit!(ProtocolMaxFeeUpdated {
+                    factory: config.factory,
+                    max_fee_bps: *max_fee_bps,
+                });
+            }
+            TimelockAction::SetVerifierProgram { program, allowed } => {
+                let config = ctx
+                    .accounts
+                    .protocol_config
+                    .as_mut()
+                    .ok_or(FactoryError::ProtocolConfigMissing)?;
+                config.set_verifier_program(*program, *allowed)?;
+                emit!(VerifierProgramUpdated {
+                    factory: config.factory,
+                    program: *program,
+                    allowed: *allowed,
+                });
+            }
+            TimelockAction::SetTreasury {
+                treasury,
+                registration_fee_lamports,
+            } => {
+                state.treasury = *treasury;
+                state.registration_fee_lamports = *registration_fee_lamports;
+                emit!(TreasuryUpdated {
+                    factory: state.key(),
+                    treasury: *treasury,
+                    registration_fee_lamports: *registration_fee_lamports,
+                });
+            }
+            TimelockAction::SetMaxDecimals { max_decimals } => {
+                state.max_decimals = *max_decimals;
+                emit!(MaxDecimalsUpdated {
+                    factory: state.key(),
+                    max_decimals: state.decimals_limit(),
+                });
+            }
+            TimelockAction::SetRegistrationBond { bond_lamports } => {
+                state.registration_bond_lamports = *bond_lamports;
+                emit!(RegistrationBondUpdated {
+                    factory: state.key(),
+                    bond_lamports: *bond_lamports,
+                });
+            }
+            TimelockAction::WithdrawTreasury { amount } => {
+                let treasury = ctx
+                    .accounts
+                    .treasury
+                    .as_ref()
+                    .ok_or(FactoryError::TreasuryNotSet)?;
+                pay_from_treasury(state, &treasury.to_account_info(), *amount)?;
+            }
+            TimelockAction::SetRestrictedExecution { restricted } => {
+                state.restricted_execution = *restricted;
+                emit!(RestrictedExecutionUpdated {
+                    factory: state.key(),
+                    restricted: *restricted,
+                });
+            }
+            TimelockAction::SetCouncil { members, threshold } => {
+                let factory_key = state.key();
+                install_council(state, factory_key, members.clone(), *threshold);
+            }
+            TimelockAction::DissolveCouncil { new_authority } => {
+                let factory_key = state.key();
+                remove_council(state, factory_key, *new_authority)?;
+            }
+            TimelockAction::SetGovernanceAuthority {
+                governance_program,
+                governance,
+            } => {
+                let factory_key = state.key();
+                install_governance_authority(state, factory_key, *governance_program, *governance);
+            }
+            TimelockAction::SetTimelockDelays { delays } => {
+                delays.validate()?;
+                state.has_timelock_delays = true;
+                state.timelock_delays = *delays;
+                emit!(TimelockDelaysUpdated {
+                    factory: state.key(),
+                    delays: *delays,
+                });
+            }
         }
 
         state.last_updated_slot = clock.slot;
         entry.executed = true;
+        ctx.accounts.timelock_index.remove(&entry.key());
 
         emit!(TimelockExecuted {
             factory: state.key(),
@@ -325,6 +1737,7 @@ pub mod ptf_factory {
         let entry = &mut ctx.accounts.timelock_entry;
         require!(!entry.executed, FactoryError::TimelockConsumed);
         entry.executed = true;
+        ctx.accounts.timelock_index.remove(&entry.key());
         let clock = Clock::get()?;
 
         emit!(TimelockCanceled {
@@ -336,10 +1749,49 @@ pub mod ptf_factory {
         Ok(())
     }
 
+    /// Closes an entry whose execution window has passed and drops it from the index.
+    /// Permissionless; the caller receives the entry's rent.
+    pub fn close_expired_timelock_action(ctx: Context<CloseExpiredTimelockAction>) -> Result<()> {
+        let entry = &ctx.accounts.timelock_entry;
+        let clock = Clock::get()?;
+        require!(
+            entry.is_expired(clock.unix_timestamp),
+            FactoryError::TimelockNotExpired
+        );
+        ctx.accounts.timelock_index.remove(&entry.key());
+
+        emit!(TimelockExpired {
+            factory: ctx.accounts.factory_state.key(),
+            action_hash: entry.action_hash,
+            expires_after: entry.expires_after,
+            closer: ctx.accounts.closer.key(),
+        });
+        Ok(())
+    }
+
+    /// Adds a pending entry to the timelock index. Queueing indexes entries itself; this
+    /// backfills entries queued before the index existed. Permissionless.
+    pub fn index_timelock_entry(ctx: Context<IndexTimelockEntry>) -> Result<()> {
+        let entry = &ctx.accounts.timelock_entry;
+        require!(!entry.executed, FactoryError::TimelockConsumed);
+
+        let index = &mut ctx.accounts.timelock_index;
+        index.factory = ctx.accounts.factory_state.key();
+        index.bump = ctx.bumps.timelock_index;
+        index.insert(PendingTimelock {
+            entry: entry.key(),
+            action_hash: entry.action_hash,
+            execute_after: entry.execute_after,
+        })
+    }
+
     pub fn mint_ptkn(ctx: Context<MintPtkn>, amount: u64) -> Result<()> {
         require!(amount > 0, FactoryError::InvalidAmount);
         let factory_state = &ctx.accounts.factory_state;
-        require!(!factory_state.paused, FactoryError::Paused);
+        require!(
+            !factory_state.is_paused(PAUSE_PTKN_MINTING),
+            FactoryError::Paused
+        );
 
         let mapping = &ctx.accounts.mint_mapping;
         require!(mapping.has_ptkn, FactoryError::PtknMintDisabled);
@@ -353,25 +1805,13 @@ pub mod ptf_factory {
             ctx.accounts.ptkn_mint.key(),
             FactoryError::PtknMintMismatch
         );
+        mapping.check_ptkn_supply(ctx.accounts.ptkn_mint.supply, amount)?;
 
-        let (expected_pool, _) = Pubkey::find_program_address(
-            &[seeds::POOL, mapping.origin_mint.as_ref()],
-            &PTF_POOL_PROGRAM_ID,
-        );
-        require_keys_eq!(
-            expected_pool,
-            ctx.accounts.pool_authority.key(),
-            FactoryError::PoolAuthorityMismatch
-        );
-        require!(
-            ctx.accounts.pool_authority.is_signer,
-            FactoryError::PoolAuthorityMismatch
-        );
-        require_keys_eq!(
-            *ctx.accounts.pool_authority.owner,
-            PTF_POOL_PROGRAM_ID,
-            FactoryError::PoolAuthorityMismatch
-        );
+        check_pool_authority(
+            &ctx.accounts.factory_state,
+            &ctx.accounts.pool_authority,
+            &mapping.origin_mint,
+        )?;
 
         let bump_seed = &[factory_state.bump];
         let signer_seeds: [&[u8]; 3] = [seeds::FACTORY, crate::ID.as_ref(), bump_seed];
@@ -388,34 +1828,375 @@ pub mod ptf_factory {
             &signer_seeds_for_cpi,
         );
         token_interface::mint_to(cpi_ctx, amount)?;
+
+        let mapping = &mut ctx.accounts.mint_mapping;
+        mapping.total_minted = mapping
+            .total_minted
+            .checked_add(u128::from(amount))
+            .ok_or(FactoryError::AmountOverflow)?;
+        emit!(PtknMinted {
+            origin_mint: mapping.origin_mint,
+            ptkn_mint: mapping.ptkn_mint,
+            amount,
+            total_minted: mapping.total_minted,
+            total_burned: mapping.total_burned,
+        });
         Ok(())
     }
+
+    /// Burns pTKN from `source_token_account` when the pool re-shields it. The holder
+    /// signs for the burn; the pool PDA must co-sign so twin supply only drops alongside
+    /// a matching note.
+    pub fn burn_ptkn(ctx: Context<BurnPtkn>, amount: u64) -> Result<()> {
+        require!(amount > 0, FactoryError::InvalidAmount);
+        require!(!ctx.accounts.factory_state.paused, FactoryError::Paused);
+
+        let mapping = &ctx.accounts.mint_mapping;
+        require!(mapping.has_ptkn, FactoryError::PtknMintDisabled);
+        require_keys_eq!(
+            mapping.ptkn_mint,
+            ctx.accounts.ptkn_mint.key(),
+            FactoryError::PtknMintMismatch
+        );
+        require_keys_eq!(
+            ctx.accounts.source_token_account.mint,
+            ctx.accounts.ptkn_mint.key(),
+            FactoryError::PtknMintMismatch
+        );
+
+        check_pool_authority(
+            &ctx.accounts.factory_state,
+            &ctx.accounts.pool_authority,
+            &mapping.origin_mint,
+        )?;
+
+        let cpi_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Burn {
+                mint: ctx.accounts.ptkn_mint.to_account_info(),
+                from: ctx.accounts.source_token_account.to_account_info(),
+                authority: ctx.accounts.owner.to_account_info(),
+            },
+        );
+        token_interface::burn(cpi_ctx, amount)?;
+
+        let mapping = &mut ctx.accounts.mint_mapping;
+        mapping.total_burned = mapping
+            .total_burned
+            .checked_add(u128::from(amount))
+            .ok_or(FactoryError::AmountOverflow)?;
+        emit!(PtknBurned {
+            origin_mint: mapping.origin_mint,
+            ptkn_mint: mapping.ptkn_mint,
+            amount,
+            total_minted: mapping.total_minted,
+            total_burned: mapping.total_burned,
+        });
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct InitializeFactory<'info> {
+    #[account(
+        init,
+        seeds = [seeds::FACTORY, crate::ID.as_ref()],
+        bump,
+        payer = payer,
+        space = FactoryState::SPACE,
+    )]
+    pub factory_state: Account<'info, FactoryState>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateFactoryAuthority<'info> {
+    #[account(mut, has_one = authority)]
+    pub factory_state: Account<'info, FactoryState>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeProtocolConfig<'info> {
+    #[account(has_one = authority)]
+    pub factory_state: Account<'info, FactoryState>,
+    pub authority: Signer<'info>,
+    #[account(
+        init,
+        seeds = [seeds::PROTOCOL_CONFIG],
+        bump,
+        payer = payer,
+        space = ProtocolConfig::SPACE,
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateProtocolConfig<'info> {
+    #[account(has_one = authority)]
+    pub factory_state: Account<'info, FactoryState>,
+    pub authority: Signer<'info>,
+    #[account(mut, seeds = [seeds::PROTOCOL_CONFIG], bump = protocol_config.bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawTreasury<'info> {
+    #[account(mut, has_one = authority)]
+    pub factory_state: Account<'info, FactoryState>,
+    pub authority: Signer<'info>,
+    /// CHECK: Receives lamports only; must be `factory_state.treasury`.
+    #[account(mut)]
+    pub treasury: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct PauseFactory<'info> {
+    #[account(
+        mut,
+        constraint = factory_state.is_authority_or_guardian(&authority.key()) @ FactoryError::Unauthorized,
+    )]
+    pub factory_state: Account<'info, FactoryState>,
+    /// The factory authority or the guardian.
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(metas: Vec<ProposalAccountMeta>, data: Vec<u8>)]
+pub struct CreateCouncilProposal<'info> {
+    #[account(mut, seeds = [seeds::FACTORY, crate::ID.as_ref()], bump = factory_state.bump)]
+    pub factory_state: Account<'info, FactoryState>,
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+    #[account(
+        init,
+        payer = proposer,
+        seeds = [
+            seeds::PROPOSAL,
+            factory_state.key().as_ref(),
+            &factory_state.council_proposals.to_le_bytes()
+        ],
+        bump,
+        space = CouncilProposal::space(metas.len(), data.len()),
+    )]
+    pub proposal: Account<'info, CouncilProposal>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveCouncilProposal<'info> {
+    #[account(seeds = [seeds::FACTORY, crate::ID.as_ref()], bump = factory_state.bump)]
+    pub factory_state: Account<'info, FactoryState>,
+    pub member: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [seeds::PROPOSAL, factory_state.key().as_ref(), &proposal.id.to_le_bytes()],
+        bump = proposal.bump,
+    )]
+    pub proposal: Account<'info, CouncilProposal>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteCouncilProposal<'info> {
+    /// Read-only here: the proposed instruction may rewrite it, and writing this copy back
+    /// on exit would undo that.
+    #[account(seeds = [seeds::FACTORY, crate::ID.as_ref()], bump = factory_state.bump)]
+    pub factory_state: Account<'info, FactoryState>,
+    /// CHECK: The council PDA; it only signs the proposed instruction.
+    #[account(seeds = [seeds::COUNCIL, factory_state.key().as_ref()], bump)]
+    pub council: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [seeds::PROPOSAL, factory_state.key().as_ref(), &proposal.id.to_le_bytes()],
+        bump = proposal.bump,
+    )]
+    pub proposal: Account<'info, CouncilProposal>,
+    pub executor: Signer<'info>,
+    /// CHECK: This program, invoked with the proposal's instruction.
+    #[account(address = crate::ID)]
+    pub factory_program: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateFactoryState<'info> {
+    /// CHECK: Deserializing would fail on the legacy layout; owner is checked in the handler.
+    #[account(mut, seeds = [seeds::FACTORY, crate::ID.as_ref()], bump)]
+    pub factory_state: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(origin_mint: Pubkey)]
+pub struct MigrateMintMapping<'info> {
+    /// CHECK: Deserializing would fail on the legacy layout; owner and discriminator are
+    /// checked in the handler.
+    #[account(mut, seeds = [seeds::MINT_MAPPING, origin_mint.as_ref()], bump)]
+    pub mint_mapping: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RegisterMint<'info> {
+    #[account(mut, has_one = authority)]
+    pub factory_state: Account<'info, FactoryState>,
+    pub authority: Signer<'info>,
+    #[account(
+        init,
+        payer = payer,
+        seeds = [seeds::MINT_MAPPING, origin_mint.key().as_ref()],
+        bump,
+        space = MintMapping::SPACE,
+    )]
+    pub mint_mapping: Account<'info, MintMapping>,
+    /// An initialized SPL token or token-2022 mint.
+    pub origin_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(mut)]
+    pub ptkn_mint: Option<UncheckedAccount<'info>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+    pub rent: Sysvar<'info, Rent>,
+    pub system_program: Program<'info, System>,
+    /// CHECK: Metaplex metadata PDA of the new pTKN mint; checked before the CPI.
+    #[account(mut)]
+    pub ptkn_metadata: Option<UncheckedAccount<'info>>,
+    /// CHECK: Pinned to the token metadata program ID.
+    #[account(address = TOKEN_METADATA_PROGRAM_ID)]
+    pub token_metadata_program: Option<UncheckedAccount<'info>>,
+    /// CHECK: The origin mint's metadata PDA, if it has one; parsed for name and symbol.
+    pub origin_metadata: Option<UncheckedAccount<'info>>,
+    /// Registration template whose parameters replace the instruction's own.
+    #[account(
+        seeds = [seeds::TEMPLATE, template.template_id.to_le_bytes().as_ref()],
+        bump = template.bump,
+    )]
+    pub template: Option<Account<'info, RegistrationTemplate>>,
+}
+
+#[derive(Accounts)]
+#[instruction(template_id: u32)]
+pub struct SetRegistrationTemplate<'info> {
+    #[account(has_one = authority)]
+    pub factory_state: Account<'info, FactoryState>,
+    pub authority: Signer<'info>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [seeds::TEMPLATE, template_id.to_le_bytes().as_ref()],
+        bump,
+        space = RegistrationTemplate::SPACE,
+    )]
+    pub template: Account<'info, RegistrationTemplate>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseRegistrationTemplate<'info> {
+    #[account(has_one = authority)]
+    pub factory_state: Account<'info, FactoryState>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [seeds::TEMPLATE, template.template_id.to_le_bytes().as_ref()],
+        bump = template.bump,
+        close = authority,
+    )]
+    pub template: Account<'info, RegistrationTemplate>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RegisterMintBonded<'info> {
+    #[account(mut)]
+    pub factory_state: Account<'info, FactoryState>,
+    #[account(mut)]
+    pub registrant: Signer<'info>,
+    #[account(
+        init,
+        payer = registrant,
+        seeds = [seeds::MINT_MAPPING, origin_mint.key().as_ref()],
+        bump,
+        space = MintMapping::SPACE,
+    )]
+    pub mint_mapping: Account<'info, MintMapping>,
+    /// An initialized SPL token or token-2022 mint.
+    pub origin_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = registrant,
+        seeds = [seeds::REGISTRATION_BOND, origin_mint.key().as_ref()],
+        bump,
+        space = RegistrationBond::SPACE,
+    )]
+    pub bond: Account<'info, RegistrationBond>,
+    pub rent: Sysvar<'info, Rent>,
+    pub system_program: Program<'info, System>,
+    /// Registration template whose parameters the mapping takes.
+    #[account(
+        seeds = [seeds::TEMPLATE, template.template_id.to_le_bytes().as_ref()],
+        bump = template.bump,
+    )]
+    pub template: Option<Account<'info, RegistrationTemplate>>,
 }
 
 #[derive(Accounts)]
-pub struct InitializeFactory<'info> {
+pub struct ResolveRegistrationBond<'info> {
+    #[account(mut, has_one = authority)]
+    pub factory_state: Account<'info, FactoryState>,
+    pub authority: Signer<'info>,
     #[account(
-        init,
-        seeds = [seeds::FACTORY, crate::ID.as_ref()],
-        bump,
-        payer = payer,
-        space = FactoryState::SPACE,
+        mut,
+        seeds = [seeds::REGISTRATION_BOND, bond.origin_mint.as_ref()],
+        bump = bond.bump,
+        has_one = registrant,
+        close = registrant,
     )]
-    pub factory_state: Account<'info, FactoryState>,
+    pub bond: Account<'info, RegistrationBond>,
+    /// CHECK: The bond's registrant, pinned by `has_one`; only receives lamports.
     #[account(mut)]
-    pub payer: Signer<'info>,
-    pub system_program: Program<'info, System>,
+    pub registrant: UncheckedAccount<'info>,
+    #[account(mut, seeds = [seeds::MINT_MAPPING, bond.origin_mint.as_ref()], bump = mint_mapping.bump)]
+    pub mint_mapping: Account<'info, MintMapping>,
 }
 
 #[derive(Accounts)]
-pub struct UpdateFactoryAuthority<'info> {
-    #[account(mut, has_one = authority)]
+pub struct UpdatePtknMetadata<'info> {
+    #[account(has_one = authority)]
     pub factory_state: Account<'info, FactoryState>,
     pub authority: Signer<'info>,
+    #[account(seeds = [seeds::MINT_MAPPING, mint_mapping.origin_mint.as_ref()], bump = mint_mapping.bump)]
+    pub mint_mapping: Account<'info, MintMapping>,
+    /// CHECK: The mapping's pTKN mint.
+    #[account(address = mint_mapping.ptkn_mint @ FactoryError::PtknMintMismatch)]
+    pub ptkn_mint: UncheckedAccount<'info>,
+    /// CHECK: Metaplex metadata PDA of `ptkn_mint`, or the mint itself when it stores its
+    /// own metadata; checked before the CPI.
+    #[account(mut)]
+    pub ptkn_metadata: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// CHECK: The token metadata program, or token-2022 for in-mint metadata; checked in
+    /// the handler.
+    pub token_metadata_program: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
 }
 
+#[event_cpi]
 #[derive(Accounts)]
-pub struct RegisterMint<'info> {
+pub struct RegisterAndDeploy<'info> {
     #[account(mut, has_one = authority)]
     pub factory_state: Account<'info, FactoryState>,
     pub authority: Signer<'info>,
@@ -427,15 +2208,51 @@ pub struct RegisterMint<'info> {
         space = MintMapping::SPACE,
     )]
     pub mint_mapping: Account<'info, MintMapping>,
-    /// CHECK: The factory only records the origin mint address.
-    pub origin_mint: UncheckedAccount<'info>,
-    #[account(mut)]
-    pub payer: Signer<'info>,
+    #[account(mint::token_program = token_program)]
+    pub origin_mint: InterfaceAccount<'info, Mint>,
     #[account(mut)]
     pub ptkn_mint: Option<UncheckedAccount<'info>>,
-    pub token_program: Option<Interface<'info, TokenInterface>>,
+    /// CHECK: Created and validated by `ptf_vault::initialize_vault`.
+    #[account(mut)]
+    pub vault_state: UncheckedAccount<'info>,
+    /// CHECK: Created by the associated token program as the vault's ATA.
+    #[account(mut)]
+    pub vault_token_account: UncheckedAccount<'info>,
+    /// CHECK: Must be the pool PDA for `origin_mint`; created by `ptf_pool::initialize_pool`.
+    #[account(mut)]
+    pub pool_state: UncheckedAccount<'info>,
+    /// CHECK: Created and validated by `ptf_pool::initialize_pool`.
+    #[account(mut)]
+    pub nullifier_set: UncheckedAccount<'info>,
+    /// CHECK: Created and validated by `ptf_pool::initialize_pool`.
+    #[account(mut)]
+    pub note_ledger: UncheckedAccount<'info>,
+    /// CHECK: Created and validated by `ptf_pool::initialize_pool`.
+    #[account(mut)]
+    pub commitment_tree: UncheckedAccount<'info>,
+    /// CHECK: Created and validated by `ptf_pool::initialize_pool`.
+    #[account(mut)]
+    pub hook_config: UncheckedAccount<'info>,
+    /// CHECK: Checked by `ptf_pool::initialize_pool`.
+    pub verifier_program: UncheckedAccount<'info>,
+    /// Pinned on the mapping; `ptf_pool::initialize_pool` checks it further.
+    pub verifying_key: Account<'info, VerifyingKeyAccount>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub vault_program: Program<'info, PtfVault>,
+    /// CHECK: Pinned to the pool program ID.
+    #[account(address = PTF_POOL_PROGRAM_ID)]
+    pub pool_program: UncheckedAccount<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
     pub rent: Sysvar<'info, Rent>,
     pub system_program: Program<'info, System>,
+    /// Registration template applied to the mapping and the new pool.
+    #[account(
+        seeds = [seeds::TEMPLATE, template.template_id.to_le_bytes().as_ref()],
+        bump = template.bump,
+    )]
+    pub template: Option<Account<'info, RegistrationTemplate>>,
 }
 
 #[derive(Accounts)]
@@ -449,6 +2266,8 @@ pub struct UpdateMint<'info> {
     pub ptkn_mint: Option<UncheckedAccount<'info>>,
     pub token_program: Option<Interface<'info, TokenInterface>>,
     pub rent: Sysvar<'info, Rent>,
+    /// CHECK: The origin mint's metadata PDA, if it has one; parsed for name and symbol.
+    pub origin_metadata: Option<UncheckedAccount<'info>>,
 }
 
 #[derive(Accounts)]
@@ -460,6 +2279,76 @@ pub struct MutationMintState<'info> {
     pub mint_mapping: Account<'info, MintMapping>,
 }
 
+#[derive(Accounts)]
+pub struct RegisterVerifyingKey<'info> {
+    #[account(has_one = authority)]
+    pub factory_state: Account<'info, FactoryState>,
+    pub authority: Signer<'info>,
+    /// CHECK: Created and validated by `ptf_verifier_groth16::initialize_verifying_key`.
+    #[account(mut)]
+    pub verifier_state: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub verifier_program: Program<'info, PtfVerifierGroth16>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PinVerifyingKey<'info> {
+    #[account(has_one = authority)]
+    pub factory_state: Account<'info, FactoryState>,
+    pub authority: Signer<'info>,
+    #[account(mut, seeds = [seeds::MINT_MAPPING, mint_mapping.origin_mint.as_ref()], bump = mint_mapping.bump)]
+    pub mint_mapping: Account<'info, MintMapping>,
+    pub verifying_key: Account<'info, VerifyingKeyAccount>,
+}
+
+#[derive(Accounts)]
+pub struct OperateMintState<'info> {
+    #[account(
+        constraint = factory_state.is_authority_or_operator(&authority.key()) @ FactoryError::Unauthorized,
+    )]
+    pub factory_state: Account<'info, FactoryState>,
+    /// The factory authority or the operator.
+    pub authority: Signer<'info>,
+    #[account(mut, seeds = [seeds::MINT_MAPPING, mint_mapping.origin_mint.as_ref()], bump = mint_mapping.bump)]
+    pub mint_mapping: Account<'info, MintMapping>,
+}
+
+#[derive(Accounts)]
+pub struct LinkPool<'info> {
+    #[account(has_one = authority)]
+    pub factory_state: Account<'info, FactoryState>,
+    pub authority: Signer<'info>,
+    #[account(mut, seeds = [seeds::MINT_MAPPING, mint_mapping.origin_mint.as_ref()], bump = mint_mapping.bump)]
+    pub mint_mapping: Account<'info, MintMapping>,
+    /// CHECK: Must be the initialized pool PDA for the mapping's mint under an allowlisted
+    /// pool program; checked by the handler.
+    pub pool_state: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseMintMapping<'info> {
+    #[account(has_one = authority)]
+    pub factory_state: Account<'info, FactoryState>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [seeds::MINT_MAPPING, mint_mapping.origin_mint.as_ref()],
+        bump = mint_mapping.bump,
+        close = authority,
+    )]
+    pub mint_mapping: Account<'info, MintMapping>,
+    /// CHECK: The mint's pool PDA under the first allowlisted pool program; the PDAs
+    /// under the others follow as remaining accounts in allowlist order. The handler
+    /// requires all of them to hold no data.
+    pub pool_state: UncheckedAccount<'info>,
+    /// CHECK: The mint's registration bond PDA; the handler requires it to hold no data.
+    #[account(seeds = [seeds::REGISTRATION_BOND, mint_mapping.origin_mint.as_ref()], bump)]
+    pub registration_bond: UncheckedAccount<'info>,
+}
+
 #[derive(Accounts)]
 #[instruction(salt: [u8; 32], action: TimelockAction)]
 pub struct QueueTimelockAction<'info> {
@@ -478,6 +2367,14 @@ pub struct QueueTimelockAction<'info> {
     pub payer: Signer<'info>,
     pub system_program: Program<'info, System>,
     pub mint_mapping: Option<Account<'info, MintMapping>>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [seeds::TIMELOCK_INDEX, factory_state.key().as_ref()],
+        bump,
+        space = TimelockIndex::SPACE,
+    )]
+    pub timelock_index: Account<'info, TimelockIndex>,
 }
 
 #[derive(Accounts)]
@@ -504,6 +2401,84 @@ pub struct ExecuteTimelockAction<'info> {
     #[account(mut)]
     pub executor: Signer<'info>,
     pub rent: Sysvar<'info, Rent>,
+    #[account(
+        mut,
+        seeds = [seeds::TIMELOCK_INDEX, factory_state.key().as_ref()],
+        bump = timelock_index.bump,
+    )]
+    pub timelock_index: Account<'info, TimelockIndex>,
+    /// CHECK: Receives lamports only; checked against `factory_state.treasury` for
+    /// `WithdrawTreasury`.
+    #[account(mut)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+    /// The key `PinVerifyingKey` pins.
+    pub verifying_key: Option<Account<'info, VerifyingKeyAccount>>,
+    /// CHECK: The target pool of the pool administration actions; checked against the
+    /// action's mint and `pool_program`.
+    #[account(mut)]
+    pub pool_state: Option<UncheckedAccount<'info>>,
+    /// CHECK: Must be an allowlisted pool program; checked by the handler.
+    pub pool_program: Option<UncheckedAccount<'info>>,
+    /// CHECK: The target pool's nullifier set, which the pool's admin instructions take
+    /// alongside the pool and check themselves.
+    #[account(mut)]
+    pub nullifier_set: Option<UncheckedAccount<'info>>,
+    /// Target of the protocol config actions.
+    #[account(mut, seeds = [seeds::PROTOCOL_CONFIG], bump = protocol_config.bump)]
+    pub protocol_config: Option<Account<'info, ProtocolConfig>>,
+}
+
+#[derive(Accounts)]
+pub struct CloseExpiredTimelockAction<'info> {
+    #[account(seeds = [seeds::FACTORY, crate::ID.as_ref()], bump = factory_state.bump)]
+    pub factory_state: Account<'info, FactoryState>,
+    #[account(
+        mut,
+        seeds = [
+            seeds::TIMELOCK,
+            factory_state.key().as_ref(),
+            timelock_entry.salt.as_ref()
+        ],
+        bump = timelock_entry.bump,
+        constraint = timelock_entry.factory == factory_state.key() @ FactoryError::TimelockInvalidFactory,
+        close = closer,
+    )]
+    pub timelock_entry: Account<'info, TimelockEntry>,
+    #[account(
+        mut,
+        seeds = [seeds::TIMELOCK_INDEX, factory_state.key().as_ref()],
+        bump = timelock_index.bump,
+    )]
+    pub timelock_index: Account<'info, TimelockIndex>,
+    #[account(mut)]
+    pub closer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct IndexTimelockEntry<'info> {
+    #[account(seeds = [seeds::FACTORY, crate::ID.as_ref()], bump = factory_state.bump)]
+    pub factory_state: Account<'info, FactoryState>,
+    #[account(
+        seeds = [
+            seeds::TIMELOCK,
+            factory_state.key().as_ref(),
+            timelock_entry.salt.as_ref()
+        ],
+        bump = timelock_entry.bump,
+        constraint = timelock_entry.factory == factory_state.key() @ FactoryError::TimelockInvalidFactory,
+    )]
+    pub timelock_entry: Account<'info, TimelockEntry>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [seeds::TIMELOCK_INDEX, factory_state.key().as_ref()],
+        bump,
+        space = TimelockIndex::SPACE,
+    )]
+    pub timelock_index: Account<'info, TimelockIndex>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
@@ -515,11 +2490,12 @@ pub struct MintPtkn<'info> {
     )]
     pub factory_state: Account<'info, FactoryState>,
     #[account(
+        mut,
         seeds = [seeds::MINT_MAPPING, mint_mapping.origin_mint.as_ref()],
         bump = mint_mapping.bump
     )]
     pub mint_mapping: Account<'info, MintMapping>,
-    /// CHECK: Verified against the expected PDA derived from the pool program id.
+    /// CHECK: Verified against the PDA derived from its owner, an allowlisted pool program.
     pub pool_authority: AccountInfo<'info>,
     #[account(mut)]
     pub ptkn_mint: InterfaceAccount<'info, Mint>,
@@ -528,10 +2504,37 @@ pub struct MintPtkn<'info> {
     pub token_program: Interface<'info, TokenInterface>,
 }
 
+#[derive(Accounts)]
+pub struct BurnPtkn<'info> {
+    #[account(
+        seeds = [seeds::FACTORY, crate::ID.as_ref()],
+        bump = factory_state.bump
+    )]
+    pub factory_state: Account<'info, FactoryState>,
+    #[account(
+        mut,
+        seeds = [seeds::MINT_MAPPING, mint_mapping.origin_mint.as_ref()],
+        bump = mint_mapping.bump
+    )]
+    pub mint_mapping: Account<'info, MintMapping>,
+    /// CHECK: Verified against the PDA derived from its owner, an allowlisted pool program.
+    pub pool_authority: AccountInfo<'info>,
+    #[account(mut)]
+    pub ptkn_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub source_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
 #[derive(Accounts)]
 pub struct CancelTimelockAction<'info> {
-    #[account(mut, has_one = authority)]
+    #[account(
+        mut,
+        constraint = factory_state.is_authority_or_guardian(&authority.key()) @ FactoryError::Unauthorized,
+    )]
     pub factory_state: Account<'info, FactoryState>,
+    /// The factory authority or the guardian.
     pub authority: Signer<'info>,
     #[account(
         mut,
@@ -545,21 +2548,351 @@ pub struct CancelTimelockAction<'info> {
         close = authority,
     )]
     pub timelock_entry: Account<'info, TimelockEntry>,
+    #[account(
+        mut,
+        seeds = [seeds::TIMELOCK_INDEX, factory_state.key().as_ref()],
+        bump = timelock_index.bump,
+    )]
+    pub timelock_index: Account<'info, TimelockIndex>,
+}
+
+#[account]
+pub struct FactoryState {
+    pub authority: Pubkey,
+    pub default_fee_bps: u16,
+    pub default_features: FeatureFlags,
+    /// Pauses every scope at once, as well as timelock queueing and `burn_ptkn`.
+    pub paused: bool,
+    pub timelock_seconds: i64,
+    pub bump: u8,
+    pub last_updated_slot: u64,
+    /// Council members in the first `council_len` slots. A non-empty council holds the
+    /// authority through its PDA, `["council", factory_state]`.
+    pub council_members: [Pubkey; FactoryState::MAX_COUNCIL_MEMBERS],
+    pub council_len: u8,
+    pub council_threshold: u8,
+    /// Bumped on every membership change so approvals never outlive the council that
+    /// gave them.
+    pub council_epoch: u32,
+    pub council_proposals: u64,
+    /// Set while the authority is an SPL Governance native treasury; default otherwise.
+    pub governance_program: Pubkey,
+    pub governance: Pubkey,
+    /// Once set, queued actions wait the delay for their kind in `timelock_delays`
+    /// instead of `timelock_seconds`.
+    pub has_timelock_delays: bool,
+    pub timelock_delays: TimelockDelays,
+    /// May cancel queued timelock actions and pause the factory; default when unset.
+    pub guardian: Pubkey,
+    /// When set, only the authority or guardian may execute ready timelock actions.
+    pub restricted_execution: bool,
+    /// Pool programs whose pool PDAs may mint and burn pTKN, in the first
+    /// `pool_programs_len` slots. Empty on factories created before the allowlist, which
+    /// accept only `PTF_POOL_PROGRAM_ID`.
+    pub pool_programs: [Pubkey; FactoryState::MAX_POOL_PROGRAMS],
+    pub pool_programs_len: u8,
+    /// Receives collected fees on `withdraw_treasury`; default when unset.
+    pub treasury: Pubkey,
+    /// Lamports `register_mint` and `register_and_deploy` charge the payer; 0 is free.
+    pub registration_fee_lamports: u64,
+    /// Collected fees held in this account on top of its rent.
+    pub treasury_balance: u64,
+    /// Largest origin mint decimals registration accepts; 0 means
+    /// `DEFAULT_MAX_DECIMALS`.
+    pub max_decimals: u8,
+    /// `PAUSE_*` scopes paused on their own, on top of `paused`.
+    pub paused_scopes: u8,
+    /// May freeze and thaw mappings and nothing else; default when unset.
+    pub operator: Pubkey,
+    /// Lamports `register_mint_bonded` holds from a permissionless registrant; 0 turns
+    /// permissionless registration off.
+    pub registration_bond_lamports: u64,
+}
+
+impl FactoryState {
+    pub const MAX_COUNCIL_MEMBERS: usize = 10;
+    pub const MAX_POOL_PROGRAMS: usize = 4;
+    const COUNCIL_SPACE: usize = 32 * Self::MAX_COUNCIL_MEMBERS + 1 + 1 + 4 + 8;
+    const POOL_PROGRAMS_SPACE: usize = 32 * Self::MAX_POOL_PROGRAMS + 1;
+    /// Fields appended after the original layout; `migrate_factory_state` grows older
+    /// accounts to fit them.
+    const APPENDED_SPACE: usize = Self::COUNCIL_SPACE
+        + 32
+        + 32
+        + 1
+        + TimelockDelays::SIZE
+        + 32
+        + 1
+        + Self::POOL_PROGRAMS_SPACE
+        + 32
+        + 8
+        + 8
+        + 1
+        + 1
+        + 32
+        + 8;
+    pub const SPACE: usize = 8 + 32 + 2 + 8 + 1 + 8 + 1 + 8 + Self::APPENDED_SPACE;
+    /// Offset of `default_features`, and the largest layout that still stored it in one
+    /// byte. `migrate_factory_state` widens it in older accounts.
+    pub const FEATURES_OFFSET: usize = 8 + 32 + 2;
+    pub const NARROW_FEATURES_SPACE: usize = 719;
+
+    /// Seconds a queued `action` waits before it can execute.
+    pub fn timelock_delay(&self, action: &TimelockAction) -> i64 {
+        if self.has_timelock_delays {
+            self.timelock_delays.for_action(action)
+        } else {
+            self.timelock_seconds
+        }
+    }
+
+    /// Whether `scope` is paused, on its own or by the factory-wide `paused`.
+    pub fn is_paused(&self, scope: u8) -> bool {
+        self.paused || self.paused_scopes & scope != 0
+    }
+
+    pub fn decimals_limit(&self) -> u8 {
+        if self.max_decimals == 0 {
+            DEFAULT_MAX_DECIMALS
+        } else {
+            self.max_decimals
+        }
+    }
+
+    pub fn is_authority_or_guardian(&self, signer: &Pubkey) -> bool {
+        *signer == self.authority
+            || (self.guardian != Pubkey::default() && *signer == self.guardian)
+    }
+
+    pub fn is_authority_or_operator(&self, signer: &Pubkey) -> bool {
+        *signer == self.authority
+            || (self.operator != Pubkey::default() && *signer == self.operator)
+    }
+
+    pub fn is_pool_program(&self, program: &Pubkey) -> bool {
+        self.allowed_pool_programs().contains(program)
+    }
+
+    /// The allowlisted pool programs; just `PTF_POOL_PROGRAM_ID` on a legacy factory.
+    pub fn allowed_pool_programs(&self) -> &[Pubkey] {
+        if self.pool_programs_len == 0 {
+            return core::slice::from_ref(&PTF_POOL_PROGRAM_ID);
+        }
+        &self.pool_programs[..self.pool_programs_len as usize]
+    }
+
+    /// Adds or removes an allowlisted pool program. A legacy factory's implicit default
+    /// entry is made explicit first, so removing it or adding a second program keeps the
+    /// allowlist accurate.
+    pub fn set_pool_program(&mut self, program: Pubkey, allowed: bool) -> Result<()> {
+        require!(
+            program != Pubkey::default(),
+            FactoryError::PoolProgramInvalid
+        );
+        if self.pool_programs_len == 0 {
+            self.pool_programs[0] = PTF_POOL_PROGRAM_ID;
+            self.pool_programs_len = 1;
+        }
+        let len = self.pool_programs_len as usize;
+        let position = self.pool_programs[..len]
+            .iter()
+            .position(|candidate| *candidate == program);
+        match (position, allowed) {
+            (None, true) => {
+                require!(
+                    len < Self::MAX_POOL_PROGRAMS,
+                    FactoryError::PoolProgramAllowlistFull
+                );
+                self.pool_programs[len] = program;
+                self.pool_programs_len += 1;
+            }
+            (Some(index), false) => {
+                // Keep at least one pool program; an empty allowlist means the default.
+                require!(len > 1, FactoryError::PoolProgramInvalid);
+                self.pool_programs[index] = self.pool_programs[len - 1];
+                self.pool_programs[len - 1] = Pubkey::default();
+                self.pool_programs_len -= 1;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn council_index(&self, member: &Pubkey) -> Option<usize> {
+        self.council_members[..self.council_len as usize]
+            .iter()
+            .position(|candidate| candidate == member)
+    }
+}
+
+/// Protocol-wide limits at `["protocol-config"]`, read by every pool on shield and
+/// unshield so one update applies to all of them.
+#[account]
+pub struct ProtocolConfig {
+    pub factory: Pubkey,
+    /// Largest shield or unshield rate a pool may charge.
+    pub max_fee_bps: u16,
+    /// Halts every shield and unshield, emergency exits included, while set.
+    pub kill_switch: bool,
+    /// Verifier programs pools may call, in the first `verifier_programs_len` slots.
+    pub verifier_programs: [Pubkey; ProtocolConfig::MAX_VERIFIER_PROGRAMS],
+    pub verifier_programs_len: u8,
+    pub bump: u8,
+}
+
+impl ProtocolConfig {
+    pub const MAX_VERIFIER_PROGRAMS: usize = 4;
+    pub const SPACE: usize = 8 + 32 + 2 + 1 + 32 * Self::MAX_VERIFIER_PROGRAMS + 1 + 1;
+
+    pub fn is_verifier_program(&self, program: &Pubkey) -> bool {
+        self.verifier_programs[..self.verifier_programs_len as usize].contains(program)
+    }
+
+    /// Adds or removes an allowed verifier program, keeping at least one.
+    pub fn set_verifier_program(&mut self, program: Pubkey, allowed: bool) -> Result<()> {
+        require!(
+            program != Pubkey::default(),
+            FactoryError::VerifierProgramInvalid
+        );
+        let len = self.verifier_programs_len as usize;
+        let position = self.verifier_programs[..len]
+            .iter()
+            .position(|candidate| *candidate == program);
+        match (position, allowed) {
+            (None, true) => {
+                require!(
+                    len < Self::MAX_VERIFIER_PROGRAMS,
+                    FactoryError::VerifierProgramAllowlistFull
+                );
+                self.verifier_programs[len] = program;
+                self.verifier_programs_len += 1;
+            }
+            (Some(index), false) => {
+                require!(len > 1, FactoryError::VerifierProgramInvalid);
+                self.verifier_programs[index] = self.verifier_programs[len - 1];
+                self.verifier_programs[len - 1] = Pubkey::default();
+                self.verifier_programs_len -= 1;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+pub fn council_address(factory_state: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[seeds::COUNCIL, factory_state.as_ref()], &crate::ID)
+}
+
+/// The native treasury of an SPL Governance `governance` account. Governance-executed
+/// transactions sign with it, so it can hold the factory authority.
+pub fn governance_native_treasury(governance_program: &Pubkey, governance: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[GOVERNANCE_NATIVE_TREASURY, governance.as_ref()],
+        governance_program,
+    )
+    .0
+}
+
+/// The factory-signed pTKN mint address for `origin_mint`, `["ptkn-mint", origin_mint]`.
+pub fn ptkn_mint_address(origin_mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[seeds::PTKN_MINT, origin_mint.as_ref()], &crate::ID)
+}
+
+/// Metaplex metadata address of `mint`, `["metadata", token_metadata_program, mint]`.
+pub fn token_metadata_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            TOKEN_METADATA_SEED,
+            TOKEN_METADATA_PROGRAM_ID.as_ref(),
+            mint.as_ref(),
+        ],
+        &TOKEN_METADATA_PROGRAM_ID,
+    )
+    .0
+}
+
+/// Name, symbol and URI shown by wallets for a pTKN mint.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PtknMetadata {
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+}
+
+impl PtknMetadata {
+    /// Metaplex's field limits.
+    pub const MAX_NAME_LEN: usize = 32;
+    pub const MAX_SYMBOL_LEN: usize = 10;
+    pub const MAX_URI_LEN: usize = 200;
+
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            self.name.len() <= Self::MAX_NAME_LEN
+                && self.symbol.len() <= Self::MAX_SYMBOL_LEN
+                && self.uri.len() <= Self::MAX_URI_LEN,
+            FactoryError::PtknMetadataInvalid
+        );
+        Ok(())
+    }
+
+    /// "Private USD Coin" / "pUSDC" for an origin with metadata, otherwise a label from
+    /// the first characters of the origin mint address. The origin's URI is reused.
+    pub fn for_origin(origin_mint: &Pubkey, origin: Option<PtknMetadata>) -> Self {
+        let (name, symbol, uri) = match origin {
+            Some(origin) => (origin.name, origin.symbol, origin.uri),
+            None => {
+                let label: String = origin_mint.to_string().chars().take(4).collect();
+                (label.clone(), label, String::new())
+            }
+        };
+        Self {
+            name: truncate_utf8(format!("Private {name}"), Self::MAX_NAME_LEN),
+            symbol: truncate_utf8(format!("p{symbol}"), Self::MAX_SYMBOL_LEN),
+            uri: truncate_utf8(uri, Self::MAX_URI_LEN),
+        }
+    }
+}
+
+fn truncate_utf8(mut value: String, max_len: usize) -> String {
+    if value.len() > max_len {
+        let mut end = max_len;
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        value.truncate(end);
+    }
+    value
+}
+
+/// One council-approved factory instruction, at `["proposal", factory_state, id]`.
+#[account]
+pub struct CouncilProposal {
+    pub factory: Pubkey,
+    pub id: u64,
+    pub proposer: Pubkey,
+    pub council_epoch: u32,
+    /// Bit `i` is set once council member `i` has approved.
+    pub approvals: u16,
+    pub executed: bool,
+    pub metas: Vec<ProposalAccountMeta>,
+    pub data: Vec<u8>,
+    pub bump: u8,
 }
 
-#[account]
-pub struct FactoryState {
-    pub authority: Pubkey,
-    pub default_fee_bps: u16,
-    pub default_features: FeatureFlags,
-    pub paused: bool,
-    pub timelock_seconds: i64,
-    pub bump: u8,
-    pub last_updated_slot: u64,
+impl CouncilProposal {
+    pub const MAX_ACCOUNTS: usize = 24;
+    pub const MAX_DATA: usize = 512;
+
+    pub const fn space(metas: usize, data: usize) -> usize {
+        8 + 32 + 8 + 32 + 4 + 2 + 1 + 4 + metas * (32 + 1 + 1) + 4 + data + 1
+    }
 }
 
-impl FactoryState {
-    pub const SPACE: usize = 8 + 32 + 2 + 1 + 1 + 8 + 1 + 8;
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ProposalAccountMeta {
+    pub pubkey: Pubkey,
+    pub is_signer: bool,
+    pub is_writable: bool,
 }
 
 #[account]
@@ -573,10 +2906,89 @@ pub struct MintMapping {
     pub fee_bps_override: u16,
     pub has_fee_override: bool,
     pub bump: u8,
+    /// [`PtknTokenProgram`] that owns `ptkn_mint`. Zero on mappings created before this
+    /// was recorded, whose twins live under the origin's token program.
+    pub ptkn_token_program: u8,
+    /// `PTKN_EXTENSION_*` bits the pTKN mint was created with.
+    pub ptkn_extensions: u8,
+    /// Most pTKN `mint_ptkn` may leave outstanding; 0 is uncapped.
+    pub max_ptkn_supply: u64,
+    /// Lifetime pTKN minted and burned through the factory. Mappings migrated from an
+    /// older layout count from their migration.
+    pub total_minted: u128,
+    pub total_burned: u128,
+    /// Accounts of the pool serving this mint, recorded by `register_and_deploy` or
+    /// `link_pool`. Default until the mint is linked to a pool.
+    pub pool: Pubkey,
+    pub vault: Pubkey,
+    pub commitment_tree: Pubkey,
+    pub nullifier_set: Pubkey,
+    /// Circuit pinned for this mint and the `ptf_verifier_groth16` key account carrying
+    /// it. Zero until pinned; once set, new pools for the mint must use this key.
+    pub circuit_tag: [u8; 32],
+    pub verifying_key: Pubkey,
+    /// Registration template the mint was registered from; default when none.
+    pub template: Pubkey,
 }
 
 impl MintMapping {
-    pub const SPACE: usize = 8 + 32 + 32 + 1 + 1 + 1 + 1 + 2 + 1 + 1 + 4;
+    pub const SPACE: usize = 8
+        + 32
+        + 32
+        + 1
+        + 1
+        + 1
+        + 8
+        + 2
+        + 1
+        + 1
+        + 1
+        + 1
+        + 8
+        + 16
+        + 16
+        + 32
+        + 32
+        + 32
+        + 32
+        + 32
+        + 32
+        + 32;
+    /// Size of the original layout. `migrate_mint_mapping` grows it, or any layout
+    /// since, to `SPACE`.
+    pub const LEGACY_SPACE: usize = 84;
+    /// Offset of `features`, and the largest layout that still stored it in one byte.
+    pub const FEATURES_OFFSET: usize = 8 + 32 + 32 + 1 + 1 + 1;
+    pub const NARROW_FEATURES_SPACE: usize = 122;
+
+    /// Fails when minting `amount` on top of `supply` would pass the cap.
+    pub fn check_ptkn_supply(&self, supply: u64, amount: u64) -> Result<()> {
+        if self.max_ptkn_supply == 0 {
+            return Ok(());
+        }
+        let next = supply
+            .checked_add(amount)
+            .ok_or(FactoryError::PtknSupplyCapExceeded)?;
+        require!(
+            next <= self.max_ptkn_supply,
+            FactoryError::PtknSupplyCapExceeded
+        );
+        Ok(())
+    }
+
+    /// Unshield fee for this mint's pools: the override if set, else `default_fee_bps`.
+    pub fn fee_bps(&self, default_fee_bps: u16) -> u16 {
+        if self.has_fee_override {
+            self.fee_bps_override
+        } else {
+            default_fee_bps
+        }
+    }
+
+    /// Whether the pTKN is a non-transferable receipt that only moves through the pool.
+    pub fn ptkn_non_transferable(&self) -> bool {
+        self.ptkn_extensions & PTKN_EXTENSION_NON_TRANSFERABLE != 0
+    }
 }
 
 #[account]
@@ -589,11 +3001,155 @@ pub struct TimelockEntry {
     pub executed: bool,
     pub action: TimelockAction,
     pub bump: u8,
+    /// Last moment the action may execute. Zero on entries queued before expiry existed,
+    /// which never expire.
+    pub expires_after: i64,
 }
 
 impl TimelockEntry {
-    pub const MAX_ACTION_SIZE: usize = 128;
-    pub const SPACE: usize = 8 + 32 + 32 + 32 + 8 + 8 + 1 + 1 + Self::MAX_ACTION_SIZE;
+    /// Fits the largest action, `SetCouncil` with a full council.
+    pub const MAX_ACTION_SIZE: usize = 1 + 4 + 32 * FactoryState::MAX_COUNCIL_MEMBERS + 1;
+    pub const SPACE: usize = 8 + 32 + 32 + 32 + 8 + 8 + 1 + 1 + Self::MAX_ACTION_SIZE + 8;
+    /// How long a ready action stays executable.
+    pub const EXECUTION_WINDOW: i64 = 7 * 86_400;
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_after != 0 && now > self.expires_after
+    }
+}
+
+/// Every pending timelock entry of a factory, at `["timelock-index", factory_state]`, so
+/// queued actions can be enumerated without knowing their salts. Entries leave the index
+/// when they are executed or canceled.
+#[account]
+pub struct TimelockIndex {
+    pub factory: Pubkey,
+    pub pending: Vec<PendingTimelock>,
+    pub bump: u8,
+}
+
+impl TimelockIndex {
+    pub const MAX_PENDING: usize = 32;
+    pub const SPACE: usize = 8 + 32 + 4 + Self::MAX_PENDING * PendingTimelock::SIZE + 1;
+
+    fn insert(&mut self, pending: PendingTimelock) -> Result<()> {
+        if self.pending.iter().any(|item| item.entry == pending.entry) {
+            return Ok(());
+        }
+        require!(
+            self.pending.len() < Self::MAX_PENDING,
+            FactoryError::TimelockIndexFull
+        );
+        self.pending.push(pending);
+        Ok(())
+    }
+
+    fn remove(&mut self, entry: &Pubkey) {
+        self.pending.retain(|item| item.entry != *entry);
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PendingTimelock {
+    pub entry: Pubkey,
+    pub action_hash: [u8; 32],
+    pub execute_after: i64,
+}
+
+impl PendingTimelock {
+    pub const SIZE: usize = 32 + 32 + 8;
+}
+
+/// Requires `pool_authority` to be the signing pool PDA of `origin_mint` under one of the
+/// factory's allowlisted pool programs.
+fn check_pool_authority(
+    state: &FactoryState,
+    pool_authority: &AccountInfo,
+    origin_mint: &Pubkey,
+) -> Result<()> {
+    let pool_program = pool_authority.owner;
+    require!(
+        state.is_pool_program(pool_program),
+        FactoryError::PoolProgramNotAllowed
+    );
+    let (expected_pool, _) =
+        Pubkey::find_program_address(&[seeds::POOL, origin_mint.as_ref()], pool_program);
+    require_keys_eq!(
+        expected_pool,
+        pool_authority.key(),
+        FactoryError::PoolAuthorityMismatch
+    );
+    require!(
+        pool_authority.is_signer,
+        FactoryError::PoolAuthorityMismatch
+    );
+    Ok(())
+}
+
+/// Moves the registration fee from `payer` into the factory state, where it is held as
+/// `treasury_balance` until withdrawn. Returns the event for the caller to emit when a
+/// fee was charged.
+fn collect_registration_fee<'info>(
+    factory_state: &mut Account<'info, FactoryState>,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+    origin_mint: Pubkey,
+) -> Result<Option<RegistrationFeeCollected>> {
+    let lamports = factory_state.registration_fee_lamports;
+    if lamports == 0 {
+        return Ok(None);
+    }
+    anchor_lang::system_program::transfer(
+        CpiContext::new(
+            system_program.to_account_info(),
+            anchor_lang::system_program::Transfer {
+                from: payer.to_account_info(),
+                to: factory_state.to_account_info(),
+            },
+        ),
+        lamports,
+    )?;
+    factory_state.treasury_balance = factory_state
+        .treasury_balance
+        .checked_add(lamports)
+        .ok_or(FactoryError::AmountOverflow)?;
+    Ok(Some(RegistrationFeeCollected {
+        origin_mint,
+        payer: payer.key(),
+        lamports,
+    }))
+}
+
+/// Pays `amount` of the collected fees to `treasury`, which must be the configured
+/// treasury. Only fees are ever paid out, so the state account stays rent-exempt.
+fn pay_from_treasury<'info>(
+    factory_state: &mut Account<'info, FactoryState>,
+    treasury: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    require!(
+        factory_state.treasury != Pubkey::default(),
+        FactoryError::TreasuryNotSet
+    );
+    require_keys_eq!(
+        treasury.key(),
+        factory_state.treasury,
+        FactoryError::TreasuryMismatch
+    );
+    require!(
+        amount > 0 && amount <= factory_state.treasury_balance,
+        FactoryError::InsufficientTreasury
+    );
+    factory_state.treasury_balance -= amount;
+    let state_info = factory_state.to_account_info();
+    **state_info.try_borrow_mut_lamports()? -= amount;
+    **treasury.try_borrow_mut_lamports()? += amount;
+    emit!(TreasuryWithdrawn {
+        factory: factory_state.key(),
+        treasury: treasury.key(),
+        amount,
+    });
+    Ok(())
 }
 
 fn ensure_direct_update_allowed(state: &FactoryState) -> Result<()> {
@@ -607,12 +3163,16 @@ fn apply_mint_update<'info>(
     factory_state: &Account<'info, FactoryState>,
     mapping: &mut MintMapping,
     params: &UpdateMintParams,
-    ptkn_mint: Option<&UncheckedAccount<'info>>,
-    token_program: Option<&Interface<'info, TokenInterface>>,
-    rent: Option<&Sysvar<'info, Rent>>,
-    payer: Option<&Signer<'info>>,
-    authority: Option<&Signer<'info>>,
+    accounts: PtknMintAccounts<'_, 'info>,
+    extensions: Option<&PtknMintExtensions>,
 ) -> Result<()> {
+    let PtknMintAccounts {
+        ptkn_mint,
+        token_program,
+        rent,
+        payer,
+        authority,
+    } = accounts;
     if let Some(fee) = params.fee_bps_override {
         require!(fee <= MAX_BPS, FactoryError::InvalidFeeBps);
         mapping.fee_bps_override = fee;
@@ -626,17 +3186,16 @@ fn apply_mint_update<'info>(
     if let Some(enable_ptkn) = params.enable_ptkn {
         if enable_ptkn {
             if !mapping.has_ptkn {
-                let mint_key = prepare_ptkn_mint(
+                prepare_ptkn_mint(
                     factory_state,
+                    mapping,
                     ptkn_mint,
                     token_program,
                     rent,
                     payer,
-                    mapping.decimals,
                     authority,
+                    extensions,
                 )?;
-                mapping.has_ptkn = true;
-                mapping.ptkn_mint = mint_key;
             } else if let Some(ptkn_mint) = ptkn_mint {
                 require_keys_eq!(
                     ptkn_mint.key(),
@@ -650,32 +3209,665 @@ fn apply_mint_update<'info>(
                 );
             }
         } else {
+            if mapping.has_ptkn {
+                // Dropping a twin with holders would strand them: they could no longer
+                // shield it back or redeem it. The mint itself is the proof of zero supply.
+                let ptkn_mint = ptkn_mint.ok_or(FactoryError::PtknMintMissing)?;
+                require_keys_eq!(
+                    ptkn_mint.key(),
+                    mapping.ptkn_mint,
+                    FactoryError::PtknMintMismatch
+                );
+                require!(
+                    load_mint_state(&ptkn_mint.to_account_info())?.supply == 0,
+                    FactoryError::PtknSupplyOutstanding
+                );
+            }
             mapping.has_ptkn = false;
             mapping.ptkn_mint = Pubkey::default();
+            mapping.ptkn_token_program = PtknTokenProgram::Unknown as u8;
+            mapping.ptkn_extensions = 0;
         }
     }
 
     Ok(())
 }
 
+fn validate_council(members: &[Pubkey], threshold: u8) -> Result<()> {
+    require!(
+        !members.is_empty()
+            && members.len() <= FactoryState::MAX_COUNCIL_MEMBERS
+            && threshold >= 1
+            && threshold as usize <= members.len(),
+        FactoryError::CouncilInvalid
+    );
+    for (idx, member) in members.iter().enumerate() {
+        require!(
+            !members[..idx].contains(member),
+            FactoryError::CouncilInvalid
+        );
+    }
+    Ok(())
+}
+
+/// Replaces the council with `members` and hands the authority to the council PDA.
+/// `members` must already have passed `validate_council`.
+fn install_council(
+    state: &mut FactoryState,
+    factory_key: Pubkey,
+    members: Vec<Pubkey>,
+    threshold: u8,
+) {
+    state.council_members = [Pubkey::default(); FactoryState::MAX_COUNCIL_MEMBERS];
+    state.council_members[..members.len()].copy_from_slice(&members);
+    state.council_len = members.len() as u8;
+    state.council_threshold = threshold;
+    state.council_epoch = state.council_epoch.wrapping_add(1);
+    state.authority = council_address(&factory_key).0;
+    state.governance_program = Pubkey::default();
+    state.governance = Pubkey::default();
+
+    emit!(CouncilUpdated {
+        factory: factory_key,
+        members,
+        threshold,
+        epoch: state.council_epoch,
+    });
+}
+
+fn remove_council(
+    state: &mut FactoryState,
+    factory_key: Pubkey,
+    new_authority: Pubkey,
+) -> Result<()> {
+    require!(state.council_len > 0, FactoryError::CouncilInvalid);
+    state.council_members = [Pubkey::default(); FactoryState::MAX_COUNCIL_MEMBERS];
+    state.council_len = 0;
+    state.council_threshold = 0;
+    state.council_epoch = state.council_epoch.wrapping_add(1);
+    state.authority = new_authority;
+
+    emit!(CouncilDissolved {
+        factory: factory_key,
+        new_authority,
+        epoch: state.council_epoch,
+    });
+    Ok(())
+}
+
+fn validate_governance_program(governance_program: &Pubkey) -> Result<()> {
+    require_keys_eq!(
+        *governance_program,
+        SPL_GOVERNANCE_PROGRAM_ID,
+        FactoryError::GovernanceInvalid
+    );
+    Ok(())
+}
+
+/// Hands the authority to the native treasury of `governance`, clearing any council.
+fn install_governance_authority(
+    state: &mut FactoryState,
+    factory_key: Pubkey,
+    governance_program: Pubkey,
+    governance: Pubkey,
+) {
+    let native_treasury = governance_native_treasury(&governance_program, &governance);
+    if state.council_len > 0 {
+        state.council_members = [Pubkey::default(); FactoryState::MAX_COUNCIL_MEMBERS];
+        state.council_len = 0;
+        state.council_threshold = 0;
+        state.council_epoch = state.council_epoch.wrapping_add(1);
+    }
+    state.authority = native_treasury;
+    state.governance_program = governance_program;
+    state.governance = governance;
+
+    emit!(GovernanceAuthoritySet {
+        factory: factory_key,
+        governance_program,
+        governance,
+        native_treasury,
+    });
+}
+
+fn validate_pause_scopes(scopes: u8) -> Result<()> {
+    require!(
+        scopes != 0 && scopes & !PAUSE_SCOPES_ALL == 0,
+        FactoryError::PauseScopesInvalid
+    );
+    Ok(())
+}
+
+/// Widens the one-byte feature field at `offset` of an account whose old layout was
+/// `old_len` bytes to a u64, moving the fields after it along. The account must already
+/// be sized for the new layout.
+fn widen_features(data: &mut [u8], offset: usize, old_len: usize) {
+    data.copy_within(offset + 1..old_len, offset + 8);
+    data[offset + 1..offset + 8].fill(0);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn register_mapping<'info>(
+    factory_state: &Account<'info, FactoryState>,
+    mapping: &mut Account<'info, MintMapping>,
+    origin_mint: Pubkey,
+    bump: u8,
+    params: RegisterMintParams,
+    ptkn_mint: Option<&UncheckedAccount<'info>>,
+    token_program: Option<&Interface<'info, TokenInterface>>,
+    rent: &Sysvar<'info, Rent>,
+    payer: &Signer<'info>,
+    authority: &Signer<'info>,
+    extensions: Option<&PtknMintExtensions>,
+) -> Result<()> {
+    require!(
+        !factory_state.is_paused(PAUSE_REGISTRATION),
+        FactoryError::Paused
+    );
+    require!(
+        params.decimals <= factory_state.decimals_limit(),
+        FactoryError::InvalidDecimals
+    );
+    if let Some(fee) = params.fee_bps_override {
+        require!(fee <= MAX_BPS, FactoryError::InvalidFeeBps);
+    }
+
+    mapping.origin_mint = origin_mint;
+    mapping.status = MintStatus::Active as u8;
+    mapping.decimals = params.decimals;
+    mapping.features = FeatureFlags::from(
+        params
+            .feature_flags
+            .unwrap_or_else(|| factory_state.default_features.bits()),
+    );
+    mapping.has_fee_override = params.fee_bps_override.is_some();
+    mapping.fee_bps_override = params.fee_bps_override.unwrap_or_default();
+    mapping.bump = bump;
+    mapping.has_ptkn = false;
+    mapping.ptkn_mint = Pubkey::default();
+    mapping.ptkn_token_program = PtknTokenProgram::Unknown as u8;
+    mapping.ptkn_extensions = 0;
+    mapping.max_ptkn_supply = 0;
+    mapping.total_minted = 0;
+    mapping.total_burned = 0;
+    mapping.pool = Pubkey::default();
+    mapping.vault = Pubkey::default();
+    mapping.commitment_tree = Pubkey::default();
+    mapping.nullifier_set = Pubkey::default();
+    mapping.circuit_tag = [0u8; 32];
+    mapping.verifying_key = Pubkey::default();
+    mapping.template = params.template;
+
+    if params.enable_ptkn {
+        prepare_ptkn_mint(
+            factory_state,
+            mapping,
+            ptkn_mint,
+            token_program,
+            Some(rent),
+            Some(payer),
+            Some(authority),
+            extensions,
+        )?;
+    }
+    Ok(())
+}
+
+/// `MintRegistered` for a mapping as it stands, so events emitted after a deployment
+/// carry its pool accounts.
+fn mint_registered_event(
+    factory_state: &FactoryState,
+    mapping: &Account<MintMapping>,
+    origin_token_program: &Pubkey,
+    registrant: Pubkey,
+) -> MintRegistered {
+    MintRegistered {
+        origin_mint: mapping.origin_mint,
+        ptkn_mint: mapping.ptkn_mint,
+        decimals: mapping.decimals,
+        features: mapping.features.bits(),
+        fee_bps: mapping.fee_bps(factory_state.default_fee_bps),
+        ptkn_extensions: mapping.ptkn_extensions,
+        mint_mapping: mapping.key(),
+        origin_token_program: *origin_token_program,
+        ptkn_token_program: PtknTokenProgram::from_u8(mapping.ptkn_token_program)
+            .program_id()
+            .unwrap_or_default(),
+        has_fee_override: mapping.has_fee_override,
+        pool: mapping.pool,
+        vault: mapping.vault,
+        commitment_tree: mapping.commitment_tree,
+        nullifier_set: mapping.nullifier_set,
+        verifying_key: mapping.verifying_key,
+        template: mapping.template,
+        registrant,
+    }
+}
+
+/// Stores `pool` and the PDAs derived alongside it on the mapping. The vault always lives
+/// under the vault program; the tree and nullifier set under `pool_program`.
+fn record_pool_accounts(mapping: &mut MintMapping, pool: Pubkey, pool_program: &Pubkey) {
+    let origin_mint = mapping.origin_mint;
+    let pool_pda =
+        |seed: &[u8]| Pubkey::find_program_address(&[seed, origin_mint.as_ref()], pool_program).0;
+    mapping.pool = pool;
+    mapping.vault =
+        Pubkey::find_program_address(&[seeds::VAULT, origin_mint.as_ref()], &ptf_vault::ID).0;
+    mapping.commitment_tree = pool_pda(seeds::TREE);
+    mapping.nullifier_set = pool_pda(seeds::NULLIFIERS);
+}
+
+fn record_verifying_key(mapping: &mut MintMapping, verifying_key: &Account<VerifyingKeyAccount>) {
+    mapping.circuit_tag = verifying_key.circuit_tag;
+    mapping.verifying_key = verifying_key.key();
+}
+
+/// CPIs `ptf_pool::initialize_pool`. The pool crate depends on this one, so the
+/// instruction is built by hand: Anchor's sighash followed by the Borsh arguments, with
+/// accounts in `InitializePool` order and the pool program standing in for an absent
+/// twin mint.
+fn invoke_initialize_pool(
+    accounts: &RegisterAndDeploy<'_>,
+    params: &RegisterAndDeployParams,
+) -> Result<()> {
+    let mut data = hashv(&[b"global:initialize_pool"]).to_bytes()[..8].to_vec();
+    (
+        params.shield_fee_bps,
+        params.pool_features,
+        params.merkle_depth,
+    )
+        .serialize(&mut data)
+        .map_err(|_| error!(FactoryError::SerializationError))?;
+
+    let twin_mint = if accounts.mint_mapping.has_ptkn {
+        accounts
+            .ptkn_mint
+            .as_ref()
+            .ok_or(FactoryError::PtknMintMissing)?
+            .to_account_info()
+    } else {
+        accounts.pool_program.to_account_info()
+    };
+    let pool_authority = if params.factory_governed {
+        accounts.factory_state.to_account_info()
+    } else {
+        accounts.authority.to_account_info()
+    };
+    let infos = [
+        pool_authority,
+        accounts.pool_state.to_account_info(),
+        accounts.nullifier_set.to_account_info(),
+        accounts.note_ledger.to_account_info(),
+        accounts.commitment_tree.to_account_info(),
+        accounts.hook_config.to_account_info(),
+        accounts.vault_state.to_account_info(),
+        accounts.origin_mint.to_account_info(),
+        accounts.mint_mapping.to_account_info(),
+        accounts.factory_state.to_account_info(),
+        twin_mint,
+        accounts.verifier_program.to_account_info(),
+        accounts.verifying_key.to_account_info(),
+        accounts.payer.to_account_info(),
+        accounts.system_program.to_account_info(),
+        accounts.token_program.to_account_info(),
+    ];
+    let metas = vec![
+        AccountMeta::new_readonly(infos[0].key(), true),
+        AccountMeta::new(infos[1].key(), false),
+        AccountMeta::new(infos[2].key(), false),
+        AccountMeta::new(infos[3].key(), false),
+        AccountMeta::new(infos[4].key(), false),
+        AccountMeta::new(infos[5].key(), false),
+        AccountMeta::new(infos[6].key(), false),
+        AccountMeta::new_readonly(infos[7].key(), false),
+        AccountMeta::new_readonly(infos[8].key(), false),
+        AccountMeta::new_readonly(infos[9].key(), false),
+        if accounts.mint_mapping.has_ptkn {
+            AccountMeta::new(infos[10].key(), false)
+        } else {
+            AccountMeta::new_readonly(infos[10].key(), false)
+        },
+        AccountMeta::new_readonly(infos[11].key(), false),
+        AccountMeta::new_readonly(infos[12].key(), false),
+        AccountMeta::new(infos[13].key(), true),
+        AccountMeta::new_readonly(infos[14].key(), false),
+        AccountMeta::new_readonly(infos[15].key(), false),
+    ];
+    let bump_seed = &[accounts.factory_state.bump];
+    let signer_seeds: [&[u8]; 3] = [seeds::FACTORY, crate::ID.as_ref(), bump_seed];
+    invoke_signed(
+        &Instruction {
+            program_id: PTF_POOL_PROGRAM_ID,
+            accounts: metas,
+            data,
+        },
+        &infos,
+        &[&signer_seeds],
+    )?;
+    if params.factory_governed {
+        // The pool defaults its fee recipient to its authority; fees owed to the factory
+        // PDA could never be spent, so hand them to the factory authority as before.
+        invoke_pool_admin(
+            &accounts.factory_state.to_account_info(),
+            accounts.factory_state.bump,
+            &[
+                accounts.pool_state.to_account_info(),
+                accounts.nullifier_set.to_account_info(),
+            ],
+            &accounts.pool_program.to_account_info(),
+            "set_fee_recipient",
+            accounts.authority.key(),
+        )?;
+    }
+    Ok(())
+}
+
+/// Calls pool instruction `name` with `args` as the pool `authority`, followed by the
+/// writable `accounts`. The factory state PDA signs, so `authority` may be either the
+/// factory itself or a signer of the outer instruction.
+fn invoke_pool_admin<'info, T: AnchorSerialize>(
+    authority: &AccountInfo<'info>,
+    factory_bump: u8,
+    accounts: &[AccountInfo<'info>],
+    pool_program: &AccountInfo<'info>,
+    name: &str,
+    args: T,
+) -> Result<()> {
+    let mut data = hashv(&[format!("global:{name}").as_bytes()]).to_bytes()[..8].to_vec();
+    args.serialize(&mut data)
+        .map_err(|_| error!(FactoryError::SerializationError))?;
+    let mut metas = vec![AccountMeta::new_readonly(authority.key(), true)];
+    metas.extend(
+        accounts
+            .iter()
+            .map(|account| AccountMeta::new(account.key(), false)),
+    );
+    let mut infos = vec![authority.clone()];
+    infos.extend(accounts.iter().cloned());
+    infos.push(pool_program.clone());
+    let bump_seed = &[factory_bump];
+    let signer_seeds: [&[u8]; 3] = [seeds::FACTORY, crate::ID.as_ref(), bump_seed];
+    invoke_signed(
+        &Instruction {
+            program_id: pool_program.key(),
+            accounts: metas,
+            data,
+        },
+        &infos,
+        &[&signer_seeds],
+    )?;
+    Ok(())
+}
+
+/// Applies a template's denominations and hooks to the pool `register_and_deploy` just
+/// created, signing as whichever account `invoke_initialize_pool` made its authority.
+fn apply_template_to_pool(
+    accounts: &RegisterAndDeploy<'_>,
+    params: &RegisterAndDeployParams,
+    template: &RegistrationTemplateParams,
+) -> Result<()> {
+    let pool_authority = if params.factory_governed {
+        accounts.factory_state.to_account_info()
+    } else {
+        accounts.authority.to_account_info()
+    };
+    if !template.denominations.is_empty() {
+        invoke_pool_admin(
+            &pool_authority,
+            accounts.factory_state.bump,
+            &[
+                accounts.pool_state.to_account_info(),
+                accounts.nullifier_set.to_account_info(),
+            ],
+            &accounts.pool_program.to_account_info(),
+            "set_denominations",
+            template.denominations.clone(),
+        )?;
+    }
+    if template.hooks.is_set() {
+        invoke_pool_admin(
+            &pool_authority,
+            accounts.factory_state.bump,
+            &[
+                accounts.pool_state.to_account_info(),
+                accounts.hook_config.to_account_info(),
+            ],
+            &accounts.pool_program.to_account_info(),
+            "configure_hooks",
+            PoolHookConfigArgs::from(&template.hooks),
+        )?;
+    }
+    Ok(())
+}
+
+/// Checks that `pool_state` is `origin_mint`'s pool under an allowlisted `pool_program`,
+/// then runs a pool administration call for a factory-governed pool.
+fn execute_pool_admin<'info, T: AnchorSerialize>(
+    state: &Account<'info, FactoryState>,
+    pool_state: Option<&UncheckedAccount<'info>>,
+    nullifier_set: Option<&UncheckedAccount<'info>>,
+    pool_program: Option<&UncheckedAccount<'info>>,
+    origin_mint: &Pubkey,
+    name: &str,
+    args: T,
+) -> Result<()> {
+    let pool_state = pool_state.ok_or(FactoryError::PoolAccountsMissing)?;
+    let nullifier_set = nullifier_set.ok_or(FactoryError::PoolAccountsMissing)?;
+    let pool_program = pool_program.ok_or(FactoryError::PoolAccountsMissing)?;
+    require!(
+        state.is_pool_program(&pool_program.key()),
+        FactoryError::PoolProgramNotAllowed
+    );
+    let (expected_pool, _) =
+        Pubkey::find_program_address(&[seeds::POOL, origin_mint.as_ref()], &pool_program.key());
+    require_keys_eq!(
+        pool_state.key(),
+        expected_pool,
+        FactoryError::PoolAddressMismatch
+    );
+    invoke_pool_admin(
+        &state.to_account_info(),
+        state.bump,
+        &[
+            pool_state.to_account_info(),
+            nullifier_set.to_account_info(),
+        ],
+        &pool_program.to_account_info(),
+        name,
+        args,
+    )
+}
+
+/// Reads name, symbol and URI from `mint`'s Metaplex metadata account. Metaplex pads
+/// the strings with NUL bytes, which are trimmed.
+fn read_token_metadata(account: &AccountInfo, mint: &Pubkey) -> Result<PtknMetadata> {
+    require_keys_eq!(
+        account.key(),
+        token_metadata_address(mint),
+        FactoryError::PtknMetadataInvalid
+    );
+    require_keys_eq!(
+        *account.owner,
+        TOKEN_METADATA_PROGRAM_ID,
+        FactoryError::PtknMetadataInvalid
+    );
+    let data = account.try_borrow_data()?;
+    // Key, update authority and mint precede the strings.
+    let mut cursor = data
+        .get(1 + 32 + 32..)
+        .ok_or(FactoryError::PtknMetadataInvalid)?;
+    let mut next = || -> Result<String> {
+        let value = String::deserialize(&mut cursor)
+            .map_err(|_| error!(FactoryError::PtknMetadataInvalid))?;
+        Ok(value.trim_end_matches('\0').to_string())
+    };
+    Ok(PtknMetadata {
+        name: next()?,
+        symbol: next()?,
+        uri: next()?,
+    })
+}
+
+/// Creates or updates the Metaplex metadata of a pTKN mint, with the factory signing as
+/// mint and update authority. Built by hand like `invoke_initialize_pool`: the
+/// instruction index, then Borsh `DataV2` with no creators, collection or uses.
+#[allow(clippy::too_many_arguments)]
+fn write_ptkn_metadata<'info>(
+    factory_state: &Account<'info, FactoryState>,
+    ptkn_mint: &UncheckedAccount<'info>,
+    ptkn_metadata: &UncheckedAccount<'info>,
+    metadata_program: &UncheckedAccount<'info>,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+    origin_mint: &Pubkey,
+    metadata: &PtknMetadata,
+) -> Result<()> {
+    metadata.validate()?;
+    require_keys_eq!(
+        ptkn_metadata.key(),
+        token_metadata_address(&ptkn_mint.key()),
+        FactoryError::PtknMetadataInvalid
+    );
+
+    let creating = ptkn_metadata.data_is_empty();
+    let data_v2 = (
+        &metadata.name,
+        &metadata.symbol,
+        &metadata.uri,
+        0u16,       // seller_fee_basis_points
+        None::<u8>, // creators
+        None::<u8>, // collection
+        None::<u8>, // uses
+    );
+    let mut data = Vec::new();
+    let (accounts, infos) = if creating {
+        // CreateMetadataAccountV3 { data, is_mutable: true, collection_details: None }
+        data.push(33);
+        (data_v2, true, None::<u8>)
+            .serialize(&mut data)
+            .map_err(|_| error!(FactoryError::SerializationError))?;
+        (
+            vec![
+                AccountMeta::new(ptkn_metadata.key(), false),
+                AccountMeta::new_readonly(ptkn_mint.key(), false),
+                AccountMeta::new_readonly(factory_state.key(), true),
+                AccountMeta::new(payer.key(), true),
+                AccountMeta::new_readonly(factory_state.key(), true),
+                AccountMeta::new_readonly(system_program.key(), false),
+            ],
+            vec![
+                ptkn_metadata.to_account_info(),
+                ptkn_mint.to_account_info(),
+                factory_state.to_account_info(),
+                payer.to_account_info(),
+                system_program.to_account_info(),
+            ],
+        )
+    } else {
+        // UpdateMetadataAccountV2 { data: Some(data), update_authority, primary_sale_happened
+        // and is_mutable unchanged }
+        data.push(15);
+        (Some(data_v2), None::<Pubkey>, None::<bool>, None::<bool>)
+            .serialize(&mut data)
+            .map_err(|_| error!(FactoryError::SerializationError))?;
+        (
+            vec![
+                AccountMeta::new(ptkn_metadata.key(), false),
+                AccountMeta::new_readonly(factory_state.key(), true),
+            ],
+            vec![
+                ptkn_metadata.to_account_info(),
+                factory_state.to_account_info(),
+            ],
+        )
+    };
+
+    let bump_seed = [factory_state.bump];
+    let signer_seeds: [&[u8]; 3] = [seeds::FACTORY, crate::ID.as_ref(), &bump_seed];
+    invoke_signed(
+        &Instruction {
+            program_id: metadata_program.key(),
+            accounts,
+            data,
+        },
+        &infos,
+        &[&signer_seeds[..]],
+    )?;
+
+    emit!(PtknMetadataUpdated {
+        origin_mint: *origin_mint,
+        ptkn_mint: ptkn_mint.key(),
+        name: metadata.name.clone(),
+        symbol: metadata.symbol.clone(),
+        uri: metadata.uri.clone(),
+    });
+    Ok(())
+}
+
+/// Creates the pTKN mint when `ptkn_mint` is still an empty system account, or takes over
+/// mint authority of an existing one. A new mint at [`ptkn_mint_address`] is created with
+/// the factory signing for it, so callers that cannot produce a fresh keypair signature,
+/// such as governance-executed transactions, can still enable pTKN. `extensions` only
+/// applies to a newly created mint. Records the mint and its token program in `mapping`.
+#[allow(clippy::too_many_arguments)]
 fn prepare_ptkn_mint<'info>(
     factory_state: &Account<'info, FactoryState>,
+    mapping: &mut MintMapping,
     ptkn_mint: Option<&UncheckedAccount<'info>>,
     token_program: Option<&Interface<'info, TokenInterface>>,
     rent: Option<&Sysvar<'info, Rent>>,
     payer: Option<&Signer<'info>>,
-    decimals: u8,
     current_authority: Option<&Signer<'info>>,
-) -> Result<Pubkey> {
+    extensions: Option<&PtknMintExtensions>,
+) -> Result<()> {
     let ptkn_account = ptkn_mint.ok_or(FactoryError::PtknMintMissing)?;
     let token_program = token_program.ok_or(FactoryError::TokenProgramMissing)?;
     let mint_info = ptkn_account.to_account_info();
+    let origin_mint = mapping.origin_mint;
+    let decimals = mapping.decimals;
+    let extension_bits = extensions.map_or(0, |extensions| extensions.bits);
+    require!(
+        extension_bits & !PTKN_EXTENSIONS_ALL == 0,
+        FactoryError::PtknExtensionsInvalid
+    );
+    let token_kind = PtknTokenProgram::from_program(&token_program.key());
 
     if mint_info.owner == &system_program::ID && mint_info.data_is_empty() {
         let payer = payer.ok_or(FactoryError::PtknPayerMissing)?;
         let rent = rent.ok_or(FactoryError::RentMissing)?;
-        let mint_space = <Token2022Mint as Token2022Pack>::LEN;
-        let lamports = rent.minimum_balance(mint_space);
+        let with_metadata = extension_bits & PTKN_EXTENSION_METADATA != 0;
+        if extension_bits != 0 {
+            require!(
+                token_kind == PtknTokenProgram::Token2022,
+                FactoryError::PtknExtensionsRequireToken2022
+            );
+        }
+        let non_transferable = extension_bits & PTKN_EXTENSION_NON_TRANSFERABLE != 0;
+        let mut mint_extensions = Vec::new();
+        if with_metadata {
+            mint_extensions.push(ExtensionType::MetadataPointer);
+        }
+        if non_transferable {
+            mint_extensions.push(ExtensionType::NonTransferable);
+        }
+        let mint_space = if mint_extensions.is_empty() {
+            <Token2022Mint as Token2022Pack>::LEN
+        } else {
+            ExtensionType::try_calculate_account_len::<Token2022Mint>(&mint_extensions)
+                .map_err(|_| error!(FactoryError::PtknExtensionsInvalid))?
+        };
+        // Token-2022 reallocs the mint for the metadata TLV itself but expects the
+        // lamports for it to be there already.
+        let mut funded_space = mint_space;
+        let metadata = extensions.map(|extensions| &extensions.metadata);
+        if with_metadata {
+            let metadata = metadata.ok_or(FactoryError::PtknMetadataInvalid)?;
+            metadata.validate()?;
+            funded_space += in_mint_metadata(&factory_state.key(), &mint_info.key(), metadata)
+                .tlv_size_of()
+                .map_err(|_| error!(FactoryError::PtknMetadataInvalid))?;
+        }
+        let lamports = rent.minimum_balance(funded_space);
         let create_ix = system_instruction::create_account(
             payer.key,
             mint_info.key,
@@ -683,13 +3875,75 @@ fn prepare_ptkn_mint<'info>(
             mint_space as u64,
             token_program.key,
         );
-        invoke(&create_ix, &[payer.to_account_info(), mint_info.clone()])?;
+        let (pda_mint, pda_bump) = ptkn_mint_address(&origin_mint);
+        if *mint_info.key == pda_mint {
+            let bump = [pda_bump];
+            let mint_seeds: [&[u8]; 3] = [seeds::PTKN_MINT, origin_mint.as_ref(), &bump];
+            invoke_signed(
+                &create_ix,
+                &[payer.to_account_info(), mint_info.clone()],
+                &[&mint_seeds[..]],
+            )?;
+        } else {
+            invoke(&create_ix, &[payer.to_account_info(), mint_info.clone()])?;
+        }
+        if with_metadata {
+            let pointer_accounts = MetadataPointerInitialize {
+                token_program_id: token_program.to_account_info(),
+                mint: mint_info.clone(),
+            };
+            token_interface::metadata_pointer_initialize(
+                CpiContext::new(token_program.to_account_info(), pointer_accounts),
+                Some(factory_state.key()),
+                Some(mint_info.key()),
+            )?;
+        }
+        if non_transferable {
+            let non_transferable_accounts = NonTransferableMintInitialize {
+                token_program_id: token_program.to_account_info(),
+                mint: mint_info.clone(),
+            };
+            token_interface::non_transferable_mint_initialize(CpiContext::new(
+                token_program.to_account_info(),
+                non_transferable_accounts,
+            ))?;
+        }
         let init_accounts = token_interface::InitializeMint2 {
             mint: mint_info.clone(),
         };
         let init_ctx = CpiContext::new(token_program.to_account_info(), init_accounts);
         token_interface::initialize_mint2(init_ctx, decimals, &factory_state.key(), None)?;
+        if let (true, Some(metadata)) = (with_metadata, metadata) {
+            let bump_seed = [factory_state.bump];
+            let signer_seeds: [&[u8]; 3] = [seeds::FACTORY, crate::ID.as_ref(), &bump_seed];
+            let signer = [&signer_seeds[..]];
+            let metadata_accounts = TokenMetadataInitialize {
+                program_id: token_program.to_account_info(),
+                metadata: mint_info.clone(),
+                update_authority: factory_state.to_account_info(),
+                mint_authority: factory_state.to_account_info(),
+                mint: mint_info.clone(),
+            };
+            token_interface::token_metadata_initialize(
+                CpiContext::new_with_signer(
+                    token_program.to_account_info(),
+                    metadata_accounts,
+                    &signer,
+                ),
+                metadata.name.clone(),
+                metadata.symbol.clone(),
+                metadata.uri.clone(),
+            )?;
+            emit!(PtknMetadataUpdated {
+                origin_mint,
+                ptkn_mint: mint_info.key(),
+                name: metadata.name.clone(),
+                symbol: metadata.symbol.clone(),
+                uri: metadata.uri.clone(),
+            });
+        }
     } else {
+        require!(extension_bits == 0, FactoryError::PtknExtensionsInvalid);
         require_keys_eq!(
             *mint_info.owner,
             token_program.key(),
@@ -718,32 +3972,407 @@ fn prepare_ptkn_mint<'info>(
         }
     }
 
-    Ok(*mint_info.key)
+    mapping.has_ptkn = true;
+    mapping.ptkn_mint = *mint_info.key;
+    mapping.ptkn_token_program = token_kind as u8;
+    mapping.ptkn_extensions = extension_bits;
+    Ok(())
+}
+
+/// The token-2022 metadata stored in a pTKN mint, with the factory as update authority.
+fn in_mint_metadata(
+    factory: &Pubkey,
+    ptkn_mint: &Pubkey,
+    metadata: &PtknMetadata,
+) -> TokenMetadata {
+    TokenMetadata {
+        update_authority: OptionalNonZeroPubkey(*factory),
+        mint: *ptkn_mint,
+        name: metadata.name.clone(),
+        symbol: metadata.symbol.clone(),
+        uri: metadata.uri.clone(),
+        additional_metadata: Vec::new(),
+    }
+}
+
+/// Rewrites the name, symbol and URI stored in a token-2022 pTKN mint, topping up its
+/// lamports for any growth first.
+#[allow(clippy::too_many_arguments)]
+fn write_in_mint_metadata<'info>(
+    factory_state: &Account<'info, FactoryState>,
+    ptkn_mint: &UncheckedAccount<'info>,
+    ptkn_metadata: &UncheckedAccount<'info>,
+    token_program: &UncheckedAccount<'info>,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+    origin_mint: &Pubkey,
+    metadata: &PtknMetadata,
+) -> Result<()> {
+    metadata.validate()?;
+    require_keys_eq!(
+        ptkn_metadata.key(),
+        ptkn_mint.key(),
+        FactoryError::PtknMetadataInvalid
+    );
+    require_keys_eq!(
+        token_program.key(),
+        spl_token_2022::ID,
+        FactoryError::PtknMetadataInvalid
+    );
+
+    let mint_info = ptkn_mint.to_account_info();
+    let (current_len, current_size) = {
+        let data = mint_info.try_borrow_data()?;
+        let state = StateWithExtensions::<Token2022Mint>::unpack(&data)
+            .map_err(|_| error!(FactoryError::PtknMetadataInvalid))?;
+        let current = state
+            .get_variable_len_extension::<TokenMetadata>()
+            .map_err(|_| error!(FactoryError::PtknMetadataInvalid))?;
+        let size = current
+            .tlv_size_of()
+            .map_err(|_| error!(FactoryError::PtknMetadataInvalid))?;
+        (data.len(), size)
+    };
+    let new_size = in_mint_metadata(&factory_state.key(), &mint_info.key(), metadata)
+        .tlv_size_of()
+        .map_err(|_| error!(FactoryError::PtknMetadataInvalid))?;
+    let required =
+        Rent::get()?.minimum_balance(current_len + new_size.saturating_sub(current_size));
+    let shortfall = required.saturating_sub(mint_info.lamports());
+    if shortfall > 0 {
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: payer.to_account_info(),
+                    to: mint_info.clone(),
+                },
+            ),
+            shortfall,
+        )?;
+    }
+
+    let bump_seed = [factory_state.bump];
+    let signer_seeds: [&[u8]; 3] = [seeds::FACTORY, crate::ID.as_ref(), &bump_seed];
+    let signer = [&signer_seeds[..]];
+    for (field, value) in [
+        (Field::Name, &metadata.name),
+        (Field::Symbol, &metadata.symbol),
+        (Field::Uri, &metadata.uri),
+    ] {
+        let update_accounts = TokenMetadataUpdateField {
+            program_id: token_program.to_account_info(),
+            metadata: mint_info.clone(),
+            update_authority: factory_state.to_account_info(),
+        };
+        token_interface::token_metadata_update_field(
+            CpiContext::new_with_signer(token_program.to_account_info(), update_accounts, &signer),
+            field,
+            value.clone(),
+        )?;
+    }
+
+    emit!(PtknMetadataUpdated {
+        origin_mint: *origin_mint,
+        ptkn_mint: mint_info.key(),
+        name: metadata.name.clone(),
+        symbol: metadata.symbol.clone(),
+        uri: metadata.uri.clone(),
+    });
+    Ok(())
+}
+
+fn load_mint_state(account_info: &AccountInfo<'_>) -> Result<Mint> {
+    let data = account_info
+        .try_borrow_data()
+        .map_err(|_| error!(FactoryError::InvalidDecimals))?;
+    let mut slice: &[u8] = &data;
+    Mint::try_deserialize(&mut slice).map_err(|_| error!(FactoryError::InvalidDecimals))
+}
+
+fn load_mint_decimals(account_info: &AccountInfo<'_>) -> Result<u8> {
+    Ok(load_mint_state(account_info)?.decimals)
+}
+
+/// Accounts `apply_mint_update` uses to create or check the pTKN twin. Updates that
+/// leave the twin alone may omit all of them.
+struct PtknMintAccounts<'a, 'info> {
+    ptkn_mint: Option<&'a UncheckedAccount<'info>>,
+    token_program: Option<&'a Interface<'info, TokenInterface>>,
+    rent: Option<&'a Sysvar<'info, Rent>>,
+    payer: Option<&'a Signer<'info>>,
+    authority: Option<&'a Signer<'info>>,
+}
+
+struct RegisterMintParams {
+    decimals: u8,
+    enable_ptkn: bool,
+    feature_flags: Option<u64>,
+    fee_bps_override: Option<u16>,
+    template: Pubkey,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RegisterAndDeployParams {
+    pub enable_ptkn: bool,
+    pub feature_flags: Option<u64>,
+    pub fee_bps_override: Option<u16>,
+    /// Passed through to `ptf_pool::initialize_pool`, which takes the unshield fee from
+    /// the mapping instead.
+    pub shield_fee_bps: u16,
+    pub pool_features: u8,
+    pub merkle_depth: u8,
+    /// Makes the factory state PDA the pool authority, so the pool's fee, features and
+    /// pause change only through factory timelock actions. The factory authority stays
+    /// the fee recipient.
+    pub factory_governed: bool,
+}
+
+impl RegisterAndDeployParams {
+    /// Takes the mapping parameters from `template`, and turns on pool hooks when it
+    /// names any hook program.
+    fn with_template(mut self, template: &RegistrationTemplateParams) -> Self {
+        self.enable_ptkn = template.enable_ptkn;
+        self.feature_flags = template.feature_flags;
+        self.fee_bps_override = template.fee_bps_override;
+        if template.hooks.is_set() {
+            self.pool_features |= FEATURE_HOOKS_ENABLED;
+        }
+        self
+    }
+}
+
+/// Lamports a permissionless registrant posted for `origin_mint`, at
+/// `["bond", origin_mint]`. Held until the authority refunds or slashes it.
+#[account]
+pub struct RegistrationBond {
+    pub factory: Pubkey,
+    pub origin_mint: Pubkey,
+    pub registrant: Pubkey,
+    /// Bond held on top of the account's rent.
+    pub lamports: u64,
+    pub posted_at: i64,
+    pub bump: u8,
+}
+
+impl RegistrationBond {
+    pub const SPACE: usize = 8 + 32 + 32 + 32 + 8 + 8 + 1;
+}
+
+/// A named parameter bundle at `["template", template_id]`, so mints registered from it
+/// share one configuration.
+#[account]
+pub struct RegistrationTemplate {
+    pub factory: Pubkey,
+    pub template_id: u32,
+    /// Label for operators, such as a UTF-8 name padded with zeros.
+    pub name: [u8; 32],
+    pub params: RegistrationTemplateParams,
+    pub bump: u8,
+}
+
+impl RegistrationTemplate {
+    pub const SPACE: usize = 8 + 32 + 4 + 32 + RegistrationTemplateParams::MAX_SIZE + 1;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RegistrationTemplateParams {
+    pub enable_ptkn: bool,
+    pub feature_flags: Option<u64>,
+    pub fee_bps_override: Option<u16>,
+    /// `PTKN_EXTENSION_*` bits of a pTKN mint the registration creates.
+    pub ptkn_extensions: u8,
+    /// Shield amounts `register_and_deploy` restricts the pool to; empty allows any.
+    pub denominations: Vec<u64>,
+    /// Hooks `register_and_deploy` configures on the pool.
+    pub hooks: TemplateHooks,
+}
+
+impl RegistrationTemplateParams {
+    /// Matches `ptf_pool::PoolState::MAX_DENOMINATIONS`.
+    pub const MAX_DENOMINATIONS: usize = 8;
+    pub const MAX_SIZE: usize =
+        1 + 9 + 3 + 1 + 4 + 8 * Self::MAX_DENOMINATIONS + TemplateHooks::SIZE;
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some(fee) = self.fee_bps_override {
+            require!(fee <= MAX_BPS, FactoryError::InvalidFeeBps);
+        }
+        require!(
+            self.ptkn_extensions & !PTKN_EXTENSIONS_ALL == 0,
+            FactoryError::PtknExtensionsInvalid
+        );
+        require!(
+            self.denominations.len() <= Self::MAX_DENOMINATIONS
+                && self.denominations.iter().all(|amount| *amount > 0),
+            FactoryError::TemplateInvalid
+        );
+        Ok(())
+    }
+}
+
+/// Pool hook programs a template installs; default leaves a hook off. The pre-hook
+/// program runs before both shields and unshields.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TemplateHooks {
+    pub pre_hook_program: Pubkey,
+    pub post_shield_program: Pubkey,
+    pub post_unshield_program: Pubkey,
+}
+
+impl TemplateHooks {
+    pub const SIZE: usize = 32 * 3;
+
+    pub fn is_set(&self) -> bool {
+        [
+            self.pre_hook_program,
+            self.post_shield_program,
+            self.post_unshield_program,
+        ]
+        .iter()
+        .any(|program| *program != Pubkey::default())
+    }
 }
 
-fn load_mint_state(account_info: &AccountInfo<'_>) -> Result<Mint> {
-    let data = account_info
-        .try_borrow_data()
-        .map_err(|_| error!(FactoryError::InvalidDecimals))?;
-    let mut slice: &[u8] = &data;
-    Mint::try_deserialize(&mut slice).map_err(|_| error!(FactoryError::InvalidDecimals))
+/// Borsh layout of `ptf_pool::HookConfigArgs` without hook accounts, which the factory
+/// cannot name since the pool crate depends on it.
+#[derive(AnchorSerialize)]
+struct PoolHookConfigArgs {
+    post_shield_program: Pubkey,
+    post_shield_enabled: bool,
+    post_unshield_program: Pubkey,
+    post_unshield_enabled: bool,
+    /// The account lists are empty, so their element type does not matter.
+    post_shield_accounts: Vec<u8>,
+    post_unshield_accounts: Vec<u8>,
+    /// `HookAccountMode::Strict`.
+    mode: u8,
+    pre_hook_program: Pubkey,
+    pre_shield_enabled: bool,
+    pre_unshield_enabled: bool,
+    post_transfer_program: Pubkey,
+    post_transfer_enabled: bool,
+    account_templates: Vec<u8>,
 }
 
-fn load_mint_decimals(account_info: &AccountInfo<'_>) -> Result<u8> {
-    Ok(load_mint_state(account_info)?.decimals)
+impl From<&TemplateHooks> for PoolHookConfigArgs {
+    fn from(hooks: &TemplateHooks) -> Self {
+        let pre_enabled = hooks.pre_hook_program != Pubkey::default();
+        Self {
+            post_shield_program: hooks.post_shield_program,
+            post_shield_enabled: hooks.post_shield_program != Pubkey::default(),
+            post_unshield_program: hooks.post_unshield_program,
+            post_unshield_enabled: hooks.post_unshield_program != Pubkey::default(),
+            post_shield_accounts: Vec::new(),
+            post_unshield_accounts: Vec::new(),
+            mode: 0,
+            pre_hook_program: hooks.pre_hook_program,
+            pre_shield_enabled: pre_enabled,
+            pre_unshield_enabled: pre_enabled,
+            post_transfer_program: Pubkey::default(),
+            post_transfer_enabled: false,
+            account_templates: Vec::new(),
+        }
+    }
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
 pub struct UpdateMintParams {
     pub enable_ptkn: Option<bool>,
-    pub features: Option<u8>,
+    pub features: Option<u64>,
     pub fee_bps_override: Option<u16>,
 }
 
+/// Extensions requested for a pTKN mint the call creates.
+struct PtknMintExtensions {
+    bits: u8,
+    metadata: PtknMetadata,
+}
+
+/// Timelock delay in seconds for each kind of action.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TimelockDelays {
+    pub pause: i64,
+    pub unpause: i64,
+    pub features: i64,
+    pub mint: i64,
+    pub fee: i64,
+    pub authority: i64,
+}
+
+impl TimelockDelays {
+    pub const SIZE: usize = 8 * 6;
+    /// Protocol minimums; a schedule below any of them is rejected.
+    pub const MINIMUM: Self = Self {
+        pause: 0,
+        unpause: 0,
+        features: 3_600,
+        mint: 3_600,
+        fee: 86_400,
+        authority: 259_200,
+    };
+    pub const MAX_DELAY: i64 = 30 * 86_400;
+
+    pub fn validate(&self) -> Result<()> {
+        let minimum = Self::MINIMUM;
+        for (delay, min) in [
+            (self.pause, minimum.pause),
+            (self.unpause, minimum.unpause),
+            (self.features, minimum.features),
+            (self.mint, minimum.mint),
+            (self.fee, minimum.fee),
+            (self.authority, minimum.authority),
+        ] {
+            require!(
+                delay >= min && delay <= Self::MAX_DELAY,
+                FactoryError::TimelockDelayOutOfBounds
+            );
+        }
+        Ok(())
+    }
+
+    pub fn for_action(&self, action: &TimelockAction) -> i64 {
+        match action {
+            TimelockAction::PauseFactory
+            | TimelockAction::PauseScopes { .. }
+            | TimelockAction::PausePool { .. }
+            | TimelockAction::SetKillSwitch { engaged: true } => self.pause,
+            TimelockAction::UnpauseFactory
+            | TimelockAction::UnpauseScopes { .. }
+            | TimelockAction::UnpausePool { .. }
+            | TimelockAction::SetKillSwitch { engaged: false } => self.unpause,
+            TimelockAction::SetDefaultFeatures { .. } | TimelockAction::SetPoolFeatures { .. } => {
+                self.features
+            }
+            TimelockAction::UpdateMint { .. }
+            | TimelockAction::SetPtknSupplyCap { .. }
+            | TimelockAction::SetMaxDecimals { .. }
+            | TimelockAction::PinVerifyingKey { .. } => self.mint,
+            TimelockAction::SetDefaultFee { .. }
+            | TimelockAction::SetPoolFee { .. }
+            | TimelockAction::SetProtocolMaxFee { .. }
+            | TimelockAction::SetTreasury { .. }
+            | TimelockAction::SetRegistrationBond { .. }
+            | TimelockAction::WithdrawTreasury { .. } => self.fee,
+            TimelockAction::SetAuthority { .. }
+            | TimelockAction::SetTimelockSeconds { .. }
+            | TimelockAction::SetGuardian { .. }
+            | TimelockAction::SetOperator { .. }
+            | TimelockAction::SetRestrictedExecution { .. }
+            | TimelockAction::SetPoolProgram { .. }
+            | TimelockAction::SetVerifierProgram { .. }
+            | TimelockAction::SetCouncil { .. }
+            | TimelockAction::DissolveCouncil { .. }
+            | TimelockAction::SetGovernanceAuthority { .. }
+            | TimelockAction::SetTimelockDelays { .. } => self.authority,
+        }
+    }
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
 pub enum TimelockAction {
     SetDefaultFeatures {
-        features: u8,
+        features: u64,
     },
     UpdateMint {
         origin_mint: Pubkey,
@@ -751,6 +4380,158 @@ pub enum TimelockAction {
     },
     PauseFactory,
     UnpauseFactory,
+    SetTimelockDelays {
+        delays: TimelockDelays,
+    },
+    SetGuardian {
+        guardian: Pubkey,
+    },
+    SetAuthority {
+        new_authority: Pubkey,
+    },
+    SetTimelockSeconds {
+        seconds: i64,
+    },
+    SetDefaultFee {
+        fee_bps: u16,
+    },
+    SetRestrictedExecution {
+        restricted: bool,
+    },
+    SetPoolProgram {
+        program: Pubkey,
+        allowed: bool,
+    },
+    SetPtknSupplyCap {
+        origin_mint: Pubkey,
+        max_ptkn_supply: u64,
+    },
+    SetTreasury {
+        treasury: Pubkey,
+        registration_fee_lamports: u64,
+    },
+    WithdrawTreasury {
+        amount: u64,
+    },
+    SetMaxDecimals {
+        max_decimals: u8,
+    },
+    PauseScopes {
+        scopes: u8,
+    },
+    UnpauseScopes {
+        scopes: u8,
+    },
+    SetOperator {
+        operator: Pubkey,
+    },
+    PinVerifyingKey {
+        origin_mint: Pubkey,
+        verifying_key: Pubkey,
+    },
+    /// Pool administration for factory-governed pools; see `RegisterAndDeployParams`.
+    SetPoolFee {
+        origin_mint: Pubkey,
+        shield_fee_bps: u16,
+        unshield_fee_bps: u16,
+        shield_flat_fee: u64,
+        unshield_flat_fee: u64,
+    },
+    SetPoolFeatures {
+        origin_mint: Pubkey,
+        features: u8,
+    },
+    PausePool {
+        origin_mint: Pubkey,
+    },
+    UnpausePool {
+        origin_mint: Pubkey,
+    },
+    /// Protocol config updates; see `ProtocolConfig`.
+    SetKillSwitch {
+        engaged: bool,
+    },
+    SetProtocolMaxFee {
+        max_fee_bps: u16,
+    },
+    SetVerifierProgram {
+        program: Pubkey,
+        allowed: bool,
+    },
+    SetRegistrationBond {
+        bond_lamports: u64,
+    },
+    /// Council changes; see `set_council` and `dissolve_council`.
+    SetCouncil {
+        members: Vec<Pubkey>,
+        threshold: u8,
+    },
+    DissolveCouncil {
+        new_authority: Pubkey,
+    },
+    /// See `set_governance_authority`.
+    SetGovernanceAuthority {
+        governance_program: Pubkey,
+        governance: Pubkey,
+    },
+}
+
+impl TimelockAction {
+    /// Checks the action's parameters, so an invalid action cannot be queued.
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            TimelockAction::SetTimelockDelays { delays } => delays.validate(),
+            TimelockAction::SetTimelockSeconds { seconds } => {
+                require!(
+                    (0..=TimelockDelays::MAX_DELAY).contains(seconds),
+                    FactoryError::TimelockDelayOutOfBounds
+                );
+                Ok(())
+            }
+            TimelockAction::SetDefaultFee { fee_bps }
+            | TimelockAction::SetProtocolMaxFee {
+                max_fee_bps: fee_bps,
+            } => {
+                require!(*fee_bps <= MAX_BPS, FactoryError::InvalidFeeBps);
+                Ok(())
+            }
+            TimelockAction::SetPoolFee {
+                shield_fee_bps,
+                unshield_fee_bps,
+                ..
+            } => {
+                require!(
+                    *shield_fee_bps <= MAX_BPS && *unshield_fee_bps <= MAX_BPS,
+                    FactoryError::InvalidFeeBps
+                );
+                Ok(())
+            }
+            TimelockAction::SetPoolProgram { program, .. } => {
+                require!(
+                    *program != Pubkey::default(),
+                    FactoryError::PoolProgramInvalid
+                );
+                Ok(())
+            }
+            TimelockAction::SetVerifierProgram { program, .. } => {
+                require!(
+                    *program != Pubkey::default(),
+                    FactoryError::VerifierProgramInvalid
+                );
+                Ok(())
+            }
+            TimelockAction::PauseScopes { scopes } | TimelockAction::UnpauseScopes { scopes } => {
+                validate_pause_scopes(*scopes)
+            }
+            TimelockAction::SetCouncil { members, threshold } => {
+                validate_council(members, *threshold)
+            }
+            TimelockAction::SetGovernanceAuthority {
+                governance_program, ..
+            } => validate_governance_program(governance_program),
+            _ => Ok(()),
+        }
+    }
 }
 
 #[event]
@@ -763,7 +4544,14 @@ pub struct FactoryInitialized {
 #[event]
 pub struct DefaultFeaturesUpdated {
     pub authority: Pubkey,
-    pub features: u8,
+    pub features: u64,
+}
+
+#[event]
+pub struct MintDeregistered {
+    pub origin_mint: Pubkey,
+    pub ptkn_mint: Pubkey,
+    pub authority: Pubkey,
 }
 
 #[event]
@@ -771,15 +4559,87 @@ pub struct MintRegistered {
     pub origin_mint: Pubkey,
     pub ptkn_mint: Pubkey,
     pub decimals: u8,
-    pub features: u8,
+    pub features: u64,
     pub fee_bps: u16,
+    /// `PTKN_EXTENSION_*` bits of the pTKN mint.
+    pub ptkn_extensions: u8,
+    pub mint_mapping: Pubkey,
+    /// Token program that owns the origin mint.
+    pub origin_token_program: Pubkey,
+    /// Token program that owns `ptkn_mint`; default without a twin.
+    pub ptkn_token_program: Pubkey,
+    /// Whether `fee_bps` is the mapping's own override rather than the factory default.
+    pub has_fee_override: bool,
+    /// Pool accounts and verifying key recorded on the mapping; default unless the mint
+    /// was deployed by `register_and_deploy`.
+    pub pool: Pubkey,
+    pub vault: Pubkey,
+    pub commitment_tree: Pubkey,
+    pub nullifier_set: Pubkey,
+    pub verifying_key: Pubkey,
+    /// Registration template the parameters came from; default when none.
+    pub template: Pubkey,
+    /// The authority, or the registrant of a bonded registration.
+    pub registrant: Pubkey,
+}
+
+#[event]
+pub struct RegistrationTemplateSet {
+    pub template: Pubkey,
+    pub template_id: u32,
+    pub name: [u8; 32],
+}
+
+#[event]
+pub struct RegistrationTemplateClosed {
+    pub template: Pubkey,
+    pub template_id: u32,
+}
+
+#[event]
+pub struct PoolDeployed {
+    pub origin_mint: Pubkey,
+    pub pool: Pubkey,
+    pub vault: Pubkey,
+    pub vault_token_account: Pubkey,
+    pub ptkn_mint: Pubkey,
+    pub commitment_tree: Pubkey,
+    pub nullifier_set: Pubkey,
+    pub note_ledger: Pubkey,
+    pub hook_config: Pubkey,
+    /// Token program of the origin mint and vault token account.
+    pub token_program: Pubkey,
+    pub verifying_key: Pubkey,
+    /// The factory state for factory-governed pools, otherwise the factory authority.
+    pub pool_authority: Pubkey,
+    pub shield_fee_bps: u16,
+    pub pool_features: u8,
+    pub merkle_depth: u8,
+    /// Registration template applied to the mapping and pool; default when none.
+    pub template: Pubkey,
+}
+
+#[event]
+pub struct PoolLinked {
+    pub origin_mint: Pubkey,
+    pub pool: Pubkey,
+    pub vault: Pubkey,
+    pub commitment_tree: Pubkey,
+    pub nullifier_set: Pubkey,
+}
+
+#[event]
+pub struct VerifyingKeyPinned {
+    pub origin_mint: Pubkey,
+    pub verifying_key: Pubkey,
+    pub circuit_tag: [u8; 32],
 }
 
 #[event]
 pub struct MintUpdated {
     pub origin_mint: Pubkey,
     pub ptkn_mint: Pubkey,
-    pub features: u8,
+    pub features: u64,
     pub fee_bps_override: Option<u16>,
 }
 
@@ -789,6 +4649,93 @@ pub struct MintFrozen {
     pub authority: Pubkey,
 }
 
+#[event]
+pub struct PtknMetadataUpdated {
+    pub origin_mint: Pubkey,
+    pub ptkn_mint: Pubkey,
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+}
+
+#[event]
+pub struct MaxDecimalsUpdated {
+    pub factory: Pubkey,
+    pub max_decimals: u8,
+}
+
+#[event]
+pub struct TreasuryUpdated {
+    pub factory: Pubkey,
+    pub treasury: Pubkey,
+    pub registration_fee_lamports: u64,
+}
+
+#[event]
+pub struct RegistrationBondUpdated {
+    pub factory: Pubkey,
+    pub bond_lamports: u64,
+}
+
+#[event]
+pub struct RegistrationBondPosted {
+    pub origin_mint: Pubkey,
+    pub registrant: Pubkey,
+    pub lamports: u64,
+}
+
+#[event]
+pub struct RegistrationBondRefunded {
+    pub origin_mint: Pubkey,
+    pub registrant: Pubkey,
+    pub lamports: u64,
+}
+
+#[event]
+pub struct RegistrationBondSlashed {
+    pub origin_mint: Pubkey,
+    pub registrant: Pubkey,
+    pub lamports: u64,
+}
+
+#[event]
+pub struct RegistrationFeeCollected {
+    pub origin_mint: Pubkey,
+    pub payer: Pubkey,
+    pub lamports: u64,
+}
+
+#[event]
+pub struct TreasuryWithdrawn {
+    pub factory: Pubkey,
+    pub treasury: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct PtknMinted {
+    pub origin_mint: Pubkey,
+    pub ptkn_mint: Pubkey,
+    pub amount: u64,
+    pub total_minted: u128,
+    pub total_burned: u128,
+}
+
+#[event]
+pub struct PtknBurned {
+    pub origin_mint: Pubkey,
+    pub ptkn_mint: Pubkey,
+    pub amount: u64,
+    pub total_minted: u128,
+    pub total_burned: u128,
+}
+
+#[event]
+pub struct PtknSupplyCapUpdated {
+    pub origin_mint: Pubkey,
+    pub max_ptkn_supply: u64,
+}
+
 #[event]
 pub struct MintThawed {
     pub origin_mint: Pubkey,
@@ -805,12 +4752,146 @@ pub struct FactoryUnpaused {
     pub authority: Pubkey,
 }
 
+#[event]
+pub struct PauseScopesUpdated {
+    pub factory: Pubkey,
+    pub paused_scopes: u8,
+}
+
+#[event]
+pub struct CouncilUpdated {
+    pub factory: Pubkey,
+    pub members: Vec<Pubkey>,
+    pub threshold: u8,
+    pub epoch: u32,
+}
+
+#[event]
+pub struct CouncilDissolved {
+    pub factory: Pubkey,
+    pub new_authority: Pubkey,
+    pub epoch: u32,
+}
+
+#[event]
+pub struct GovernanceAuthoritySet {
+    pub factory: Pubkey,
+    pub governance_program: Pubkey,
+    pub governance: Pubkey,
+    pub native_treasury: Pubkey,
+}
+
+#[event]
+pub struct CouncilProposalCreated {
+    pub factory: Pubkey,
+    pub proposal: Pubkey,
+    pub id: u64,
+    pub proposer: Pubkey,
+}
+
+#[event]
+pub struct CouncilProposalApproved {
+    pub proposal: Pubkey,
+    pub member: Pubkey,
+    pub approvals: u8,
+}
+
+#[event]
+pub struct CouncilProposalExecuted {
+    pub proposal: Pubkey,
+    pub executor: Pubkey,
+}
+
+#[event]
+pub struct AuthorityUpdated {
+    pub factory: Pubkey,
+    pub previous_authority: Pubkey,
+    pub new_authority: Pubkey,
+}
+
+#[event]
+pub struct TimelockSecondsUpdated {
+    pub factory: Pubkey,
+    pub timelock_seconds: i64,
+}
+
+#[event]
+pub struct DefaultFeeUpdated {
+    pub factory: Pubkey,
+    pub fee_bps: u16,
+}
+
+#[event]
+pub struct PoolProgramUpdated {
+    pub factory: Pubkey,
+    pub program: Pubkey,
+    pub allowed: bool,
+}
+
+#[event]
+pub struct ProtocolConfigInitialized {
+    pub factory: Pubkey,
+    pub max_fee_bps: u16,
+}
+
+#[event]
+pub struct KillSwitchUpdated {
+    pub factory: Pubkey,
+    pub engaged: bool,
+}
+
+#[event]
+pub struct ProtocolMaxFeeUpdated {
+    pub factory: Pubkey,
+    pub max_fee_bps: u16,
+}
+
+#[event]
+pub struct VerifierProgramUpdated {
+    pub factory: Pubkey,
+    pub program: Pubkey,
+    pub allowed: bool,
+}
+
+#[event]
+pub struct RestrictedExecutionUpdated {
+    pub factory: Pubkey,
+    pub restricted: bool,
+}
+
+#[event]
+pub struct GuardianUpdated {
+    pub factory: Pubkey,
+    pub guardian: Pubkey,
+}
+
+#[event]
+pub struct OperatorUpdated {
+    pub factory: Pubkey,
+    pub operator: Pubkey,
+}
+
+#[event]
+pub struct TimelockDelaysUpdated {
+    pub factory: Pubkey,
+    pub delays: TimelockDelays,
+}
+
+#[event]
+pub struct TimelockExpired {
+    pub factory: Pubkey,
+    pub action_hash: [u8; 32],
+    pub expires_after: i64,
+    pub closer: Pubkey,
+}
+
 #[event]
 pub struct TimelockQueued {
     pub factory: Pubkey,
     pub action_hash: [u8; 32],
     pub queued_at: i64,
     pub execute_after: i64,
+    pub expires_after: i64,
 }
 
 #[event]
@@ -835,6 +4916,42 @@ pub enum MintStatus {
     Frozen = 2,
 }
 
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PtknTokenProgram {
+    Unknown = 0,
+    Token = 1,
+    Token2022 = 2,
+}
+
+impl PtknTokenProgram {
+    pub fn from_program(program: &Pubkey) -> Self {
+        if *program == anchor_spl::token::ID {
+            Self::Token
+        } else if *program == spl_token_2022::ID {
+            Self::Token2022
+        } else {
+            Self::Unknown
+        }
+    }
+
+    pub fn program_id(self) -> Option<Pubkey> {
+        match self {
+            Self::Unknown => None,
+            Self::Token => Some(anchor_spl::token::ID),
+            Self::Token2022 => Some(spl_token_2022::ID),
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::Token,
+            2 => Self::Token2022,
+            _ => Self::Unknown,
+        }
+    }
+}
+
 #[error_code]
 pub enum FactoryError {
     #[msg("E_ALREADY_REGISTERED")]
@@ -881,4 +4998,80 @@ pub enum FactoryError {
     OriginMintMismatch,
     #[msg("E_INVALID_AMOUNT")]
     InvalidAmount,
+    #[msg("E_POOL_ADDRESS_MISMATCH")]
+    PoolAddressMismatch,
+    #[msg("E_COUNCIL_INVALID")]
+    CouncilInvalid,
+    #[msg("E_NOT_COUNCIL_MEMBER")]
+    NotCouncilMember,
+    #[msg("E_COUNCIL_PROPOSAL_TOO_LARGE")]
+    CouncilProposalTooLarge,
+    #[msg("E_COUNCIL_PROPOSAL_EXECUTED")]
+    CouncilProposalExecuted,
+    #[msg("E_COUNCIL_PROPOSAL_STALE")]
+    CouncilProposalStale,
+    #[msg("E_COUNCIL_THRESHOLD_NOT_MET")]
+    CouncilThresholdNotMet,
+    #[msg("E_COUNCIL_ACCOUNT_MISSING")]
+    CouncilAccountMissing,
+    #[msg("E_GOVERNANCE_INVALID")]
+    GovernanceInvalid,
+    #[msg("E_TIMELOCK_INDEX_FULL")]
+    TimelockIndexFull,
+    #[msg("E_TIMELOCK_DELAY_OUT_OF_BOUNDS")]
+    TimelockDelayOutOfBounds,
+    #[msg("E_TIMELOCK_EXPIRED")]
+    TimelockExpired,
+    #[msg("E_TIMELOCK_NOT_EXPIRED")]
+    TimelockNotExpired,
+    #[msg("E_MINT_NOT_FROZEN")]
+    MintNotFrozen,
+    #[msg("E_POOL_STILL_OPEN")]
+    PoolStillOpen,
+    #[msg("E_POOL_PROGRAM_NOT_ALLOWED")]
+    PoolProgramNotAllowed,
+    #[msg("E_POOL_PROGRAM_ALLOWLIST_FULL")]
+    PoolProgramAllowlistFull,
+    #[msg("E_POOL_PROGRAM_INVALID")]
+    PoolProgramInvalid,
+    #[msg("E_PTKN_METADATA_INVALID")]
+    PtknMetadataInvalid,
+    #[msg("E_PTKN_EXTENSIONS_INVALID")]
+    PtknExtensionsInvalid,
+    #[msg("E_PTKN_EXTENSIONS_REQUIRE_TOKEN_2022")]
+    PtknExtensionsRequireToken2022,
+    #[msg("E_PTKN_SUPPLY_CAP_EXCEEDED")]
+    PtknSupplyCapExceeded,
+    #[msg("E_AMOUNT_OVERFLOW")]
+    AmountOverflow,
+    #[msg("E_TREASURY_NOT_SET")]
+    TreasuryNotSet,
+    #[msg("E_TREASURY_MISMATCH")]
+    TreasuryMismatch,
+    #[msg("E_INSUFFICIENT_TREASURY")]
+    InsufficientTreasury,
+    #[msg("E_PTKN_SUPPLY_OUTSTANDING")]
+    PtknSupplyOutstanding,
+    #[msg("E_PAUSE_SCOPES_INVALID")]
+    PauseScopesInvalid,
+    #[msg("E_POOL_NOT_DEPLOYED")]
+    PoolNotDeployed,
+    #[msg("E_VERIFYING_KEY_MISSING")]
+    VerifyingKeyMissing,
+    #[msg("E_VERIFYING_KEY_MISMATCH")]
+    VerifyingKeyMismatch,
+    #[msg("E_POOL_ACCOUNTS_MISSING")]
+    PoolAccountsMissing,
+    #[msg("E_PROTOCOL_CONFIG_MISSING")]
+    ProtocolConfigMissing,
+    #[msg("E_VERIFIER_PROGRAM_INVALID")]
+    VerifierProgramInvalid,
+    #[msg("E_VERIFIER_PROGRAM_ALLOWLIST_FULL")]
+    VerifierProgramAllowlistFull,
+    #[msg("E_TEMPLATE_INVALID")]
+    TemplateInvalid,
+    #[msg("E_PERMISSIONLESS_REGISTRATION_DISABLED")]
+    PermissionlessRegistrationDisabled,
+    #[msg("E_REGISTRATION_BOND_OUTSTANDING")]
+    RegistrationBondOutstanding,
 }
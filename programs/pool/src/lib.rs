@@ -1,26 +1,33 @@
 use anchor_lang::prelude::*;
 #[cfg(feature = "idl-build")]
 use anchor_lang::idl::IdlBuild;
-use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
-use anchor_lang::solana_program::program::invoke_signed;
+use anchor_lang::solana_program::instruction::{
+    get_stack_height, AccountMeta, Instruction, TRANSACTION_LEVEL_STACK_HEIGHT,
+};
+use anchor_lang::solana_program::program::{invoke, invoke_signed};
 use anchor_lang::solana_program::program_option::COption;
+use anchor_lang::solana_program::program_pack::Pack;
 use anchor_lang::solana_program::sysvar::instructions::{
     load_current_index_checked, load_instruction_at_checked,
 };
+use anchor_lang::InstructionData;
+use anchor_spl::associated_token::AssociatedToken;
 use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
 use ark_bn254::Fr;
 use ark_ff::{BigInteger256, PrimeField};
-#[cfg(feature = "invariant_checks")]
-use core::convert::TryFrom;
 use core::convert::TryInto;
 use sha3::{Digest, Keccak256};
 use solana_program::hash::hashv;
 
-use ptf_common::hooks::{HookInstruction, PostShieldHook, PostUnshieldHook};
+use ptf_common::hooks::{
+    HookInstruction, PostShieldHook, PostTransferHook, PostUnshieldHook, PreShieldHook,
+    PreUnshieldHook,
+};
 use ptf_common::{
-    seeds, FeatureFlags, FEATURE_HOOKS_ENABLED, FEATURE_PRIVATE_TRANSFER_ENABLED, MAX_BPS,
+    seeds, FeatureFlags, FEATURE_HOOKS_ENABLED, FEATURE_INVARIANT_CHECKS, FEATURE_LEAF_INDEX,
+    FEATURE_PRIVATE_TRANSFER_ENABLED, MAX_BPS,
 };
-use ptf_factory::{program::PtfFactory, MintMapping};
+use ptf_factory::{program::PtfFactory, MintMapping, MintStatus, ProtocolConfig, PtknTokenProgram};
 use ptf_vault::program::PtfVault;
 use ptf_vault::{self};
 use ptf_verifier_groth16::program::PtfVerifierGroth16;
@@ -31,13 +38,43 @@ mod poseidon;
 declare_id!("7kbUWzeTPY6qb1mFJC1ZMRmTZAdaHC27yukc3Czj7fKh");
 
 const DEFAULT_CANOPY_DEPTH: u8 = 8;
+/// Nullifiers are stored in the legacy set or its shards.
+pub const NULLIFIER_MODE_SET: u8 = 0;
+/// Every spent nullifier gets its own `NullifierRecord` PDA.
+pub const NULLIFIER_MODE_PDA: u8 = 1;
+/// Percentage fees round down (truncating division).
+pub const FEE_ROUNDING_FLOOR: u8 = 0;
+/// Percentage fees round up to the next base unit.
+pub const FEE_ROUNDING_CEIL: u8 = 1;
+/// Wrapped SOL mint used by native SOL pools.
+pub const NATIVE_MINT: Pubkey = anchor_spl::token::spl_token::native_mint::ID;
+/// Upper bound on recipients paid by a single `unshield_many`.
+pub const MAX_UNSHIELD_RECIPIENTS: usize = 8;
+/// Upper bound on the encrypted note payload carried by shields and transfers.
+pub const MAX_ENCRYPTED_NOTE_LEN: usize = 512;
+/// Upper bound on each per-output ciphertext carried by a transfer.
+pub const MAX_OUTPUT_CIPHERTEXT_LEN: usize = 256;
 
 #[program]
 pub mod ptf_pool {
     use super::*;
 
-    pub fn initialize_pool(ctx: Context<InitializePool>, fee_bps: u16, features: u8) -> Result<()> {
-        require!(fee_bps <= MAX_BPS, PoolError::InvalidFeeBps);
+    /// The unshield fee comes from the mint mapping's override or the factory default,
+    /// so factory governance sets it; `refresh_fee_from_factory` picks up later changes.
+    pub fn initialize_pool(
+        ctx: Context<InitializePool>,
+        shield_fee_bps: u16,
+        features: u8,
+        merkle_depth: u8,
+    ) -> Result<()> {
+        let unshield_fee_bps = ctx
+            .accounts
+            .mint_mapping
+            .fee_bps(ctx.accounts.factory_state.default_fee_bps);
+        require!(
+            shield_fee_bps <= MAX_BPS && unshield_fee_bps <= MAX_BPS,
+            PoolError::InvalidFeeBps
+        );
 
         require_keys_eq!(
             ctx.accounts.vault_state.origin_mint,
@@ -49,9 +86,15 @@ pub mod ptf_pool {
             ctx.accounts.origin_mint.key(),
             PoolError::OriginMintMismatch,
         );
+        let pinned_key = ctx.accounts.mint_mapping.verifying_key;
+        require!(
+            pinned_key == Pubkey::default() || pinned_key == ctx.accounts.verifying_key.key(),
+            PoolError::VerifyingKeyNotPinned
+        );
 
         let pool_key = ctx.accounts.pool_state.key();
         let mut pool_state = ctx.accounts.pool_state.load_init()?;
+        pool_state.version = PoolState::CURRENT_VERSION;
         pool_state.origin_mint = ctx.accounts.vault_state.origin_mint;
         pool_state.vault = ctx.accounts.vault_state.key();
         pool_state.verifier_program = ctx.accounts.verifier_program.key();
@@ -59,8 +102,10 @@ pub mod ptf_pool {
         pool_state.verifying_key_id = ctx.accounts.verifying_key.verifying_key_id;
         pool_state.verifying_key_hash = ctx.accounts.verifying_key.hash;
         pool_state.authority = ctx.accounts.authority.key();
-        pool_state.fee_bps = fee_bps;
-        pool_state.features = FeatureFlags::from(features);
+        pool_state.fee_recipient = ctx.accounts.authority.key();
+        pool_state.shield_fee_bps = shield_fee_bps;
+        pool_state.unshield_fee_bps = unshield_fee_bps;
+        pool_state.features = features;
         pool_state.bump = ctx.bumps.pool_state;
         pool_state.commitment_tree = ctx.accounts.commitment_tree.key();
         pool_state.roots_len = 0;
@@ -107,6 +152,11 @@ pub mod ptf_pool {
             pool_state.twin_mint_enabled = false;
         }
         pool_state.pending_shield = PendingShield::inactive();
+        pool_state.nullifier_shard_count = 0;
+        pool_state.nullifier_mode = NULLIFIER_MODE_SET;
+        pool_state.max_change_notes = 1;
+        pool_state.max_inputs = 0;
+        pool_state.max_outputs = 0;
 
         require_keys_eq!(
             ctx.accounts.vault_state.pool_authority,
@@ -121,8 +171,20 @@ pub mod ptf_pool {
             hook_config.post_shield_enabled = false;
             hook_config.post_unshield_program_id = Pubkey::default();
             hook_config.post_unshield_enabled = false;
-            hook_config.required_accounts = [[0u8; 32]; HookConfig::MAX_REQUIRED_ACCOUNTS];
-            hook_config.required_accounts_len = 0;
+            hook_config.pre_hook_program_id = Pubkey::default();
+            hook_config.pre_shield_enabled = false;
+            hook_config.pre_unshield_enabled = false;
+            hook_config.post_transfer_program_id = Pubkey::default();
+            hook_config.post_transfer_enabled = false;
+            hook_config.post_shield_accounts = [[0u8; 32]; HookConfig::MAX_REQUIRED_ACCOUNTS];
+            hook_config.post_shield_accounts_len = 0;
+            hook_config.post_shield_writable = 0;
+            hook_config.post_unshield_accounts = [[0u8; 32]; HookConfig::MAX_REQUIRED_ACCOUNTS];
+            hook_config.post_unshield_accounts_len = 0;
+            hook_config.post_unshield_writable = 0;
+            hook_config.account_templates =
+                [HookAccountTemplate::empty(); HookConfig::MAX_ACCOUNT_TEMPLATES];
+            hook_config.account_templates_len = 0;
             hook_config.mode = HookAccountMode::Strict;
             hook_config.bump = ctx.bumps.hook_config;
         }
@@ -131,16 +193,27 @@ pub mod ptf_pool {
             let mut nulls = ctx.accounts.nullifier_set.load_init()?;
             nulls.pool = pool_key;
             nulls.bump = ctx.bumps.nullifier_set;
+            nulls.shard = 0;
             nulls.count = 0;
             nulls.bloom = [0u8; NullifierSet::BLOOM_BYTES];
+            nulls.version = NullifierSet::CURRENT_VERSION;
         }
 
         {
-            let mut tree = ctx.accounts.commitment_tree.load_init()?;
-            tree.init(pool_key, DEFAULT_CANOPY_DEPTH, ctx.bumps.commitment_tree)?;
+            let info = ctx.accounts.commitment_tree.to_account_info();
+            let mut data = info.try_borrow_mut_data()?;
+            let (tree, levels) = CommitmentTree::split_data_mut(&mut data)?;
+            tree.init(
+                levels,
+                pool_key,
+                merkle_depth,
+                DEFAULT_CANOPY_DEPTH,
+                ctx.bumps.commitment_tree,
+            )?;
             pool_state.current_root = tree.current_root;
             pool_state.roots_len = 1;
             pool_state.recent_roots[0] = tree.current_root;
+            pool_state.recent_root_slots[0] = Clock::get()?.slot;
         }
 
         {
@@ -150,1375 +223,6318 @@ pub mod ptf_pool {
 
         emit!(PoolInitialized {
             origin_mint: pool_state.origin_mint,
-            fee_bps,
+            shield_fee_bps,
+            unshield_fee_bps,
             features,
         });
         Ok(())
     }
 
-    pub fn set_fee(ctx: Context<UpdateAuthority>, fee_bps: u16) -> Result<()> {
-        require!(fee_bps <= MAX_BPS, PoolError::InvalidFeeBps);
-        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
-        pool_state.fee_bps = fee_bps;
-        emit!(FeeUpdated {
-            origin_mint: pool_state.origin_mint,
-            fee_bps,
-        });
-        Ok(())
-    }
-
-    pub fn set_features(ctx: Context<UpdateAuthority>, features: u8) -> Result<()> {
+    pub fn set_fee(
+        ctx: Context<UpdateAuthority>,
+        shield_fee_bps: u16,
+        unshield_fee_bps: u16,
+        shield_flat_fee: u64,
+        unshield_flat_fee: u64,
+    ) -> Result<()> {
         let mut pool_state = ctx.accounts.pool_state.load_mut()?;
-        pool_state.features = FeatureFlags::from(features);
-        emit!(FeaturesUpdated {
-            origin_mint: pool_state.origin_mint,
-            features,
-        });
-        Ok(())
+        ensure_direct_update_allowed_by(&pool_state, &ctx.accounts.authority.key())?;
+        apply_fee_update(
+            &mut pool_state,
+            shield_fee_bps,
+            unshield_fee_bps,
+            shield_flat_fee,
+            unshield_flat_fee,
+        )
     }
 
-    pub fn configure_hooks(ctx: Context<ConfigureHooks>, args: HookConfigArgs) -> Result<()> {
+    /// Resets the unshield fee to the mint mapping's override or the factory default,
+    /// replacing any rate set with `set_fee`. Permissionless: the factory's own timelock
+    /// already governs the value.
+    pub fn refresh_fee_from_factory(ctx: Context<RefreshFeeFromFactory>) -> Result<()> {
         let mut pool_state = ctx.accounts.pool_state.load_mut()?;
-        require!(
-            pool_state
-                .features
-                .contains(FeatureFlags::from(FEATURE_HOOKS_ENABLED)),
-            PoolError::HooksDisabled,
+        let unshield_fee_bps = ctx
+            .accounts
+            .mint_mapping
+            .fee_bps(ctx.accounts.factory_state.default_fee_bps);
+        let (shield_fee_bps, shield_flat_fee, unshield_flat_fee) = (
+            pool_state.shield_fee_bps,
+            pool_state.shield_flat_fee,
+            pool_state.unshield_flat_fee,
         );
+        apply_fee_update(
+            &mut pool_state,
+            shield_fee_bps,
+            unshield_fee_bps,
+            shield_flat_fee,
+            unshield_flat_fee,
+        )
+    }
 
-        let mut hook_config = ctx.accounts.hook_config.load_mut()?;
-        hook_config.pool = ctx.accounts.pool_state.key();
-        hook_config.post_shield_program_id = args.post_shield_program;
-        hook_config.post_shield_enabled = args.post_shield_enabled;
-        hook_config.post_unshield_program_id = args.post_unshield_program;
-        hook_config.post_unshield_enabled = args.post_unshield_enabled;
-        hook_config.mode = args.mode;
-        hook_config.required_accounts_len = 0;
-        hook_config.required_accounts = [[0u8; 32]; HookConfig::MAX_REQUIRED_ACCOUNTS];
-        for (idx, key) in args.required_accounts.iter().enumerate() {
+    /// Reallocates a pool created by an older program version to the current
+    /// `PoolState` layout. Appended fields start zeroed; the fee recipient defaults to
+    /// the authority, and the shield rate stays 0 so deposits remain free until set.
+    pub fn migrate_pool_state(ctx: Context<MigratePoolState>) -> Result<()> {
+        let info = ctx.accounts.pool_state.to_account_info();
+        {
+            let data = info.try_borrow_data()?;
             require!(
-                idx < HookConfig::MAX_REQUIRED_ACCOUNTS,
-                PoolError::TooManyHookAccounts
+                data.len() >= 8 + 64 && data[..8] == *PoolState::DISCRIMINATOR,
+                PoolError::PoolStateInvalid
+            );
+            let authority = Pubkey::try_from(&data[8..40]).unwrap();
+            let origin_mint = Pubkey::try_from(&data[40..72]).unwrap();
+            require_keys_eq!(
+                authority,
+                ctx.accounts.authority.key(),
+                PoolError::PoolStateInvalid
             );
-            hook_config.required_accounts[idx] = key.to_bytes();
-            hook_config.required_accounts_len += 1;
+            let (expected, _) =
+                Pubkey::find_program_address(&[seeds::POOL, origin_mint.as_ref()], &crate::ID);
+            require_keys_eq!(info.key(), expected, PoolError::PoolStateInvalid);
         }
 
-        pool_state.hook_config = ctx.accounts.hook_config.key();
-        pool_state.hook_config_present = (args.post_shield_enabled
-            && args.post_shield_program != Pubkey::default())
-            || (args.post_unshield_enabled && args.post_unshield_program != Pubkey::default());
+        let old_len = info.data_len();
+        grow_account(
+            &info,
+            PoolState::SPACE,
+            &ctx.accounts.authority,
+            &ctx.accounts.system_program,
+        )?;
 
-        emit!(HookConfigUpdated {
+        let mut data = info.try_borrow_mut_data()?;
+        let pool_state = bytemuck::from_bytes_mut::<PoolState>(
+            &mut data[8..8 + core::mem::size_of::<PoolState>()],
+        );
+        if pool_state.fee_recipient == Pubkey::default() {
+            pool_state.fee_recipient = pool_state.authority;
+        }
+        // Pools already winding down keep exiting under the verifier they had.
+        if pool_state.emergency_exit && pool_state.emergency_verifier_program == Pubkey::default() {
+            pool_state.emergency_verifier_program = pool_state.verifier_program;
+            pool_state.emergency_verifying_key = pool_state.verifying_key;
+            pool_state.emergency_verifying_key_id = pool_state.verifying_key_id;
+            pool_state.emergency_verifying_key_hash = pool_state.verifying_key_hash;
+        }
+        pool_state.version = PoolState::CURRENT_VERSION;
+        if let Some(hook_config) = ctx.accounts.hook_config.as_ref() {
+            require_keys_eq!(
+                hook_config.key(),
+                pool_state.hook_config,
+                PoolError::HookConfigInvalid
+            );
+            grow_account(
+                &hook_config.to_account_info(),
+                HookConfig::SPACE,
+                &ctx.accounts.authority,
+                &ctx.accounts.system_program,
+            )?;
+        }
+        emit!(PoolStateMigrated {
             origin_mint: pool_state.origin_mint,
-            post_shield_program: args.post_shield_program,
-            post_unshield_program: args.post_unshield_program,
-            post_shield_enabled: args.post_shield_enabled,
-            post_unshield_enabled: args.post_unshield_enabled,
-            mode: args.mode as u8,
+            previous_len: old_len as u32,
+            new_len: info.data_len() as u32,
         });
         Ok(())
     }
 
-    pub fn shield<'info>(
-        ctx: Context<'_, '_, '_, 'info, Shield<'info>>,
-        args: ShieldArgs,
-    ) -> Result<()> {
-        let pool_loader = &ctx.accounts.pool_state;
-        let mut pool_state = pool_loader.load_mut()?;
-        require!(
-            pool_state.pending_shield.is_inactive(),
-            PoolError::PendingShieldInFlight
-        );
-        let claim_bump = ctx.bumps.shield_claim;
-        {
-            let shield_claim = &mut ctx.accounts.shield_claim;
-            if shield_claim.pool == Pubkey::default() {
-                shield_claim.pool = pool_loader.key();
-                shield_claim.bump = claim_bump;
+    pub fn migrate_commitment_tree(ctx: Context<MigratePoolAccount>) -> Result<()> {
+        let (origin_mint, expected) = {
+            let pool_state = ctx.accounts.pool_state.load()?;
+            (pool_state.origin_mint, pool_state.commitment_tree)
+        };
+        let info = ctx.accounts.target.to_account_info();
+        require_keys_eq!(info.key(), expected, PoolError::CommitmentTreeMismatch);
+        let (previous_version, depth) = {
+            let mut data = info.try_borrow_mut_data()?;
+            let previous_version = if data.len() == CommitmentTree::LEGACY_SPACE {
+                CommitmentTree::unpack_legacy(&mut data)?
             } else {
-                require_keys_eq!(
-                    shield_claim.pool,
-                    pool_loader.key(),
-                    PoolError::ShieldClaimMismatch
-                );
-            }
-            require!(!shield_claim.is_active(), PoolError::PendingShieldInFlight);
-        }
-        require_keys_eq!(
-            ctx.accounts.verifier_program.key(),
-            pool_state.verifier_program,
-            PoolError::VerifierMismatch,
-        );
-        require_keys_eq!(
-            ctx.accounts.verifying_key.key(),
-            pool_state.verifying_key,
-            PoolError::VerifierMismatch,
-        );
-        require!(
-            ctx.accounts.verifying_key.verifying_key_id == pool_state.verifying_key_id,
-            PoolError::VerifierMismatch,
-        );
-        require!(
-            ctx.accounts.verifying_key.hash == pool_state.verifying_key_hash,
-            PoolError::VerifyingKeyHashMismatch,
-        );
-        require_keys_eq!(
-            ctx.accounts.vault_state.key(),
-            pool_state.vault,
-            PoolError::MismatchedVaultAuthority,
-        );
-        require_keys_eq!(
-            ctx.accounts.vault_state.pool_authority,
-            pool_loader.key(),
-            PoolError::MismatchedVaultAuthority,
-        );
-        require_keys_eq!(
-            ctx.accounts.vault_token_account.owner,
-            pool_state.vault,
-            PoolError::VaultTokenAccountMismatch,
-        );
-        require_keys_eq!(
-            ctx.accounts.vault_token_account.mint,
-            pool_state.origin_mint,
-            PoolError::OriginMintMismatch,
-        );
-        require_keys_eq!(
-            ctx.accounts.origin_mint.key(),
-            pool_state.origin_mint,
-            PoolError::OriginMintMismatch,
-        );
-        require_keys_eq!(
-            ctx.accounts.depositor_token_account.owner,
-            ctx.accounts.payer.key(),
-            PoolError::InvalidDepositorAccount,
-        );
-        require_keys_eq!(
-            ctx.accounts.depositor_token_account.mint,
-            pool_state.origin_mint,
-            PoolError::OriginMintMismatch,
-        );
-        require_keys_eq!(
-            ctx.accounts.commitment_tree.key(),
-            pool_state.commitment_tree,
-            PoolError::CommitmentTreeMismatch,
+                CommitmentTree::split_data_mut(&mut data)?.0.version
+            };
+            let (tree, _) = CommitmentTree::split_data_mut(&mut data)?;
+            (previous_version, tree.depth() as u8)
+        };
+        shrink_account(
+            &info,
+            CommitmentTree::space(depth),
+            &ctx.accounts.authority.to_account_info(),
+        )?;
+        emit!(AccountMigrated {
+            origin_mint,
+            account: info.key(),
+            previous_version,
+            version: CommitmentTree::CURRENT_VERSION,
+            new_len: info.data_len() as u32,
+        });
+        Ok(())
+    }
+
+    pub fn migrate_note_ledger(ctx: Context<MigratePoolAccount>) -> Result<()> {
+        let (origin_mint, expected) = {
+            let pool_state = ctx.accounts.pool_state.load()?;
+            (pool_state.origin_mint, pool_state.note_ledger)
+        };
+        let info = ctx.accounts.target.to_account_info();
+        require_keys_eq!(info.key(), expected, PoolError::NoteLedgerMismatch);
+        grow_account(
+            &info,
+            NoteLedger::SPACE,
+            &ctx.accounts.authority,
+            &ctx.accounts.system_program,
+        )?;
+        let mut data = info.try_borrow_mut_data()?;
+        let ledger = bytemuck::from_bytes_mut::<NoteLedger>(
+            &mut data[8..8 + core::mem::size_of::<NoteLedger>()],
         );
+        let previous_version = ledger.version;
+        ledger.version = NoteLedger::CURRENT_VERSION;
+        emit!(AccountMigrated {
+            origin_mint,
+            account: info.key(),
+            previous_version,
+            version: ledger.version,
+            new_len: info.data_len() as u32,
+        });
+        Ok(())
+    }
+
+    /// Upgrades the legacy nullifier set or one of its shards. Grown sets keep their
+    /// overflow entries in place because the fixed header size has not changed.
+    pub fn migrate_nullifier_set(ctx: Context<MigratePoolAccount>) -> Result<()> {
+        let pool_key = ctx.accounts.pool_state.key();
+        let origin_mint = ctx.accounts.pool_state.load()?.origin_mint;
+        let info = ctx.accounts.target.to_account_info();
+        require_keys_eq!(*info.owner, crate::ID, PoolError::NullifierSetInvalid);
+        let (legacy, _) =
+            Pubkey::find_program_address(&[seeds::NULLIFIERS, origin_mint.as_ref()], &crate::ID);
+        let mut data = if info.key() == legacy {
+            let data = info.try_borrow_mut_data()?;
+            require!(
+                data.len() >= 8 && &data[..8] == NullifierSet::DISCRIMINATOR,
+                PoolError::NullifierSetInvalid
+            );
+            data
+        } else {
+            let shard = {
+                let data = info.try_borrow_data()?;
+                require!(
+                    data.len() >= 8 && &data[..8] == NullifierSet::DISCRIMINATOR,
+                    PoolError::NullifierSetInvalid
+                );
+                NullifierSet::split_data(&data)?.0.shard
+            };
+            NullifierSet::load_shard_data(&info, pool_key, origin_mint, shard)?
+        };
+        let (set, _) = NullifierSet::split_data_mut(&mut data)?;
+        let previous_version = set.version;
+        set.version = NullifierSet::CURRENT_VERSION;
+        emit!(AccountMigrated {
+            origin_mint,
+            account: info.key(),
+            previous_version,
+            version: set.version,
+            new_len: info.data_len() as u32,
+        });
+        Ok(())
+    }
 
-        let commitment_tree_data = ctx.accounts.commitment_tree.load()?;
+    pub fn set_max_change_notes(ctx: Context<UpdateAuthority>, max_change_notes: u8) -> Result<()> {
         require!(
-            commitment_tree_data.current_root == pool_state.current_root,
-            PoolError::RootMismatch,
+            (1..=PoolState::MAX_CHANGE_NOTES).contains(&max_change_notes),
+            PoolError::InvalidChangeNoteCount
         );
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        pool_state.max_change_notes = max_change_notes;
+        emit!(MaxChangeNotesUpdated {
+            origin_mint: pool_state.origin_mint,
+            max_change_notes,
+        });
+        Ok(())
+    }
 
-        if pool_state.twin_mint_enabled {
-            let twin_mint = ctx
-                .accounts
-                .twin_mint
-                .as_ref()
-                .ok_or(PoolError::TwinMintNotConfigured)?;
+    pub fn set_fee_recipient(ctx: Context<UpdateAuthority>, fee_recipient: Pubkey) -> Result<()> {
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        pool_state.fee_recipient = fee_recipient;
+        emit!(FeeRecipientUpdated {
+            origin_mint: pool_state.origin_mint,
+            fee_recipient,
+        });
+        Ok(())
+    }
+
+    /// Releases accrued protocol fees from the vault. Funds can only go to a token account
+    /// owned by `pool_state.fee_recipient`, so the call is permissionless.
+    pub fn withdraw_fees(ctx: Context<WithdrawFees>, amount: u64) -> Result<()> {
+        let (origin_mint, pool_bump, fee_recipient) = {
+            let mut pool_state = ctx.accounts.pool_state.load_mut()?;
             require_keys_eq!(
-                twin_mint.key(),
-                pool_state.twin_mint,
-                PoolError::TwinMintMismatch,
+                ctx.accounts.vault_state.key(),
+                pool_state.vault,
+                PoolError::MismatchedVaultAuthority,
             );
-        }
+            require_keys_eq!(
+                ctx.accounts.fee_recipient_token_account.owner,
+                pool_state.fee_recipient,
+                PoolError::FeeRecipientMismatch,
+            );
+            require_keys_eq!(
+                ctx.accounts.fee_recipient_token_account.mint,
+                pool_state.origin_mint,
+                PoolError::OriginMintMismatch,
+            );
+            require!(
+                amount > 0 && u128::from(amount) <= pool_state.protocol_fees,
+                PoolError::InsufficientProtocolFees
+            );
+            pool_state.protocol_fees -= u128::from(amount);
+            (
+                pool_state.origin_mint,
+                pool_state.bump,
+                pool_state.fee_recipient,
+            )
+        };
 
-        let public_fields = parse_field_elements(&args.public_inputs)?;
-        require!(public_fields.len() >= 3, PoolError::InvalidPublicInputs);
+        let signer_seeds: [&[u8]; 3] = [seeds::POOL, origin_mint.as_ref(), &[pool_bump]];
+        let cpi_accounts = ptf_vault::cpi::accounts::Release {
+            vault_state: ctx.accounts.vault_state.to_account_info(),
+            vault_token_account: ctx.accounts.vault_token_account.to_account_info(),
+            destination_token_account: ctx.accounts.fee_recipient_token_account.to_account_info(),
+            pool_authority: ctx.accounts.pool_state.to_account_info(),
+            token_program: ctx.accounts.token_program.to_account_info(),
+        };
+        let signer = &[&signer_seeds[..]];
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.vault_program.to_account_info(),
+            cpi_accounts,
+            signer,
+        );
+        ptf_vault::cpi::release(cpi_ctx, amount)?;
 
-        let old_root_bytes = public_fields[0];
-        let new_root_bytes = public_fields[1];
-        let commitment_bytes = public_fields[2];
-        let mut old_root_be = old_root_bytes;
-        old_root_be.reverse();
-        let mut new_root_be = new_root_bytes;
-        new_root_be.reverse();
+        emit!(FeesWithdrawn {
+            origin_mint,
+            fee_recipient,
+            amount,
+        });
+        Ok(())
+    }
 
-        require!(
-            old_root_bytes == pool_state.current_root,
-            PoolError::RootMismatch
-        );
+    /// Creates the accrual account shields referred by `referrer` credit. Anyone may pay
+    /// for it.
+    pub fn register_referrer(ctx: Context<RegisterReferrer>, referrer: Pubkey) -> Result<()> {
+        let accrual = &mut ctx.accounts.referral_accrual;
+        accrual.pool = ctx.accounts.pool_state.key();
+        accrual.referrer = referrer;
+        accrual.accrued = 0;
+        accrual.bump = ctx.bumps.referral_accrual;
+        Ok(())
+    }
 
-        let cpi_accounts = ptf_verifier_groth16::cpi::accounts::VerifyGroth16 {
-            verifier_state: ctx.accounts.verifying_key.to_account_info(),
+    /// Pays `amount` of the referrer's accrued fee share from the vault to a token account
+    /// the referrer owns.
+    pub fn claim_referral_fees(ctx: Context<ClaimReferralFees>, amount: u64) -> Result<()> {
+        let (origin_mint, pool_bump) = {
+            let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+            require_keys_eq!(
+                ctx.accounts.vault_state.key(),
+                pool_state.vault,
+                PoolError::MismatchedVaultAuthority,
+            );
+            require_keys_eq!(
+                ctx.accounts.referrer_token_account.owner,
+                ctx.accounts.referrer.key(),
+                PoolError::ReferralAccrualMismatch,
+            );
+            require_keys_eq!(
+                ctx.accounts.referrer_token_account.mint,
+                pool_state.origin_mint,
+                PoolError::OriginMintMismatch,
+            );
+            let accrual = &mut ctx.accounts.referral_accrual;
+            require!(
+                amount > 0 && amount <= accrual.accrued,
+                PoolError::InsufficientReferralFees
+            );
+            accrual.accrued -= amount;
+            pool_state.referral_fees = pool_state
+                .referral_fees
+                .checked_sub(u128::from(amount))
+                .ok_or(PoolError::InsufficientReferralFees)?;
+            (pool_state.origin_mint, pool_state.bump)
         };
-        let cpi_ctx = CpiContext::new(
-            ctx.accounts.verifier_program.to_account_info(),
-            cpi_accounts,
-        );
-        ptf_verifier_groth16::cpi::verify_groth16(
-            cpi_ctx,
-            pool_state.verifying_key_id,
-            args.proof.clone(),
-            args.public_inputs.clone(),
-        )?;
 
-        let deposit_accounts = ptf_vault::cpi::accounts::Deposit {
+        let signer_seeds: [&[u8]; 3] = [seeds::POOL, origin_mint.as_ref(), &[pool_bump]];
+        let cpi_accounts = ptf_vault::cpi::accounts::Release {
             vault_state: ctx.accounts.vault_state.to_account_info(),
             vault_token_account: ctx.accounts.vault_token_account.to_account_info(),
-            origin_mint: ctx.accounts.origin_mint.to_account_info(),
-            depositor: ctx.accounts.payer.to_account_info(),
-            depositor_token_account: ctx.accounts.depositor_token_account.to_account_info(),
+            destination_token_account: ctx.accounts.referrer_token_account.to_account_info(),
+            pool_authority: ctx.accounts.pool_state.to_account_info(),
             token_program: ctx.accounts.token_program.to_account_info(),
         };
-        let deposit_ctx = CpiContext::new(
+        let signer = &[&signer_seeds[..]];
+        let cpi_ctx = CpiContext::new_with_signer(
             ctx.accounts.vault_program.to_account_info(),
-            deposit_accounts,
+            cpi_accounts,
+            signer,
         );
-        ptf_vault::cpi::deposit(deposit_ctx, args.amount)?;
+        ptf_vault::cpi::release(cpi_ctx, amount)?;
 
-        pool_state.pending_shield = PendingShield {
-            active: 1,
-            old_root: old_root_bytes,
-            new_root: new_root_bytes,
-            commitment: commitment_bytes,
-            amount_commit: args.amount_commit,
-            amount: args.amount,
-            depositor: ctx.accounts.payer.key(),
-            next_index: commitment_tree_data.next_index,
+        emit!(ReferralFeesClaimed {
+            origin_mint,
+            referrer: ctx.accounts.referrer.key(),
+            amount,
+        });
+        Ok(())
+    }
+
+    /// Mints accrued twin-mode fees as pTKN to a token account owned by
+    /// `pool_state.fee_recipient`. Permissionless like `withdraw_fees`; the minted supply
+    /// replaces the fee in the vault invariant.
+    pub fn withdraw_twin_fees(ctx: Context<WithdrawTwinFees>, amount: u64) -> Result<()> {
+        let (origin_mint, pool_bump, fee_recipient) = {
+            let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+            require!(
+                pool_state.twin_mint_enabled && ctx.accounts.mint_mapping.has_ptkn,
+                PoolError::TwinMintNotConfigured
+            );
+            require_keys_eq!(
+                ctx.accounts.twin_mint.key(),
+                pool_state.twin_mint,
+                PoolError::TwinMintMismatch,
+            );
+            require_keys_eq!(
+                ctx.accounts.fee_recipient_token_account.owner,
+                pool_state.fee_recipient,
+                PoolError::FeeRecipientMismatch,
+            );
+            require_keys_eq!(
+                ctx.accounts.fee_recipient_token_account.mint,
+                pool_state.twin_mint,
+                PoolError::TwinMintMismatch,
+            );
+            require!(
+                amount > 0 && u128::from(amount) <= pool_state.twin_protocol_fees,
+                PoolError::InsufficientProtocolFees
+            );
+            pool_state.twin_protocol_fees -= u128::from(amount);
+            (
+                pool_state.origin_mint,
+                pool_state.bump,
+                pool_state.fee_recipient,
+            )
         };
-        ctx.accounts.shield_claim.activate(
-            pool_loader.key(),
-            ctx.accounts.payer.key(),
-            commitment_bytes,
-            args.amount_commit,
-            old_root_bytes,
-            new_root_bytes,
-            args.amount,
-            commitment_tree_data.next_index,
-            claim_bump,
-        );
-
-        fn is_finalize_ix(ix: &Instruction, pool_key: Pubkey) -> bool {
-            ix.program_id == crate::ID
-                && ix.data.len() >= 8
-                && ix.data[..8] == instruction_discriminator("shield_finalize_ledger")
-                && ix.accounts.first().map(|meta| meta.pubkey) == Some(pool_key)
-        }
-
-        let ix_sysvar = ctx.accounts.instructions.to_account_info();
-        let mut finalize_found = false;
-
-        if let Ok(current_index) = load_current_index_checked(&ix_sysvar) {
-            let mut search_index = current_index as usize + 1;
-            loop {
-                match load_instruction_at_checked(search_index, &ix_sysvar) {
-                    Ok(ix) => {
-                        if is_finalize_ix(&ix, pool_loader.key()) {
-                            finalize_found = true;
-                            break;
-                        }
-                        search_index += 1;
-                    }
-                    Err(_) => break,
-                }
-            }
-        }
 
-        if !finalize_found {
-            let mut search_index = 0usize;
-            loop {
-                match load_instruction_at_checked(search_index, &ix_sysvar) {
-                    Ok(ix) => {
-                        if is_finalize_ix(&ix, pool_loader.key()) {
-                            finalize_found = true;
-                            break;
-                        }
-                        search_index += 1;
-                    }
-                    Err(_) => break,
-                }
-            }
-        }
+        ensure_twin_token_program(
+            &ctx.accounts.mint_mapping,
+            &ctx.accounts.token_program.key(),
+        )?;
 
-        if !finalize_found {
-            msg!("shield finalize instruction not detected; skipping enforcement");
-        }
+        let signer_seeds: [&[u8]; 3] = [seeds::POOL, origin_mint.as_ref(), &[pool_bump]];
+        let signer = &[&signer_seeds[..]];
+        let factory_accounts = ptf_factory::cpi::accounts::MintPtkn {
+            factory_state: ctx.accounts.factory_state.to_account_info(),
+            mint_mapping: ctx.accounts.mint_mapping.to_account_info(),
+            pool_authority: ctx.accounts.pool_state.to_account_info(),
+            ptkn_mint: ctx.accounts.twin_mint.to_account_info(),
+            destination_token_account: ctx.accounts.fee_recipient_token_account.to_account_info(),
+            token_program: ctx.accounts.token_program.to_account_info(),
+        };
+        let mint_ctx = CpiContext::new_with_signer(
+            ctx.accounts.factory_program.to_account_info(),
+            factory_accounts,
+            signer,
+        );
+        ptf_factory::cpi::mint_ptkn(mint_ctx, amount)?;
 
+        emit!(TwinFeesWithdrawn {
+            origin_mint,
+            fee_recipient,
+            amount,
+        });
         Ok(())
     }
 
-    pub fn shield_finalize_tree<'info>(
-        ctx: Context<'_, '_, '_, 'info, ShieldFinalizeTree<'info>>,
-    ) -> Result<()> {
-        process_shield_finalize_tree(
-            &ctx.accounts.pool_state,
-            &ctx.accounts.commitment_tree,
-            &mut ctx.accounts.shield_claim,
-        )
-    }
-
-    pub fn shield_finalize_ledger<'info>(
-        ctx: Context<'_, '_, '_, 'info, ShieldFinalizeLedger<'info>>,
-    ) -> Result<()> {
-        let pool_loader = &ctx.accounts.pool_state;
-
-        require!(
-            ctx.accounts.shield_claim.is_awaiting_ledger(),
-            PoolError::ShieldClaimStage
-        );
-        require_keys_eq!(
-            ctx.accounts.shield_claim.pool,
-            pool_loader.key(),
-            PoolError::ShieldClaimMismatch
-        );
+    /// Burns `amount` pTKN from the holder through the factory and releases the same
+    /// amount of origin tokens from the vault, less the unshield fee, without touching the
+    /// shielded pool. The fee stays in the vault as protocol fees.
+    pub fn redeem_ptkn(ctx: Context<RedeemPtkn>, amount: u64) -> Result<()> {
+        let (origin_mint, pool_bump, fee, payout) = {
+            let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+            pool_state.check_not_paused()?;
+            ensure_mint_active(&ctx.accounts.mint_mapping)?;
+            require!(
+                pool_state.twin_mint_enabled && ctx.accounts.mint_mapping.has_ptkn,
+                PoolError::TwinMintNotConfigured
+            );
+            require_keys_eq!(
+                ctx.accounts.twin_mint.key(),
+                pool_state.twin_mint,
+                PoolError::TwinMintMismatch,
+            );
+            ensure_twin_token_program(
+                &ctx.accounts.mint_mapping,
+                &ctx.accounts.twin_token_program.key(),
+            )?;
+            require_keys_eq!(
+                ctx.accounts.vault_state.key(),
+                pool_state.vault,
+                PoolError::MismatchedVaultAuthority,
+            );
+            require_keys_eq!(
+                ctx.accounts.vault_token_account.owner,
+                pool_state.vault,
+                PoolError::VaultTokenAccountMismatch,
+            );
+            require_keys_eq!(
+                ctx.accounts.vault_token_account.mint,
+                pool_state.origin_mint,
+                PoolError::OriginMintMismatch,
+            );
+            require_keys_eq!(
+                ctx.accounts.destination_token_account.mint,
+                pool_state.origin_mint,
+                PoolError::OriginMintMismatch,
+            );
+            check_destination_allowlist(
+                &pool_state,
+                ctx.accounts.destination_allowlist.as_ref(),
+                &[ctx.accounts.destination_token_account.owner],
+            )?;
 
-        let pending = ctx.accounts.shield_claim.snapshot();
-        let (hook_enabled, pool_key, pool_bump, origin_mint) = {
-            let pool_state = pool_loader.load()?;
-            let hook_enabled = pool_state
-                .features
-                .contains(FeatureFlags::from(FEATURE_HOOKS_ENABLED))
-                && pool_state.hook_config_present;
-            let pool_key = pool_loader.key();
-            let pool_bump = pool_state.bump;
-            let origin_mint = pool_state.origin_mint;
-            (hook_enabled, pool_key, pool_bump, origin_mint)
+            let (fee, payout) = pool_state.ptkn_redemption(amount)?;
+            if pool_state.rate_limiter != Pubkey::default() {
+                let limiter = ctx
+                    .accounts
+                    .rate_limiter
+                    .as_mut()
+                    .ok_or(PoolError::RateLimiterMismatch)?;
+                require_keys_eq!(
+                    limiter.key(),
+                    pool_state.rate_limiter,
+                    PoolError::RateLimiterMismatch
+                );
+                limiter.record_outflow(payout, Clock::get()?.slot)?;
+            }
+            // Twin supply falls by `amount` and the vault by `payout`, so the fee moves
+            // into `protocol_fees` and the supply invariant stays balanced.
+            pool_state.protocol_fees = pool_state
+                .protocol_fees
+                .checked_add(u128::from(fee))
+                .ok_or(PoolError::AmountOverflow)?;
+            (pool_state.origin_mint, pool_state.bump, fee, payout)
         };
 
-        #[cfg(feature = "invariant_checks")]
-        let requires_invariant = {
-            let mut note_ledger = ctx.accounts.note_ledger.load_mut()?;
-            note_ledger.record_shield(pending.amount, pending.amount_commit)?;
-            note_ledger.should_enforce_invariant(pending.amount)
-        };
-        #[cfg(not(feature = "invariant_checks"))]
-        let requires_invariant = {
-            let mut note_ledger = ctx.accounts.note_ledger.load_mut()?;
-            note_ledger.record_shield(pending.amount, pending.amount_commit)?;
-            false
-        };
+        let signer_seeds: [&[u8]; 3] = [seeds::POOL, origin_mint.as_ref(), &[pool_bump]];
+        let signer = &[&signer_seeds[..]];
+        let burn_ctx = CpiContext::new_with_signer(
+            ctx.accounts.factory_program.to_account_info(),
+            ptf_factory::cpi::accounts::BurnPtkn {
+                factory_state: ctx.accounts.factory_state.to_account_info(),
+                mint_mapping: ctx.accounts.mint_mapping.to_account_info(),
+                pool_authority: ctx.accounts.pool_state.to_account_info(),
+                ptkn_mint: ctx.accounts.twin_mint.to_account_info(),
+                source_token_account: ctx.accounts.holder_twin_account.to_account_info(),
+                owner: ctx.accounts.holder.to_account_info(),
+                token_program: ctx.accounts.twin_token_program.to_account_info(),
+            },
+            signer,
+        );
+        ptf_factory::cpi::burn_ptkn(burn_ctx, amount)?;
 
-        if hook_enabled {
-            let (required_accounts, hook_mode, target_program, post_shield_enabled) = {
-                let hook_config = ctx.accounts.hook_config.load()?;
-                (
-                    hook_config.required_keys().collect::<Vec<_>>(),
-                    hook_config.mode,
-                    hook_config.post_shield_program_id,
-                    hook_config.post_shield_enabled,
-                )
-            };
-            if post_shield_enabled && target_program != Pubkey::default() {
-                validate_hook_accounts(&required_accounts, hook_mode, ctx.remaining_accounts)?;
+        let release_ctx = CpiContext::new_with_signer(
+            ctx.accounts.vault_program.to_account_info(),
+            ptf_vault::cpi::accounts::Release {
+                vault_state: ctx.accounts.vault_state.to_account_info(),
+                vault_token_account: ctx.accounts.vault_token_account.to_account_info(),
+                destination_token_account: ctx.accounts.destination_token_account.to_account_info(),
+                pool_authority: ctx.accounts.pool_state.to_account_info(),
+                token_program: ctx.accounts.token_program.to_account_info(),
+            },
+            signer,
+        );
+        ptf_vault::cpi::release(release_ctx, payout)?;
+
+        emit!(PtknRedeemed {
+            origin_mint,
+            holder: ctx.accounts.holder.key(),
+            destination: ctx.accounts.destination_token_account.key(),
+            amount,
+            fee,
+        });
+        Ok(())
+    }
 
-                let mut metas = Vec::with_capacity(2 + ctx.remaining_accounts.len());
-                let mut infos = Vec::with_capacity(2 + ctx.remaining_accounts.len());
-
-                let hook_config_info = ctx.accounts.hook_config.to_account_info();
-                let pool_info = ctx.accounts.pool_state.to_account_info();
-                metas.push(AccountMeta::new_readonly(hook_config_info.key(), false));
-                metas.push(AccountMeta::new_readonly(pool_info.key(), false));
-                infos.push(hook_config_info);
-                infos.push(pool_info);
-
-                for account in ctx.remaining_accounts.iter() {
-                    let meta = if account.is_writable {
-                        AccountMeta::new(account.key(), account.is_signer)
-                    } else {
-                        AccountMeta::new_readonly(account.key(), account.is_signer)
-                    };
-                    metas.push(meta);
-                    infos.push(account.clone());
-                }
+    /// Caps the join-split shape accepted by `private_transfer`; 0 leaves a side unbounded.
+    pub fn set_transfer_arity(
+        ctx: Context<UpdateAuthority>,
+        max_inputs: u8,
+        max_outputs: u8,
+    ) -> Result<()> {
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        pool_state.max_inputs = max_inputs;
+        pool_state.max_outputs = max_outputs;
+        emit!(TransferArityUpdated {
+            origin_mint: pool_state.origin_mint,
+            max_inputs,
+            max_outputs,
+        });
+        Ok(())
+    }
 
-                let ix = Instruction {
-                    program_id: target_program,
-                    accounts: metas,
-                    data: HookInstruction::PostShield(PostShieldHook {
-                        origin_mint,
-                        pool: pool_key,
-                        depositor: pending.depositor,
-                        commitment: pending.commitment,
-                        amount_commit: pending.amount_commit,
-                        amount: pending.amount,
-                    })
-                    .try_to_vec()?,
-                };
+    /// Binds a verifying key to an `inputs`-in/`outputs`-out transfer circuit. Transfers of
+    /// that shape may pass the binding and verify against its key instead of the pool's.
+    pub fn register_arity_verifier(
+        ctx: Context<RegisterArityVerifier>,
+        inputs: u8,
+        outputs: u8,
+    ) -> Result<()> {
+        require!(inputs > 0 && outputs > 0, PoolError::TransferArityInvalid);
+        let pool_key = ctx.accounts.pool_state.key();
+        let verifying_key = &ctx.accounts.verifying_key;
+        let arity_verifier = &mut ctx.accounts.arity_verifier;
+        arity_verifier.pool = pool_key;
+        arity_verifier.inputs = inputs;
+        arity_verifier.outputs = outputs;
+        arity_verifier.verifying_key = verifying_key.key();
+        arity_verifier.verifying_key_id = verifying_key.verifying_key_id;
+        arity_verifier.verifying_key_hash = verifying_key.hash;
+        arity_verifier.bump = ctx.bumps.arity_verifier;
+        emit!(ArityVerifierRegistered {
+            origin_mint: ctx.accounts.pool_state.load()?.origin_mint,
+            inputs,
+            outputs,
+            verifying_key: verifying_key.key(),
+        });
+        Ok(())
+    }
 
-                let signer_seeds: [&[u8]; 3] = [seeds::POOL, origin_mint.as_ref(), &[pool_bump]];
-                invoke_signed(&ix, &infos, &[&signer_seeds])?;
-            }
-        }
+    /// Registers the `inputs`-in/1-out circuit used by `consolidate_notes`.
+    pub fn register_consolidation_verifier(
+        ctx: Context<RegisterConsolidationVerifier>,
+        inputs: u8,
+    ) -> Result<()> {
+        require!(inputs > 1, PoolError::TransferArityInvalid);
+        let pool_key = ctx.accounts.pool_state.key();
+        let verifying_key = &ctx.accounts.verifying_key;
+        let consolidation_verifier = &mut ctx.accounts.consolidation_verifier;
+        consolidation_verifier.pool = pool_key;
+        consolidation_verifier.inputs = inputs;
+        consolidation_verifier.outputs = 1;
+        consolidation_verifier.verifying_key = verifying_key.key();
+        consolidation_verifier.verifying_key_id = verifying_key.verifying_key_id;
+        consolidation_verifier.verifying_key_hash = verifying_key.hash;
+        consolidation_verifier.bump = ctx.bumps.consolidation_verifier;
+        emit!(ArityVerifierRegistered {
+            origin_mint: ctx.accounts.pool_state.load()?.origin_mint,
+            inputs,
+            outputs: 1,
+            verifying_key: verifying_key.key(),
+        });
+        Ok(())
+    }
 
-        ctx.accounts
-            .shield_claim
-            .mark_ledger_complete(requires_invariant);
+    /// Registers the unshield circuit for notes carrying an unlock timestamp. Unshields
+    /// passing `unlock_timestamp` must prove against this key.
+    pub fn register_scheduled_verifier(ctx: Context<RegisterScheduledVerifier>) -> Result<()> {
+        let pool_key = ctx.accounts.pool_state.key();
+        let verifying_key = &ctx.accounts.verifying_key;
+        let scheduled_verifier = &mut ctx.accounts.scheduled_verifier;
+        scheduled_verifier.pool = pool_key;
+        scheduled_verifier.inputs = 0;
+        scheduled_verifier.outputs = 0;
+        scheduled_verifier.verifying_key = verifying_key.key();
+        scheduled_verifier.verifying_key_id = verifying_key.verifying_key_id;
+        scheduled_verifier.verifying_key_hash = verifying_key.hash;
+        scheduled_verifier.bump = ctx.bumps.scheduled_verifier;
+        emit!(ScheduledVerifierRegistered {
+            origin_mint: ctx.accounts.pool_state.load()?.origin_mint,
+            verifying_key: verifying_key.key(),
+        });
+        Ok(())
+    }
 
+    /// Registers the selective disclosure circuit used by `verify_disclosure`.
+    pub fn register_disclosure_verifier(ctx: Context<RegisterDisclosureVerifier>) -> Result<()> {
+        let pool_key = ctx.accounts.pool_state.key();
+        let verifying_key = &ctx.accounts.verifying_key;
+        let disclosure_verifier = &mut ctx.accounts.disclosure_verifier;
+        disclosure_verifier.pool = pool_key;
+        disclosure_verifier.inputs = 1;
+        disclosure_verifier.outputs = 0;
+        disclosure_verifier.verifying_key = verifying_key.key();
+        disclosure_verifier.verifying_key_id = verifying_key.verifying_key_id;
+        disclosure_verifier.verifying_key_hash = verifying_key.hash;
+        disclosure_verifier.bump = ctx.bumps.disclosure_verifier;
+        emit!(DisclosureVerifierRegistered {
+            origin_mint: ctx.accounts.pool_state.load()?.origin_mint,
+            verifying_key: verifying_key.key(),
+        });
         Ok(())
     }
 
-    pub fn shield_check_invariant<'info>(
-        ctx: Context<'_, '_, '_, 'info, ShieldCheckInvariant<'info>>,
+    /// Registers the aggregated circuit proving `operations` transfers at once, used by
+    /// `private_transfer_batch`.
+    pub fn register_aggregation_verifier(
+        ctx: Context<RegisterAggregationVerifier>,
+        operations: u8,
     ) -> Result<()> {
-        if !ctx.accounts.shield_claim.is_awaiting_invariant()
-            || !ctx.accounts.shield_claim.needs_invariant()
-        {
-            return Ok(());
-        }
-        require_keys_eq!(
-            ctx.accounts.shield_claim.pool,
-            ctx.accounts.pool_state.key(),
-            PoolError::ShieldClaimMismatch
-        );
+        require!(operations > 1, PoolError::TransferArityInvalid);
+        let pool_key = ctx.accounts.pool_state.key();
+        let verifying_key = &ctx.accounts.verifying_key;
+        let aggregation_verifier = &mut ctx.accounts.aggregation_verifier;
+        aggregation_verifier.pool = pool_key;
+        aggregation_verifier.inputs = operations;
+        aggregation_verifier.outputs = 0;
+        aggregation_verifier.verifying_key = verifying_key.key();
+        aggregation_verifier.verifying_key_id = verifying_key.verifying_key_id;
+        aggregation_verifier.verifying_key_hash = verifying_key.hash;
+        aggregation_verifier.bump = ctx.bumps.aggregation_verifier;
+        emit!(ArityVerifierRegistered {
+            origin_mint: ctx.accounts.pool_state.load()?.origin_mint,
+            inputs: operations,
+            outputs: 0,
+            verifying_key: verifying_key.key(),
+        });
+        Ok(())
+    }
 
-        #[cfg(feature = "invariant_checks")]
-        {
-            let pool_state = ctx.accounts.pool_state.load()?;
-            let note_ledger = ctx.accounts.note_ledger.load()?;
-            enforce_supply_invariant(
-                &pool_state,
-                &note_ledger,
-                &ctx.accounts.vault_token_account,
-                ctx.accounts.twin_mint.as_ref(),
-            )?;
-        }
+    pub fn set_features(ctx: Context<UpdateAuthority>, features: u8) -> Result<()> {
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        ensure_direct_update_allowed_by(&pool_state, &ctx.accounts.authority.key())?;
+        apply_features_update(&mut pool_state, features);
+        Ok(())
+    }
 
-        ctx.accounts.shield_claim.deactivate();
+    /// Names a second key that must co-sign every root update. The default key removes
+    /// the requirement. Timelocked like other pool settings, since a guardian that stops
+    /// signing halts shields and spends (emergency exits still work).
+    pub fn set_root_guardian(ctx: Context<UpdateAuthority>, root_guardian: Pubkey) -> Result<()> {
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        ensure_direct_update_allowed(&pool_state)?;
+        apply_root_guardian_update(&mut pool_state, root_guardian);
         Ok(())
     }
 
-    pub fn unshield_to_origin<'info>(
-        ctx: Context<'_, '_, '_, 'info, Unshield<'info>>,
-        args: UnshieldArgs,
+    /// Enables or disables `program` as a proof verifier for this pool. The pinned
+    /// `verifier_program` is accepted unless it is listed here as disabled, so a pool can
+    /// run the legacy and a new verifier side by side and later retire either one.
+    pub fn set_verifier_entry(
+        ctx: Context<UpdateAuthority>,
+        program: Pubkey,
+        enabled: bool,
     ) -> Result<()> {
-        process_unshield(ctx, args, UnshieldMode::Origin)
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        ensure_direct_update_allowed(&pool_state)?;
+        apply_verifier_entry_update(&mut pool_state, program, enabled)
     }
 
-    pub fn unshield_to_ptkn<'info>(
-        ctx: Context<'_, '_, '_, 'info, Unshield<'info>>,
-        args: UnshieldArgs,
+    /// Sets the share of each shield fee, in bps of the fee, credited to the shield's
+    /// referrer instead of protocol fees. Timelocked like other fee changes.
+    pub fn set_referral_fee_share(
+        ctx: Context<UpdateAuthority>,
+        referral_fee_bps: u16,
     ) -> Result<()> {
-        process_unshield(ctx, args, UnshieldMode::Twin)
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        ensure_direct_update_allowed(&pool_state)?;
+        apply_referral_fee_share_update(&mut pool_state, referral_fee_bps)
     }
 
-    pub fn accept_root(ctx: Context<UpdateAuthority>, root: [u8; 32]) -> Result<()> {
+    /// Chooses how the bps fee component rounds. Provers must round the same way, so
+    /// this goes through the timelock like any other fee change.
+    pub fn set_fee_rounding(ctx: Context<UpdateAuthority>, fee_rounding: u8) -> Result<()> {
         let mut pool_state = ctx.accounts.pool_state.load_mut()?;
-        pool_state.push_root(root);
-        Ok(())
+        ensure_direct_update_allowed(&pool_state)?;
+        apply_fee_rounding_update(&mut pool_state, fee_rounding)
     }
 
-    pub fn write_nullifier(ctx: Context<UpdateAuthority>, nullifier: [u8; 32]) -> Result<()> {
-        {
-            let mut nullifier_set = ctx.accounts.nullifier_set.load_mut()?;
-            nullifier_set
-                .insert(nullifier)
-                .map_err(|_| PoolError::NullifierReuse)?;
-        }
-        let pool_state = ctx.accounts.pool_state.load()?;
-        emit!(PTFNullifierUsed {
-            mint: pool_state.origin_mint,
-            nullifier,
+    /// Bounds how many slots a non-current root stays spendable; 0 disables expiry.
+    pub fn set_max_root_age(ctx: Context<UpdateAuthority>, max_root_age_slots: u64) -> Result<()> {
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        pool_state.max_root_age_slots = max_root_age_slots;
+        emit!(MaxRootAgeUpdated {
+            origin_mint: pool_state.origin_mint,
+            max_root_age_slots,
         });
         Ok(())
     }
 
-    pub fn private_transfer(ctx: Context<PrivateTransfer>, args: TransferArgs) -> Result<()> {
-        execute_private_transfer(
-            &ctx.accounts.pool_state,
-            &ctx.accounts.nullifier_set,
-            &ctx.accounts.commitment_tree,
-            &ctx.accounts.note_ledger,
-            &ctx.accounts.verifier_program,
-            &ctx.accounts.verifying_key,
-            &args,
-        )
+    /// Requires `min_note_age_leaves` later commitments before a note can be spent, so a
+    /// deposit cannot be withdrawn right after it lands. 0 lifts the policy.
+    pub fn set_min_note_age(ctx: Context<UpdateAuthority>, min_note_age_leaves: u64) -> Result<()> {
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        pool_state.min_note_age_leaves = min_note_age_leaves;
+        emit!(MinNoteAgeUpdated {
+            origin_mint: pool_state.origin_mint,
+            min_note_age_leaves,
+        });
+        Ok(())
     }
 
-    pub fn approve_allowance(ctx: Context<ManageAllowance>, args: ApproveAllowanceArgs) -> Result<()> {
-        write_allowance(
-            &ctx.accounts.pool_state,
-            &mut ctx.accounts.allowance,
-            ctx.accounts.owner.key(),
-            ctx.accounts.spender.key(),
-            ctx.accounts.origin_mint.key(),
-            ctx.bumps.allowance,
-            args.amount,
-        )
+    /// Restricts `shield` to a fixed set of amounts so deposits blend into uniform
+    /// buckets. An empty list lifts the restriction.
+    pub fn set_denominations(ctx: Context<UpdateAuthority>, denominations: Vec<u64>) -> Result<()> {
+        require!(
+            denominations.len() <= PoolState::MAX_DENOMINATIONS
+                && denominations.iter().all(|amount| *amount > 0),
+            PoolError::InvalidDenominations
+        );
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        pool_state.denominations = [0u64; PoolState::MAX_DENOMINATIONS];
+        pool_state.denominations[..denominations.len()].copy_from_slice(&denominations);
+        pool_state.denominations_len = denominations.len() as u8;
+        emit!(DenominationsUpdated {
+            origin_mint: pool_state.origin_mint,
+            denominations,
+        });
+        Ok(())
     }
 
-    pub fn revoke_allowance(ctx: Context<ManageAllowance>) -> Result<()> {
-        write_allowance(
-            &ctx.accounts.pool_state,
-            &mut ctx.accounts.allowance,
-            ctx.accounts.owner.key(),
-            ctx.accounts.spender.key(),
-            ctx.accounts.origin_mint.key(),
-            ctx.bumps.allowance,
-            0,
-        )
+    /// Caps single deposits and the vault balance so a new pool can ramp up exposure.
+    /// Zero disables either limit.
+    pub fn set_limits(
+        ctx: Context<UpdateAuthority>,
+        max_deposit_amount: u64,
+        max_total_locked: u64,
+    ) -> Result<()> {
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        pool_state.max_deposit_amount = max_deposit_amount;
+        pool_state.max_total_locked = max_total_locked;
+        emit!(LimitsUpdated {
+            origin_mint: pool_state.origin_mint,
+            max_deposit_amount,
+            max_total_locked,
+        });
+        Ok(())
     }
 
-    pub fn transfer_from(ctx: Context<TransferFrom>, args: TransferFromArgs) -> Result<()> {
-        require!(args.allowance_amount > 0, PoolError::AllowanceAmountInvalid);
+    /// Caps shielded inflow and outflow per window of `window_slots` slots (about 9_000 per
+    /// hour). A zero cap leaves that direction unlimited; `window_slots == 0` turns the
+    /// limiter off.
+    pub fn configure_rate_limit(
+        ctx: Context<ConfigureRateLimit>,
+        window_slots: u64,
+        max_inflow: u64,
+        max_outflow: u64,
+    ) -> Result<()> {
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        let limiter = &mut ctx.accounts.rate_limiter;
+        limiter.pool = ctx.accounts.pool_state.key();
+        limiter.window_slots = window_slots;
+        limiter.max_inflow = max_inflow;
+        limiter.max_outflow = max_outflow;
+        limiter.window_start_slot = Clock::get()?.slot;
+        limiter.inflow = 0;
+        limiter.outflow = 0;
+        limiter.bump = ctx.bumps.rate_limiter;
+        pool_state.rate_limiter = if window_slots == 0 {
+            Pubkey::default()
+        } else {
+            limiter.key()
+        };
+        emit!(RateLimitConfigured {
+            origin_mint: pool_state.origin_mint,
+            window_slots,
+            max_inflow,
+            max_outflow,
+        });
+        Ok(())
+    }
 
-        {
-            let allowance = &mut ctx.accounts.allowance;
-            require_keys_eq!(
-                allowance.pool,
-                ctx.accounts.pool_state.key(),
-                PoolError::AllowancePoolMismatch
-            );
-            require_keys_eq!(
-                allowance.owner,
-                ctx.accounts.allowance_owner.key(),
-                PoolError::AllowanceOwnerMismatch
-            );
-            require_keys_eq!(
-                allowance.spender,
-                ctx.accounts.spender.key(),
-                PoolError::AllowanceSpenderMismatch
-            );
-            let pool_state = ctx.accounts.pool_state.load()?;
-            require_keys_eq!(allowance.mint, pool_state.origin_mint, PoolError::AllowanceMintMismatch);
-            require!(
-                allowance.amount >= args.allowance_amount,
-                PoolError::AllowanceInsufficient
-            );
-            allowance.amount = allowance
-                .amount
-                .checked_sub(args.allowance_amount)
-                .ok_or(PoolError::AllowanceInsufficient)?;
-            allowance.updated_at = Clock::get()?.unix_timestamp;
-            emit!(PTFAllowanceUpdated {
-                mint: allowance.mint,
-                owner: allowance.owner,
-                spender: allowance.spender,
-                amount: allowance.amount,
-            });
+    /// Turns per-epoch statistics on or off. While on, shields, unshields and transfers
+    /// must pass the `PoolStats` account, which counts them for the current epoch.
+    pub fn configure_pool_stats(ctx: Context<ConfigurePoolStats>, enabled: bool) -> Result<()> {
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        let stats = &mut ctx.accounts.pool_stats;
+        if stats.pool == Pubkey::default() {
+            stats.pool = ctx.accounts.pool_state.key();
+            stats.current.epoch = Clock::get()?.epoch;
+            stats.bump = ctx.bumps.pool_stats;
         }
+        pool_state.pool_stats = if enabled {
+            stats.key()
+        } else {
+            Pubkey::default()
+        };
+        emit!(PoolStatsConfigured {
+            origin_mint: pool_state.origin_mint,
+            enabled,
+        });
+        Ok(())
+    }
 
-        execute_private_transfer(
-            &ctx.accounts.pool_state,
-            &ctx.accounts.nullifier_set,
-            &ctx.accounts.commitment_tree,
-            &ctx.accounts.note_ledger,
-            &ctx.accounts.verifier_program,
-            &ctx.accounts.verifying_key,
-            &args.transfer,
-        )
+    /// Turns the destination allowlist on or off and names the compliance authority that
+    /// maintains it. While on, every unshield must pay only allowlisted owners.
+    pub fn configure_destination_allowlist(
+        ctx: Context<ConfigureDestinationAllowlist>,
+        compliance_authority: Pubkey,
+        enabled: bool,
+    ) -> Result<()> {
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        let allowlist = &mut ctx.accounts.destination_allowlist;
+        if allowlist.pool == Pubkey::default() {
+            allowlist.pool = ctx.accounts.pool_state.key();
+            allowlist.bump = ctx.bumps.destination_allowlist;
+        }
+        allowlist.compliance_authority = compliance_authority;
+        pool_state.destination_allowlist = if enabled {
+            allowlist.key()
+        } else {
+            Pubkey::default()
+        };
+        emit!(DestinationAllowlistConfigured {
+            origin_mint: pool_state.origin_mint,
+            compliance_authority,
+            enabled,
+        });
+        Ok(())
     }
-}
 
-fn execute_private_transfer<'info>(
-    pool_loader: &AccountLoader<'info, PoolState>,
-    nullifier_set_loader: &AccountLoader<'info, NullifierSet>,
-    commitment_tree_loader: &AccountLoader<'info, CommitmentTree>,
-    note_ledger_loader: &AccountLoader<'info, NoteLedger>,
-    verifier_program: &Program<'info, PtfVerifierGroth16>,
-    verifying_key: &Account<'info, VerifyingKeyAccount>,
-    args: &TransferArgs,
-) -> Result<()> {
-    let mut pool_state = pool_loader.load_mut()?;
-    require_keys_eq!(
-        verifier_program.key(),
-        pool_state.verifier_program,
-        PoolError::VerifierMismatch,
-    );
-    require_keys_eq!(
-        verifying_key.key(),
-        pool_state.verifying_key,
-        PoolError::VerifierMismatch,
-    );
-    require!(
-        verifying_key.verifying_key_id == pool_state.verifying_key_id,
-        PoolError::VerifierMismatch,
-    );
-    require!(
-        verifying_key.hash == pool_state.verifying_key_hash,
-        PoolError::VerifyingKeyHashMismatch,
-    );
-    require!(
-        pool_state
-            .features
-            .contains(FeatureFlags::from(FEATURE_PRIVATE_TRANSFER_ENABLED)),
-        PoolError::FeatureDisabled,
-    );
-    require!(
-        pool_state.is_known_root(&args.old_root),
-        PoolError::UnknownRoot,
-    );
-    {
-        let commitment_tree = commitment_tree_loader.load()?;
+    /// Adds or removes a withdrawal destination owner. Signed by the compliance authority.
+    pub fn set_allowed_destination(
+        ctx: Context<SetAllowedDestination>,
+        owner: Pubkey,
+        allowed: bool,
+    ) -> Result<()> {
+        let allowlist = &mut ctx.accounts.destination_allowlist;
+        if allowed {
+            allowlist.insert(owner)?;
+        } else {
+            allowlist.remove(&owner);
+        }
+        emit!(DestinationAllowlistUpdated {
+            origin_mint: ctx.accounts.pool_state.load()?.origin_mint,
+            owner,
+            allowed,
+        });
+        Ok(())
+    }
+
+    /// Switches the pool to a new verifying key. Proofs against the old key keep
+    /// verifying for `overlap_slots` so in-flight proofs are not stranded.
+    pub fn rotate_verifying_key(
+        ctx: Context<RotateVerifyingKey>,
+        overlap_slots: u64,
+    ) -> Result<()> {
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        let verifying_key = &ctx.accounts.verifying_key;
         require!(
-            commitment_tree.current_root == args.old_root,
-            PoolError::RootMismatch,
+            verifying_key.key() != pool_state.verifying_key,
+            PoolError::VerifyingKeyUnchanged
         );
+        let overlap_ends_slot = Clock::get()?
+            .slot
+            .checked_add(overlap_slots)
+            .ok_or(PoolError::AmountOverflow)?;
+
+        pool_state.previous_verifying_key = pool_state.verifying_key;
+        pool_state.previous_verifying_key_id = pool_state.verifying_key_id;
+        pool_state.previous_verifying_key_hash = pool_state.verifying_key_hash;
+        pool_state.previous_verifying_key_expiry_slot = overlap_ends_slot;
+        pool_state.verifying_key = verifying_key.key();
+        pool_state.verifying_key_id = verifying_key.verifying_key_id;
+        pool_state.verifying_key_hash = verifying_key.hash;
+
+        emit!(VerifyingKeyRotated {
+            origin_mint: pool_state.origin_mint,
+            previous_verifying_key: pool_state.previous_verifying_key,
+            verifying_key: pool_state.verifying_key,
+            overlap_ends_slot,
+        });
+        Ok(())
     }
 
-    let cpi_accounts = ptf_verifier_groth16::cpi::accounts::VerifyGroth16 {
-        verifier_state: verifying_key.to_account_info(),
-    };
-    let cpi_ctx = CpiContext::new(verifier_program.to_account_info(), cpi_accounts);
-    ptf_verifier_groth16::cpi::verify_groth16(
-        cpi_ctx,
-        pool_state.verifying_key_id,
-        args.proof.clone(),
-        args.public_inputs.clone(),
-    )?;
+    /// Halts shields, unshields and transfers for this pool. Not subject to the pool
+    /// timelock so it can be used during an incident.
+    pub fn pause(ctx: Context<UpdateAuthority>) -> Result<()> {
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        pool_state.paused = true;
+        emit!(PoolPaused {
+            origin_mint: pool_state.origin_mint,
+            authority: pool_state.authority,
+        });
+        Ok(())
+    }
 
-    let origin_mint = pool_state.origin_mint;
-    {
-        let mut nullifier_set = nullifier_set_loader.load_mut()?;
-        for nullifier in &args.nullifiers {
-            nullifier_set
-                .insert(*nullifier)
-                .map_err(|_| PoolError::NullifierReuse)?;
-            emit!(PTFNullifierUsed {
-                mint: origin_mint,
+    pub fn unpause(ctx: Context<UpdateAuthority>) -> Result<()> {
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        pool_state.unpause()?;
+        emit!(PoolUnpaused {
+            origin_mint: pool_state.origin_mint,
+            authority: pool_state.authority,
+        });
+        Ok(())
+    }
+
+    /// Pauses the pool for good and freezes the current root so holders can leave through
+    /// `emergency_unshield`. There is no way back; the root must stay fixed for exits.
+    /// The passed verifier program and verifying key are pinned for the exits, so a
+    /// deprecated or rotated-out verifier the pool once trusted can still open notes.
+    pub fn enable_emergency_exit(ctx: Context<EnableEmergencyExit>) -> Result<()> {
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        require!(!pool_state.emergency_exit, PoolError::EmergencyExitActive);
+        let verifier_program = ctx.accounts.verifier_program.key();
+        let verifying_key = &ctx.accounts.verifying_key;
+        let (verifying_key_id, verifying_key_hash) =
+            pool_state.emergency_verifier(&verifier_program, verifying_key.key())?;
+        require!(
+            verifying_key.verifying_key_id == verifying_key_id,
+            PoolError::VerifierMismatch
+        );
+        require!(
+            verifying_key.hash == verifying_key_hash,
+            PoolError::VerifyingKeyHashMismatch
+        );
+        pool_state.paused = true;
+        pool_state.emergency_exit = true;
+        pool_state.emergency_root = pool_state.current_root;
+        pool_state.emergency_verifier_program = verifier_program;
+        pool_state.emergency_verifying_key = verifying_key.key();
+        pool_state.emergency_verifying_key_id = verifying_key_id;
+        pool_state.emergency_verifying_key_hash = verifying_key_hash;
+        emit!(EmergencyExitEnabled {
+            origin_mint: pool_state.origin_mint,
+            root: pool_state.emergency_root,
+            verifier_program,
+            verifying_key: verifying_key.key(),
+        });
+        Ok(())
+    }
+
+    /// Sets the delay applied to queued fee and feature changes. Once non-zero, the
+    /// delay itself can only be changed through the queue.
+    pub fn set_timelock(ctx: Context<UpdateAuthority>, timelock_seconds: i64) -> Result<()> {
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        ensure_direct_update_allowed(&pool_state)?;
+        apply_timelock_update(&mut pool_state, timelock_seconds)
+    }
+
+    pub fn queue_pool_timelock_action(
+        ctx: Context<QueuePoolTimelockAction>,
+        salt: [u8; 32],
+        action: PoolTimelockAction,
+    ) -> Result<()> {
+        let pool_key = ctx.accounts.pool_state.key();
+        let pool_state = ctx.accounts.pool_state.load()?;
+        // Nullifier writes come from the sequencer; every other change from the authority.
+        let expected_signer = match &action {
+            PoolTimelockAction::WriteNullifier { .. } => {
+                require!(
+                    pool_state.sequencer != Pubkey::default(),
+                    PoolError::SequencerMismatch
+                );
+                pool_state.sequencer
+            }
+            _ => pool_state.authority,
+        };
+        require_keys_eq!(
+            ctx.accounts.authority.key(),
+            expected_signer,
+            PoolError::SequencerMismatch
+        );
+
+        let clock = Clock::get()?;
+        let execute_after = clock
+            .unix_timestamp
+            .checked_add(action.delay(pool_state.timelock_seconds))
+            .ok_or_else(|| error!(PoolError::TimelockOverflow))?;
+
+        let action_bytes = action
+            .try_to_vec()
+            .map_err(|_| error!(PoolError::SerializationError))?;
+        let expected_hash = hashv(&[
+            pool_key.as_ref(),
+            &action_bytes,
+            &execute_after.to_le_bytes(),
+        ]);
+
+        if let PoolTimelockAction::WriteNullifier { nullifier } = &action {
+            emit!(NullifierWriteQueued {
+                origin_mint: pool_state.origin_mint,
                 nullifier: *nullifier,
+                sequencer: pool_state.sequencer,
+                execute_after,
             });
         }
+
+        let entry = &mut ctx.accounts.timelock_entry;
+        entry.pool = pool_key;
+        entry.salt = salt;
+        entry.action_hash = expected_hash.to_bytes();
+        entry.queued_at = clock.unix_timestamp;
+        entry.execute_after = execute_after;
+        entry.executed = false;
+        entry.action = action;
+        entry.bump = ctx.bumps.timelock_entry;
+        entry.payer = ctx.accounts.payer.key();
+
+        emit!(PoolTimelockQueued {
+            pool: pool_key,
+            action_hash: entry.action_hash,
+            queued_at: clock.unix_timestamp,
+            execute_after,
+        });
+        Ok(())
     }
-    require!(
-        args.output_commitments.len() == args.output_amount_commitments.len(),
-        PoolError::OutputSetMismatch,
-    );
-    let (new_root, _output_indices) = {
-        let mut commitment_tree = commitment_tree_loader.load_mut()?;
-        commitment_tree.append_many(
-            args.output_commitments.as_slice(),
-            args.output_amount_commitments.as_slice(),
-        )?
-    };
-    if new_root != args.new_root {
-        msg!(
-            "unshield proof new root ({}) differs from computed root ({})",
-            hex::encode(args.new_root),
-            hex::encode(new_root)
+
+    pub fn execute_pool_timelock_action<'info>(
+        ctx: Context<'_, '_, '_, 'info, ExecutePoolTimelockAction<'info>>,
+    ) -> Result<()> {
+        let pool_key = ctx.accounts.pool_state.key();
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        let entry = &mut ctx.accounts.timelock_entry;
+        require!(!entry.executed, PoolError::TimelockConsumed);
+
+        let clock = Clock::get()?;
+        require!(
+            clock.unix_timestamp >= entry.execute_after,
+            PoolError::TimelockNotReady
         );
+
+        match &entry.action {
+            PoolTimelockAction::SetFee {
+                shield_fee_bps,
+                unshield_fee_bps,
+                shield_flat_fee,
+                unshield_flat_fee,
+            } => apply_fee_update(
+                &mut pool_state,
+                *shield_fee_bps,
+                *unshield_fee_bps,
+                *shield_flat_fee,
+                *unshield_flat_fee,
+            )?,
+            PoolTimelockAction::SetFeatures { features } => {
+                apply_features_update(&mut pool_state, *features)
+            }
+            PoolTimelockAction::SetFeeRounding { fee_rounding } => {
+                apply_fee_rounding_update(&mut pool_state, *fee_rounding)?
+            }
+            PoolTimelockAction::SetVerifierEntry { program, enabled } => {
+                apply_verifier_entry_update(&mut pool_state, *program, *enabled)?
+            }
+            PoolTimelockAction::SetReferralFeeShare { referral_fee_bps } => {
+                apply_referral_fee_share_update(&mut pool_state, *referral_fee_bps)?
+            }
+            PoolTimelockAction::SetRootGuardian { root_guardian } => {
+                apply_root_guardian_update(&mut pool_state, *root_guardian)
+            }
+            PoolTimelockAction::SetTimelock { timelock_seconds } => {
+                apply_timelock_update(&mut pool_state, *timelock_seconds)?
+            }
+            PoolTimelockAction::SetSequencer { sequencer } => {
+                apply_sequencer_update(&mut pool_state, *sequencer)
+            }
+            PoolTimelockAction::WriteNullifier { nullifier } => {
+                let nullifier_set = ctx
+                    .accounts
+                    .nullifier_set
+                    .as_ref()
+                    .ok_or(PoolError::NullifierSetInvalid)?;
+                spend_nullifiers(
+                    pool_key,
+                    &pool_state,
+                    nullifier_set,
+                    ctx.remaining_accounts,
+                    Some(&ctx.accounts.executor),
+                    ctx.accounts.system_program.as_ref(),
+                    &[*nullifier],
+                )?;
+                emit!(NullifierWritten {
+                    origin_mint: pool_state.origin_mint,
+                    nullifier: *nullifier,
+                    sequencer: pool_state.sequencer,
+                });
+            }
+        }
+
+        entry.executed = true;
+
+        emit!(PoolTimelockExecuted {
+            pool: pool_key,
+            action_hash: entry.action_hash,
+            executed_at: clock.unix_timestamp,
+            executor: ctx.accounts.executor.key(),
+        });
+        Ok(())
     }
-    pool_state.push_root(new_root);
 
-    {
-        let mut note_ledger = note_ledger_loader.load_mut()?;
-        note_ledger.record_transfer(&args.nullifiers, args.output_amount_commitments.as_slice())?;
+    pub fn cancel_pool_timelock_action(ctx: Context<CancelPoolTimelockAction>) -> Result<()> {
+        let entry = &mut ctx.accounts.timelock_entry;
+        require!(!entry.executed, PoolError::TimelockConsumed);
+        entry.executed = true;
+        let clock = Clock::get()?;
+
+        emit!(PoolTimelockCanceled {
+            pool: ctx.accounts.pool_state.key(),
+            action_hash: entry.action_hash,
+            canceled_at: clock.unix_timestamp,
+            authority: ctx.accounts.authority.key(),
+        });
+        Ok(())
     }
 
-    emit!(PTFTransferred {
-        mint: pool_state.origin_mint,
-        inputs: args.nullifiers.clone(),
-        outputs: args.output_commitments.clone(),
-        root: new_root,
-    });
-    Ok(())
-}
+    pub fn configure_hooks(ctx: Context<ConfigureHooks>, args: HookConfigArgs) -> Result<()> {
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        require!(
+            pool_state
+                .features()
+                .contains(FeatureFlags::from(FEATURE_HOOKS_ENABLED)),
+            PoolError::HooksDisabled,
+        );
 
-fn write_allowance(
-    pool_loader: &AccountLoader<PoolState>,
-    allowance_account: &mut Account<AllowanceAccount>,
-    owner: Pubkey,
-    spender: Pubkey,
-    mint: Pubkey,
-    bump: u8,
-    amount: u64,
-) -> Result<()> {
-    let pool_state = pool_loader.load()?;
-    let origin_mint = pool_state.origin_mint;
-    let pool_key = pool_loader.key();
-    require_keys_eq!(origin_mint, mint, PoolError::OriginMintMismatch);
-    drop(pool_state);
+        let mut hook_config = ctx.accounts.hook_config.load_mut()?;
+        hook_config.pool = ctx.accounts.pool_state.key();
+        hook_config.post_shield_program_id = args.post_shield_program;
+        hook_config.post_shield_enabled = args.post_shield_enabled;
+        hook_config.post_unshield_program_id = args.post_unshield_program;
+        hook_config.post_unshield_enabled = args.post_unshield_enabled;
+        hook_config.pre_hook_program_id = args.pre_hook_program;
+        hook_config.pre_shield_enabled = args.pre_shield_enabled;
+        hook_config.pre_unshield_enabled = args.pre_unshield_enabled;
+        hook_config.post_transfer_program_id = args.post_transfer_program;
+        hook_config.post_transfer_enabled = args.post_transfer_enabled;
+        hook_config.mode = args.mode;
+        let (accounts, len, writable) = HookConfig::pack_accounts(&args.post_shield_accounts)?;
+        hook_config.post_shield_accounts = accounts;
+        hook_config.post_shield_accounts_len = len;
+        hook_config.post_shield_writable = writable;
+        let (accounts, len, writable) = HookConfig::pack_accounts(&args.post_unshield_accounts)?;
+        hook_config.post_unshield_accounts = accounts;
+        hook_config.post_unshield_accounts_len = len;
+        hook_config.post_unshield_writable = writable;
+        require!(
+            args.account_templates.len() <= HookConfig::MAX_ACCOUNT_TEMPLATES,
+            PoolError::TooManyHookAccounts
+        );
+        hook_config.account_templates =
+            [HookAccountTemplate::empty(); HookConfig::MAX_ACCOUNT_TEMPLATES];
+        for (idx, template) in args.account_templates.iter().enumerate() {
+            hook_config.account_templates[idx] = HookAccountTemplate::from_args(template)?;
+        }
+        hook_config.account_templates_len = args.account_templates.len() as u8;
 
-    if allowance_account.pool == Pubkey::default() {
-        allowance_account.pool = pool_key;
-        allowance_account.owner = owner;
-        allowance_account.spender = spender;
-        allowance_account.mint = mint;
-        allowance_account.bump = bump;
-    } else {
-        require_keys_eq!(allowance_account.pool, pool_key, PoolError::AllowancePoolMismatch);
-        require_keys_eq!(allowance_account.owner, owner, PoolError::AllowanceOwnerMismatch);
-        require_keys_eq!(allowance_account.spender, spender, PoolError::AllowanceSpenderMismatch);
-        require_keys_eq!(allowance_account.mint, mint, PoolError::AllowanceMintMismatch);
+        pool_state.hook_config = ctx.accounts.hook_config.key();
+        pool_state.hook_config_present = (args.post_shield_enabled
+            && args.post_shield_program != Pubkey::default())
+            || (args.post_unshield_enabled && args.post_unshield_program != Pubkey::default())
+            || ((args.pre_shield_enabled || args.pre_unshield_enabled)
+                && args.pre_hook_program != Pubkey::default())
+            || (args.post_transfer_enabled && args.post_transfer_program != Pubkey::default());
+
+        emit!(HookConfigUpdated {
+            origin_mint: pool_state.origin_mint,
+            post_shield_program: args.post_shield_program,
+            post_unshield_program: args.post_unshield_program,
+            post_shield_enabled: args.post_shield_enabled,
+            post_unshield_enabled: args.post_unshield_enabled,
+            mode: args.mode as u8,
+            pre_hook_program: args.pre_hook_program,
+            pre_shield_enabled: args.pre_shield_enabled,
+            pre_unshield_enabled: args.pre_unshield_enabled,
+            post_transfer_program: args.post_transfer_program,
+            post_transfer_enabled: args.post_transfer_enabled,
+            account_templates: args.account_templates.len() as u8,
+        });
+        Ok(())
     }
-    allowance_account.amount = amount;
-    allowance_account.updated_at = Clock::get()?.unix_timestamp;
-    emit!(PTFAllowanceUpdated {
-        mint,
-        owner,
-        spender,
-        amount,
-    });
-    Ok(())
-}
 
-fn process_unshield<'info>(
-    ctx: Context<'_, '_, '_, 'info, Unshield<'info>>,
-    args: UnshieldArgs,
-    mode: UnshieldMode,
-) -> Result<()> {
-    let pool_loader = &ctx.accounts.pool_state;
-    let mut pool_state = pool_loader.load_mut()?;
-    #[cfg(all(feature = "invariant_checks", not(feature = "lightweight")))]
-    let mut should_enforce_invariant = false;
-    #[cfg(not(feature = "lightweight"))]
-    let mut note_ledger = ctx.accounts.note_ledger.load_mut()?;
-    #[cfg(feature = "lightweight")]
-    let _note_ledger = &ctx.accounts.note_ledger;
-    let origin_mint = pool_state.origin_mint;
+    pub fn shield<'info>(
+        ctx: Context<'_, '_, '_, 'info, Shield<'info>>,
+        args: ShieldArgs,
+    ) -> Result<()> {
+        process_shield(ctx, args, ShieldSource::Origin, None)
+    }
 
-    require_keys_eq!(
-        ctx.accounts.verifier_program.key(),
-        pool_state.verifier_program,
-        PoolError::VerifierMismatch,
-    );
-    require_keys_eq!(
-        ctx.accounts.verifying_key.key(),
-        pool_state.verifying_key,
-        PoolError::VerifierMismatch,
-    );
-    require!(
-        ctx.accounts.verifying_key.verifying_key_id == pool_state.verifying_key_id,
-        PoolError::VerifierMismatch,
-    );
-    require!(
-        ctx.accounts.verifying_key.hash == pool_state.verifying_key_hash,
-        PoolError::VerifyingKeyHashMismatch,
-    );
-    require_keys_eq!(
-        ctx.accounts.vault_state.key(),
-        pool_state.vault,
-        PoolError::MismatchedVaultAuthority,
-    );
-    require_keys_eq!(
-        ctx.accounts.vault_state.pool_authority,
-        pool_loader.key(),
-        PoolError::MismatchedVaultAuthority,
-    );
-    require_keys_eq!(
-        ctx.accounts.vault_state.origin_mint,
-        origin_mint,
-        PoolError::OriginMintMismatch,
-    );
-    require_keys_eq!(
-        ctx.accounts.vault_token_account.owner,
-        pool_state.vault,
-        PoolError::VaultTokenAccountMismatch,
-    );
-    require_keys_eq!(
-        ctx.accounts.vault_token_account.mint,
-        origin_mint,
-        PoolError::OriginMintMismatch,
-    );
-    require_keys_eq!(
-        ctx.accounts.commitment_tree.key(),
-        pool_state.commitment_tree,
-        PoolError::CommitmentTreeMismatch,
-    );
-
-    if pool_state.twin_mint_enabled {
-        let twin_mint = ctx
-            .accounts
-            .twin_mint
-            .as_ref()
-            .ok_or(PoolError::TwinMintNotConfigured)?;
-        require_keys_eq!(
-            twin_mint.key(),
-            pool_state.twin_mint,
-            PoolError::TwinMintMismatch,
+    /// `shield` for other programs: `depositor` is a PDA of the calling program that owns
+    /// `depositor_token_account` and signs through `invoke_signed`, while `payer` only
+    /// funds rent. The caller's program ID is bound into `PTFShieldedViaCpi`.
+    pub fn shield_via_cpi<'info>(
+        ctx: Context<'_, '_, '_, 'info, Shield<'info>>,
+        args: ShieldArgs,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.depositor.is_some(),
+            PoolError::ShieldCpiRequired
         );
+        let caller_program = calling_program(&ctx.accounts.instructions)?;
+        process_shield(ctx, args, ShieldSource::Origin, Some(caller_program))
     }
 
-    require!(
-        pool_state.is_known_root(&args.old_root),
-        PoolError::UnknownRoot,
-    );
-    #[cfg(not(feature = "lightweight"))]
-    {
-        let commitment_tree = ctx.accounts.commitment_tree.load()?;
+    /// Re-shields pTKN: burns `amount` of the caller's twin tokens through the factory
+    /// instead of depositing into the vault. Twin supply drops by what the new note
+    /// carries, so the supply invariant stays balanced. No shield fee is charged.
+    pub fn shield_from_ptkn<'info>(
+        ctx: Context<'_, '_, '_, 'info, Shield<'info>>,
+        args: ShieldArgs,
+    ) -> Result<()> {
+        process_shield(ctx, args, ShieldSource::Twin, None)
+    }
+
+    /// Shields tokens an upstream program already moved into the vault token account
+    /// earlier in the transaction. Instead of a deposit CPI, the vault must hold at least
+    /// `amount + shield fee` more than the ledger, fees and twin supply account for.
+    pub fn shield_prefunded<'info>(
+        ctx: Context<'_, '_, '_, 'info, Shield<'info>>,
+        args: ShieldArgs,
+    ) -> Result<()> {
+        process_shield(ctx, args, ShieldSource::Prefunded, None)
+    }
+
+    pub fn shield_finalize_tree<'info>(
+        ctx: Context<'_, '_, '_, 'info, ShieldFinalizeTree<'info>>,
+    ) -> Result<()> {
+        ctx.accounts
+            .pool_state
+            .load()?
+            .check_root_guardian(ctx.accounts.root_guardian.as_ref())?;
+        process_shield_finalize_tree(
+            &ctx.accounts.pool_state,
+            &ctx.accounts.commitment_tree,
+            &mut ctx.accounts.shield_claim,
+        )
+    }
+
+    pub fn shield_finalize_ledger<'info>(
+        ctx: Context<'_, '_, '_, 'info, ShieldFinalizeLedger<'info>>,
+    ) -> Result<()> {
+        let pool_loader = &ctx.accounts.pool_state;
+
         require!(
-            commitment_tree.current_root == args.old_root,
-            PoolError::RootMismatch,
+            ctx.accounts.shield_claim.is_awaiting_ledger(),
+            PoolError::ShieldClaimStage
+        );
+        require_keys_eq!(
+            ctx.accounts.shield_claim.pool,
+            pool_loader.key(),
+            PoolError::ShieldClaimMismatch
         );
-    }
-    require!(
-        args.output_commitments.len() == args.output_amount_commitments.len(),
-        PoolError::OutputSetMismatch,
-    );
-    require!(
-        args.output_commitments.len() == 1,
-        PoolError::InvalidChangeNoteCount,
-    );
-    require_keys_eq!(
-        ctx.accounts.mint_mapping.origin_mint,
-        origin_mint,
-        PoolError::OriginMintMismatch,
-    );
 
-    let destination_owner = ctx.accounts.destination_token_account.owner;
+        let pending = ctx.accounts.shield_claim.snapshot();
+        let (hook_enabled, pool_key, pool_bump, origin_mint) = {
+            let pool_state = pool_loader.load()?;
+            let hook_enabled = pool_state
+                .features()
+                .contains(FeatureFlags::from(FEATURE_HOOKS_ENABLED))
+                && pool_state.hook_config_present;
+            let pool_key = pool_loader.key();
+            let pool_bump = pool_state.bump;
+            let origin_mint = pool_state.origin_mint;
+            (hook_enabled, pool_key, pool_bump, origin_mint)
+        };
 
-    let cpi_accounts = ptf_verifier_groth16::cpi::accounts::VerifyGroth16 {
-        verifier_state: ctx.accounts.verifying_key.to_account_info(),
-    };
-    let cpi_ctx = CpiContext::new(
-        ctx.accounts.verifier_program.to_account_info(),
-        cpi_accounts,
-    );
-    ptf_verifier_groth16::cpi::verify_groth16(
-        cpi_ctx,
-        pool_state.verifying_key_id,
-        args.proof.clone(),
-        args.public_inputs.clone(),
-    )?;
+        let invariant_checks = pool_loader.load()?.invariant_checks_enabled();
+        let requires_invariant = {
+            let mut note_ledger = ctx.accounts.note_ledger.load_mut()?;
+            note_ledger.record_shield(pending.amount, pending.amount_commit)?;
+            invariant_checks && note_ledger.should_enforce_invariant(pending.amount)
+        };
 
-    let pool_account_key = pool_loader.key();
-    let fee = validate_unshield_public_inputs(
-        &pool_state,
-        pool_account_key,
-        &args,
-        mode,
-        destination_owner,
-        ctx.accounts.mint_mapping.decimals,
-    )?;
-    let total_spent = args
-        .amount
-        .checked_add(fee)
-        .ok_or(PoolError::AmountOverflow)?;
-    #[cfg(not(feature = "lightweight"))]
-    note_ledger.ensure_capacity(total_spent)?;
-    #[cfg(feature = "lightweight")]
-    let _ = total_spent;
+        if hook_enabled {
+            let (required_accounts, hook_mode, target_program, post_shield_enabled) = {
+                let hook_config = ctx.accounts.hook_config.load()?;
+                (
+                    hook_config.required_metas(HookDirection::Shield, &[pending.depositor]),
+                    hook_config.mode,
+                    hook_config.post_shield_program_id,
+                    hook_config.post_shield_enabled,
+                )
+            };
+            if post_shield_enabled && target_program != Pubkey::default() {
+                validate_hook_accounts(&required_accounts, hook_mode, ctx.remaining_accounts)?;
 
-    {
-        let mut nullifier_set = ctx.accounts.nullifier_set.load_mut()?;
-        for nullifier in &args.nullifiers {
-            nullifier_set
-                .insert(*nullifier)
-                .map_err(|_| PoolError::NullifierReuse)?;
-            emit!(PTFNullifierUsed {
-                mint: origin_mint,
-                nullifier: *nullifier,
-            });
+                let (metas, infos) = hook_invocation_accounts(
+                    ctx.accounts.hook_config.to_account_info(),
+                    ctx.accounts.pool_state.to_account_info(),
+                    ctx.remaining_accounts,
+                    &required_accounts,
+                )?;
+
+                let ix = Instruction {
+                    program_id: target_program,
+                    accounts: metas,
+                    data: HookInstruction::PostShield(PostShieldHook {
+                        origin_mint,
+                        pool: pool_key,
+                        depositor: pending.depositor,
+                        commitment: pending.commitment,
+                        amount_commit: pending.amount_commit,
+                        amount: pending.amount,
+                        leaf_index: pending.next_index,
+                        root: pool_loader.load()?.current_root,
+                        slot: Clock::get()?.slot,
+                    })
+                    .try_to_vec()?,
+                };
+
+                let signer_seeds: [&[u8]; 3] = [seeds::POOL, origin_mint.as_ref(), &[pool_bump]];
+                invoke_signed(&ix, &infos, &[&signer_seeds])?;
+            }
         }
+
+        ctx.accounts
+            .shield_claim
+            .mark_ledger_complete(requires_invariant);
+
+        Ok(())
     }
 
-    #[cfg(not(feature = "lightweight"))]
-    {
-        let (new_root, _output_indices) = {
-            let mut commitment_tree = ctx.accounts.commitment_tree.load_mut()?;
-            commitment_tree.append_many(
-                args.output_commitments.as_slice(),
-                args.output_amount_commitments.as_slice(),
-            )?
-        };
-        if new_root != args.new_root {
-            msg!(
-                "unshield proof new root ({}) differs from computed root ({})",
-                hex::encode(args.new_root),
-                hex::encode(new_root)
-            );
+    pub fn shield_check_invariant<'info>(
+        ctx: Context<'_, '_, '_, 'info, ShieldCheckInvariant<'info>>,
+    ) -> Result<()> {
+        if !ctx.accounts.shield_claim.is_awaiting_invariant()
+            || !ctx.accounts.shield_claim.needs_invariant()
+        {
+            return Ok(());
         }
-        pool_state.push_root(new_root);
+        require_keys_eq!(
+            ctx.accounts.shield_claim.pool,
+            ctx.accounts.pool_state.key(),
+            PoolError::ShieldClaimMismatch
+        );
 
-        note_ledger.record_unshield(
-            total_spent,
-            &args.nullifiers,
-            args.output_amount_commitments.as_slice(),
-        )?;
-        #[cfg(all(feature = "invariant_checks", not(feature = "lightweight")))]
         {
-            should_enforce_invariant = note_ledger.should_enforce_invariant(total_spent);
+            let pool_state = ctx.accounts.pool_state.load()?;
+            let note_ledger = ctx.accounts.note_ledger.load()?;
+            enforce_supply_invariant(
+                &pool_state,
+                &note_ledger,
+                &ctx.accounts.vault_token_account,
+                ctx.accounts.twin_mint.as_ref(),
+            )?;
         }
+
+        ctx.accounts.shield_claim.deactivate();
+        Ok(())
     }
 
-    #[cfg(feature = "lightweight")]
-    pool_state.push_root(args.new_root);
-    pool_state.protocol_fees = pool_state
-        .protocol_fees
-        .checked_add(u128::from(fee))
-        .ok_or(PoolError::AmountOverflow)?;
+    /// Permissionless supply audit. Emits `PTFInvariantOk` when the vault covers the
+    /// ledger; on a shortfall it pauses the pool instead of failing, so the halt sticks.
+    /// A surplus is a donation to the vault and leaves the pool running, so anyone
+    /// sending tokens to the vault cannot pause it.
+    pub fn check_invariant<'info>(
+        ctx: Context<'_, '_, '_, 'info, CheckInvariant<'info>>,
+    ) -> Result<()> {
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        let note_ledger = ctx.accounts.note_ledger.load()?;
+        require_keys_eq!(
+            ctx.accounts.vault_token_account.owner,
+            pool_state.vault,
+            PoolError::VaultTokenAccountMismatch,
+        );
+        require_keys_eq!(
+            ctx.accounts.vault_token_account.mint,
+            pool_state.origin_mint,
+            PoolError::OriginMintMismatch,
+        );
+        let twin_supply = resolve_twin_supply(&pool_state, ctx.accounts.twin_mint.as_ref())?;
+        let expected = expected_vault_balance(&pool_state, &note_ledger, twin_supply)?;
+        let vault_balance = u128::from(ctx.accounts.vault_token_account.amount);
+        if vault_balance >= expected {
+            emit!(PTFInvariantOk {
+                mint: pool_state.origin_mint,
+                vault: pool_state.vault,
+                supply_pm: u64::try_from(twin_supply).map_err(|_| PoolError::AmountOverflow)?,
+                live_notes_commit: note_ledger.amount_commitment_digest,
+                fees: pool_state.protocol_fees,
+            });
+        } else {
+            pool_state.paused = true;
+            emit!(PTFInvariantBreached {
+                mint: pool_state.origin_mint,
+                vault: pool_state.vault,
+                vault_balance,
+                expected,
+            });
+        }
+        Ok(())
+    }
 
-    let pool_bump = pool_state.bump;
-    let twin_mint_key = pool_state.twin_mint;
-    let twin_mint_enabled = pool_state.twin_mint_enabled;
-    let pool_features = pool_state.features;
-    let hook_config_present = pool_state.hook_config_present;
+    /// Permissionless read of the ledger digests and counters so watchers can compare
+    /// them against their own reconstruction.
+    pub fn audit_ledger(ctx: Context<AuditLedger>) -> Result<()> {
+        let pool_state = ctx.accounts.pool_state.load()?;
+        let note_ledger = ctx.accounts.note_ledger.load()?;
+        emit!(LedgerAudited {
+            origin_mint: pool_state.origin_mint,
+            note_ledger: ctx.accounts.note_ledger.key(),
+            amount_commitment_digest: note_ledger.amount_commitment_digest,
+            nullifier_digest: note_ledger.nullifier_digest,
+            archived_nullifier_digest: note_ledger.archived_nullifier_digest,
+            nullifier_epoch: note_ledger.nullifier_epoch,
+            notes_created: note_ledger.notes_created,
+            notes_consumed: note_ledger.notes_consumed,
+            total_minted: note_ledger.total_minted,
+            total_spent: note_ledger.total_spent,
+            live_value: note_ledger.live_value,
+        });
+        Ok(())
+    }
 
-    drop(pool_state);
+    /// `shield` for native SOL pools: wraps `amount` plus the shield fee from the payer's
+    /// lamports into their wSOL account, then shields as usual.
+    pub fn shield_native<'info>(
+        ctx: Context<'_, '_, '_, 'info, Shield<'info>>,
+        args: ShieldArgs,
+    ) -> Result<()> {
+        require_keys_eq!(
+            ctx.accounts.origin_mint.key(),
+            NATIVE_MINT,
+            PoolError::NativeMintRequired
+        );
+        let deposit_amount = ctx
+            .accounts
+            .pool_state
+            .load()?
+            .native_shield_deposit(args.amount)?;
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.payer.to_account_info(),
+                    to: ctx.accounts.depositor_token_account.to_account_info(),
+                },
+            ),
+            deposit_amount,
+        )?;
+        anchor_spl::token_interface::sync_native(CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            anchor_spl::token_interface::SyncNative {
+                account: ctx.accounts.depositor_token_account.to_account_info(),
+            },
+        ))?;
+        ctx.accounts.depositor_token_account.reload()?;
+        shield(ctx, args)
+    }
 
-    match mode {
-        UnshieldMode::Origin => {
-            require_keys_eq!(
-                ctx.accounts.destination_token_account.mint,
-                origin_mint,
-                PoolError::OriginMintMismatch,
-            );
-            let signer_seeds: [&[u8]; 3] = [seeds::POOL, origin_mint.as_ref(), &[pool_bump]];
-            let cpi_accounts = ptf_vault::cpi::accounts::Release {
-                vault_state: ctx.accounts.vault_state.to_account_info(),
-                vault_token_account: ctx.accounts.vault_token_account.to_account_info(),
-                destination_token_account: ctx.accounts.destination_token_account.to_account_info(),
-                pool_authority: ctx.accounts.pool_state.to_account_info(),
-                token_program: ctx.accounts.token_program.to_account_info(),
-            };
-            let signer = &[&signer_seeds[..]];
-            let cpi_ctx = CpiContext::new_with_signer(
-                ctx.accounts.vault_program.to_account_info(),
-                cpi_accounts,
-                signer,
-            );
-            ptf_vault::cpi::release(cpi_ctx, args.amount)?;
-            emit!(PTFUnshieldOrigin {
-                mint: origin_mint,
-                destination: destination_owner,
-                amount: args.amount,
-                fee,
-            });
-        }
-        UnshieldMode::Twin => {
-            require!(twin_mint_enabled, PoolError::TwinMintNotConfigured);
-            require!(
-                ctx.accounts.mint_mapping.has_ptkn,
-                PoolError::TwinMintNotConfigured
-            );
-            let twin_mint = ctx
-                .accounts
-                .twin_mint
+    pub fn unshield_to_origin<'info>(
+        ctx: Context<'_, '_, '_, 'info, Unshield<'info>>,
+        args: UnshieldArgs,
+    ) -> Result<()> {
+        process_unshield(ctx, args.into(), UnshieldMode::Origin, false, None)
+    }
+
+    pub fn unshield_to_ptkn<'info>(
+        ctx: Context<'_, '_, '_, 'info, Unshield<'info>>,
+        args: UnshieldArgs,
+    ) -> Result<()> {
+        process_unshield(ctx, args.into(), UnshieldMode::Twin, false, None)
+    }
+
+    /// Unshields into a token account owned by the escrow PDA for `recipient`, `arbiter`
+    /// and the first nullifier, which the proof binds as its destination. The funds only
+    /// leave through `release_escrow`, signed by both.
+    pub fn unshield_to_escrow<'info>(
+        ctx: Context<'_, '_, '_, 'info, Unshield<'info>>,
+        args: UnshieldArgs,
+        recipient: Pubkey,
+        arbiter: Pubkey,
+    ) -> Result<()> {
+        let pool = ctx.accounts.pool_state.key();
+        let nullifier = *args.nullifiers.first().ok_or(PoolError::EscrowInvalid)?;
+        require!(
+            ctx.accounts.native_destination.is_none(),
+            PoolError::EscrowInvalid
+        );
+        let (Some(escrow), Some(payer), Some(system_program)) = (
+            ctx.accounts.escrow.as_ref(),
+            ctx.accounts.payer.as_ref(),
+            ctx.accounts.system_program.as_ref(),
+        ) else {
+            return err!(PoolError::EscrowInvalid);
+        };
+        let token_account = ctx.accounts.destination_token_account.key();
+        // The escrow PDA must own the payout account, or the release could not move it.
+        let token_owner = if args.create_destination_ata {
+            ctx.accounts
+                .destination_owner
                 .as_ref()
-                .ok_or(PoolError::TwinMintNotConfigured)?;
+                .ok_or(PoolError::EscrowInvalid)?
+                .key()
+        } else {
+            let info = ctx.accounts.destination_token_account.to_account_info();
             require_keys_eq!(
-                ctx.accounts.destination_token_account.mint,
-                twin_mint_key,
-                PoolError::TwinMintMismatch,
-            );
-            let signer_seeds: [&[u8]; 3] = [seeds::POOL, origin_mint.as_ref(), &[pool_bump]];
-            let factory_accounts = ptf_factory::cpi::accounts::MintPtkn {
-                factory_state: ctx.accounts.factory_state.to_account_info(),
-                mint_mapping: ctx.accounts.mint_mapping.to_account_info(),
-                pool_authority: ctx.accounts.pool_state.to_account_info(),
-                ptkn_mint: twin_mint.to_account_info(),
-                destination_token_account: ctx.accounts.destination_token_account.to_account_info(),
-                token_program: ctx.accounts.token_program.to_account_info(),
-            };
-            let signer = &[&signer_seeds[..]];
-            let mint_ctx = CpiContext::new_with_signer(
-                ctx.accounts.factory_program.to_account_info(),
-                factory_accounts,
-                signer,
+                *info.owner,
+                ctx.accounts.token_program.key(),
+                PoolError::EscrowInvalid
             );
-            ptf_factory::cpi::mint_ptkn(mint_ctx, args.amount)?;
-            emit!(PTFUnshieldPMint {
-                mint: origin_mint,
-                destination: destination_owner,
+            let data = info.try_borrow_data()?;
+            TokenAccount::try_deserialize(&mut &data[..])?.owner
+        };
+        require_keys_eq!(token_owner, escrow.key(), PoolError::EscrowInvalid);
+        UnshieldEscrow::create(
+            escrow,
+            payer,
+            system_program,
+            UnshieldEscrow {
+                pool,
+                nullifier,
+                recipient,
+                arbiter,
+                token_account,
                 amount: args.amount,
-                fee,
-            });
-        }
+                bump: 0,
+            },
+        )?;
+        emit!(PTFUnshieldEscrowed {
+            mint: ctx.accounts.pool_state.load()?.origin_mint,
+            escrow: escrow.key(),
+            recipient,
+            arbiter,
+            amount: args.amount,
+        });
+        process_unshield(
+            ctx,
+            args.into(),
+            UnshieldMode::Origin,
+            false,
+            Some(recipient),
+        )
     }
 
-    let hook_enabled =
-        pool_features.contains(FeatureFlags::from(FEATURE_HOOKS_ENABLED)) && hook_config_present;
-    let pool_key = pool_loader.key();
-
-    if hook_enabled {
-        let (required_accounts, hook_mode, target_program, post_unshield_enabled) = {
-            let hook_config = ctx.accounts.hook_config.load()?;
-            (
-                hook_config.required_keys().collect::<Vec<_>>(),
-                hook_config.mode,
-                hook_config.post_unshield_program_id,
-                hook_config.post_unshield_enabled,
-            )
-        };
-        if post_unshield_enabled && target_program != Pubkey::default() {
-            validate_hook_accounts(&required_accounts, hook_mode, ctx.remaining_accounts)?;
+    /// Pays out an escrowed unshield once the recipient and the arbiter both sign, and
+    /// closes the escrow.
+    pub fn release_escrow(ctx: Context<ReleaseEscrow>) -> Result<()> {
+        let escrow = &ctx.accounts.escrow;
+        let bump_seed = [escrow.bump];
+        let signer_seeds: [&[u8]; 6] = [
+            seeds::ESCROW,
+            escrow.pool.as_ref(),
+            escrow.recipient.as_ref(),
+            escrow.arbiter.as_ref(),
+            &escrow.nullifier,
+            &bump_seed,
+        ];
+        let signer = &[&signer_seeds[..]];
+        let amount = ctx.accounts.escrow_token_account.amount;
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                anchor_spl::token_interface::TransferChecked {
+                    from: ctx.accounts.escrow_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.recipient_token_account.to_account_info(),
+                    authority: ctx.accounts.escrow.to_account_info(),
+                },
+                signer,
+            ),
+            amount,
+            ctx.accounts.mint.decimals,
+        )?;
+        anchor_spl::token_interface::close_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            anchor_spl::token_interface::CloseAccount {
+                account: ctx.accounts.escrow_token_account.to_account_info(),
+                destination: ctx.accounts.recipient.to_account_info(),
+                authority: ctx.accounts.escrow.to_account_info(),
+            },
+            signer,
+        ))?;
+        emit!(EscrowReleased {
+            mint: ctx.accounts.mint.key(),
+            escrow: ctx.accounts.escrow.key(),
+            recipient: ctx.accounts.recipient.key(),
+            amount,
+        });
+        Ok(())
+    }
 
-            let mut metas = Vec::with_capacity(2 + ctx.remaining_accounts.len());
-            let mut infos = Vec::with_capacity(2 + ctx.remaining_accounts.len());
+    /// Exit path once the authority has called `enable_emergency_exit`. Proofs must be
+    /// against the frozen `emergency_root` under the pinned emergency verifier; hooks and
+    /// rate limits are skipped, change notes are rejected, and funds only go to the
+    /// destination bound in the proof.
+    pub fn emergency_unshield<'info>(
+        ctx: Context<'_, '_, '_, 'info, Unshield<'info>>,
+        args: UnshieldArgs,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.pool_state.load()?.emergency_exit,
+            PoolError::EmergencyExitDisabled
+        );
+        process_unshield(ctx, args.into(), UnshieldMode::Origin, true, None)
+    }
 
-            let hook_config_info = ctx.accounts.hook_config.to_account_info();
-            let pool_info = ctx.accounts.pool_state.to_account_info();
-            metas.push(AccountMeta::new_readonly(hook_config_info.key(), false));
-            metas.push(AccountMeta::new_readonly(pool_info.key(), false));
-            infos.push(hook_config_info);
-            infos.push(pool_info);
+    /// Pays several recipients from one proof. The first destination is the context's
+    /// `destination_token_account`; the rest lead the remaining accounts, in the order of
+    /// `args.amounts`, ahead of any nullifier storage and hook accounts.
+    pub fn unshield_many<'info>(
+        ctx: Context<'_, '_, '_, 'info, Unshield<'info>>,
+        args: UnshieldManyArgs,
+        mode: UnshieldMode,
+    ) -> Result<()> {
+        process_unshield(ctx, args, mode, false, None)
+    }
 
-            for account in ctx.remaining_accounts.iter() {
-                let meta = if account.is_writable {
-                    AccountMeta::new(account.key(), account.is_signer)
-                } else {
-                    AccountMeta::new_readonly(account.key(), account.is_signer)
-                };
-                metas.push(meta);
-                infos.push(account.clone());
-            }
+    /// Re-derives the pool root from the `CommitmentTree` account and records it if it
+    /// moved. The tree is only written by this program, so the call is permissionless and
+    /// cannot introduce a root that the tree does not hold.
+    pub fn sync_root(ctx: Context<SyncRoot>) -> Result<()> {
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        pool_state.check_root_guardian(ctx.accounts.root_guardian.as_ref())?;
+        let root = ctx.accounts.commitment_tree.load()?.current_root;
 
-            let ix = Instruction {
-                program_id: target_program,
-                accounts: metas,
-                data: HookInstruction::PostUnshield(PostUnshieldHook {
-                    origin_mint,
-                    pool: pool_key,
-                    destination: destination_owner,
-                    mode: mode as u8,
-                    amount: args.amount,
-                    fee,
-                })
-                .try_to_vec()?,
-            };
+        if root == pool_state.current_root {
+            return Ok(());
+        }
+        pool_state.push_root(root, Clock::get()?.slot);
+        emit!(RootSynced {
+            origin_mint: pool_state.origin_mint,
+            root,
+        });
+        Ok(())
+    }
 
-            let signer_seeds: [&[u8]; 3] = [seeds::POOL, origin_mint.as_ref(), &[pool_bump]];
-            invoke_signed(&ix, &infos, &[&signer_seeds])?;
+    /// Recomputes the root for `commitment` at `leaf_index` from `siblings` (leaf level
+    /// first) using the on-chain tree's hashing, and succeeds only if the pool still
+    /// accepts that root. The root is returned as CPI return data.
+    pub fn verify_membership(
+        ctx: Context<VerifyMembership>,
+        commitment: [u8; 32],
+        leaf_index: u64,
+        siblings: Vec<[u8; 32]>,
+    ) -> Result<[u8; 32]> {
+        let tree = ctx.accounts.commitment_tree.load()?;
+        require!(
+            siblings.len() == tree.depth() && leaf_index < tree.next_index,
+            PoolError::MembershipProofInvalid,
+        );
+        let root = CommitmentTree::root_from_path(&commitment, leaf_index, &siblings);
+        let pool_state = ctx.accounts.pool_state.load()?;
+        require!(
+            pool_state.is_known_root(&root, Clock::get()?.slot),
+            PoolError::MembershipProofInvalid,
+        );
+        Ok(root)
+    }
 
-            emit!(PTFHookPostUnshield {
-                mint: origin_mint,
-                mode: mode as u8,
-                destination: destination_owner,
-            });
+    /// Reports through return data whether `nullifier` has been spent, for relayers and
+    /// front-ends to pre-check withdrawals by simulation. The legacy set is always
+    /// consulted; sharded pools pass the nullifier's shard and PDA-mode pools its record
+    /// address as the first remaining account.
+    pub fn is_nullifier_spent(ctx: Context<IsNullifierSpent>, nullifier: [u8; 32]) -> Result<bool> {
+        let pool_state = ctx.accounts.pool_state.load()?;
+        let pool_key = ctx.accounts.pool_state.key();
+        {
+            let legacy_info = ctx.accounts.nullifier_set.to_account_info();
+            let legacy_data = legacy_info.try_borrow_data()?;
+            let (legacy, legacy_overflow) = NullifierSet::split_data(&legacy_data)?;
+            if legacy.contains(legacy_overflow, &nullifier) {
+                return Ok(true);
+            }
+        }
+        if pool_state.nullifier_mode == NULLIFIER_MODE_PDA {
+            let info = ctx
+                .remaining_accounts
+                .first()
+                .ok_or(PoolError::NullifierRecordMissing)?;
+            let (expected, _) = NullifierRecord::address(&pool_key, &nullifier);
+            require_keys_eq!(expected, info.key(), PoolError::NullifierRecordMismatch);
+            return Ok(info.owner == &crate::ID);
+        }
+        if pool_state.nullifier_shard_count == 0 {
+            return Ok(false);
         }
+        let info = ctx
+            .remaining_accounts
+            .first()
+            .ok_or(PoolError::NullifierShardMissing)?;
+        let shard = NullifierSet::shard_index(&nullifier, pool_state.nullifier_shard_count);
+        let mut shard_data =
+            NullifierSet::load_shard_data(info, pool_key, pool_state.origin_mint, shard)?;
+        let (shard_set, shard_overflow) = NullifierSet::split_data_mut(&mut shard_data)?;
+        Ok(shard_set.contains(shard_overflow, &nullifier))
     }
 
-    #[cfg(all(feature = "invariant_checks", not(feature = "lightweight")))]
-    if should_enforce_invariant {
-        let pool_state = pool_loader.load()?;
-        enforce_supply_invariant(
+    /// Returns `PoolInfo` as return data so composing programs need not know the
+    /// zero-copy layout.
+    pub fn get_pool_info(ctx: Context<GetPoolInfo>) -> Result<PoolInfo> {
+        let pool_state = ctx.accounts.pool_state.load()?;
+        Ok(PoolInfo {
+            origin_mint: pool_state.origin_mint,
+            current_root: pool_state.current_root,
+            shield_fee_bps: pool_state.shield_fee_bps,
+            unshield_fee_bps: pool_state.unshield_fee_bps,
+            features: pool_state.features,
+            paused: pool_state.paused,
+            version: pool_state.version,
+            fee_rounding: pool_state.fee_rounding,
+        })
+    }
+
+    /// Returns the current and previous epoch's `EpochStats` as return data. An epoch
+    /// with no activity yet reads as zeros.
+    pub fn get_pool_stats(ctx: Context<GetPoolStats>) -> Result<PoolStatsInfo> {
+        Ok(ctx.accounts.pool_stats.view(Clock::get()?.epoch))
+    }
+
+    /// Checks a disclosure proof that `args.commitment` is in the tree under a known root
+    /// and opens to `args.owner` and `args.amount`. Nothing is spent or written; the
+    /// attestation is the successful transaction and its `NoteDisclosed` event.
+    pub fn verify_disclosure(ctx: Context<VerifyDisclosure>, args: DisclosureArgs) -> Result<()> {
+        let pool_state = ctx.accounts.pool_state.load()?;
+        pool_state.check_verifier_program(&ctx.accounts.verifier_program.key())?;
+        require!(
+            pool_state.is_known_root(&args.root, Clock::get()?.slot),
+            PoolError::UnknownRoot
+        );
+        validate_disclosure_public_inputs(
             &pool_state,
-            &note_ledger,
-            &ctx.accounts.vault_token_account,
-            ctx.accounts.twin_mint.as_ref(),
+            ctx.accounts.pool_state.key(),
+            &args,
+            ctx.accounts.mint_mapping.decimals,
+        )?;
+        invoke_verifier(
+            &ctx.accounts.verifier_program,
+            &ctx.accounts.verifying_key,
+            ctx.accounts.disclosure_verifier.verifying_key_id,
+            args.proof.clone(),
+            args.public_inputs.clone(),
         )?;
+        emit!(NoteDisclosed {
+            origin_mint: pool_state.origin_mint,
+            commitment: args.commitment,
+            root: args.root,
+            owner: args.owner,
+            amount: args.amount,
+        });
+        Ok(())
     }
-    Ok(())
-}
 
-fn process_shield_finalize_tree<'info>(
-    pool_loader: &AccountLoader<'info, PoolState>,
-    commitment_tree: &AccountLoader<'info, CommitmentTree>,
-    shield_claim: &mut Account<'info, ShieldClaim>,
-) -> Result<()> {
-    require!(shield_claim.is_pending_tree(), PoolError::ShieldClaimStage);
-    require_keys_eq!(
-        shield_claim.pool,
-        pool_loader.key(),
-        PoolError::ShieldClaimMismatch
-    );
-    let pending = shield_claim.snapshot();
+    /// Returns `TreeInfo` for the pool's commitment tree.
+    pub fn get_tree_info(ctx: Context<GetTreeInfo>) -> Result<TreeInfo> {
+        let commitment_tree = ctx.accounts.commitment_tree.load()?;
+        Ok(TreeInfo {
+            root: commitment_tree.current_root,
+            next_leaf_index: commitment_tree.next_index,
+        })
+    }
 
-    #[cfg(feature = "full_tree")]
-    {
-        let mut tree = commitment_tree.load_mut()?;
+    /// Sets how many leaves each tree checkpoint must cover; 0 turns checkpoints off.
+    /// Checkpoints only describe history, so this skips the timelock.
+    pub fn set_checkpoint_interval(ctx: Context<UpdateAuthority>, interval: u64) -> Result<()> {
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        pool_state.checkpoint_interval = interval;
+        emit!(CheckpointIntervalUpdated {
+            origin_mint: pool_state.origin_mint,
+            interval,
+        });
+        Ok(())
+    }
+
+    /// Records the current root and the leaves appended since the previous checkpoint
+    /// once at least `checkpoint_interval` of them exist. Anyone may crank it. Each
+    /// checkpoint hashes its predecessor's digest, so the series is tamper-evident and a
+    /// light client can start syncing from the newest one.
+    pub fn write_tree_checkpoint(ctx: Context<WriteTreeCheckpoint>) -> Result<()> {
+        let pool_key = ctx.accounts.pool_state.key();
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
         require!(
-            tree.current_root == pending.old_root,
-            PoolError::RootMismatch,
+            pool_state.checkpoint_interval > 0,
+            PoolError::FeatureDisabled
         );
+        let (leaf_count, root) = {
+            let commitment_tree = ctx.accounts.commitment_tree.load()?;
+            (commitment_tree.next_index, commitment_tree.current_root)
+        };
         require!(
-            tree.next_index == pending.next_index,
-            PoolError::PendingShieldMismatch,
+            pool_state.checkpoint_due(leaf_count),
+            PoolError::CheckpointNotDue
         );
-        let (new_root, _) = tree.append_note(pending.commitment, pending.amount_commit)?;
-        {
-            let mut pool_state = pool_loader.load_mut()?;
-            pool_state.push_root(new_root);
-            pool_state.pending_shield.deactivate();
-        }
-        shield_claim.mark_tree_complete();
-        return Ok(());
+        let index = pool_state.checkpoint_count;
+        let start_leaf = pool_state.checkpoint_leaf;
+        let previous_digest = pool_state.checkpoint_digest;
+        let digest = TreeCheckpoint::chain(&previous_digest, index, start_leaf, leaf_count, &root);
+        let slot = Clock::get()?.slot;
+
+        let checkpoint = &mut ctx.accounts.checkpoint;
+        checkpoint.pool = pool_key;
+        checkpoint.index = index;
+        checkpoint.start_leaf = start_leaf;
+        checkpoint.end_leaf = leaf_count;
+        checkpoint.root = root;
+        checkpoint.slot = slot;
+        checkpoint.previous_digest = previous_digest;
+        checkpoint.digest = digest;
+        checkpoint.bump = ctx.bumps.checkpoint;
+
+        pool_state.checkpoint_count = index + 1;
+        pool_state.checkpoint_leaf = leaf_count;
+        pool_state.checkpoint_digest = digest;
+        emit!(TreeCheckpointWritten {
+            origin_mint: pool_state.origin_mint,
+            index,
+            start_leaf,
+            end_leaf: leaf_count,
+            root,
+            digest,
+        });
+        Ok(())
     }
 
-    #[cfg(not(feature = "full_tree"))]
-    {
-        let mut tree = commitment_tree.load_mut()?;
+    pub fn initialize_nullifier_shard(
+        ctx: Context<InitializeNullifierShard>,
+        shard: u8,
+    ) -> Result<()> {
         require!(
-            tree.current_root == pending.old_root,
-            PoolError::RootMismatch,
+            shard < NullifierSet::MAX_SHARDS,
+            PoolError::NullifierShardCountInvalid
         );
+        let mut nulls = ctx.accounts.nullifier_shard.load_init()?;
+        nulls.pool = ctx.accounts.pool_state.key();
+        nulls.bump = ctx.bumps.nullifier_shard;
+        nulls.shard = shard;
+        nulls.count = 0;
+        nulls.bloom = [0u8; NullifierSet::BLOOM_BYTES];
+        nulls.version = NullifierSet::CURRENT_VERSION;
+        Ok(())
+    }
+
+    /// One-way migration from the single legacy nullifier set to `shard_count` shards.
+    /// Every shard must already be initialized and is passed, in order, as a remaining
+    /// account so the pool never routes a spend to a shard that does not exist.
+    pub fn migrate_nullifier_shards(ctx: Context<UpdateAuthority>, shard_count: u8) -> Result<()> {
         require!(
-            tree.next_index == pending.next_index,
-            PoolError::PendingShieldMismatch,
+            shard_count > 0 && shard_count <= NullifierSet::MAX_SHARDS,
+            PoolError::NullifierShardCountInvalid
+        );
+        let pool_key = ctx.accounts.pool_state.key();
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        require!(
+            pool_state.nullifier_shard_count == 0,
+            PoolError::NullifierShardsAlreadyEnabled
         );
         require!(
-            tree.next_index < (1u128 << CommitmentTree::DEPTH) as u64,
-            PoolError::TreeFull
+            pool_state.nullifier_mode == NULLIFIER_MODE_SET,
+            PoolError::NullifierModeUnsupported
         );
-        tree.next_index = tree
-            .next_index
-            .checked_add(1)
-            .ok_or(PoolError::AmountOverflow)?;
-        tree.current_root = pending.new_root;
-
-        {
-            let mut pool_state = pool_loader.load_mut()?;
-            pool_state.push_root(pending.new_root);
-            pool_state.pending_shield.deactivate();
+        require!(
+            ctx.remaining_accounts.len() == shard_count as usize,
+            PoolError::NullifierShardMissing
+        );
+        for (shard, info) in ctx.remaining_accounts.iter().enumerate() {
+            NullifierSet::load_shard_data(info, pool_key, pool_state.origin_mint, shard as u8)?;
         }
-        shield_claim.tree_level = CommitmentTree::DEPTH as u8;
-        shield_claim.tree_node = pending.new_root;
-        shield_claim.tree_index_cursor = 0;
-        shield_claim.mark_tree_complete();
-        return Ok(());
+        pool_state.nullifier_shard_count = shard_count;
+        emit!(NullifierShardsEnabled {
+            origin_mint: pool_state.origin_mint,
+            shard_count,
+        });
+        Ok(())
     }
-}
-#[cfg(feature = "invariant_checks")]
-fn enforce_supply_invariant<'info>(
-    pool_state: &PoolState,
-    note_ledger: &NoteLedger,
-    vault_token_account: &InterfaceAccount<'info, TokenAccount>,
-    twin_mint: Option<&InterfaceAccount<'info, Mint>>,
-) -> Result<()> {
-    let vault_balance = u128::from(vault_token_account.amount);
-    let twin_supply = match (pool_state.twin_mint_enabled, twin_mint) {
-        (true, Some(mint)) => {
-            require_keys_eq!(
-                mint.key(),
-                pool_state.twin_mint,
-                PoolError::TwinMintMismatch
-            );
-            u128::from(mint.supply)
-        }
-        (true, None) => return err!(PoolError::TwinMintNotConfigured),
-        (false, Some(_)) => return err!(PoolError::TwinMintMismatch),
-        (false, None) => 0u128,
-    };
 
-    validate_supply_components(pool_state, note_ledger, twin_supply, vault_balance).map(|_| ())
-}
+    /// One-way switch to per-nullifier record PDAs. Only unsharded pools can move over;
+    /// the legacy set remains readable so earlier spends are still rejected.
+    pub fn enable_nullifier_records(ctx: Context<UpdateAuthority>) -> Result<()> {
+        let mut pool_state = ctx.accounts.pool_state.load_mut()?;
+        require!(
+            pool_state.nullifier_mode == NULLIFIER_MODE_SET
+                && pool_state.nullifier_shard_count == 0,
+            PoolError::NullifierModeUnsupported
+        );
+        pool_state.nullifier_mode = NULLIFIER_MODE_PDA;
+        emit!(NullifierModeUpdated {
+            origin_mint: pool_state.origin_mint,
+            mode: NULLIFIER_MODE_PDA,
+        });
+        Ok(())
+    }
 
-#[cfg(feature = "invariant_checks")]
-fn validate_supply_components(
-    pool_state: &PoolState,
-    note_ledger: &NoteLedger,
-    twin_supply: u128,
-    vault_balance: u128,
-) -> Result<u128> {
-    let expected = twin_supply
-        .checked_add(note_ledger.live_value)
-        .ok_or(PoolError::AmountOverflow)?
-        .checked_add(pool_state.protocol_fees)
-        .ok_or(PoolError::AmountOverflow)?;
+    /// Moves up to `max_entries` nullifiers from the legacy set into the pool's sharded
+    /// or PDA storage, newest first. Entries leave the legacy set in the same instruction
+    /// that writes them to their new home, so reuse checks hold throughout and spends can
+    /// continue while a live pool is compacted. Sharded pools pass the distinct shards of
+    /// the batch in ascending order, then a record per entry bound for an archived shard;
+    /// PDA-mode pools pass one record per entry in pop
+    /// order. Permissionless; the payer funds any record accounts.
+    pub fn compact_nullifiers<'info>(
+        ctx: Context<'_, '_, '_, 'info, CompactNullifiers<'info>>,
+        max_entries: u16,
+    ) -> Result<()> {
+        let pool_key = ctx.accounts.pool_state.key();
+        let pool_state = ctx.accounts.pool_state.load()?;
+        let origin_mint = pool_state.origin_mint;
+        let shard_count = pool_state.nullifier_shard_count;
+        require!(
+            pool_state.nullifier_mode == NULLIFIER_MODE_PDA || shard_count > 0,
+            PoolError::NullifierModeUnsupported
+        );
 
-    require!(vault_balance == expected, PoolError::InvariantBreach);
-    Ok(expected)
-}
+        let legacy_info = ctx.accounts.nullifier_set.to_account_info();
+        let mut legacy_data = legacy_info.try_borrow_mut_data()?;
+        let (legacy, legacy_overflow) = NullifierSet::split_data_mut(&mut legacy_data)?;
+        let batch = legacy.pop_entries(legacy_overflow, max_entries as usize);
+        let remaining = legacy.count;
+        drop(legacy_data);
 
-#[inline(always)]
-fn highest_power_of_two_leq(n: usize) -> usize {
-    debug_assert!(n > 0);
-    let mut power = 1usize;
-    while (power << 1) <= n {
-        power <<= 1;
-    }
-    power
-}
+        if pool_state.nullifier_mode == NULLIFIER_MODE_PDA {
+            require!(
+                ctx.remaining_accounts.len() >= batch.len(),
+                PoolError::NullifierRecordMissing
+            );
+            let slot = Clock::get()?.slot;
+            for (nullifier, info) in batch.iter().zip(ctx.remaining_accounts.iter()) {
+                NullifierRecord::create(
+                    info,
+                    &ctx.accounts.payer,
+                    &ctx.accounts.system_program,
+                    pool_key,
+                    *nullifier,
+                    slot,
+                )?;
+            }
+        } else {
+            insert_sharded_nullifiers(
+                pool_key,
+                &pool_state,
+                ctx.remaining_accounts,
+                Some(&ctx.accounts.payer),
+                Some(&ctx.accounts.system_program),
+                &batch,
+            )?;
+        }
 
-#[inline(always)]
-fn fr_from_bytes(bytes: &[u8; 32]) -> Fr {
-    let mut limbs = [0u64; 4];
-    for (index, limb) in limbs.iter_mut().enumerate() {
-        let start = index * 8;
-        let chunk: [u8; 8] = bytes[start..start + 8]
-            .try_into()
-            .expect("slice with incorrect length");
-        *limb = u64::from_le_bytes(chunk);
+        emit!(NullifiersCompacted {
+            origin_mint,
+            migrated: batch.len() as u32,
+            remaining,
+        });
+        Ok(())
     }
-    Fr::new(BigInteger256::new(limbs))
-}
 
-#[inline(always)]
-fn fr_to_bytes(value: &Fr) -> [u8; 32] {
-    let limbs = (*value).into_bigint().0;
-    let mut bytes = [0u8; 32];
-    for (index, limb) in limbs.iter().enumerate() {
-        let start = index * 8;
-        bytes[start..start + 8].copy_from_slice(&limb.to_le_bytes());
-    }
-    bytes
-}
-#[derive(Accounts)]
-pub struct InitializePool<'info> {
-    pub authority: Signer<'info>,
-    #[account(
-        init,
-        payer = payer,
-        seeds = [seeds::POOL, origin_mint.key().as_ref()],
-        bump,
-        space = PoolState::SPACE,
-    )]
-    pub pool_state: AccountLoader<'info, PoolState>,
-    #[account(
-        init,
-        payer = payer,
-        seeds = [seeds::NULLIFIERS, origin_mint.key().as_ref()],
-        bump,
-        space = NullifierSet::SPACE,
-    )]
-    pub nullifier_set: AccountLoader<'info, NullifierSet>,
-    #[account(
-        init,
-        payer = payer,
-        seeds = [seeds::NOTES, origin_mint.key().as_ref()],
-        bump,
-        space = NoteLedger::SPACE,
-    )]
-    pub note_ledger: AccountLoader<'info, NoteLedger>,
-    #[account(
-        init,
-        payer = payer,
-        seeds = [seeds::TREE, origin_mint.key().as_ref()],
-        bump,
-        space = CommitmentTree::SPACE,
-    )]
-    pub commitment_tree: AccountLoader<'info, CommitmentTree>,
-    #[account(
-        init,
-        payer = payer,
-        seeds = [seeds::HOOKS, origin_mint.key().as_ref()],
-        bump,
-        space = HookConfig::SPACE,
-    )]
-    pub hook_config: AccountLoader<'info, HookConfig>,
-    #[account(mut)]
-    pub vault_state: Account<'info, ptf_vault::VaultState>,
-    pub origin_mint: InterfaceAccount<'info, Mint>,
-    #[account(
-        seeds = [seeds::MINT_MAPPING, origin_mint.key().as_ref()],
-        bump = mint_mapping.bump,
-        seeds::program = ptf_factory::ID
-    )]
-    pub mint_mapping: Account<'info, MintMapping>,
-    #[account(
-        seeds = [seeds::FACTORY, ptf_factory::ID.as_ref()],
-        bump = factory_state.bump,
-        seeds::program = ptf_factory::ID
-    )]
-    pub factory_state: Account<'info, ptf_factory::FactoryState>,
-    #[account(mut)]
-    pub twin_mint: Option<InterfaceAccount<'info, Mint>>,
-    pub verifier_program: Program<'info, PtfVerifierGroth16>,
-    pub verifying_key: Account<'info, VerifyingKeyAccount>,
-    #[account(mut)]
-    pub payer: Signer<'info>,
-    pub system_program: Program<'info, System>,
-    pub token_program: Interface<'info, TokenInterface>,
-}
+    /// Extends a nullifier set (legacy or shard) by `GROWTH_ENTRIES` slots. The pool
+    /// authority may grow at any time; anyone else may once the set is 90% full.
+    pub fn grow_nullifier_set(ctx: Context<GrowNullifierSet>) -> Result<()> {
+        let pool_state = ctx.accounts.pool_state.load()?;
+        let info = ctx.accounts.nullifier_set.to_account_info();
+        let (count, capacity) = {
+            let data = info.try_borrow_data()?;
+            let (set, overflow) = NullifierSet::split_data(&data)?;
+            (set.count as usize, NullifierSet::capacity(overflow))
+        };
+        require!(
+            ctx.accounts.payer.key() == pool_state.authority || count * 10 >= capacity * 9,
+            PoolError::NullifierGrowthNotNeeded
+        );
 
-#[derive(Accounts)]
-pub struct UpdateAuthority<'info> {
-    pub authority: Signer<'info>,
-    #[account(
-        mut,
-        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
-        bump = pool_state.load()?.bump,
-        has_one = authority
-    )]
-    pub pool_state: AccountLoader<'info, PoolState>,
-    #[account(
-        mut,
-        seeds = [seeds::NULLIFIERS, pool_state.load()?.origin_mint.as_ref()],
-        bump = nullifier_set.load()?.bump
-    )]
-    pub nullifier_set: AccountLoader<'info, NullifierSet>,
-}
+        let new_len = info
+            .data_len()
+            .checked_add(NullifierSet::GROWTH_ENTRIES * 32)
+            .ok_or(PoolError::AmountOverflow)?;
+        require!(
+            new_len <= NullifierSet::MAX_ACCOUNT_BYTES,
+            PoolError::NullifierCapacity
+        );
+        let required_lamports = Rent::get()?.minimum_balance(new_len);
+        let top_up = required_lamports.saturating_sub(info.lamports());
+        if top_up > 0 {
+            anchor_lang::system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    anchor_lang::system_program::Transfer {
+                        from: ctx.accounts.payer.to_account_info(),
+                        to: info.clone(),
+                    },
+                ),
+                top_up,
+            )?;
+        }
+        #[allow(deprecated)]
+        info.realloc(new_len, true)?;
 
-#[derive(Accounts)]
-pub struct Shield<'info> {
-    #[account(
-        mut,
-        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
-        bump = pool_state.load()?.bump
-    )]
-    pub pool_state: AccountLoader<'info, PoolState>,
-    #[account(
-        seeds = [seeds::HOOKS, pool_state.load()?.origin_mint.as_ref()],
-        bump = pool_state.load()?.hook_config_bump,
-        constraint = hook_config.load()?.pool == pool_state.key() @ PoolError::HookConfigInvalid,
-    )]
-    pub hook_config: AccountLoader<'info, HookConfig>,
-    #[account(
-        mut,
-        seeds = [seeds::NULLIFIERS, pool_state.load()?.origin_mint.as_ref()],
-        bump = nullifier_set.load()?.bump
-    )]
-    pub nullifier_set: AccountLoader<'info, NullifierSet>,
-    #[account(
-        mut,
-        seeds = [seeds::TREE, pool_state.load()?.origin_mint.as_ref()],
-        bump = commitment_tree.load()?.bump,
-        constraint = commitment_tree.load()?.pool == pool_state.key() @ PoolError::CommitmentTreeMismatch
-    )]
-    pub commitment_tree: AccountLoader<'info, CommitmentTree>,
-    #[account(
-        mut,
-        seeds = [seeds::NOTES, pool_state.load()?.origin_mint.as_ref()],
-        bump = pool_state.load()?.note_ledger_bump,
-        constraint = note_ledger.key() == pool_state.load()?.note_ledger @ PoolError::NoteLedgerMismatch,
-        constraint = note_ledger.load()?.pool == pool_state.key() @ PoolError::NoteLedgerMismatch,
-    )]
-    pub note_ledger: AccountLoader<'info, NoteLedger>,
-    #[account(
-        mut,
-        seeds = [seeds::VAULT, pool_state.load()?.origin_mint.as_ref()],
-        bump = vault_state.bump,
-        seeds::program = ptf_vault::ID
-    )]
-    pub vault_state: Account<'info, ptf_vault::VaultState>,
-    #[account(mut)]
-    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
-    #[account(mut)]
-    pub depositor_token_account: InterfaceAccount<'info, TokenAccount>,
-    #[account(mut)]
-    pub twin_mint: Option<InterfaceAccount<'info, Mint>>,
-    pub verifier_program: Program<'info, PtfVerifierGroth16>,
-    #[account(
-        address = pool_state.load()?.verifying_key,
-        constraint = verifying_key.hash == pool_state.load()?.verifying_key_hash @ PoolError::VerifyingKeyHashMismatch,
-    )]
-    pub verifying_key: Account<'info, VerifyingKeyAccount>,
-    #[account(
-        init_if_needed,
-        payer = payer,
-        space = ShieldClaim::SPACE,
-        seeds = [seeds::CLAIM, pool_state.key().as_ref()],
-        bump
-    )]
-    pub shield_claim: Account<'info, ShieldClaim>,
-    #[account(mut)]
-    pub payer: Signer<'info>,
-    pub origin_mint: InterfaceAccount<'info, Mint>,
-    pub vault_program: Program<'info, PtfVault>,
-    pub token_program: Interface<'info, TokenInterface>,
-    /// CHECK: constrained by address check
-    #[account(address = solana_program::sysvar::instructions::ID)]
-    pub instructions: UncheckedAccount<'info>,
-    pub system_program: Program<'info, System>,
-}
+        emit!(NullifierSetGrown {
+            origin_mint: pool_state.origin_mint,
+            nullifier_set: info.key(),
+            capacity: (capacity + NullifierSet::GROWTH_ENTRIES) as u32,
+        });
+        Ok(())
+    }
 
-#[derive(Accounts)]
-pub struct ShieldFinalizeTree<'info> {
-    #[account(
-        mut,
-        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
-        bump = pool_state.load()?.bump
-    )]
-    pub pool_state: AccountLoader<'info, PoolState>,
-    #[account(
-        mut,
-        seeds = [seeds::TREE, pool_state.load()?.origin_mint.as_ref()],
-        bump = commitment_tree.load()?.bump,
-        constraint = commitment_tree.load()?.pool == pool_state.key() @ PoolError::CommitmentTreeMismatch
-    )]
-    pub commitment_tree: AccountLoader<'info, CommitmentTree>,
-    #[account(
-        mut,
-        seeds = [seeds::CLAIM, pool_state.key().as_ref()],
-        bump = shield_claim.bump
-    )]
-    pub shield_claim: Account<'info, ShieldClaim>,
-}
+    /// Archives a full nullifier shard and moves up to `max_entries` of its entries into
+    /// `NullifierRecord` PDAs, newest first, passed as remaining accounts in pop order.
+    /// The first call on a full shard folds its contents into the ledger's archival
+    /// digest and advances the epoch; later calls keep emptying it. Spends routed to an
+    /// archived shard check what it still holds and create a record instead of inserting,
+    /// so every archived nullifier stays individually checkable. Authority only; the
+    /// authority funds the records.
+    pub fn rotate_nullifier_shard<'info>(
+        ctx: Context<'_, '_, '_, 'info, RotateNullifierShard<'info>>,
+        shard: u8,
+        max_entries: u16,
+    ) -> Result<()> {
+        let pool_key = ctx.accounts.pool_state.key();
+        let pool_state = ctx.accounts.pool_state.load()?;
+        require!(
+            pool_state.nullifier_mode == NULLIFIER_MODE_SET
+                && shard < pool_state.nullifier_shard_count,
+            PoolError::NullifierShardCountInvalid
+        );
 
-#[derive(Accounts)]
-pub struct ShieldFinalizeLedger<'info> {
-    #[account(
-        mut,
-        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        let info = ctx.accounts.nullifier_shard.to_account_info();
+        let (shard_digest, batch, remaining) = {
+            let mut data = info.try_borrow_mut_data()?;
+            let (set, overflow) = NullifierSet::split_data_mut(&mut data)?;
+            let shard_digest = if set.archived == 0 {
+                require!(
+                    set.count as usize >= NullifierSet::capacity(overflow),
+                    PoolError::NullifierShardNotFull
+                );
+                set.archived = 1;
+                Some(set.digest(overflow))
+            } else {
+                None
+            };
+            let batch = set.pop_entries(overflow, max_entries as usize);
+            (shard_digest, batch, set.count)
+        };
+
+        require!(
+            ctx.remaining_accounts.len() >= batch.len(),
+            PoolError::NullifierRecordMissing
+        );
+        let slot = Clock::get()?.slot;
+        for (nullifier, info) in batch.iter().zip(ctx.remaining_accounts.iter()) {
+            NullifierRecord::create(
+                info,
+                &ctx.accounts.authority,
+                &ctx.accounts.system_program,
+                pool_key,
+                *nullifier,
+                slot,
+            )?;
+        }
+
+        let mut note_ledger = ctx.accounts.note_ledger.load_mut()?;
+        if let Some(shard_digest) = shard_digest {
+            note_ledger.archive_nullifiers(shard_digest, slot)?;
+        }
+
+        emit!(NullifierEpochRotated {
+            origin_mint: pool_state.origin_mint,
+            shard,
+            epoch: note_ledger.nullifier_epoch,
+            shard_digest: shard_digest.unwrap_or_default(),
+            archived_digest: note_ledger.archived_nullifier_digest,
+            migrated: batch.len() as u32,
+            remaining,
+        });
+        Ok(())
+    }
+
+    pub fn private_transfer<'info>(
+        ctx: Context<'_, '_, '_, 'info, PrivateTransfer<'info>>,
+        args: TransferArgs,
+    ) -> Result<()> {
+        ensure_mint_active(&ctx.accounts.mint_mapping)?;
+        {
+            let pool_state = ctx.accounts.pool_state.load()?;
+            pool_state.check_root_guardian(ctx.accounts.root_guardian.as_ref())?;
+            pool_state
+                .check_transfer_arity(args.nullifiers.len(), args.output_commitments.len())?;
+            require!(
+                ctx.accounts.hook_config.is_some() || !pool_state.hooks_enabled(),
+                PoolError::HookConfigInvalid
+            );
+        }
+        enforce_note_age(
+            &ctx.accounts.pool_state,
+            &ctx.accounts.commitment_tree,
+            &args.public_inputs,
+            1,
+        )?;
+        execute_private_transfer(
+            &ctx.accounts.pool_state,
+            &ctx.accounts.nullifier_set,
+            &ctx.accounts.commitment_tree,
+            &ctx.accounts.note_ledger,
+            &ctx.accounts.verifier_program,
+            &ctx.accounts.verifying_key,
+            ctx.accounts.arity_verifier.as_ref(),
+            ctx.accounts.hook_config.as_ref(),
+            ctx.remaining_accounts,
+            ctx.accounts.payer.as_ref(),
+            ctx.accounts.system_program.as_ref(),
+            &args,
+        )?;
+        record_pool_stats(
+            &*ctx.accounts.pool_state.load()?,
+            ctx.accounts.pool_stats.as_mut(),
+            |stats| stats.record_transfers(1),
+        )
+    }
+
+    /// Atomic two-pool swap: `leg_a` spends notes in pool A and pays the counterparty in
+    /// A, `leg_b` does the same in pool B. Both proofs must end with the field element
+    /// `terms.binding(pool_a, pool_b)`, so neither leg lands without the other at the
+    /// agreed amounts. Remaining accounts hold leg A's nullifier/hook accounts
+    /// (`leg_a_accounts` of them) followed by leg B's.
+    pub fn private_swap<'info>(
+        ctx: Context<'_, '_, '_, 'info, PrivateSwap<'info>>,
+        leg_a: TransferArgs,
+        leg_b: TransferArgs,
+        terms: SwapTerms,
+        leg_a_accounts: u8,
+    ) -> Result<()> {
+        let pool_a = ctx.accounts.pool_state_a.key();
+        let pool_b = ctx.accounts.pool_state_b.key();
+        require_keys_neq!(pool_a, pool_b, PoolError::SwapTermsInvalid);
+        require!(
+            terms.amount_a > 0 && terms.amount_b > 0,
+            PoolError::SwapTermsInvalid
+        );
+        require!(
+            Clock::get()?.slot <= terms.expiry_slot,
+            PoolError::SwapExpired
+        );
+        let binding = terms.binding(pool_a, pool_b);
+        require!(
+            SwapTerms::is_bound(&leg_a.public_inputs, &binding)
+                && SwapTerms::is_bound(&leg_b.public_inputs, &binding),
+            PoolError::SwapTermsInvalid
+        );
+        for (pool_loader, hook_config, root_guardian, leg) in [
+            (
+                &ctx.accounts.pool_state_a,
+                &ctx.accounts.hook_config_a,
+                &ctx.accounts.root_guardian_a,
+                &leg_a,
+            ),
+            (
+                &ctx.accounts.pool_state_b,
+                &ctx.accounts.hook_config_b,
+                &ctx.accounts.root_guardian_b,
+                &leg_b,
+            ),
+        ] {
+            let pool_state = pool_loader.load()?;
+            pool_state.check_root_guardian(root_guardian.as_ref())?;
+            pool_state.check_transfer_arity(leg.nullifiers.len(), leg.output_commitments.len())?;
+            require!(
+                hook_config.is_some() || !pool_state.hooks_enabled(),
+                PoolError::HookConfigInvalid
+            );
+        }
+        require!(
+            leg_a_accounts as usize <= ctx.remaining_accounts.len(),
+            PoolError::SwapTermsInvalid
+        );
+        let (accounts_a, accounts_b) = ctx.remaining_accounts.split_at(leg_a_accounts as usize);
+        // The swap binding is the last field, so the leaf index sits just before it.
+        enforce_note_age(
+            &ctx.accounts.pool_state_a,
+            &ctx.accounts.commitment_tree_a,
+            &leg_a.public_inputs,
+            2,
+        )?;
+        enforce_note_age(
+            &ctx.accounts.pool_state_b,
+            &ctx.accounts.commitment_tree_b,
+            &leg_b.public_inputs,
+            2,
+        )?;
+
+        execute_private_transfer(
+            &ctx.accounts.pool_state_a,
+            &ctx.accounts.nullifier_set_a,
+            &ctx.accounts.commitment_tree_a,
+            &ctx.accounts.note_ledger_a,
+            &ctx.accounts.verifier_program,
+            &ctx.accounts.verifying_key_a,
+            None,
+            ctx.accounts.hook_config_a.as_ref(),
+            accounts_a,
+            ctx.accounts.payer.as_ref(),
+            ctx.accounts.system_program.as_ref(),
+            &leg_a,
+        )?;
+        execute_private_transfer(
+            &ctx.accounts.pool_state_b,
+            &ctx.accounts.nullifier_set_b,
+            &ctx.accounts.commitment_tree_b,
+            &ctx.accounts.note_ledger_b,
+            &ctx.accounts.verifier_program,
+            &ctx.accounts.verifying_key_b,
+            None,
+            ctx.accounts.hook_config_b.as_ref(),
+            accounts_b,
+            ctx.accounts.payer.as_ref(),
+            ctx.accounts.system_program.as_ref(),
+            &leg_b,
+        )?;
+
+        record_pool_stats(
+            &*ctx.accounts.pool_state_a.load()?,
+            ctx.accounts.pool_stats_a.as_mut(),
+            |stats| stats.record_transfers(1),
+        )?;
+        record_pool_stats(
+            &*ctx.accounts.pool_state_b.load()?,
+            ctx.accounts.pool_stats_b.as_mut(),
+            |stats| stats.record_transfers(1),
+        )?;
+        emit!(PTFPrivateSwap {
+            mint_a: ctx.accounts.pool_state_a.load()?.origin_mint,
+            mint_b: ctx.accounts.pool_state_b.load()?.origin_mint,
+            binding,
+        });
+        Ok(())
+    }
+
+    /// Applies several transfers behind one aggregated proof, so the verifier runs once
+    /// for the whole batch. The proof's public inputs must equal
+    /// `BatchTransferArgs::public_inputs_for`; operations then apply in order, each
+    /// taking its nullifier storage accounts from the front of what remains. Hooks are
+    /// not dispatched, so pools with hooks enabled cannot batch.
+    pub fn private_transfer_batch<'info>(
+        ctx: Context<'_, '_, '_, 'info, PrivateTransferBatch<'info>>,
+        args: BatchTransferArgs,
+    ) -> Result<()> {
+        {
+            let pool_state = ctx.accounts.pool_state.load()?;
+            pool_state.check_not_paused()?;
+            pool_state.check_root_guardian(ctx.accounts.root_guardian.as_ref())?;
+            pool_state.check_verifier_program(&ctx.accounts.verifier_program.key())?;
+            require!(
+                pool_state
+                    .features()
+                    .contains(FeatureFlags::from(FEATURE_PRIVATE_TRANSFER_ENABLED)),
+                PoolError::FeatureDisabled,
+            );
+            require!(!pool_state.hooks_enabled(), PoolError::HookConfigInvalid);
+            // The aggregated public inputs carry no leaf indices to age-check.
+            require!(
+                pool_state.min_note_age_leaves == 0,
+                PoolError::FeatureDisabled
+            );
+            require!(
+                args.operations.len() == ctx.accounts.aggregation_verifier.inputs as usize,
+                PoolError::TransferArityInvalid
+            );
+            for operation in &args.operations {
+                pool_state.check_transfer_arity(
+                    operation.nullifiers.len(),
+                    operation.output_commitments.len(),
+                )?;
+                require!(
+                    operation.encrypted_note.len() <= MAX_ENCRYPTED_NOTE_LEN,
+                    PoolError::EncryptedNoteTooLarge
+                );
+            }
+            require!(
+                args.public_inputs == BatchTransferArgs::public_inputs_for(&args.operations),
+                PoolError::InvalidPublicInputs
+            );
+        }
+        invoke_verifier(
+            &ctx.accounts.verifier_program,
+            &ctx.accounts.verifying_key,
+            ctx.accounts.aggregation_verifier.verifying_key_id,
+            args.proof,
+            args.public_inputs,
+        )?;
+
+        record_pool_stats(
+            &*ctx.accounts.pool_state.load()?,
+            ctx.accounts.pool_stats.as_mut(),
+            |stats| stats.record_transfers(args.operations.len() as u64),
+        )?;
+        let mut offset = 0usize;
+        for operation in args.operations {
+            let transfer = TransferArgs {
+                old_root: operation.old_root,
+                new_root: operation.new_root,
+                nullifiers: operation.nullifiers,
+                output_commitments: operation.output_commitments,
+                output_amount_commitments: operation.output_amount_commitments,
+                proof: Vec::new(),
+                public_inputs: Vec::new(),
+                encrypted_note: operation.encrypted_note,
+                output_ciphertexts: Vec::new(),
+            };
+            offset += apply_private_transfer(
+                &ctx.accounts.pool_state,
+                &ctx.accounts.nullifier_set,
+                &ctx.accounts.commitment_tree,
+                &ctx.accounts.note_ledger,
+                None,
+                &ctx.remaining_accounts[offset..],
+                ctx.accounts.payer.as_ref(),
+                ctx.accounts.system_program.as_ref(),
+                &transfer,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Merges many notes into one against the pool's consolidation circuit. Unlike
+    /// `private_transfer` it ignores the pool's transfer arity limits and has no allowance
+    /// or hook handling.
+    pub fn consolidate_notes<'info>(
+        ctx: Context<'_, '_, '_, 'info, ConsolidateNotes<'info>>,
+        args: ConsolidateArgs,
+    ) -> Result<()> {
+        ctx.accounts
+            .pool_state
+            .load()?
+            .check_root_guardian(ctx.accounts.root_guardian.as_ref())?;
+        let transfer = TransferArgs {
+            old_root: args.old_root,
+            new_root: args.new_root,
+            nullifiers: args.nullifiers,
+            output_commitments: vec![args.output_commitment],
+            output_amount_commitments: vec![args.output_amount_commitment],
+            proof: args.proof,
+            public_inputs: args.public_inputs,
+            encrypted_note: args.encrypted_note,
+            output_ciphertexts: Vec::new(),
+        };
+        enforce_note_age(
+            &ctx.accounts.pool_state,
+            &ctx.accounts.commitment_tree,
+            &transfer.public_inputs,
+            1,
+        )?;
+        execute_private_transfer(
+            &ctx.accounts.pool_state,
+            &ctx.accounts.nullifier_set,
+            &ctx.accounts.commitment_tree,
+            &ctx.accounts.note_ledger,
+            &ctx.accounts.verifier_program,
+            &ctx.accounts.verifying_key,
+            Some(&ctx.accounts.consolidation_verifier),
+            None,
+            ctx.remaining_accounts,
+            ctx.accounts.payer.as_ref(),
+            ctx.accounts.system_program.as_ref(),
+            &transfer,
+        )?;
+        record_pool_stats(
+            &*ctx.accounts.pool_state.load()?,
+            ctx.accounts.pool_stats.as_mut(),
+            |stats| stats.record_transfers(1),
+        )
+    }
+
+    pub fn approve_allowance(
+        ctx: Context<ManageAllowance>,
+        args: ApproveAllowanceArgs,
+    ) -> Result<()> {
+        write_allowance(
+            &ctx.accounts.pool_state,
+            &mut ctx.accounts.allowance,
+            ctx.accounts.owner.key(),
+            ctx.accounts.spender.key(),
+            ctx.accounts.origin_mint.key(),
+            ctx.bumps.allowance,
+            args.amount,
+        )
+    }
+
+    pub fn revoke_allowance(ctx: Context<ManageAllowance>) -> Result<()> {
+        write_allowance(
+            &ctx.accounts.pool_state,
+            &mut ctx.accounts.allowance,
+            ctx.accounts.owner.key(),
+            ctx.accounts.spender.key(),
+            ctx.accounts.origin_mint.key(),
+            ctx.bumps.allowance,
+            0,
+        )
+    }
+
+    pub fn transfer_from<'info>(
+        ctx: Context<'_, '_, '_, 'info, TransferFrom<'info>>,
+        args: TransferFromArgs,
+    ) -> Result<()> {
+        require!(args.allowance_amount > 0, PoolError::AllowanceAmountInvalid);
+
+        {
+            let allowance = &mut ctx.accounts.allowance;
+            require_keys_eq!(
+                allowance.pool,
+                ctx.accounts.pool_state.key(),
+                PoolError::AllowancePoolMismatch
+            );
+            require_keys_eq!(
+                allowance.owner,
+                ctx.accounts.allowance_owner.key(),
+                PoolError::AllowanceOwnerMismatch
+            );
+            require_keys_eq!(
+                allowance.spender,
+                ctx.accounts.spender.key(),
+                PoolError::AllowanceSpenderMismatch
+            );
+            let pool_state = ctx.accounts.pool_state.load()?;
+            pool_state.check_root_guardian(ctx.accounts.root_guardian.as_ref())?;
+            require_keys_eq!(
+                allowance.mint,
+                pool_state.origin_mint,
+                PoolError::AllowanceMintMismatch
+            );
+            require!(
+                allowance.amount >= args.allowance_amount,
+                PoolError::AllowanceInsufficient
+            );
+            allowance.amount = allowance
+                .amount
+                .checked_sub(args.allowance_amount)
+                .ok_or(PoolError::AllowanceInsufficient)?;
+            allowance.updated_at = Clock::get()?.unix_timestamp;
+            pool_state.check_transfer_arity(
+                args.transfer.nullifiers.len(),
+                args.transfer.output_commitments.len(),
+            )?;
+            require!(
+                ctx.accounts.hook_config.is_some() || !pool_state.hooks_enabled(),
+                PoolError::HookConfigInvalid
+            );
+            emit!(PTFAllowanceUpdated {
+                mint: allowance.mint,
+                owner: allowance.owner,
+                spender: allowance.spender,
+                amount: allowance.amount,
+            });
+        }
+
+        enforce_note_age(
+            &ctx.accounts.pool_state,
+            &ctx.accounts.commitment_tree,
+            &args.transfer.public_inputs,
+            1,
+        )?;
+        execute_private_transfer(
+            &ctx.accounts.pool_state,
+            &ctx.accounts.nullifier_set,
+            &ctx.accounts.commitment_tree,
+            &ctx.accounts.note_ledger,
+            &ctx.accounts.verifier_program,
+            &ctx.accounts.verifying_key,
+            ctx.accounts.arity_verifier.as_ref(),
+            ctx.accounts.hook_config.as_ref(),
+            ctx.remaining_accounts,
+            ctx.accounts.payer.as_ref(),
+            ctx.accounts.system_program.as_ref(),
+            &args.transfer,
+        )?;
+        record_pool_stats(
+            &*ctx.accounts.pool_state.load()?,
+            ctx.accounts.pool_stats.as_mut(),
+            |stats| stats.record_transfers(1),
+        )
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn execute_private_transfer<'info>(
+    pool_loader: &AccountLoader<'info, PoolState>,
+    nullifier_set_loader: &AccountLoader<'info, NullifierSet>,
+    commitment_tree_loader: &AccountLoader<'info, CommitmentTree>,
+    note_ledger_loader: &AccountLoader<'info, NoteLedger>,
+    verifier_program: &UncheckedAccount<'info>,
+    verifying_key: &Account<'info, VerifyingKeyAccount>,
+    arity_verifier: Option<&Account<'info, ArityVerifier>>,
+    hook_config: Option<&AccountLoader<'info, HookConfig>>,
+    remaining_accounts: &[AccountInfo<'info>],
+    payer: Option<&Signer<'info>>,
+    system_program: Option<&Program<'info, System>>,
+    args: &TransferArgs,
+) -> Result<()> {
+    {
+        let pool_state = pool_loader.load()?;
+        pool_state.check_not_paused()?;
+        require!(
+            args.encrypted_note.len() <= MAX_ENCRYPTED_NOTE_LEN,
+            PoolError::EncryptedNoteTooLarge
+        );
+        args.check_output_ciphertexts()?;
+        pool_state.check_verifier_program(&verifier_program.key())?;
+        let (expected_key, expected_id, expected_hash) = match arity_verifier {
+            Some(arity_verifier) => {
+                require_keys_eq!(
+                    arity_verifier.pool,
+                    pool_loader.key(),
+                    PoolError::VerifierMismatch
+                );
+                require!(
+                    arity_verifier.inputs as usize == args.nullifiers.len()
+                        && arity_verifier.outputs as usize == args.output_commitments.len(),
+                    PoolError::TransferArityInvalid
+                );
+                (
+                    arity_verifier.verifying_key,
+                    arity_verifier.verifying_key_id,
+                    arity_verifier.verifying_key_hash,
+                )
+            }
+            None => pool_state.active_verifying_key(verifying_key.key(), Clock::get()?.slot),
+        };
+        require_keys_eq!(
+            verifying_key.key(),
+            expected_key,
+            PoolError::VerifierMismatch
+        );
+        require!(
+            verifying_key.verifying_key_id == expected_id,
+            PoolError::VerifierMismatch,
+        );
+        require!(
+            verifying_key.hash == expected_hash,
+            PoolError::VerifyingKeyHashMismatch,
+        );
+        require!(
+            pool_state
+                .features()
+                .contains(FeatureFlags::from(FEATURE_PRIVATE_TRANSFER_ENABLED)),
+            PoolError::FeatureDisabled,
+        );
+        invoke_verifier(
+            verifier_program,
+            verifying_key,
+            expected_id,
+            args.proof.clone(),
+            args.public_inputs.clone(),
+        )?;
+    }
+    apply_private_transfer(
+        pool_loader,
+        nullifier_set_loader,
+        commitment_tree_loader,
+        note_ledger_loader,
+        hook_config,
+        remaining_accounts,
+        payer,
+        system_program,
+        args,
+    )
+    .map(|_| ())
+}
+
+/// State transition for a transfer whose proof has already been verified: roots,
+/// nullifiers, tree, ledger, event and post-transfer hook. Returns how many leading
+/// remaining accounts went to nullifier storage and leaf-index records.
+#[allow(clippy::too_many_arguments)]
+fn apply_private_transfer<'info>(
+    pool_loader: &AccountLoader<'info, PoolState>,
+    nullifier_set_loader: &AccountLoader<'info, NullifierSet>,
+    commitment_tree_loader: &AccountLoader<'info, CommitmentTree>,
+    note_ledger_loader: &AccountLoader<'info, NoteLedger>,
+    hook_config: Option<&AccountLoader<'info, HookConfig>>,
+    remaining_accounts: &[AccountInfo<'info>],
+    payer: Option<&Signer<'info>>,
+    system_program: Option<&Program<'info, System>>,
+    args: &TransferArgs,
+) -> Result<usize> {
+    let mut pool_state = pool_loader.load_mut()?;
+    require!(
+        pool_state.is_known_root(&args.old_root, Clock::get()?.slot),
+        PoolError::UnknownRoot,
+    );
+    note_ledger_loader
+        .load()?
+        .check_root_epoch(&pool_state, &args.old_root)?;
+    {
+        let commitment_tree = commitment_tree_loader.load()?;
+        require!(
+            commitment_tree.current_root == args.old_root,
+            PoolError::RootMismatch,
+        );
+    }
+
+    let nullifier_accounts = spend_nullifiers(
+        pool_loader.key(),
+        &pool_state,
+        nullifier_set_loader,
+        remaining_accounts,
+        payer,
+        system_program,
+        &args.nullifiers,
+    )?;
+    let leaf_accounts = if pool_state.leaf_index_enabled() {
+        args.output_commitments.len()
+    } else {
+        0
+    };
+    require!(
+        remaining_accounts.len() >= nullifier_accounts + leaf_accounts,
+        PoolError::LeafRecordMissing
+    );
+    let (leaf_records, hook_accounts) =
+        remaining_accounts[nullifier_accounts..].split_at(leaf_accounts);
+    require!(
+        args.output_commitments.len() == args.output_amount_commitments.len(),
+        PoolError::OutputSetMismatch,
+    );
+    let tree_key = commitment_tree_loader.key();
+    let (new_root, leaf_indices) = {
+        let info = commitment_tree_loader.to_account_info();
+        let mut data = info.try_borrow_mut_data()?;
+        let (commitment_tree, levels) = CommitmentTree::split_data_mut(&mut data)?;
+        commitment_tree.append_many(
+            levels,
+            args.output_commitments.as_slice(),
+            args.output_amount_commitments.as_slice(),
+        )?
+    };
+    if new_root != args.new_root {
+        msg!(
+            "unshield proof new root ({}) differs from computed root ({})",
+            hex::encode(args.new_root),
+            hex::encode(new_root)
+        );
+    }
+    pool_state.push_root(new_root, Clock::get()?.slot);
+
+    if !leaf_records.is_empty() {
+        let payer = payer.ok_or(PoolError::LeafRecordMissing)?;
+        let system_program = system_program.ok_or(PoolError::LeafRecordMissing)?;
+        for ((commitment, leaf_index), info) in args
+            .output_commitments
+            .iter()
+            .zip(leaf_indices.iter())
+            .zip(leaf_records.iter())
+        {
+            LeafIndexRecord::create(
+                info,
+                payer,
+                system_program,
+                LeafIndexRecord {
+                    pool: pool_loader.key(),
+                    commitment: *commitment,
+                    tree: tree_key,
+                    leaf_index: *leaf_index,
+                    bump: 0,
+                },
+            )?;
+        }
+    }
+
+    {
+        let mut note_ledger = note_ledger_loader.load_mut()?;
+        note_ledger.record_transfer(&args.nullifiers, args.output_amount_commitments.as_slice())?;
+    }
+
+    emit!(PTFTransferred {
+        mint: pool_state.origin_mint,
+        inputs: args.nullifiers.clone(),
+        outputs: args.output_commitments.clone(),
+        root: new_root,
+        encrypted_note: args.encrypted_note.clone(),
+        tree: tree_key,
+        leaf_indices,
+        output_ciphertexts: args.output_ciphertexts.clone(),
+    });
+
+    // Consolidation passes no hook config and never dispatches.
+    let hook_enabled = hook_config.is_some() && pool_state.hooks_enabled();
+    let origin_mint = pool_state.origin_mint;
+    let pool_bump = pool_state.bump;
+    drop(pool_state);
+
+    if let (true, Some(hook_config)) = (hook_enabled, hook_config) {
+        let (required_accounts, hook_mode, target_program, post_transfer_enabled) = {
+            let hook_config = hook_config.load()?;
+            (
+                hook_config.required_metas(HookDirection::Shield, &[]),
+                hook_config.mode,
+                hook_config.post_transfer_program_id,
+                hook_config.post_transfer_enabled,
+            )
+        };
+        if post_transfer_enabled && target_program != Pubkey::default() {
+            validate_hook_accounts(&required_accounts, hook_mode, hook_accounts)?;
+            let (metas, infos) = hook_invocation_accounts(
+                hook_config.to_account_info(),
+                pool_loader.to_account_info(),
+                hook_accounts,
+                &required_accounts,
+            )?;
+            let ix = Instruction {
+                program_id: target_program,
+                accounts: metas,
+                data: HookInstruction::PostTransfer(PostTransferHook {
+                    origin_mint,
+                    pool: pool_loader.key(),
+                    nullifiers: args.nullifiers.clone(),
+                    output_commitments: args.output_commitments.clone(),
+                    new_root,
+                })
+                .try_to_vec()?,
+            };
+            let signer_seeds: [&[u8]; 3] = [seeds::POOL, origin_mint.as_ref(), &[pool_bump]];
+            invoke_signed(&ix, &infos, &[&signer_seeds])?;
+
+            emit!(PTFHookPostTransfer {
+                mint: origin_mint,
+                root: new_root,
+            });
+        }
+    }
+    Ok(nullifier_accounts + leaf_accounts)
+}
+
+/// Records spent nullifiers and returns how many leading remaining accounts were consumed
+/// for nullifier storage. Unsharded pools write straight into the legacy set; sharded pools
+/// take the touched shards (plus records for archived ones, see
+/// `insert_sharded_nullifiers`) and PDA-mode pools take one record account per nullifier, in
+/// argument order. Both still consult the legacy set so nullifiers spent before a migration
+/// stay spent.
+fn spend_nullifiers<'info>(
+    pool_key: Pubkey,
+    pool_state: &PoolState,
+    legacy_set: &AccountLoader<'info, NullifierSet>,
+    remaining_accounts: &[AccountInfo<'info>],
+    payer: Option<&Signer<'info>>,
+    system_program: Option<&Program<'info, System>>,
+    nullifiers: &[[u8; 32]],
+) -> Result<usize> {
+    let origin_mint = pool_state.origin_mint;
+    let shard_count = pool_state.nullifier_shard_count;
+
+    if pool_state.nullifier_mode == NULLIFIER_MODE_PDA {
+        require!(
+            remaining_accounts.len() >= nullifiers.len(),
+            PoolError::NullifierRecordMissing
+        );
+        let payer = payer.ok_or(PoolError::NullifierPayerMissing)?;
+        let system_program = system_program.ok_or(PoolError::NullifierPayerMissing)?;
+        let legacy_info = legacy_set.to_account_info();
+        let legacy_data = legacy_info.try_borrow_data()?;
+        let (legacy, legacy_overflow) = NullifierSet::split_data(&legacy_data)?;
+        let slot = Clock::get()?.slot;
+        for (nullifier, info) in nullifiers.iter().zip(remaining_accounts.iter()) {
+            require!(
+                !legacy.contains(legacy_overflow, nullifier),
+                PoolError::NullifierReuse
+            );
+            NullifierRecord::create(info, payer, system_program, pool_key, *nullifier, slot)?;
+            emit!(PTFNullifierUsed {
+                mint: origin_mint,
+                nullifier: *nullifier,
+            });
+        }
+        return Ok(nullifiers.len());
+    }
+
+    if shard_count == 0 {
+        let legacy_info = legacy_set.to_account_info();
+        let mut legacy_data = legacy_info.try_borrow_mut_data()?;
+        let (nullifier_set, overflow) = NullifierSet::split_data_mut(&mut legacy_data)?;
+        for nullifier in nullifiers {
+            nullifier_set.insert(overflow, *nullifier)?;
+            emit!(PTFNullifierUsed {
+                mint: origin_mint,
+                nullifier: *nullifier,
+            });
+        }
+        return Ok(0);
+    }
+
+    {
+        let legacy_info = legacy_set.to_account_info();
+        let legacy_data = legacy_info.try_borrow_data()?;
+        let (legacy, legacy_overflow) = NullifierSet::split_data(&legacy_data)?;
+        for nullifier in nullifiers {
+            require!(
+                !legacy.contains(legacy_overflow, nullifier),
+                PoolError::NullifierReuse
+            );
+        }
+    }
+    let used = insert_sharded_nullifiers(
+        pool_key,
+        pool_state,
+        remaining_accounts,
+        payer,
+        system_program,
+        nullifiers,
+    )?;
+    for nullifier in nullifiers {
+        emit!(PTFNullifierUsed {
+            mint: origin_mint,
+            nullifier: *nullifier,
+        });
+    }
+    Ok(used)
+}
+
+/// Writes `nullifiers` into their shards, which lead `remaining_accounts` in ascending
+/// order. Nullifiers routed to an archived shard are checked against what it still holds
+/// and get a `NullifierRecord` instead; those record accounts follow the shards, one per
+/// such nullifier in argument order. Returns how many accounts were consumed.
+fn insert_sharded_nullifiers<'info>(
+    pool_key: Pubkey,
+    pool_state: &PoolState,
+    remaining_accounts: &[AccountInfo<'info>],
+    payer: Option<&Signer<'info>>,
+    system_program: Option<&Program<'info, System>>,
+    nullifiers: &[[u8; 32]],
+) -> Result<usize> {
+    let origin_mint = pool_state.origin_mint;
+    let shard_count = pool_state.nullifier_shard_count;
+    let shards = NullifierSet::required_shards(nullifiers, shard_count);
+    require!(
+        remaining_accounts.len() >= shards.len(),
+        PoolError::NullifierShardMissing
+    );
+    let mut archived_shards = Vec::new();
+    for (shard, info) in shards.iter().zip(remaining_accounts.iter()) {
+        let mut shard_data = NullifierSet::load_shard_data(info, pool_key, origin_mint, *shard)?;
+        let (shard_set, shard_overflow) = NullifierSet::split_data_mut(&mut shard_data)?;
+        for nullifier in nullifiers
+            .iter()
+            .filter(|nullifier| NullifierSet::shard_index(nullifier, shard_count) == *shard)
+        {
+            if shard_set.archived == 0 {
+                shard_set.insert(shard_overflow, *nullifier)?;
+            } else {
+                require!(
+                    !shard_set.contains(shard_overflow, nullifier),
+                    PoolError::NullifierReuse
+                );
+            }
+        }
+        if shard_set.archived != 0 {
+            archived_shards.push(*shard);
+        }
+    }
+
+    let archived: Vec<&[u8; 32]> = nullifiers
+        .iter()
+        .filter(|nullifier| {
+            archived_shards.contains(&NullifierSet::shard_index(nullifier, shard_count))
+        })
+        .collect();
+    if archived.is_empty() {
+        return Ok(shards.len());
+    }
+    let record_accounts = &remaining_accounts[shards.len()..];
+    require!(
+        record_accounts.len() >= archived.len(),
+        PoolError::NullifierRecordMissing
+    );
+    let payer = payer.ok_or(PoolError::NullifierPayerMissing)?;
+    let system_program = system_program.ok_or(PoolError::NullifierPayerMissing)?;
+    let slot = Clock::get()?.slot;
+    for (nullifier, info) in archived.iter().zip(record_accounts.iter()) {
+        NullifierRecord::create(info, payer, system_program, pool_key, **nullifier, slot)?;
+    }
+    Ok(shards.len() + archived.len())
+}
+
+/// Calls `verify_groth16` on whichever allowlisted verifier the caller passed. Built by
+/// hand so the instruction targets that program rather than the legacy verifier's ID.
+fn invoke_verifier<'info>(
+    verifier_program: &UncheckedAccount<'info>,
+    verifying_key: &Account<'info, VerifyingKeyAccount>,
+    verifying_key_id: [u8; 32],
+    proof: Vec<u8>,
+    public_inputs: Vec<u8>,
+) -> Result<()> {
+    let ix = Instruction {
+        program_id: verifier_program.key(),
+        accounts: vec![AccountMeta::new_readonly(verifying_key.key(), false)],
+        data: ptf_verifier_groth16::instruction::VerifyGroth16 {
+            verifying_key_id,
+            proof,
+            public_inputs,
+        }
+        .data(),
+    };
+    invoke(
+        &ix,
+        &[
+            verifying_key.to_account_info(),
+            verifier_program.to_account_info(),
+        ],
+    )?;
+    Ok(())
+}
+
+fn write_allowance(
+    pool_loader: &AccountLoader<PoolState>,
+    allowance_account: &mut Account<AllowanceAccount>,
+    owner: Pubkey,
+    spender: Pubkey,
+    mint: Pubkey,
+    bump: u8,
+    amount: u64,
+) -> Result<()> {
+    let pool_state = pool_loader.load()?;
+    let origin_mint = pool_state.origin_mint;
+    let pool_key = pool_loader.key();
+    require_keys_eq!(origin_mint, mint, PoolError::OriginMintMismatch);
+    drop(pool_state);
+
+    if allowance_account.pool == Pubkey::default() {
+        allowance_account.pool = pool_key;
+        allowance_account.owner = owner;
+        allowance_account.spender = spender;
+        allowance_account.mint = mint;
+        allowance_account.bump = bump;
+    } else {
+        require_keys_eq!(allowance_account.pool, pool_key, PoolError::AllowancePoolMismatch);
+        require_keys_eq!(allowance_account.owner, owner, PoolError::AllowanceOwnerMismatch);
+        require_keys_eq!(allowance_account.spender, spender, PoolError::AllowanceSpenderMismatch);
+        require_keys_eq!(allowance_account.mint, mint, PoolError::AllowanceMintMismatch);
+    }
+    allowance_account.amount = amount;
+    allowance_account.updated_at = Clock::get()?.unix_timestamp;
+    emit!(PTFAllowanceUpdated {
+        mint,
+        owner,
+        spender,
+        amount,
+    });
+    Ok(())
+}
+
+fn process_shield<'info>(
+    ctx: Context<'_, '_, '_, 'info, Shield<'info>>,
+    args: ShieldArgs,
+    source: ShieldSource,
+    caller_program: Option<Pubkey>,
+) -> Result<()> {
+    let pool_loader = &ctx.accounts.pool_state;
+    ensure_mint_active(&ctx.accounts.mint_mapping)?;
+    let depositor = match ctx.accounts.depositor.as_ref() {
+        Some(depositor) => depositor.to_account_info(),
+        None => ctx.accounts.payer.to_account_info(),
+    };
+    let depositor_key = depositor.key();
+    {
+        let (hook_enabled, pool_bump, origin_mint) = {
+            let pool_state = pool_loader.load()?;
+            pool_state.check_not_paused()?;
+            let hook_enabled = pool_state
+                .features()
+                .contains(FeatureFlags::from(FEATURE_HOOKS_ENABLED))
+                && pool_state.hook_config_present;
+            (hook_enabled, pool_state.bump, pool_state.origin_mint)
+        };
+        if hook_enabled {
+            let (required_accounts, hook_mode, target_program, pre_shield_enabled) = {
+                let hook_config = ctx.accounts.hook_config.load()?;
+                (
+                    hook_config.required_metas(HookDirection::Shield, &[depositor_key]),
+                    hook_config.mode,
+                    hook_config.pre_hook_program_id,
+                    hook_config.pre_shield_enabled,
+                )
+            };
+            if pre_shield_enabled && target_program != Pubkey::default() {
+                validate_hook_accounts(&required_accounts, hook_mode, ctx.remaining_accounts)?;
+                let (metas, infos) = hook_invocation_accounts(
+                    ctx.accounts.hook_config.to_account_info(),
+                    ctx.accounts.pool_state.to_account_info(),
+                    ctx.remaining_accounts,
+                    &required_accounts,
+                )?;
+                let ix = Instruction {
+                    program_id: target_program,
+                    accounts: metas,
+                    data: HookInstruction::PreShield(PreShieldHook {
+                        origin_mint,
+                        pool: pool_loader.key(),
+                        depositor: depositor_key,
+                        amount: args.amount,
+                    })
+                    .try_to_vec()?,
+                };
+                let signer_seeds: [&[u8]; 3] = [seeds::POOL, origin_mint.as_ref(), &[pool_bump]];
+                invoke_signed(&ix, &infos, &[&signer_seeds])?;
+
+                emit!(PTFHookPreShield {
+                    mint: origin_mint,
+                    depositor: depositor_key,
+                });
+            }
+        }
+    }
+    let mut pool_state = pool_loader.load_mut()?;
+    pool_state.check_denomination(args.amount)?;
+    require!(
+        args.encrypted_note.len() <= MAX_ENCRYPTED_NOTE_LEN,
+        PoolError::EncryptedNoteTooLarge
+    );
+    require!(
+        pool_state.pending_shield.is_inactive(),
+        PoolError::PendingShieldInFlight
+    );
+    let claim_bump = ctx.bumps.shield_claim;
+    {
+        let shield_claim = &mut ctx.accounts.shield_claim;
+        if shield_claim.pool == Pubkey::default() {
+            shield_claim.pool = pool_loader.key();
+            shield_claim.bump = claim_bump;
+        } else {
+            require_keys_eq!(
+                shield_claim.pool,
+                pool_loader.key(),
+                PoolError::ShieldClaimMismatch
+            );
+        }
+        require!(!shield_claim.is_active(), PoolError::PendingShieldInFlight);
+    }
+    ensure_protocol_allows(
+        &ctx.accounts.protocol_config,
+        &pool_state,
+        &ctx.accounts.verifier_program.key(),
+    )?;
+    pool_state.check_verifier_program(&ctx.accounts.verifier_program.key())?;
+    let (expected_key, expected_id, expected_hash) =
+        pool_state.active_verifying_key(ctx.accounts.verifying_key.key(), Clock::get()?.slot);
+    require_keys_eq!(
+        ctx.accounts.verifying_key.key(),
+        expected_key,
+        PoolError::VerifierMismatch,
+    );
+    require!(
+        ctx.accounts.verifying_key.verifying_key_id == expected_id,
+        PoolError::VerifierMismatch,
+    );
+    require!(
+        ctx.accounts.verifying_key.hash == expected_hash,
+        PoolError::VerifyingKeyHashMismatch,
+    );
+    require_keys_eq!(
+        ctx.accounts.vault_state.key(),
+        pool_state.vault,
+        PoolError::MismatchedVaultAuthority,
+    );
+    require_keys_eq!(
+        ctx.accounts.vault_state.pool_authority,
+        pool_loader.key(),
+        PoolError::MismatchedVaultAuthority,
+    );
+    require_keys_eq!(
+        ctx.accounts.vault_token_account.owner,
+        pool_state.vault,
+        PoolError::VaultTokenAccountMismatch,
+    );
+    require_keys_eq!(
+        ctx.accounts.vault_token_account.mint,
+        pool_state.origin_mint,
+        PoolError::OriginMintMismatch,
+    );
+    require_keys_eq!(
+        ctx.accounts.origin_mint.key(),
+        pool_state.origin_mint,
+        PoolError::OriginMintMismatch,
+    );
+    require_keys_eq!(
+        ctx.accounts.depositor_token_account.owner,
+        depositor_key,
+        PoolError::InvalidDepositorAccount,
+    );
+    check_shield_source_mint(
+        &pool_state,
+        source,
+        &ctx.accounts.depositor_token_account.mint,
+    )?;
+    require_keys_eq!(
+        ctx.accounts.commitment_tree.key(),
+        pool_state.commitment_tree,
+        PoolError::CommitmentTreeMismatch,
+    );
+
+    let commitment_tree_data = ctx.accounts.commitment_tree.load()?;
+    require!(
+        commitment_tree_data.current_root == pool_state.current_root,
+        PoolError::RootMismatch,
+    );
+
+    if pool_state.twin_mint_enabled {
+        let twin_mint = ctx
+            .accounts
+            .twin_mint
+            .as_ref()
+            .ok_or(PoolError::TwinMintNotConfigured)?;
+        require_keys_eq!(
+            twin_mint.key(),
+            pool_state.twin_mint,
+            PoolError::TwinMintMismatch,
+        );
+    }
+
+    // Finalisation requires the tree to still sit at `old_root`, so nothing else can
+    // be appended before this note and its index is known now.
+    let (leaf_tree, leaf_index) = (
+        ctx.accounts.commitment_tree.key(),
+        commitment_tree_data.next_index,
+    );
+
+    let (old_root_bytes, new_root_bytes, commitment_bytes) =
+        validate_shield_public_inputs(&pool_state, pool_loader.key(), &args)?;
+    let mut old_root_be = old_root_bytes;
+    old_root_be.reverse();
+    let mut new_root_be = new_root_bytes;
+    new_root_be.reverse();
+
+    require!(
+        old_root_bytes == pool_state.current_root,
+        PoolError::RootMismatch
+    );
+
+    invoke_verifier(
+        &ctx.accounts.verifier_program,
+        &ctx.accounts.verifying_key,
+        expected_id,
+        args.proof.clone(),
+        args.public_inputs.clone(),
+    )?;
+
+    let stats_fee = match source {
+        ShieldSource::Twin => 0,
+        ShieldSource::Origin | ShieldSource::Prefunded => pool_state.shield_fee(args.amount)?,
+    };
+    record_pool_stats(&pool_state, ctx.accounts.pool_stats.as_mut(), |stats| {
+        stats.record_shield(args.amount, stats_fee)
+    })?;
+    let mut referral_share = 0;
+    match source {
+        ShieldSource::Origin => {
+            let deposit_accounts = ptf_vault::cpi::accounts::Deposit {
+                vault_state: ctx.accounts.vault_state.to_account_info(),
+                vault_token_account: ctx.accounts.vault_token_account.to_account_info(),
+                origin_mint: ctx.accounts.origin_mint.to_account_info(),
+                depositor: depositor.clone(),
+                depositor_token_account: ctx.accounts.depositor_token_account.to_account_info(),
+                token_program: ctx.accounts.token_program.to_account_info(),
+            };
+            let deposit_ctx = CpiContext::new(
+                ctx.accounts.vault_program.to_account_info(),
+                deposit_accounts,
+            );
+            let fee = pool_state.shield_fee(args.amount)?;
+            let deposit_amount = args
+                .amount
+                .checked_add(fee)
+                .ok_or(PoolError::AmountOverflow)?;
+            let locked_after = ctx
+                .accounts
+                .vault_token_account
+                .amount
+                .checked_add(deposit_amount)
+                .ok_or(PoolError::AmountOverflow)?;
+            record_shield_inflow(
+                &pool_state,
+                ctx.accounts.rate_limiter.as_mut(),
+                args.amount,
+                locked_after,
+            )?;
+            ptf_vault::cpi::deposit(deposit_ctx, deposit_amount)?;
+            // Token-2022 transfer fees (or any other skim) would leave the vault short of
+            // what the note commits to, so only exact deposits are accepted.
+            ctx.accounts.vault_token_account.reload()?;
+            require!(
+                ctx.accounts.vault_token_account.amount == locked_after,
+                PoolError::DepositShortfall
+            );
+            referral_share = credit_shield_fee(
+                &mut pool_state,
+                pool_loader.key(),
+                args.referrer,
+                ctx.accounts.referral_accrual.as_mut(),
+                fee,
+            )?;
+        }
+        ShieldSource::Prefunded => {
+            let fee = pool_state.shield_fee(args.amount)?;
+            let deposit_amount = args
+                .amount
+                .checked_add(fee)
+                .ok_or(PoolError::AmountOverflow)?;
+            let expected = {
+                let note_ledger = ctx.accounts.note_ledger.load()?;
+                let twin_supply =
+                    resolve_twin_supply(&pool_state, ctx.accounts.twin_mint.as_ref())?;
+                expected_vault_balance(&pool_state, &note_ledger, twin_supply)?
+            };
+            let locked = ctx.accounts.vault_token_account.amount;
+            check_prefunded_deposit(locked, expected, deposit_amount)?;
+            record_shield_inflow(
+                &pool_state,
+                ctx.accounts.rate_limiter.as_mut(),
+                args.amount,
+                locked,
+            )?;
+            referral_share = credit_shield_fee(
+                &mut pool_state,
+                pool_loader.key(),
+                args.referrer,
+                ctx.accounts.referral_accrual.as_mut(),
+                fee,
+            )?;
+        }
+        ShieldSource::Twin => {
+            // The factory needs the pool PDA as a signer, so release the borrow first.
+            let (pool_bump, origin_mint) = (pool_state.bump, pool_state.origin_mint);
+            drop(pool_state);
+            let twin_mint = ctx
+                .accounts
+                .twin_mint
+                .as_ref()
+                .ok_or(PoolError::TwinMintNotConfigured)?;
+            let mint_mapping = &ctx.accounts.mint_mapping;
+            ensure_twin_token_program(mint_mapping, &ctx.accounts.token_program.key())?;
+            let (factory_state, factory_program) = match (
+                ctx.accounts.factory_state.as_ref(),
+                ctx.accounts.factory_program.as_ref(),
+            ) {
+                (Some(state), Some(program)) => (state, program),
+                _ => return err!(PoolError::TwinMintNotConfigured),
+            };
+            let bump_seed = [pool_bump];
+            let signer_seeds: [&[u8]; 3] = [seeds::POOL, origin_mint.as_ref(), &bump_seed];
+            let signer = &[&signer_seeds[..]];
+            let burn_ctx = CpiContext::new_with_signer(
+                factory_program.to_account_info(),
+                ptf_factory::cpi::accounts::BurnPtkn {
+                    factory_state: factory_state.to_account_info(),
+                    mint_mapping: mint_mapping.to_account_info(),
+                    pool_authority: pool_loader.to_account_info(),
+                    ptkn_mint: twin_mint.to_account_info(),
+                    source_token_account: ctx.accounts.depositor_token_account.to_account_info(),
+                    owner: depositor.clone(),
+                    token_program: ctx.accounts.token_program.to_account_info(),
+                },
+                signer,
+            );
+            ptf_factory::cpi::burn_ptkn(burn_ctx, args.amount)?;
+            pool_state = pool_loader.load_mut()?;
+        }
+    }
+
+    pool_state.pending_shield = PendingShield {
+        active: 1,
+        old_root: old_root_bytes,
+        new_root: new_root_bytes,
+        commitment: commitment_bytes,
+        amount_commit: args.amount_commit,
+        amount: args.amount,
+        depositor: depositor_key,
+        next_index: leaf_index,
+    };
+    ctx.accounts.shield_claim.activate(
+        pool_loader.key(),
+        depositor_key,
+        commitment_bytes,
+        args.amount_commit,
+        old_root_bytes,
+        new_root_bytes,
+        args.amount,
+        leaf_index,
+        claim_bump,
+    );
+    emit!(PTFShielded {
+        mint: pool_state.origin_mint,
+        depositor: depositor_key,
+        commitment: commitment_bytes,
+        root: new_root_bytes,
+        amount_commit: args.amount_commit,
+        encrypted_note: args.encrypted_note.clone(),
+        tree: leaf_tree,
+        leaf_index,
+    });
+    if let Some(caller_program) = caller_program {
+        emit!(PTFShieldedViaCpi {
+            mint: pool_state.origin_mint,
+            depositor: depositor_key,
+            caller_program,
+            commitment: commitment_bytes,
+        });
+    }
+    if let Some(referrer) = args.referrer {
+        emit!(PTFShieldReferred {
+            mint: pool_state.origin_mint,
+            depositor: depositor_key,
+            referrer,
+            commitment: commitment_bytes,
+            fee_share: referral_share,
+        });
+    }
+    if pool_state.leaf_index_enabled() {
+        let leaf_record = ctx
+            .accounts
+            .leaf_record
+            .as_ref()
+            .ok_or(PoolError::LeafRecordMissing)?;
+        LeafIndexRecord::create(
+            leaf_record,
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+            LeafIndexRecord {
+                pool: pool_loader.key(),
+                commitment: commitment_bytes,
+                tree: leaf_tree,
+                leaf_index,
+                bump: 0,
+            },
+        )?;
+    }
+
+    fn is_finalize_ix(ix: &Instruction, pool_key: Pubkey) -> bool {
+        ix.program_id == crate::ID
+            && ix.data.len() >= 8
+            && ix.data[..8] == instruction_discriminator("shield_finalize_ledger")
+            && ix.accounts.first().map(|meta| meta.pubkey) == Some(pool_key)
+    }
+
+    let ix_sysvar = ctx.accounts.instructions.to_account_info();
+    let mut finalize_found = false;
+
+    if let Ok(current_index) = load_current_index_checked(&ix_sysvar) {
+        let mut search_index = current_index as usize + 1;
+        loop {
+            match load_instruction_at_checked(search_index, &ix_sysvar) {
+                Ok(ix) => {
+                    if is_finalize_ix(&ix, pool_loader.key()) {
+                        finalize_found = true;
+                        break;
+                    }
+                    search_index += 1;
+                }
+                Err(_) => break,
+            }
+        }
+    }
+
+    if !finalize_found {
+        let mut search_index = 0usize;
+        loop {
+            match load_instruction_at_checked(search_index, &ix_sysvar) {
+                Ok(ix) => {
+                    if is_finalize_ix(&ix, pool_loader.key()) {
+                        finalize_found = true;
+                        break;
+                    }
+                    search_index += 1;
+                }
+                Err(_) => break,
+            }
+        }
+    }
+
+    if !finalize_found {
+        msg!("shield finalize instruction not detected; skipping enforcement");
+    }
+
+    Ok(())
+}
+fn process_unshield<'info>(
+    ctx: Context<'_, '_, '_, 'info, Unshield<'info>>,
+    args: UnshieldManyArgs,
+    mode: UnshieldMode,
+    emergency: bool,
+    escrow_recipient: Option<Pubkey>,
+) -> Result<()> {
+    let pool_loader = &ctx.accounts.pool_state;
+    let mut pool_state = pool_loader.load_mut()?;
+    // Emergency exits stay open for paused pools and frozen mints.
+    if !emergency {
+        pool_state.check_not_paused()?;
+        ensure_mint_active(&ctx.accounts.mint_mapping)?;
+    }
+    #[cfg(not(feature = "lightweight"))]
+    let mut should_enforce_invariant = false;
+    #[cfg(not(feature = "lightweight"))]
+    let mut note_ledger = ctx.accounts.note_ledger.load_mut()?;
+    #[cfg(feature = "lightweight")]
+    let _note_ledger = &ctx.accounts.note_ledger;
+    let origin_mint = pool_state.origin_mint;
+
+    if emergency {
+        // Exits run under the verifier pinned by `enable_emergency_exit`, whatever has
+        // happened to the pool's verifier or the protocol allowlist since.
+        require!(
+            !ctx.accounts.protocol_config.kill_switch,
+            PoolError::ProtocolHalted
+        );
+        require_keys_eq!(
+            ctx.accounts.verifier_program.key(),
+            pool_state.emergency_verifier_program,
+            PoolError::VerifierMismatch
+        );
+    } else {
+        ensure_protocol_allows(
+            &ctx.accounts.protocol_config,
+            &pool_state,
+            &ctx.accounts.verifier_program.key(),
+        )?;
+        pool_state.check_verifier_program(&ctx.accounts.verifier_program.key())?;
+    }
+    // Time-locked notes only open under the scheduled circuit.
+    let (expected_key, expected_id, expected_hash) = match args.unlock_timestamp {
+        Some(_) => {
+            let scheduled = ctx
+                .accounts
+                .scheduled_verifier
+                .as_ref()
+                .ok_or(PoolError::ScheduledVerifierMissing)?;
+            (
+                scheduled.verifying_key,
+                scheduled.verifying_key_id,
+                scheduled.verifying_key_hash,
+            )
+        }
+        None if emergency => (
+            pool_state.emergency_verifying_key,
+            pool_state.emergency_verifying_key_id,
+            pool_state.emergency_verifying_key_hash,
+        ),
+        None => {
+            pool_state.active_verifying_key(ctx.accounts.verifying_key.key(), Clock::get()?.slot)
+        }
+    };
+    require_keys_eq!(
+        ctx.accounts.verifying_key.key(),
+        expected_key,
+        PoolError::VerifierMismatch,
+    );
+    require!(
+        ctx.accounts.verifying_key.verifying_key_id == expected_id,
+        PoolError::VerifierMismatch,
+    );
+    require!(
+        ctx.accounts.verifying_key.hash == expected_hash,
+        PoolError::VerifyingKeyHashMismatch,
+    );
+    require_keys_eq!(
+        ctx.accounts.vault_state.key(),
+        pool_state.vault,
+        PoolError::MismatchedVaultAuthority,
+    );
+    require_keys_eq!(
+        ctx.accounts.vault_state.pool_authority,
+        pool_loader.key(),
+        PoolError::MismatchedVaultAuthority,
+    );
+    require_keys_eq!(
+        ctx.accounts.vault_state.origin_mint,
+        origin_mint,
+        PoolError::OriginMintMismatch,
+    );
+    require_keys_eq!(
+        ctx.accounts.vault_token_account.owner,
+        pool_state.vault,
+        PoolError::VaultTokenAccountMismatch,
+    );
+    require_keys_eq!(
+        ctx.accounts.vault_token_account.mint,
+        origin_mint,
+        PoolError::OriginMintMismatch,
+    );
+    require_keys_eq!(
+        ctx.accounts.commitment_tree.key(),
+        pool_state.commitment_tree,
+        PoolError::CommitmentTreeMismatch,
+    );
+
+    if pool_state.twin_mint_enabled {
+        let twin_mint = ctx
+            .accounts
+            .twin_mint
+            .as_ref()
+            .ok_or(PoolError::TwinMintNotConfigured)?;
+        require_keys_eq!(
+            twin_mint.key(),
+            pool_state.twin_mint,
+            PoolError::TwinMintMismatch,
+        );
+    }
+
+    if emergency {
+        check_emergency_exit(&pool_state, &args)?;
+    } else {
+        require!(
+            pool_state.is_known_root(&args.old_root, Clock::get()?.slot),
+            PoolError::UnknownRoot,
+        );
+        #[cfg(not(feature = "lightweight"))]
+        note_ledger.check_root_epoch(&pool_state, &args.old_root)?;
+        #[cfg(feature = "lightweight")]
+        ctx.accounts
+            .note_ledger
+            .load()?
+            .check_root_epoch(&pool_state, &args.old_root)?;
+        #[cfg(not(feature = "lightweight"))]
+        {
+            let commitment_tree = ctx.accounts.commitment_tree.load()?;
+            require!(
+                commitment_tree.current_root == args.old_root,
+                PoolError::RootMismatch,
+            );
+        }
+    }
+    require!(
+        args.output_commitments.len() == args.output_amount_commitments.len(),
+        PoolError::OutputSetMismatch,
+    );
+    require_keys_eq!(
+        ctx.accounts.mint_mapping.origin_mint,
+        origin_mint,
+        PoolError::OriginMintMismatch,
+    );
+
+    let recipient_count = args.amounts.len();
+    require!(
+        (1..=MAX_UNSHIELD_RECIPIENTS).contains(&recipient_count)
+            && ctx.remaining_accounts.len() >= recipient_count - 1,
+        PoolError::UnshieldRecipientsInvalid,
+    );
+    let (extra_destinations, storage_and_hook_accounts) =
+        ctx.remaining_accounts.split_at(recipient_count - 1);
+    // Native exits release wSOL into a pool-owned account created for this instruction
+    // and close it to the system account the proof binds as the recipient.
+    let native_destination = ctx.accounts.native_destination.as_ref();
+    if let Some(native_destination) = native_destination {
+        check_native_unwrap(
+            mode,
+            recipient_count,
+            origin_mint,
+            args.create_destination_ata,
+        )?;
+        create_native_unwrap_account(ctx.accounts, native_destination)?;
+    }
+    if args.create_destination_ata {
+        create_destination_ata(ctx.accounts)?;
+    }
+    let first_destination = {
+        let info = ctx.accounts.destination_token_account.to_account_info();
+        require_keys_eq!(
+            *info.owner,
+            ctx.accounts.token_program.key(),
+            PoolError::UnshieldRecipientsInvalid
+        );
+        let data = info.try_borrow_data()?;
+        TokenAccount::try_deserialize(&mut &data[..])?
+    };
+    // (token account, owner, mint) per recipient, in `args.amounts` order.
+    let mut destinations = Vec::with_capacity(recipient_count);
+    let first_owner = match native_destination {
+        Some(native_destination) => native_destination.key(),
+        None => first_destination.owner,
+    };
+    destinations.push((
+        ctx.accounts.destination_token_account.to_account_info(),
+        first_owner,
+        first_destination.mint,
+    ));
+    for info in extra_destinations {
+        require!(info.is_writable, PoolError::UnshieldRecipientsInvalid);
+        require_keys_eq!(
+            *info.owner,
+            ctx.accounts.token_program.key(),
+            PoolError::UnshieldRecipientsInvalid
+        );
+        let token_account = TokenAccount::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+        destinations.push((info.clone(), token_account.owner, token_account.mint));
+    }
+    let destination_owners: Vec<Pubkey> = destinations.iter().map(|(_, owner, _)| *owner).collect();
+    // An escrow PDA is off-curve by construction; compliance screens who it pays out to.
+    for owner in &destination_owners {
+        check_destination_owner(
+            owner,
+            args.allow_owner_off_curve || escrow_recipient.is_some(),
+        )?;
+    }
+    let screened_owners = match escrow_recipient {
+        Some(recipient) => vec![recipient],
+        None => destination_owners.clone(),
+    };
+    check_destination_allowlist(
+        &pool_state,
+        ctx.accounts.destination_allowlist.as_ref(),
+        &screened_owners,
+    )?;
+
+    invoke_verifier(
+        &ctx.accounts.verifier_program,
+        &ctx.accounts.verifying_key,
+        expected_id,
+        args.proof.clone(),
+        args.public_inputs.clone(),
+    )?;
+
+    let pool_account_key = pool_loader.key();
+    // Emergency exits push no root, so they never wait on the guardian.
+    if !emergency {
+        pool_state.check_root_guardian(ctx.accounts.root_guardian.as_ref())?;
+    }
+    // Emergency exits prove against the frozen root and skip the note-age policy.
+    let leaf_count = if emergency || pool_state.min_note_age_leaves == 0 {
+        None
+    } else {
+        Some(ctx.accounts.commitment_tree.load()?.next_index)
+    };
+    let fee = validate_unshield_public_inputs(
+        &pool_state,
+        pool_account_key,
+        &args,
+        mode,
+        &destination_owners,
+        ctx.accounts.mint_mapping.decimals,
+        leaf_count,
+    )?;
+    check_unlock_timestamp(args.unlock_timestamp, Clock::get()?.unix_timestamp)?;
+    let total_amount = args
+        .amounts
+        .iter()
+        .try_fold(0u64, |total, amount| total.checked_add(*amount))
+        .ok_or(PoolError::AmountOverflow)?;
+    if !emergency && pool_state.rate_limiter != Pubkey::default() {
+        let limiter = ctx
+            .accounts
+            .rate_limiter
+            .as_mut()
+            .ok_or(PoolError::RateLimiterMismatch)?;
+        require_keys_eq!(
+            limiter.key(),
+            pool_state.rate_limiter,
+            PoolError::RateLimiterMismatch
+        );
+        limiter.record_outflow(total_amount, Clock::get()?.slot)?;
+    }
+    record_pool_stats(&pool_state, ctx.accounts.pool_stats.as_mut(), |stats| {
+        stats.record_unshield(total_amount, fee)
+    })?;
+    let total_spent = total_amount
+        .checked_add(fee)
+        .ok_or(PoolError::AmountOverflow)?;
+    #[cfg(not(feature = "lightweight"))]
+    note_ledger.ensure_capacity(total_spent)?;
+    #[cfg(feature = "lightweight")]
+    let _ = total_spent;
+
+    let nullifier_accounts_used = spend_nullifiers(
+        pool_account_key,
+        &pool_state,
+        &ctx.accounts.nullifier_set,
+        storage_and_hook_accounts,
+        ctx.accounts.payer.as_ref(),
+        ctx.accounts.system_program.as_ref(),
+        &args.nullifiers,
+    )?;
+    let hook_accounts = &storage_and_hook_accounts[nullifier_accounts_used..];
+
+    let mut change_leaf_indices: Vec<u64> = Vec::new();
+    #[cfg(not(feature = "lightweight"))]
+    {
+        if !emergency {
+            let (new_root, output_indices) = {
+                let info = ctx.accounts.commitment_tree.to_account_info();
+                let mut data = info.try_borrow_mut_data()?;
+                let (commitment_tree, levels) = CommitmentTree::split_data_mut(&mut data)?;
+                commitment_tree.append_many(
+                    levels,
+                    args.output_commitments.as_slice(),
+                    args.output_amount_commitments.as_slice(),
+                )?
+            };
+            if new_root != args.new_root {
+                msg!(
+                    "unshield proof new root ({}) differs from computed root ({})",
+                    hex::encode(args.new_root),
+                    hex::encode(new_root)
+                );
+            }
+            pool_state.push_root(new_root, Clock::get()?.slot);
+            change_leaf_indices = output_indices;
+        }
+
+        note_ledger.record_unshield(
+            total_spent,
+            &args.nullifiers,
+            &args.output_amount_commitments,
+        )?;
+        should_enforce_invariant = pool_state.invariant_checks_enabled()
+            && note_ledger.should_enforce_invariant(total_spent);
+    }
+
+    #[cfg(feature = "lightweight")]
+    if !emergency {
+        pool_state.push_root(args.new_root, Clock::get()?.slot);
+    }
+    // Twin payouts leave the origin tokens in the vault, so their fee is tracked apart
+    // from `protocol_fees` and withdrawn as pTKN rather than released.
+    match mode {
+        UnshieldMode::Origin => {
+            pool_state.protocol_fees = pool_state
+                .protocol_fees
+                .checked_add(u128::from(fee))
+                .ok_or(PoolError::AmountOverflow)?;
+        }
+        UnshieldMode::Twin => {
+            pool_state.twin_protocol_fees = pool_state
+                .twin_protocol_fees
+                .checked_add(u128::from(fee))
+                .ok_or(PoolError::AmountOverflow)?;
+        }
+    }
+
+    let pool_bump = pool_state.bump;
+    let twin_mint_key = pool_state.twin_mint;
+    let twin_mint_enabled = pool_state.twin_mint_enabled;
+    let pool_features = pool_state.features();
+    let hook_config_present = pool_state.hook_config_present;
+
+    drop(pool_state);
+
+    // The fee is reported once, against the first recipient.
+    let payout_fee = |idx: usize| if idx == 0 { fee } else { 0 };
+    let signer_seeds: [&[u8]; 3] = [seeds::POOL, origin_mint.as_ref(), &[pool_bump]];
+    let signer = &[&signer_seeds[..]];
+    let pool_key = pool_loader.key();
+    let hook_enabled = !emergency
+        && pool_features.contains(FeatureFlags::from(FEATURE_HOOKS_ENABLED))
+        && hook_config_present;
+
+    if hook_enabled {
+        let (required_accounts, hook_mode, target_program, pre_unshield_enabled) = {
+            let hook_config = ctx.accounts.hook_config.load()?;
+            (
+                hook_config.required_metas(HookDirection::Unshield, &destination_owners),
+                hook_config.mode,
+                hook_config.pre_hook_program_id,
+                hook_config.pre_unshield_enabled,
+            )
+        };
+        if pre_unshield_enabled && target_program != Pubkey::default() {
+            validate_hook_accounts(&required_accounts, hook_mode, hook_accounts)?;
+            let (metas, infos) = hook_invocation_accounts(
+                ctx.accounts.hook_config.to_account_info(),
+                ctx.accounts.pool_state.to_account_info(),
+                hook_accounts,
+                &required_accounts,
+            )?;
+            for (idx, destination_owner) in destination_owners.iter().enumerate() {
+                let ix = Instruction {
+                    program_id: target_program,
+                    accounts: metas.clone(),
+                    data: HookInstruction::PreUnshield(PreUnshieldHook {
+                        origin_mint,
+                        pool: pool_key,
+                        destination: *destination_owner,
+                        mode: mode as u8,
+                        amount: args.amounts[idx],
+                        fee: payout_fee(idx),
+                    })
+                    .try_to_vec()?,
+                };
+                invoke_signed(&ix, &infos, signer)?;
+
+                emit!(PTFHookPreUnshield {
+                    mint: origin_mint,
+                    mode: mode as u8,
+                    destination: *destination_owner,
+                });
+            }
+        }
+    }
+
+    match mode {
+        UnshieldMode::Origin => {
+            for (idx, (destination_info, destination_owner, destination_mint)) in
+                destinations.iter().enumerate()
+            {
+                require_keys_eq!(
+                    *destination_mint,
+                    origin_mint,
+                    PoolError::OriginMintMismatch
+                );
+                let cpi_accounts = ptf_vault::cpi::accounts::Release {
+                    vault_state: ctx.accounts.vault_state.to_account_info(),
+                    vault_token_account: ctx.accounts.vault_token_account.to_account_info(),
+                    destination_token_account: destination_info.clone(),
+                    pool_authority: ctx.accounts.pool_state.to_account_info(),
+                    token_program: ctx.accounts.token_program.to_account_info(),
+                };
+                let cpi_ctx = CpiContext::new_with_signer(
+                    ctx.accounts.vault_program.to_account_info(),
+                    cpi_accounts,
+                    signer,
+                );
+                ptf_vault::cpi::release(cpi_ctx, args.amounts[idx])?;
+                if let Some(native_destination) = native_destination {
+                    anchor_spl::token_interface::close_account(CpiContext::new_with_signer(
+                        ctx.accounts.token_program.to_account_info(),
+                        anchor_spl::token_interface::CloseAccount {
+                            account: destination_info.clone(),
+                            destination: native_destination.to_account_info(),
+                            authority: ctx.accounts.pool_state.to_account_info(),
+                        },
+                        signer,
+                    ))?;
+                }
+                emit!(PTFUnshieldOrigin {
+                    mint: origin_mint,
+                    destination: *destination_owner,
+                    amount: args.amounts[idx],
+                    fee: payout_fee(idx),
+                });
+            }
+        }
+        UnshieldMode::Twin => {
+            require!(twin_mint_enabled, PoolError::TwinMintNotConfigured);
+            require!(
+                ctx.accounts.mint_mapping.has_ptkn,
+                PoolError::TwinMintNotConfigured
+            );
+            ensure_twin_token_program(
+                &ctx.accounts.mint_mapping,
+                &ctx.accounts.token_program.key(),
+            )?;
+            let twin_mint = ctx
+                .accounts
+                .twin_mint
+                .as_ref()
+                .ok_or(PoolError::TwinMintNotConfigured)?;
+            for (idx, (destination_info, destination_owner, destination_mint)) in
+                destinations.iter().enumerate()
+            {
+                require_keys_eq!(
+                    *destination_mint,
+                    twin_mint_key,
+                    PoolError::TwinMintMismatch
+                );
+                let factory_accounts = ptf_factory::cpi::accounts::MintPtkn {
+                    factory_state: ctx.accounts.factory_state.to_account_info(),
+                    mint_mapping: ctx.accounts.mint_mapping.to_account_info(),
+                    pool_authority: ctx.accounts.pool_state.to_account_info(),
+                    ptkn_mint: twin_mint.to_account_info(),
+                    destination_token_account: destination_info.clone(),
+                    token_program: ctx.accounts.token_program.to_account_info(),
+                };
+                let mint_ctx = CpiContext::new_with_signer(
+                    ctx.accounts.factory_program.to_account_info(),
+                    factory_accounts,
+                    signer,
+                );
+                ptf_factory::cpi::mint_ptkn(mint_ctx, args.amounts[idx])?;
+                emit!(PTFUnshieldPMint {
+                    mint: origin_mint,
+                    destination: *destination_owner,
+                    amount: args.amounts[idx],
+                    fee: payout_fee(idx),
+                });
+            }
+        }
+    }
+
+    if let Some(receipt) = ctx.accounts.receipt.as_ref() {
+        let (Some(payer), Some(system_program)) = (
+            ctx.accounts.payer.as_ref(),
+            ctx.accounts.system_program.as_ref(),
+        ) else {
+            return err!(PoolError::ReceiptInvalid);
+        };
+        require!(recipient_count == 1, PoolError::ReceiptInvalid);
+        UnshieldReceipt::create(
+            receipt,
+            payer,
+            system_program,
+            UnshieldReceipt {
+                pool: pool_key,
+                nullifier: args.nullifiers[0],
+                amount: args.amounts[0],
+                destination: destination_owners[0],
+                mode: mode as u8,
+                slot: Clock::get()?.slot,
+                bump: 0,
+            },
+        )?;
+    }
+
+    if hook_enabled {
+        let (required_accounts, hook_mode, target_program, post_unshield_enabled) = {
+            let hook_config = ctx.accounts.hook_config.load()?;
+            (
+                hook_config.required_metas(HookDirection::Unshield, &destination_owners),
+                hook_config.mode,
+                hook_config.post_unshield_program_id,
+                hook_config.post_unshield_enabled,
+            )
+        };
+        if post_unshield_enabled && target_program != Pubkey::default() {
+            validate_hook_accounts(&required_accounts, hook_mode, hook_accounts)?;
+
+            let (metas, infos) = hook_invocation_accounts(
+                ctx.accounts.hook_config.to_account_info(),
+                ctx.accounts.pool_state.to_account_info(),
+                hook_accounts,
+                &required_accounts,
+            )?;
+
+            let root = pool_loader.load()?.current_root;
+            let slot = Clock::get()?.slot;
+            for (idx, destination_owner) in destination_owners.iter().enumerate() {
+                let ix = Instruction {
+                    program_id: target_program,
+                    accounts: metas.clone(),
+                    data: HookInstruction::PostUnshield(PostUnshieldHook {
+                        origin_mint,
+                        pool: pool_key,
+                        destination: *destination_owner,
+                        mode: mode as u8,
+                        amount: args.amounts[idx],
+                        fee: payout_fee(idx),
+                        change_leaf_indices: change_leaf_indices.clone(),
+                        root,
+                        slot,
+                    })
+                    .try_to_vec()?,
+                };
+                invoke_signed(&ix, &infos, &[&signer_seeds])?;
+
+                emit!(PTFHookPostUnshield {
+                    mint: origin_mint,
+                    mode: mode as u8,
+                    destination: *destination_owner,
+                });
+            }
+        }
+    }
+
+    #[cfg(not(feature = "lightweight"))]
+    if should_enforce_invariant {
+        let pool_state = pool_loader.load()?;
+        enforce_supply_invariant(
+            &pool_state,
+            &note_ledger,
+            &ctx.accounts.vault_token_account,
+            ctx.accounts.twin_mint.as_ref(),
+        )?;
+    }
+    Ok(())
+}
+
+fn process_shield_finalize_tree<'info>(
+    pool_loader: &AccountLoader<'info, PoolState>,
+    commitment_tree: &AccountLoader<'info, CommitmentTree>,
+    shield_claim: &mut Account<'info, ShieldClaim>,
+) -> Result<()> {
+    require!(shield_claim.is_pending_tree(), PoolError::ShieldClaimStage);
+    require_keys_eq!(
+        shield_claim.pool,
+        pool_loader.key(),
+        PoolError::ShieldClaimMismatch
+    );
+    let pending = shield_claim.snapshot();
+
+    #[cfg(feature = "full_tree")]
+    {
+        let info = commitment_tree.to_account_info();
+        let mut data = info.try_borrow_mut_data()?;
+        let (tree, levels) = CommitmentTree::split_data_mut(&mut data)?;
+        require!(
+            tree.current_root == pending.old_root,
+            PoolError::RootMismatch,
+        );
+        require!(
+            tree.next_index == pending.next_index,
+            PoolError::PendingShieldMismatch,
+        );
+        let (new_root, _) = tree.append_note(levels, pending.commitment, pending.amount_commit)?;
+        {
+            let mut pool_state = pool_loader.load_mut()?;
+            pool_state.push_root(new_root, Clock::get()?.slot);
+            pool_state.pending_shield.deactivate();
+        }
+        shield_claim.mark_tree_complete(tree.depth() as u8);
+        return Ok(());
+    }
+
+    #[cfg(not(feature = "full_tree"))]
+    {
+        let mut tree = commitment_tree.load_mut()?;
+        require!(
+            tree.current_root == pending.old_root,
+            PoolError::RootMismatch,
+        );
+        require!(
+            tree.next_index == pending.next_index,
+            PoolError::PendingShieldMismatch,
+        );
+        require!(
+            (tree.next_index as u128) < (1u128 << tree.depth()),
+            PoolError::TreeFull
+        );
+        tree.next_index = tree
+            .next_index
+            .checked_add(1)
+            .ok_or(PoolError::AmountOverflow)?;
+        tree.current_root = pending.new_root;
+
+        {
+            let mut pool_state = pool_loader.load_mut()?;
+            pool_state.push_root(pending.new_root, Clock::get()?.slot);
+            pool_state.pending_shield.deactivate();
+        }
+        shield_claim.tree_node = pending.new_root;
+        shield_claim.tree_index_cursor = 0;
+        shield_claim.mark_tree_complete(tree.depth() as u8);
+        return Ok(());
+    }
+}
+
+fn enforce_supply_invariant<'info>(
+    pool_state: &PoolState,
+    note_ledger: &NoteLedger,
+    vault_token_account: &InterfaceAccount<'info, TokenAccount>,
+    twin_mint: Option<&InterfaceAccount<'info, Mint>>,
+) -> Result<()> {
+    let vault_balance = u128::from(vault_token_account.amount);
+    let twin_supply = resolve_twin_supply(pool_state, twin_mint)?;
+
+    validate_supply_components(pool_state, note_ledger, twin_supply, vault_balance).map(|_| ())
+}
+
+/// Program ID of the transaction-level instruction that led to this one. Fails unless
+/// the pool is running inside a CPI, so the result always names an outside program.
+fn calling_program(instructions: &UncheckedAccount) -> Result<Pubkey> {
+    require!(
+        get_stack_height() > TRANSACTION_LEVEL_STACK_HEIGHT,
+        PoolError::ShieldCpiRequired
+    );
+    let ix_sysvar = instructions.to_account_info();
+    let current_index = load_current_index_checked(&ix_sysvar)?;
+    let program_id = load_instruction_at_checked(current_index as usize, &ix_sysvar)?.program_id;
+    require_keys_neq!(program_id, crate::ID, PoolError::ShieldCpiRequired);
+    Ok(program_id)
+}
+
+/// Rejects a scheduled unshield before its note unlocks.
+fn check_unlock_timestamp(unlock_timestamp: Option<i64>, now: i64) -> Result<()> {
+    if let Some(unlock_timestamp) = unlock_timestamp {
+        require!(now >= unlock_timestamp, PoolError::NoteLocked);
+    }
+    Ok(())
+}
+
+/// Program-derived owners are off the ed25519 curve. They are rejected unless the caller
+/// opts in, so a token account address passed by mistake as the owner cannot strand
+/// funds in an account nobody controls.
+fn check_destination_owner(owner: &Pubkey, allow_owner_off_curve: bool) -> Result<()> {
+    require!(
+        allow_owner_off_curve || is_on_curve(owner),
+        PoolError::DestinationOwnerOffCurve
+    );
+    Ok(())
+}
+
+#[cfg(target_os = "solana")]
+fn is_on_curve(key: &Pubkey) -> bool {
+    // Curve id 0 is edwards25519; the syscall returns 0 for a valid point.
+    let mut result = 0u8;
+    unsafe {
+        solana_program::syscalls::sol_curve_validate_point(0, key.as_ref().as_ptr(), &mut result)
+            == 0
+    }
+}
+
+#[cfg(not(target_os = "solana"))]
+fn is_on_curve(key: &Pubkey) -> bool {
+    key.is_on_curve()
+}
+
+/// Only single-recipient origin exits from a native SOL pool can unwrap, into the
+/// handler-created account rather than a requested ATA.
+fn check_native_unwrap(
+    mode: UnshieldMode,
+    recipient_count: usize,
+    origin_mint: Pubkey,
+    create_destination_ata: bool,
+) -> Result<()> {
+    require!(
+        matches!(mode, UnshieldMode::Origin) && recipient_count == 1 && !create_destination_ata,
+        PoolError::NativeUnwrapInvalid
+    );
+    require_keys_eq!(origin_mint, NATIVE_MINT, PoolError::NativeMintRequired);
+    Ok(())
+}
+
+/// Temporary wSOL account a native exit from `pool` to `native_destination` releases into.
+pub fn native_unwrap_address(pool: &Pubkey, native_destination: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[seeds::UNWRAP, pool.as_ref(), native_destination.as_ref()],
+        &crate::ID,
+    )
+}
+
+/// Creates `destination_token_account` as a wSOL account owned by the pool PDA, funded
+/// by the payer. It is closed to `native_destination` once the release lands, so its
+/// rent goes out with the unwrapped lamports.
+fn create_native_unwrap_account<'info>(
+    accounts: &Unshield<'info>,
+    native_destination: &SystemAccount<'info>,
+) -> Result<()> {
+    let payer = accounts
+        .payer
+        .as_ref()
+        .ok_or(PoolError::NativeUnwrapInvalid)?;
+    let system_program = accounts
+        .system_program
+        .as_ref()
+        .ok_or(PoolError::NativeUnwrapInvalid)?;
+    let native_mint = accounts
+        .destination_mint
+        .as_ref()
+        .ok_or(PoolError::NativeUnwrapInvalid)?;
+    require_keys_eq!(
+        native_mint.key(),
+        NATIVE_MINT,
+        PoolError::NativeMintRequired
+    );
+    let pool = accounts.pool_state.key();
+    let (address, bump) = native_unwrap_address(&pool, &native_destination.key());
+    let info = accounts.destination_token_account.to_account_info();
+    require_keys_eq!(info.key(), address, PoolError::NativeUnwrapInvalid);
+    let destination_key = native_destination.key();
+    let bump_seed = [bump];
+    let signer_seeds: [&[u8]; 4] = [
+        seeds::UNWRAP,
+        pool.as_ref(),
+        destination_key.as_ref(),
+        &bump_seed,
+    ];
+    create_pda_account(
+        &info,
+        payer,
+        system_program,
+        &signer_seeds,
+        anchor_spl::token::spl_token::state::Account::LEN,
+        &accounts.token_program.key(),
+    )?;
+    anchor_spl::token_interface::initialize_account3(CpiContext::new(
+        accounts.token_program.to_account_info(),
+        anchor_spl::token_interface::InitializeAccount3 {
+            account: info,
+            mint: native_mint.to_account_info(),
+            authority: accounts.pool_state.to_account_info(),
+        },
+    ))
+}
+
+/// Idempotently creates the first recipient's associated token account, funded by the
+/// payer, so relayed exits do not fail on a missing account.
+fn create_destination_ata(accounts: &Unshield<'_>) -> Result<()> {
+    let missing = || error!(PoolError::DestinationAtaAccountsMissing);
+    let payer = accounts.payer.as_ref().ok_or_else(missing)?;
+    let system_program = accounts.system_program.as_ref().ok_or_else(missing)?;
+    let destination_owner = accounts.destination_owner.as_ref().ok_or_else(missing)?;
+    let destination_mint = accounts.destination_mint.as_ref().ok_or_else(missing)?;
+    let associated_token_program = accounts
+        .associated_token_program
+        .as_ref()
+        .ok_or_else(missing)?;
+    anchor_spl::associated_token::create_idempotent(CpiContext::new(
+        associated_token_program.to_account_info(),
+        anchor_spl::associated_token::Create {
+            payer: payer.to_account_info(),
+            associated_token: accounts.destination_token_account.to_account_info(),
+            authority: destination_owner.to_account_info(),
+            mint: destination_mint.to_account_info(),
+            system_program: system_program.to_account_info(),
+            token_program: accounts.token_program.to_account_info(),
+        },
+    ))
+}
+
+/// Applies the minimum note age to a transfer. With the policy on, the proof exposes the
+/// highest input leaf index `from_end` fields before the end of its public inputs.
+fn enforce_note_age<'info>(
+    pool_loader: &AccountLoader<'info, PoolState>,
+    commitment_tree: &AccountLoader<'info, CommitmentTree>,
+    public_inputs: &[u8],
+    from_end: usize,
+) -> Result<()> {
+    let pool_state = pool_loader.load()?;
+    if pool_state.min_note_age_leaves == 0 {
+        return Ok(());
+    }
+    let fields = parse_field_elements(public_inputs)?;
+    require!(
+        fields.len() >= from_end && from_end > 0,
+        PoolError::InvalidPublicInputs
+    );
+    let leaf_index = field_to_u64(&fields[fields.len() - from_end])?;
+    let leaf_count = commitment_tree.load()?.next_index;
+    pool_state.check_note_age(leaf_index, leaf_count)
+}
+
+/// The depositor's tokens must be the ones `source` spends: origin tokens, or pTKN of an
+/// enabled twin mint.
+fn check_shield_source_mint(
+    pool_state: &PoolState,
+    source: ShieldSource,
+    depositor_mint: &Pubkey,
+) -> Result<()> {
+    match source {
+        ShieldSource::Origin | ShieldSource::Prefunded => require_keys_eq!(
+            *depositor_mint,
+            pool_state.origin_mint,
+            PoolError::OriginMintMismatch,
+        ),
+        ShieldSource::Twin => {
+            require!(
+                pool_state.twin_mint_enabled,
+                PoolError::TwinMintNotConfigured
+            );
+            require_keys_eq!(
+                *depositor_mint,
+                pool_state.twin_mint,
+                PoolError::TwinMintMismatch,
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Twin mints may live under a different token program than the origin; the factory
+/// records which one. Mappings from before that was recorded carry no program and are
+/// left to the token program's own owner check.
+fn ensure_twin_token_program(mapping: &MintMapping, token_program: &Pubkey) -> Result<()> {
+    if let Some(expected) = PtknTokenProgram::from_u8(mapping.ptkn_token_program).program_id() {
+        require_keys_eq!(
+            *token_program,
+            expected,
+            PoolError::TwinTokenProgramMismatch
+        );
+    }
+    Ok(())
+}
+
+fn resolve_twin_supply<'info>(
+    pool_state: &PoolState,
+    twin_mint: Option<&InterfaceAccount<'info, Mint>>,
+) -> Result<u128> {
+    match (pool_state.twin_mint_enabled, twin_mint) {
+        (true, Some(mint)) => {
+            require_keys_eq!(
+                mint.key(),
+                pool_state.twin_mint,
+                PoolError::TwinMintMismatch
+            );
+            Ok(u128::from(mint.supply))
+        }
+        (true, None) => err!(PoolError::TwinMintNotConfigured),
+        (false, Some(_)) => err!(PoolError::TwinMintMismatch),
+        (false, None) => Ok(0u128),
+    }
+}
+
+/// Applies deposit caps and the inflow rate limit to a shield of `amount` that leaves
+/// `locked_after` in the vault.
+fn record_shield_inflow(
+    pool_state: &PoolState,
+    rate_limiter: Option<&mut Account<RateLimiter>>,
+    amount: u64,
+    locked_after: u64,
+) -> Result<()> {
+    pool_state.check_deposit_limits(amount, locked_after)?;
+    if pool_state.rate_limiter != Pubkey::default() {
+        let limiter = rate_limiter.ok_or(PoolError::RateLimiterMismatch)?;
+        require_keys_eq!(
+            limiter.key(),
+            pool_state.rate_limiter,
+            PoolError::RateLimiterMismatch
+        );
+        limiter.record_inflow(amount, Clock::get()?.slot)?;
+    }
+    Ok(())
+}
+
+/// Rolls the pool's stats to the current epoch and applies `record`. A no-op while stats
+/// are off.
+fn record_pool_stats(
+    pool_state: &PoolState,
+    pool_stats: Option<&mut Account<PoolStats>>,
+    record: impl FnOnce(&mut EpochStats) -> Result<()>,
+) -> Result<()> {
+    if pool_state.pool_stats == Pubkey::default() {
+        return Ok(());
+    }
+    let stats = pool_stats.ok_or(PoolError::PoolStatsMismatch)?;
+    require_keys_eq!(
+        stats.key(),
+        pool_state.pool_stats,
+        PoolError::PoolStatsMismatch
+    );
+    stats.roll(pool_state.origin_mint, Clock::get()?.epoch);
+    record(&mut stats.current)
+}
+
+/// Requires every destination owner to be allowlisted. A no-op while the allowlist is off.
+fn check_destination_allowlist(
+    pool_state: &PoolState,
+    allowlist: Option<&Account<DestinationAllowlist>>,
+    owners: &[Pubkey],
+) -> Result<()> {
+    if pool_state.destination_allowlist == Pubkey::default() {
+        return Ok(());
+    }
+    let allowlist = allowlist.ok_or(PoolError::DestinationAllowlistMismatch)?;
+    require_keys_eq!(
+        allowlist.key(),
+        pool_state.destination_allowlist,
+        PoolError::DestinationAllowlistMismatch
+    );
+    for owner in owners {
+        require!(
+            allowlist.contains(owner),
+            PoolError::DestinationNotAllowlisted
+        );
+    }
+    Ok(())
+}
+
+/// Books a shield fee: the referrer's share goes to its accrual account when one is passed,
+/// the rest to protocol fees. Returns the share credited.
+fn credit_shield_fee(
+    pool_state: &mut PoolState,
+    pool_key: Pubkey,
+    referrer: Option<Pubkey>,
+    accrual: Option<&mut Account<ReferralAccrual>>,
+    fee: u64,
+) -> Result<u64> {
+    let share = match (referrer, accrual) {
+        (Some(referrer), Some(accrual)) if pool_state.referral_fee_bps > 0 => {
+            require!(
+                accrual.pool == pool_key && accrual.referrer == referrer,
+                PoolError::ReferralAccrualMismatch
+            );
+            let share = u64::try_from(
+                u128::from(fee) * u128::from(pool_state.referral_fee_bps) / u128::from(MAX_BPS),
+            )
+            .map_err(|_| PoolError::AmountOverflow)?;
+            accrual.accrued = accrual
+                .accrued
+                .checked_add(share)
+                .ok_or(PoolError::AmountOverflow)?;
+            pool_state.referral_fees = pool_state
+                .referral_fees
+                .checked_add(u128::from(share))
+                .ok_or(PoolError::AmountOverflow)?;
+            share
+        }
+        _ => 0,
+    };
+    pool_state.protocol_fees = pool_state
+        .protocol_fees
+        .checked_add(u128::from(fee - share))
+        .ok_or(PoolError::AmountOverflow)?;
+    Ok(share)
+}
+
+/// Vault tokens nothing in the pool accounts for yet, available to a prefunded shield.
+fn prefunded_surplus(vault_balance: u64, expected: u128) -> u128 {
+    u128::from(vault_balance).saturating_sub(expected)
+}
+
+/// Requires the untracked vault balance to cover `deposit_amount`. Anything beyond it
+/// stays untracked, like a donation, so a stray transfer into the vault cannot block
+/// prefunded shields.
+fn check_prefunded_deposit(vault_balance: u64, expected: u128, deposit_amount: u64) -> Result<()> {
+    require!(
+        prefunded_surplus(vault_balance, expected) >= u128::from(deposit_amount),
+        PoolError::PrefundedAmountMismatch
+    );
+    Ok(())
+}
+
+/// Vault balance the pool should hold: outstanding twin supply, live note value and
+/// accrued fees, including twin fees not yet minted.
+fn expected_vault_balance(
+    pool_state: &PoolState,
+    note_ledger: &NoteLedger,
+    twin_supply: u128,
+) -> Result<u128> {
+    twin_supply
+        .checked_add(note_ledger.live_value)
+        .ok_or(PoolError::AmountOverflow)?
+        .checked_add(pool_state.protocol_fees)
+        .ok_or(PoolError::AmountOverflow)?
+        .checked_add(pool_state.twin_protocol_fees)
+        .ok_or(PoolError::AmountOverflow)?
+        .checked_add(pool_state.referral_fees)
+        .ok_or(PoolError::AmountOverflow.into())
+}
+
+fn validate_supply_components(
+    pool_state: &PoolState,
+    note_ledger: &NoteLedger,
+    twin_supply: u128,
+    vault_balance: u128,
+) -> Result<u128> {
+    let expected = expected_vault_balance(pool_state, note_ledger, twin_supply)?;
+    require!(vault_balance == expected, PoolError::InvariantBreach);
+    Ok(expected)
+}
+
+#[inline(always)]
+fn highest_power_of_two_leq(n: usize) -> usize {
+    debug_assert!(n > 0);
+    let mut power = 1usize;
+    while (power << 1) <= n {
+        power <<= 1;
+    }
+    power
+}
+
+#[inline(always)]
+fn fr_from_bytes(bytes: &[u8; 32]) -> Fr {
+    let mut limbs = [0u64; 4];
+    for (index, limb) in limbs.iter_mut().enumerate() {
+        let start = index * 8;
+        let chunk: [u8; 8] = bytes[start..start + 8]
+            .try_into()
+            .expect("slice with incorrect length");
+        *limb = u64::from_le_bytes(chunk);
+    }
+    Fr::new(BigInteger256::new(limbs))
+}
+
+#[inline(always)]
+fn fr_to_bytes(value: &Fr) -> [u8; 32] {
+    let limbs = (*value).into_bigint().0;
+    let mut bytes = [0u8; 32];
+    for (index, limb) in limbs.iter().enumerate() {
+        let start = index * 8;
+        bytes[start..start + 8].copy_from_slice(&limb.to_le_bytes());
+    }
+    bytes
+}
+#[derive(Accounts)]
+#[instruction(shield_fee_bps: u16, features: u8, merkle_depth: u8)]
+pub struct InitializePool<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        init,
+        payer = payer,
+        seeds = [seeds::POOL, origin_mint.key().as_ref()],
+        bump,
+        space = PoolState::SPACE,
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        init,
+        payer = payer,
+        seeds = [seeds::NULLIFIERS, origin_mint.key().as_ref()],
+        bump,
+        space = NullifierSet::SPACE,
+    )]
+    pub nullifier_set: AccountLoader<'info, NullifierSet>,
+    #[account(
+        init,
+        payer = payer,
+        seeds = [seeds::NOTES, origin_mint.key().as_ref()],
+        bump,
+        space = NoteLedger::SPACE,
+    )]
+    pub note_ledger: AccountLoader<'info, NoteLedger>,
+    #[account(
+        init,
+        payer = payer,
+        seeds = [seeds::TREE, origin_mint.key().as_ref()],
+        bump,
+        space = CommitmentTree::space(merkle_depth),
+    )]
+    pub commitment_tree: AccountLoader<'info, CommitmentTree>,
+    #[account(
+        init,
+        payer = payer,
+        seeds = [seeds::HOOKS, origin_mint.key().as_ref()],
+        bump,
+        space = HookConfig::SPACE,
+    )]
+    pub hook_config: AccountLoader<'info, HookConfig>,
+    #[account(mut)]
+    pub vault_state: Account<'info, ptf_vault::VaultState>,
+    pub origin_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        seeds = [seeds::MINT_MAPPING, origin_mint.key().as_ref()],
+        bump = mint_mapping.bump,
+        seeds::program = ptf_factory::ID
+    )]
+    pub mint_mapping: Account<'info, MintMapping>,
+    #[account(
+        seeds = [seeds::FACTORY, ptf_factory::ID.as_ref()],
+        bump = factory_state.bump,
+        seeds::program = ptf_factory::ID
+    )]
+    pub factory_state: Account<'info, ptf_factory::FactoryState>,
+    #[account(mut)]
+    pub twin_mint: Option<InterfaceAccount<'info, Mint>>,
+    pub verifier_program: Program<'info, PtfVerifierGroth16>,
+    pub verifying_key: Account<'info, VerifyingKeyAccount>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct RefreshFeeFromFactory<'info> {
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump,
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        seeds = [seeds::MINT_MAPPING, pool_state.load()?.origin_mint.as_ref()],
+        bump = mint_mapping.bump,
+        seeds::program = ptf_factory::ID
+    )]
+    pub mint_mapping: Account<'info, MintMapping>,
+    #[account(
+        seeds = [seeds::FACTORY, ptf_factory::ID.as_ref()],
+        bump = factory_state.bump,
+        seeds::program = ptf_factory::ID
+    )]
+    pub factory_state: Account<'info, ptf_factory::FactoryState>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateAuthority<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump,
+        has_one = authority
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        mut,
+        seeds = [seeds::NULLIFIERS, pool_state.load()?.origin_mint.as_ref()],
+        bump = nullifier_set.load()?.bump
+    )]
+    pub nullifier_set: AccountLoader<'info, NullifierSet>,
+}
+
+#[derive(Accounts)]
+pub struct EnableEmergencyExit<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump,
+        has_one = authority
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    /// CHECK: must be the pool's pinned verifier or one of its allowlist entries, checked
+    /// by the handler.
+    #[account(executable)]
+    pub verifier_program: UncheckedAccount<'info>,
+    /// The pool's current or previous verifying key.
+    pub verifying_key: Account<'info, VerifyingKeyAccount>,
+}
+
+#[derive(Accounts)]
+#[instruction(shard: u8)]
+pub struct InitializeNullifierShard<'info> {
+    #[account(
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        init,
+        payer = payer,
+        seeds = [seeds::NULLIFIERS, pool_state.load()?.origin_mint.as_ref(), &[shard]],
+        bump,
+        space = NullifierSet::SPACE,
+    )]
+    pub nullifier_shard: AccountLoader<'info, NullifierSet>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(shard: u8)]
+pub struct RotateNullifierShard<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump,
+        has_one = authority
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        mut,
+        seeds = [seeds::NULLIFIERS, pool_state.load()?.origin_mint.as_ref(), &[shard]],
+        bump = nullifier_shard.load()?.bump,
+        constraint = nullifier_shard.load()?.pool == pool_state.key() @ PoolError::NullifierShardMismatch,
+    )]
+    pub nullifier_shard: AccountLoader<'info, NullifierSet>,
+    #[account(
+        mut,
+        seeds = [seeds::NOTES, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.note_ledger_bump,
+        constraint = note_ledger.key() == pool_state.load()?.note_ledger @ PoolError::NoteLedgerMismatch,
+        constraint = note_ledger.load()?.pool == pool_state.key() @ PoolError::NoteLedgerMismatch,
+    )]
+    pub note_ledger: AccountLoader<'info, NoteLedger>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(inputs: u8, outputs: u8)]
+pub struct RegisterArityVerifier<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump,
+        has_one = authority
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        init,
+        payer = payer,
+        space = ArityVerifier::SPACE,
+        seeds = [
+            seeds::ARITY_VERIFIER,
+            pool_state.key().as_ref(),
+            &[inputs],
+            &[outputs]
+        ],
+        bump
+    )]
+    pub arity_verifier: Account<'info, ArityVerifier>,
+    pub verifying_key: Account<'info, VerifyingKeyAccount>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigureRateLimit<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump,
+        has_one = authority
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = RateLimiter::SPACE,
+        seeds = [seeds::RATE_LIMIT, pool_state.key().as_ref()],
+        bump
+    )]
+    pub rate_limiter: Account<'info, RateLimiter>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigurePoolStats<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump,
+        has_one = authority
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = PoolStats::SPACE,
+        seeds = [seeds::STATS, pool_state.key().as_ref()],
+        bump
+    )]
+    pub pool_stats: Account<'info, PoolStats>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigureDestinationAllowlist<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump,
+        has_one = authority
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = DestinationAllowlist::SPACE,
+        seeds = [seeds::DESTINATIONS, pool_state.key().as_ref()],
+        bump
+    )]
+    pub destination_allowlist: Account<'info, DestinationAllowlist>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetAllowedDestination<'info> {
+    pub compliance_authority: Signer<'info>,
+    #[account(
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        mut,
+        seeds = [seeds::DESTINATIONS, pool_state.key().as_ref()],
+        bump = destination_allowlist.bump,
+        has_one = compliance_authority
+    )]
+    pub destination_allowlist: Account<'info, DestinationAllowlist>,
+}
+
+#[derive(Accounts)]
+pub struct RotateVerifyingKey<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump,
+        has_one = authority
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    pub verifying_key: Account<'info, VerifyingKeyAccount>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterAggregationVerifier<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump,
+        has_one = authority
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = ArityVerifier::SPACE,
+        seeds = [seeds::AGGREGATION_VERIFIER, pool_state.key().as_ref()],
+        bump
+    )]
+    pub aggregation_verifier: Account<'info, ArityVerifier>,
+    pub verifying_key: Account<'info, VerifyingKeyAccount>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PrivateTransferBatch<'info> {
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        mut,
+        seeds = [seeds::NULLIFIERS, pool_state.load()?.origin_mint.as_ref()],
+        bump = nullifier_set.load()?.bump
+    )]
+    pub nullifier_set: AccountLoader<'info, NullifierSet>,
+    #[account(
+        mut,
+        seeds = [seeds::TREE, pool_state.load()?.origin_mint.as_ref()],
+        bump = commitment_tree.load()?.bump,
+        constraint = commitment_tree.load()?.pool == pool_state.key() @ PoolError::CommitmentTreeMismatch
+    )]
+    pub commitment_tree: AccountLoader<'info, CommitmentTree>,
+    #[account(
+        mut,
+        seeds = [seeds::NOTES, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.note_ledger_bump,
+        constraint = note_ledger.key() == pool_state.load()?.note_ledger @ PoolError::NoteLedgerMismatch,
+        constraint = note_ledger.load()?.pool == pool_state.key() @ PoolError::NoteLedgerMismatch,
+    )]
+    pub note_ledger: AccountLoader<'info, NoteLedger>,
+    /// CHECK: must be the pool's pinned verifier or an enabled allowlist entry, checked
+    /// before the proof CPI.
+    #[account(executable)]
+    pub verifier_program: UncheckedAccount<'info>,
+    #[account(
+        address = aggregation_verifier.verifying_key,
+        constraint = verifying_key.hash == aggregation_verifier.verifying_key_hash @ PoolError::VerifyingKeyHashMismatch,
+    )]
+    pub verifying_key: Account<'info, VerifyingKeyAccount>,
+    #[account(
+        seeds = [seeds::AGGREGATION_VERIFIER, pool_state.key().as_ref()],
+        bump = aggregation_verifier.bump
+    )]
+    pub aggregation_verifier: Account<'info, ArityVerifier>,
+    /// Funds nullifier record PDAs when the pool runs in PDA nullifier mode.
+    #[account(mut)]
+    pub payer: Option<Signer<'info>>,
+    pub system_program: Option<Program<'info, System>>,
+    /// Must sign when `pool_state.root_guardian` is set.
+    pub root_guardian: Option<Signer<'info>>,
+    /// Required when `pool_state.pool_stats` is set.
+    #[account(mut)]
+    pub pool_stats: Option<Account<'info, PoolStats>>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterConsolidationVerifier<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump,
+        has_one = authority
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = ArityVerifier::SPACE,
+        seeds = [seeds::CONSOLIDATION_VERIFIER, pool_state.key().as_ref()],
+        bump
+    )]
+    pub consolidation_verifier: Account<'info, ArityVerifier>,
+    pub verifying_key: Account<'info, VerifyingKeyAccount>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterScheduledVerifier<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump,
+        has_one = authority
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = ArityVerifier::SPACE,
+        seeds = [seeds::SCHEDULED_VERIFIER, pool_state.key().as_ref()],
+        bump
+    )]
+    pub scheduled_verifier: Account<'info, ArityVerifier>,
+    pub verifying_key: Account<'info, VerifyingKeyAccount>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterDisclosureVerifier<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump,
+        has_one = authority
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = ArityVerifier::SPACE,
+        seeds = [seeds::DISCLOSURE_VERIFIER, pool_state.key().as_ref()],
+        bump
+    )]
+    pub disclosure_verifier: Account<'info, ArityVerifier>,
+    pub verifying_key: Account<'info, VerifyingKeyAccount>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct VerifyDisclosure<'info> {
+    #[account(
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        seeds = [seeds::MINT_MAPPING, pool_state.load()?.origin_mint.as_ref()],
+        bump = mint_mapping.bump,
+        seeds::program = ptf_factory::ID,
+    )]
+    pub mint_mapping: Account<'info, MintMapping>,
+    /// CHECK: must be the pool's pinned verifier or an enabled allowlist entry, checked
+    /// before the proof CPI.
+    #[account(executable)]
+    pub verifier_program: UncheckedAccount<'info>,
+    #[account(
+        address = disclosure_verifier.verifying_key,
+        constraint = verifying_key.hash == disclosure_verifier.verifying_key_hash @ PoolError::VerifyingKeyHashMismatch,
+    )]
+    pub verifying_key: Account<'info, VerifyingKeyAccount>,
+    #[account(
+        seeds = [seeds::DISCLOSURE_VERIFIER, pool_state.key().as_ref()],
+        bump = disclosure_verifier.bump
+    )]
+    pub disclosure_verifier: Account<'info, ArityVerifier>,
+}
+
+#[derive(Accounts)]
+pub struct ConsolidateNotes<'info> {
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        mut,
+        seeds = [seeds::NULLIFIERS, pool_state.load()?.origin_mint.as_ref()],
+        bump = nullifier_set.load()?.bump
+    )]
+    pub nullifier_set: AccountLoader<'info, NullifierSet>,
+    #[account(
+        mut,
+        seeds = [seeds::TREE, pool_state.load()?.origin_mint.as_ref()],
+        bump = commitment_tree.load()?.bump,
+        constraint = commitment_tree.load()?.pool == pool_state.key() @ PoolError::CommitmentTreeMismatch
+    )]
+    pub commitment_tree: AccountLoader<'info, CommitmentTree>,
+    #[account(
+        mut,
+        seeds = [seeds::NOTES, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.note_ledger_bump,
+        constraint = note_ledger.key() == pool_state.load()?.note_ledger @ PoolError::NoteLedgerMismatch,
+        constraint = note_ledger.load()?.pool == pool_state.key() @ PoolError::NoteLedgerMismatch,
+    )]
+    pub note_ledger: AccountLoader<'info, NoteLedger>,
+    /// CHECK: must be the pool's pinned verifier or an enabled allowlist entry, checked
+    /// before the proof CPI.
+    #[account(executable)]
+    pub verifier_program: UncheckedAccount<'info>,
+    #[account(
+        address = consolidation_verifier.verifying_key,
+        constraint = verifying_key.hash == consolidation_verifier.verifying_key_hash @ PoolError::VerifyingKeyHashMismatch,
+    )]
+    pub verifying_key: Account<'info, VerifyingKeyAccount>,
+    #[account(
+        seeds = [seeds::CONSOLIDATION_VERIFIER, pool_state.key().as_ref()],
+        bump = consolidation_verifier.bump
+    )]
+    pub consolidation_verifier: Account<'info, ArityVerifier>,
+    /// Funds nullifier record PDAs when the pool runs in PDA nullifier mode.
+    #[account(mut)]
+    pub payer: Option<Signer<'info>>,
+    pub system_program: Option<Program<'info, System>>,
+    /// Must sign when `pool_state.root_guardian` is set.
+    pub root_guardian: Option<Signer<'info>>,
+    /// Required when `pool_state.pool_stats` is set.
+    #[account(mut)]
+    pub pool_stats: Option<Account<'info, PoolStats>>,
+}
+
+#[derive(Accounts)]
+#[instruction(salt: [u8; 32], action: PoolTimelockAction)]
+pub struct QueuePoolTimelockAction<'info> {
+    /// The pool authority, or the sequencer for `WriteNullifier`.
+    pub authority: Signer<'info>,
+    #[account(
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        init,
+        payer = payer,
+        seeds = [seeds::TIMELOCK, pool_state.key().as_ref(), salt.as_ref()],
+        bump,
+        space = PoolTimelockEntry::SPACE,
+    )]
+    pub timelock_entry: Account<'info, PoolTimelockEntry>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ExecutePoolTimelockAction<'info> {
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        mut,
+        seeds = [
+            seeds::TIMELOCK,
+            pool_state.key().as_ref(),
+            timelock_entry.salt.as_ref()
+        ],
+        bump = timelock_entry.bump,
+        constraint = timelock_entry.pool == pool_state.key() @ PoolError::TimelockInvalidPool,
+        close = executor,
+    )]
+    pub timelock_entry: Account<'info, PoolTimelockEntry>,
+    #[account(mut)]
+    pub executor: Signer<'info>,
+    /// Nullifier storage for `WriteNullifier`; shards or records follow as remaining
+    /// accounts.
+    #[account(
+        mut,
+        constraint = nullifier_set.load()?.pool == pool_state.key() @ PoolError::NullifierSetInvalid,
+    )]
+    pub nullifier_set: Option<AccountLoader<'info, NullifierSet>>,
+    pub system_program: Option<Program<'info, System>>,
+}
+
+#[derive(Accounts)]
+pub struct CancelPoolTimelockAction<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump,
+        has_one = authority
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        mut,
+        seeds = [
+            seeds::TIMELOCK,
+            pool_state.key().as_ref(),
+            timelock_entry.salt.as_ref()
+        ],
+        bump = timelock_entry.bump,
+        constraint = timelock_entry.pool == pool_state.key() @ PoolError::TimelockInvalidPool,
+        has_one = payer,
+        close = payer,
+    )]
+    pub timelock_entry: Account<'info, PoolTimelockEntry>,
+    /// CHECK: The account that funded the entry, pinned by `has_one`; only receives its
+    /// rent.
+    #[account(mut)]
+    pub payer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigratePoolState<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    /// CHECK: loaded manually since older layouts are too short for `AccountLoader`.
+    #[account(mut, owner = crate::ID @ PoolError::PoolStateInvalid)]
+    pub pool_state: UncheckedAccount<'info>,
+    /// CHECK: matched against `pool_state.hook_config`; grown when the hook layout gains fields.
+    #[account(mut, owner = crate::ID @ PoolError::HookConfigInvalid)]
+    pub hook_config: Option<UncheckedAccount<'info>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigratePoolAccount<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump,
+        has_one = authority
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    /// CHECK: matched against the pool's own accounts before being loaded, since older
+    /// layouts may be too short for `AccountLoader`.
+    #[account(mut, owner = crate::ID)]
+    pub target: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawFees<'info> {
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(mut)]
+    pub vault_state: Account<'info, ptf_vault::VaultState>,
+    #[account(mut)]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub fee_recipient_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub vault_program: Program<'info, PtfVault>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(referrer: Pubkey)]
+pub struct RegisterReferrer<'info> {
+    #[account(
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        init,
+        payer = payer,
+        space = ReferralAccrual::SPACE,
+        seeds = [seeds::REFERRAL, pool_state.key().as_ref(), referrer.as_ref()],
+        bump
+    )]
+    pub referral_accrual: Account<'info, ReferralAccrual>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimReferralFees<'info> {
+    pub referrer: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        mut,
+        seeds = [seeds::REFERRAL, pool_state.key().as_ref(), referrer.key().as_ref()],
+        bump = referral_accrual.bump,
+        has_one = referrer
+    )]
+    pub referral_accrual: Account<'info, ReferralAccrual>,
+    #[account(mut)]
+    pub vault_state: Account<'info, ptf_vault::VaultState>,
+    #[account(mut)]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub referrer_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub vault_program: Program<'info, PtfVault>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawTwinFees<'info> {
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        mut,
+        seeds = [seeds::MINT_MAPPING, pool_state.load()?.origin_mint.as_ref()],
+        bump = mint_mapping.bump,
+        seeds::program = ptf_factory::ID,
+        constraint = mint_mapping.origin_mint == pool_state.load()?.origin_mint @ PoolError::OriginMintMismatch,
+    )]
+    pub mint_mapping: Account<'info, MintMapping>,
+    #[account(
+        seeds = [seeds::FACTORY, ptf_factory::ID.as_ref()],
+        bump = factory_state.bump,
+        seeds::program = ptf_factory::ID
+    )]
+    pub factory_state: Account<'info, ptf_factory::FactoryState>,
+    #[account(mut)]
+    pub twin_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub fee_recipient_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub factory_program: Program<'info, PtfFactory>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct RedeemPtkn<'info> {
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        mut,
+        seeds = [seeds::MINT_MAPPING, pool_state.load()?.origin_mint.as_ref()],
+        bump = mint_mapping.bump,
+        seeds::program = ptf_factory::ID,
+        constraint = mint_mapping.origin_mint == pool_state.load()?.origin_mint @ PoolError::OriginMintMismatch,
+    )]
+    pub mint_mapping: Account<'info, MintMapping>,
+    #[account(
+        seeds = [seeds::FACTORY, ptf_factory::ID.as_ref()],
+        bump = factory_state.bump,
+        seeds::program = ptf_factory::ID
+    )]
+    pub factory_state: Account<'info, ptf_factory::FactoryState>,
+    #[account(mut)]
+    pub vault_state: Account<'info, ptf_vault::VaultState>,
+    #[account(mut)]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub twin_mint: InterfaceAccount<'info, Mint>,
+    pub holder: Signer<'info>,
+    #[account(mut)]
+    pub holder_twin_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub destination_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub vault_program: Program<'info, PtfVault>,
+    pub factory_program: Program<'info, PtfFactory>,
+    /// The origin mint's token program.
+    pub token_program: Interface<'info, TokenInterface>,
+    pub twin_token_program: Interface<'info, TokenInterface>,
+    /// Required when `pool_state.rate_limiter` is set.
+    #[account(mut)]
+    pub rate_limiter: Option<Account<'info, RateLimiter>>,
+    /// Required when `pool_state.destination_allowlist` is set.
+    pub destination_allowlist: Option<Account<'info, DestinationAllowlist>>,
+}
+
+#[derive(Accounts)]
+pub struct VerifyMembership<'info> {
+    #[account(
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        constraint = commitment_tree.key() == pool_state.load()?.commitment_tree @ PoolError::CommitmentTreeMismatch
+    )]
+    pub commitment_tree: AccountLoader<'info, CommitmentTree>,
+}
+
+#[derive(Accounts)]
+pub struct IsNullifierSpent<'info> {
+    #[account(
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        seeds = [seeds::NULLIFIERS, pool_state.load()?.origin_mint.as_ref()],
+        bump = nullifier_set.load()?.bump
+    )]
+    pub nullifier_set: AccountLoader<'info, NullifierSet>,
+}
+
+#[derive(Accounts)]
+pub struct GetPoolInfo<'info> {
+    #[account(
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+}
+
+#[derive(Accounts)]
+pub struct GetTreeInfo<'info> {
+    #[account(
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        constraint = commitment_tree.key() == pool_state.load()?.commitment_tree @ PoolError::CommitmentTreeMismatch
+    )]
+    pub commitment_tree: AccountLoader<'info, CommitmentTree>,
+}
+
+#[derive(Accounts)]
+pub struct WriteTreeCheckpoint<'info> {
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        constraint = commitment_tree.key() == pool_state.load()?.commitment_tree @ PoolError::CommitmentTreeMismatch
+    )]
+    pub commitment_tree: AccountLoader<'info, CommitmentTree>,
+    #[account(
+        init,
+        payer = payer,
+        space = TreeCheckpoint::SPACE,
+        seeds = [
+            seeds::CHECKPOINT,
+            pool_state.key().as_ref(),
+            &pool_state.load()?.checkpoint_count.to_le_bytes()
+        ],
+        bump
+    )]
+    pub checkpoint: Account<'info, TreeCheckpoint>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct GetPoolStats<'info> {
+    #[account(
+        seeds = [seeds::STATS, pool_stats.pool.as_ref()],
+        bump = pool_stats.bump
+    )]
+    pub pool_stats: Account<'info, PoolStats>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PoolStatsInfo {
+    pub current: EpochStats,
+    pub previous: EpochStats,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PoolInfo {
+    pub origin_mint: Pubkey,
+    pub current_root: [u8; 32],
+    pub shield_fee_bps: u16,
+    pub unshield_fee_bps: u16,
+    pub features: u8,
+    pub paused: bool,
+    pub version: u8,
+    pub fee_rounding: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TreeInfo {
+    pub root: [u8; 32],
+    pub next_leaf_index: u64,
+}
+
+#[derive(Accounts)]
+pub struct SyncRoot<'info> {
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        constraint = commitment_tree.key() == pool_state.load()?.commitment_tree @ PoolError::CommitmentTreeMismatch
+    )]
+    pub commitment_tree: AccountLoader<'info, CommitmentTree>,
+    /// Must sign when `pool_state.root_guardian` is set.
+    pub root_guardian: Option<Signer<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct CompactNullifiers<'info> {
+    #[account(
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        mut,
+        seeds = [seeds::NULLIFIERS, pool_state.load()?.origin_mint.as_ref()],
+        bump = nullifier_set.load()?.bump
+    )]
+    pub nullifier_set: AccountLoader<'info, NullifierSet>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct GrowNullifierSet<'info> {
+    #[account(
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        mut,
+        constraint = nullifier_set.load()?.pool == pool_state.key() @ PoolError::NullifierSetInvalid,
+    )]
+    pub nullifier_set: AccountLoader<'info, NullifierSet>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Shield<'info> {
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        seeds = [seeds::HOOKS, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.hook_config_bump,
+        constraint = hook_config.load()?.pool == pool_state.key() @ PoolError::HookConfigInvalid,
+    )]
+    pub hook_config: AccountLoader<'info, HookConfig>,
+    #[account(
+        mut,
+        seeds = [seeds::NULLIFIERS, pool_state.load()?.origin_mint.as_ref()],
+        bump = nullifier_set.load()?.bump
+    )]
+    pub nullifier_set: AccountLoader<'info, NullifierSet>,
+    #[account(
+        mut,
+        seeds = [seeds::TREE, pool_state.load()?.origin_mint.as_ref()],
+        bump = commitment_tree.load()?.bump,
+        constraint = commitment_tree.load()?.pool == pool_state.key() @ PoolError::CommitmentTreeMismatch
+    )]
+    pub commitment_tree: AccountLoader<'info, CommitmentTree>,
+    #[account(
+        mut,
+        seeds = [seeds::NOTES, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.note_ledger_bump,
+        constraint = note_ledger.key() == pool_state.load()?.note_ledger @ PoolError::NoteLedgerMismatch,
+        constraint = note_ledger.load()?.pool == pool_state.key() @ PoolError::NoteLedgerMismatch,
+    )]
+    pub note_ledger: AccountLoader<'info, NoteLedger>,
+    #[account(
+        mut,
+        seeds = [seeds::VAULT, pool_state.load()?.origin_mint.as_ref()],
+        bump = vault_state.bump,
+        seeds::program = ptf_vault::ID
+    )]
+    pub vault_state: Account<'info, ptf_vault::VaultState>,
+    #[account(mut)]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub depositor_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub twin_mint: Option<InterfaceAccount<'info, Mint>>,
+    /// CHECK: must be the pool's pinned verifier or an enabled allowlist entry, checked
+    /// before the proof CPI.
+    #[account(executable)]
+    pub verifier_program: UncheckedAccount<'info>,
+    #[account(
+        address = pool_state.load()?.verifying_key,
+        constraint = verifying_key.hash == pool_state.load()?.verifying_key_hash @ PoolError::VerifyingKeyHashMismatch,
+    )]
+    pub verifying_key: Account<'info, VerifyingKeyAccount>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = ShieldClaim::SPACE,
+        seeds = [seeds::CLAIM, pool_state.key().as_ref()],
+        bump
+    )]
+    pub shield_claim: Account<'info, ShieldClaim>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub origin_mint: InterfaceAccount<'info, Mint>,
+    pub vault_program: Program<'info, PtfVault>,
+    pub token_program: Interface<'info, TokenInterface>,
+    /// CHECK: constrained by address check
+    #[account(address = solana_program::sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+    /// Required when `pool_state.rate_limiter` is set.
+    #[account(mut)]
+    pub rate_limiter: Option<Account<'info, RateLimiter>>,
+    /// `shield_from_ptkn` only: factory accounts for the pTKN burn.
+    pub factory_state: Option<Account<'info, ptf_factory::FactoryState>>,
+    /// Read on every shield so frozen mints are rejected; also used for the pTKN burn.
+    #[account(
+        mut,
+        seeds = [seeds::MINT_MAPPING, pool_state.load()?.origin_mint.as_ref()],
+        bump = mint_mapping.bump,
+        seeds::program = ptf_factory::ID,
+        constraint = mint_mapping.origin_mint == pool_state.load()?.origin_mint @ PoolError::OriginMintMismatch,
+    )]
+    pub mint_mapping: Account<'info, MintMapping>,
+    /// Factory-wide kill switch, fee ceiling and verifier allowlist.
+    #[account(
+        seeds = [seeds::PROTOCOL_CONFIG],
+        bump = protocol_config.bump,
+        seeds::program = ptf_factory::ID
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub factory_program: Option<Program<'info, PtfFactory>>,
+    /// CHECK: `LeafIndexRecord` PDA for the new commitment; required while
+    /// `FEATURE_LEAF_INDEX` is on.
+    #[account(mut)]
+    pub leaf_record: Option<UncheckedAccount<'info>>,
+    /// Owner of `depositor_token_account` when it is not `payer`, such as a PDA signing
+    /// for `shield_via_cpi`.
+    pub depositor: Option<Signer<'info>>,
+    /// Required when `pool_state.pool_stats` is set.
+    #[account(mut)]
+    pub pool_stats: Option<Account<'info, PoolStats>>,
+    /// Accrual account of `args.referrer`; the referrer is credited only when it is passed.
+    #[account(mut)]
+    pub referral_accrual: Option<Account<'info, ReferralAccrual>>,
+}
+
+#[derive(Accounts)]
+pub struct ShieldFinalizeTree<'info> {
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        mut,
+        seeds = [seeds::TREE, pool_state.load()?.origin_mint.as_ref()],
+        bump = commitment_tree.load()?.bump,
+        constraint = commitment_tree.load()?.pool == pool_state.key() @ PoolError::CommitmentTreeMismatch
+    )]
+    pub commitment_tree: AccountLoader<'info, CommitmentTree>,
+    #[account(
+        mut,
+        seeds = [seeds::CLAIM, pool_state.key().as_ref()],
+        bump = shield_claim.bump
+    )]
+    pub shield_claim: Account<'info, ShieldClaim>,
+    /// Must sign when `pool_state.root_guardian` is set.
+    pub root_guardian: Option<Signer<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct ShieldFinalizeLedger<'info> {
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        seeds = [seeds::HOOKS, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.hook_config_bump,
+        constraint = hook_config.load()?.pool == pool_state.key() @ PoolError::HookConfigInvalid,
+    )]
+    pub hook_config: AccountLoader<'info, HookConfig>,
+    #[account(
+        mut,
+        seeds = [seeds::NOTES, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.note_ledger_bump,
+        constraint = note_ledger.key() == pool_state.load()?.note_ledger @ PoolError::NoteLedgerMismatch,
+        constraint = note_ledger.load()?.pool == pool_state.key() @ PoolError::NoteLedgerMismatch,
+    )]
+    pub note_ledger: AccountLoader<'info, NoteLedger>,
+    #[account(
+        mut,
+        seeds = [seeds::CLAIM, pool_state.key().as_ref()],
+        bump = shield_claim.bump
+    )]
+    pub shield_claim: Account<'info, ShieldClaim>,
+}
+
+#[derive(Accounts)]
+pub struct ShieldCheckInvariant<'info> {
+    #[account(
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        seeds = [seeds::NOTES, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.note_ledger_bump,
+        constraint = note_ledger.key() == pool_state.load()?.note_ledger @ PoolError::NoteLedgerMismatch,
+        constraint = note_ledger.load()?.pool == pool_state.key() @ PoolError::NoteLedgerMismatch,
+    )]
+    pub note_ledger: AccountLoader<'info, NoteLedger>,
+    #[account(
+        mut,
+        seeds = [seeds::CLAIM, pool_state.key().as_ref()],
+        bump = shield_claim.bump
+    )]
+    pub shield_claim: Account<'info, ShieldClaim>,
+    #[account(mut)]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub twin_mint: Option<InterfaceAccount<'info, Mint>>,
+}
+
+#[derive(Accounts)]
+pub struct AuditLedger<'info> {
+    #[account(
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        constraint = note_ledger.key() == pool_state.load()?.note_ledger @ PoolError::NoteLedgerMismatch,
+    )]
+    pub note_ledger: AccountLoader<'info, NoteLedger>,
+}
+
+#[derive(Accounts)]
+pub struct CheckInvariant<'info> {
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        constraint = note_ledger.key() == pool_state.load()?.note_ledger @ PoolError::NoteLedgerMismatch,
+    )]
+    pub note_ledger: AccountLoader<'info, NoteLedger>,
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub twin_mint: Option<InterfaceAccount<'info, Mint>>,
+}
+
+#[derive(Accounts)]
+pub struct Unshield<'info> {
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        seeds = [seeds::HOOKS, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.hook_config_bump,
+        constraint = hook_config.load()?.pool == pool_state.key() @ PoolError::HookConfigInvalid,
+    )]
+    pub hook_config: AccountLoader<'info, HookConfig>,
+    #[account(
+        mut,
+        seeds = [seeds::NULLIFIERS, pool_state.load()?.origin_mint.as_ref()],
+        bump = nullifier_set.load()?.bump
+    )]
+    pub nullifier_set: AccountLoader<'info, NullifierSet>,
+    #[account(
+        mut,
+        seeds = [seeds::TREE, pool_state.load()?.origin_mint.as_ref()],
+        bump = commitment_tree.load()?.bump,
+        constraint = commitment_tree.load()?.pool == pool_state.key() @ PoolError::CommitmentTreeMismatch
+    )]
+    pub commitment_tree: AccountLoader<'info, CommitmentTree>,
+    #[account(
+        mut,
+        seeds = [seeds::NOTES, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.note_ledger_bump,
+        constraint = note_ledger.key() == pool_state.load()?.note_ledger @ PoolError::NoteLedgerMismatch,
+        constraint = note_ledger.load()?.pool == pool_state.key() @ PoolError::NoteLedgerMismatch,
+    )]
+    pub note_ledger: AccountLoader<'info, NoteLedger>,
+    #[account(
+        mut,
+        seeds = [seeds::MINT_MAPPING, pool_state.load()?.origin_mint.as_ref()],
+        bump = mint_mapping.bump,
+        seeds::program = ptf_factory::ID,
+        constraint = mint_mapping.origin_mint == pool_state.load()?.origin_mint @ PoolError::OriginMintMismatch,
+    )]
+    pub mint_mapping: Account<'info, MintMapping>,
+    /// CHECK: must be the pool's pinned verifier or an enabled allowlist entry, or the
+    /// emergency verifier for `emergency_unshield`, checked before the proof CPI.
+    #[account(executable)]
+    pub verifier_program: UncheckedAccount<'info>,
+    /// The pool's active, scheduled or emergency key, checked by the handler.
+    pub verifying_key: Account<'info, VerifyingKeyAccount>,
+    #[account(mut)]
+    pub vault_state: Account<'info, ptf_vault::VaultState>,
+    #[account(mut)]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: deserialized as a token account after any requested ATA creation.
+    #[account(mut)]
+    pub destination_token_account: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub twin_mint: Option<InterfaceAccount<'info, Mint>>,
+    pub vault_program: Program<'info, PtfVault>,
+    #[account(
+        seeds = [seeds::FACTORY, ptf_factory::ID.as_ref()],
+        bump = factory_state.bump,
+        seeds::program = ptf_factory::ID
+    )]
+    pub factory_state: Account<'info, ptf_factory::FactoryState>,
+    /// Factory-wide kill switch, fee ceiling and verifier allowlist.
+    #[account(
+        seeds = [seeds::PROTOCOL_CONFIG],
+        bump = protocol_config.bump,
+        seeds::program = ptf_factory::ID
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub factory_program: Program<'info, PtfFactory>,
+    pub token_program: Interface<'info, TokenInterface>,
+    /// Funds nullifier record PDAs when the pool runs in PDA nullifier mode.
+    #[account(mut)]
+    pub payer: Option<Signer<'info>>,
+    pub system_program: Option<Program<'info, System>>,
+    /// Required when `pool_state.rate_limiter` is set.
+    #[account(mut)]
+    pub rate_limiter: Option<Account<'info, RateLimiter>>,
+    /// Native SOL pools only: system account receiving unwrapped lamports. The handler
+    /// creates `destination_token_account` at `native_unwrap_address` as a pool-owned
+    /// wSOL account and closes it here after the release; `destination_mint`, `payer`
+    /// and `system_program` are required.
+    #[account(mut)]
+    pub native_destination: Option<SystemAccount<'info>>,
+    /// CHECK: wallet the destination ATA is derived for; only read by the ATA program.
+    pub destination_owner: Option<UncheckedAccount<'info>>,
+    pub destination_mint: Option<InterfaceAccount<'info, Mint>>,
+    pub associated_token_program: Option<Program<'info, AssociatedToken>>,
+    /// CHECK: `UnshieldReceipt` PDA for the first nullifier; created when supplied.
+    #[account(mut)]
+    pub receipt: Option<UncheckedAccount<'info>>,
+    /// Must sign when `pool_state.root_guardian` is set.
+    pub root_guardian: Option<Signer<'info>>,
+    /// Required when `pool_state.pool_stats` is set.
+    #[account(mut)]
+    pub pool_stats: Option<Account<'info, PoolStats>>,
+    /// Required when `pool_state.destination_allowlist` is set.
+    pub destination_allowlist: Option<Account<'info, DestinationAllowlist>>,
+    /// CHECK: `UnshieldEscrow` PDA created by `unshield_to_escrow`.
+    #[account(mut)]
+    pub escrow: Option<UncheckedAccount<'info>>,
+    /// Binds `verifying_key` when `args.unlock_timestamp` is set.
+    #[account(
+        seeds = [seeds::SCHEDULED_VERIFIER, pool_state.key().as_ref()],
+        bump = scheduled_verifier.bump
+    )]
+    pub scheduled_verifier: Option<Account<'info, ArityVerifier>>,
+}
+
+#[derive(Accounts)]
+pub struct ReleaseEscrow<'info> {
+    #[account(mut)]
+    pub recipient: Signer<'info>,
+    pub arbiter: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [
+            seeds::ESCROW,
+            escrow.pool.as_ref(),
+            recipient.key().as_ref(),
+            arbiter.key().as_ref(),
+            &escrow.nullifier,
+        ],
+        bump = escrow.bump,
+        has_one = recipient,
+        has_one = arbiter,
+        close = recipient
+    )]
+    pub escrow: Account<'info, UnshieldEscrow>,
+    #[account(mut, address = escrow.token_account @ PoolError::EscrowInvalid)]
+    pub escrow_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, token::mint = mint)]
+    pub recipient_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(address = escrow_token_account.mint @ PoolError::EscrowInvalid)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigureHooks<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump,
+        has_one = authority
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        mut,
+        seeds = [seeds::HOOKS, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.hook_config_bump,
+        constraint = hook_config.load()?.pool == pool_state.key() @ PoolError::HookConfigInvalid,
+    )]
+    pub hook_config: AccountLoader<'info, HookConfig>,
+}
+
+#[derive(Accounts)]
+pub struct PrivateTransfer<'info> {
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        mut,
+        seeds = [seeds::NULLIFIERS, pool_state.load()?.origin_mint.as_ref()],
+        bump = nullifier_set.load()?.bump
+    )]
+    pub nullifier_set: AccountLoader<'info, NullifierSet>,
+    #[account(
+        mut,
+        seeds = [seeds::TREE, pool_state.load()?.origin_mint.as_ref()],
+        bump = commitment_tree.load()?.bump,
+        constraint = commitment_tree.load()?.pool == pool_state.key() @ PoolError::CommitmentTreeMismatch
+    )]
+    pub commitment_tree: AccountLoader<'info, CommitmentTree>,
+    #[account(
+        mut,
+        seeds = [seeds::NOTES, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.note_ledger_bump,
+        constraint = note_ledger.key() == pool_state.load()?.note_ledger @ PoolError::NoteLedgerMismatch,
+        constraint = note_ledger.load()?.pool == pool_state.key() @ PoolError::NoteLedgerMismatch,
+    )]
+    pub note_ledger: AccountLoader<'info, NoteLedger>,
+    #[account(
+        seeds = [seeds::MINT_MAPPING, pool_state.load()?.origin_mint.as_ref()],
+        bump = mint_mapping.bump,
+        seeds::program = ptf_factory::ID,
+        constraint = mint_mapping.origin_mint == pool_state.load()?.origin_mint @ PoolError::OriginMintMismatch,
+    )]
+    pub mint_mapping: Account<'info, MintMapping>,
+    /// CHECK: must be the pool's pinned verifier or an enabled allowlist entry, checked
+    /// before the proof CPI.
+    #[account(executable)]
+    pub verifier_program: UncheckedAccount<'info>,
+    /// The pool's verifying key, or the one bound by `arity_verifier`; checked in
+    /// `execute_private_transfer`.
+    pub verifying_key: Account<'info, VerifyingKeyAccount>,
+    pub arity_verifier: Option<Account<'info, ArityVerifier>>,
+    /// Required whenever hooks are enabled on the pool.
+    #[account(
+        seeds = [seeds::HOOKS, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.hook_config_bump,
+        constraint = hook_config.load()?.pool == pool_state.key() @ PoolError::HookConfigInvalid,
+    )]
+    pub hook_config: Option<AccountLoader<'info, HookConfig>>,
+    /// Funds nullifier record PDAs when the pool runs in PDA nullifier mode.
+    #[account(mut)]
+    pub payer: Option<Signer<'info>>,
+    pub system_program: Option<Program<'info, System>>,
+    /// Must sign when `pool_state.root_guardian` is set.
+    pub root_guardian: Option<Signer<'info>>,
+    /// Required when `pool_state.pool_stats` is set.
+    #[account(mut)]
+    pub pool_stats: Option<Account<'info, PoolStats>>,
+}
+
+#[derive(Accounts)]
+pub struct PrivateSwap<'info> {
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state_a.load()?.origin_mint.as_ref()],
+        bump = pool_state_a.load()?.bump
+    )]
+    pub pool_state_a: AccountLoader<'info, PoolState>,
+    #[account(
+        mut,
+        seeds = [seeds::NULLIFIERS, pool_state_a.load()?.origin_mint.as_ref()],
+        bump = nullifier_set_a.load()?.bump
+    )]
+    pub nullifier_set_a: AccountLoader<'info, NullifierSet>,
+    #[account(
+        mut,
+        seeds = [seeds::TREE, pool_state_a.load()?.origin_mint.as_ref()],
+        bump = commitment_tree_a.load()?.bump,
+        constraint = commitment_tree_a.load()?.pool == pool_state_a.key() @ PoolError::CommitmentTreeMismatch
+    )]
+    pub commitment_tree_a: AccountLoader<'info, CommitmentTree>,
+    #[account(
+        mut,
+        constraint = note_ledger_a.key() == pool_state_a.load()?.note_ledger @ PoolError::NoteLedgerMismatch,
+    )]
+    pub note_ledger_a: AccountLoader<'info, NoteLedger>,
+    /// Checked against pool A's active key in `execute_private_transfer`.
+    pub verifying_key_a: Account<'info, VerifyingKeyAccount>,
+    #[account(
+        seeds = [seeds::HOOKS, pool_state_a.load()?.origin_mint.as_ref()],
+        bump = pool_state_a.load()?.hook_config_bump,
+        constraint = hook_config_a.load()?.pool == pool_state_a.key() @ PoolError::HookConfigInvalid,
+    )]
+    pub hook_config_a: Option<AccountLoader<'info, HookConfig>>,
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state_b.load()?.origin_mint.as_ref()],
+        bump = pool_state_b.load()?.bump
+    )]
+    pub pool_state_b: AccountLoader<'info, PoolState>,
+    #[account(
+        mut,
+        seeds = [seeds::NULLIFIERS, pool_state_b.load()?.origin_mint.as_ref()],
+        bump = nullifier_set_b.load()?.bump
+    )]
+    pub nullifier_set_b: AccountLoader<'info, NullifierSet>,
+    #[account(
+        mut,
+        seeds = [seeds::TREE, pool_state_b.load()?.origin_mint.as_ref()],
+        bump = commitment_tree_b.load()?.bump,
+        constraint = commitment_tree_b.load()?.pool == pool_state_b.key() @ PoolError::CommitmentTreeMismatch
+    )]
+    pub commitment_tree_b: AccountLoader<'info, CommitmentTree>,
+    #[account(
+        mut,
+        constraint = note_ledger_b.key() == pool_state_b.load()?.note_ledger @ PoolError::NoteLedgerMismatch,
+    )]
+    pub note_ledger_b: AccountLoader<'info, NoteLedger>,
+    /// Checked against pool B's active key in `execute_private_transfer`.
+    pub verifying_key_b: Account<'info, VerifyingKeyAccount>,
+    #[account(
+        seeds = [seeds::HOOKS, pool_state_b.load()?.origin_mint.as_ref()],
+        bump = pool_state_b.load()?.hook_config_bump,
+        constraint = hook_config_b.load()?.pool == pool_state_b.key() @ PoolError::HookConfigInvalid,
+    )]
+    pub hook_config_b: Option<AccountLoader<'info, HookConfig>>,
+    /// CHECK: must be the pool's pinned verifier or an enabled allowlist entry, checked
+    /// before the proof CPI.
+    #[account(executable)]
+    pub verifier_program: UncheckedAccount<'info>,
+    /// Funds nullifier record PDAs for pools in PDA nullifier mode.
+    #[account(mut)]
+    pub payer: Option<Signer<'info>>,
+    pub system_program: Option<Program<'info, System>>,
+    /// Must sign when `pool_state_a.root_guardian` is set.
+    pub root_guardian_a: Option<Signer<'info>>,
+    /// Must sign when `pool_state_b.root_guardian` is set.
+    pub root_guardian_b: Option<Signer<'info>>,
+    /// Required when `pool_state_a.pool_stats` is set.
+    #[account(mut)]
+    pub pool_stats_a: Option<Account<'info, PoolStats>>,
+    /// Required when `pool_state_b.pool_stats` is set.
+    #[account(mut)]
+    pub pool_stats_b: Option<Account<'info, PoolStats>>,
+}
+
+/// Amounts two parties agree to exchange across pools; `amount_a` moves in pool A and
+/// `amount_b` in pool B.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SwapTerms {
+    pub amount_a: u64,
+    pub amount_b: u64,
+    /// Last slot at which the swap may execute.
+    pub expiry_slot: u64,
+}
+
+impl SwapTerms {
+    /// Field element both legs must expose as their last public input.
+    pub fn binding(&self, pool_a: Pubkey, pool_b: Pubkey) -> [u8; 32] {
+        let digest = hashv(&[
+            b"ptf-swap",
+            pool_a.as_ref(),
+            pool_b.as_ref(),
+            &self.amount_a.to_le_bytes(),
+            &self.amount_b.to_le_bytes(),
+            &self.expiry_slot.to_le_bytes(),
+        ]);
+        fr_to_bytes(&Fr::from_le_bytes_mod_order(&digest.to_bytes()))
+    }
+
+    pub fn is_bound(public_inputs: &[u8], binding: &[u8; 32]) -> bool {
+        public_inputs.len() >= 32 && public_inputs[public_inputs.len() - 32..] == binding[..]
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ShieldArgs {
+    pub amount_commit: [u8; 32],
+    pub amount: u64,
+    pub proof: Vec<u8>,
+    pub public_inputs: Vec<u8>,
+    /// Note plaintext encrypted to the recipient, emitted verbatim in `PTFShielded`.
+    pub encrypted_note: Vec<u8>,
+    /// Attributed in `PTFShieldReferred`, and credited `referral_fee_bps` of the fee.
+    pub referrer: Option<Pubkey>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct UnshieldArgs {
+    pub old_root: [u8; 32],
+    pub new_root: [u8; 32],
+    pub nullifiers: Vec<[u8; 32]>,
+    pub output_commitments: Vec<[u8; 32]>,
+    pub output_amount_commitments: Vec<[u8; 32]>,
+    pub amount: u64,
+    /// Highest fee the caller accepts; guards against fee changes after proving.
+    pub max_fee: u64,
+    pub proof: Vec<u8>,
+    pub public_inputs: Vec<u8>,
+    /// Creates the destination ATA (paid by `payer`) before release; a no-op if it
+    /// already exists.
+    pub create_destination_ata: bool,
+    /// Accepts destination owners that are program-derived addresses (DAO treasuries,
+    /// smart-contract wallets).
+    pub allow_owner_off_curve: bool,
+    /// Unlock time (unix seconds) of a time-locked note, proven by the scheduled circuit.
+    pub unlock_timestamp: Option<i64>,
+}
+
+/// `UnshieldArgs` generalised to several recipients; `amounts[i]` pays destination `i`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct UnshieldManyArgs {
+    pub old_root: [u8; 32],
+    pub new_root: [u8; 32],
+    pub nullifiers: Vec<[u8; 32]>,
+    pub output_commitments: Vec<[u8; 32]>,
+    pub output_amount_commitments: Vec<[u8; 32]>,
+    pub amounts: Vec<u64>,
+    pub max_fee: u64,
+    pub proof: Vec<u8>,
+    pub public_inputs: Vec<u8>,
+    /// Applies to the first recipient only.
+    pub create_destination_ata: bool,
+    /// Applies to every recipient.
+    pub allow_owner_off_curve: bool,
+    pub unlock_timestamp: Option<i64>,
+}
+
+impl From<UnshieldArgs> for UnshieldManyArgs {
+    fn from(args: UnshieldArgs) -> Self {
+        Self {
+            old_root: args.old_root,
+            new_root: args.new_root,
+            nullifiers: args.nullifiers,
+            output_commitments: args.output_commitments,
+            output_amount_commitments: args.output_amount_commitments,
+            amounts: vec![args.amount],
+            max_fee: args.max_fee,
+            proof: args.proof,
+            public_inputs: args.public_inputs,
+            create_destination_ata: args.create_destination_ata,
+            allow_owner_off_curve: args.allow_owner_off_curve,
+            unlock_timestamp: args.unlock_timestamp,
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub enum PoolTimelockAction {
+    SetFee {
+        shield_fee_bps: u16,
+        unshield_fee_bps: u16,
+        shield_flat_fee: u64,
+        unshield_flat_fee: u64,
+    },
+    SetFeatures {
+        features: u8,
+    },
+    SetFeeRounding {
+        fee_rounding: u8,
+    },
+    SetRootGuardian {
+        root_guardian: Pubkey,
+    },
+    SetVerifierEntry {
+        program: Pubkey,
+        enabled: bool,
+    },
+    SetReferralFeeShare {
+        referral_fee_bps: u16,
+    },
+    SetTimelock {
+        timelock_seconds: i64,
+    },
+    SetSequencer {
+        sequencer: Pubkey,
+    },
+    /// Queued by the sequencer; marks `nullifier` spent once the delay elapses.
+    WriteNullifier {
+        nullifier: [u8; 32],
+    },
+}
+
+impl PoolTimelockAction {
+    /// Floor on the delay of `SetSequencer` and `WriteNullifier`, so a sequencer change
+    /// or nullifier write can be audited and canceled even when `timelock_seconds` is 0.
+    pub const MIN_SEQUENCER_DELAY: i64 = 24 * 60 * 60;
+
+    /// Seconds between queueing and execution on a pool with `timelock_seconds`.
+    pub fn delay(&self, timelock_seconds: i64) -> i64 {
+        match self {
+            Self::SetSequencer { .. } | Self::WriteNullifier { .. } => {
+                timelock_seconds.max(Self::MIN_SEQUENCER_DELAY)
+            }
+            _ => timelock_seconds,
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct DisclosureArgs {
+    pub root: [u8; 32],
+    pub commitment: [u8; 32],
+    /// Revealed owner and amount the note is proven to open to.
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub proof: Vec<u8>,
+    pub public_inputs: Vec<u8>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ConsolidateArgs {
+    pub old_root: [u8; 32],
+    pub new_root: [u8; 32],
+    pub nullifiers: Vec<[u8; 32]>,
+    pub output_commitment: [u8; 32],
+    pub output_amount_commitment: [u8; 32],
+    pub proof: Vec<u8>,
+    pub public_inputs: Vec<u8>,
+    pub encrypted_note: Vec<u8>,
+}
+
+/// One transfer inside a `private_transfer_batch`; its proof is the batch's.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct BatchedTransfer {
+    pub old_root: [u8; 32],
+    pub new_root: [u8; 32],
+    pub nullifiers: Vec<[u8; 32]>,
+    pub output_commitments: Vec<[u8; 32]>,
+    pub output_amount_commitments: Vec<[u8; 32]>,
+    pub encrypted_note: Vec<u8>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct BatchTransferArgs {
+    pub operations: Vec<BatchedTransfer>,
+    pub proof: Vec<u8>,
+    pub public_inputs: Vec<u8>,
+}
+
+impl BatchTransferArgs {
+    /// Public inputs the aggregated circuit exposes: per operation, in order, the old and
+    /// new roots, nullifiers, output commitments and output amount commitments.
+    pub fn public_inputs_for(operations: &[BatchedTransfer]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for operation in operations {
+            out.extend_from_slice(&operation.old_root);
+            out.extend_from_slice(&operation.new_root);
+            for field in operation
+                .nullifiers
+                .iter()
+                .chain(&operation.output_commitments)
+                .chain(&operation.output_amount_commitments)
+            {
+                out.extend_from_slice(field);
+            }
+        }
+        out
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct TransferArgs {
+    pub old_root: [u8; 32],
+    pub new_root: [u8; 32],
+    pub nullifiers: Vec<[u8; 32]>,
+    pub output_commitments: Vec<[u8; 32]>,
+    pub output_amount_commitments: Vec<[u8; 32]>,
+    pub proof: Vec<u8>,
+    pub public_inputs: Vec<u8>,
+    /// Output note plaintexts encrypted to their recipients, emitted verbatim in
+    /// `PTFTransferred`.
+    pub encrypted_note: Vec<u8>,
+    /// Either empty or one ciphertext per output commitment, in output order, each
+    /// encrypted to that output's recipient.
+    pub output_ciphertexts: Vec<Vec<u8>>,
+}
+
+impl TransferArgs {
+    pub fn check_output_ciphertexts(&self) -> Result<()> {
+        require!(
+            self.output_ciphertexts.is_empty()
+                || (self.output_ciphertexts.len() == self.output_commitments.len()
+                    && self
+                        .output_ciphertexts
+                        .iter()
+                        .all(|ciphertext| (1..=MAX_OUTPUT_CIPHERTEXT_LEN)
+                            .contains(&ciphertext.len()))),
+            PoolError::OutputCiphertextInvalid
+        );
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct HookConfigArgs {
+    pub post_shield_program: Pubkey,
+    pub post_shield_enabled: bool,
+    pub post_unshield_program: Pubkey,
+    pub post_unshield_enabled: bool,
+    /// Accounts required by shield-side hooks (pre-shield, post-shield and post-transfer).
+    pub post_shield_accounts: Vec<HookAccountArgs>,
+    /// Accounts required by unshield-side hooks (pre-unshield and post-unshield).
+    pub post_unshield_accounts: Vec<HookAccountArgs>,
+    pub mode: HookAccountMode,
+    pub pre_hook_program: Pubkey,
+    pub pre_shield_enabled: bool,
+    pub pre_unshield_enabled: bool,
+    pub post_transfer_program: Pubkey,
+    pub post_transfer_enabled: bool,
+    /// PDAs derived per call and required after the direction's fixed accounts.
+    pub account_templates: Vec<HookAccountTemplateArgs>,
+}
+
+/// One seed of a templated hook account.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub enum HookSeed {
+    /// Fixed bytes, at most 32.
+    Literal(Vec<u8>),
+    /// The depositor on shield or the destination owner on unshield.
+    User,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct HookAccountTemplateArgs {
+    pub program_id: Pubkey,
+    pub seeds: Vec<HookSeed>,
+    pub is_writable: bool,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct HookAccountArgs {
+    pub key: Pubkey,
+    /// Passed writable to the hook; the caller must supply it writable.
+    pub is_writable: bool,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ApproveAllowanceArgs {
+    pub amount: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct TransferFromArgs {
+    pub transfer: TransferArgs,
+    pub allowance_amount: u64,
+}
+
+#[derive(Accounts)]
+pub struct ManageAllowance<'info> {
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
         bump = pool_state.load()?.bump
     )]
     pub pool_state: AccountLoader<'info, PoolState>,
     #[account(
-        seeds = [seeds::HOOKS, pool_state.load()?.origin_mint.as_ref()],
-        bump = pool_state.load()?.hook_config_bump,
-        constraint = hook_config.load()?.pool == pool_state.key() @ PoolError::HookConfigInvalid,
+        init_if_needed,
+        payer = owner,
+        space = AllowanceAccount::SPACE,
+        seeds = [
+            seeds::ALLOWANCE,
+            pool_state.key().as_ref(),
+            owner.key().as_ref(),
+            spender.key().as_ref()
+        ],
+        bump
     )]
-    pub hook_config: AccountLoader<'info, HookConfig>,
+    pub allowance: Account<'info, AllowanceAccount>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    /// CHECK: spender authority
+    pub spender: AccountInfo<'info>,
+    #[account(address = pool_state.load()?.origin_mint)]
+    /// CHECK: origin mint is constrained by address equality
+    pub origin_mint: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct TransferFrom<'info> {
+    #[account(
+        mut,
+        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.bump
+    )]
+    pub pool_state: AccountLoader<'info, PoolState>,
+    #[account(
+        mut,
+        seeds = [seeds::NULLIFIERS, pool_state.load()?.origin_mint.as_ref()],
+        bump = nullifier_set.load()?.bump
+    )]
+    pub nullifier_set: AccountLoader<'info, NullifierSet>,
+    #[account(
+        mut,
+        seeds = [seeds::TREE, pool_state.load()?.origin_mint.as_ref()],
+        bump = commitment_tree.load()?.bump,
+        constraint = commitment_tree.load()?.pool == pool_state.key() @ PoolError::CommitmentTreeMismatch
+    )]
+    pub commitment_tree: AccountLoader<'info, CommitmentTree>,
     #[account(
         mut,
         seeds = [seeds::NOTES, pool_state.load()?.origin_mint.as_ref()],
@@ -1527,1671 +6543,5749 @@ pub struct ShieldFinalizeLedger<'info> {
         constraint = note_ledger.load()?.pool == pool_state.key() @ PoolError::NoteLedgerMismatch,
     )]
     pub note_ledger: AccountLoader<'info, NoteLedger>,
+    /// CHECK: must be the pool's pinned verifier or an enabled allowlist entry, checked
+    /// before the proof CPI.
+    #[account(executable)]
+    pub verifier_program: UncheckedAccount<'info>,
+    /// The pool's verifying key, or the one bound by `arity_verifier`; checked in
+    /// `execute_private_transfer`.
+    pub verifying_key: Account<'info, VerifyingKeyAccount>,
+    pub arity_verifier: Option<Account<'info, ArityVerifier>>,
+    /// Required whenever hooks are enabled on the pool.
+    #[account(
+        seeds = [seeds::HOOKS, pool_state.load()?.origin_mint.as_ref()],
+        bump = pool_state.load()?.hook_config_bump,
+        constraint = hook_config.load()?.pool == pool_state.key() @ PoolError::HookConfigInvalid,
+    )]
+    pub hook_config: Option<AccountLoader<'info, HookConfig>>,
     #[account(
         mut,
-        seeds = [seeds::CLAIM, pool_state.key().as_ref()],
-        bump = shield_claim.bump
+        seeds = [
+            seeds::ALLOWANCE,
+            pool_state.key().as_ref(),
+            allowance_owner.key().as_ref(),
+            spender.key().as_ref()
+        ],
+        bump = allowance.bump
     )]
-    pub shield_claim: Account<'info, ShieldClaim>,
+    pub allowance: Account<'info, AllowanceAccount>,
+    /// CHECK: allowance owner reference
+    pub allowance_owner: AccountInfo<'info>,
+    pub spender: Signer<'info>,
+    /// Funds nullifier record PDAs when the pool runs in PDA nullifier mode.
+    #[account(mut)]
+    pub payer: Option<Signer<'info>>,
+    pub system_program: Option<Program<'info, System>>,
+    /// Must sign when `pool_state.root_guardian` is set.
+    pub root_guardian: Option<Signer<'info>>,
+    /// Required when `pool_state.pool_stats` is set.
+    #[account(mut)]
+    pub pool_stats: Option<Account<'info, PoolStats>>,
+}
+
+/// A tree's frontier and zero hashes, borrowed from the storage behind its header.
+type TreeLevelsMut<'a> = (&'a mut [[u8; 32]], &'a mut [[u8; 32]]);
+
+#[account(zero_copy(unsafe))]
+#[repr(C)]
+pub struct CommitmentTree {
+    pub pool: Pubkey,
+    pub canopy_depth: u8,
+    pub next_index: u64,
+    pub current_root: [u8; 32],
+    pub canopy: [[u8; 32]; CommitmentTree::MAX_CANOPY],
+    pub recent_commitments: [[u8; 32]; CommitmentTree::MAX_CANOPY],
+    pub recent_amount_commitments: [[u8; 32]; CommitmentTree::MAX_CANOPY],
+    pub recent_indices: [u64; CommitmentTree::MAX_CANOPY],
+    pub recent_len: u8,
+    pub bump: u8,
+    pub depth: u8,
+    /// Layout version; 0 marks accounts created before versioning. Version 2 moved the
+    /// frontier and zero hashes behind the header.
+    pub version: u8,
+}
+
+impl CommitmentTree {
+    /// Maximum depth.
+    pub const DEPTH: usize = ptf_common::MERKLE_DEPTH as usize;
+    pub const MIN_DEPTH: u8 = 16;
+    pub const MAX_CANOPY: usize = 16;
+    pub const HEADER_SPACE: usize = 8 + core::mem::size_of::<CommitmentTree>();
+    /// Size of every tree created before version 2, which stored the frontier and zero
+    /// hashes inline for the maximum depth. No current layout is this long.
+    pub const LEGACY_SPACE: usize = Self::HEADER_SPACE + 64 * Self::DEPTH + 64;
+    pub const CURRENT_VERSION: u8 = 2;
+    pub const PRECOMPUTED_ZEROES: [[u8; 32]; Self::DEPTH] = [
+        [
+            0x64, 0x48, 0xb6, 0x46, 0x84, 0xee, 0x39, 0xa8, 0x23, 0xd5, 0xfe, 0x5f, 0xd5, 0x24,
+            0x31, 0xdc, 0x81, 0xe4, 0x81, 0x7b, 0xf2, 0xc3, 0xea, 0x3c, 0xab, 0x9e, 0x23, 0x9e,
+            0xfb, 0xf5, 0x98, 0x20,
+        ],
+        [
+            0xe1, 0xf1, 0xb1, 0x60, 0x44, 0x77, 0xa4, 0x67, 0xf0, 0x8d, 0xc6, 0x9d, 0xcb, 0x44,
+            0x1a, 0x26, 0xec, 0xa7, 0x84, 0xf5, 0x6f, 0x1a, 0x30, 0xdf, 0x63, 0x22, 0xb1, 0xcd,
+            0x3d, 0x67, 0x69, 0x10,
+        ],
+        [
+            0x38, 0xd2, 0x56, 0xb8, 0xb2, 0x7e, 0xd5, 0x28, 0xd5, 0x1d, 0x37, 0x50, 0xea, 0x6e,
+            0x7c, 0x46, 0x06, 0x21, 0xf7, 0x50, 0x8d, 0x75, 0x3d, 0x2e, 0xaf, 0xe2, 0x7e, 0x53,
+            0x31, 0x33, 0xf4, 0x18,
+        ],
+        [
+            0x2a, 0x95, 0xbc, 0x9d, 0x55, 0x97, 0xac, 0xca, 0x65, 0x82, 0x56, 0x1a, 0x57, 0x28,
+            0xb7, 0xf1, 0x45, 0x23, 0xa5, 0x3b, 0xe9, 0xff, 0x20, 0x63, 0xd3, 0xb0, 0x17, 0xcb,
+            0x37, 0xd8, 0xf9, 0x07,
+        ],
+        [
+            0x55, 0x3f, 0x18, 0x39, 0x16, 0xec, 0x5c, 0x7b, 0x4d, 0xad, 0xb2, 0x94, 0x8c, 0xc5,
+            0x99, 0xa6, 0x07, 0x29, 0xf3, 0x5d, 0x4c, 0x1f, 0x63, 0xc9, 0xf5, 0xb3, 0x46, 0x87,
+            0x5e, 0xcf, 0x94, 0x2b,
+        ],
+        [
+            0x78, 0x9d, 0xa0, 0x2e, 0xa3, 0xdd, 0x11, 0x1d, 0x61, 0x53, 0xb9, 0x51, 0x69, 0x1e,
+            0xd7, 0xfe, 0xbc, 0xe1, 0xa9, 0xcc, 0x22, 0x7d, 0xea, 0x46, 0x96, 0x45, 0x66, 0xa6,
+            0xc5, 0x93, 0xee, 0x2d,
+        ],
+        [
+            0x9d, 0x34, 0x87, 0x3c, 0xbe, 0xaa, 0xa4, 0xa8, 0x7f, 0xac, 0xb5, 0x8c, 0xa8, 0x15,
+            0x05, 0x8b, 0x7b, 0x59, 0x39, 0xb6, 0x1e, 0x60, 0xcf, 0x82, 0xe9, 0x84, 0x2b, 0xa2,
+            0xe5, 0x95, 0x82, 0x07,
+        ],
+        [
+            0x61, 0xcc, 0xf3, 0x99, 0x3a, 0xbe, 0x4c, 0x44, 0x1a, 0x21, 0x41, 0x4a, 0x27, 0x2e,
+            0x6b, 0x61, 0x2a, 0x47, 0x64, 0x45, 0x86, 0xec, 0x1b, 0x50, 0xa6, 0x27, 0x60, 0x8f,
+            0xf1, 0xe5, 0xa5, 0x2f,
+        ],
+        [
+            0x47, 0xd7, 0xfc, 0x14, 0xa6, 0x56, 0x21, 0x3e, 0xab, 0x28, 0xe2, 0xe3, 0xcc, 0x7a,
+            0x5e, 0xe4, 0x66, 0x1f, 0x94, 0x9e, 0x38, 0x80, 0xb7, 0xec, 0x21, 0xfd, 0xd8, 0xd0,
+            0x76, 0x43, 0x88, 0x0e,
+        ],
+        [
+            0xf2, 0x0a, 0x19, 0xda, 0xe5, 0x75, 0x61, 0xde, 0x33, 0x35, 0x71, 0x57, 0xf9, 0x92,
+            0x58, 0xf9, 0x69, 0xb4, 0x2e, 0xa5, 0xd1, 0x7a, 0x71, 0x28, 0x1e, 0x4f, 0x49, 0x72,
+            0xda, 0x01, 0x72, 0x1b,
+        ],
+        [
+            0x36, 0x76, 0x7d, 0xce, 0xfa, 0x6b, 0xbc, 0xbe, 0xb5, 0x08, 0x08, 0x65, 0xe4, 0xe1,
+            0xe6, 0xa6, 0x19, 0x98, 0x24, 0x01, 0xb2, 0xc0, 0x00, 0x52, 0x38, 0x36, 0x5e, 0x72,
+            0x22, 0x88, 0x8d, 0x1f,
+        ],
+        [
+            0x5a, 0xf8, 0xb5, 0x71, 0x04, 0x9a, 0x87, 0xd0, 0xa8, 0x88, 0xcf, 0x2a, 0xa1, 0xb0,
+            0x62, 0x61, 0xfb, 0xfc, 0x8c, 0xba, 0x89, 0x15, 0x70, 0xb9, 0xaf, 0x4b, 0x91, 0x6c,
+            0xf6, 0x82, 0x5d, 0x2c,
+        ],
+        [
+            0xd0, 0xbf, 0xbf, 0xe0, 0x70, 0xf2, 0x58, 0x64, 0x64, 0xf4, 0x13, 0xa1, 0xaa, 0xc4,
+            0xf5, 0x4e, 0x13, 0xa1, 0x3f, 0xdf, 0x5a, 0x7f, 0x95, 0x20, 0xb8, 0x0b, 0x94, 0xa0,
+            0x48, 0x41, 0xc5, 0x14,
+        ],
+        [
+            0x0c, 0xe8, 0xeb, 0xf4, 0x4b, 0x8e, 0x11, 0x16, 0xd4, 0x89, 0xad, 0x8c, 0x58, 0x25,
+            0xbe, 0x11, 0xaf, 0xb9, 0xd8, 0x44, 0xee, 0xc0, 0x10, 0x1e, 0x96, 0x6f, 0x98, 0x2f,
+            0xb1, 0x33, 0x0d, 0x19,
+        ],
+        [
+            0x92, 0x6c, 0xe0, 0x25, 0x93, 0x64, 0xb3, 0xa5, 0x0a, 0x51, 0xaf, 0x96, 0x65, 0xae,
+            0x67, 0x11, 0xed, 0x73, 0xad, 0x14, 0x49, 0x35, 0x17, 0xac, 0x52, 0x41, 0x70, 0xce,
+            0xa9, 0x8a, 0xf9, 0x22,
+        ],
+        [
+            0x23, 0x73, 0xba, 0x8b, 0xd3, 0x53, 0xb7, 0xf8, 0xee, 0xcc, 0x6e, 0xc6, 0x29, 0x6f,
+            0x52, 0x5a, 0x57, 0x6a, 0xbf, 0x72, 0x8d, 0x22, 0x6f, 0x9f, 0x0b, 0x88, 0xe5, 0x6c,
+            0x9b, 0x7c, 0x7c, 0x2a,
+        ],
+        [
+            0x92, 0xb9, 0x36, 0x3f, 0x64, 0xdd, 0x75, 0x4d, 0x95, 0x8b, 0x98, 0xc2, 0xc9, 0x43,
+            0x00, 0x47, 0xfc, 0x3f, 0x46, 0x4d, 0xc1, 0xf9, 0x7a, 0xc6, 0xc1, 0x8e, 0x69, 0x58,
+            0xe5, 0x86, 0x81, 0x2e,
+        ],
+        [
+            0x0f, 0xf1, 0x1f, 0x1c, 0x9d, 0x24, 0x46, 0x35, 0x27, 0x92, 0x73, 0x64, 0xad, 0x6e,
+            0xef, 0x8a, 0x94, 0xae, 0x0d, 0x05, 0xcf, 0xc8, 0xe2, 0x49, 0xab, 0x4e, 0x9a, 0x1e,
+            0x57, 0xc5, 0x57, 0x0f,
+        ],
+        [
+            0xca, 0x2c, 0xf7, 0x34, 0x61, 0xe3, 0x9c, 0x3c, 0xe4, 0x46, 0x7d, 0x69, 0x10, 0xe3,
+            0x78, 0xfe, 0x1c, 0x0e, 0x80, 0x88, 0x43, 0x3d, 0xf6, 0xd5, 0x4a, 0x55, 0xfb, 0xb5,
+            0x67, 0xee, 0x30, 0x18,
+        ],
+        [
+            0x3e, 0x1f, 0x19, 0x22, 0xdf, 0xb6, 0x71, 0xd3, 0xf9, 0x12, 0xf7, 0xea, 0x46, 0x1e,
+            0x0a, 0x88, 0xee, 0x84, 0x8f, 0xdd, 0xe1, 0x2b, 0x6c, 0x18, 0xab, 0x1a, 0xd2, 0xc5,
+            0x6a, 0xe7, 0x34, 0x21,
+        ],
+        [
+            0xb1, 0xa5, 0x91, 0xdb, 0xf3, 0x8d, 0x8f, 0x8f, 0xa8, 0x3a, 0xee, 0x58, 0xc9, 0xd8,
+            0x51, 0xc0, 0xb0, 0x59, 0x38, 0xf3, 0x66, 0xd8, 0xeb, 0xfe, 0x4f, 0xbc, 0x4e, 0x84,
+            0xec, 0x90, 0xdf, 0x19,
+        ],
+        [
+            0x2b, 0xe5, 0xef, 0x22, 0xf7, 0x05, 0x8c, 0x64, 0xb4, 0x12, 0x49, 0xef, 0x93, 0x0e,
+            0xaf, 0x74, 0x2d, 0x85, 0x84, 0xfd, 0xae, 0x69, 0x1e, 0x98, 0x87, 0x07, 0x5c, 0x6b,
+            0xa6, 0xa2, 0xcc, 0x18,
+        ],
+        [
+            0x8d, 0x53, 0xd2, 0x49, 0x45, 0x64, 0x05, 0xdf, 0xfa, 0x83, 0xad, 0xef, 0xf2, 0x38,
+            0x83, 0x62, 0x3a, 0x47, 0x4f, 0xd5, 0xd2, 0x04, 0x13, 0x4d, 0x1b, 0x0d, 0x23, 0x15,
+            0x94, 0x90, 0x88, 0x23,
+        ],
+        [
+            0x40, 0xd5, 0x9e, 0x52, 0xe4, 0x73, 0xe6, 0x96, 0x1d, 0x0b, 0x8d, 0x9c, 0x2c, 0xaf,
+            0xa2, 0x66, 0xe8, 0x4d, 0x29, 0xb5, 0x43, 0xf5, 0xe8, 0xe9, 0xc0, 0x6c, 0x7b, 0xa9,
+            0xb4, 0x1f, 0x17, 0x27,
+        ],
+        [
+            0x21, 0xae, 0xe6, 0xdd, 0x96, 0x96, 0xd5, 0xf8, 0xe5, 0x83, 0x25, 0x39, 0xb9, 0x30,
+            0xb2, 0xdc, 0x28, 0x0d, 0xfc, 0x74, 0xbc, 0xa0, 0x11, 0x57, 0xfd, 0x29, 0xf6, 0x40,
+            0x05, 0x65, 0xf6, 0x2f,
+        ],
+        [
+            0x18, 0x15, 0x61, 0xce, 0x30, 0x26, 0x93, 0x69, 0x56, 0xd7, 0xad, 0xf6, 0x68, 0x51,
+            0xad, 0xe0, 0xa2, 0x78, 0x77, 0x27, 0xf5, 0xf7, 0x02, 0x59, 0xe9, 0x91, 0xd4, 0x43,
+            0xf1, 0x58, 0x0c, 0x12,
+        ],
+        [
+            0x95, 0x37, 0x48, 0x79, 0xd2, 0x65, 0xd6, 0xa2, 0x1d, 0xa2, 0x65, 0xa5, 0xa0, 0x95,
+            0xc4, 0x1e, 0x07, 0x03, 0xdb, 0xe5, 0xd5, 0x53, 0xf8, 0x7b, 0xb0, 0x21, 0x3f, 0x0d,
+            0xb7, 0xfe, 0x21, 0x1f,
+        ],
+        [
+            0xd2, 0x72, 0x8a, 0xe1, 0xdd, 0xa1, 0xb1, 0x8b, 0x96, 0x9e, 0x8a, 0x06, 0x68, 0xe7,
+            0x26, 0xa8, 0x23, 0x86, 0x6a, 0xf6, 0xc0, 0x8c, 0x63, 0x4c, 0xe1, 0x35, 0x13, 0xa7,
+            0x5f, 0x90, 0xbe, 0x24,
+        ],
+        [
+            0x6d, 0xc2, 0xda, 0x53, 0xe5, 0x74, 0x4c, 0x74, 0x28, 0xc3, 0x65, 0x1d, 0x82, 0xf3,
+            0x7e, 0x59, 0xcd, 0xd4, 0x57, 0xad, 0xde, 0xea, 0x0c, 0xc5, 0x91, 0x74, 0xd1, 0x2e,
+            0xb6, 0x66, 0x86, 0x0f,
+        ],
+        [
+            0xef, 0x59, 0x19, 0x0e, 0x23, 0x2a, 0x1a, 0x3d, 0xb4, 0x8c, 0xe0, 0x6a, 0x3f, 0x7a,
+            0x7a, 0x4e, 0x59, 0x41, 0x1c, 0x1a, 0x4a, 0x3f, 0x41, 0x34, 0xb0, 0x98, 0x2d, 0xf5,
+            0x6b, 0xd4, 0x18, 0x09,
+        ],
+        [
+            0xf2, 0x5f, 0x5c, 0x37, 0xad, 0x13, 0x85, 0x12, 0x65, 0x5a, 0xfc, 0x0a, 0x0d, 0xf9,
+            0x26, 0x2e, 0xfa, 0x4d, 0x40, 0x5e, 0x64, 0x17, 0x69, 0xe7, 0xcd, 0x9e, 0x47, 0x4c,
+            0x1b, 0xb0, 0xbe, 0x1b,
+        ],
+        [
+            0xd9, 0xea, 0x34, 0x97, 0x4c, 0x18, 0x8e, 0xac, 0xd5, 0x19, 0xb1, 0x2a, 0x92, 0xb9,
+            0x60, 0xd5, 0x1e, 0x55, 0xf5, 0xdf, 0x61, 0x6c, 0x7a, 0xa1, 0x42, 0x7e, 0x25, 0x8e,
+            0xc5, 0xa1, 0x68, 0x2f,
+        ],
+    ];
+
+    /// Account size for a tree of `depth` levels: the header, then the frontier and the
+    /// zero hashes with `depth` entries each.
+    pub fn space(depth: u8) -> usize {
+        Self::HEADER_SPACE + 64 * depth as usize
+    }
+
+    /// Splits raw account data into the header and the level storage behind it.
+    pub fn split_data_mut(data: &mut [u8]) -> Result<(&mut CommitmentTree, &mut [[u8; 32]])> {
+        require!(
+            data.len() >= Self::HEADER_SPACE && data.len() != Self::LEGACY_SPACE,
+            PoolError::CommitmentTreeMigrationRequired
+        );
+        let (head, tail) = data.split_at_mut(Self::HEADER_SPACE);
+        let tree = bytemuck::from_bytes_mut::<CommitmentTree>(&mut head[8..]);
+        let whole = tail.len() / 32 * 32;
+        Ok((tree, bytemuck::cast_slice_mut(&mut tail[..whole])))
+    }
+
+    /// Rewrites a pre-version-2 tree in place: the inline frontier and zero hashes move
+    /// behind the header, cut to the tree's depth. Returns the previous version; the
+    /// caller shrinks the account to `space` afterwards.
+    fn unpack_legacy(data: &mut [u8]) -> Result<u8> {
+        require!(
+            data.len() == Self::LEGACY_SPACE,
+            PoolError::CommitmentTreeMigrationRequired
+        );
+        let split = 8 + core::mem::offset_of!(CommitmentTree, canopy);
+        let inline = 32 * Self::DEPTH;
+        let levels = data[split..split + 2 * inline].to_vec();
+        data.copy_within(split + 2 * inline..Self::HEADER_SPACE + 2 * inline, split);
+        let (tree, tail) = data.split_at_mut(Self::HEADER_SPACE);
+        let tree = bytemuck::from_bytes_mut::<CommitmentTree>(&mut tree[8..]);
+        let previous_version = tree.version;
+        let depth = tree.depth();
+        tree.depth = depth as u8;
+        tree.version = Self::CURRENT_VERSION;
+        let row = 32 * depth;
+        tail[..row].copy_from_slice(&levels[..row]);
+        tail[row..2 * row].copy_from_slice(&levels[inline..inline + row]);
+        tail[2 * row..].fill(0);
+        Ok(previous_version)
+    }
+
+    /// The frontier and zero hashes, `depth` entries each, from the level storage.
+    fn levels<'a>(&self, levels: &'a mut [[u8; 32]]) -> Result<TreeLevelsMut<'a>> {
+        let depth = self.depth();
+        require!(
+            levels.len() >= 2 * depth,
+            PoolError::CommitmentTreeMigrationRequired
+        );
+        let (frontier, zeroes) = levels.split_at_mut(depth);
+        Ok((frontier, &mut zeroes[..depth]))
+    }
+
+    pub fn init(
+        &mut self,
+        levels: &mut [[u8; 32]],
+        pool: Pubkey,
+        depth: u8,
+        canopy_depth: u8,
+        bump: u8,
+    ) -> Result<()> {
+        require!(
+            depth >= Self::MIN_DEPTH && (depth as usize) <= Self::DEPTH,
+            PoolError::MerkleDepthInvalid,
+        );
+        require!(
+            canopy_depth <= depth && (canopy_depth as usize) <= Self::MAX_CANOPY,
+            PoolError::CanopyDepthInvalid,
+        );
+        self.pool = pool;
+        self.version = Self::CURRENT_VERSION;
+        self.depth = depth;
+        self.canopy_depth = canopy_depth;
+        self.bump = bump;
+        self.next_index = 0;
+        let (frontier, zeroes) = self.levels(levels)?;
+        Self::compute_zeroes(zeroes);
+        frontier.fill([0u8; 32]);
+        self.current_root = zeroes[depth as usize - 1];
+        self.canopy = [[0u8; 32]; Self::MAX_CANOPY];
+        self.recent_commitments = [[0u8; 32]; Self::MAX_CANOPY];
+        self.recent_amount_commitments = [[0u8; 32]; Self::MAX_CANOPY];
+        self.recent_indices = [0u64; Self::MAX_CANOPY];
+        self.recent_len = 0;
+        Ok(())
+    }
+
+    /// Trees created before depth became configurable store 0 and run at full depth.
+    pub fn depth(&self) -> usize {
+        if self.depth == 0 {
+            Self::DEPTH
+        } else {
+            self.depth as usize
+        }
+    }
+
+    pub fn append_note(
+        &mut self,
+        levels: &mut [[u8; 32]],
+        commitment: [u8; 32],
+        amount_commit: [u8; 32],
+    ) -> Result<([u8; 32], u64)> {
+        self.insert_leaf(levels, commitment, amount_commit)
+    }
+
+    pub fn append_many(
+        &mut self,
+        levels: &mut [[u8; 32]],
+        commitments: &[[u8; 32]],
+        amount_commitments: &[[u8; 32]],
+    ) -> Result<([u8; 32], Vec<u64>)> {
+        if commitments.is_empty() {
+            return Ok((self.current_root, Vec::new()));
+        }
+        require!(
+            commitments.len() == amount_commitments.len(),
+            PoolError::OutputSetMismatch,
+        );
+        let (frontier, zeroes) = self.levels(levels)?;
+        let mut indices = Vec::with_capacity(commitments.len());
+        let mut frontier_cache = ([[0u8; 32]; Self::DEPTH], [false; Self::DEPTH]);
+        let canopy_len = core::cmp::min(self.canopy_depth as usize, Self::MAX_CANOPY);
+        let depth = self.depth();
+        let mut processed = 0usize;
+        let total = commitments.len();
+
+        while processed < total {
+            let remaining = total - processed;
+            let base_index = self.next_index as usize;
+            require!((base_index as u128) < (1u128 << depth), PoolError::TreeFull,);
+
+            let tz = if base_index == 0 {
+                depth
+            } else {
+                core::cmp::min(base_index.trailing_zeros() as usize, depth)
+            };
+
+            let mut chunk_size = (1u128 << tz) as usize;
+            if chunk_size > remaining {
+                chunk_size = highest_power_of_two_leq(remaining);
+            }
+
+            let capacity_remaining = ((1u128 << depth) - base_index as u128) as usize;
+            require!(capacity_remaining > 0, PoolError::TreeFull);
+            chunk_size = core::cmp::min(chunk_size, highest_power_of_two_leq(capacity_remaining));
+
+            let chunk_commitments = &commitments[processed..processed + chunk_size];
+            let chunk_amounts = &amount_commitments[processed..processed + chunk_size];
+
+            for (offset, (commitment, amount_commit)) in chunk_commitments
+                .iter()
+                .zip(chunk_amounts.iter())
+                .enumerate()
+            {
+                let index_position = self
+                    .next_index
+                    .checked_add(offset as u64)
+                    .ok_or(PoolError::AmountOverflow)?;
+                self.record_recent(index_position, *commitment, *amount_commit);
+                indices.push(index_position);
+            }
+
+            let level_start = chunk_size.trailing_zeros() as usize;
+            let mut level_nodes: Vec<Vec<[u8; 32]>> = Vec::with_capacity(level_start + 1);
+            let mut current_level: Vec<[u8; 32]> = chunk_commitments
+                .iter()
+                .map(|commitment| sha_leaf(commitment))
+                .collect();
+            level_nodes.push(current_level.clone());
+
+            for _ in 0..level_start {
+                let mut next_level = Vec::with_capacity(current_level.len() / 2);
+                for pair in current_level.chunks_exact(2) {
+                    next_level.push(sha_branch(&pair[0], &pair[1]));
+                }
+                current_level = next_level;
+                level_nodes.push(current_level.clone());
+            }
+
+            let mut node_bytes = current_level[0];
+
+            for level in 0..level_start {
+                let pos = ((chunk_size - (1 << level) - 1) >> level) as usize;
+                let cached = level_nodes[level][pos];
+                frontier[level] = cached;
+                frontier_cache.0[level] = cached;
+                frontier_cache.1[level] = true;
+            }
+
+            let mut index = (self.next_index + chunk_size as u64 - 1) >> (level_start as u32);
+            let mut level = level_start;
+            while level < depth {
+                if index % 2 == 0 {
+                    frontier_cache.0[level] = node_bytes;
+                    frontier_cache.1[level] = true;
+                    frontier[level] = node_bytes;
+                    let zero = zeroes[level];
+                    node_bytes = sha_branch(&frontier_cache.0[level], &zero);
+                } else {
+                    if !frontier_cache.1[level] {
+                        frontier_cache.0[level] = frontier[level];
+                        frontier_cache.1[level] = true;
+                    }
+                    let left = frontier_cache.0[level];
+                    node_bytes = sha_branch(&left, &node_bytes);
+                }
+                if canopy_len > 0 {
+                    let offset = depth - 1 - level;
+                    if offset < canopy_len {
+                        self.canopy[offset] = node_bytes;
+                    }
+                }
+                index >>= 1;
+                level += 1;
+            }
+
+            self.current_root = node_bytes;
+            self.next_index = self
+                .next_index
+                .checked_add(chunk_size as u64)
+                .ok_or(PoolError::AmountOverflow)?;
+            processed += chunk_size;
+        }
+
+        Ok((self.current_root, indices))
+    }
+
+    fn insert_leaf(
+        &mut self,
+        levels: &mut [[u8; 32]],
+        commitment: [u8; 32],
+        amount_commit: [u8; 32],
+    ) -> Result<([u8; 32], u64)> {
+        let mut frontier_cache = ([[0u8; 32]; Self::DEPTH], [false; Self::DEPTH]);
+        self.insert_leaf_with_cache(levels, &mut frontier_cache, commitment, amount_commit)
+    }
+
+    fn insert_leaf_with_cache(
+        &mut self,
+        levels: &mut [[u8; 32]],
+        frontier_cache: &mut ([[u8; 32]; Self::DEPTH], [bool; Self::DEPTH]),
+        commitment: [u8; 32],
+        amount_commit: [u8; 32],
+    ) -> Result<([u8; 32], u64)> {
+        let (frontier, zeroes) = self.levels(levels)?;
+        let depth = self.depth();
+        require!(
+            (self.next_index as u128) < (1u128 << depth),
+            PoolError::TreeFull,
+        );
+        let index_position = self.next_index;
+        let mut node_bytes = sha_leaf(&commitment);
+        let mut index = self.next_index;
+        let canopy_len = core::cmp::min(self.canopy_depth as usize, Self::MAX_CANOPY);
+        for level in 0..depth {
+            if index % 2 == 0 {
+                frontier_cache.0[level] = node_bytes;
+                frontier_cache.1[level] = true;
+                frontier[level] = node_bytes;
+                let zero = zeroes[level];
+                node_bytes = sha_branch(&frontier_cache.0[level], &zero);
+            } else {
+                if !frontier_cache.1[level] {
+                    frontier_cache.0[level] = frontier[level];
+                    frontier_cache.1[level] = true;
+                }
+                let left = frontier_cache.0[level];
+                node_bytes = sha_branch(&left, &node_bytes);
+            }
+            if canopy_len > 0 {
+                let offset = depth - 1 - level;
+                if offset < canopy_len {
+                    self.canopy[offset] = node_bytes;
+                }
+            }
+            index >>= 1;
+        }
+        self.next_index = self
+            .next_index
+            .checked_add(1)
+            .ok_or(PoolError::AmountOverflow)?;
+        self.current_root = node_bytes;
+        self.record_recent(index_position, commitment, amount_commit);
+        Ok((self.current_root, index_position))
+    }
+
+    fn record_recent(&mut self, index: u64, commitment: [u8; 32], amount_commit: [u8; 32]) {
+        if (self.recent_len as usize) < Self::MAX_CANOPY {
+            let idx = self.recent_len as usize;
+            self.recent_commitments[idx] = commitment;
+            self.recent_amount_commitments[idx] = amount_commit;
+            self.recent_indices[idx] = index;
+            self.recent_len += 1;
+        } else {
+            self.recent_commitments.copy_within(1..Self::MAX_CANOPY, 0);
+            self.recent_amount_commitments
+                .copy_within(1..Self::MAX_CANOPY, 0);
+            self.recent_indices.copy_within(1..Self::MAX_CANOPY, 0);
+            self.recent_commitments[Self::MAX_CANOPY - 1] = commitment;
+            self.recent_amount_commitments[Self::MAX_CANOPY - 1] = amount_commit;
+            self.recent_indices[Self::MAX_CANOPY - 1] = index;
+        }
+    }
+
+    /// Folds a leaf up through `siblings`, choosing the side at each level from the
+    /// corresponding bit of `leaf_index`.
+    pub fn root_from_path(
+        commitment: &[u8; 32],
+        leaf_index: u64,
+        siblings: &[[u8; 32]],
+    ) -> [u8; 32] {
+        let mut node = sha_leaf(commitment);
+        let mut index = leaf_index;
+        for sibling in siblings {
+            node = if index.is_multiple_of(2) {
+                sha_branch(&node, sibling)
+            } else {
+                sha_branch(sibling, &node)
+            };
+            index >>= 1;
+        }
+        node
+    }
+
+    fn compute_zeroes(zeroes: &mut [[u8; 32]]) {
+        let empty_leaf = [0u8; 32];
+        zeroes[0] = sha_leaf(&empty_leaf);
+        for level in 1..zeroes.len() {
+            let prev = zeroes[level - 1];
+            zeroes[level] = sha_branch(&prev, &prev);
+        }
+    }
+}
+
+#[account(zero_copy(unsafe))]
+#[repr(C)]
+pub struct PoolState {
+    pub authority: Pubkey,
+    pub origin_mint: Pubkey,
+    pub vault: Pubkey,
+    pub verifier_program: Pubkey,
+    pub verifying_key: Pubkey,
+    pub commitment_tree: Pubkey,
+    pub verifying_key_id: [u8; 32],
+    pub verifying_key_hash: [u8; 32],
+    pub current_root: [u8; 32],
+    pub recent_roots: [[u8; 32]; PoolState::MAX_ROOTS],
+    pub roots_len: u8,
+    /// Exit rate; occupies the slot of the original single `fee_bps`.
+    pub unshield_fee_bps: u16,
+    /// Low byte of `FeatureFlags`; read it through `features()`.
+    pub features: u8,
+    pub note_ledger: Pubkey,
+    pub note_ledger_bump: u8,
+    pub protocol_fees: u128,
+    pub hook_config: Pubkey,
+    pub hook_config_present: bool,
+    pub hook_config_bump: u8,
+    pub bump: u8,
+    pub twin_mint: Pubkey,
+    pub twin_mint_enabled: bool,
+    pub pending_shield: PendingShield,
+    pub nullifier_shard_count: u8,
+    pub nullifier_mode: u8,
+    pub max_change_notes: u8,
+    pub max_inputs: u8,
+    pub max_outputs: u8,
+    pub fee_recipient: Pubkey,
+    pub shield_fee_bps: u16,
+    /// Base units charged on top of the bps component of each fee.
+    pub shield_flat_fee: u64,
+    pub unshield_flat_fee: u64,
+    /// Delay for queued fee and feature changes; 0 allows direct updates.
+    pub timelock_seconds: i64,
+    pub paused: bool,
+    /// Key replaced by the last `rotate_verifying_key`, still accepted until
+    /// `previous_verifying_key_expiry_slot`.
+    pub previous_verifying_key: Pubkey,
+    pub previous_verifying_key_id: [u8; 32],
+    pub previous_verifying_key_hash: [u8; 32],
+    pub previous_verifying_key_expiry_slot: u64,
+    /// Slot each `recent_roots` entry was recorded at.
+    pub recent_root_slots: [u64; PoolState::MAX_ROOTS],
+    pub max_root_age_slots: u64,
+    pub sequencer: Pubkey,
+    /// Allowed shield amounts; an empty set accepts any amount.
+    pub denominations: [u64; PoolState::MAX_DENOMINATIONS],
+    pub denominations_len: u8,
+    /// Largest single shield amount; 0 means unlimited.
+    pub max_deposit_amount: u64,
+    /// Cap on the vault balance after a shield; 0 means unlimited.
+    pub max_total_locked: u64,
+    /// Rate limiter PDA enforced on shield and unshield; default when unlimited.
+    pub rate_limiter: Pubkey,
+    /// Set once by `enable_emergency_exit`; exits then prove against `emergency_root`.
+    pub emergency_exit: bool,
+    pub emergency_root: [u8; 32],
+    /// Layout version; 0 marks accounts created before versioning.
+    pub version: u8,
+    /// `FEE_ROUNDING_FLOOR` or `FEE_ROUNDING_CEIL` for the bps fee component. Sits
+    /// after `version` so version 1 accounts keep their layout and migrate to floor.
+    pub fee_rounding: u8,
+    /// Fees taken by twin-mode unshields. Backed by origin tokens in the vault and paid
+    /// out as freshly minted pTKN by `withdraw_twin_fees`.
+    pub twin_protocol_fees: u128,
+    /// Commitments that must be appended after a note before it can be spent; 0 disables.
+    pub min_note_age_leaves: u64,
+    /// Co-signer required on every instruction that pushes a root; default when unset.
+    pub root_guardian: Pubkey,
+    /// Verifier programs added by `set_verifier_entry`; unused slots hold the default key.
+    pub verifier_allowlist: [Pubkey; PoolState::MAX_VERIFIER_PROGRAMS],
+    /// Bit `i` set when `verifier_allowlist[i]` may verify proofs.
+    pub verifier_allowlist_enabled: u8,
+    /// Minimum leaves between tree checkpoints; 0 disables them.
+    pub checkpoint_interval: u64,
+    /// Checkpoints written so far; the next one lives at this index.
+    pub checkpoint_count: u64,
+    /// Leaf count covered by the latest checkpoint.
+    pub checkpoint_leaf: u64,
+    /// Digest of the latest checkpoint, chained into the next.
+    pub checkpoint_digest: [u8; 32],
+    /// `PoolStats` account counting activity; default while statistics are off.
+    pub pool_stats: Pubkey,
+    /// `DestinationAllowlist` unshields must pay into; default while the allowlist is off.
+    pub destination_allowlist: Pubkey,
+    /// Referrer share of each shield fee, in bps of the fee.
+    pub referral_fee_bps: u16,
+    /// Fee shares credited to referrers and not yet claimed; held in the vault.
+    pub referral_fees: u128,
+    /// Verifier program and key `emergency_unshield` proves under, pinned by
+    /// `enable_emergency_exit` so later rotations or allowlist changes cannot strand exits.
+    pub emergency_verifier_program: Pubkey,
+    pub emergency_verifying_key: Pubkey,
+    pub emergency_verifying_key_id: [u8; 32],
+    pub emergency_verifying_key_hash: [u8; 32],
+}
+
+impl PoolState {
+    pub const MAX_ROOTS: usize = 16;
+    pub const MAX_CHANGE_NOTES: u8 = 4;
+    pub const MAX_DENOMINATIONS: usize = 8;
+    pub const MAX_VERIFIER_PROGRAMS: usize = 4;
+    pub const CURRENT_VERSION: u8 = 11;
+    pub const SPACE: usize = 8 + core::mem::size_of::<PoolState>() + 64;
+
+    pub fn check_denomination(&self, amount: u64) -> Result<()> {
+        let denominations = &self.denominations[..self.denominations_len as usize];
+        require!(
+            denominations.is_empty() || denominations.contains(&amount),
+            PoolError::DenominationNotAllowed
+        );
+        Ok(())
+    }
+
+    /// `locked_after` is the vault balance once the deposit lands.
+    pub fn check_deposit_limits(&self, amount: u64, locked_after: u64) -> Result<()> {
+        require!(
+            self.max_deposit_amount == 0 || amount <= self.max_deposit_amount,
+            PoolError::DepositLimitExceeded
+        );
+        require!(
+            self.max_total_locked == 0 || locked_after <= self.max_total_locked,
+            PoolError::TotalLockedLimitExceeded
+        );
+        Ok(())
+    }
+
+    pub fn push_root(&mut self, root: [u8; 32], slot: u64) {
+        if self.roots_len as usize >= Self::MAX_ROOTS {
+            for idx in 1..Self::MAX_ROOTS {
+                self.recent_roots[idx - 1] = self.recent_roots[idx];
+                self.recent_root_slots[idx - 1] = self.recent_root_slots[idx];
+            }
+            self.recent_roots[Self::MAX_ROOTS - 1] = root;
+            self.recent_root_slots[Self::MAX_ROOTS - 1] = slot;
+            self.current_root = root;
+        } else {
+            self.recent_roots[self.roots_len as usize] = root;
+            self.recent_root_slots[self.roots_len as usize] = slot;
+            self.roots_len += 1;
+            self.current_root = root;
+        }
+    }
+
+    /// The current root is always accepted; older entries are rejected once they are
+    /// more than `max_root_age_slots` behind `slot` (0 disables expiry).
+    pub fn is_known_root(&self, candidate: &[u8; 32], slot: u64) -> bool {
+        if &self.current_root == candidate {
+            return true;
+        }
+        for idx in 0..self.roots_len as usize {
+            if &self.recent_roots[idx] == candidate && !self.root_expired(idx, slot) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Slot at which `candidate` entered the root history, while it is still there.
+    pub fn root_slot(&self, candidate: &[u8; 32]) -> Option<u64> {
+        (0..self.roots_len as usize)
+            .rev()
+            .find(|&idx| &self.recent_roots[idx] == candidate)
+            .map(|idx| self.recent_root_slots[idx])
+    }
+
+    fn root_expired(&self, idx: usize, slot: u64) -> bool {
+        self.max_root_age_slots > 0
+            && slot.saturating_sub(self.recent_root_slots[idx]) > self.max_root_age_slots
+    }
+
+    /// Pools created before the limit was configurable store 0 and keep the original
+    /// single change note.
+    pub fn change_note_limit(&self) -> usize {
+        core::cmp::max(self.max_change_notes, 1) as usize
+    }
+
+    /// Returns the key, id and hash a proof submitted with `candidate` must match: the
+    /// previous key while its overlap window is open, otherwise the current one.
+    pub fn active_verifying_key(
+        &self,
+        candidate: Pubkey,
+        slot: u64,
+    ) -> (Pubkey, [u8; 32], [u8; 32]) {
+        if candidate != Pubkey::default()
+            && candidate == self.previous_verifying_key
+            && slot <= self.previous_verifying_key_expiry_slot
+        {
+            return (
+                self.previous_verifying_key,
+                self.previous_verifying_key_id,
+                self.previous_verifying_key_hash,
+            );
+        }
+        (
+            self.verifying_key,
+            self.verifying_key_id,
+            self.verifying_key_hash,
+        )
+    }
+
+    /// The `invariant_checks` build feature forces supply checks on for every pool;
+    /// otherwise `FEATURE_INVARIANT_CHECKS` opts a pool in at runtime.
+    pub fn invariant_checks_enabled(&self) -> bool {
+        cfg!(feature = "invariant_checks")
+            || self
+                .features()
+                .contains(FeatureFlags::from(FEATURE_INVARIANT_CHECKS))
+    }
+
+    pub fn features(&self) -> FeatureFlags {
+        FeatureFlags::from(self.features)
+    }
+
+    pub fn leaf_index_enabled(&self) -> bool {
+        self.features()
+            .contains(FeatureFlags::from(FEATURE_LEAF_INDEX))
+    }
+
+    /// Whether hooks are switched on and a hook config with at least one target exists.
+    pub fn hooks_enabled(&self) -> bool {
+        self.features()
+            .contains(FeatureFlags::from(FEATURE_HOOKS_ENABLED))
+            && self.hook_config_present
+    }
+
+    /// Checks a verifier `enable_emergency_exit` may pin: the pinned program or any
+    /// allowlist entry, enabled or not, with the current or previous verifying key, whose
+    /// id and hash are returned.
+    pub fn emergency_verifier(
+        &self,
+        program: &Pubkey,
+        verifying_key: Pubkey,
+    ) -> Result<([u8; 32], [u8; 32])> {
+        require!(
+            *program == self.verifier_program
+                || (*program != Pubkey::default() && self.verifier_allowlist.contains(program)),
+            PoolError::VerifierMismatch
+        );
+        if verifying_key == self.verifying_key {
+            Ok((self.verifying_key_id, self.verifying_key_hash))
+        } else if verifying_key != Pubkey::default() && verifying_key == self.previous_verifying_key
+        {
+            Ok((
+                self.previous_verifying_key_id,
+                self.previous_verifying_key_hash,
+            ))
+        } else {
+            Err(error!(PoolError::VerifierMismatch))
+        }
+    }
+
+    /// An allowlist entry decides for its program; an unlisted program is accepted only
+    /// when it is the pinned `verifier_program`.
+    pub fn check_verifier_program(&self, program: &Pubkey) -> Result<()> {
+        let allowed = match self
+            .verifier_allowlist
+            .iter()
+            .position(|entry| entry == program)
+        {
+            Some(index) => self.verifier_allowlist_enabled & (1 << index) != 0,
+            None => *program == self.verifier_program,
+        };
+        require!(allowed, PoolError::VerifierMismatch);
+        Ok(())
+    }
+
+    pub fn checkpoint_due(&self, leaf_count: u64) -> bool {
+        self.checkpoint_interval > 0
+            && leaf_count.saturating_sub(self.checkpoint_leaf) >= self.checkpoint_interval
+    }
+
+    pub fn check_not_paused(&self) -> Result<()> {
+        require!(!self.paused, PoolError::PoolPaused);
+        Ok(())
+    }
+
+    /// An emergency exit pauses the pool for good.
+    pub fn unpause(&mut self) -> Result<()> {
+        require!(!self.emergency_exit, PoolError::EmergencyExitActive);
+        self.paused = false;
+        Ok(())
+    }
+
+    pub fn check_root_guardian(&self, guardian: Option<&Signer>) -> Result<()> {
+        if self.root_guardian == Pubkey::default() {
+            return Ok(());
+        }
+        let guardian = guardian.ok_or(PoolError::RootGuardianRequired)?;
+        require_keys_eq!(
+            guardian.key(),
+            self.root_guardian,
+            PoolError::RootGuardianRequired
+        );
+        Ok(())
+    }
+
+    /// `leaf_index` is the highest leaf a spend consumes and `leaf_count` the number of
+    /// commitments appended so far.
+    pub fn check_note_age(&self, leaf_index: u64, leaf_count: u64) -> Result<()> {
+        if self.min_note_age_leaves == 0 {
+            return Ok(());
+        }
+        let newer = leaf_count
+            .checked_sub(leaf_index)
+            .and_then(|count| count.checked_sub(1))
+            .ok_or(PoolError::InvalidPublicInputs)?;
+        require!(newer >= self.min_note_age_leaves, PoolError::NoteTooYoung);
+        Ok(())
+    }
+
+    pub fn check_transfer_arity(&self, inputs: usize, outputs: usize) -> Result<()> {
+        require!(
+            inputs > 0
+                && (self.max_inputs == 0 || inputs <= self.max_inputs as usize)
+                && (self.max_outputs == 0 || outputs <= self.max_outputs as usize),
+            PoolError::TransferArityInvalid
+        );
+        Ok(())
+    }
+
+    pub fn shield_fee(&self, amount: u64) -> Result<u64> {
+        self.calculate_fee(amount, self.shield_fee_bps, self.shield_flat_fee)
+    }
+
+    pub fn unshield_fee(&self, amount: u64) -> Result<u64> {
+        self.calculate_fee(amount, self.unshield_fee_bps, self.unshield_flat_fee)
+    }
+
+    /// Lamports `shield_native` wraps so that `amount` lands after the shield fee.
+    pub fn native_shield_deposit(&self, amount: u64) -> Result<u64> {
+        amount
+            .checked_add(self.shield_fee(amount)?)
+            .ok_or_else(|| error!(PoolError::AmountOverflow))
+    }
+
+    /// Splits a `redeem_ptkn` of `amount` into the unshield fee and the origin payout.
+    pub fn ptkn_redemption(&self, amount: u64) -> Result<(u64, u64)> {
+        let fee = self.unshield_fee(amount)?;
+        require!(amount > fee, PoolError::RedeemAmountTooSmall);
+        Ok((fee, amount - fee))
+    }
+
+    /// `amount * fee_bps / 10_000`, rounded per `fee_rounding`, plus `flat_fee`.
+    pub fn calculate_fee(&self, amount: u64, fee_bps: u16, flat_fee: u64) -> Result<u64> {
+        let scaled = (amount as u128)
+            .checked_mul(fee_bps as u128)
+            .ok_or(PoolError::AmountOverflow)?;
+        let fee = if self.fee_rounding == FEE_ROUNDING_CEIL {
+            scaled.div_ceil(10_000u128)
+        } else {
+            scaled / 10_000u128
+        };
+        let fee = fee
+            .checked_add(u128::from(flat_fee))
+            .ok_or(PoolError::AmountOverflow)?;
+        u64::try_from(fee).map_err(|_| error!(PoolError::AmountOverflow))
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "idl-build", derive(IdlBuild))]
+pub struct PendingShield {
+    pub active: u8,
+    pub old_root: [u8; 32],
+    pub new_root: [u8; 32],
+    pub commitment: [u8; 32],
+    pub amount_commit: [u8; 32],
+    pub amount: u64,
+    pub depositor: Pubkey,
+    pub next_index: u64,
+}
+
+impl PendingShield {
+    pub fn inactive() -> Self {
+        Self {
+            active: 0,
+            old_root: [0u8; 32],
+            new_root: [0u8; 32],
+            commitment: [0u8; 32],
+            amount_commit: [0u8; 32],
+            amount: 0,
+            depositor: Pubkey::default(),
+            next_index: 0,
+        }
+    }
+
+    pub fn is_inactive(&self) -> bool {
+        self.active == 0
+    }
+
+    pub fn deactivate(&mut self) {
+        *self = Self::inactive();
+    }
+}
+
+#[account]
+#[repr(C)]
+pub struct ShieldClaim {
+    pub pool: Pubkey,
+    pub depositor: Pubkey,
+    pub commitment: [u8; 32],
+    pub amount_commit: [u8; 32],
+    pub old_root: [u8; 32],
+    pub new_root: [u8; 32],
+    pub amount: u64,
+    pub next_index: u64,
+    pub bump: u8,
+    pub status: u8,
+    pub enforce_invariant: u8,
+    pub tree_level: u8,
+    pub tree_index_cursor: u64,
+    pub tree_node: [u8; 32],
+}
+
+impl ShieldClaim {
+    pub const STATUS_INACTIVE: u8 = 0;
+    pub const STATUS_PENDING_TREE: u8 = 1;
+    pub const STATUS_AWAITING_LEDGER: u8 = 2;
+    pub const STATUS_AWAITING_INVARIANT: u8 = 3;
+    pub const SPACE: usize = 8 + core::mem::size_of::<ShieldClaim>();
+
+    pub fn is_active(&self) -> bool {
+        self.status != Self::STATUS_INACTIVE
+    }
+
+    pub fn is_pending_tree(&self) -> bool {
+        self.status == Self::STATUS_PENDING_TREE
+    }
+
+    pub fn is_awaiting_ledger(&self) -> bool {
+        self.status == Self::STATUS_AWAITING_LEDGER
+    }
+
+    pub fn is_awaiting_invariant(&self) -> bool {
+        self.status == Self::STATUS_AWAITING_INVARIANT
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn activate(
+        &mut self,
+        pool: Pubkey,
+        depositor: Pubkey,
+        commitment: [u8; 32],
+        amount_commit: [u8; 32],
+        old_root: [u8; 32],
+        new_root: [u8; 32],
+        amount: u64,
+        next_index: u64,
+        bump: u8,
+    ) {
+        self.pool = pool;
+        self.depositor = depositor;
+        self.commitment = commitment;
+        self.amount_commit = amount_commit;
+        self.old_root = old_root;
+        self.new_root = new_root;
+        self.amount = amount;
+        self.next_index = next_index;
+        self.bump = bump;
+        self.status = Self::STATUS_PENDING_TREE;
+        self.enforce_invariant = 0;
+        self.tree_level = 0;
+        self.tree_index_cursor = next_index;
+        self.tree_node = commitment;
+    }
+
+    pub fn deactivate(&mut self) {
+        self.depositor = Pubkey::default();
+        self.commitment = [0u8; 32];
+        self.amount_commit = [0u8; 32];
+        self.old_root = [0u8; 32];
+        self.new_root = [0u8; 32];
+        self.amount = 0;
+        self.next_index = 0;
+        self.status = Self::STATUS_INACTIVE;
+        self.enforce_invariant = 0;
+        self.tree_level = 0;
+        self.tree_index_cursor = 0;
+        self.tree_node = [0u8; 32];
+    }
+
+    pub fn mark_tree_complete(&mut self, depth: u8) {
+        self.status = Self::STATUS_AWAITING_LEDGER;
+        self.tree_level = depth;
+    }
+
+    pub fn mark_ledger_complete(&mut self, requires_invariant: bool) {
+        if requires_invariant {
+            self.enforce_invariant = 1;
+            self.status = Self::STATUS_AWAITING_INVARIANT;
+        } else {
+            self.deactivate();
+        }
+    }
+
+    pub fn needs_invariant(&self) -> bool {
+        self.enforce_invariant == 1
+    }
+
+    pub fn snapshot(&self) -> PendingShield {
+        PendingShield {
+            active: if self.is_active() { 1 } else { 0 },
+            old_root: self.old_root,
+            new_root: self.new_root,
+            commitment: self.commitment,
+            amount_commit: self.amount_commit,
+            amount: self.amount,
+            depositor: self.depositor,
+            next_index: self.next_index,
+        }
+    }
+}
+
+#[account(zero_copy(unsafe))]
+#[repr(C)]
+pub struct NullifierSet {
+    pub pool: Pubkey,
+    pub count: u32,
+    pub entries: [[u8; 32]; NullifierSet::MAX_NULLIFIERS],
+    pub bloom: [u8; NullifierSet::BLOOM_BYTES],
+    pub bump: u8,
+    pub shard: u8,
+    /// Layout version; 0 marks accounts created before versioning. Sits in what was
+    /// trailing padding, so `SPACE` and the overflow offset are unchanged.
+    pub version: u8,
+    /// Set by `rotate_nullifier_shard`. An archived shard takes no new entries: spends
+    /// routed to it create `NullifierRecord`s. Also sits in former padding.
+    pub archived: u8,
+}
+
+impl NullifierSet {
+    pub const MAX_NULLIFIERS: usize = 256;
+    pub const BLOOM_BYTES: usize = 512;
+    pub const MAX_SHARDS: u8 = 32;
+    pub const SPACE: usize = 8 + core::mem::size_of::<NullifierSet>() + 64;
+    pub const CURRENT_VERSION: u8 = 1;
+    /// Entries added per `grow_nullifier_set` call; stays under the per-instruction
+    /// realloc limit.
+    pub const GROWTH_ENTRIES: usize = 256;
+    pub const MAX_ACCOUNT_BYTES: usize = 10 * 1024 * 1024;
+
+    /// Routes a nullifier to its shard using the first byte as the prefix key.
+    pub fn shard_index(nullifier: &[u8; 32], shard_count: u8) -> u8 {
+        debug_assert!(shard_count > 0);
+        nullifier[0] % shard_count
+    }
+
+    /// Returns the distinct shards touched by `nullifiers` in ascending order. Callers
+    /// supply the shard accounts in this order at the front of the remaining accounts.
+    pub fn required_shards(nullifiers: &[[u8; 32]], shard_count: u8) -> Vec<u8> {
+        let mut shards: Vec<u8> = nullifiers
+            .iter()
+            .map(|nullifier| Self::shard_index(nullifier, shard_count))
+            .collect();
+        shards.sort_unstable();
+        shards.dedup();
+        shards
+    }
+
+    /// Loads a shard account passed outside of the typed account context, checking the
+    /// owner, discriminator, pool binding, and PDA derivation before handing out the data.
+    pub fn load_shard_data<'a, 'info>(
+        info: &'a AccountInfo<'info>,
+        pool_key: Pubkey,
+        origin_mint: Pubkey,
+        shard: u8,
+    ) -> Result<core::cell::RefMut<'a, &'info mut [u8]>> {
+        require_keys_eq!(*info.owner, crate::ID, PoolError::NullifierShardMismatch);
+        require!(info.is_writable, PoolError::NullifierShardMismatch);
+        let mut data = info.try_borrow_mut_data()?;
+        require!(
+            data.len() >= 8 && &data[..8] == NullifierSet::DISCRIMINATOR,
+            PoolError::NullifierShardMismatch
+        );
+        let (set, _) = Self::split_data_mut(&mut data)?;
+        require_keys_eq!(set.pool, pool_key, PoolError::NullifierShardMismatch);
+        require!(set.shard == shard, PoolError::NullifierShardMismatch);
+        let bump = set.bump;
+        let expected = Pubkey::create_program_address(
+            &[seeds::NULLIFIERS, origin_mint.as_ref(), &[shard], &[bump]],
+            &crate::ID,
+        )
+        .map_err(|_| error!(PoolError::NullifierShardMismatch))?;
+        require_keys_eq!(expected, info.key(), PoolError::NullifierShardMismatch);
+        Ok(data)
+    }
+
+    /// Splits raw account data into the fixed set and the overflow entries appended by
+    /// `grow_nullifier_set`.
+    pub fn split_data_mut(data: &mut [u8]) -> Result<(&mut NullifierSet, &mut [u8])> {
+        require!(data.len() >= Self::SPACE, PoolError::NullifierSetInvalid);
+        let (head, overflow) = data.split_at_mut(Self::SPACE);
+        let set = bytemuck::from_bytes_mut::<NullifierSet>(
+            &mut head[8..8 + core::mem::size_of::<NullifierSet>()],
+        );
+        Ok((set, overflow))
+    }
+
+    pub fn split_data(data: &[u8]) -> Result<(&NullifierSet, &[u8])> {
+        require!(data.len() >= Self::SPACE, PoolError::NullifierSetInvalid);
+        let (head, overflow) = data.split_at(Self::SPACE);
+        let set = bytemuck::from_bytes::<NullifierSet>(
+            &head[8..8 + core::mem::size_of::<NullifierSet>()],
+        );
+        Ok((set, overflow))
+    }
+
+    pub fn capacity(overflow: &[u8]) -> usize {
+        Self::MAX_NULLIFIERS + overflow.len() / 32
+    }
+
+    pub fn insert(&mut self, overflow: &mut [u8], value: [u8; 32]) -> Result<()> {
+        if self.contains(overflow, &value) {
+            return err!(PoolError::NullifierReuse);
+        }
+        let count = self.count as usize;
+        require!(
+            count < Self::capacity(overflow),
+            PoolError::NullifierCapacity,
+        );
+        if count < Self::MAX_NULLIFIERS {
+            self.entries[count] = value;
+        } else {
+            let start = (count - Self::MAX_NULLIFIERS) * 32;
+            overflow[start..start + 32].copy_from_slice(&value);
+        }
+        self.count += 1;
+        self.set_bloom_bits(&value);
+        Ok(())
+    }
+
+    /// Hash over the entries the set holds, in insertion order.
+    pub fn digest(&self, overflow: &[u8]) -> [u8; 32] {
+        let count = self.count as usize;
+        let fixed = core::cmp::min(count, Self::MAX_NULLIFIERS);
+        let spilled = (count - fixed) * 32;
+        let mut chunks: Vec<&[u8]> = self.entries[..fixed]
+            .iter()
+            .map(|entry| &entry[..])
+            .collect();
+        chunks.push(&overflow[..spilled]);
+        hashv(&chunks).to_bytes()
+    }
+
+    /// Removes up to `max` entries from the end of the set and returns them, newest
+    /// first. Bloom bits are only cleared once the set is empty; until then a stale bit
+    /// just falls through to the entry scan, which covers live entries only.
+    pub fn pop_entries(&mut self, overflow: &mut [u8], max: usize) -> Vec<[u8; 32]> {
+        let take = core::cmp::min(max, self.count as usize);
+        let mut popped = Vec::with_capacity(take);
+        for _ in 0..take {
+            let index = self.count as usize - 1;
+            let mut value = [0u8; 32];
+            if index < Self::MAX_NULLIFIERS {
+                value = self.entries[index];
+                self.entries[index] = [0u8; 32];
+            } else {
+                let start = (index - Self::MAX_NULLIFIERS) * 32;
+                value.copy_from_slice(&overflow[start..start + 32]);
+                overflow[start..start + 32].fill(0);
+            }
+            self.count -= 1;
+            popped.push(value);
+        }
+        if self.count == 0 {
+            self.bloom = [0u8; Self::BLOOM_BYTES];
+        }
+        popped
+    }
+
+    fn contains(&self, overflow: &[u8], value: &[u8; 32]) -> bool {
+        if !self.test_bloom_bits(value) {
+            return false;
+        }
+        let count = self.count as usize;
+        let fixed = core::cmp::min(count, Self::MAX_NULLIFIERS);
+        if self.entries[..fixed].iter().any(|entry| entry == value) {
+            return true;
+        }
+        overflow
+            .chunks_exact(32)
+            .take(count.saturating_sub(Self::MAX_NULLIFIERS))
+            .any(|entry| entry == &value[..])
+    }
+
+    fn set_bloom_bits(&mut self, value: &[u8; 32]) {
+        for position in Self::bloom_positions(value) {
+            let byte_index = position / 8;
+            let bit_index = position % 8;
+            self.bloom[byte_index] |= 1 << bit_index;
+        }
+    }
+
+    fn test_bloom_bits(&self, value: &[u8; 32]) -> bool {
+        for position in Self::bloom_positions(value) {
+            let byte_index = position / 8;
+            let bit_index = position % 8;
+            if (self.bloom[byte_index] & (1 << bit_index)) == 0 {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn bloom_positions(value: &[u8; 32]) -> [usize; 3] {
+        let mut hasher = Keccak256::new();
+        hasher.update(value);
+        let bytes: [u8; 32] = hasher.finalize().into();
+        let mut positions = [0usize; 3];
+        for (idx, chunk) in positions.iter_mut().enumerate() {
+            let start = idx * 8;
+            let mut slice = [0u8; 8];
+            slice.copy_from_slice(&bytes[start..start + 8]);
+            let value = u64::from_le_bytes(slice) as usize;
+            *chunk = value % (Self::BLOOM_BYTES * 8);
+        }
+        positions
+    }
+}
+
+#[account]
+pub struct NullifierRecord {
+    pub pool: Pubkey,
+    pub nullifier: [u8; 32],
+    pub spent_slot: u64,
+    pub bump: u8,
+}
+
+impl NullifierRecord {
+    pub const SPACE: usize = 8 + 32 + 32 + 8 + 1;
+
+    pub fn address(pool: &Pubkey, nullifier: &[u8; 32]) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[seeds::NULLIFIER, pool.as_ref(), nullifier], &crate::ID)
+    }
+
+    /// Creates the record PDA for `nullifier`. An existing record means the note was
+    /// already spent, so the account must still be an empty system account.
+    fn create<'info>(
+        info: &AccountInfo<'info>,
+        payer: &Signer<'info>,
+        system_program: &Program<'info, System>,
+        pool: Pubkey,
+        nullifier: [u8; 32],
+        slot: u64,
+    ) -> Result<()> {
+        let (expected, bump) = Self::address(&pool, &nullifier);
+        require_keys_eq!(expected, info.key(), PoolError::NullifierRecordMismatch);
+        require!(info.owner != &crate::ID, PoolError::NullifierReuse);
+        require!(
+            info.owner == &anchor_lang::system_program::ID && info.data_is_empty(),
+            PoolError::NullifierRecordMismatch
+        );
+
+        let bump_seed = [bump];
+        let signer_seeds: [&[u8]; 4] = [seeds::NULLIFIER, pool.as_ref(), &nullifier, &bump_seed];
+        create_program_account(info, payer, system_program, &signer_seeds, Self::SPACE)?;
+
+        let record = NullifierRecord {
+            pool,
+            nullifier,
+            spent_slot: slot,
+            bump,
+        };
+        let mut data = info.try_borrow_mut_data()?;
+        let mut writer: &mut [u8] = &mut data;
+        record.try_serialize(&mut writer)?;
+        Ok(())
+    }
+}
+
+/// Written by an unshield that asks for it, so escrow and claim programs can prove a
+/// withdrawal happened without scanning logs. Keyed by the first spent nullifier.
+#[account]
+pub struct UnshieldReceipt {
+    pub pool: Pubkey,
+    pub nullifier: [u8; 32],
+    pub amount: u64,
+    pub destination: Pubkey,
+    pub mode: u8,
+    pub slot: u64,
+    pub bump: u8,
+}
+
+impl UnshieldReceipt {
+    pub const SPACE: usize = 8 + 32 + 32 + 8 + 32 + 1 + 8 + 1;
+
+    pub fn address(pool: &Pubkey, nullifier: &[u8; 32]) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[seeds::RECEIPT, pool.as_ref(), nullifier], &crate::ID)
+    }
+
+    fn create<'info>(
+        info: &AccountInfo<'info>,
+        payer: &Signer<'info>,
+        system_program: &Program<'info, System>,
+        receipt: UnshieldReceipt,
+    ) -> Result<()> {
+        let (expected, bump) = Self::address(&receipt.pool, &receipt.nullifier);
+        require_keys_eq!(expected, info.key(), PoolError::ReceiptInvalid);
+        require!(
+            info.owner == &anchor_lang::system_program::ID && info.data_is_empty(),
+            PoolError::ReceiptInvalid
+        );
+        let bump_seed = [bump];
+        let signer_seeds: [&[u8]; 4] = [
+            seeds::RECEIPT,
+            receipt.pool.as_ref(),
+            &receipt.nullifier,
+            &bump_seed,
+        ];
+        create_program_account(info, payer, system_program, &signer_seeds, Self::SPACE)?;
+
+        let receipt = UnshieldReceipt { bump, ..receipt };
+        let mut data = info.try_borrow_mut_data()?;
+        let mut writer: &mut [u8] = &mut data;
+        receipt.try_serialize(&mut writer)?;
+        Ok(())
+    }
+}
+
+/// Holds an unshield until its recipient and arbiter both sign `release_escrow`, at
+/// `["escrow", pool_state, recipient, arbiter, nullifier]`. The PDA also owns
+/// `token_account`, where the unshield paid out.
+#[account]
+pub struct UnshieldEscrow {
+    pub pool: Pubkey,
+    pub nullifier: [u8; 32],
+    pub recipient: Pubkey,
+    pub arbiter: Pubkey,
+    pub token_account: Pubkey,
+    pub amount: u64,
+    pub bump: u8,
+}
+
+impl UnshieldEscrow {
+    pub const SPACE: usize = 8 + 32 + 32 + 32 + 32 + 32 + 8 + 1;
+
+    pub fn address(
+        pool: &Pubkey,
+        recipient: &Pubkey,
+        arbiter: &Pubkey,
+        nullifier: &[u8; 32],
+    ) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[
+                seeds::ESCROW,
+                pool.as_ref(),
+                recipient.as_ref(),
+                arbiter.as_ref(),
+                nullifier,
+            ],
+            &crate::ID,
+        )
+    }
+
+    fn create<'info>(
+        info: &AccountInfo<'info>,
+        payer: &Signer<'info>,
+        system_program: &Program<'info, System>,
+        escrow: UnshieldEscrow,
+    ) -> Result<()> {
+        let (expected, bump) = Self::address(
+            &escrow.pool,
+            &escrow.recipient,
+            &escrow.arbiter,
+            &escrow.nullifier,
+        );
+        require_keys_eq!(expected, info.key(), PoolError::EscrowInvalid);
+        require!(
+            info.owner == &anchor_lang::system_program::ID && info.data_is_empty(),
+            PoolError::EscrowInvalid
+        );
+        let bump_seed = [bump];
+        let signer_seeds: [&[u8]; 6] = [
+            seeds::ESCROW,
+            escrow.pool.as_ref(),
+            escrow.recipient.as_ref(),
+            escrow.arbiter.as_ref(),
+            &escrow.nullifier,
+            &bump_seed,
+        ];
+        create_program_account(info, payer, system_program, &signer_seeds, Self::SPACE)?;
+
+        let escrow = UnshieldEscrow { bump, ..escrow };
+        let mut data = info.try_borrow_mut_data()?;
+        let mut writer: &mut [u8] = &mut data;
+        escrow.try_serialize(&mut writer)?;
+        Ok(())
+    }
+}
+
+/// Maps a commitment to where it sits in the pool's tree so light clients can find
+/// their notes with one lookup. Only written while `FEATURE_LEAF_INDEX` is on.
+#[account]
+pub struct LeafIndexRecord {
+    pub pool: Pubkey,
+    pub commitment: [u8; 32],
+    pub tree: Pubkey,
+    pub leaf_index: u64,
+    pub bump: u8,
+}
+
+impl LeafIndexRecord {
+    pub const SPACE: usize = 8 + 32 + 32 + 32 + 8 + 1;
+
+    pub fn address(pool: &Pubkey, commitment: &[u8; 32]) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[seeds::LEAF, pool.as_ref(), commitment], &crate::ID)
+    }
+
+    fn create<'info>(
+        info: &AccountInfo<'info>,
+        payer: &Signer<'info>,
+        system_program: &Program<'info, System>,
+        record: LeafIndexRecord,
+    ) -> Result<()> {
+        let (expected, bump) = Self::address(&record.pool, &record.commitment);
+        require_keys_eq!(expected, info.key(), PoolError::LeafRecordInvalid);
+        require!(
+            info.owner == &anchor_lang::system_program::ID && info.data_is_empty(),
+            PoolError::LeafRecordInvalid
+        );
+        let bump_seed = [bump];
+        let signer_seeds: [&[u8]; 4] = [
+            seeds::LEAF,
+            record.pool.as_ref(),
+            &record.commitment,
+            &bump_seed,
+        ];
+        create_program_account(info, payer, system_program, &signer_seeds, Self::SPACE)?;
+
+        let record = LeafIndexRecord { bump, ..record };
+        let mut data = info.try_borrow_mut_data()?;
+        let mut writer: &mut [u8] = &mut data;
+        record.try_serialize(&mut writer)?;
+        Ok(())
+    }
+}
+
+/// Creates a `space`-byte account owned by this program at the PDA signed for by
+/// `signer_seeds`, funded by `payer`. Handles addresses that were pre-funded with
+/// lamports, which `create_account` would reject.
+fn create_program_account<'info>(
+    info: &AccountInfo<'info>,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+    signer_seeds: &[&[u8]],
+    space: usize,
+) -> Result<()> {
+    create_pda_account(info, payer, system_program, signer_seeds, space, &crate::ID)
+}
+
+/// `create_program_account` for a PDA handed to another program, such as a token account.
+fn create_pda_account<'info>(
+    info: &AccountInfo<'info>,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+    signer_seeds: &[&[u8]],
+    space: usize,
+    owner: &Pubkey,
+) -> Result<()> {
+    let signer = &[signer_seeds];
+    let required_lamports = Rent::get()?.minimum_balance(space);
+    let current_lamports = info.lamports();
+    if current_lamports == 0 {
+        anchor_lang::system_program::create_account(
+            CpiContext::new_with_signer(
+                system_program.to_account_info(),
+                anchor_lang::system_program::CreateAccount {
+                    from: payer.to_account_info(),
+                    to: info.clone(),
+                },
+                signer,
+            ),
+            required_lamports,
+            space as u64,
+            owner,
+        )?;
+    } else {
+        let top_up = required_lamports.saturating_sub(current_lamports);
+        if top_up > 0 {
+            anchor_lang::system_program::transfer(
+                CpiContext::new(
+                    system_program.to_account_info(),
+                    anchor_lang::system_program::Transfer {
+                        from: payer.to_account_info(),
+                        to: info.clone(),
+                    },
+                ),
+                top_up,
+            )?;
+        }
+        anchor_lang::system_program::allocate(
+            CpiContext::new_with_signer(
+                system_program.to_account_info(),
+                anchor_lang::system_program::Allocate {
+                    account_to_allocate: info.clone(),
+                },
+                signer,
+            ),
+            space as u64,
+        )?;
+        anchor_lang::system_program::assign(
+            CpiContext::new_with_signer(
+                system_program.to_account_info(),
+                anchor_lang::system_program::Assign {
+                    account_to_assign: info.clone(),
+                },
+                signer,
+            ),
+            owner,
+        )?;
+    }
+    Ok(())
+}
+
+#[account(zero_copy(unsafe))]
+#[repr(C)]
+pub struct NoteLedger {
+    pub pool: Pubkey,
+    pub total_minted: u128,
+    pub total_spent: u128,
+    pub live_value: u128,
+    pub notes_created: u64,
+    pub notes_consumed: u64,
+    pub amount_commitment_digest: [u8; 32],
+    pub nullifier_digest: [u8; 32],
+    pub bump: u8,
+    pub nullifier_epoch: u64,
+    pub archived_nullifier_digest: [u8; 32],
+    /// Layout version; 0 marks accounts created before versioning.
+    pub version: u8,
+    /// Slot the current nullifier epoch began at.
+    pub epoch_start_slot: u64,
+    /// Slot the most recently archived epoch began at. Spends must prove against a root
+    /// recorded at or after it; 0 until the second rotation.
+    pub archived_epoch_start_slot: u64,
+}
+
+// Bypass invariant enforcement for routine low-value traffic.
+const INVARIANT_CHECK_MIN_NOTE_AMOUNT: u64 = 100_000_000;
+// Sample the invariant check every N wraps for sub-threshold flows.
+const INVARIANT_CHECK_SAMPLE_INTERVAL: u64 = 16;
+
+impl NoteLedger {
+    pub const SPACE: usize = 8 + core::mem::size_of::<NoteLedger>() + 64;
+    pub const CURRENT_VERSION: u8 = 1;
+
+    pub fn init(&mut self, pool: Pubkey, bump: u8) {
+        self.pool = pool;
+        self.total_minted = 0;
+        self.total_spent = 0;
+        self.live_value = 0;
+        self.notes_created = 0;
+        self.notes_consumed = 0;
+        self.amount_commitment_digest = [0u8; 32];
+        self.nullifier_digest = [0u8; 32];
+        self.bump = bump;
+        self.nullifier_epoch = 0;
+        self.archived_nullifier_digest = [0u8; 32];
+        self.version = Self::CURRENT_VERSION;
+        self.epoch_start_slot = 0;
+        self.archived_epoch_start_slot = 0;
+    }
+
+    pub fn record_shield(&mut self, amount: u64, amount_commit: [u8; 32]) -> Result<()> {
+        self.total_minted = self
+            .total_minted
+            .checked_add(u128::from(amount))
+            .ok_or(PoolError::AmountOverflow)?;
+        self.live_value = self
+            .live_value
+            .checked_add(u128::from(amount))
+            .ok_or(PoolError::AmountOverflow)?;
+        self.notes_created = self
+            .notes_created
+            .checked_add(1)
+            .ok_or(PoolError::AmountOverflow)?;
+        self.absorb_amount_commitments(core::slice::from_ref(&amount_commit));
+        Ok(())
+    }
+
+    pub fn record_transfer(
+        &mut self,
+        nullifiers: &[[u8; 32]],
+        amount_commitments: &[[u8; 32]],
+    ) -> Result<()> {
+        if !nullifiers.is_empty() {
+            self.absorb_nullifiers(nullifiers);
+            self.notes_consumed = self
+                .notes_consumed
+                .checked_add(nullifiers.len() as u64)
+                .ok_or(PoolError::AmountOverflow)?;
+        }
+        if !amount_commitments.is_empty() {
+            self.absorb_amount_commitments(amount_commitments);
+            self.notes_created = self
+                .notes_created
+                .checked_add(amount_commitments.len() as u64)
+                .ok_or(PoolError::AmountOverflow)?;
+        }
+        Ok(())
+    }
+
+    pub fn record_unshield(
+        &mut self,
+        total_spent: u64,
+        nullifiers: &[[u8; 32]],
+        output_amount_commitments: &[[u8; 32]],
+    ) -> Result<()> {
+        self.total_spent = self
+            .total_spent
+            .checked_add(u128::from(total_spent))
+            .ok_or(PoolError::AmountOverflow)?;
+        self.live_value = self
+            .live_value
+            .checked_sub(u128::from(total_spent))
+            .ok_or(PoolError::InsufficientLiquidity)?;
+        if !nullifiers.is_empty() {
+            self.absorb_nullifiers(nullifiers);
+            self.notes_consumed = self
+                .notes_consumed
+                .checked_add(nullifiers.len() as u64)
+                .ok_or(PoolError::AmountOverflow)?;
+        }
+        if !output_amount_commitments.is_empty() {
+            self.absorb_amount_commitments(output_amount_commitments);
+            self.notes_created = self
+                .notes_created
+                .checked_add(output_amount_commitments.len() as u64)
+                .ok_or(PoolError::AmountOverflow)?;
+        }
+        Ok(())
+    }
+
+    pub fn ensure_capacity(&self, amount: u64) -> Result<()> {
+        require!(
+            self.live_value >= u128::from(amount),
+            PoolError::InsufficientLiquidity
+        );
+        Ok(())
+    }
+
+    pub fn should_enforce_invariant(&self, note_amount: u64) -> bool {
+        if note_amount >= INVARIANT_CHECK_MIN_NOTE_AMOUNT {
+            return true;
+        }
+        if INVARIANT_CHECK_SAMPLE_INTERVAL == 0 {
+            return true;
+        }
+        let operations = self.notes_created.saturating_add(self.notes_consumed);
+        operations % INVARIANT_CHECK_SAMPLE_INTERVAL == 0
+    }
+
+    fn absorb_amount_commitments(&mut self, commits: &[[u8; 32]]) {
+        if commits.is_empty() {
+            return;
+        }
+
+        let mut digest = self.amount_commitment_digest;
+        for commit in commits {
+            digest = hashv(&[&digest, &commit[..]]).to_bytes();
+        }
+        self.amount_commitment_digest = digest;
+    }
+
+    /// Folds a drained shard into the Poseidon accumulator and advances the epoch, which
+    /// starts at `slot`.
+    pub fn archive_nullifiers(&mut self, shard_digest: [u8; 32], slot: u64) -> Result<()> {
+        let accumulator = Fr::from_le_bytes_mod_order(&self.archived_nullifier_digest);
+        let leaf = Fr::from_le_bytes_mod_order(&shard_digest);
+        self.archived_nullifier_digest = fr_to_bytes(&poseidon::hash_two(&accumulator, &leaf));
+        self.nullifier_epoch = self
+            .nullifier_epoch
+            .checked_add(1)
+            .ok_or(PoolError::AmountOverflow)?;
+        self.archived_epoch_start_slot = self.epoch_start_slot;
+        self.epoch_start_slot = slot;
+        Ok(())
+    }
+
+    /// Rejects a spend against a root older than the archived epoch. The current root is
+    /// always accepted, as in `PoolState::is_known_root`.
+    pub fn check_root_epoch(&self, pool_state: &PoolState, root: &[u8; 32]) -> Result<()> {
+        if &pool_state.current_root == root {
+            return Ok(());
+        }
+        require!(
+            pool_state
+                .root_slot(root)
+                .is_some_and(|slot| slot >= self.archived_epoch_start_slot),
+            PoolError::RootBeforeArchivedEpoch
+        );
+        Ok(())
+    }
+
+    fn absorb_nullifiers(&mut self, nullifiers: &[[u8; 32]]) {
+        if nullifiers.is_empty() {
+            return;
+        }
+
+        let mut digest = self.nullifier_digest;
+        for nullifier in nullifiers {
+            digest = hashv(&[&digest, &nullifier[..]]).to_bytes();
+        }
+        self.nullifier_digest = digest;
+    }
+}
+
+fn instruction_discriminator(name: &str) -> [u8; 8] {
+    let hash = hashv(&[b"global", name.as_bytes()]);
+    let mut out = [0u8; 8];
+    out.copy_from_slice(&hash.to_bytes()[..8]);
+    out
+}
+
+fn sha_leaf(data: &[u8; 32]) -> [u8; 32] {
+    hashv(&[&data[..]]).to_bytes()
+}
+
+fn sha_branch(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    hashv(&[&left[..], &right[..]]).to_bytes()
+}
+
+fn parse_field_elements(bytes: &[u8]) -> Result<Vec<[u8; 32]>> {
+    require!(bytes.len() % 32 == 0, PoolError::InvalidPublicInputs);
+    let mut elements = Vec::with_capacity(bytes.len() / 32);
+    for chunk in bytes.chunks(32) {
+        let mut elem = [0u8; 32];
+        elem.copy_from_slice(chunk);
+        elements.push(elem);
+    }
+    Ok(elements)
+}
+
+fn u64_to_field_bytes(value: u64) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    out[..8].copy_from_slice(&value.to_le_bytes());
+    out
+}
+
+fn u8_to_field_bytes(value: u8) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    out[0] = value;
+    out
+}
+
+fn pubkey_to_field_bytes(pubkey: &Pubkey) -> [u8; 32] {
+    let mut bytes = pubkey.to_bytes();
+    bytes.reverse();
+    bytes
+}
+
+fn field_bytes_to_u128_le(bytes: &[u8; 32]) -> u128 {
+    let mut value = 0u128;
+    for (idx, byte) in bytes.iter().enumerate().take(16) {
+        value |= (*byte as u128) << (idx * 8);
+    }
+    value
+}
+
+fn field_to_u64(bytes: &[u8; 32]) -> Result<u64> {
+    require!(
+        bytes[8..].iter().all(|b| *b == 0),
+        PoolError::InvalidPublicInputs
+    );
+    Ok(u64::from_le_bytes(bytes[..8].try_into().unwrap()))
+}
+
+/// Amounts are raw base units whatever the mint's decimals, so an 18-decimal mint fits
+/// the same u64 as a 6-decimal one. Any bit above the low 64 is rejected rather than
+/// truncated.
+fn decode_amount_from_field(bytes: &[u8; 32], _decimals: u8) -> Result<u64> {
+    require!(
+        bytes[16..].iter().all(|byte| *byte == 0),
+        PoolError::AmountOverflow
+    );
+    let raw = field_bytes_to_u128_le(bytes);
+    u64::try_from(raw).map_err(|_| error!(PoolError::AmountOverflow))
+}
+
+/// Rejects mints the factory authority has frozen with `freeze_mapping`.
+fn ensure_mint_active(mint_mapping: &MintMapping) -> Result<()> {
+    require!(
+        mint_mapping.status != MintStatus::Frozen as u8,
+        PoolError::MintFrozen
+    );
+    Ok(())
+}
+
+/// Applies the factory's `ProtocolConfig`: the kill switch halts the call outright, and
+/// the pool's rates and verifier program must sit within the protocol-wide limits.
+fn ensure_protocol_allows(
+    protocol_config: &ProtocolConfig,
+    pool_state: &PoolState,
+    verifier_program: &Pubkey,
+) -> Result<()> {
+    require!(!protocol_config.kill_switch, PoolError::ProtocolHalted);
+    require!(
+        pool_state.shield_fee_bps <= protocol_config.max_fee_bps
+            && pool_state.unshield_fee_bps <= protocol_config.max_fee_bps,
+        PoolError::FeeAboveProtocolMax
+    );
+    require!(
+        protocol_config.is_verifier_program(verifier_program),
+        PoolError::VerifierNotAllowedByProtocol
+    );
+    Ok(())
+}
+
+/// Emergency exits prove against the frozen `emergency_root`. The tree stays frozen, so
+/// change notes would be lost; exits must spend the full note value.
+fn check_emergency_exit(pool_state: &PoolState, args: &UnshieldManyArgs) -> Result<()> {
+    require!(
+        args.old_root == pool_state.emergency_root,
+        PoolError::UnknownRoot
+    );
+    require!(
+        args.output_commitments.is_empty() && args.output_amount_commitments.is_empty(),
+        PoolError::EmergencyChangeNotAllowed
+    );
+    Ok(())
+}
+
+fn ensure_direct_update_allowed(pool_state: &PoolState) -> Result<()> {
+    if pool_state.timelock_seconds > 0 {
+        return Err(error!(PoolError::TimelockOnlyQueue));
+    }
+    Ok(())
+}
+
+/// Factory-governed pools take `set_fee` and `set_features` from factory timelock
+/// actions, which have already waited out the factory's delay, so the factory state PDA
+/// is not held to the pool's own.
+fn ensure_direct_update_allowed_by(pool_state: &PoolState, authority: &Pubkey) -> Result<()> {
+    if *authority == factory_state_address() {
+        return Ok(());
+    }
+    ensure_direct_update_allowed(pool_state)
+}
+
+fn factory_state_address() -> Pubkey {
+    Pubkey::find_program_address(
+        &[seeds::FACTORY, ptf_factory::ID.as_ref()],
+        &ptf_factory::ID,
+    )
+    .0
+}
+
+fn apply_fee_update(
+    pool_state: &mut PoolState,
+    shield_fee_bps: u16,
+    unshield_fee_bps: u16,
+    shield_flat_fee: u64,
+    unshield_flat_fee: u64,
+) -> Result<()> {
+    require!(
+        shield_fee_bps <= MAX_BPS && unshield_fee_bps <= MAX_BPS,
+        PoolError::InvalidFeeBps
+    );
+    pool_state.shield_fee_bps = shield_fee_bps;
+    pool_state.unshield_fee_bps = unshield_fee_bps;
+    pool_state.shield_flat_fee = shield_flat_fee;
+    pool_state.unshield_flat_fee = unshield_flat_fee;
+    emit!(FeeUpdated {
+        origin_mint: pool_state.origin_mint,
+        shield_fee_bps,
+        unshield_fee_bps,
+        shield_flat_fee,
+        unshield_flat_fee,
+    });
+    Ok(())
+}
+
+fn apply_fee_rounding_update(pool_state: &mut PoolState, fee_rounding: u8) -> Result<()> {
+    require!(
+        fee_rounding == FEE_ROUNDING_FLOOR || fee_rounding == FEE_ROUNDING_CEIL,
+        PoolError::FeeRoundingInvalid
+    );
+    pool_state.fee_rounding = fee_rounding;
+    emit!(FeeRoundingUpdated {
+        origin_mint: pool_state.origin_mint,
+        fee_rounding,
+    });
+    Ok(())
+}
+
+fn apply_verifier_entry_update(
+    pool_state: &mut PoolState,
+    program: Pubkey,
+    enabled: bool,
+) -> Result<()> {
+    require!(program != Pubkey::default(), PoolError::VerifierMismatch);
+    let index = pool_state
+        .verifier_allowlist
+        .iter()
+        .position(|entry| *entry == program)
+        .or_else(|| {
+            pool_state
+                .verifier_allowlist
+                .iter()
+                .position(|entry| *entry == Pubkey::default())
+        })
+        .ok_or(PoolError::VerifierAllowlistFull)?;
+    pool_state.verifier_allowlist[index] = program;
+    if enabled {
+        pool_state.verifier_allowlist_enabled |= 1 << index;
+    } else {
+        pool_state.verifier_allowlist_enabled &= !(1 << index);
+    }
+    emit!(VerifierEntryUpdated {
+        origin_mint: pool_state.origin_mint,
+        program,
+        enabled,
+    });
+    Ok(())
+}
+
+fn apply_referral_fee_share_update(
+    pool_state: &mut PoolState,
+    referral_fee_bps: u16,
+) -> Result<()> {
+    require!(referral_fee_bps <= MAX_BPS, PoolError::InvalidFeeBps);
+    pool_state.referral_fee_bps = referral_fee_bps;
+    emit!(ReferralFeeShareUpdated {
+        origin_mint: pool_state.origin_mint,
+        referral_fee_bps,
+    });
+    Ok(())
+}
+
+fn apply_root_guardian_update(pool_state: &mut PoolState, root_guardian: Pubkey) {
+    pool_state.root_guardian = root_guardian;
+    emit!(RootGuardianUpdated {
+        origin_mint: pool_state.origin_mint,
+        root_guardian,
+    });
+}
+
+fn apply_features_update(pool_state: &mut PoolState, features: u8) {
+    pool_state.features = features;
+    emit!(FeaturesUpdated {
+        origin_mint: pool_state.origin_mint,
+        features,
+    });
+}
+
+fn apply_sequencer_update(pool_state: &mut PoolState, sequencer: Pubkey) {
+    pool_state.sequencer = sequencer;
+    emit!(SequencerUpdated {
+        origin_mint: pool_state.origin_mint,
+        sequencer,
+    });
+}
+
+fn apply_timelock_update(pool_state: &mut PoolState, timelock_seconds: i64) -> Result<()> {
+    require!(timelock_seconds >= 0, PoolError::TimelockInvalid);
+    pool_state.timelock_seconds = timelock_seconds;
+    emit!(PoolTimelockUpdated {
+        origin_mint: pool_state.origin_mint,
+        timelock_seconds,
+    });
+    Ok(())
+}
+
+/// Checks the shield public inputs against the pool and returns the old root, new root
+/// and commitment. Layout, as the shield circuit exposes it: old root, new root,
+/// commitment, origin mint, pool, deposit id, optionally followed by 32 commitment bytes.
+fn validate_shield_public_inputs(
+    pool_state: &PoolState,
+    pool_key: Pubkey,
+    args: &ShieldArgs,
+) -> Result<([u8; 32], [u8; 32], [u8; 32])> {
+    const BASE_LEN: usize = 6;
+    let fields = parse_field_elements(&args.public_inputs)?;
+    require!(
+        fields.len() == BASE_LEN || fields.len() == BASE_LEN + 32,
+        PoolError::InvalidPublicInputs
+    );
+
+    if fields[3] != pubkey_to_field_bytes(&pool_state.origin_mint) {
+        msg!(
+            "origin mint mismatch actual={} expected={}",
+            hex::encode(fields[3]),
+            hex::encode(pubkey_to_field_bytes(&pool_state.origin_mint))
+        );
+        return err!(PoolError::PublicInputMismatch);
+    }
+    if fields[4] != pubkey_to_field_bytes(&pool_key) {
+        msg!(
+            "pool key mismatch actual={} expected={}",
+            hex::encode(fields[4]),
+            hex::encode(pubkey_to_field_bytes(&pool_key))
+        );
+        return err!(PoolError::PublicInputMismatch);
+    }
+
+    if fields.len() > BASE_LEN {
+        for byte_field in &fields[BASE_LEN..] {
+            require!(
+                byte_field.iter().skip(1).all(|b| *b == 0),
+                PoolError::InvalidPublicInputs
+            );
+        }
+    }
+
+    Ok((fields[0], fields[1], fields[2]))
+}
+
+fn validate_disclosure_public_inputs(
+    pool_state: &PoolState,
+    pool_key: Pubkey,
+    args: &DisclosureArgs,
+    decimals: u8,
+) -> Result<()> {
+    // root, commitment, owner, amount, origin mint, pool.
+    let fields = parse_field_elements(&args.public_inputs)?;
+    require!(fields.len() == 6, PoolError::InvalidPublicInputs);
+    let expected = [
+        args.root,
+        args.commitment,
+        pubkey_to_field_bytes(&args.owner),
+    ];
+    if fields[..3] != expected {
+        return err!(PoolError::PublicInputMismatch);
+    }
+    if decode_amount_from_field(&fields[3], decimals)? != args.amount {
+        return err!(PoolError::PublicInputMismatch);
+    }
+    if fields[4] != pubkey_to_field_bytes(&pool_state.origin_mint)
+        || fields[5] != pubkey_to_field_bytes(&pool_key)
+    {
+        return err!(PoolError::PublicInputMismatch);
+    }
+    Ok(())
+}
+
+fn validate_unshield_public_inputs(
+    pool_state: &PoolState,
+    pool_key: Pubkey,
+    args: &UnshieldManyArgs,
+    mode: UnshieldMode,
+    destinations: &[Pubkey],
+    decimals: u8,
+    leaf_count: Option<u64>,
+) -> Result<u64> {
+    let fields = parse_field_elements(&args.public_inputs)?;
+    let change_outputs = args.output_commitments.len();
+    let recipients = args.amounts.len();
+    require!(
+        destinations.len() == recipients,
+        PoolError::UnshieldRecipientsInvalid
+    );
+    // A pool in emergency exit stays paused, so its only unshields are emergency exits,
+    // which spend whole notes against the frozen root and carry no change.
+    let change_limits = if pool_state.emergency_exit {
+        0..=0
+    } else {
+        1..=pool_state.change_note_limit()
+    };
+    require!(
+        change_limits.contains(&change_outputs)
+            && args.output_amount_commitments.len() == change_outputs,
+        PoolError::InvalidChangeNoteCount
+    );
+    // old/new root, nullifiers, change commitments and amount commitments, one amount and
+    // one destination per recipient, then fee, mode, origin mint, and pool. A pool with a
+    // minimum note age adds the highest input leaf index after the pool, and a scheduled
+    // unshield adds the note's unlock timestamp last.
+    let age_fields = usize::from(leaf_count.is_some() && pool_state.min_note_age_leaves > 0);
+    let schedule_fields = usize::from(args.unlock_timestamp.is_some());
+    let base_len = 2
+        + args.nullifiers.len()
+        + (2 * change_outputs)
+        + (2 * recipients)
+        + 4
+        + age_fields
+        + schedule_fields;
+    require!(
+        fields.len() == base_len || fields.len() == base_len + 32,
+        PoolError::InvalidPublicInputs
+    );
+    let extra_fields = fields.len() - base_len;
+
+    if fields[0] != args.old_root {
+        return err!(PoolError::PublicInputMismatch);
+    }
+    if fields[1] != args.new_root {
+        return err!(PoolError::PublicInputMismatch);
+    }
+
+    for (expected, actual) in args
+        .nullifiers
+        .iter()
+        .zip(&fields[2..2 + args.nullifiers.len()])
+    {
+        if actual != expected {
+            return err!(PoolError::PublicInputMismatch);
+        }
+    }
+
+    let mut index = 2 + args.nullifiers.len();
+    for (expected, actual) in args
+        .output_commitments
+        .iter()
+        .zip(&fields[index..index + change_outputs])
+    {
+        if actual != expected {
+            return err!(PoolError::PublicInputMismatch);
+        }
+    }
+    index += change_outputs;
+
+    for (expected, actual) in args
+        .output_amount_commitments
+        .iter()
+        .zip(&fields[index..index + change_outputs])
+    {
+        if actual != expected {
+            return err!(PoolError::PublicInputMismatch);
+        }
+    }
+    index += change_outputs;
+
+    for amount in &args.amounts {
+        let amount_from_proof = decode_amount_from_field(&fields[index], decimals)?;
+        if amount_from_proof != *amount {
+            msg!(
+                "amount mismatch amount_from_proof={} args_amount={}",
+                amount_from_proof,
+                amount
+            );
+            return err!(PoolError::PublicInputMismatch);
+        }
+        index += 1;
+    }
+    let fee_from_proof = decode_amount_from_field(&fields[index], decimals)?;
+    let total_amount = args
+        .amounts
+        .iter()
+        .try_fold(0u64, |total, amount| total.checked_add(*amount))
+        .ok_or(PoolError::AmountOverflow)?;
+    let expected_fee = pool_state.unshield_fee(total_amount)?;
+    require!(expected_fee <= args.max_fee, PoolError::FeeExceedsLimit);
+    if fee_from_proof != expected_fee {
+        msg!(
+            "fee mismatch fee_from_proof={} expected={} fee_rounding={}",
+            fee_from_proof,
+            expected_fee,
+            pool_state.fee_rounding
+        );
+        return err!(PoolError::FeeMismatch);
+    }
+    index += 1;
+    for destination in destinations {
+        if fields[index] != pubkey_to_field_bytes(destination) {
+            msg!(
+                "destination mismatch actual={} expected={}",
+                hex::encode(fields[index]),
+                hex::encode(pubkey_to_field_bytes(destination))
+            );
+            return err!(PoolError::PublicInputMismatch);
+        }
+        index += 1;
+    }
+    if fields[index] != u8_to_field_bytes(mode as u8) {
+        msg!(
+            "mode mismatch actual={} expected={}",
+            hex::encode(fields[index]),
+            hex::encode(u8_to_field_bytes(mode as u8))
+        );
+        return err!(PoolError::PublicInputMismatch);
+    }
+    index += 1;
+    if fields[index] != pubkey_to_field_bytes(&pool_state.origin_mint) {
+        msg!(
+            "origin mint mismatch actual={} expected={}",
+            hex::encode(fields[index]),
+            hex::encode(pubkey_to_field_bytes(&pool_state.origin_mint))
+        );
+        return err!(PoolError::PublicInputMismatch);
+    }
+    index += 1;
+    if fields[index] != pubkey_to_field_bytes(&pool_key) {
+        msg!(
+            "pool key mismatch actual={} expected={}",
+            hex::encode(fields[index]),
+            hex::encode(pubkey_to_field_bytes(&pool_key))
+        );
+        return err!(PoolError::PublicInputMismatch);
+    }
+    if let (1, Some(leaf_count)) = (age_fields, leaf_count) {
+        index += 1;
+        pool_state.check_note_age(field_to_u64(&fields[index])?, leaf_count)?;
+    }
+    if let Some(unlock_timestamp) = args.unlock_timestamp {
+        index += 1;
+        let unlock_timestamp =
+            u64::try_from(unlock_timestamp).map_err(|_| PoolError::InvalidPublicInputs)?;
+        if fields[index] != u64_to_field_bytes(unlock_timestamp) {
+            return err!(PoolError::PublicInputMismatch);
+        }
+    }
+
+    if extra_fields == 32 {
+        let byte_fields = &fields[fields.len() - 32..];
+        for byte_field in byte_fields {
+            require!(
+                byte_field.iter().skip(1).all(|b| *b == 0),
+                PoolError::InvalidPublicInputs
+            );
+        }
+    }
+
+    Ok(fee_from_proof)
+}
+
+#[account(zero_copy(unsafe))]
+#[repr(C)]
+pub struct HookConfig {
+    pub pool: Pubkey,
+    pub post_shield_program_id: Pubkey,
+    pub post_shield_enabled: bool,
+    pub post_unshield_program_id: Pubkey,
+    pub post_unshield_enabled: bool,
+    pub post_shield_accounts: [[u8; 32]; HookConfig::MAX_REQUIRED_ACCOUNTS],
+    pub post_shield_accounts_len: u8,
+    pub mode: HookAccountMode,
+    pub bump: u8,
+    /// Screening program invoked before funds move. A single program serves both
+    /// directions so the fields fit in the account's reserved tail.
+    pub pre_hook_program_id: Pubkey,
+    pub pre_shield_enabled: bool,
+    pub pre_unshield_enabled: bool,
+    pub post_transfer_program_id: Pubkey,
+    pub post_transfer_enabled: bool,
+    pub account_templates: [HookAccountTemplate; HookConfig::MAX_ACCOUNT_TEMPLATES],
+    pub account_templates_len: u8,
+    pub post_unshield_accounts: [[u8; 32]; HookConfig::MAX_REQUIRED_ACCOUNTS],
+    pub post_unshield_accounts_len: u8,
+    /// Bit `i` marks entry `i` of the matching account list as writable.
+    pub post_shield_writable: u8,
+    pub post_unshield_writable: u8,
+}
+
+/// Which account list a hook validates against.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum HookDirection {
+    Shield,
+    Unshield,
+}
+
+impl HookConfig {
+    pub const MAX_REQUIRED_ACCOUNTS: usize = 8;
+    pub const MAX_ACCOUNT_TEMPLATES: usize = 4;
+    pub const SPACE: usize = 8 + core::mem::size_of::<HookConfig>() + 64;
+
+    /// Packs an account list into fixed storage plus its length and writable bitmask.
+    pub fn pack_accounts(
+        accounts: &[HookAccountArgs],
+    ) -> Result<([[u8; 32]; Self::MAX_REQUIRED_ACCOUNTS], u8, u8)> {
+        require!(
+            accounts.len() <= Self::MAX_REQUIRED_ACCOUNTS,
+            PoolError::TooManyHookAccounts
+        );
+        let mut keys = [[0u8; 32]; Self::MAX_REQUIRED_ACCOUNTS];
+        let mut writable = 0u8;
+        for (idx, account) in accounts.iter().enumerate() {
+            keys[idx] = account.key.to_bytes();
+            if account.is_writable {
+                writable |= 1 << idx;
+            }
+        }
+        Ok((keys, accounts.len() as u8, writable))
+    }
+
+    /// The direction's fixed accounts followed by the templated PDAs, each carrying its
+    /// declared writability. Templates with a user seed are expanded once per entry of
+    /// `users` (and skipped when no user is known); the rest are expanded once.
+    pub fn required_metas(&self, direction: HookDirection, users: &[Pubkey]) -> Vec<AccountMeta> {
+        let (accounts, len, writable) = match direction {
+            HookDirection::Shield => (
+                &self.post_shield_accounts,
+                self.post_shield_accounts_len,
+                self.post_shield_writable,
+            ),
+            HookDirection::Unshield => (
+                &self.post_unshield_accounts,
+                self.post_unshield_accounts_len,
+                self.post_unshield_writable,
+            ),
+        };
+        let mut metas: Vec<AccountMeta> = accounts
+            .iter()
+            .take(len as usize)
+            .enumerate()
+            .map(|(idx, bytes)| AccountMeta {
+                pubkey: Pubkey::new_from_array(*bytes),
+                is_signer: false,
+                is_writable: writable & (1 << idx) != 0,
+            })
+            .collect();
+        for template in self
+            .account_templates
+            .iter()
+            .take(self.account_templates_len as usize)
+        {
+            let meta = |pubkey| AccountMeta {
+                pubkey,
+                is_signer: false,
+                is_writable: template.is_writable != 0,
+            };
+            if template.uses_user() {
+                metas.extend(users.iter().map(|user| meta(template.derive(user))));
+            } else {
+                metas.push(meta(template.derive(&Pubkey::default())));
+            }
+        }
+        metas
+    }
+}
+
+/// A required hook account given as PDA seeds under `program_id`, so per-user hook state
+/// can be demanded without listing every user.
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "idl-build", derive(IdlBuild))]
+pub struct HookAccountTemplate {
+    pub program_id: Pubkey,
+    pub seeds: [[u8; 32]; HookAccountTemplate::MAX_SEEDS],
+    pub seed_lens: [u8; HookAccountTemplate::MAX_SEEDS],
+    pub seed_kinds: [u8; HookAccountTemplate::MAX_SEEDS],
+    pub seeds_len: u8,
+    pub is_writable: u8,
+}
+
+impl HookAccountTemplate {
+    pub const MAX_SEEDS: usize = 3;
+    pub const SEED_LITERAL: u8 = 0;
+    pub const SEED_USER: u8 = 1;
+
+    pub fn empty() -> Self {
+        Self {
+            program_id: Pubkey::default(),
+            seeds: [[0u8; 32]; Self::MAX_SEEDS],
+            seed_lens: [0u8; Self::MAX_SEEDS],
+            seed_kinds: [0u8; Self::MAX_SEEDS],
+            seeds_len: 0,
+            is_writable: 0,
+        }
+    }
+
+    pub fn from_args(args: &HookAccountTemplateArgs) -> Result<Self> {
+        require!(
+            !args.seeds.is_empty() && args.seeds.len() <= Self::MAX_SEEDS,
+            PoolError::HookTemplateInvalid
+        );
+        let mut template = Self::empty();
+        template.program_id = args.program_id;
+        for (idx, seed) in args.seeds.iter().enumerate() {
+            match seed {
+                HookSeed::Literal(bytes) => {
+                    require!(bytes.len() <= 32, PoolError::HookTemplateInvalid);
+                    template.seeds[idx][..bytes.len()].copy_from_slice(bytes);
+                    template.seed_lens[idx] = bytes.len() as u8;
+                    template.seed_kinds[idx] = Self::SEED_LITERAL;
+                }
+                HookSeed::User => {
+                    template.seed_lens[idx] = 32;
+                    template.seed_kinds[idx] = Self::SEED_USER;
+                }
+            }
+        }
+        template.seeds_len = args.seeds.len() as u8;
+        template.is_writable = args.is_writable as u8;
+        Ok(template)
+    }
+
+    pub fn uses_user(&self) -> bool {
+        self.seed_kinds
+            .iter()
+            .take(self.seeds_len as usize)
+            .any(|kind| *kind == Self::SEED_USER)
+    }
+
+    /// Derives the templated address, substituting `user` for each user seed.
+    pub fn derive(&self, user: &Pubkey) -> Pubkey {
+        let seeds: Vec<&[u8]> = (0..self.seeds_len as usize)
+            .map(|idx| {
+                if self.seed_kinds[idx] == Self::SEED_USER {
+                    user.as_ref()
+                } else {
+                    &self.seeds[idx][..self.seed_lens[idx] as usize]
+                }
+            })
+            .collect();
+        Pubkey::find_program_address(&seeds, &self.program_id).0
+    }
+}
+
+#[account]
+pub struct AllowanceAccount {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub spender: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub updated_at: i64,
+    pub bump: u8,
+    pub _reserved: [u8; 7],
+}
+
+impl AllowanceAccount {
+    pub const SPACE: usize = 8 + 32 * 4 + 8 + 8 + 1 + 7;
+}
+
+/// Verifying key for one join-split shape, registered per pool.
+#[account]
+pub struct ArityVerifier {
+    pub pool: Pubkey,
+    pub inputs: u8,
+    pub outputs: u8,
+    pub verifying_key: Pubkey,
+    pub verifying_key_id: [u8; 32],
+    pub verifying_key_hash: [u8; 32],
+    pub bump: u8,
+}
+
+impl ArityVerifier {
+    pub const SPACE: usize = 8 + 32 + 1 + 1 + 32 + 32 + 32 + 1;
+}
+
+/// Root and leaf range at one point of the tree's history, at
+/// `["checkpoint", pool_state, index]`.
+#[account]
+pub struct TreeCheckpoint {
+    pub pool: Pubkey,
+    pub index: u64,
+    pub start_leaf: u64,
+    pub end_leaf: u64,
+    pub root: [u8; 32],
+    pub slot: u64,
+    pub previous_digest: [u8; 32],
+    pub digest: [u8; 32],
+    pub bump: u8,
+}
+
+impl TreeCheckpoint {
+    pub const SPACE: usize = 8 + 32 + 8 + 8 + 8 + 32 + 8 + 32 + 32 + 1;
+
+    pub fn chain(
+        previous: &[u8; 32],
+        index: u64,
+        start_leaf: u64,
+        end_leaf: u64,
+        root: &[u8; 32],
+    ) -> [u8; 32] {
+        hashv(&[
+            previous,
+            &index.to_le_bytes(),
+            &start_leaf.to_le_bytes(),
+            &end_leaf.to_le_bytes(),
+            root,
+        ])
+        .to_bytes()
+    }
+}
+
+/// Shield and unshield volume within the current slot window.
+#[account]
+pub struct RateLimiter {
+    pub pool: Pubkey,
+    pub window_slots: u64,
+    pub max_inflow: u64,
+    pub max_outflow: u64,
+    pub window_start_slot: u64,
+    pub inflow: u64,
+    pub outflow: u64,
+    pub bump: u8,
 }
 
-#[derive(Accounts)]
-pub struct ShieldCheckInvariant<'info> {
-    #[account(
-        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
-        bump = pool_state.load()?.bump
-    )]
-    pub pool_state: AccountLoader<'info, PoolState>,
-    #[account(
-        seeds = [seeds::NOTES, pool_state.load()?.origin_mint.as_ref()],
-        bump = pool_state.load()?.note_ledger_bump,
-        constraint = note_ledger.key() == pool_state.load()?.note_ledger @ PoolError::NoteLedgerMismatch,
-        constraint = note_ledger.load()?.pool == pool_state.key() @ PoolError::NoteLedgerMismatch,
-    )]
-    pub note_ledger: AccountLoader<'info, NoteLedger>,
-    #[account(
-        mut,
-        seeds = [seeds::CLAIM, pool_state.key().as_ref()],
-        bump = shield_claim.bump
-    )]
-    pub shield_claim: Account<'info, ShieldClaim>,
-    #[account(mut)]
-    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
-    #[account(mut)]
-    pub twin_mint: Option<InterfaceAccount<'info, Mint>>,
+impl RateLimiter {
+    pub const SPACE: usize = 8 + 32 + 8 * 6 + 1;
+
+    /// Starts a fresh window once `slot` has moved past the current one.
+    fn roll(&mut self, slot: u64) {
+        if self.window_slots > 0 && slot >= self.window_start_slot.saturating_add(self.window_slots)
+        {
+            self.window_start_slot = slot - slot % self.window_slots;
+            self.inflow = 0;
+            self.outflow = 0;
+        }
+    }
+
+    pub fn record_inflow(&mut self, amount: u64, slot: u64) -> Result<()> {
+        self.roll(slot);
+        self.inflow = self
+            .inflow
+            .checked_add(amount)
+            .ok_or(PoolError::AmountOverflow)?;
+        require!(
+            self.max_inflow == 0 || self.inflow <= self.max_inflow,
+            PoolError::RateLimitExceeded
+        );
+        Ok(())
+    }
+
+    pub fn record_outflow(&mut self, amount: u64, slot: u64) -> Result<()> {
+        self.roll(slot);
+        self.outflow = self
+            .outflow
+            .checked_add(amount)
+            .ok_or(PoolError::AmountOverflow)?;
+        require!(
+            self.max_outflow == 0 || self.outflow <= self.max_outflow,
+            PoolError::RateLimitExceeded
+        );
+        Ok(())
+    }
 }
 
-#[derive(Accounts)]
-pub struct Unshield<'info> {
-    #[account(
-        mut,
-        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
-        bump = pool_state.load()?.bump
-    )]
-    pub pool_state: AccountLoader<'info, PoolState>,
-    #[account(
-        seeds = [seeds::HOOKS, pool_state.load()?.origin_mint.as_ref()],
-        bump = pool_state.load()?.hook_config_bump,
-        constraint = hook_config.load()?.pool == pool_state.key() @ PoolError::HookConfigInvalid,
-    )]
-    pub hook_config: AccountLoader<'info, HookConfig>,
-    #[account(
-        mut,
-        seeds = [seeds::NULLIFIERS, pool_state.load()?.origin_mint.as_ref()],
-        bump = nullifier_set.load()?.bump
-    )]
-    pub nullifier_set: AccountLoader<'info, NullifierSet>,
-    #[account(
-        mut,
-        seeds = [seeds::TREE, pool_state.load()?.origin_mint.as_ref()],
-        bump = commitment_tree.load()?.bump,
-        constraint = commitment_tree.load()?.pool == pool_state.key() @ PoolError::CommitmentTreeMismatch
-    )]
-    pub commitment_tree: AccountLoader<'info, CommitmentTree>,
-    #[account(
-        mut,
-        seeds = [seeds::NOTES, pool_state.load()?.origin_mint.as_ref()],
-        bump = pool_state.load()?.note_ledger_bump,
-        constraint = note_ledger.key() == pool_state.load()?.note_ledger @ PoolError::NoteLedgerMismatch,
-        constraint = note_ledger.load()?.pool == pool_state.key() @ PoolError::NoteLedgerMismatch,
-    )]
-    pub note_ledger: AccountLoader<'info, NoteLedger>,
-    #[account(
-        seeds = [seeds::MINT_MAPPING, pool_state.load()?.origin_mint.as_ref()],
-        bump = mint_mapping.bump,
-        seeds::program = ptf_factory::ID,
-        constraint = mint_mapping.origin_mint == pool_state.load()?.origin_mint @ PoolError::OriginMintMismatch,
-    )]
-    pub mint_mapping: Account<'info, MintMapping>,
-    pub verifier_program: Program<'info, PtfVerifierGroth16>,
-    #[account(
-        address = pool_state.load()?.verifying_key,
-        constraint = verifying_key.hash == pool_state.load()?.verifying_key_hash @ PoolError::VerifyingKeyHashMismatch,
-    )]
-    pub verifying_key: Account<'info, VerifyingKeyAccount>,
-    #[account(mut)]
-    pub vault_state: Account<'info, ptf_vault::VaultState>,
-    #[account(mut)]
-    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
-    #[account(mut)]
-    pub destination_token_account: InterfaceAccount<'info, TokenAccount>,
-    #[account(mut)]
-    pub twin_mint: Option<InterfaceAccount<'info, Mint>>,
-    pub vault_program: Program<'info, PtfVault>,
-    #[account(
-        seeds = [seeds::FACTORY, ptf_factory::ID.as_ref()],
-        bump = factory_state.bump,
-        seeds::program = ptf_factory::ID
-    )]
-    pub factory_state: Account<'info, ptf_factory::FactoryState>,
-    pub factory_program: Program<'info, PtfFactory>,
-    pub token_program: Interface<'info, TokenInterface>,
+/// Activity counters for one epoch.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EpochStats {
+    pub epoch: u64,
+    pub shields: u64,
+    pub unshields: u64,
+    pub transfers: u64,
+    pub shield_volume: u128,
+    pub unshield_volume: u128,
+    pub fees: u128,
+}
+
+impl EpochStats {
+    pub const SPACE: usize = 8 * 4 + 16 * 3;
+
+    pub fn record_shield(&mut self, amount: u64, fee: u64) -> Result<()> {
+        self.shields = self
+            .shields
+            .checked_add(1)
+            .ok_or(PoolError::AmountOverflow)?;
+        self.shield_volume = self
+            .shield_volume
+            .checked_add(u128::from(amount))
+            .ok_or(PoolError::AmountOverflow)?;
+        self.add_fee(fee)
+    }
+
+    pub fn record_unshield(&mut self, amount: u64, fee: u64) -> Result<()> {
+        self.unshields = self
+            .unshields
+            .checked_add(1)
+            .ok_or(PoolError::AmountOverflow)?;
+        self.unshield_volume = self
+            .unshield_volume
+            .checked_add(u128::from(amount))
+            .ok_or(PoolError::AmountOverflow)?;
+        self.add_fee(fee)
+    }
+
+    pub fn record_transfers(&mut self, count: u64) -> Result<()> {
+        self.transfers = self
+            .transfers
+            .checked_add(count)
+            .ok_or(PoolError::AmountOverflow)?;
+        Ok(())
+    }
+
+    fn add_fee(&mut self, fee: u64) -> Result<()> {
+        self.fees = self
+            .fees
+            .checked_add(u128::from(fee))
+            .ok_or(PoolError::AmountOverflow)?;
+        Ok(())
+    }
+}
+
+/// Unclaimed fee share of one referrer, at `["referral", pool_state, referrer]`.
+#[account]
+pub struct ReferralAccrual {
+    pub pool: Pubkey,
+    pub referrer: Pubkey,
+    pub accrued: u64,
+    pub bump: u8,
+}
+
+impl ReferralAccrual {
+    pub const SPACE: usize = 8 + 32 + 32 + 8 + 1;
+}
+
+/// Withdrawal destination owners of a regulated pool, at `["destinations", pool_state]`.
+#[account]
+pub struct DestinationAllowlist {
+    pub pool: Pubkey,
+    /// May add and remove destinations.
+    pub compliance_authority: Pubkey,
+    pub destinations: Vec<Pubkey>,
+    pub bump: u8,
+}
+
+impl DestinationAllowlist {
+    pub const MAX_DESTINATIONS: usize = 64;
+    pub const SPACE: usize = 8 + 32 + 32 + 4 + 32 * Self::MAX_DESTINATIONS + 1;
+
+    pub fn contains(&self, owner: &Pubkey) -> bool {
+        self.destinations.contains(owner)
+    }
+
+    fn insert(&mut self, owner: Pubkey) -> Result<()> {
+        if self.contains(&owner) {
+            return Ok(());
+        }
+        require!(
+            self.destinations.len() < Self::MAX_DESTINATIONS,
+            PoolError::DestinationAllowlistFull
+        );
+        self.destinations.push(owner);
+        Ok(())
+    }
+
+    fn remove(&mut self, owner: &Pubkey) {
+        self.destinations.retain(|entry| entry != owner);
+    }
+}
+
+/// Per-pool activity for the current and previous active epoch, at `["stats", pool_state]`.
+#[account]
+pub struct PoolStats {
+    pub pool: Pubkey,
+    pub current: EpochStats,
+    pub previous: EpochStats,
+    pub bump: u8,
+}
+
+impl PoolStats {
+    pub const SPACE: usize = 8 + 32 + 2 * EpochStats::SPACE + 1;
+
+    /// Closes the current epoch once `epoch` has moved on. The closed counters stay in
+    /// `previous` and are emitted, so history survives without keeping every epoch.
+    fn roll(&mut self, origin_mint: Pubkey, epoch: u64) {
+        if epoch != self.current.epoch {
+            emit!(PoolStatsEpochClosed {
+                origin_mint,
+                stats: self.current,
+            });
+            self.previous = self.current;
+            self.current = EpochStats {
+                epoch,
+                ..EpochStats::default()
+            };
+        }
+    }
+
+    pub fn view(&self, epoch: u64) -> PoolStatsInfo {
+        if epoch == self.current.epoch {
+            return PoolStatsInfo {
+                current: self.current,
+                previous: self.previous,
+            };
+        }
+        PoolStatsInfo {
+            current: EpochStats {
+                epoch,
+                ..EpochStats::default()
+            },
+            previous: self.current,
+        }
+    }
+}
+
+#[account]
+pub struct PoolTimelockEntry {
+    pub pool: Pubkey,
+    pub salt: [u8; 32],
+    pub action_hash: [u8; 32],
+    pub queued_at: i64,
+    pub execute_after: i64,
+    pub executed: bool,
+    pub action: PoolTimelockAction,
+    pub bump: u8,
+    /// Funded the entry; a cancellation refunds its rent here.
+    pub payer: Pubkey,
+}
+
+impl PoolTimelockEntry {
+    pub const MAX_ACTION_SIZE: usize = 64;
+    pub const SPACE: usize = 8 + 32 + 32 + 32 + 8 + 8 + 1 + 1 + Self::MAX_ACTION_SIZE + 32;
+}
+
+#[cfg(feature = "idl-build")]
+mod idl_build_impls {
+    use super::*;
+
+    impl IdlBuild for PendingShield {}
+}
+
+#[event]
+pub struct PoolInitialized {
+    pub origin_mint: Pubkey,
+    pub shield_fee_bps: u16,
+    pub unshield_fee_bps: u16,
+    pub features: u8,
+}
+
+#[event]
+pub struct PTFUnshieldEscrowed {
+    pub mint: Pubkey,
+    pub escrow: Pubkey,
+    pub recipient: Pubkey,
+    pub arbiter: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct EscrowReleased {
+    pub mint: Pubkey,
+    pub escrow: Pubkey,
+    pub recipient: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct PTFShieldReferred {
+    pub mint: Pubkey,
+    pub depositor: Pubkey,
+    pub referrer: Pubkey,
+    pub commitment: [u8; 32],
+    /// Part of the shield fee credited to the referrer.
+    pub fee_share: u64,
+}
+
+/// Companion to `PTFShielded` for `shield_via_cpi`, naming the program that shielded.
+#[event]
+pub struct PTFShieldedViaCpi {
+    pub mint: Pubkey,
+    pub depositor: Pubkey,
+    pub caller_program: Pubkey,
+    pub commitment: [u8; 32],
+}
+
+#[event]
+pub struct PTFShielded {
+    pub mint: Pubkey,
+    pub depositor: Pubkey,
+    pub commitment: [u8; 32],
+    pub root: [u8; 32],
+    pub amount_commit: [u8; 32],
+    pub encrypted_note: Vec<u8>,
+    /// Commitment tree the note is appended to by `shield_finalize_tree`, and its leaf
+    /// index there.
+    pub tree: Pubkey,
+    pub leaf_index: u64,
+}
+
+#[event]
+pub struct PTFUnshieldOrigin {
+    pub mint: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+}
+
+#[event]
+pub struct PTFUnshieldPMint {
+    pub mint: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+}
+
+#[event]
+pub struct PTFHookPreShield {
+    pub mint: Pubkey,
+    pub depositor: Pubkey,
+}
+
+#[event]
+pub struct PTFHookPreUnshield {
+    pub mint: Pubkey,
+    pub mode: u8,
+    pub destination: Pubkey,
+}
+
+#[event]
+pub struct PTFHookPostTransfer {
+    pub mint: Pubkey,
+    pub root: [u8; 32],
 }
 
-#[derive(Accounts)]
-pub struct ConfigureHooks<'info> {
-    pub authority: Signer<'info>,
-    #[account(
-        mut,
-        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
-        bump = pool_state.load()?.bump,
-        has_one = authority
-    )]
-    pub pool_state: AccountLoader<'info, PoolState>,
-    #[account(
-        mut,
-        seeds = [seeds::HOOKS, pool_state.load()?.origin_mint.as_ref()],
-        bump = pool_state.load()?.hook_config_bump,
-        constraint = hook_config.load()?.pool == pool_state.key() @ PoolError::HookConfigInvalid,
-    )]
-    pub hook_config: AccountLoader<'info, HookConfig>,
+#[event]
+pub struct PTFHookPostUnshield {
+    pub mint: Pubkey,
+    pub mode: u8,
+    pub destination: Pubkey,
 }
 
-#[derive(Accounts)]
-pub struct PrivateTransfer<'info> {
-    #[account(
-        mut,
-        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
-        bump = pool_state.load()?.bump
-    )]
-    pub pool_state: AccountLoader<'info, PoolState>,
-    #[account(
-        mut,
-        seeds = [seeds::NULLIFIERS, pool_state.load()?.origin_mint.as_ref()],
-        bump = nullifier_set.load()?.bump
-    )]
-    pub nullifier_set: AccountLoader<'info, NullifierSet>,
-    #[account(
-        mut,
-        seeds = [seeds::TREE, pool_state.load()?.origin_mint.as_ref()],
-        bump = commitment_tree.load()?.bump,
-        constraint = commitment_tree.load()?.pool == pool_state.key() @ PoolError::CommitmentTreeMismatch
-    )]
-    pub commitment_tree: AccountLoader<'info, CommitmentTree>,
-    #[account(
-        mut,
-        seeds = [seeds::NOTES, pool_state.load()?.origin_mint.as_ref()],
-        bump = pool_state.load()?.note_ledger_bump,
-        constraint = note_ledger.key() == pool_state.load()?.note_ledger @ PoolError::NoteLedgerMismatch,
-        constraint = note_ledger.load()?.pool == pool_state.key() @ PoolError::NoteLedgerMismatch,
-    )]
-    pub note_ledger: AccountLoader<'info, NoteLedger>,
-    pub verifier_program: Program<'info, PtfVerifierGroth16>,
-    #[account(
-        address = pool_state.load()?.verifying_key,
-        constraint = verifying_key.hash == pool_state.load()?.verifying_key_hash @ PoolError::VerifyingKeyHashMismatch,
-    )]
-    pub verifying_key: Account<'info, VerifyingKeyAccount>,
+#[event]
+pub struct PTFHookPostShield {
+    pub mint: Pubkey,
+    pub deposit_id: u64,
+    pub commitment: [u8; 32],
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct ShieldArgs {
-    pub amount_commit: [u8; 32],
-    pub amount: u64,
-    pub proof: Vec<u8>,
-    pub public_inputs: Vec<u8>,
+#[event]
+pub struct PTFTransferred {
+    pub mint: Pubkey,
+    pub inputs: Vec<[u8; 32]>,
+    pub outputs: Vec<[u8; 32]>,
+    pub root: [u8; 32],
+    pub encrypted_note: Vec<u8>,
+    /// Commitment tree the outputs were appended to, and each output's leaf index.
+    pub tree: Pubkey,
+    pub leaf_indices: Vec<u64>,
+    /// Per-output ciphertexts from `TransferArgs`, empty when none were sent.
+    pub output_ciphertexts: Vec<Vec<u8>>,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct UnshieldArgs {
-    pub old_root: [u8; 32],
-    pub new_root: [u8; 32],
-    pub nullifiers: Vec<[u8; 32]>,
-    pub output_commitments: Vec<[u8; 32]>,
-    pub output_amount_commitments: Vec<[u8; 32]>,
+#[event]
+pub struct PTFAllowanceUpdated {
+    pub mint: Pubkey,
+    pub owner: Pubkey,
+    pub spender: Pubkey,
     pub amount: u64,
-    pub proof: Vec<u8>,
-    pub public_inputs: Vec<u8>,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct TransferArgs {
-    pub old_root: [u8; 32],
-    pub new_root: [u8; 32],
-    pub nullifiers: Vec<[u8; 32]>,
-    pub output_commitments: Vec<[u8; 32]>,
-    pub output_amount_commitments: Vec<[u8; 32]>,
-    pub proof: Vec<u8>,
-    pub public_inputs: Vec<u8>,
+#[event]
+pub struct PTFNullifierUsed {
+    pub mint: Pubkey,
+    pub nullifier: [u8; 32],
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct HookConfigArgs {
+#[event]
+pub struct HookConfigUpdated {
+    pub origin_mint: Pubkey,
     pub post_shield_program: Pubkey,
-    pub post_shield_enabled: bool,
     pub post_unshield_program: Pubkey,
+    pub post_shield_enabled: bool,
     pub post_unshield_enabled: bool,
-    pub required_accounts: Vec<Pubkey>,
-    pub mode: HookAccountMode,
+    pub mode: u8,
+    pub pre_hook_program: Pubkey,
+    pub pre_shield_enabled: bool,
+    pub pre_unshield_enabled: bool,
+    pub post_transfer_program: Pubkey,
+    pub post_transfer_enabled: bool,
+    pub account_templates: u8,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct ApproveAllowanceArgs {
-    pub amount: u64,
+#[event]
+pub struct PTFPrivateSwap {
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
+    pub binding: [u8; 32],
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct TransferFromArgs {
-    pub transfer: TransferArgs,
-    pub allowance_amount: u64,
+#[event]
+pub struct LedgerAudited {
+    pub origin_mint: Pubkey,
+    pub note_ledger: Pubkey,
+    pub amount_commitment_digest: [u8; 32],
+    pub nullifier_digest: [u8; 32],
+    pub archived_nullifier_digest: [u8; 32],
+    pub nullifier_epoch: u64,
+    pub notes_created: u64,
+    pub notes_consumed: u64,
+    pub total_minted: u128,
+    pub total_spent: u128,
+    pub live_value: u128,
 }
 
-#[derive(Accounts)]
-pub struct ManageAllowance<'info> {
-    #[account(
-        mut,
-        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
-        bump = pool_state.load()?.bump
-    )]
-    pub pool_state: AccountLoader<'info, PoolState>,
-    #[account(
-        init_if_needed,
-        payer = owner,
-        space = AllowanceAccount::SPACE,
-        seeds = [
-            seeds::ALLOWANCE,
-            pool_state.key().as_ref(),
-            owner.key().as_ref(),
-            spender.key().as_ref()
-        ],
-        bump
-    )]
-    pub allowance: Account<'info, AllowanceAccount>,
-    #[account(mut)]
-    pub owner: Signer<'info>,
-    /// CHECK: spender authority
-    pub spender: AccountInfo<'info>,
-    #[account(address = pool_state.load()?.origin_mint)]
-    /// CHECK: origin mint is constrained by address equality
-    pub origin_mint: UncheckedAccount<'info>,
-    pub system_program: Program<'info, System>,
+#[event]
+pub struct PTFInvariantBreached {
+    pub mint: Pubkey,
+    pub vault: Pubkey,
+    pub vault_balance: u128,
+    pub expected: u128,
 }
 
-#[derive(Accounts)]
-pub struct TransferFrom<'info> {
-    #[account(
-        mut,
-        seeds = [seeds::POOL, pool_state.load()?.origin_mint.as_ref()],
-        bump = pool_state.load()?.bump
-    )]
-    pub pool_state: AccountLoader<'info, PoolState>,
-    #[account(
-        mut,
-        seeds = [seeds::NULLIFIERS, pool_state.load()?.origin_mint.as_ref()],
-        bump = nullifier_set.load()?.bump
-    )]
-    pub nullifier_set: AccountLoader<'info, NullifierSet>,
-    #[account(
-        mut,
-        seeds = [seeds::TREE, pool_state.load()?.origin_mint.as_ref()],
-        bump = commitment_tree.load()?.bump,
-        constraint = commitment_tree.load()?.pool == pool_state.key() @ PoolError::CommitmentTreeMismatch
-    )]
-    pub commitment_tree: AccountLoader<'info, CommitmentTree>,
-    #[account(
-        mut,
-        seeds = [seeds::NOTES, pool_state.load()?.origin_mint.as_ref()],
-        bump = pool_state.load()?.note_ledger_bump,
-        constraint = note_ledger.key() == pool_state.load()?.note_ledger @ PoolError::NoteLedgerMismatch,
-        constraint = note_ledger.load()?.pool == pool_state.key() @ PoolError::NoteLedgerMismatch,
-    )]
-    pub note_ledger: AccountLoader<'info, NoteLedger>,
-    pub verifier_program: Program<'info, PtfVerifierGroth16>,
-    #[account(
-        address = pool_state.load()?.verifying_key,
-        constraint = verifying_key.hash == pool_state.load()?.verifying_key_hash @ PoolError::VerifyingKeyHashMismatch,
-    )]
-    pub verifying_key: Account<'info, VerifyingKeyAccount>,
-    #[account(
-        mut,
-        seeds = [
-            seeds::ALLOWANCE,
-            pool_state.key().as_ref(),
-            allowance_owner.key().as_ref(),
-            spender.key().as_ref()
-        ],
-        bump = allowance.bump
-    )]
-    pub allowance: Account<'info, AllowanceAccount>,
-    /// CHECK: allowance owner reference
-    pub allowance_owner: AccountInfo<'info>,
-    pub spender: Signer<'info>,
+#[event]
+pub struct PTFInvariantOk {
+    pub mint: Pubkey,
+    pub vault: Pubkey,
+    pub supply_pm: u64,
+    pub live_notes_commit: [u8; 32],
+    pub fees: u128,
+}
+
+#[event]
+pub struct NullifierShardsEnabled {
+    pub origin_mint: Pubkey,
+    pub shard_count: u8,
+}
+
+#[event]
+pub struct NullifierModeUpdated {
+    pub origin_mint: Pubkey,
+    pub mode: u8,
+}
+
+#[event]
+pub struct NullifiersCompacted {
+    pub origin_mint: Pubkey,
+    pub migrated: u32,
+    pub remaining: u32,
+}
+
+#[event]
+pub struct NullifierEpochRotated {
+    pub origin_mint: Pubkey,
+    pub shard: u8,
+    pub epoch: u64,
+    /// The leaf folded into `archived_digest` by this call; zero on calls that only
+    /// keep emptying an already archived shard.
+    pub shard_digest: [u8; 32],
+    pub archived_digest: [u8; 32],
+    pub migrated: u32,
+    pub remaining: u32,
+}
+
+#[event]
+pub struct NullifierSetGrown {
+    pub origin_mint: Pubkey,
+    pub nullifier_set: Pubkey,
+    pub capacity: u32,
+}
+
+#[event]
+pub struct MaxChangeNotesUpdated {
+    pub origin_mint: Pubkey,
+    pub max_change_notes: u8,
+}
+
+#[event]
+pub struct TransferArityUpdated {
+    pub origin_mint: Pubkey,
+    pub max_inputs: u8,
+    pub max_outputs: u8,
+}
+
+#[event]
+pub struct ArityVerifierRegistered {
+    pub origin_mint: Pubkey,
+    pub inputs: u8,
+    pub outputs: u8,
+    pub verifying_key: Pubkey,
+}
+
+#[event]
+pub struct DisclosureVerifierRegistered {
+    pub origin_mint: Pubkey,
+    pub verifying_key: Pubkey,
+}
+
+#[event]
+pub struct NoteDisclosed {
+    pub origin_mint: Pubkey,
+    pub commitment: [u8; 32],
+    pub root: [u8; 32],
+    pub owner: Pubkey,
+    pub amount: u64,
 }
 
-#[account(zero_copy(unsafe))]
-#[repr(C)]
-pub struct CommitmentTree {
-    pub pool: Pubkey,
-    pub canopy_depth: u8,
-    pub next_index: u64,
-    pub current_root: [u8; 32],
-    pub frontier: [[u8; 32]; CommitmentTree::DEPTH],
-    pub zeroes: [[u8; 32]; CommitmentTree::DEPTH],
-    pub canopy: [[u8; 32]; CommitmentTree::MAX_CANOPY],
-    pub recent_commitments: [[u8; 32]; CommitmentTree::MAX_CANOPY],
-    pub recent_amount_commitments: [[u8; 32]; CommitmentTree::MAX_CANOPY],
-    pub recent_indices: [u64; CommitmentTree::MAX_CANOPY],
-    pub recent_len: u8,
-    pub bump: u8,
+#[event]
+pub struct ScheduledVerifierRegistered {
+    pub origin_mint: Pubkey,
+    pub verifying_key: Pubkey,
 }
 
-impl CommitmentTree {
-    pub const DEPTH: usize = ptf_common::MERKLE_DEPTH as usize;
-    pub const MAX_CANOPY: usize = 16;
-    pub const SPACE: usize = 8 + core::mem::size_of::<CommitmentTree>() + 64;
-    pub const PRECOMPUTED_ZEROES: [[u8; 32]; Self::DEPTH] = [
-        [
-            0x64, 0x48, 0xb6, 0x46, 0x84, 0xee, 0x39, 0xa8, 0x23, 0xd5, 0xfe, 0x5f, 0xd5, 0x24,
-            0x31, 0xdc, 0x81, 0xe4, 0x81, 0x7b, 0xf2, 0xc3, 0xea, 0x3c, 0xab, 0x9e, 0x23, 0x9e,
-            0xfb, 0xf5, 0x98, 0x20,
-        ],
-        [
-            0xe1, 0xf1, 0xb1, 0x60, 0x44, 0x77, 0xa4, 0x67, 0xf0, 0x8d, 0xc6, 0x9d, 0xcb, 0x44,
-            0x1a, 0x26, 0xec, 0xa7, 0x84, 0xf5, 0x6f, 0x1a, 0x30, 0xdf, 0x63, 0x22, 0xb1, 0xcd,
-            0x3d, 0x67, 0x69, 0x10,
-        ],
-        [
-            0x38, 0xd2, 0x56, 0xb8, 0xb2, 0x7e, 0xd5, 0x28, 0xd5, 0x1d, 0x37, 0x50, 0xea, 0x6e,
-            0x7c, 0x46, 0x06, 0x21, 0xf7, 0x50, 0x8d, 0x75, 0x3d, 0x2e, 0xaf, 0xe2, 0x7e, 0x53,
-            0x31, 0x33, 0xf4, 0x18,
-        ],
-        [
-            0x2a, 0x95, 0xbc, 0x9d, 0x55, 0x97, 0xac, 0xca, 0x65, 0x82, 0x56, 0x1a, 0x57, 0x28,
-            0xb7, 0xf1, 0x45, 0x23, 0xa5, 0x3b, 0xe9, 0xff, 0x20, 0x63, 0xd3, 0xb0, 0x17, 0xcb,
-            0x37, 0xd8, 0xf9, 0x07,
-        ],
-        [
-            0x55, 0x3f, 0x18, 0x39, 0x16, 0xec, 0x5c, 0x7b, 0x4d, 0xad, 0xb2, 0x94, 0x8c, 0xc5,
-            0x99, 0xa6, 0x07, 0x29, 0xf3, 0x5d, 0x4c, 0x1f, 0x63, 0xc9, 0xf5, 0xb3, 0x46, 0x87,
-            0x5e, 0xcf, 0x94, 0x2b,
-        ],
-        [
-            0x78, 0x9d, 0xa0, 0x2e, 0xa3, 0xdd, 0x11, 0x1d, 0x61, 0x53, 0xb9, 0x51, 0x69, 0x1e,
-            0xd7, 0xfe, 0xbc, 0xe1, 0xa9, 0xcc, 0x22, 0x7d, 0xea, 0x46, 0x96, 0x45, 0x66, 0xa6,
-            0xc5, 0x93, 0xee, 0x2d,
-        ],
-        [
-            0x9d, 0x34, 0x87, 0x3c, 0xbe, 0xaa, 0xa4, 0xa8, 0x7f, 0xac, 0xb5, 0x8c, 0xa8, 0x15,
-            0x05, 0x8b, 0x7b, 0x59, 0x39, 0xb6, 0x1e, 0x60, 0xcf, 0x82, 0xe9, 0x84, 0x2b, 0xa2,
-            0xe5, 0x95, 0x82, 0x07,
-        ],
-        [
-            0x61, 0xcc, 0xf3, 0x99, 0x3a, 0xbe, 0x4c, 0x44, 0x1a, 0x21, 0x41, 0x4a, 0x27, 0x2e,
-            0x6b, 0x61, 0x2a, 0x47, 0x64, 0x45, 0x86, 0xec, 0x1b, 0x50, 0xa6, 0x27, 0x60, 0x8f,
-            0xf1, 0xe5, 0xa5, 0x2f,
-        ],
-        [
-            0x47, 0xd7, 0xfc, 0x14, 0xa6, 0x56, 0x21, 0x3e, 0xab, 0x28, 0xe2, 0xe3, 0xcc, 0x7a,
-            0x5e, 0xe4, 0x66, 0x1f, 0x94, 0x9e, 0x38, 0x80, 0xb7, 0xec, 0x21, 0xfd, 0xd8, 0xd0,
-            0x76, 0x43, 0x88, 0x0e,
-        ],
-        [
-            0xf2, 0x0a, 0x19, 0xda, 0xe5, 0x75, 0x61, 0xde, 0x33, 0x35, 0x71, 0x57, 0xf9, 0x92,
-            0x58, 0xf9, 0x69, 0xb4, 0x2e, 0xa5, 0xd1, 0x7a, 0x71, 0x28, 0x1e, 0x4f, 0x49, 0x72,
-            0xda, 0x01, 0x72, 0x1b,
-        ],
-        [
-            0x36, 0x76, 0x7d, 0xce, 0xfa, 0x6b, 0xbc, 0xbe, 0xb5, 0x08, 0x08, 0x65, 0xe4, 0xe1,
-            0xe6, 0xa6, 0x19, 0x98, 0x24, 0x01, 0xb2, 0xc0, 0x00, 0x52, 0x38, 0x36, 0x5e, 0x72,
-            0x22, 0x88, 0x8d, 0x1f,
-        ],
-        [
-            0x5a, 0xf8, 0xb5, 0x71, 0x04, 0x9a, 0x87, 0xd0, 0xa8, 0x88, 0xcf, 0x2a, 0xa1, 0xb0,
-            0x62, 0x61, 0xfb, 0xfc, 0x8c, 0xba, 0x89, 0x15, 0x70, 0xb9, 0xaf, 0x4b, 0x91, 0x6c,
-            0xf6, 0x82, 0x5d, 0x2c,
-        ],
-        [
-            0xd0, 0xbf, 0xbf, 0xe0, 0x70, 0xf2, 0x58, 0x64, 0x64, 0xf4, 0x13, 0xa1, 0xaa, 0xc4,
-            0xf5, 0x4e, 0x13, 0xa1, 0x3f, 0xdf, 0x5a, 0x7f, 0x95, 0x20, 0xb8, 0x0b, 0x94, 0xa0,
-            0x48, 0x41, 0xc5, 0x14,
-        ],
-        [
-            0x0c, 0xe8, 0xeb, 0xf4, 0x4b, 0x8e, 0x11, 0x16, 0xd4, 0x89, 0xad, 0x8c, 0x58, 0x25,
-            0xbe, 0x11, 0xaf, 0xb9, 0xd8, 0x44, 0xee, 0xc0, 0x10, 0x1e, 0x96, 0x6f, 0x98, 0x2f,
-            0xb1, 0x33, 0x0d, 0x19,
-        ],
-        [
-            0x92, 0x6c, 0xe0, 0x25, 0x93, 0x64, 0xb3, 0xa5, 0x0a, 0x51, 0xaf, 0x96, 0x65, 0xae,
-            0x67, 0x11, 0xed, 0x73, 0xad, 0x14, 0x49, 0x35, 0x17, 0xac, 0x52, 0x41, 0x70, 0xce,
-            0xa9, 0x8a, 0xf9, 0x22,
-        ],
-        [
-            0x23, 0x73, 0xba, 0x8b, 0xd3, 0x53, 0xb7, 0xf8, 0xee, 0xcc, 0x6e, 0xc6, 0x29, 0x6f,
-            0x52, 0x5a, 0x57, 0x6a, 0xbf, 0x72, 0x8d, 0x22, 0x6f, 0x9f, 0x0b, 0x88, 0xe5, 0x6c,
-            0x9b, 0x7c, 0x7c, 0x2a,
-        ],
-        [
-            0x92, 0xb9, 0x36, 0x3f, 0x64, 0xdd, 0x75, 0x4d, 0x95, 0x8b, 0x98, 0xc2, 0xc9, 0x43,
-            0x00, 0x47, 0xfc, 0x3f, 0x46, 0x4d, 0xc1, 0xf9, 0x7a, 0xc6, 0xc1, 0x8e, 0x69, 0x58,
-            0xe5, 0x86, 0x81, 0x2e,
-        ],
-        [
-            0x0f, 0xf1, 0x1f, 0x1c, 0x9d, 0x24, 0x46, 0x35, 0x27, 0x92, 0x73, 0x64, 0xad, 0x6e,
-            0xef, 0x8a, 0x94, 0xae, 0x0d, 0x05, 0xcf, 0xc8, 0xe2, 0x49, 0xab, 0x4e, 0x9a, 0x1e,
-            0x57, 0xc5, 0x57, 0x0f,
-        ],
-        [
-            0xca, 0x2c, 0xf7, 0x34, 0x61, 0xe3, 0x9c, 0x3c, 0xe4, 0x46, 0x7d, 0x69, 0x10, 0xe3,
-            0x78, 0xfe, 0x1c, 0x0e, 0x80, 0x88, 0x43, 0x3d, 0xf6, 0xd5, 0x4a, 0x55, 0xfb, 0xb5,
-            0x67, 0xee, 0x30, 0x18,
-        ],
-        [
-            0x3e, 0x1f, 0x19, 0x22, 0xdf, 0xb6, 0x71, 0xd3, 0xf9, 0x12, 0xf7, 0xea, 0x46, 0x1e,
-            0x0a, 0x88, 0xee, 0x84, 0x8f, 0xdd, 0xe1, 0x2b, 0x6c, 0x18, 0xab, 0x1a, 0xd2, 0xc5,
-            0x6a, 0xe7, 0x34, 0x21,
-        ],
-        [
-            0xb1, 0xa5, 0x91, 0xdb, 0xf3, 0x8d, 0x8f, 0x8f, 0xa8, 0x3a, 0xee, 0x58, 0xc9, 0xd8,
-            0x51, 0xc0, 0xb0, 0x59, 0x38, 0xf3, 0x66, 0xd8, 0xeb, 0xfe, 0x4f, 0xbc, 0x4e, 0x84,
-            0xec, 0x90, 0xdf, 0x19,
-        ],
-        [
-            0x2b, 0xe5, 0xef, 0x22, 0xf7, 0x05, 0x8c, 0x64, 0xb4, 0x12, 0x49, 0xef, 0x93, 0x0e,
-            0xaf, 0x74, 0x2d, 0x85, 0x84, 0xfd, 0xae, 0x69, 0x1e, 0x98, 0x87, 0x07, 0x5c, 0x6b,
-            0xa6, 0xa2, 0xcc, 0x18,
-        ],
-        [
-            0x8d, 0x53, 0xd2, 0x49, 0x45, 0x64, 0x05, 0xdf, 0xfa, 0x83, 0xad, 0xef, 0xf2, 0x38,
-            0x83, 0x62, 0x3a, 0x47, 0x4f, 0xd5, 0xd2, 0x04, 0x13, 0x4d, 0x1b, 0x0d, 0x23, 0x15,
-            0x94, 0x90, 0x88, 0x23,
-        ],
-        [
-            0x40, 0xd5, 0x9e, 0x52, 0xe4, 0x73, 0xe6, 0x96, 0x1d, 0x0b, 0x8d, 0x9c, 0x2c, 0xaf,
-            0xa2, 0x66, 0xe8, 0x4d, 0x29, 0xb5, 0x43, 0xf5, 0xe8, 0xe9, 0xc0, 0x6c, 0x7b, 0xa9,
-            0xb4, 0x1f, 0x17, 0x27,
-        ],
-        [
-            0x21, 0xae, 0xe6, 0xdd, 0x96, 0x96, 0xd5, 0xf8, 0xe5, 0x83, 0x25, 0x39, 0xb9, 0x30,
-            0xb2, 0xdc, 0x28, 0x0d, 0xfc, 0x74, 0xbc, 0xa0, 0x11, 0x57, 0xfd, 0x29, 0xf6, 0x40,
-            0x05, 0x65, 0xf6, 0x2f,
-        ],
-        [
-            0x18, 0x15, 0x61, 0xce, 0x30, 0x26, 0x93, 0x69, 0x56, 0xd7, 0xad, 0xf6, 0x68, 0x51,
-            0xad, 0xe0, 0xa2, 0x78, 0x77, 0x27, 0xf5, 0xf7, 0x02, 0x59, 0xe9, 0x91, 0xd4, 0x43,
-            0xf1, 0x58, 0x0c, 0x12,
-        ],
-        [
-            0x95, 0x37, 0x48, 0x79, 0xd2, 0x65, 0xd6, 0xa2, 0x1d, 0xa2, 0x65, 0xa5, 0xa0, 0x95,
-            0xc4, 0x1e, 0x07, 0x03, 0xdb, 0xe5, 0xd5, 0x53, 0xf8, 0x7b, 0xb0, 0x21, 0x3f, 0x0d,
-            0xb7, 0xfe, 0x21, 0x1f,
-        ],
-        [
-            0xd2, 0x72, 0x8a, 0xe1, 0xdd, 0xa1, 0xb1, 0x8b, 0x96, 0x9e, 0x8a, 0x06, 0x68, 0xe7,
-            0x26, 0xa8, 0x23, 0x86, 0x6a, 0xf6, 0xc0, 0x8c, 0x63, 0x4c, 0xe1, 0x35, 0x13, 0xa7,
-            0x5f, 0x90, 0xbe, 0x24,
-        ],
-        [
-            0x6d, 0xc2, 0xda, 0x53, 0xe5, 0x74, 0x4c, 0x74, 0x28, 0xc3, 0x65, 0x1d, 0x82, 0xf3,
-            0x7e, 0x59, 0xcd, 0xd4, 0x57, 0xad, 0xde, 0xea, 0x0c, 0xc5, 0x91, 0x74, 0xd1, 0x2e,
-            0xb6, 0x66, 0x86, 0x0f,
-        ],
-        [
-            0xef, 0x59, 0x19, 0x0e, 0x23, 0x2a, 0x1a, 0x3d, 0xb4, 0x8c, 0xe0, 0x6a, 0x3f, 0x7a,
-            0x7a, 0x4e, 0x59, 0x41, 0x1c, 0x1a, 0x4a, 0x3f, 0x41, 0x34, 0xb0, 0x98, 0x2d, 0xf5,
-            0x6b, 0xd4, 0x18, 0x09,
-        ],
-        [
-            0xf2, 0x5f, 0x5c, 0x37, 0xad, 0x13, 0x85, 0x12, 0x65, 0x5a, 0xfc, 0x0a, 0x0d, 0xf9,
-            0x26, 0x2e, 0xfa, 0x4d, 0x40, 0x5e, 0x64, 0x17, 0x69, 0xe7, 0xcd, 0x9e, 0x47, 0x4c,
-            0x1b, 0xb0, 0xbe, 0x1b,
-        ],
-        [
-            0xd9, 0xea, 0x34, 0x97, 0x4c, 0x18, 0x8e, 0xac, 0xd5, 0x19, 0xb1, 0x2a, 0x92, 0xb9,
-            0x60, 0xd5, 0x1e, 0x55, 0xf5, 0xdf, 0x61, 0x6c, 0x7a, 0xa1, 0x42, 0x7e, 0x25, 0x8e,
-            0xc5, 0xa1, 0x68, 0x2f,
-        ],
-    ];
+#[event]
+pub struct FeeRecipientUpdated {
+    pub origin_mint: Pubkey,
+    pub fee_recipient: Pubkey,
+}
 
-    pub fn init(&mut self, pool: Pubkey, canopy_depth: u8, bump: u8) -> Result<()> {
-        require!(
-            (canopy_depth as usize) <= Self::MAX_CANOPY,
-            PoolError::CanopyDepthInvalid,
-        );
-        self.pool = pool;
-        self.canopy_depth = canopy_depth;
-        self.bump = bump;
-        self.next_index = 0;
-        self.zeroes = Self::compute_zeroes();
-        self.frontier = [[0u8; 32]; Self::DEPTH];
-        self.current_root = self.zeroes[Self::DEPTH - 1];
-        self.canopy = [[0u8; 32]; Self::MAX_CANOPY];
-        self.recent_commitments = [[0u8; 32]; Self::MAX_CANOPY];
-        self.recent_amount_commitments = [[0u8; 32]; Self::MAX_CANOPY];
-        self.recent_indices = [0u64; Self::MAX_CANOPY];
-        self.recent_len = 0;
-        Ok(())
-    }
+#[event]
+pub struct ReferralFeeShareUpdated {
+    pub origin_mint: Pubkey,
+    pub referral_fee_bps: u16,
+}
 
-    pub fn append_note(
-        &mut self,
-        commitment: [u8; 32],
-        amount_commit: [u8; 32],
-    ) -> Result<([u8; 32], u64)> {
-        self.insert_leaf(commitment, amount_commit)
-    }
+#[event]
+pub struct ReferralFeesClaimed {
+    pub origin_mint: Pubkey,
+    pub referrer: Pubkey,
+    pub amount: u64,
+}
 
-    pub fn append_many(
-        &mut self,
-        commitments: &[[u8; 32]],
-        amount_commitments: &[[u8; 32]],
-    ) -> Result<([u8; 32], Vec<u64>)> {
-        if commitments.is_empty() {
-            return Ok((self.current_root, Vec::new()));
-        }
-        require!(
-            commitments.len() == amount_commitments.len(),
-            PoolError::OutputSetMismatch,
-        );
-        let mut indices = Vec::with_capacity(commitments.len());
-        let mut frontier_cache = ([[0u8; 32]; Self::DEPTH], [false; Self::DEPTH]);
-        let canopy_len = core::cmp::min(self.canopy_depth as usize, Self::MAX_CANOPY);
-        let mut processed = 0usize;
-        let total = commitments.len();
+#[event]
+pub struct FeesWithdrawn {
+    pub origin_mint: Pubkey,
+    pub fee_recipient: Pubkey,
+    pub amount: u64,
+}
 
-        while processed < total {
-            let remaining = total - processed;
-            let base_index = self.next_index as usize;
-            require!(
-                (base_index as u128) < (1u128 << Self::DEPTH),
-                PoolError::TreeFull,
-            );
+#[event]
+pub struct TwinFeesWithdrawn {
+    pub origin_mint: Pubkey,
+    pub fee_recipient: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct PtknRedeemed {
+    pub origin_mint: Pubkey,
+    pub holder: Pubkey,
+    pub destination: Pubkey,
+    /// pTKN burned; the destination receives `amount - fee` origin tokens.
+    pub amount: u64,
+    pub fee: u64,
+}
+
+#[event]
+pub struct FeeUpdated {
+    pub origin_mint: Pubkey,
+    pub shield_fee_bps: u16,
+    pub unshield_fee_bps: u16,
+    pub shield_flat_fee: u64,
+    pub unshield_flat_fee: u64,
+}
 
-            let tz = if base_index == 0 {
-                Self::DEPTH
-            } else {
-                core::cmp::min(base_index.trailing_zeros() as usize, Self::DEPTH)
-            };
+#[event]
+pub struct RootGuardianUpdated {
+    pub origin_mint: Pubkey,
+    pub root_guardian: Pubkey,
+}
 
-            let mut chunk_size = (1u128 << tz) as usize;
-            if chunk_size > remaining {
-                chunk_size = highest_power_of_two_leq(remaining);
-            }
+#[event]
+pub struct CheckpointIntervalUpdated {
+    pub origin_mint: Pubkey,
+    pub interval: u64,
+}
 
-            let capacity_remaining = ((1u128 << Self::DEPTH) - base_index as u128) as usize;
-            require!(capacity_remaining > 0, PoolError::TreeFull);
-            chunk_size = core::cmp::min(chunk_size, highest_power_of_two_leq(capacity_remaining));
+#[event]
+pub struct TreeCheckpointWritten {
+    pub origin_mint: Pubkey,
+    pub index: u64,
+    pub start_leaf: u64,
+    pub end_leaf: u64,
+    pub root: [u8; 32],
+    pub digest: [u8; 32],
+}
 
-            let chunk_commitments = &commitments[processed..processed + chunk_size];
-            let chunk_amounts = &amount_commitments[processed..processed + chunk_size];
+#[event]
+pub struct VerifierEntryUpdated {
+    pub origin_mint: Pubkey,
+    pub program: Pubkey,
+    pub enabled: bool,
+}
 
-            for (offset, (commitment, amount_commit)) in chunk_commitments
-                .iter()
-                .zip(chunk_amounts.iter())
-                .enumerate()
-            {
-                let index_position = self
-                    .next_index
-                    .checked_add(offset as u64)
-                    .ok_or(PoolError::AmountOverflow)?;
-                self.record_recent(index_position, *commitment, *amount_commit);
-                indices.push(index_position);
-            }
+#[event]
+pub struct FeeRoundingUpdated {
+    pub origin_mint: Pubkey,
+    pub fee_rounding: u8,
+}
 
-            let level_start = chunk_size.trailing_zeros() as usize;
-            let mut level_nodes: Vec<Vec<[u8; 32]>> = Vec::with_capacity(level_start + 1);
-            let mut current_level: Vec<[u8; 32]> = chunk_commitments
-                .iter()
-                .map(|commitment| sha_leaf(commitment))
-                .collect();
-            level_nodes.push(current_level.clone());
+#[event]
+pub struct AccountMigrated {
+    pub origin_mint: Pubkey,
+    pub account: Pubkey,
+    pub previous_version: u8,
+    pub version: u8,
+    pub new_len: u32,
+}
 
-            for _ in 0..level_start {
-                let mut next_level = Vec::with_capacity(current_level.len() / 2);
-                for pair in current_level.chunks_exact(2) {
-                    next_level.push(sha_branch(&pair[0], &pair[1]));
-                }
-                current_level = next_level;
-                level_nodes.push(current_level.clone());
-            }
+#[event]
+pub struct PoolStateMigrated {
+    pub origin_mint: Pubkey,
+    pub previous_len: u32,
+    pub new_len: u32,
+}
 
-            let mut node_bytes = current_level[0];
+#[event]
+pub struct FeaturesUpdated {
+    pub origin_mint: Pubkey,
+    pub features: u8,
+}
 
-            for level in 0..level_start {
-                let pos = ((chunk_size - (1 << level) - 1) >> level) as usize;
-                let cached = level_nodes[level][pos];
-                self.frontier[level] = cached;
-                frontier_cache.0[level] = cached;
-                frontier_cache.1[level] = true;
-            }
+#[event]
+pub struct SequencerUpdated {
+    pub origin_mint: Pubkey,
+    pub sequencer: Pubkey,
+}
 
-            let mut index = (self.next_index + chunk_size as u64 - 1) >> (level_start as u32);
-            let mut level = level_start;
-            while level < Self::DEPTH {
-                if index % 2 == 0 {
-                    frontier_cache.0[level] = node_bytes;
-                    frontier_cache.1[level] = true;
-                    self.frontier[level] = node_bytes;
-                    let zero = self.zeroes[level];
-                    node_bytes = sha_branch(&frontier_cache.0[level], &zero);
-                } else {
-                    if !frontier_cache.1[level] {
-                        frontier_cache.0[level] = self.frontier[level];
-                        frontier_cache.1[level] = true;
-                    }
-                    let left = frontier_cache.0[level];
-                    node_bytes = sha_branch(&left, &node_bytes);
-                }
-                if canopy_len > 0 {
-                    let offset = Self::DEPTH - 1 - level;
-                    if offset < canopy_len {
-                        self.canopy[offset] = node_bytes;
-                    }
-                }
-                index >>= 1;
-                level += 1;
-            }
+#[event]
+pub struct NullifierWriteQueued {
+    pub origin_mint: Pubkey,
+    pub nullifier: [u8; 32],
+    pub sequencer: Pubkey,
+    pub execute_after: i64,
+}
 
-            self.current_root = node_bytes;
-            self.next_index = self
-                .next_index
-                .checked_add(chunk_size as u64)
-                .ok_or(PoolError::AmountOverflow)?;
-            processed += chunk_size;
-        }
+#[event]
+pub struct NullifierWritten {
+    pub origin_mint: Pubkey,
+    pub nullifier: [u8; 32],
+    pub sequencer: Pubkey,
+}
 
-        Ok((self.current_root, indices))
-    }
+#[event]
+pub struct RootSynced {
+    pub origin_mint: Pubkey,
+    pub root: [u8; 32],
+}
 
-    fn insert_leaf(
-        &mut self,
-        commitment: [u8; 32],
-        amount_commit: [u8; 32],
-    ) -> Result<([u8; 32], u64)> {
-        let mut frontier_cache = ([[0u8; 32]; Self::DEPTH], [false; Self::DEPTH]);
-        self.insert_leaf_with_cache(&mut frontier_cache, commitment, amount_commit)
-    }
+#[event]
+pub struct DenominationsUpdated {
+    pub origin_mint: Pubkey,
+    pub denominations: Vec<u64>,
+}
 
-    fn insert_leaf_with_cache(
-        &mut self,
-        frontier_cache: &mut ([[u8; 32]; Self::DEPTH], [bool; Self::DEPTH]),
-        commitment: [u8; 32],
-        amount_commit: [u8; 32],
-    ) -> Result<([u8; 32], u64)> {
-        require!(
-            self.next_index < (1u128 << Self::DEPTH) as u64,
-            PoolError::TreeFull,
-        );
-        let index_position = self.next_index;
-        let mut node_bytes = sha_leaf(&commitment);
-        let mut index = self.next_index;
-        let canopy_len = core::cmp::min(self.canopy_depth as usize, Self::MAX_CANOPY);
-        for level in 0..Self::DEPTH {
-            if index % 2 == 0 {
-                frontier_cache.0[level] = node_bytes;
-                frontier_cache.1[level] = true;
-                self.frontier[level] = node_bytes;
-                let zero = self.zeroes[level];
-                node_bytes = sha_branch(&frontier_cache.0[level], &zero);
-            } else {
-                if !frontier_cache.1[level] {
-                    frontier_cache.0[level] = self.frontier[level];
-                    frontier_cache.1[level] = true;
-                }
-                let left = frontier_cache.0[level];
-                node_bytes = sha_branch(&left, &node_bytes);
-            }
-            if canopy_len > 0 {
-                let offset = Self::DEPTH - 1 - level;
-                if offset < canopy_len {
-                    self.canopy[offset] = node_bytes;
-                }
-            }
-            index >>= 1;
-        }
-        self.next_index = self
-            .next_index
-            .checked_add(1)
-            .ok_or(PoolError::AmountOverflow)?;
-        self.current_root = node_bytes;
-        self.record_recent(index_position, commitment, amount_commit);
-        Ok((self.current_root, index_position))
-    }
+#[event]
+pub struct LimitsUpdated {
+    pub origin_mint: Pubkey,
+    pub max_deposit_amount: u64,
+    pub max_total_locked: u64,
+}
 
-    fn record_recent(&mut self, index: u64, commitment: [u8; 32], amount_commit: [u8; 32]) {
-        if (self.recent_len as usize) < Self::MAX_CANOPY {
-            let idx = self.recent_len as usize;
-            self.recent_commitments[idx] = commitment;
-            self.recent_amount_commitments[idx] = amount_commit;
-            self.recent_indices[idx] = index;
-            self.recent_len += 1;
-        } else {
-            self.recent_commitments.copy_within(1..Self::MAX_CANOPY, 0);
-            self.recent_amount_commitments
-                .copy_within(1..Self::MAX_CANOPY, 0);
-            self.recent_indices.copy_within(1..Self::MAX_CANOPY, 0);
-            self.recent_commitments[Self::MAX_CANOPY - 1] = commitment;
-            self.recent_amount_commitments[Self::MAX_CANOPY - 1] = amount_commit;
-            self.recent_indices[Self::MAX_CANOPY - 1] = index;
-        }
-    }
+#[event]
+pub struct PoolStatsConfigured {
+    pub origin_mint: Pubkey,
+    pub enabled: bool,
+}
 
-    fn compute_zeroes() -> [[u8; 32]; Self::DEPTH] {
-        let mut zeroes = [[0u8; 32]; Self::DEPTH];
-        let empty_leaf = [0u8; 32];
-        zeroes[0] = sha_leaf(&empty_leaf);
-        for level in 1..Self::DEPTH {
-            let prev = zeroes[level - 1];
-            zeroes[level] = sha_branch(&prev, &prev);
-        }
-        zeroes
-    }
+#[event]
+pub struct PoolStatsEpochClosed {
+    pub origin_mint: Pubkey,
+    pub stats: EpochStats,
 }
 
-#[account(zero_copy(unsafe))]
-#[repr(C)]
-pub struct PoolState {
-    pub authority: Pubkey,
+#[event]
+pub struct DestinationAllowlistConfigured {
     pub origin_mint: Pubkey,
-    pub vault: Pubkey,
-    pub verifier_program: Pubkey,
-    pub verifying_key: Pubkey,
-    pub commitment_tree: Pubkey,
-    pub verifying_key_id: [u8; 32],
-    pub verifying_key_hash: [u8; 32],
-    pub current_root: [u8; 32],
-    pub recent_roots: [[u8; 32]; PoolState::MAX_ROOTS],
-    pub roots_len: u8,
-    pub fee_bps: u16,
-    pub features: FeatureFlags,
-    pub note_ledger: Pubkey,
-    pub note_ledger_bump: u8,
-    pub protocol_fees: u128,
-    pub hook_config: Pubkey,
-    pub hook_config_present: bool,
-    pub hook_config_bump: u8,
-    pub bump: u8,
-    pub twin_mint: Pubkey,
-    pub twin_mint_enabled: bool,
-    pub pending_shield: PendingShield,
+    pub compliance_authority: Pubkey,
+    pub enabled: bool,
 }
 
-impl PoolState {
-    pub const MAX_ROOTS: usize = 16;
-    pub const SPACE: usize = 8 + core::mem::size_of::<PoolState>() + 64;
+#[event]
+pub struct DestinationAllowlistUpdated {
+    pub origin_mint: Pubkey,
+    pub owner: Pubkey,
+    pub allowed: bool,
+}
 
-    pub fn push_root(&mut self, root: [u8; 32]) {
-        if self.roots_len as usize >= Self::MAX_ROOTS {
-            for idx in 1..Self::MAX_ROOTS {
-                self.recent_roots[idx - 1] = self.recent_roots[idx];
-            }
-            self.recent_roots[Self::MAX_ROOTS - 1] = root;
-            self.current_root = root;
-        } else {
-            self.recent_roots[self.roots_len as usize] = root;
-            self.roots_len += 1;
-            self.current_root = root;
-        }
-    }
+#[event]
+pub struct RateLimitConfigured {
+    pub origin_mint: Pubkey,
+    pub window_slots: u64,
+    pub max_inflow: u64,
+    pub max_outflow: u64,
+}
 
-    pub fn is_known_root(&self, candidate: &[u8; 32]) -> bool {
-        if &self.current_root == candidate {
-            return true;
-        }
-        for idx in 0..self.roots_len as usize {
-            if &self.recent_roots[idx] == candidate {
-                return true;
-            }
-        }
-        false
-    }
+#[event]
+pub struct MaxRootAgeUpdated {
+    pub origin_mint: Pubkey,
+    pub max_root_age_slots: u64,
+}
 
-    pub fn calculate_fee(&self, amount: u64) -> Result<u64> {
-        let fee = (amount as u128)
-            .checked_mul(self.fee_bps as u128)
-            .ok_or(PoolError::AmountOverflow)?
-            / 10_000u128;
-        Ok(fee as u64)
-    }
+#[event]
+pub struct MinNoteAgeUpdated {
+    pub origin_mint: Pubkey,
+    pub min_note_age_leaves: u64,
 }
 
-#[repr(C)]
-#[derive(Clone, Copy)]
-#[cfg_attr(feature = "idl-build", derive(IdlBuild))]
-pub struct PendingShield {
-    pub active: u8,
-    pub old_root: [u8; 32],
-    pub new_root: [u8; 32],
-    pub commitment: [u8; 32],
-    pub amount_commit: [u8; 32],
-    pub amount: u64,
-    pub depositor: Pubkey,
-    pub next_index: u64,
+#[event]
+pub struct VerifyingKeyRotated {
+    pub origin_mint: Pubkey,
+    pub previous_verifying_key: Pubkey,
+    pub verifying_key: Pubkey,
+    pub overlap_ends_slot: u64,
 }
 
-impl PendingShield {
-    pub fn inactive() -> Self {
-        Self {
-            active: 0,
-            old_root: [0u8; 32],
-            new_root: [0u8; 32],
-            commitment: [0u8; 32],
-            amount_commit: [0u8; 32],
-            amount: 0,
-            depositor: Pubkey::default(),
-            next_index: 0,
-        }
-    }
+#[event]
+pub struct PoolPaused {
+    pub origin_mint: Pubkey,
+    pub authority: Pubkey,
+}
 
-    pub fn is_inactive(&self) -> bool {
-        self.active == 0
-    }
+#[event]
+pub struct EmergencyExitEnabled {
+    pub origin_mint: Pubkey,
+    pub root: [u8; 32],
+    pub verifier_program: Pubkey,
+    pub verifying_key: Pubkey,
+}
 
-    pub fn deactivate(&mut self) {
-        *self = Self::inactive();
-    }
+#[event]
+pub struct PoolUnpaused {
+    pub origin_mint: Pubkey,
+    pub authority: Pubkey,
 }
 
-#[account]
-#[repr(C)]
-pub struct ShieldClaim {
+#[event]
+pub struct PoolTimelockUpdated {
+    pub origin_mint: Pubkey,
+    pub timelock_seconds: i64,
+}
+
+#[event]
+pub struct PoolTimelockQueued {
     pub pool: Pubkey,
-    pub depositor: Pubkey,
-    pub commitment: [u8; 32],
-    pub amount_commit: [u8; 32],
-    pub old_root: [u8; 32],
-    pub new_root: [u8; 32],
-    pub amount: u64,
-    pub next_index: u64,
-    pub bump: u8,
-    pub status: u8,
-    pub enforce_invariant: u8,
-    pub tree_level: u8,
-    pub tree_index_cursor: u64,
-    pub tree_node: [u8; 32],
+    pub action_hash: [u8; 32],
+    pub queued_at: i64,
+    pub execute_after: i64,
 }
 
-impl ShieldClaim {
-    pub const STATUS_INACTIVE: u8 = 0;
-    pub const STATUS_PENDING_TREE: u8 = 1;
-    pub const STATUS_AWAITING_LEDGER: u8 = 2;
-    pub const STATUS_AWAITING_INVARIANT: u8 = 3;
-    pub const SPACE: usize = 8 + core::mem::size_of::<ShieldClaim>();
+#[event]
+pub struct PoolTimelockExecuted {
+    pub pool: Pubkey,
+    pub action_hash: [u8; 32],
+    pub executed_at: i64,
+    pub executor: Pubkey,
+}
 
-    pub fn is_active(&self) -> bool {
-        self.status != Self::STATUS_INACTIVE
-    }
+#[event]
+pub struct PoolTimelockCanceled {
+    pub pool: Pubkey,
+    pub action_hash: [u8; 32],
+    pub canceled_at: i64,
+    pub authority: Pubkey,
+}
 
-    pub fn is_pending_tree(&self) -> bool {
-        self.status == Self::STATUS_PENDING_TREE
-    }
+/// Where the value behind a new shield note comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShieldSource {
+    /// Origin tokens deposited into the vault.
+    Origin,
+    /// pTKN burned through the factory.
+    Twin,
+    /// Origin tokens already moved into the vault by an earlier instruction.
+    Prefunded,
+}
 
-    pub fn is_awaiting_ledger(&self) -> bool {
-        self.status == Self::STATUS_AWAITING_LEDGER
-    }
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub enum UnshieldMode {
+    Origin = 0,
+    Twin = 1,
+}
 
-    pub fn is_awaiting_invariant(&self) -> bool {
-        self.status == Self::STATUS_AWAITING_INVARIANT
-    }
+#[repr(u8)]
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum HookAccountMode {
+    Strict = 0,
+    Lenient = 1,
+}
 
-    pub fn activate(
-        &mut self,
-        pool: Pubkey,
-        depositor: Pubkey,
-        commitment: [u8; 32],
-        amount_commit: [u8; 32],
-        old_root: [u8; 32],
-        new_root: [u8; 32],
-        amount: u64,
-        next_index: u64,
-        bump: u8,
-    ) {
-        self.pool = pool;
-        self.depositor = depositor;
-        self.commitment = commitment;
-        self.amount_commit = amount_commit;
-        self.old_root = old_root;
-        self.new_root = new_root;
-        self.amount = amount;
-        self.next_index = next_index;
-        self.bump = bump;
-        self.status = Self::STATUS_PENDING_TREE;
-        self.enforce_invariant = 0;
-        self.tree_level = 0;
-        self.tree_index_cursor = next_index;
-        self.tree_node = commitment;
-    }
+#[error_code]
+pub enum PoolError {
+    #[msg("E_INVALID_FEE_BPS")]
+    InvalidFeeBps,
+    #[msg("E_VERIFIER_MISMATCH")]
+    VerifierMismatch,
+    #[msg("E_VERIFYING_KEY_HASH_MISMATCH")]
+    VerifyingKeyHashMismatch,
+    #[msg("E_INVALID_PUBLIC_INPUTS")]
+    InvalidPublicInputs,
+    #[msg("E_PUBLIC_INPUT_MISMATCH")]
+    PublicInputMismatch,
+    #[msg("E_UNKNOWN_ROOT")]
+    UnknownRoot,
+    #[msg("E_NULLIFIER_REUSE")]
+    NullifierReuse,
+    #[msg("E_NULLIFIER_CAPACITY")]
+    NullifierCapacity,
+    #[msg("E_AMOUNT_OVERFLOW")]
+    AmountOverflow,
+    #[msg("E_INSUFFICIENT_LIQUIDITY")]
+    InsufficientLiquidity,
+    #[msg("E_FEATURE_DISABLED")]
+    FeatureDisabled,
+    #[msg("E_VAULT_AUTHORITY_MISMATCH")]
+    MismatchedVaultAuthority,
+    #[msg("E_ORIGIN_MINT_MISMATCH")]
+    OriginMintMismatch,
+    #[msg("E_VAULT_TOKEN_ACCOUNT_MISMATCH")]
+    VaultTokenAccountMismatch,
+    #[msg("E_INVALID_DEPOSITOR_ACCOUNT")]
+    InvalidDepositorAccount,
+    #[msg("E_TWIN_MINT_MISMATCH")]
+    TwinMintMismatch,
+    #[msg("E_TWIN_TOKEN_PROGRAM_MISMATCH")]
+    TwinTokenProgramMismatch,
+    #[msg("E_REDEEM_AMOUNT_TOO_SMALL")]
+    RedeemAmountTooSmall,
+    #[msg("E_TWIN_MINT_NOT_CONFIGURED")]
+    TwinMintNotConfigured,
+    #[msg("E_TWIN_MINT_AUTHORITY_MISMATCH")]
+    TwinMintAuthorityMismatch,
+    #[msg("E_TWIN_MINT_DECIMALS_MISMATCH")]
+    TwinMintDecimalsMismatch,
+    #[msg("E_INVARIANT_BREACH")]
+    InvariantBreach,
+    #[msg("E_HOOKS_DISABLED")]
+    HooksDisabled,
+    #[msg("E_TOO_MANY_HOOK_ACCOUNTS")]
+    TooManyHookAccounts,
+    #[msg("E_HOOK_CONFIG_INVALID")]
+    HookConfigInvalid,
+    #[msg("E_HOOK_ACCOUNT_MISMATCH")]
+    HookAccountMismatch,
+    #[msg("E_HOOK_ACCOUNT_MISSING")]
+    HookAccountMissing,
+    #[msg("E_HOOK_ACCOUNT_UNEXPECTED")]
+    HookAccountUnexpected,
+    #[msg("E_NOTE_LEDGER_MISMATCH")]
+    NoteLedgerMismatch,
+    #[msg("E_TREE_MISMATCH")]
+    CommitmentTreeMismatch,
+    #[msg("E_COMMITMENT_TREE_MIGRATION_REQUIRED")]
+    CommitmentTreeMigrationRequired,
+    #[msg("E_INVALID_CHANGE_NOTE_COUNT")]
+    InvalidChangeNoteCount,
+    #[msg("E_OUTPUT_SET_MISMATCH")]
+    OutputSetMismatch,
+    #[msg("E_CANOPY_DEPTH_INVALID")]
+    CanopyDepthInvalid,
+    #[msg("E_TREE_FULL")]
+    TreeFull,
+    #[msg("E_ROOT_MISMATCH")]
+    RootMismatch,
+    #[msg("E_PENDING_SHIELD_IN_FLIGHT")]
+    PendingShieldInFlight,
+    #[msg("E_NO_PENDING_SHIELD")]
+    NoPendingShield,
+    #[msg("E_PENDING_SHIELD_MISMATCH")]
+    PendingShieldMismatch,
+    #[msg("E_SHIELD_FINALIZE_MISSING")]
+    MissingShieldFinalize,
+    #[msg("E_SHIELD_CLAIM_MISMATCH")]
+    ShieldClaimMismatch,
+    #[msg("E_SHIELD_CLAIM_STAGE")]
+    ShieldClaimStage,
+    #[msg("E_ALLOWANCE_POOL_MISMATCH")]
+    AllowancePoolMismatch,
+    #[msg("E_ALLOWANCE_OWNER_MISMATCH")]
+    AllowanceOwnerMismatch,
+    #[msg("E_ALLOWANCE_SPENDER_MISMATCH")]
+    AllowanceSpenderMismatch,
+    #[msg("E_ALLOWANCE_MINT_MISMATCH")]
+    AllowanceMintMismatch,
+    #[msg("E_ALLOWANCE_INSUFFICIENT")]
+    AllowanceInsufficient,
+    #[msg("E_ALLOWANCE_AMOUNT_INVALID")]
+    AllowanceAmountInvalid,
+    #[msg("E_NULLIFIER_SHARD_MISMATCH")]
+    NullifierShardMismatch,
+    #[msg("E_NULLIFIER_SHARD_MISSING")]
+    NullifierShardMissing,
+    #[msg("E_NULLIFIER_SHARD_COUNT_INVALID")]
+    NullifierShardCountInvalid,
+    #[msg("E_NULLIFIER_SHARDS_ALREADY_ENABLED")]
+    NullifierShardsAlreadyEnabled,
+    #[msg("E_NULLIFIER_MODE_UNSUPPORTED")]
+    NullifierModeUnsupported,
+    #[msg("E_NULLIFIER_RECORD_MISMATCH")]
+    NullifierRecordMismatch,
+    #[msg("E_NULLIFIER_RECORD_MISSING")]
+    NullifierRecordMissing,
+    #[msg("E_NULLIFIER_PAYER_MISSING")]
+    NullifierPayerMissing,
+    #[msg("E_NULLIFIER_SET_INVALID")]
+    NullifierSetInvalid,
+    #[msg("E_NULLIFIER_GROWTH_NOT_NEEDED")]
+    NullifierGrowthNotNeeded,
+    #[msg("E_NULLIFIER_SHARD_NOT_FULL")]
+    NullifierShardNotFull,
+    #[msg("E_ROOT_BEFORE_ARCHIVED_EPOCH")]
+    RootBeforeArchivedEpoch,
+    #[msg("E_MERKLE_DEPTH_INVALID")]
+    MerkleDepthInvalid,
+    #[msg("E_UNSHIELD_RECIPIENTS_INVALID")]
+    UnshieldRecipientsInvalid,
+    #[msg("E_TRANSFER_ARITY_INVALID")]
+    TransferArityInvalid,
+    #[msg("E_FEE_RECIPIENT_MISMATCH")]
+    FeeRecipientMismatch,
+    #[msg("E_INSUFFICIENT_PROTOCOL_FEES")]
+    InsufficientProtocolFees,
+    #[msg("E_FEE_MISMATCH")]
+    FeeMismatch,
+    #[msg("E_POOL_STATE_INVALID")]
+    PoolStateInvalid,
+    #[msg("E_FEE_EXCEEDS_LIMIT")]
+    FeeExceedsLimit,
+    #[msg("E_TIMELOCK_OVERFLOW")]
+    TimelockOverflow,
+    #[msg("E_TIMELOCK_CONSUMED")]
+    TimelockConsumed,
+    #[msg("E_TIMELOCK_NOT_READY")]
+    TimelockNotReady,
+    #[msg("E_TIMELOCK_INVALID_POOL")]
+    TimelockInvalidPool,
+    #[msg("E_TIMELOCK_ONLY_QUEUE")]
+    TimelockOnlyQueue,
+    #[msg("E_TIMELOCK_INVALID")]
+    TimelockInvalid,
+    #[msg("E_SERIALIZATION")]
+    SerializationError,
+    #[msg("E_POOL_PAUSED")]
+    PoolPaused,
+    #[msg("E_VERIFYING_KEY_UNCHANGED")]
+    VerifyingKeyUnchanged,
+    #[msg("E_SEQUENCER_MISMATCH")]
+    SequencerMismatch,
+    #[msg("E_ENCRYPTED_NOTE_TOO_LARGE")]
+    EncryptedNoteTooLarge,
+    #[msg("E_HOOK_TEMPLATE_INVALID")]
+    HookTemplateInvalid,
+    #[msg("E_HOOK_ACCOUNT_NOT_WRITABLE")]
+    HookAccountNotWritable,
+    #[msg("E_DENOMINATION_NOT_ALLOWED")]
+    DenominationNotAllowed,
+    #[msg("E_INVALID_DENOMINATIONS")]
+    InvalidDenominations,
+    #[msg("E_DEPOSIT_LIMIT_EXCEEDED")]
+    DepositLimitExceeded,
+    #[msg("E_TOTAL_LOCKED_LIMIT_EXCEEDED")]
+    TotalLockedLimitExceeded,
+    #[msg("E_RATE_LIMIT_EXCEEDED")]
+    RateLimitExceeded,
+    #[msg("E_RATE_LIMITER_MISMATCH")]
+    RateLimiterMismatch,
+    #[msg("E_EMERGENCY_EXIT_DISABLED")]
+    EmergencyExitDisabled,
+    #[msg("E_EMERGENCY_EXIT_ACTIVE")]
+    EmergencyExitActive,
+    #[msg("E_EMERGENCY_CHANGE_NOT_ALLOWED")]
+    EmergencyChangeNotAllowed,
+    #[msg("E_MEMBERSHIP_PROOF_INVALID")]
+    MembershipProofInvalid,
+    #[msg("E_DEPOSIT_SHORTFALL")]
+    DepositShortfall,
+    #[msg("E_NATIVE_MINT_REQUIRED")]
+    NativeMintRequired,
+    #[msg("E_NATIVE_UNWRAP_INVALID")]
+    NativeUnwrapInvalid,
+    #[msg("E_DESTINATION_ATA_ACCOUNTS_MISSING")]
+    DestinationAtaAccountsMissing,
+    #[msg("E_SWAP_TERMS_INVALID")]
+    SwapTermsInvalid,
+    #[msg("E_SWAP_EXPIRED")]
+    SwapExpired,
+    #[msg("E_RECEIPT_INVALID")]
+    ReceiptInvalid,
+    #[msg("E_LEAF_RECORD_MISSING")]
+    LeafRecordMissing,
+    #[msg("E_LEAF_RECORD_INVALID")]
+    LeafRecordInvalid,
+    #[msg("E_FEE_ROUNDING_INVALID")]
+    FeeRoundingInvalid,
+    #[msg("E_OUTPUT_CIPHERTEXT_INVALID")]
+    OutputCiphertextInvalid,
+    #[msg("E_NOTE_TOO_YOUNG")]
+    NoteTooYoung,
+    #[msg("E_ROOT_GUARDIAN_REQUIRED")]
+    RootGuardianRequired,
+    #[msg("E_VERIFIER_ALLOWLIST_FULL")]
+    VerifierAllowlistFull,
+    #[msg("E_CHECKPOINT_NOT_DUE")]
+    CheckpointNotDue,
+    #[msg("E_DESTINATION_OWNER_OFF_CURVE")]
+    DestinationOwnerOffCurve,
+    #[msg("E_SHIELD_CPI_REQUIRED")]
+    ShieldCpiRequired,
+    #[msg("E_PREFUNDED_AMOUNT_MISMATCH")]
+    PrefundedAmountMismatch,
+    #[msg("E_POOL_STATS_MISMATCH")]
+    PoolStatsMismatch,
+    #[msg("E_DESTINATION_ALLOWLIST_MISMATCH")]
+    DestinationAllowlistMismatch,
+    #[msg("E_DESTINATION_NOT_ALLOWLISTED")]
+    DestinationNotAllowlisted,
+    #[msg("E_DESTINATION_ALLOWLIST_FULL")]
+    DestinationAllowlistFull,
+    #[msg("E_REFERRAL_ACCRUAL_MISMATCH")]
+    ReferralAccrualMismatch,
+    #[msg("E_INSUFFICIENT_REFERRAL_FEES")]
+    InsufficientReferralFees,
+    #[msg("E_SCHEDULED_VERIFIER_MISSING")]
+    ScheduledVerifierMissing,
+    #[msg("E_NOTE_LOCKED")]
+    NoteLocked,
+    #[msg("E_ESCROW_INVALID")]
+    EscrowInvalid,
+    #[msg("E_MINT_FROZEN")]
+    MintFrozen,
+    #[msg("E_VERIFYING_KEY_NOT_PINNED")]
+    VerifyingKeyNotPinned,
+    #[msg("E_PROTOCOL_HALTED")]
+    ProtocolHalted,
+    #[msg("E_FEE_ABOVE_PROTOCOL_MAX")]
+    FeeAboveProtocolMax,
+    #[msg("E_VERIFIER_NOT_ALLOWED_BY_PROTOCOL")]
+    VerifierNotAllowedByProtocol,
+}
 
-    pub fn deactivate(&mut self) {
-        self.depositor = Pubkey::default();
-        self.commitment = [0u8; 32];
-        self.amount_commit = [0u8; 32];
-        self.old_root = [0u8; 32];
-        self.new_root = [0u8; 32];
-        self.amount = 0;
-        self.next_index = 0;
-        self.status = Self::STATUS_INACTIVE;
-        self.enforce_invariant = 0;
-        self.tree_level = 0;
-        self.tree_index_cursor = 0;
-        self.tree_node = [0u8; 32];
+fn validate_hook_accounts(
+    required_accounts: &[AccountMeta],
+    mode: HookAccountMode,
+    remaining_accounts: &[AccountInfo<'_>],
+) -> Result<()> {
+    let required: Vec<Pubkey> = required_accounts.iter().map(|meta| meta.pubkey).collect();
+    let provided: Vec<Pubkey> = remaining_accounts
+        .iter()
+        .map(|account| account.key())
+        .collect();
+    validate_hook_keys(&required, mode, &provided)
+}
+
+/// Shrinks a program-owned account to `space` and returns the rent it no longer needs
+/// to `recipient`.
+fn shrink_account<'info>(
+    info: &AccountInfo<'info>,
+    space: usize,
+    recipient: &AccountInfo<'info>,
+) -> Result<()> {
+    if info.data_len() <= space {
+        return Ok(());
     }
+    #[allow(deprecated)]
+    info.realloc(space, false)?;
+    let excess = info
+        .lamports()
+        .saturating_sub(Rent::get()?.minimum_balance(space));
+    **info.try_borrow_mut_lamports()? -= excess;
+    **recipient.try_borrow_mut_lamports()? += excess;
+    Ok(())
+}
 
-    pub fn mark_tree_complete(&mut self) {
-        self.status = Self::STATUS_AWAITING_LEDGER;
-        self.tree_level = CommitmentTree::DEPTH as u8;
+/// Reallocs a program-owned account up to `space`, topping up rent from `payer` first.
+/// Accounts already at least `space` bytes long are left untouched.
+fn grow_account<'info>(
+    info: &AccountInfo<'info>,
+    space: usize,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+) -> Result<()> {
+    if info.data_len() >= space {
+        return Ok(());
     }
-
-    pub fn mark_ledger_complete(&mut self, requires_invariant: bool) {
-        if requires_invariant {
-            self.enforce_invariant = 1;
-            self.status = Self::STATUS_AWAITING_INVARIANT;
-        } else {
-            self.deactivate();
-        }
+    let required_lamports = Rent::get()?.minimum_balance(space);
+    let top_up = required_lamports.saturating_sub(info.lamports());
+    if top_up > 0 {
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: payer.to_account_info(),
+                    to: info.clone(),
+                },
+            ),
+            top_up,
+        )?;
     }
+    #[allow(deprecated)]
+    info.realloc(space, true)?;
+    Ok(())
+}
 
-    pub fn needs_invariant(&self) -> bool {
-        self.enforce_invariant == 1
+/// Builds the account list handed to a hook program: the hook config and pool first,
+/// followed by the caller-supplied hook accounts. Only accounts the config declares
+/// writable are passed writable, and those must have been supplied writable.
+fn hook_invocation_accounts<'info>(
+    hook_config: AccountInfo<'info>,
+    pool: AccountInfo<'info>,
+    hook_accounts: &[AccountInfo<'info>],
+    required_accounts: &[AccountMeta],
+) -> Result<(Vec<AccountMeta>, Vec<AccountInfo<'info>>)> {
+    let mut metas = Vec::with_capacity(2 + hook_accounts.len());
+    let mut infos = Vec::with_capacity(2 + hook_accounts.len());
+    metas.push(AccountMeta::new_readonly(hook_config.key(), false));
+    metas.push(AccountMeta::new_readonly(pool.key(), false));
+    infos.push(hook_config);
+    infos.push(pool);
+    for account in hook_accounts.iter() {
+        let writable = required_accounts
+            .iter()
+            .any(|meta| meta.pubkey == account.key() && meta.is_writable);
+        let meta = if writable {
+            require!(account.is_writable, PoolError::HookAccountNotWritable);
+            AccountMeta::new(account.key(), account.is_signer)
+        } else {
+            AccountMeta::new_readonly(account.key(), account.is_signer)
+        };
+        metas.push(meta);
+        infos.push(account.clone());
     }
+    Ok((metas, infos))
+}
 
-    pub fn snapshot(&self) -> PendingShield {
-        PendingShield {
-            active: if self.is_active() { 1 } else { 0 },
-            old_root: self.old_root,
-            new_root: self.new_root,
-            commitment: self.commitment,
-            amount_commit: self.amount_commit,
-            amount: self.amount,
-            depositor: self.depositor,
-            next_index: self.next_index,
+fn validate_hook_keys(
+    required_accounts: &[Pubkey],
+    mode: HookAccountMode,
+    provided_accounts: &[Pubkey],
+) -> Result<()> {
+    match mode {
+        HookAccountMode::Strict => {
+            require!(
+                provided_accounts.len() == required_accounts.len(),
+                PoolError::HookAccountMismatch
+            );
+            for (expected, provided) in required_accounts.iter().zip(provided_accounts.iter()) {
+                require_keys_eq!(*expected, *provided, PoolError::HookAccountMismatch);
+            }
+        }
+        HookAccountMode::Lenient => {
+            for expected in required_accounts {
+                require!(
+                    provided_accounts.iter().any(|account| account == expected),
+                    PoolError::HookAccountMissing
+                );
+            }
         }
     }
-}
 
-#[account(zero_copy(unsafe))]
-#[repr(C)]
-pub struct NullifierSet {
-    pub pool: Pubkey,
-    pub count: u32,
-    pub entries: [[u8; 32]; NullifierSet::MAX_NULLIFIERS],
-    pub bloom: [u8; NullifierSet::BLOOM_BYTES],
-    pub bump: u8,
+    Ok(())
 }
 
-impl NullifierSet {
-    pub const MAX_NULLIFIERS: usize = 256;
-    pub const BLOOM_BYTES: usize = 512;
-    pub const SPACE: usize = 8 + core::mem::size_of::<NullifierSet>() + 64;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::prelude::InterfaceAccount;
+    use anchor_lang::solana_program::{
+        account_info::AccountInfo, entrypoint::ProgramResult, program_pack::Pack,
+    };
+    use anchor_spl::token::spl_token;
+    use anchor_spl::token::spl_token::state::{
+        Account as SplAccountState, AccountState, Mint as SplMintState,
+    };
+    use anchor_spl::token_interface::{Mint as InterfaceMint, TokenAccount};
 
-    pub fn insert(&mut self, value: [u8; 32]) -> Result<()> {
-        if self.contains(&value) {
-            return err!(PoolError::NullifierReuse);
-        }
-        require!(
-            (self.count as usize) < Self::MAX_NULLIFIERS,
-            PoolError::NullifierCapacity,
-        );
-        self.entries[self.count as usize] = value;
-        self.count += 1;
-        self.set_bloom_bits(&value);
-        Ok(())
+    #[test]
+    fn strict_mode_requires_exact_accounts() {
+        let a = Pubkey::new_unique();
+        let b = Pubkey::new_unique();
+        assert!(validate_hook_keys(&[a, b], HookAccountMode::Strict, &[a, b]).is_ok());
+        assert!(validate_hook_keys(&[a, b], HookAccountMode::Strict, &[b, a]).is_err());
+        assert!(validate_hook_keys(&[a, b], HookAccountMode::Strict, &[a]).is_err());
     }
 
-    fn contains(&self, value: &[u8; 32]) -> bool {
-        if !self.test_bloom_bits(value) {
-            return false;
-        }
-        for idx in 0..self.count as usize {
-            if self.entries[idx] == *value {
-                return true;
-            }
-        }
-        false
+    #[test]
+    fn lenient_mode_requires_subset_only() {
+        let a = Pubkey::new_unique();
+        let b = Pubkey::new_unique();
+        let c = Pubkey::new_unique();
+        assert!(validate_hook_keys(&[a, b], HookAccountMode::Lenient, &[c, a, b]).is_ok());
+        assert!(validate_hook_keys(&[a, b], HookAccountMode::Lenient, &[c, a]).is_err());
     }
 
-    fn set_bloom_bits(&mut self, value: &[u8; 32]) {
-        for position in Self::bloom_positions(value) {
-            let byte_index = position / 8;
-            let bit_index = position % 8;
-            self.bloom[byte_index] |= 1 << bit_index;
-        }
+    #[test]
+    fn hook_templates_expand_per_user() {
+        let program = Pubkey::new_unique();
+        let user = Pubkey::new_unique();
+        let template = HookAccountTemplate::from_args(&HookAccountTemplateArgs {
+            program_id: program,
+            seeds: vec![HookSeed::Literal(b"state".to_vec()), HookSeed::User],
+            is_writable: false,
+        })
+        .unwrap();
+        assert!(template.uses_user());
+        let (expected, _) =
+            Pubkey::find_program_address(&[b"state".as_ref(), user.as_ref()], &program);
+        assert_eq!(template.derive(&user), expected);
+
+        assert!(HookAccountTemplate::from_args(&HookAccountTemplateArgs {
+            program_id: program,
+            seeds: vec![HookSeed::Literal(vec![0u8; 33])],
+            is_writable: false,
+        })
+        .is_err());
     }
 
-    fn test_bloom_bits(&self, value: &[u8; 32]) -> bool {
-        for position in Self::bloom_positions(value) {
-            let byte_index = position / 8;
-            let bit_index = position % 8;
-            if (self.bloom[byte_index] & (1 << bit_index)) == 0 {
-                return false;
-            }
-        }
-        true
+    #[test]
+    fn pre_hooks_reject_accounts_derived_for_other_users() {
+        let mut hook_config: HookConfig = bytemuck::Zeroable::zeroed();
+        hook_config.account_templates[0] =
+            HookAccountTemplate::from_args(&HookAccountTemplateArgs {
+                program_id: Pubkey::new_unique(),
+                seeds: vec![HookSeed::Literal(b"screen".to_vec()), HookSeed::User],
+                is_writable: false,
+            })
+            .unwrap();
+        hook_config.account_templates_len = 1;
+        let depositor = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+        let keys = |direction, users: &[Pubkey]| -> Vec<Pubkey> {
+            hook_config
+                .required_metas(direction, users)
+                .into_iter()
+                .map(|meta| meta.pubkey)
+                .collect()
+        };
+
+        // A pre-shield screen only accepts accounts derived for the depositor.
+        let for_depositor = keys(HookDirection::Shield, &[depositor]);
+        let for_other = keys(HookDirection::Shield, &[other]);
+        assert_eq!(
+            validate_hook_keys(&for_depositor, HookAccountMode::Strict, &for_other).unwrap_err(),
+            error!(PoolError::HookAccountMismatch)
+        );
+        assert_eq!(
+            validate_hook_keys(&for_depositor, HookAccountMode::Lenient, &for_other).unwrap_err(),
+            error!(PoolError::HookAccountMissing)
+        );
+
+        // A pre-unshield screen needs the accounts of every destination owner.
+        let both = keys(HookDirection::Unshield, &[depositor, other]);
+        assert_eq!(both.len(), 2);
+        assert_eq!(
+            validate_hook_keys(&both, HookAccountMode::Strict, &for_depositor).unwrap_err(),
+            error!(PoolError::HookAccountMismatch)
+        );
+        assert_eq!(
+            validate_hook_keys(&both, HookAccountMode::Lenient, &for_depositor).unwrap_err(),
+            error!(PoolError::HookAccountMissing)
+        );
+        assert!(validate_hook_keys(&both, HookAccountMode::Lenient, &both).is_ok());
     }
 
-    fn bloom_positions(value: &[u8; 32]) -> [usize; 3] {
-        let mut hasher = Keccak256::new();
-        hasher.update(value);
-        let bytes: [u8; 32] = hasher.finalize().into();
-        let mut positions = [0usize; 3];
-        for (idx, chunk) in positions.iter_mut().enumerate() {
-            let start = idx * 8;
-            let mut slice = [0u8; 8];
-            slice.copy_from_slice(&bytes[start..start + 8]);
-            let value = u64::from_le_bytes(slice) as usize;
-            *chunk = value % (Self::BLOOM_BYTES * 8);
-        }
-        positions
+    #[test]
+    fn post_transfer_hook_checks_accounts_and_writability() {
+        let mut hook_config: HookConfig = bytemuck::Zeroable::zeroed();
+        let (config_key, pool_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (oracle, ledger) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (keys, len, writable) = HookConfig::pack_accounts(&[
+            HookAccountArgs {
+                key: oracle,
+                is_writable: false,
+            },
+            HookAccountArgs {
+                key: ledger,
+                is_writable: true,
+            },
+        ])
+        .unwrap();
+        hook_config.post_shield_accounts = keys;
+        hook_config.post_shield_accounts_len = len;
+        hook_config.post_shield_writable = writable;
+        hook_config.account_templates[0] =
+            HookAccountTemplate::from_args(&HookAccountTemplateArgs {
+                program_id: Pubkey::new_unique(),
+                seeds: vec![HookSeed::User],
+                is_writable: false,
+            })
+            .unwrap();
+        hook_config.account_templates_len = 1;
+        // Transfers name no user, so per-user templates add nothing.
+        let required = hook_config.required_metas(HookDirection::Shield, &[]);
+        assert_eq!(required.len(), 2);
+
+        let owner = crate::ID;
+        let mut lamports = [0u64; 4];
+        let mut data: [Vec<u8>; 4] = Default::default();
+        let [config_lamports, pool_lamports, oracle_lamports, ledger_lamports] = &mut lamports;
+        let [config_data, pool_data, oracle_data, ledger_data] = &mut data;
+        let config_info = AccountInfo::new(
+            &config_key,
+            false,
+            false,
+            config_lamports,
+            config_data,
+            &owner,
+            false,
+            0,
+        );
+        let pool_info = AccountInfo::new(
+            &pool_key,
+            false,
+            false,
+            pool_lamports,
+            pool_data,
+            &owner,
+            false,
+            0,
+        );
+        let oracle_info = AccountInfo::new(
+            &oracle,
+            false,
+            false,
+            oracle_lamports,
+            oracle_data,
+            &owner,
+            false,
+            0,
+        );
+        let mut ledger_info = AccountInfo::new(
+            &ledger,
+            false,
+            false,
+            ledger_lamports,
+            ledger_data,
+            &owner,
+            false,
+            0,
+        );
+
+        assert_eq!(
+            validate_hook_accounts(
+                &required,
+                HookAccountMode::Strict,
+                core::slice::from_ref(&oracle_info)
+            )
+            .unwrap_err(),
+            error!(PoolError::HookAccountMismatch)
+        );
+        let hook_accounts = [oracle_info.clone(), ledger_info.clone()];
+        validate_hook_accounts(&required, HookAccountMode::Strict, &hook_accounts).unwrap();
+        // An account the config declares writable must be supplied writable.
+        assert_eq!(
+            hook_invocation_accounts(
+                config_info.clone(),
+                pool_info.clone(),
+                &hook_accounts,
+                &required
+            )
+            .err(),
+            Some(error!(PoolError::HookAccountNotWritable))
+        );
+
+        ledger_info.is_writable = true;
+        let (metas, infos) = hook_invocation_accounts(
+            config_info,
+            pool_info,
+            &[oracle_info, ledger_info],
+            &required,
+        )
+        .unwrap();
+        assert_eq!(infos.len(), 4);
+        assert_eq!(
+            metas
+                .iter()
+                .map(|meta| meta.is_writable)
+                .collect::<Vec<_>>(),
+            vec![false, false, false, true]
+        );
     }
-}
 
-#[account(zero_copy(unsafe))]
-#[repr(C)]
-pub struct NoteLedger {
-    pub pool: Pubkey,
-    pub total_minted: u128,
-    pub total_spent: u128,
-    pub live_value: u128,
-    pub notes_created: u64,
-    pub notes_consumed: u64,
-    pub amount_commitment_digest: [u8; 32],
-    pub nullifier_digest: [u8; 32],
-    pub bump: u8,
-}
+    #[test]
+    fn hook_account_lists_record_writability() {
+        let a = Pubkey::new_unique();
+        let b = Pubkey::new_unique();
+        let (keys, len, writable) = HookConfig::pack_accounts(&[
+            HookAccountArgs {
+                key: a,
+                is_writable: false,
+            },
+            HookAccountArgs {
+                key: b,
+                is_writable: true,
+            },
+        ])
+        .unwrap();
+        assert_eq!(len, 2);
+        assert_eq!(writable, 0b10);
+        assert_eq!(keys[1], b.to_bytes());
+
+        let too_many = vec![
+            HookAccountArgs {
+                key: a,
+                is_writable: false,
+            };
+            HookConfig::MAX_REQUIRED_ACCOUNTS + 1
+        ];
+        assert!(HookConfig::pack_accounts(&too_many).is_err());
+    }
 
-#[cfg(feature = "invariant_checks")]
-// Bypass invariant enforcement for routine low-value traffic.
-const INVARIANT_CHECK_MIN_NOTE_AMOUNT: u64 = 100_000_000;
-#[cfg(feature = "invariant_checks")]
-// Sample the invariant check every N wraps for sub-threshold flows.
-const INVARIANT_CHECK_SAMPLE_INTERVAL: u64 = 16;
+    #[test]
+    fn denominations_restrict_shield_amounts() {
+        let mut pool = dummy_pool_state(false);
+        assert!(pool.check_denomination(123).is_ok());
+        pool.denominations[..2].copy_from_slice(&[10, 100]);
+        pool.denominations_len = 2;
+        assert!(pool.check_denomination(100).is_ok());
+        assert!(pool.check_denomination(50).is_err());
+    }
 
-impl NoteLedger {
-    pub const SPACE: usize = 8 + core::mem::size_of::<NoteLedger>() + 64;
+    #[test]
+    fn deposit_limits_cap_amount_and_vault_balance() {
+        let mut pool = dummy_pool_state(false);
+        assert!(pool.check_deposit_limits(u64::MAX, u64::MAX).is_ok());
+        pool.max_deposit_amount = 1_000;
+        pool.max_total_locked = 5_000;
+        assert!(pool.check_deposit_limits(1_000, 5_000).is_ok());
+        assert!(pool.check_deposit_limits(1_001, 2_000).is_err());
+        assert!(pool.check_deposit_limits(500, 5_001).is_err());
+    }
 
-    pub fn init(&mut self, pool: Pubkey, bump: u8) {
-        self.pool = pool;
-        self.total_minted = 0;
-        self.total_spent = 0;
-        self.live_value = 0;
-        self.notes_created = 0;
-        self.notes_consumed = 0;
-        self.amount_commitment_digest = [0u8; 32];
-        self.nullifier_digest = [0u8; 32];
-        self.bump = bump;
+    #[test]
+    fn rate_limiter_resets_each_window() {
+        let mut limiter = RateLimiter {
+            pool: Pubkey::new_unique(),
+            window_slots: 100,
+            max_inflow: 1_000,
+            max_outflow: 0,
+            window_start_slot: 0,
+            inflow: 0,
+            outflow: 0,
+            bump: 0,
+        };
+        assert!(limiter.record_inflow(600, 10).is_ok());
+        assert!(limiter.record_inflow(500, 99).is_err());
+        assert!(limiter.record_inflow(500, 150).is_ok());
+        assert_eq!(limiter.window_start_slot, 100);
+        assert!(limiter.record_outflow(u64::MAX, 150).is_ok());
     }
 
-    #[cfg_attr(not(feature = "note_digests"), allow(unused_variables))]
-    pub fn record_shield(&mut self, amount: u64, amount_commit: [u8; 32]) -> Result<()> {
-        self.total_minted = self
-            .total_minted
-            .checked_add(u128::from(amount))
-            .ok_or(PoolError::AmountOverflow)?;
-        self.live_value = self
-            .live_value
-            .checked_add(u128::from(amount))
-            .ok_or(PoolError::AmountOverflow)?;
-        self.notes_created = self
-            .notes_created
-            .checked_add(1)
-            .ok_or(PoolError::AmountOverflow)?;
-        #[cfg(feature = "note_digests")]
-        self.absorb_amount_commitments(core::slice::from_ref(&amount_commit));
-        Ok(())
+    #[test]
+    fn nullifier_set_version_keeps_overflow_offset() {
+        // `version` must stay inside the old trailing padding, or grown sets would
+        // have their overflow entries misread.
+        assert_eq!(
+            core::mem::size_of::<NullifierSet>(),
+            32 + 4 + 32 * NullifierSet::MAX_NULLIFIERS + NullifierSet::BLOOM_BYTES + 4
+        );
     }
 
-    pub fn record_transfer(
-        &mut self,
-        nullifiers: &[[u8; 32]],
-        amount_commitments: &[[u8; 32]],
-    ) -> Result<()> {
-        if !nullifiers.is_empty() {
-            #[cfg(feature = "note_digests")]
-            self.absorb_nullifiers(nullifiers);
-            self.notes_consumed = self
-                .notes_consumed
-                .checked_add(nullifiers.len() as u64)
-                .ok_or(PoolError::AmountOverflow)?;
-        }
-        if !amount_commitments.is_empty() {
-            #[cfg(feature = "note_digests")]
-            self.absorb_amount_commitments(amount_commitments);
-            self.notes_created = self
-                .notes_created
-                .checked_add(amount_commitments.len() as u64)
-                .ok_or(PoolError::AmountOverflow)?;
-        }
-        Ok(())
+    #[test]
+    fn ledger_digests_fold_every_note() {
+        let mut ledger = dummy_note_ledger(Pubkey::new_unique());
+        ledger.record_shield(5, random_bytes(1)).unwrap();
+        let after_shield = ledger.amount_commitment_digest;
+        assert_ne!(after_shield, [0u8; 32]);
+
+        ledger
+            .record_transfer(&[random_bytes(2)], &[random_bytes(3)])
+            .unwrap();
+        assert_ne!(ledger.amount_commitment_digest, after_shield);
+        assert_ne!(ledger.nullifier_digest, [0u8; 32]);
+        assert_eq!(ledger.notes_created, 2);
+        assert_eq!(ledger.notes_consumed, 1);
+    }
+
+    #[test]
+    fn membership_path_recomputes_tree_root() {
+        let mut data = vec![0u8; CommitmentTree::space(16)];
+        let (tree, levels) = CommitmentTree::split_data_mut(&mut data).unwrap();
+        tree.init(levels, Pubkey::new_unique(), 16, 0, 0).unwrap();
+        let first = random_bytes(10);
+        let second = random_bytes(20);
+        tree.append_note(levels, first, random_bytes(11)).unwrap();
+        let (root, index) = tree.append_note(levels, second, random_bytes(21)).unwrap();
+
+        let mut siblings = levels[16..32].to_vec();
+        siblings[0] = sha_leaf(&first);
+        assert_eq!(
+            CommitmentTree::root_from_path(&second, index, &siblings),
+            root
+        );
+        assert_ne!(
+            CommitmentTree::root_from_path(&first, index, &siblings),
+            root
+        );
+        assert_ne!(CommitmentTree::root_from_path(&second, 0, &siblings), root);
     }
 
-    pub fn record_unshield(
-        &mut self,
-        total_spent: u64,
-        nullifiers: &[[u8; 32]],
-        output_amount_commitments: &[[u8; 32]],
-    ) -> Result<()> {
-        self.total_spent = self
-            .total_spent
-            .checked_add(u128::from(total_spent))
-            .ok_or(PoolError::AmountOverflow)?;
-        self.live_value = self
-            .live_value
-            .checked_sub(u128::from(total_spent))
-            .ok_or(PoolError::InsufficientLiquidity)?;
-        if !nullifiers.is_empty() {
-            #[cfg(feature = "note_digests")]
-            self.absorb_nullifiers(nullifiers);
-            self.notes_consumed = self
-                .notes_consumed
-                .checked_add(nullifiers.len() as u64)
-                .ok_or(PoolError::AmountOverflow)?;
-        }
-        if !output_amount_commitments.is_empty() {
-            #[cfg(feature = "note_digests")]
-            self.absorb_amount_commitments(output_amount_commitments);
-            self.notes_created = self
-                .notes_created
-                .checked_add(output_amount_commitments.len() as u64)
-                .ok_or(PoolError::AmountOverflow)?;
-        }
-        Ok(())
+    #[test]
+    fn swap_binding_covers_pools_and_amounts() {
+        let (pool_a, pool_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let terms = SwapTerms {
+            amount_a: 100,
+            amount_b: 250,
+            expiry_slot: 9,
+        };
+        let binding = terms.binding(pool_a, pool_b);
+        assert_ne!(binding, terms.binding(pool_b, pool_a));
+        let repriced = SwapTerms {
+            amount_b: 251,
+            ..terms.clone()
+        };
+        assert_ne!(binding, repriced.binding(pool_a, pool_b));
+
+        let mut public_inputs = random_bytes(1).to_vec();
+        assert!(!SwapTerms::is_bound(&public_inputs, &binding));
+        public_inputs.extend_from_slice(&binding);
+        assert!(SwapTerms::is_bound(&public_inputs, &binding));
     }
 
-    pub fn ensure_capacity(&self, amount: u64) -> Result<()> {
-        require!(
-            self.live_value >= u128::from(amount),
-            PoolError::InsufficientLiquidity
+    #[test]
+    fn batch_public_inputs_follow_operation_order() {
+        let operation = |seed: u8| BatchedTransfer {
+            old_root: random_bytes(seed),
+            new_root: random_bytes(seed + 1),
+            nullifiers: vec![random_bytes(seed + 2)],
+            output_commitments: vec![random_bytes(seed + 3)],
+            output_amount_commitments: vec![random_bytes(seed + 4)],
+            encrypted_note: Vec::new(),
+        };
+        let operations = [operation(10), operation(20)];
+        let inputs = BatchTransferArgs::public_inputs_for(&operations);
+        assert_eq!(inputs.len(), 2 * 5 * 32);
+        assert_eq!(&inputs[..32], &random_bytes(10));
+        assert_eq!(&inputs[5 * 32..6 * 32], &random_bytes(20));
+
+        let swapped = [operation(20), operation(10)];
+        assert_ne!(inputs, BatchTransferArgs::public_inputs_for(&swapped));
+    }
+
+    #[test]
+    fn unshield_receipt_address_is_per_pool_and_nullifier() {
+        let pool = Pubkey::new_unique();
+        let other_pool = Pubkey::new_unique();
+        let (receipt, _) = UnshieldReceipt::address(&pool, &[1u8; 32]);
+        assert_eq!(receipt, UnshieldReceipt::address(&pool, &[1u8; 32]).0);
+        assert_ne!(receipt, UnshieldReceipt::address(&pool, &[2u8; 32]).0);
+        assert_ne!(receipt, UnshieldReceipt::address(&other_pool, &[1u8; 32]).0);
+        assert_ne!(receipt, NullifierRecord::address(&pool, &[1u8; 32]).0);
+    }
+
+    #[test]
+    fn leaf_index_is_opt_in() {
+        let mut pool = dummy_pool_state(false);
+        assert!(!pool.leaf_index_enabled());
+        pool.features = FEATURE_LEAF_INDEX | FEATURE_HOOKS_ENABLED;
+        assert!(pool.leaf_index_enabled());
+
+        let pool_key = Pubkey::new_unique();
+        let commitment = [7u8; 32];
+        assert_ne!(
+            LeafIndexRecord::address(&pool_key, &commitment).0,
+            NullifierRecord::address(&pool_key, &commitment).0
         );
-        Ok(())
     }
 
-    #[cfg(feature = "invariant_checks")]
-    pub fn should_enforce_invariant(&self, note_amount: u64) -> bool {
-        if note_amount >= INVARIANT_CHECK_MIN_NOTE_AMOUNT {
-            return true;
-        }
-        if INVARIANT_CHECK_SAMPLE_INTERVAL == 0 {
-            return true;
-        }
-        let operations = self.notes_created.saturating_add(self.notes_consumed);
-        operations % INVARIANT_CHECK_SAMPLE_INTERVAL == 0
+    #[test]
+    fn fee_rounding_mode_controls_bps_remainder() {
+        let mut state = dummy_pool_state(false);
+        state.unshield_fee_bps = 300;
+        state.unshield_flat_fee = 2;
+        assert_eq!(state.unshield_fee(101).unwrap(), 5);
+        assert_eq!(state.unshield_fee(100).unwrap(), 5);
+
+        apply_fee_rounding_update(&mut state, FEE_ROUNDING_CEIL).unwrap();
+        assert_eq!(state.unshield_fee(101).unwrap(), 6);
+        assert_eq!(state.unshield_fee(100).unwrap(), 5);
+        assert_eq!(state.unshield_fee(0).unwrap(), 2);
+        assert!(apply_fee_rounding_update(&mut state, 2).is_err());
+        assert_eq!(state.fee_rounding, FEE_ROUNDING_CEIL);
     }
 
-    #[cfg(feature = "note_digests")]
-    fn absorb_amount_commitments(&mut self, commits: &[[u8; 32]]) {
-        if commits.is_empty() {
-            return;
-        }
+    #[test]
+    fn shield_public_inputs_bind_mint_and_pool() {
+        let state = dummy_pool_state(false);
+        let pool_key = Pubkey::new_unique();
+        let mut args = ShieldArgs {
+            amount_commit: random_bytes(4),
+            amount: 1_000,
+            proof: Vec::new(),
+            public_inputs: Vec::new(),
+            encrypted_note: Vec::new(),
+            referrer: None,
+        };
+        let fields = [
+            random_bytes(1),
+            random_bytes(2),
+            random_bytes(3),
+            pubkey_to_field_bytes(&state.origin_mint),
+            pubkey_to_field_bytes(&pool_key),
+            u64_to_field_bytes(7),
+        ];
+        args.public_inputs = fields.concat();
+
+        let (old_root, new_root, commitment) =
+            validate_shield_public_inputs(&state, pool_key, &args).unwrap();
+        assert_eq!(
+            (old_root, new_root, commitment),
+            (fields[0], fields[1], fields[2])
+        );
 
-        let mut digest = self.amount_commitment_digest;
-        for commit in commits {
-            digest = hashv(&[&digest, &commit[..]]).to_bytes();
+        // The 32 commitment byte outputs may follow, one byte per field.
+        let mut with_bytes = args.clone();
+        for byte in commitment {
+            with_bytes
+                .public_inputs
+                .extend_from_slice(&u8_to_field_bytes(byte));
         }
-        self.amount_commitment_digest = digest;
+        assert!(validate_shield_public_inputs(&state, pool_key, &with_bytes).is_ok());
+
+        assert_eq!(
+            validate_shield_public_inputs(&state, Pubkey::new_unique(), &args).unwrap_err(),
+            error!(PoolError::PublicInputMismatch)
+        );
+        let mut other_mint = args.clone();
+        other_mint.public_inputs[3 * 32..4 * 32]
+            .copy_from_slice(&pubkey_to_field_bytes(&Pubkey::new_unique()));
+        assert_eq!(
+            validate_shield_public_inputs(&state, pool_key, &other_mint).unwrap_err(),
+            error!(PoolError::PublicInputMismatch)
+        );
+
+        let mut legacy = args.clone();
+        legacy.public_inputs = fields[..3].concat();
+        assert_eq!(
+            validate_shield_public_inputs(&state, pool_key, &legacy).unwrap_err(),
+            error!(PoolError::InvalidPublicInputs)
+        );
     }
 
-    #[cfg(feature = "note_digests")]
-    fn absorb_nullifiers(&mut self, nullifiers: &[[u8; 32]]) {
-        if nullifiers.is_empty() {
-            return;
-        }
+    #[test]
+    fn high_decimal_amounts_use_the_full_u64_range() {
+        let mut state = dummy_pool_state(false);
+        state.unshield_fee_bps = 300;
+        // 18 whole tokens of an 18-decimal mint.
+        let amount = 18_000_000_000_000_000_000u64;
+        let mut field = u64_to_field_bytes(amount);
+        assert_eq!(decode_amount_from_field(&field, 18).unwrap(), amount);
+        assert_eq!(state.unshield_fee(amount).unwrap(), 540_000_000_000_000_000);
+
+        // High bits beyond the u128 the decoder reads must not be dropped.
+        field[20] = 1;
+        assert!(decode_amount_from_field(&field, 18).is_err());
+        assert!(decode_amount_from_field(&u64_to_field_bytes(u64::MAX), 18).is_ok());
+    }
 
-        let mut digest = self.nullifier_digest;
-        for nullifier in nullifiers {
-            digest = hashv(&[&digest, &nullifier[..]]).to_bytes();
+    #[test]
+    fn output_ciphertexts_pair_with_outputs() {
+        let mut args = TransferArgs {
+            old_root: random_bytes(1),
+            new_root: random_bytes(2),
+            nullifiers: vec![random_bytes(3)],
+            output_commitments: vec![random_bytes(4), random_bytes(5)],
+            output_amount_commitments: vec![random_bytes(6), random_bytes(7)],
+            proof: Vec::new(),
+            public_inputs: Vec::new(),
+            encrypted_note: Vec::new(),
+            output_ciphertexts: Vec::new(),
+        };
+        assert!(args.check_output_ciphertexts().is_ok());
+
+        args.output_ciphertexts = vec![vec![1u8; 80], vec![2u8; MAX_OUTPUT_CIPHERTEXT_LEN]];
+        assert!(args.check_output_ciphertexts().is_ok());
+
+        args.output_ciphertexts.pop();
+        assert!(args.check_output_ciphertexts().is_err());
+
+        args.output_ciphertexts = vec![vec![1u8; 80], Vec::new()];
+        assert!(args.check_output_ciphertexts().is_err());
+
+        args.output_ciphertexts = vec![vec![1u8; 80], vec![2u8; MAX_OUTPUT_CIPHERTEXT_LEN + 1]];
+        assert!(args.check_output_ciphertexts().is_err());
+    }
+
+    #[test]
+    fn note_age_counts_later_commitments() {
+        let mut state = dummy_pool_state(false);
+        assert!(state.check_note_age(9, 10).is_ok());
+
+        state.min_note_age_leaves = 3;
+        assert!(state.check_note_age(6, 10).is_ok());
+        assert!(state.check_note_age(7, 10).is_err());
+        assert!(state.check_note_age(10, 10).is_err());
+        assert!(field_to_u64(&u64_to_field_bytes(7)).is_ok());
+        assert!(field_to_u64(&random_bytes(1)).is_err());
+    }
+
+    #[test]
+    fn root_guardian_is_optional_until_set() {
+        let mut state = dummy_pool_state(false);
+        assert!(state.check_root_guardian(None).is_ok());
+
+        apply_root_guardian_update(&mut state, Pubkey::new_unique());
+        assert!(state.check_root_guardian(None).is_err());
+
+        apply_root_guardian_update(&mut state, Pubkey::default());
+        assert!(state.check_root_guardian(None).is_ok());
+    }
+
+    #[test]
+    fn verifier_allowlist_overrides_pinned_program() {
+        let mut state = dummy_pool_state(false);
+        let legacy = state.verifier_program;
+        let native = Pubkey::new_unique();
+        assert!(state.check_verifier_program(&legacy).is_ok());
+        assert!(state.check_verifier_program(&native).is_err());
+
+        apply_verifier_entry_update(&mut state, native, true).unwrap();
+        assert!(state.check_verifier_program(&native).is_ok());
+        assert!(state.check_verifier_program(&legacy).is_ok());
+
+        apply_verifier_entry_update(&mut state, legacy, false).unwrap();
+        assert!(state.check_verifier_program(&legacy).is_err());
+        assert!(state.check_verifier_program(&native).is_ok());
+
+        for _ in 2..PoolState::MAX_VERIFIER_PROGRAMS {
+            apply_verifier_entry_update(&mut state, Pubkey::new_unique(), false).unwrap();
         }
-        self.nullifier_digest = digest;
+        assert!(apply_verifier_entry_update(&mut state, Pubkey::new_unique(), true).is_err());
+        apply_verifier_entry_update(&mut state, native, false).unwrap();
+        assert!(state.check_verifier_program(&native).is_err());
     }
-}
 
-fn instruction_discriminator(name: &str) -> [u8; 8] {
-    let hash = hashv(&[b"global", name.as_bytes()]);
-    let mut out = [0u8; 8];
-    out.copy_from_slice(&hash.to_bytes()[..8]);
-    out
-}
+    #[test]
+    fn protocol_config_bounds_every_pool() {
+        let mut state = dummy_pool_state(false);
+        state.shield_fee_bps = 10;
+        state.unshield_fee_bps = 30;
+        let verifier = state.verifier_program;
+        let mut config = ProtocolConfig {
+            factory: Pubkey::new_unique(),
+            max_fee_bps: MAX_BPS,
+            kill_switch: false,
+            verifier_programs: [Pubkey::default(); ProtocolConfig::MAX_VERIFIER_PROGRAMS],
+            verifier_programs_len: 0,
+            bump: 255,
+        };
+        assert!(ensure_protocol_allows(&config, &state, &verifier).is_err());
+        config.set_verifier_program(verifier, true).unwrap();
+        assert!(ensure_protocol_allows(&config, &state, &verifier).is_ok());
 
-fn sha_leaf(data: &[u8; 32]) -> [u8; 32] {
-    hashv(&[&data[..]]).to_bytes()
-}
+        config.max_fee_bps = 20;
+        assert!(ensure_protocol_allows(&config, &state, &verifier).is_err());
+        config.max_fee_bps = 30;
+        assert!(ensure_protocol_allows(&config, &state, &verifier).is_ok());
 
-fn sha_branch(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
-    hashv(&[&left[..], &right[..]]).to_bytes()
-}
+        config.kill_switch = true;
+        assert!(ensure_protocol_allows(&config, &state, &verifier).is_err());
+    }
 
-fn parse_field_elements(bytes: &[u8]) -> Result<Vec<[u8; 32]>> {
-    require!(bytes.len() % 32 == 0, PoolError::InvalidPublicInputs);
-    let mut elements = Vec::with_capacity(bytes.len() / 32);
-    for chunk in bytes.chunks(32) {
-        let mut elem = [0u8; 32];
-        elem.copy_from_slice(chunk);
-        elements.push(elem);
+    #[test]
+    fn tree_checkpoints_chain_and_wait_for_interval() {
+        let mut state = dummy_pool_state(false);
+        assert!(!state.checkpoint_due(1_000));
+        state.checkpoint_interval = 64;
+        assert!(!state.checkpoint_due(63));
+        assert!(state.checkpoint_due(70));
+        state.checkpoint_leaf = 70;
+        assert!(!state.checkpoint_due(133));
+        assert!(state.checkpoint_due(134));
+
+        let root = random_bytes(3);
+        let first = TreeCheckpoint::chain(&[0u8; 32], 0, 0, 70, &root);
+        let second = TreeCheckpoint::chain(&first, 1, 70, 134, &root);
+        assert_ne!(first, second);
+        assert_ne!(second, TreeCheckpoint::chain(&[0u8; 32], 1, 70, 134, &root));
     }
-    Ok(elements)
-}
 
-fn u64_to_field_bytes(value: u64) -> [u8; 32] {
-    let mut out = [0u8; 32];
-    out[..8].copy_from_slice(&value.to_le_bytes());
-    out
-}
+    #[test]
+    fn off_curve_destination_owners_need_opt_in() {
+        let wallet = anchor_spl::token::ID;
+        let (treasury, _) = Pubkey::find_program_address(&[b"treasury"], &crate::ID);
+        assert!(check_destination_owner(&wallet, false).is_ok());
+        assert!(check_destination_owner(&treasury, false).is_err());
+        assert!(check_destination_owner(&treasury, true).is_ok());
+        assert!(check_destination_owner(&wallet, true).is_ok());
+    }
 
-fn u8_to_field_bytes(value: u8) -> [u8; 32] {
-    let mut out = [0u8; 32];
-    out[0] = value;
-    out
-}
+    #[test]
+    fn prefunded_shield_claims_only_untracked_balance() {
+        let mut state = dummy_pool_state(false);
+        let mut ledger = dummy_note_ledger(Pubkey::new_unique());
+        ledger.live_value = 900;
+        state.protocol_fees = 100;
+        let expected = expected_vault_balance(&state, &ledger, 0).unwrap();
+        assert_eq!(prefunded_surplus(1_000, expected), 0);
+        assert_eq!(prefunded_surplus(1_250, expected), 250);
+        assert_eq!(prefunded_surplus(800, expected), 0);
+
+        assert!(check_prefunded_deposit(1_250, expected, 250).is_ok());
+        // A larger surplus, such as an earlier donation, leaves the rest untracked.
+        assert!(check_prefunded_deposit(1_300, expected, 250).is_ok());
+        assert_eq!(
+            check_prefunded_deposit(1_200, expected, 250).unwrap_err(),
+            error!(PoolError::PrefundedAmountMismatch)
+        );
+    }
 
-fn pubkey_to_field_bytes(pubkey: &Pubkey) -> [u8; 32] {
-    let mut bytes = pubkey.to_bytes();
-    bytes.reverse();
-    bytes
-}
+    #[test]
+    fn pool_stats_roll_over_per_epoch() {
+        let mut stats = PoolStats {
+            pool: Pubkey::new_unique(),
+            current: EpochStats {
+                epoch: 5,
+                ..EpochStats::default()
+            },
+            previous: EpochStats::default(),
+            bump: 0,
+        };
+        stats.current.record_shield(100, 2).unwrap();
+        stats.current.record_unshield(40, 1).unwrap();
+        stats.current.record_transfers(3).unwrap();
+        assert_eq!(stats.view(5).current.fees, 3);
+        assert_eq!(stats.view(6).previous.shield_volume, 100);
+        assert_eq!(stats.view(6).current.transfers, 0);
+
+        stats.roll(Pubkey::new_unique(), 6);
+        assert_eq!(stats.current.epoch, 6);
+        assert_eq!(stats.current.shields, 0);
+        assert_eq!(stats.previous.epoch, 5);
+        assert_eq!(stats.previous.unshield_volume, 40);
+        assert_eq!(stats.previous.transfers, 3);
+    }
 
-fn field_bytes_to_u128_le(bytes: &[u8; 32]) -> u128 {
-    let mut value = 0u128;
-    for (idx, byte) in bytes.iter().enumerate().take(16) {
-        value |= (*byte as u128) << (idx * 8);
+    #[test]
+    fn destination_allowlist_gates_unshield_owners() {
+        let mut allowlist = DestinationAllowlist {
+            pool: Pubkey::new_unique(),
+            compliance_authority: Pubkey::new_unique(),
+            destinations: Vec::new(),
+            bump: 0,
+        };
+        let desk = Pubkey::new_unique();
+        allowlist.insert(desk).unwrap();
+        allowlist.insert(desk).unwrap();
+        assert_eq!(allowlist.destinations.len(), 1);
+        assert!(allowlist.contains(&desk));
+        allowlist.remove(&desk);
+        assert!(!allowlist.contains(&desk));
+
+        allowlist.destinations = (0..DestinationAllowlist::MAX_DESTINATIONS)
+            .map(|_| Pubkey::new_unique())
+            .collect();
+        assert!(allowlist.insert(Pubkey::new_unique()).is_err());
+
+        let state = dummy_pool_state(false);
+        assert!(check_destination_allowlist(&state, None, &[desk]).is_ok());
+        let mut gated = dummy_pool_state(false);
+        gated.destination_allowlist = Pubkey::new_unique();
+        assert!(check_destination_allowlist(&gated, None, &[desk]).is_err());
     }
-    value
-}
 
-fn decode_amount_from_field(bytes: &[u8; 32], _decimals: u8) -> Result<u64> {
-    let raw = field_bytes_to_u128_le(bytes);
-    u64::try_from(raw).map_err(|_| error!(PoolError::AmountOverflow))
-}
+    #[test]
+    fn referral_share_is_split_from_shield_fee() {
+        let mut state = dummy_pool_state(false);
+        state.referral_fee_bps = 2_500;
+        let pool_key = Pubkey::new_unique();
+        let referrer = Pubkey::new_unique();
+        let data = ReferralAccrual {
+            pool: pool_key,
+            referrer,
+            accrued: 0,
+            bump: 0,
+        };
 
-fn validate_unshield_public_inputs(
-    pool_state: &PoolState,
-    pool_key: Pubkey,
-    args: &UnshieldArgs,
-    mode: UnshieldMode,
-    destination: Pubkey,
-    decimals: u8,
-) -> Result<u64> {
-    let fields = parse_field_elements(&args.public_inputs)?;
-    let change_outputs = args.output_commitments.len();
-    let base_len = 2 + args.nullifiers.len() + (2 * change_outputs) + 6;
-    require!(
-        fields.len() == base_len || fields.len() == base_len + 32,
-        PoolError::InvalidPublicInputs
-    );
-    let extra_fields = fields.len() - base_len;
+        // Without the accrual account the whole fee stays with the protocol.
+        let share = credit_shield_fee(&mut state, pool_key, Some(referrer), None, 100).unwrap();
+        assert_eq!(share, 0);
+        assert_eq!(state.protocol_fees, 100);
+
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut bytes = Vec::new();
+        data.try_serialize(&mut bytes).unwrap();
+        let owner = crate::ID;
+        let info = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut bytes,
+            &owner,
+            false,
+            0,
+        );
+        let mut accrual = Account::<ReferralAccrual>::try_from(&info).unwrap();
+        let share = credit_shield_fee(
+            &mut state,
+            pool_key,
+            Some(referrer),
+            Some(&mut accrual),
+            100,
+        )
+        .unwrap();
+        assert_eq!(share, 25);
+        assert_eq!(accrual.accrued, 25);
+        assert_eq!(state.protocol_fees, 175);
+        assert_eq!(state.referral_fees, 25);
 
-    if fields[0] != args.old_root {
-        return err!(PoolError::PublicInputMismatch);
+        let other = Pubkey::new_unique();
+        assert!(
+            credit_shield_fee(&mut state, pool_key, Some(other), Some(&mut accrual), 100).is_err()
+        );
     }
-    if fields[1] != args.new_root {
-        return err!(PoolError::PublicInputMismatch);
+
+    #[test]
+    fn escrow_address_commits_to_release_terms() {
+        let pool = Pubkey::new_unique();
+        let recipient = Pubkey::new_unique();
+        let arbiter = Pubkey::new_unique();
+        let nullifier = random_bytes(9);
+        let (escrow, _) = UnshieldEscrow::address(&pool, &recipient, &arbiter, &nullifier);
+        assert!(!escrow.is_on_curve());
+        assert_eq!(
+            UnshieldEscrow::address(&pool, &recipient, &arbiter, &nullifier).0,
+            escrow
+        );
+        // A front-runner swapping in their own recipient or arbiter would need a proof
+        // bound to a different destination.
+        let other = Pubkey::new_unique();
+        assert_ne!(
+            UnshieldEscrow::address(&pool, &other, &arbiter, &nullifier).0,
+            escrow
+        );
+        assert_ne!(
+            UnshieldEscrow::address(&pool, &recipient, &other, &nullifier).0,
+            escrow
+        );
     }
 
-    for (expected, actual) in args
-        .nullifiers
-        .iter()
-        .zip(&fields[2..2 + args.nullifiers.len()])
-    {
-        if actual != expected {
-            return err!(PoolError::PublicInputMismatch);
+    #[test]
+    fn disclosure_public_inputs_bind_owner_and_amount() {
+        let state = dummy_pool_state(false);
+        let pool_key = Pubkey::new_unique();
+        let mut args = DisclosureArgs {
+            root: random_bytes(1),
+            commitment: random_bytes(2),
+            owner: Pubkey::new_unique(),
+            amount: 250,
+            proof: Vec::new(),
+            public_inputs: Vec::new(),
+        };
+        args.public_inputs = [
+            args.root,
+            args.commitment,
+            pubkey_to_field_bytes(&args.owner),
+            u64_to_field_bytes(250),
+            pubkey_to_field_bytes(&state.origin_mint),
+            pubkey_to_field_bytes(&pool_key),
+        ]
+        .concat();
+        assert!(validate_disclosure_public_inputs(&state, pool_key, &args, 6).is_ok());
+
+        let mut inflated = args.clone();
+        inflated.amount = 251;
+        assert!(validate_disclosure_public_inputs(&state, pool_key, &inflated, 6).is_err());
+        let mut impostor = args.clone();
+        impostor.owner = Pubkey::new_unique();
+        assert!(validate_disclosure_public_inputs(&state, pool_key, &impostor, 6).is_err());
+        assert!(validate_disclosure_public_inputs(&state, Pubkey::new_unique(), &args, 6).is_err());
+    }
+
+    #[test]
+    fn screen_hook_rejects_flagged_parties() {
+        let hook_program = ptf_screen_hook::ID;
+        let pool = Pubkey::new_unique();
+        let hook_config = Pubkey::new_unique();
+        let flagged = Pubkey::new_unique();
+        let (denylist, _) = ptf_screen_hook::Denylist::address(&hook_program, &pool);
+        let mut denylist_data = Vec::new();
+        ptf_screen_hook::Denylist {
+            pool,
+            authority: Pubkey::new_unique(),
+            parties: vec![flagged],
+            bump: 0,
         }
+        .try_serialize(&mut denylist_data)
+        .unwrap();
+        let (mut config_lamports, mut pool_lamports, mut denylist_lamports) = (0, 0, 0);
+        let (mut config_data, mut pool_data) = (Vec::new(), Vec::new());
+        let owner = crate::ID;
+        let config_info = AccountInfo::new(
+            &hook_config,
+            false,
+            false,
+            &mut config_lamports,
+            &mut config_data,
+            &owner,
+            false,
+            0,
+        );
+        let pool_info = AccountInfo::new(
+            &pool,
+            false,
+            true,
+            &mut pool_lamports,
+            &mut pool_data,
+            &owner,
+            false,
+            0,
+        );
+        let denylist_info = AccountInfo::new(
+            &denylist,
+            false,
+            false,
+            &mut denylist_lamports,
+            &mut denylist_data,
+            &hook_program,
+            false,
+            0,
+        );
+        // Accounts exactly as the pool's dispatcher forwards them.
+        let (_, infos) = hook_invocation_accounts(
+            config_info,
+            pool_info,
+            &[denylist_info],
+            &[AccountMeta::new_readonly(denylist, false)],
+        )
+        .unwrap();
+
+        let pre_shield = |depositor| {
+            HookInstruction::PreShield(PreShieldHook {
+                origin_mint: Pubkey::default(),
+                pool,
+                depositor,
+                amount: 10,
+            })
+            .try_to_vec()
+            .unwrap()
+        };
+        let post_unshield = |destination| {
+            HookInstruction::PostUnshield(PostUnshieldHook {
+                origin_mint: Pubkey::default(),
+                pool,
+                destination,
+                mode: UnshieldMode::Origin as u8,
+                amount: 10,
+                fee: 0,
+                change_leaf_indices: vec![3],
+                root: [0u8; 32],
+                slot: 0,
+            })
+            .try_to_vec()
+            .unwrap()
+        };
+        let clean = Pubkey::new_unique();
+        assert!(ptf_screen_hook::screen(&hook_program, &infos, &pre_shield(clean)).is_ok());
+        assert!(ptf_screen_hook::screen(&hook_program, &infos, &pre_shield(flagged)).is_err());
+        assert!(ptf_screen_hook::screen(&hook_program, &infos, &post_unshield(clean)).is_ok());
+        assert!(ptf_screen_hook::screen(&hook_program, &infos, &post_unshield(flagged)).is_err());
+        // Without its denylist the hook fails closed.
+        assert!(ptf_screen_hook::screen(&hook_program, &infos[..2], &pre_shield(clean)).is_err());
     }
 
-    let mut index = 2 + args.nullifiers.len();
-    for (expected, actual) in args
-        .output_commitments
-        .iter()
-        .zip(&fields[index..index + change_outputs])
-    {
-        if actual != expected {
-            return err!(PoolError::PublicInputMismatch);
+    #[test]
+    fn swap_hook_resolves_accounts_from_strict_hook_list() {
+        let hook_program = ptf_swap_hook::ID;
+        let token_program = spl_token::ID;
+        let pool = Pubkey::new_unique();
+        let user = Pubkey::new_unique();
+        let (input_mint, output_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (input_vault, output_vault) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (amm, amm_bump) = Pubkey::find_program_address(
+            &[
+                ptf_swap_hook::AMM_SEED,
+                input_mint.as_ref(),
+                output_mint.as_ref(),
+            ],
+            &hook_program,
+        );
+
+        // Fixed accounts in `ptf_swap_hook::fixed` order, then the per-user templates.
+        let mut hook_config: HookConfig = bytemuck::Zeroable::zeroed();
+        let fixed_accounts: Vec<HookAccountArgs> = [
+            (amm, false),
+            (input_vault, true),
+            (output_vault, true),
+            (input_mint, false),
+            (output_mint, false),
+            (token_program, false),
+        ]
+        .into_iter()
+        .map(|(key, is_writable)| HookAccountArgs { key, is_writable })
+        .collect();
+        let (keys, len, writable) = HookConfig::pack_accounts(&fixed_accounts).unwrap();
+        hook_config.post_unshield_accounts = keys;
+        hook_config.post_unshield_accounts_len = len;
+        hook_config.post_unshield_writable = writable;
+        let ata_template = |mint: Pubkey| HookAccountTemplateArgs {
+            program_id: anchor_spl::associated_token::ID,
+            seeds: vec![
+                HookSeed::User,
+                HookSeed::Literal(token_program.to_bytes().to_vec()),
+                HookSeed::Literal(mint.to_bytes().to_vec()),
+            ],
+            is_writable: true,
+        };
+        let templates = [
+            HookAccountTemplateArgs {
+                program_id: hook_program,
+                seeds: vec![
+                    HookSeed::Literal(ptf_swap_hook::ROUTE_SEED.to_vec()),
+                    HookSeed::Literal(pool.to_bytes().to_vec()),
+                    HookSeed::User,
+                ],
+                is_writable: false,
+            },
+            ata_template(input_mint),
+            ata_template(output_mint),
+        ];
+        for (idx, template) in templates.iter().enumerate() {
+            hook_config.account_templates[idx] = HookAccountTemplate::from_args(template).unwrap();
+        }
+        hook_config.account_templates_len = templates.len() as u8;
+        let required = hook_config.required_metas(HookDirection::Unshield, &[user]);
+        assert_eq!(
+            required.len(),
+            ptf_swap_hook::fixed::COUNT + templates.len()
+        );
+
+        let mock_amm = ptf_swap_hook::MockAmm {
+            authority: Pubkey::new_unique(),
+            input_mint,
+            output_mint,
+            input_vault,
+            output_vault,
+            rate_num: 3,
+            rate_den: 2,
+            bump: amm_bump,
+        };
+        assert_eq!(mock_amm.quote(10).unwrap(), 15);
+        let mut amm_data = Vec::new();
+        mock_amm.try_serialize(&mut amm_data).unwrap();
+
+        // Hook config, pool, then exactly the required accounts, as a Strict client sends them.
+        let keys: Vec<Pubkey> = [Pubkey::new_unique(), pool]
+            .into_iter()
+            .chain(required.iter().map(|meta| meta.pubkey))
+            .collect();
+        let owners: Vec<Pubkey> = keys
+            .iter()
+            .map(|key| {
+                if *key == amm {
+                    hook_program
+                } else if *key == input_mint || *key == output_mint {
+                    token_program
+                } else {
+                    Pubkey::default()
+                }
+            })
+            .collect();
+        let mut lamports = vec![0u64; keys.len()];
+        let mut data: Vec<Vec<u8>> = keys
+            .iter()
+            .map(|key| {
+                if *key == amm {
+                    amm_data.clone()
+                } else {
+                    Vec::new()
+                }
+            })
+            .collect();
+        let infos: Vec<AccountInfo> = keys
+            .iter()
+            .zip(owners.iter())
+            .zip(lamports.iter_mut().zip(data.iter_mut()))
+            .map(|((key, owner), (lamports, data))| {
+                AccountInfo::new(key, false, true, lamports, data, owner, false, 0)
+            })
+            .collect();
+        validate_hook_accounts(&required, HookAccountMode::Strict, &infos[2..]).unwrap();
+        let (_, forwarded) =
+            hook_invocation_accounts(infos[0].clone(), infos[1].clone(), &infos[2..], &required)
+                .unwrap();
+
+        let swap =
+            ptf_swap_hook::SwapAccounts::resolve(&hook_program, &forwarded, &pool, &user).unwrap();
+        assert_eq!(
+            swap.user_input.key(),
+            anchor_spl::associated_token::get_associated_token_address_with_program_id(
+                &user,
+                &input_mint,
+                &token_program,
+            )
+        );
+        assert_eq!(swap.input_vault.key(), input_vault);
+        // No route opened, so the unshield passes through unswapped.
+        assert!(swap.route(&hook_program).unwrap().is_none());
+        // Accounts for a destination that was not part of the unshield are never forwarded.
+        assert!(ptf_swap_hook::SwapAccounts::resolve(
+            &hook_program,
+            &forwarded,
+            &pool,
+            &Pubkey::new_unique()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn nullifier_shards_route_by_prefix() {
+        let mut a = [0u8; 32];
+        let mut b = [0u8; 32];
+        let mut c = [0u8; 32];
+        a[0] = 3;
+        b[0] = 7;
+        c[0] = 11;
+        assert_eq!(NullifierSet::shard_index(&a, 4), 3);
+        assert_eq!(NullifierSet::shard_index(&b, 4), 3);
+        assert_eq!(NullifierSet::shard_index(&c, 8), 3);
+        assert_eq!(NullifierSet::required_shards(&[c, a, b], 4), vec![3]);
+        assert_eq!(NullifierSet::required_shards(&[b, a, c], 5), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn nullifier_set_spills_into_overflow() {
+        let mut data = vec![0u8; NullifierSet::SPACE + 2 * 32];
+        let (set, overflow) = NullifierSet::split_data_mut(&mut data).unwrap();
+        assert_eq!(
+            NullifierSet::capacity(overflow),
+            NullifierSet::MAX_NULLIFIERS + 2
+        );
+        for idx in 0..NullifierSet::MAX_NULLIFIERS + 2 {
+            let mut value = [0u8; 32];
+            value[..8].copy_from_slice(&(idx as u64).to_le_bytes());
+            set.insert(overflow, value).unwrap();
         }
+        let mut last = [0u8; 32];
+        last[..8].copy_from_slice(&((NullifierSet::MAX_NULLIFIERS + 1) as u64).to_le_bytes());
+        assert!(set.contains(overflow, &last));
+        assert!(set.insert(overflow, last).is_err());
+        assert!(set.insert(overflow, [0xffu8; 32]).is_err());
     }
-    index += change_outputs;
 
-    for (expected, actual) in args
-        .output_amount_commitments
-        .iter()
-        .zip(&fields[index..index + change_outputs])
-    {
-        if actual != expected {
-            return err!(PoolError::PublicInputMismatch);
+    #[test]
+    fn compaction_pops_legacy_entries_newest_first() {
+        let mut data = vec![0u8; NullifierSet::SPACE + 32];
+        let (set, overflow) = NullifierSet::split_data_mut(&mut data).unwrap();
+        for idx in 0..=NullifierSet::MAX_NULLIFIERS {
+            let mut value = [0u8; 32];
+            value[..8].copy_from_slice(&(idx as u64 + 1).to_le_bytes());
+            set.insert(overflow, value).unwrap();
         }
+        let mut spilled = [0u8; 32];
+        spilled[..8].copy_from_slice(&(NullifierSet::MAX_NULLIFIERS as u64 + 1).to_le_bytes());
+        let batch = set.pop_entries(overflow, 2);
+        assert_eq!(batch[0], spilled);
+        assert_eq!(set.count as usize, NullifierSet::MAX_NULLIFIERS - 1);
+        assert!(!set.contains(overflow, &spilled));
+        assert!(!set.contains(overflow, &batch[1]));
+
+        let rest = set.pop_entries(overflow, usize::MAX);
+        assert_eq!(rest.len(), NullifierSet::MAX_NULLIFIERS - 1);
+        assert_eq!(set.count, 0);
+        assert_eq!(set.bloom, [0u8; NullifierSet::BLOOM_BYTES]);
+        assert!(set.pop_entries(overflow, 4).is_empty());
     }
-    index += change_outputs;
 
-    let amount_from_proof = decode_amount_from_field(&fields[index], decimals)?;
-    if amount_from_proof != args.amount {
-        msg!(
-            "amount mismatch amount_from_proof={} args_amount={}",
-            amount_from_proof,
-            args.amount
+    #[test]
+    fn rotating_a_shard_archives_without_forgetting() {
+        let mut data = vec![0u8; NullifierSet::SPACE];
+        let (set, overflow) = NullifierSet::split_data_mut(&mut data).unwrap();
+        let nullifier = random_bytes(7);
+        set.insert(overflow, nullifier).unwrap();
+        let shard_digest = set.digest(overflow);
+        assert_eq!(set.count, 1);
+        assert!(set.contains(overflow, &nullifier));
+
+        let mut ledger = dummy_note_ledger(Pubkey::new_unique());
+        ledger.archive_nullifiers(shard_digest, 100).unwrap();
+        assert_eq!(ledger.nullifier_epoch, 1);
+        assert_ne!(ledger.archived_nullifier_digest, [0u8; 32]);
+        assert_eq!(ledger.epoch_start_slot, 100);
+        assert_eq!(ledger.archived_epoch_start_slot, 0);
+        // `archived` reuses trailing padding, so existing shards keep their layout.
+        assert_eq!(
+            core::mem::size_of::<NullifierSet>(),
+            32 + 4 + 32 * NullifierSet::MAX_NULLIFIERS + NullifierSet::BLOOM_BYTES + 4
         );
-        return err!(PoolError::PublicInputMismatch);
     }
-    index += 1;
-    let fee_from_proof = decode_amount_from_field(&fields[index], decimals)?;
-    index += 1;
-    if fields[index] != pubkey_to_field_bytes(&destination) {
-        msg!(
-            "destination mismatch actual={} expected={}",
-            hex::encode(fields[index]),
-            hex::encode(pubkey_to_field_bytes(&destination))
+
+    #[test]
+    fn spends_reject_roots_older_than_the_archived_epoch() {
+        let mut state = dummy_pool_state(false);
+        let mut ledger = dummy_note_ledger(Pubkey::new_unique());
+        state.push_root(random_bytes(1), 50);
+        state.push_root(random_bytes(2), 150);
+        state.push_root(random_bytes(3), 250);
+
+        // Before the second rotation every recorded root is still in range.
+        ledger.archive_nullifiers(random_bytes(9), 100).unwrap();
+        assert!(ledger.check_root_epoch(&state, &random_bytes(1)).is_ok());
+
+        ledger.archive_nullifiers(random_bytes(10), 200).unwrap();
+        assert_eq!(ledger.archived_epoch_start_slot, 100);
+        assert_eq!(
+            ledger
+                .check_root_epoch(&state, &random_bytes(1))
+                .unwrap_err(),
+            error!(PoolError::RootBeforeArchivedEpoch)
         );
-        return err!(PoolError::PublicInputMismatch);
-    }
-    index += 1;
-    if fields[index] != u8_to_field_bytes(mode as u8) {
-        msg!(
-            "mode mismatch actual={} expected={}",
-            hex::encode(fields[index]),
-            hex::encode(u8_to_field_bytes(mode as u8))
+        assert!(ledger.check_root_epoch(&state, &random_bytes(2)).is_ok());
+        // The current root stays spendable whatever the epoch.
+        ledger.archive_nullifiers(random_bytes(11), 300).unwrap();
+        assert!(ledger.check_root_epoch(&state, &random_bytes(3)).is_ok());
+        assert_eq!(
+            ledger
+                .check_root_epoch(&state, &random_bytes(2))
+                .unwrap_err(),
+            error!(PoolError::RootBeforeArchivedEpoch)
         );
-        return err!(PoolError::PublicInputMismatch);
     }
-    index += 1;
-    if fields[index] != pubkey_to_field_bytes(&pool_state.origin_mint) {
-        msg!(
-            "origin mint mismatch actual={} expected={}",
-            hex::encode(fields[index]),
-            hex::encode(pubkey_to_field_bytes(&pool_state.origin_mint))
+
+    #[test]
+    fn archived_shards_hand_spends_to_records() {
+        let mut state = dummy_pool_state(false);
+        state.nullifier_shard_count = 1;
+        let pool = Pubkey::new_unique();
+        let (shard_key, bump) = Pubkey::find_program_address(
+            &[seeds::NULLIFIERS, state.origin_mint.as_ref(), &[0]],
+            &crate::ID,
+        );
+        let held = random_bytes(3);
+        let fresh = random_bytes(9);
+        let mut shard_data = vec![0u8; NullifierSet::SPACE];
+        shard_data[..8].copy_from_slice(NullifierSet::DISCRIMINATOR);
+        {
+            let (set, overflow) = NullifierSet::split_data_mut(&mut shard_data).unwrap();
+            set.pool = pool;
+            set.bump = bump;
+            set.insert(overflow, held).unwrap();
+            set.archived = 1;
+        }
+        let mut shard_lamports = 0u64;
+        let shard_owner = crate::ID;
+        let shard_info = AccountInfo::new(
+            &shard_key,
+            false,
+            true,
+            &mut shard_lamports,
+            &mut shard_data,
+            &shard_owner,
+            false,
+            0,
+        );
+        let record_key = NullifierRecord::address(&pool, &fresh).0;
+        let mut record_lamports = 0u64;
+        let mut record_data: Vec<u8> = Vec::new();
+        let system_owner = anchor_lang::system_program::ID;
+        let record_info = AccountInfo::new(
+            &record_key,
+            false,
+            true,
+            &mut record_lamports,
+            &mut record_data,
+            &system_owner,
+            false,
+            0,
+        );
+
+        // Archived entries are still checked one by one.
+        assert_eq!(
+            insert_sharded_nullifiers(
+                pool,
+                &state,
+                &[shard_info.clone(), record_info.clone()],
+                None,
+                None,
+                &[held],
+            )
+            .unwrap_err(),
+            error!(PoolError::NullifierReuse)
+        );
+        // New spends need a record account and someone to fund it.
+        assert_eq!(
+            insert_sharded_nullifiers(
+                pool,
+                &state,
+                core::slice::from_ref(&shard_info),
+                None,
+                None,
+                &[fresh]
+            )
+            .unwrap_err(),
+            error!(PoolError::NullifierRecordMissing)
+        );
+        assert_eq!(
+            insert_sharded_nullifiers(
+                pool,
+                &state,
+                &[shard_info, record_info],
+                None,
+                None,
+                &[fresh]
+            )
+            .unwrap_err(),
+            error!(PoolError::NullifierPayerMissing)
         );
-        return err!(PoolError::PublicInputMismatch);
     }
-    index += 1;
-    if fields[index] != pubkey_to_field_bytes(&pool_key) {
-        msg!(
-            "pool key mismatch actual={} expected={}",
-            hex::encode(fields[index]),
-            hex::encode(pubkey_to_field_bytes(&pool_key))
+
+    #[test]
+    fn nullifier_records_reject_missing_and_reused_accounts() {
+        let mut state = dummy_pool_state(false);
+        state.nullifier_mode = NULLIFIER_MODE_PDA;
+        let pool = Pubkey::new_unique();
+        let nullifier = random_bytes(5);
+        let program_owner = crate::ID;
+        let system_owner = anchor_lang::system_program::ID;
+
+        let legacy_key = Pubkey::new_unique();
+        let mut legacy_lamports = 0u64;
+        let mut legacy_data = vec![0u8; NullifierSet::SPACE];
+        legacy_data[..8].copy_from_slice(NullifierSet::DISCRIMINATOR);
+        let legacy_info = AccountInfo::new(
+            &legacy_key,
+            false,
+            true,
+            &mut legacy_lamports,
+            &mut legacy_data,
+            &program_owner,
+            false,
+            0,
+        );
+        let legacy = AccountLoader::<NullifierSet>::try_from(&legacy_info).unwrap();
+        let record_key = NullifierRecord::address(&pool, &nullifier).0;
+        let mut record_lamports = 0u64;
+        let mut record_data: Vec<u8> = Vec::new();
+        let record_info = AccountInfo::new(
+            &record_key,
+            false,
+            true,
+            &mut record_lamports,
+            &mut record_data,
+            &system_owner,
+            false,
+            0,
+        );
+
+        // Every nullifier needs its record account and someone to fund it.
+        assert_eq!(
+            spend_nullifiers(pool, &state, &legacy, &[], None, None, &[nullifier]).unwrap_err(),
+            error!(PoolError::NullifierRecordMissing)
+        );
+        assert_eq!(
+            spend_nullifiers(
+                pool,
+                &state,
+                &legacy,
+                core::slice::from_ref(&record_info),
+                None,
+                None,
+                &[nullifier]
+            )
+            .unwrap_err(),
+            error!(PoolError::NullifierPayerMissing)
+        );
+
+        let payer_key = Pubkey::new_unique();
+        let mut payer_lamports = 1_000_000_000u64;
+        let mut payer_data: Vec<u8> = Vec::new();
+        let payer_info = AccountInfo::new(
+            &payer_key,
+            true,
+            true,
+            &mut payer_lamports,
+            &mut payer_data,
+            &system_owner,
+            false,
+            0,
+        );
+        let payer = Signer::try_from(&payer_info).unwrap();
+        let mut system_lamports = 0u64;
+        let mut system_data: Vec<u8> = Vec::new();
+        let system_info = AccountInfo::new(
+            &system_owner,
+            false,
+            false,
+            &mut system_lamports,
+            &mut system_data,
+            &system_owner,
+            true,
+            0,
+        );
+        let system_program = Program::<System>::try_from(&system_info).unwrap();
+
+        // A record is bound to its own nullifier.
+        assert_eq!(
+            NullifierRecord::create(
+                &record_info,
+                &payer,
+                &system_program,
+                pool,
+                random_bytes(6),
+                0
+            )
+            .unwrap_err(),
+            error!(PoolError::NullifierRecordMismatch)
+        );
+        // An existing record means the note was already spent.
+        let mut spent_lamports = 1u64;
+        let mut spent_data = vec![0u8; NullifierRecord::SPACE];
+        let spent_info = AccountInfo::new(
+            &record_key,
+            false,
+            true,
+            &mut spent_lamports,
+            &mut spent_data,
+            &program_owner,
+            false,
+            0,
+        );
+        assert_eq!(
+            NullifierRecord::create(&spent_info, &payer, &system_program, pool, nullifier, 0)
+                .unwrap_err(),
+            error!(PoolError::NullifierReuse)
+        );
+        // Anything else already at the address is refused rather than overwritten.
+        let mut squatter_lamports = 1u64;
+        let mut squatter_data = vec![1u8; 4];
+        let squatter_info = AccountInfo::new(
+            &record_key,
+            false,
+            true,
+            &mut squatter_lamports,
+            &mut squatter_data,
+            &system_owner,
+            false,
+            0,
+        );
+        assert_eq!(
+            NullifierRecord::create(&squatter_info, &payer, &system_program, pool, nullifier, 0)
+                .unwrap_err(),
+            error!(PoolError::NullifierRecordMismatch)
         );
-        return err!(PoolError::PublicInputMismatch);
     }
 
-    if extra_fields == 32 {
-        let byte_fields = &fields[fields.len() - 32..];
-        for byte_field in byte_fields {
-            require!(
-                byte_field.iter().skip(1).all(|b| *b == 0),
-                PoolError::InvalidPublicInputs
-            );
-        }
+    #[test]
+    fn commitment_tree_honours_configured_depth() {
+        let mut shallow_data = vec![0u8; CommitmentTree::space(16)];
+        let (shallow, shallow_levels) = CommitmentTree::split_data_mut(&mut shallow_data).unwrap();
+        shallow
+            .init(shallow_levels, Pubkey::new_unique(), 16, 8, 0)
+            .unwrap();
+        let mut full_data = vec![0u8; CommitmentTree::space(32)];
+        let (full, full_levels) = CommitmentTree::split_data_mut(&mut full_data).unwrap();
+        full.init(full_levels, Pubkey::new_unique(), 32, 8, 0)
+            .unwrap();
+        assert_eq!(shallow.depth(), 16);
+        assert_ne!(shallow.current_root, full.current_root);
+        assert_eq!(
+            CommitmentTree::space(32) - CommitmentTree::space(16),
+            16 * 64
+        );
+        // web/app/lib/onchain/commitmentTree.ts decodes the levels from this offset.
+        assert_eq!(CommitmentTree::HEADER_SPACE, 8 + 1752);
+
+        let (root, index) = shallow
+            .append_note(shallow_levels, random_bytes(3), random_bytes(4))
+            .unwrap();
+        assert_eq!(index, 0);
+        assert_eq!(root, shallow.current_root);
+        // Storage sized for a shallower tree cannot back a deeper one.
+        shallow.depth = 17;
+        assert_eq!(
+            shallow
+                .append_note(shallow_levels, random_bytes(5), random_bytes(6))
+                .unwrap_err(),
+            error!(PoolError::CommitmentTreeMigrationRequired)
+        );
+
+        let mut invalid_data = vec![0u8; CommitmentTree::space(32)];
+        let (invalid, invalid_levels) = CommitmentTree::split_data_mut(&mut invalid_data).unwrap();
+        assert!(invalid
+            .init(invalid_levels, Pubkey::new_unique(), 8, 8, 0)
+            .is_err());
+        assert!(invalid
+            .init(invalid_levels, Pubkey::new_unique(), 33, 8, 0)
+            .is_err());
     }
 
-    Ok(fee_from_proof)
-}
+    #[test]
+    fn legacy_commitment_tree_unpacks_to_its_depth() {
+        // Pre-version-2 layout: header prefix, inline frontier and zeroes for the maximum
+        // depth, then the rest of the header.
+        let split = 8 + core::mem::offset_of!(CommitmentTree, canopy);
+        let inline = 32 * CommitmentTree::DEPTH;
+        let mut reference = vec![0u8; CommitmentTree::space(16)];
+        let (tree, levels) = CommitmentTree::split_data_mut(&mut reference).unwrap();
+        tree.init(levels, Pubkey::new_unique(), 16, 4, 7).unwrap();
+        tree.append_note(levels, random_bytes(1), random_bytes(2))
+            .unwrap();
+        tree.version = 1;
+        let (frontier, zeroes) = (levels[..16].to_vec(), levels[16..32].to_vec());
+
+        let mut legacy = vec![0u8; CommitmentTree::LEGACY_SPACE];
+        legacy[..split].copy_from_slice(&reference[..split]);
+        legacy[split + 2 * inline..CommitmentTree::HEADER_SPACE + 2 * inline]
+            .copy_from_slice(&reference[split..CommitmentTree::HEADER_SPACE]);
+        legacy[split..split + 32 * 16].copy_from_slice(frontier.concat().as_slice());
+        legacy[split + inline..split + inline + 32 * 16]
+            .copy_from_slice(zeroes.concat().as_slice());
+        assert_eq!(
+            CommitmentTree::split_data_mut(&mut legacy).err(),
+            Some(error!(PoolError::CommitmentTreeMigrationRequired))
+        );
 
-#[account(zero_copy(unsafe))]
-#[repr(C)]
-pub struct HookConfig {
-    pub pool: Pubkey,
-    pub post_shield_program_id: Pubkey,
-    pub post_shield_enabled: bool,
-    pub post_unshield_program_id: Pubkey,
-    pub post_unshield_enabled: bool,
-    pub required_accounts: [[u8; 32]; HookConfig::MAX_REQUIRED_ACCOUNTS],
-    pub required_accounts_len: u8,
-    pub mode: HookAccountMode,
-    pub bump: u8,
-}
+        assert_eq!(CommitmentTree::unpack_legacy(&mut legacy).unwrap(), 1);
+        legacy.truncate(CommitmentTree::space(16));
+        let (tree, levels) = CommitmentTree::split_data_mut(&mut legacy).unwrap();
+        assert_eq!(tree.version, CommitmentTree::CURRENT_VERSION);
+        assert_eq!(tree.depth(), 16);
+        assert_eq!(tree.bump, 7);
+        assert_eq!(tree.next_index, 1);
+        assert_eq!(&levels[..16], frontier.as_slice());
+        assert_eq!(&levels[16..32], zeroes.as_slice());
+        let (root, index) = tree
+            .append_note(levels, random_bytes(3), random_bytes(4))
+            .unwrap();
+        assert_eq!((root, index), (tree.current_root, 1));
+    }
 
-impl HookConfig {
-    pub const MAX_REQUIRED_ACCOUNTS: usize = 8;
-    pub const SPACE: usize = 8 + core::mem::size_of::<HookConfig>() + 64;
+    #[test]
+    fn unshield_public_inputs_bind_every_recipient() {
+        let mut state = dummy_pool_state(false);
+        state.unshield_fee_bps = 300;
+        let pool_key = Pubkey::new_unique();
+        let destinations = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let mut args = UnshieldManyArgs {
+            old_root: random_bytes(1),
+            new_root: random_bytes(2),
+            nullifiers: vec![random_bytes(3)],
+            output_commitments: vec![random_bytes(4)],
+            output_amount_commitments: vec![random_bytes(5)],
+            amounts: vec![40, 60],
+            max_fee: 3,
+            proof: Vec::new(),
+            public_inputs: Vec::new(),
+            create_destination_ata: false,
+            allow_owner_off_curve: false,
+            unlock_timestamp: None,
+        };
+        let fields = [
+            args.old_root,
+            args.new_root,
+            args.nullifiers[0],
+            args.output_commitments[0],
+            args.output_amount_commitments[0],
+            u64_to_field_bytes(40),
+            u64_to_field_bytes(60),
+            u64_to_field_bytes(3),
+            pubkey_to_field_bytes(&destinations[0]),
+            pubkey_to_field_bytes(&destinations[1]),
+            u8_to_field_bytes(UnshieldMode::Origin as u8),
+            pubkey_to_field_bytes(&state.origin_mint),
+            pubkey_to_field_bytes(&pool_key),
+        ];
+        args.public_inputs = fields.concat();
+
+        let fee = validate_unshield_public_inputs(
+            &state,
+            pool_key,
+            &args,
+            UnshieldMode::Origin,
+            &destinations,
+            6,
+            None,
+        )
+        .unwrap();
+        assert_eq!(fee, 3);
+
+        let mut two_change = args.clone();
+        two_change.output_commitments.push(random_bytes(6));
+        two_change.output_amount_commitments.push(random_bytes(7));
+        assert!(validate_unshield_public_inputs(
+            &state,
+            pool_key,
+            &two_change,
+            UnshieldMode::Origin,
+            &destinations,
+            6,
+            None,
+        )
+        .is_err());
 
-    pub fn required_keys(&self) -> impl Iterator<Item = Pubkey> + '_ {
-        self.required_accounts
-            .iter()
-            .take(self.required_accounts_len as usize)
-            .map(|bytes| Pubkey::new_from_array(*bytes))
+        let swapped = [destinations[1], destinations[0]];
+        assert!(validate_unshield_public_inputs(
+            &state,
+            pool_key,
+            &args,
+            UnshieldMode::Origin,
+            &swapped,
+            6,
+            None,
+        )
+        .is_err());
     }
-}
 
-#[account]
-pub struct AllowanceAccount {
-    pub pool: Pubkey,
-    pub owner: Pubkey,
-    pub spender: Pubkey,
-    pub mint: Pubkey,
-    pub amount: u64,
-    pub updated_at: i64,
-    pub bump: u8,
-    pub _reserved: [u8; 7],
-}
+    #[test]
+    fn emergency_unshield_validates_without_change() {
+        let mut state = dummy_pool_state(false);
+        state.emergency_exit = true;
+        state.emergency_root = random_bytes(1);
+        let pool_key = Pubkey::new_unique();
+        let destination = Pubkey::new_unique();
+        let mut args = UnshieldManyArgs {
+            old_root: state.emergency_root,
+            new_root: state.emergency_root,
+            nullifiers: vec![random_bytes(3)],
+            output_commitments: Vec::new(),
+            output_amount_commitments: Vec::new(),
+            amounts: vec![100],
+            max_fee: 0,
+            proof: Vec::new(),
+            public_inputs: Vec::new(),
+            create_destination_ata: false,
+            allow_owner_off_curve: false,
+            unlock_timestamp: None,
+        };
+        args.public_inputs = [
+            args.old_root,
+            args.new_root,
+            args.nullifiers[0],
+            u64_to_field_bytes(100),
+            u64_to_field_bytes(0),
+            pubkey_to_field_bytes(&destination),
+            u8_to_field_bytes(UnshieldMode::Origin as u8),
+            pubkey_to_field_bytes(&state.origin_mint),
+            pubkey_to_field_bytes(&pool_key),
+        ]
+        .concat();
+
+        check_emergency_exit(&state, &args).unwrap();
+        let fee = validate_unshield_public_inputs(
+            &state,
+            pool_key,
+            &args,
+            UnshieldMode::Origin,
+            &[destination],
+            6,
+            None,
+        )
+        .unwrap();
+        assert_eq!(fee, 0);
+
+        // Outside an emergency exit the same proof lacks its change note.
+        state.emergency_exit = false;
+        assert_eq!(
+            validate_unshield_public_inputs(
+                &state,
+                pool_key,
+                &args,
+                UnshieldMode::Origin,
+                &[destination],
+                6,
+                None,
+            )
+            .unwrap_err(),
+            error!(PoolError::InvalidChangeNoteCount)
+        );
+    }
 
-impl AllowanceAccount {
-    pub const SPACE: usize = 8 + 32 * 4 + 8 + 8 + 1 + 7;
-}
+    #[test]
+    fn paused_pool_rejects_activity_until_unpaused() {
+        let mut state = dummy_pool_state(false);
+        assert!(state.check_not_paused().is_ok());
+        state.paused = true;
+        assert_eq!(
+            state.check_not_paused().unwrap_err(),
+            error!(PoolError::PoolPaused)
+        );
+        state.unpause().unwrap();
+        assert!(state.check_not_paused().is_ok());
+
+        // Emergency exit keeps the pool paused for good.
+        state.paused = true;
+        state.emergency_exit = true;
+        assert_eq!(
+            state.unpause().unwrap_err(),
+            error!(PoolError::EmergencyExitActive)
+        );
+        assert!(state.paused);
+    }
 
-#[cfg(feature = "idl-build")]
-mod idl_build_impls {
-    use super::*;
+    #[test]
+    fn emergency_exit_proves_against_frozen_root_without_change() {
+        let mut state = dummy_pool_state(false);
+        state.emergency_root = random_bytes(9);
+        let mut args = UnshieldManyArgs {
+            old_root: state.emergency_root,
+            new_root: state.emergency_root,
+            nullifiers: vec![random_bytes(3)],
+            output_commitments: Vec::new(),
+            output_amount_commitments: Vec::new(),
+            amounts: vec![100],
+            max_fee: 0,
+            proof: Vec::new(),
+            public_inputs: Vec::new(),
+            create_destination_ata: false,
+            allow_owner_off_curve: false,
+            unlock_timestamp: None,
+        };
+        check_emergency_exit(&state, &args).unwrap();
 
-    impl IdlBuild for PendingShield {}
-}
+        args.output_commitments = vec![random_bytes(4)];
+        args.output_amount_commitments = vec![random_bytes(5)];
+        assert_eq!(
+            check_emergency_exit(&state, &args).unwrap_err(),
+            error!(PoolError::EmergencyChangeNotAllowed)
+        );
 
-#[event]
-pub struct PoolInitialized {
-    pub origin_mint: Pubkey,
-    pub fee_bps: u16,
-    pub features: u8,
-}
+        args.output_commitments.clear();
+        args.output_amount_commitments.clear();
+        args.old_root = random_bytes(1);
+        assert_eq!(
+            check_emergency_exit(&state, &args).unwrap_err(),
+            error!(PoolError::UnknownRoot)
+        );
+    }
 
-#[event]
-pub struct PTFShielded {
-    pub mint: Pubkey,
-    pub depositor: Pubkey,
-    pub commitment: [u8; 32],
-    pub root: [u8; 32],
-    pub amount_commit: [u8; 32],
-}
+    #[test]
+    fn emergency_verifier_must_be_one_the_pool_trusted() {
+        let mut state = dummy_pool_state(false);
+        state.verifying_key_hash = random_bytes(1);
+        state.previous_verifying_key = Pubkey::new_unique();
+        state.previous_verifying_key_hash = random_bytes(2);
+        // A disabled allowlist entry, as left behind by a deprecated verifier.
+        let deprecated = Pubkey::new_unique();
+        state.verifier_allowlist[0] = deprecated;
+        state.verifier_allowlist_enabled = 0;
+
+        let (_, hash) = state
+            .emergency_verifier(&state.verifier_program, state.verifying_key)
+            .unwrap();
+        assert_eq!(hash, state.verifying_key_hash);
+        let (_, hash) = state
+            .emergency_verifier(&deprecated, state.previous_verifying_key)
+            .unwrap();
+        assert_eq!(hash, state.previous_verifying_key_hash);
+
+        assert!(state
+            .emergency_verifier(&Pubkey::new_unique(), state.verifying_key)
+            .is_err());
+        assert!(state
+            .emergency_verifier(&state.verifier_program, Pubkey::new_unique())
+            .is_err());
+        assert!(state
+            .emergency_verifier(&Pubkey::default(), state.verifying_key)
+            .is_err());
+    }
 
-#[event]
-pub struct PTFUnshieldOrigin {
-    pub mint: Pubkey,
-    pub destination: Pubkey,
-    pub amount: u64,
-    pub fee: u64,
-}
+    #[test]
+    fn scheduled_unshield_binds_unlock_timestamp() {
+        let state = dummy_pool_state(false);
+        let pool_key = Pubkey::new_unique();
+        let destination = Pubkey::new_unique();
+        let mut args = UnshieldManyArgs {
+            old_root: random_bytes(1),
+            new_root: random_bytes(2),
+            nullifiers: vec![random_bytes(3)],
+            output_commitments: vec![random_bytes(4)],
+            output_amount_commitments: vec![random_bytes(5)],
+            amounts: vec![50],
+            max_fee: 0,
+            proof: Vec::new(),
+            public_inputs: Vec::new(),
+            create_destination_ata: false,
+            allow_owner_off_curve: false,
+            unlock_timestamp: Some(1_700_000_000),
+        };
+        args.public_inputs = [
+            args.old_root,
+            args.new_root,
+            args.nullifiers[0],
+            args.output_commitments[0],
+            args.output_amount_commitments[0],
+            u64_to_field_bytes(50),
+            u64_to_field_bytes(0),
+            pubkey_to_field_bytes(&destination),
+            u8_to_field_bytes(UnshieldMode::Origin as u8),
+            pubkey_to_field_bytes(&state.origin_mint),
+            pubkey_to_field_bytes(&pool_key),
+            u64_to_field_bytes(1_700_000_000),
+        ]
+        .concat();
+        let validate = |args: &UnshieldManyArgs| {
+            validate_unshield_public_inputs(
+                &state,
+                pool_key,
+                args,
+                UnshieldMode::Origin,
+                &[destination],
+                6,
+                None,
+            )
+        };
+        assert!(validate(&args).is_ok());
+
+        let mut earlier = args.clone();
+        earlier.unlock_timestamp = Some(1_600_000_000);
+        assert!(validate(&earlier).is_err());
+        let mut unscheduled = args.clone();
+        unscheduled.unlock_timestamp = None;
+        assert!(validate(&unscheduled).is_err());
+
+        assert!(check_unlock_timestamp(args.unlock_timestamp, 1_699_999_999).is_err());
+        assert!(check_unlock_timestamp(args.unlock_timestamp, 1_700_000_000).is_ok());
+        assert!(check_unlock_timestamp(None, 0).is_ok());
+    }
 
-#[event]
-pub struct PTFUnshieldPMint {
-    pub mint: Pubkey,
-    pub destination: Pubkey,
-    pub amount: u64,
-    pub fee: u64,
-}
+    #[test]
+    fn transfer_arity_limits_apply_per_side() {
+        let mut state = dummy_pool_state(false);
+        assert!(state.check_transfer_arity(16, 2).is_ok());
+        assert!(state.check_transfer_arity(0, 2).is_err());
+
+        state.max_inputs = 2;
+        state.max_outputs = 2;
+        assert!(state.check_transfer_arity(2, 2).is_ok());
+        assert!(state.check_transfer_arity(3, 2).is_err());
+        assert!(state.check_transfer_arity(2, 3).is_err());
+    }
 
-#[event]
-pub struct PTFHookPostUnshield {
-    pub mint: Pubkey,
-    pub mode: u8,
-    pub destination: Pubkey,
-}
+    #[test]
+    fn shield_and_unshield_fees_use_their_own_rate() {
+        let mut state = dummy_pool_state(false);
+        state.shield_fee_bps = 0;
+        state.unshield_fee_bps = 30;
+        assert_eq!(state.shield_fee(1_000_000).unwrap(), 0);
+        assert_eq!(state.unshield_fee(1_000_000).unwrap(), 3_000);
+
+        state.shield_fee_bps = 10;
+        assert_eq!(state.shield_fee(1_000_000).unwrap(), 1_000);
+        assert_eq!(state.unshield_fee(1_000_000).unwrap(), 3_000);
+    }
 
-#[event]
-pub struct PTFHookPostShield {
-    pub mint: Pubkey,
-    pub deposit_id: u64,
-    pub commitment: [u8; 32],
-}
+    #[test]
+    fn ptkn_redemption_pays_out_net_of_unshield_fee() {
+        let mut state = dummy_pool_state(false);
+        state.unshield_fee_bps = 30;
+        assert_eq!(state.ptkn_redemption(1_000_000).unwrap(), (3_000, 997_000));
+
+        state.unshield_flat_fee = 1_000;
+        assert_eq!(state.ptkn_redemption(1_000_000).unwrap(), (4_000, 996_000));
+        assert!(state.ptkn_redemption(1_000).is_err());
+    }
 
-#[event]
-pub struct PTFTransferred {
-    pub mint: Pubkey,
-    pub inputs: Vec<[u8; 32]>,
-    pub outputs: Vec<[u8; 32]>,
-    pub root: [u8; 32],
-}
+    #[test]
+    fn frozen_mint_mapping_is_rejected() {
+        let mut mapping = MintMapping {
+            origin_mint: Pubkey::new_unique(),
+            ptkn_mint: Pubkey::default(),
+            has_ptkn: false,
+            status: MintStatus::Active as u8,
+            decimals: 6,
+            features: FeatureFlags::empty(),
+            fee_bps_override: 0,
+            has_fee_override: false,
+            bump: 255,
+            ptkn_token_program: PtknTokenProgram::Unknown as u8,
+            ptkn_extensions: 0,
+            max_ptkn_supply: 0,
+            total_minted: 0,
+            total_burned: 0,
+            pool: Pubkey::default(),
+            vault: Pubkey::default(),
+            commitment_tree: Pubkey::default(),
+            nullifier_set: Pubkey::default(),
+            circuit_tag: [0u8; 32],
+            verifying_key: Pubkey::default(),
+            template: Pubkey::default(),
+        };
+        assert!(ensure_mint_active(&mapping).is_ok());
+
+        mapping.status = MintStatus::Frozen as u8;
+        assert!(ensure_mint_active(&mapping).is_err());
+    }
 
-#[event]
-pub struct PTFAllowanceUpdated {
-    pub mint: Pubkey,
-    pub owner: Pubkey,
-    pub spender: Pubkey,
-    pub amount: u64,
-}
+    #[test]
+    fn unshield_fee_follows_mapping_override() {
+        let mut mapping = MintMapping {
+            origin_mint: Pubkey::new_unique(),
+            ptkn_mint: Pubkey::default(),
+            has_ptkn: false,
+            status: MintStatus::Active as u8,
+            decimals: 6,
+            features: FeatureFlags::empty(),
+            fee_bps_override: 0,
+            has_fee_override: false,
+            bump: 255,
+            ptkn_token_program: PtknTokenProgram::Unknown as u8,
+            ptkn_extensions: 0,
+            max_ptkn_supply: 0,
+            total_minted: 0,
+            total_burned: 0,
+            pool: Pubkey::default(),
+            vault: Pubkey::default(),
+            commitment_tree: Pubkey::default(),
+            nullifier_set: Pubkey::default(),
+            circuit_tag: [0u8; 32],
+            verifying_key: Pubkey::default(),
+            template: Pubkey::default(),
+        };
+        assert_eq!(mapping.fee_bps(5), 5);
 
-#[event]
-pub struct PTFNullifierUsed {
-    pub mint: Pubkey,
-    pub nullifier: [u8; 32],
-}
+        // A zero override is still an override.
+        mapping.has_fee_override = true;
+        assert_eq!(mapping.fee_bps(5), 0);
+        mapping.fee_bps_override = 25;
+        assert_eq!(mapping.fee_bps(5), 25);
+    }
 
-#[event]
-pub struct HookConfigUpdated {
-    pub origin_mint: Pubkey,
-    pub post_shield_program: Pubkey,
-    pub post_unshield_program: Pubkey,
-    pub post_shield_enabled: bool,
-    pub post_unshield_enabled: bool,
-    pub mode: u8,
-}
+    #[test]
+    fn twin_token_program_follows_mapping() {
+        let mut mapping = MintMapping {
+            origin_mint: Pubkey::new_unique(),
+            ptkn_mint: Pubkey::new_unique(),
+            has_ptkn: true,
+            status: MintStatus::Active as u8,
+            decimals: 6,
+            features: FeatureFlags::empty(),
+            fee_bps_override: 0,
+            has_fee_override: false,
+            bump: 255,
+            ptkn_token_program: PtknTokenProgram::Unknown as u8,
+            ptkn_extensions: 0,
+            max_ptkn_supply: 0,
+            total_minted: 0,
+            total_burned: 0,
+            pool: Pubkey::default(),
+            vault: Pubkey::default(),
+            commitment_tree: Pubkey::default(),
+            nullifier_set: Pubkey::default(),
+            circuit_tag: [0u8; 32],
+            verifying_key: Pubkey::default(),
+            template: Pubkey::default(),
+        };
+        assert!(ensure_twin_token_program(&mapping, &anchor_spl::token::ID).is_ok());
+        assert!(ensure_twin_token_program(&mapping, &anchor_spl::token_2022::ID).is_ok());
 
-#[event]
-pub struct PTFInvariantOk {
-    pub mint: Pubkey,
-    pub vault: Pubkey,
-    pub supply_pm: u64,
-    pub live_notes_commit: [u8; 32],
-    pub fees: u128,
-}
+        mapping.ptkn_token_program = PtknTokenProgram::Token2022 as u8;
+        assert!(ensure_twin_token_program(&mapping, &anchor_spl::token_2022::ID).is_ok());
+        assert!(ensure_twin_token_program(&mapping, &anchor_spl::token::ID).is_err());
+    }
 
-#[event]
-pub struct FeeUpdated {
-    pub origin_mint: Pubkey,
-    pub fee_bps: u16,
-}
+    #[test]
+    fn shield_from_ptkn_requires_enabled_twin_tokens() {
+        let origin_only = dummy_pool_state(false);
+        assert_eq!(
+            check_shield_source_mint(&origin_only, ShieldSource::Twin, &origin_only.origin_mint)
+                .unwrap_err(),
+            error!(PoolError::TwinMintNotConfigured)
+        );
 
-#[event]
-pub struct FeaturesUpdated {
-    pub origin_mint: Pubkey,
-    pub features: u8,
-}
+        let pool = dummy_pool_state(true);
+        assert!(check_shield_source_mint(&pool, ShieldSource::Twin, &pool.twin_mint).is_ok());
+        assert_eq!(
+            check_shield_source_mint(&pool, ShieldSource::Twin, &pool.origin_mint).unwrap_err(),
+            error!(PoolError::TwinMintMismatch)
+        );
+        // pTKN cannot stand in for origin tokens either.
+        for source in [ShieldSource::Origin, ShieldSource::Prefunded] {
+            assert!(check_shield_source_mint(&pool, source, &pool.origin_mint).is_ok());
+            assert_eq!(
+                check_shield_source_mint(&pool, source, &pool.twin_mint).unwrap_err(),
+                error!(PoolError::OriginMintMismatch)
+            );
+        }
+    }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
-pub enum UnshieldMode {
-    Origin = 0,
-    Twin = 1,
-}
+    #[test]
+    fn recent_roots_expire_after_max_age() {
+        let mut state = dummy_pool_state(false);
+        state.push_root(random_bytes(1), 100);
+        state.push_root(random_bytes(2), 150);
+        assert!(state.is_known_root(&random_bytes(1), 10_000));
+
+        state.max_root_age_slots = 60;
+        assert!(state.is_known_root(&random_bytes(1), 160));
+        assert!(!state.is_known_root(&random_bytes(1), 161));
+        // The current root never expires.
+        assert!(state.is_known_root(&random_bytes(2), 10_000));
+    }
 
-#[repr(u8)]
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
-pub enum HookAccountMode {
-    Strict = 0,
-    Lenient = 1,
-}
+    #[test]
+    fn previous_verifying_key_accepted_during_overlap() {
+        let mut state = dummy_pool_state(false);
+        let old_key = state.verifying_key;
+        state.previous_verifying_key = old_key;
+        state.previous_verifying_key_id = [1u8; 32];
+        state.previous_verifying_key_expiry_slot = 100;
+        state.verifying_key = Pubkey::new_unique();
+        state.verifying_key_id = [2u8; 32];
+
+        assert_eq!(state.active_verifying_key(old_key, 100).1, [1u8; 32]);
+        assert_eq!(
+            state.active_verifying_key(old_key, 101).0,
+            state.verifying_key
+        );
+        assert_eq!(
+            state.active_verifying_key(state.verifying_key, 50).1,
+            [2u8; 32]
+        );
+    }
 
-#[error_code]
-pub enum PoolError {
-    #[msg("E_INVALID_FEE_BPS")]
-    InvalidFeeBps,
-    #[msg("E_VERIFIER_MISMATCH")]
-    VerifierMismatch,
-    #[msg("E_VERIFYING_KEY_HASH_MISMATCH")]
-    VerifyingKeyHashMismatch,
-    #[msg("E_INVALID_PUBLIC_INPUTS")]
-    InvalidPublicInputs,
-    #[msg("E_PUBLIC_INPUT_MISMATCH")]
-    PublicInputMismatch,
-    #[msg("E_UNKNOWN_ROOT")]
-    UnknownRoot,
-    #[msg("E_NULLIFIER_REUSE")]
-    NullifierReuse,
-    #[msg("E_NULLIFIER_CAPACITY")]
-    NullifierCapacity,
-    #[msg("E_AMOUNT_OVERFLOW")]
-    AmountOverflow,
-    #[msg("E_INSUFFICIENT_LIQUIDITY")]
-    InsufficientLiquidity,
-    #[msg("E_FEATURE_DISABLED")]
-    FeatureDisabled,
-    #[msg("E_VAULT_AUTHORITY_MISMATCH")]
-    MismatchedVaultAuthority,
-    #[msg("E_ORIGIN_MINT_MISMATCH")]
-    OriginMintMismatch,
-    #[msg("E_VAULT_TOKEN_ACCOUNT_MISMATCH")]
-    VaultTokenAccountMismatch,
-    #[msg("E_INVALID_DEPOSITOR_ACCOUNT")]
-    InvalidDepositorAccount,
-    #[msg("E_TWIN_MINT_MISMATCH")]
-    TwinMintMismatch,
-    #[msg("E_TWIN_MINT_NOT_CONFIGURED")]
-    TwinMintNotConfigured,
-    #[msg("E_TWIN_MINT_AUTHORITY_MISMATCH")]
-    TwinMintAuthorityMismatch,
-    #[msg("E_TWIN_MINT_DECIMALS_MISMATCH")]
-    TwinMintDecimalsMismatch,
-    #[msg("E_INVARIANT_BREACH")]
-    InvariantBreach,
-    #[msg("E_HOOKS_DISABLED")]
-    HooksDisabled,
-    #[msg("E_TOO_MANY_HOOK_ACCOUNTS")]
-    TooManyHookAccounts,
-    #[msg("E_HOOK_CONFIG_INVALID")]
-    HookConfigInvalid,
-    #[msg("E_HOOK_ACCOUNT_MISMATCH")]
-    HookAccountMismatch,
-    #[msg("E_HOOK_ACCOUNT_MISSING")]
-    HookAccountMissing,
-    #[msg("E_HOOK_ACCOUNT_UNEXPECTED")]
-    HookAccountUnexpected,
-    #[msg("E_NOTE_LEDGER_MISMATCH")]
-    NoteLedgerMismatch,
-    #[msg("E_TREE_MISMATCH")]
-    CommitmentTreeMismatch,
-    #[msg("E_INVALID_CHANGE_NOTE_COUNT")]
-    InvalidChangeNoteCount,
-    #[msg("E_OUTPUT_SET_MISMATCH")]
-    OutputSetMismatch,
-    #[msg("E_CANOPY_DEPTH_INVALID")]
-    CanopyDepthInvalid,
-    #[msg("E_TREE_FULL")]
-    TreeFull,
-    #[msg("E_ROOT_MISMATCH")]
-    RootMismatch,
-    #[msg("E_PENDING_SHIELD_IN_FLIGHT")]
-    PendingShieldInFlight,
-    #[msg("E_NO_PENDING_SHIELD")]
-    NoPendingShield,
-    #[msg("E_PENDING_SHIELD_MISMATCH")]
-    PendingShieldMismatch,
-    #[msg("E_SHIELD_FINALIZE_MISSING")]
-    MissingShieldFinalize,
-    #[msg("E_SHIELD_CLAIM_MISMATCH")]
-    ShieldClaimMismatch,
-    #[msg("E_SHIELD_CLAIM_STAGE")]
-    ShieldClaimStage,
-    #[msg("E_ALLOWANCE_POOL_MISMATCH")]
-    AllowancePoolMismatch,
-    #[msg("E_ALLOWANCE_OWNER_MISMATCH")]
-    AllowanceOwnerMismatch,
-    #[msg("E_ALLOWANCE_SPENDER_MISMATCH")]
-    AllowanceSpenderMismatch,
-    #[msg("E_ALLOWANCE_MINT_MISMATCH")]
-    AllowanceMintMismatch,
-    #[msg("E_ALLOWANCE_INSUFFICIENT")]
-    AllowanceInsufficient,
-    #[msg("E_ALLOWANCE_AMOUNT_INVALID")]
-    AllowanceAmountInvalid,
-}
+    #[test]
+    fn timelocked_pools_reject_direct_updates() {
+        let mut state = dummy_pool_state(false);
+        assert!(ensure_direct_update_allowed(&state).is_ok());
+        assert!(apply_timelock_update(&mut state, -1).is_err());
+
+        apply_timelock_update(&mut state, 86_400).unwrap();
+        assert!(ensure_direct_update_allowed(&state).is_err());
+        assert!(ensure_direct_update_allowed_by(&state, &state.authority).is_err());
+        assert!(ensure_direct_update_allowed_by(&state, &factory_state_address()).is_ok());
+        assert!(apply_fee_update(&mut state, MAX_BPS + 1, 0, 0, 0).is_err());
+    }
 
-fn validate_hook_accounts(
-    required_accounts: &[Pubkey],
-    mode: HookAccountMode,
-    remaining_accounts: &[AccountInfo<'_>],
-) -> Result<()> {
-    let provided: Vec<Pubkey> = remaining_accounts
-        .iter()
-        .map(|account| account.key())
-        .collect();
-    validate_hook_keys(required_accounts, mode, &provided)
-}
+    #[test]
+    fn sequencer_actions_wait_at_least_the_minimum_delay() {
+        let write = PoolTimelockAction::WriteNullifier {
+            nullifier: [7u8; 32],
+        };
+        let sequencer = PoolTimelockAction::SetSequencer {
+            sequencer: Pubkey::new_unique(),
+        };
+        let fee = PoolTimelockAction::SetFeatures { features: 0 };
+        let floor = PoolTimelockAction::MIN_SEQUENCER_DELAY;
+        assert_eq!(write.delay(0), floor);
+        assert_eq!(sequencer.delay(0), floor);
+        assert_eq!(fee.delay(0), 0);
+        assert_eq!(write.delay(floor * 2), floor * 2);
+        assert_eq!(fee.delay(floor * 2), floor * 2);
+    }
 
-fn validate_hook_keys(
-    required_accounts: &[Pubkey],
-    mode: HookAccountMode,
-    provided_accounts: &[Pubkey],
-) -> Result<()> {
-    match mode {
-        HookAccountMode::Strict => {
-            require!(
-                provided_accounts.len() == required_accounts.len(),
-                PoolError::HookAccountMismatch
+    #[test]
+    fn native_shield_wraps_amount_plus_fee() {
+        let mut state = dummy_pool_state(false);
+        state.shield_fee_bps = 100;
+        state.shield_flat_fee = 5;
+        assert_eq!(state.native_shield_deposit(10_000).unwrap(), 10_105);
+        assert_eq!(
+            state.native_shield_deposit(u64::MAX).unwrap_err(),
+            error!(PoolError::AmountOverflow)
+        );
+    }
+
+    #[test]
+    fn native_unwrap_is_single_recipient_origin_only() {
+        assert!(check_native_unwrap(UnshieldMode::Origin, 1, NATIVE_MINT, false).is_ok());
+        for (mode, recipients, create_ata) in [
+            (UnshieldMode::Twin, 1, false),
+            (UnshieldMode::Origin, 2, false),
+            (UnshieldMode::Origin, 1, true),
+        ] {
+            assert_eq!(
+                check_native_unwrap(mode, recipients, NATIVE_MINT, create_ata).unwrap_err(),
+                error!(PoolError::NativeUnwrapInvalid)
             );
-            for (expected, provided) in required_accounts.iter().zip(provided_accounts.iter()) {
-                require_keys_eq!(*expected, *provided, PoolError::HookAccountMismatch);
-            }
-        }
-        HookAccountMode::Lenient => {
-            for expected in required_accounts {
-                require!(
-                    provided_accounts.iter().any(|account| account == expected),
-                    PoolError::HookAccountMissing
-                );
-            }
         }
+        assert_eq!(
+            check_native_unwrap(UnshieldMode::Origin, 1, Pubkey::new_unique(), false).unwrap_err(),
+            error!(PoolError::NativeMintRequired)
+        );
     }
 
-    Ok(())
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use anchor_lang::prelude::InterfaceAccount;
-    use anchor_lang::solana_program::{
-        account_info::AccountInfo, entrypoint::ProgramResult, program_pack::Pack,
-    };
-    use anchor_spl::token::spl_token;
-    use anchor_spl::token::spl_token::state::{
-        Account as SplAccountState, AccountState, Mint as SplMintState,
-    };
-    use anchor_spl::token_interface::{Mint as InterfaceMint, TokenAccount};
+    #[test]
+    fn native_unwrap_account_is_per_pool_and_destination() {
+        let pool = Pubkey::new_unique();
+        let destination = Pubkey::new_unique();
+        let (address, _) = native_unwrap_address(&pool, &destination);
+        assert!(!address.is_on_curve());
+        assert_ne!(
+            address,
+            native_unwrap_address(&pool, &Pubkey::new_unique()).0
+        );
+        assert_ne!(
+            address,
+            native_unwrap_address(&Pubkey::new_unique(), &destination).0
+        );
+    }
 
     #[test]
-    fn strict_mode_requires_exact_accounts() {
-        let a = Pubkey::new_unique();
-        let b = Pubkey::new_unique();
-        assert!(validate_hook_keys(&[a, b], HookAccountMode::Strict, &[a, b]).is_ok());
-        assert!(validate_hook_keys(&[a, b], HookAccountMode::Strict, &[b, a]).is_err());
-        assert!(validate_hook_keys(&[a, b], HookAccountMode::Strict, &[a]).is_err());
+    fn pool_timelock_actions_fit_entry_space() {
+        let largest = PoolTimelockAction::SetFee {
+            shield_fee_bps: MAX_BPS,
+            unshield_fee_bps: MAX_BPS,
+            shield_flat_fee: u64::MAX,
+            unshield_flat_fee: u64::MAX,
+        };
+        assert!(largest.try_to_vec().unwrap().len() <= PoolTimelockEntry::MAX_ACTION_SIZE);
     }
 
     #[test]
-    fn lenient_mode_requires_subset_only() {
-        let a = Pubkey::new_unique();
-        let b = Pubkey::new_unique();
-        let c = Pubkey::new_unique();
-        assert!(validate_hook_keys(&[a, b], HookAccountMode::Lenient, &[c, a, b]).is_ok());
-        assert!(validate_hook_keys(&[a, b], HookAccountMode::Lenient, &[c, a]).is_err());
+    fn flat_fee_is_added_to_the_bps_component() {
+        let mut state = dummy_pool_state(false);
+        state.unshield_fee_bps = 30;
+        state.unshield_flat_fee = 5_000;
+        assert_eq!(state.unshield_fee(10).unwrap(), 5_000);
+        assert_eq!(state.unshield_fee(1_000_000).unwrap(), 8_000);
+        assert_eq!(state.shield_fee(1_000_000).unwrap(), 0);
+
+        state.unshield_flat_fee = u64::MAX;
+        assert!(state.unshield_fee(1_000_000).is_err());
     }
 
     #[test]
@@ -3204,7 +12298,6 @@ mod tests {
         );
     }
 
-    #[cfg(feature = "invariant_checks")]
     #[test]
     fn supply_invariant_tracks_origin_flow() {
         let pool_key = Pubkey::new_unique();
@@ -3250,7 +12343,6 @@ mod tests {
         }
     }
 
-    #[cfg(feature = "invariant_checks")]
     #[test]
     fn supply_invariant_tracks_twin_flow() {
         let pool_key = Pubkey::new_unique();
@@ -3277,7 +12369,7 @@ mod tests {
         ledger
             .record_unshield(306, &[random_bytes(10)], &[random_bytes(11)])
             .expect("unshield accounting must succeed");
-        pool_state.protocol_fees = 6;
+        pool_state.twin_protocol_fees = 6;
         twin_supply += 300;
         assert_eq!(ledger.live_value, 414);
         let mut mint_harness = MintHarness::new(pool_state.twin_mint, twin_supply as u64, 6);
@@ -3294,6 +12386,15 @@ mod tests {
             enforce_supply_invariant(&pool_state, &ledger, &vault_account, Some(&mint_account))
                 .expect("twin invariant should pass");
         }
+
+        // Withdrawing twin fees mints them, moving the fee into twin supply.
+        pool_state.twin_protocol_fees = 0;
+        twin_supply += 6;
+        validate_supply_components(&pool_state, &ledger, twin_supply, {
+            let vault_account = vault_harness.interface_account();
+            u128::from(vault_account.amount)
+        })
+        .expect("minted twin fees must stay backed");
     }
 
     fn dummy_pool_state(twin_enabled: bool) -> PoolState {
@@ -3314,8 +12415,8 @@ mod tests {
             current_root: [0u8; 32],
             recent_roots: [[0u8; 32]; PoolState::MAX_ROOTS],
             roots_len: 0,
-            fee_bps: 5,
-            features: FeatureFlags::from(0),
+            unshield_fee_bps: 5,
+            features: 0,
             note_ledger: Pubkey::new_unique(),
             note_ledger_bump: 0,
             protocol_fees: 0,
@@ -3326,6 +12427,50 @@ mod tests {
             twin_mint,
             twin_mint_enabled: twin_enabled,
             pending_shield: PendingShield::inactive(),
+            nullifier_shard_count: 0,
+            nullifier_mode: NULLIFIER_MODE_SET,
+            max_change_notes: 1,
+            max_inputs: 0,
+            max_outputs: 0,
+            fee_recipient: Pubkey::default(),
+            shield_fee_bps: 0,
+            shield_flat_fee: 0,
+            unshield_flat_fee: 0,
+            timelock_seconds: 0,
+            paused: false,
+            previous_verifying_key: Pubkey::default(),
+            previous_verifying_key_id: [0u8; 32],
+            previous_verifying_key_hash: [0u8; 32],
+            previous_verifying_key_expiry_slot: 0,
+            recent_root_slots: [0u64; PoolState::MAX_ROOTS],
+            max_root_age_slots: 0,
+            sequencer: Pubkey::default(),
+            denominations: [0u64; PoolState::MAX_DENOMINATIONS],
+            denominations_len: 0,
+            max_deposit_amount: 0,
+            max_total_locked: 0,
+            rate_limiter: Pubkey::default(),
+            emergency_exit: false,
+            emergency_root: [0u8; 32],
+            version: PoolState::CURRENT_VERSION,
+            fee_rounding: FEE_ROUNDING_FLOOR,
+            twin_protocol_fees: 0,
+            min_note_age_leaves: 0,
+            root_guardian: Pubkey::default(),
+            verifier_allowlist: [Pubkey::default(); PoolState::MAX_VERIFIER_PROGRAMS],
+            verifier_allowlist_enabled: 0,
+            checkpoint_interval: 0,
+            checkpoint_count: 0,
+            checkpoint_leaf: 0,
+            checkpoint_digest: [0u8; 32],
+            pool_stats: Pubkey::default(),
+            destination_allowlist: Pubkey::default(),
+            referral_fee_bps: 0,
+            referral_fees: 0,
+            emergency_verifier_program: Pubkey::default(),
+            emergency_verifying_key: Pubkey::default(),
+            emergency_verifying_key_id: [0u8; 32],
+            emergency_verifying_key_hash: [0u8; 32],
         }
     }
 
@@ -3340,6 +12485,11 @@ mod tests {
             amount_commitment_digest: [0u8; 32],
             nullifier_digest: [0u8; 32],
             bump: 0,
+            nullifier_epoch: 0,
+            archived_nullifier_digest: [0u8; 32],
+            version: NoteLedger::CURRENT_VERSION,
+            epoch_start_slot: 0,
+            archived_epoch_start_slot: 0,
         }
     }
 
@@ -3626,14 +12776,21 @@ mod tests {
             let fixture = IdentityFixture::new();
             let (mut context, setup) = setup_pool_test(&fixture).await;
 
-            let mut tree: CommitmentTree = fetch_account(&mut context, setup.commitment_tree).await;
+            let mut tree_data = context
+                .banks_client
+                .get_account(setup.commitment_tree)
+                .await
+                .unwrap()
+                .unwrap()
+                .data;
+            let (tree, levels) = CommitmentTree::split_data_mut(&mut tree_data).unwrap();
             let mut ledger: NoteLedger = fetch_account(&mut context, setup.note_ledger).await;
             let mut pool_state: PoolState = fetch_account(&mut context, setup.pool_state).await;
 
             let amount: u64 = 1_000_000;
             let commitment = [1u8; 32];
             let amount_commit = [2u8; 32];
-            let (new_root, _) = tree.append_note(commitment, amount_commit).unwrap();
+            let (new_root, _) = tree.append_note(levels, commitment, amount_commit).unwrap();
             ledger.record_shield(amount, amount_commit).unwrap();
 
             let zeros = vec![Fr::from(0u64); IDENTITY_PUBLIC_INPUTS];
@@ -3657,6 +12814,7 @@ mod tests {
                     origin_mint: setup.origin_mint.pubkey(),
                     vault_program: ptf_vault::id(),
                     token_program: spl_token::id(),
+                    mint_mapping: setup.mint_mapping,
                 }
                 .to_account_metas(None),
                 data: crate::instruction::Shield {
@@ -3667,6 +12825,7 @@ mod tests {
                         amount,
                         proof: proof_bytes.clone(),
                         public_inputs: public_inputs.clone(),
+                        encrypted_note: Vec::new(),
                     },
                 }
                 .data(),
@@ -3678,7 +12837,7 @@ mod tests {
             let vault_after = get_token_balance(&mut context, setup.vault_token_account).await;
             assert_eq!(vault_after, amount);
 
-            pool_state.push_root(new_root);
+            pool_state.push_root(new_root, 0);
 
             let set_features_ix = Instruction {
                 program_id: crate::id(),
@@ -3700,7 +12859,7 @@ mod tests {
             let old_root = tree.current_root;
             let outputs = vec![[3u8; 32], [4u8; 32]];
             let output_amounts = vec![[5u8; 32], [6u8; 32]];
-            let (transfer_root, _) = tree.append_many(&outputs, &output_amounts).unwrap();
+            let (transfer_root, _) = tree.append_many(levels, &outputs, &output_amounts).unwrap();
             ledger
                 .record_transfer(&[], &output_amounts)
                 .expect("ledger transfer");
@@ -3712,6 +12871,7 @@ mod tests {
                     nullifier_set: setup.nullifier_set,
                     commitment_tree: setup.commitment_tree,
                     note_ledger: setup.note_ledger,
+                    mint_mapping: setup.mint_mapping,
                     verifier_program: ptf_verifier_groth16::id(),
                     verifying_key: setup.verifier_state,
                 }
@@ -3725,6 +12885,8 @@ mod tests {
                         output_amount_commitments: output_amounts.clone(),
                         proof: proof_bytes.clone(),
                         public_inputs: public_inputs.clone(),
+                        encrypted_note: Vec::new(),
+                        output_ciphertexts: Vec::new(),
                     },
                 }
                 .data(),
@@ -3733,16 +12895,16 @@ mod tests {
                 .await
                 .expect("transfer");
 
-            pool_state.push_root(transfer_root);
+            pool_state.push_root(transfer_root, 0);
 
             let nullifier = [7u8; 32];
             let unshield_outputs = vec![[8u8; 32]];
             let unshield_amount_commits = vec![[9u8; 32]];
             let (unshield_root, _) = tree
-                .append_many(&unshield_outputs, &unshield_amount_commits)
+                .append_many(levels, &unshield_outputs, &unshield_amount_commits)
                 .unwrap();
 
-            let fee = pool_state.calculate_fee(amount).unwrap();
+            let fee = pool_state.unshield_fee(amount).unwrap();
             ledger
                 .record_unshield(amount + fee, &[nullifier], &unshield_amount_commits)
                 .expect("ledger unshield");
@@ -3794,8 +12956,12 @@ mod tests {
                         output_commitments: unshield_outputs.clone(),
                         output_amount_commitments: unshield_amount_commits.clone(),
                         amount,
+                        max_fee: fee,
                         proof: unshield_proof,
                         public_inputs: unshield_inputs,
+                        create_destination_ata: false,
+                        allow_owner_off_curve: false,
+                        unlock_timestamp: None,
                     },
                 }
                 .data(),
@@ -3831,8 +12997,15 @@ mod tests {
                         post_shield_enabled: true,
                         post_unshield_program: Pubkey::default(),
                         post_unshield_enabled: false,
-                        required_accounts: vec![],
+                        post_shield_accounts: vec![],
+                        post_unshield_accounts: vec![],
                         mode: HookAccountMode::Strict,
+                        pre_hook_program: Pubkey::default(),
+                        pre_shield_enabled: false,
+                        pre_unshield_enabled: false,
+                        post_transfer_program: Pubkey::default(),
+                        post_transfer_enabled: false,
+                        account_templates: vec![],
                     },
                 }
                 .data(),
@@ -3886,8 +13059,21 @@ mod tests {
                         post_shield_enabled: true,
                         post_unshield_program: hook_stub::ID,
                         post_unshield_enabled: true,
-                        required_accounts: vec![required.pubkey()],
+                        post_shield_accounts: vec![HookAccountArgs {
+                            key: required.pubkey(),
+                            is_writable: false,
+                        }],
+                        post_unshield_accounts: vec![HookAccountArgs {
+                            key: required.pubkey(),
+                            is_writable: false,
+                        }],
                         mode: HookAccountMode::Strict,
+                        pre_hook_program: Pubkey::default(),
+                        pre_shield_enabled: false,
+                        pre_unshield_enabled: false,
+                        post_transfer_program: Pubkey::default(),
+                        post_transfer_enabled: false,
+                        account_templates: vec![],
                     },
                 }
                 .data(),
@@ -3904,10 +13090,17 @@ mod tests {
                 .await
                 .expect("configure hooks");
 
-            let mut tree: CommitmentTree = fetch_account(&mut context, setup.commitment_tree).await;
+            let mut tree_data = context
+                .banks_client
+                .get_account(setup.commitment_tree)
+                .await
+                .unwrap()
+                .unwrap()
+                .data;
+            let (tree, levels) = CommitmentTree::split_data_mut(&mut tree_data).unwrap();
             let commitment = [11u8; 32];
             let amount_commit = [12u8; 32];
-            let (new_root, _) = tree.append_note(commitment, amount_commit).unwrap();
+            let (new_root, _) = tree.append_note(levels, commitment, amount_commit).unwrap();
             let (proof_bytes, public_inputs) =
                 fixture.proof(&vec![Fr::from(0u64); IDENTITY_PUBLIC_INPUTS]);
 
@@ -3927,6 +13120,7 @@ mod tests {
                 origin_mint: setup.origin_mint.pubkey(),
                 vault_program: ptf_vault::id(),
                 token_program: spl_token::id(),
+                mint_mapping: setup.mint_mapping,
             }
             .to_account_metas(None);
             accounts.push(AccountMeta::new_readonly(required.pubkey(), false));
@@ -3942,6 +13136,7 @@ mod tests {
                         amount: 10,
                         proof: proof_bytes,
                         public_inputs,
+                        encrypted_note: Vec::new(),
                     },
                 }
                 .data(),
@@ -3952,7 +13147,7 @@ mod tests {
 
             let pool_state_after: PoolState = fetch_account(&mut context, setup.pool_state).await;
             assert!(pool_state_after
-                .features
+                .features()
                 .contains(FeatureFlags::from(FEATURE_HOOKS_ENABLED)));
         }
 
@@ -4194,8 +13389,10 @@ mod tests {
                 }
                 .to_account_metas(None),
                 data: crate::instruction::InitializePool {
-                    fee_bps: 5,
+                    shield_fee_bps: 0,
+                    unshield_fee_bps: 5,
                     features: 0,
+                    merkle_depth: ptf_common::MERKLE_DEPTH,
                 }
                 .data(),
             };
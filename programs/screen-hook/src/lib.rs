@@ -0,0 +1,164 @@
+//! Reference compliance hook for `ptf_pool`. Configured as a pool's pre-hook and
+//! post-unshield hook, it rejects shields from flagged depositors and unshields to flagged
+//! destinations, so the whole pool instruction fails.
+//!
+//! The pool dispatches hooks as a raw Borsh `HookInstruction` with no Anchor
+//! discriminator; those calls land in `fallback`.
+
+use anchor_lang::prelude::*;
+use ptf_common::hooks::HookInstruction;
+
+declare_id!("C51LBy9NQY8dupena4oRpAiApF8RCq23wiJhN3eJv9oh");
+
+pub const DENYLIST_SEED: &[u8] = b"denylist";
+
+#[program]
+pub mod ptf_screen_hook {
+    use super::*;
+
+    /// Creates the denylist for `pool`, maintained by the signing authority.
+    pub fn initialize_denylist(ctx: Context<InitializeDenylist>, pool: Pubkey) -> Result<()> {
+        let denylist = &mut ctx.accounts.denylist;
+        denylist.pool = pool;
+        denylist.authority = ctx.accounts.authority.key();
+        denylist.parties = Vec::new();
+        denylist.bump = ctx.bumps.denylist;
+        Ok(())
+    }
+
+    pub fn set_flagged(ctx: Context<SetFlagged>, party: Pubkey, flagged: bool) -> Result<()> {
+        let denylist = &mut ctx.accounts.denylist;
+        if flagged {
+            denylist.flag(party)?;
+        } else {
+            denylist.unflag(&party);
+        }
+        emit!(PartyFlagged {
+            pool: denylist.pool,
+            party,
+            flagged,
+        });
+        Ok(())
+    }
+
+    pub fn fallback<'info>(
+        program_id: &Pubkey,
+        accounts: &'info [AccountInfo<'info>],
+        data: &[u8],
+    ) -> Result<()> {
+        screen(program_id, accounts, data)
+    }
+}
+
+/// Screens one hook call. `accounts` are as the pool passes them: hook config, pool, then
+/// the configured hook accounts, which must include the pool's denylist. A missing
+/// denylist fails closed.
+pub fn screen(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> Result<()> {
+    let instruction =
+        HookInstruction::try_from_slice(data).map_err(|_| ScreenError::UnknownInstruction)?;
+    let (pool, party) = match &instruction {
+        HookInstruction::PreShield(hook) => (hook.pool, hook.depositor),
+        HookInstruction::PostUnshield(hook) => (hook.pool, hook.destination),
+        _ => return Ok(()),
+    };
+    let pool_info = accounts.get(1).ok_or(ScreenError::PoolMismatch)?;
+    require_keys_eq!(pool_info.key(), pool, ScreenError::PoolMismatch);
+
+    let (address, _) = Denylist::address(program_id, &pool);
+    let info = accounts
+        .iter()
+        .find(|info| info.key() == address)
+        .ok_or(ScreenError::DenylistMissing)?;
+    require_keys_eq!(*info.owner, *program_id, ScreenError::DenylistMissing);
+    let denylist = Denylist::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+    require!(!denylist.is_flagged(&party), ScreenError::PartyFlagged);
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(pool: Pubkey)]
+pub struct InitializeDenylist<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        init,
+        payer = payer,
+        space = Denylist::SPACE,
+        seeds = [DENYLIST_SEED, pool.as_ref()],
+        bump
+    )]
+    pub denylist: Account<'info, Denylist>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetFlagged<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [DENYLIST_SEED, denylist.pool.as_ref()],
+        bump = denylist.bump,
+        has_one = authority
+    )]
+    pub denylist: Account<'info, Denylist>,
+}
+
+/// Flagged parties for one pool, at `["denylist", pool]`.
+#[account]
+pub struct Denylist {
+    pub pool: Pubkey,
+    pub authority: Pubkey,
+    pub parties: Vec<Pubkey>,
+    pub bump: u8,
+}
+
+impl Denylist {
+    pub const MAX_PARTIES: usize = 128;
+    pub const SPACE: usize = 8 + 32 + 32 + 4 + 32 * Self::MAX_PARTIES + 1;
+
+    pub fn address(program_id: &Pubkey, pool: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[DENYLIST_SEED, pool.as_ref()], program_id)
+    }
+
+    pub fn is_flagged(&self, party: &Pubkey) -> bool {
+        self.parties.contains(party)
+    }
+
+    fn flag(&mut self, party: Pubkey) -> Result<()> {
+        if self.is_flagged(&party) {
+            return Ok(());
+        }
+        require!(
+            self.parties.len() < Self::MAX_PARTIES,
+            ScreenError::DenylistFull
+        );
+        self.parties.push(party);
+        Ok(())
+    }
+
+    fn unflag(&mut self, party: &Pubkey) {
+        self.parties.retain(|entry| entry != party);
+    }
+}
+
+#[event]
+pub struct PartyFlagged {
+    pub pool: Pubkey,
+    pub party: Pubkey,
+    pub flagged: bool,
+}
+
+#[error_code]
+pub enum ScreenError {
+    #[msg("E_UNKNOWN_HOOK_INSTRUCTION")]
+    UnknownInstruction,
+    #[msg("E_POOL_MISMATCH")]
+    PoolMismatch,
+    #[msg("E_DENYLIST_MISSING")]
+    DenylistMissing,
+    #[msg("E_DENYLIST_FULL")]
+    DenylistFull,
+    #[msg("E_PARTY_FLAGGED")]
+    PartyFlagged,
+}
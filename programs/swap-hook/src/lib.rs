@@ -0,0 +1,397 @@
+//! Example post-unshield hook for `ptf_pool`. After an origin-mode unshield it swaps the
+//! tokens just delivered to the destination into another mint through a mock fixed-rate
+//! AMM, so the recipient ends up holding the output mint.
+//!
+//! Everything the swap touches reaches the hook through the pool's hook accounts: the AMM
+//! and its vaults are configured as fixed `post_unshield_accounts`, and the recipient's
+//! route and token accounts as per-user account templates. Destinations opt in by opening
+//! a route and approving it as delegate on their origin token account; unshields to anyone
+//! else pass through untouched.
+
+use anchor_lang::prelude::*;
+use anchor_spl::associated_token::get_associated_token_address_with_program_id;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+use ptf_common::hooks::HookInstruction;
+
+declare_id!("AB6rASw1szLWymUBHE8cWjPJRQM5p72MeWDXHvQ5ukoc");
+
+pub const AMM_SEED: &[u8] = b"amm";
+pub const ROUTE_SEED: &[u8] = b"route";
+
+/// `UnshieldMode::Origin` as carried in `PostUnshieldHook::mode`.
+const ORIGIN_MODE: u8 = 0;
+
+/// Positions of the fixed hook accounts, in the order the pool's `post_unshield_accounts`
+/// must list them. The pool passes the hook config and pool first, so these start at
+/// account index 2; the per-user template accounts follow them.
+pub mod fixed {
+    pub const AMM: usize = 0;
+    pub const INPUT_VAULT: usize = 1;
+    pub const OUTPUT_VAULT: usize = 2;
+    pub const INPUT_MINT: usize = 3;
+    pub const OUTPUT_MINT: usize = 4;
+    pub const TOKEN_PROGRAM: usize = 5;
+    pub const COUNT: usize = 6;
+}
+
+#[program]
+pub mod ptf_swap_hook {
+    use super::*;
+
+    /// Creates a mock AMM paying `rate_num / rate_den` output tokens per input token. Both
+    /// vaults must already exist and be owned by the AMM PDA; the output vault is funded
+    /// by plain transfers.
+    pub fn initialize_amm(ctx: Context<InitializeAmm>, rate_num: u64, rate_den: u64) -> Result<()> {
+        require!(rate_num > 0 && rate_den > 0, SwapError::InvalidRate);
+        let amm = &mut ctx.accounts.amm;
+        amm.authority = ctx.accounts.authority.key();
+        amm.input_mint = ctx.accounts.input_mint.key();
+        amm.output_mint = ctx.accounts.output_mint.key();
+        amm.input_vault = ctx.accounts.input_vault.key();
+        amm.output_vault = ctx.accounts.output_vault.key();
+        amm.rate_num = rate_num;
+        amm.rate_den = rate_den;
+        amm.bump = ctx.bumps.amm;
+        Ok(())
+    }
+
+    pub fn set_rate(ctx: Context<SetRate>, rate_num: u64, rate_den: u64) -> Result<()> {
+        require!(rate_num > 0 && rate_den > 0, SwapError::InvalidRate);
+        let amm = &mut ctx.accounts.amm;
+        amm.rate_num = rate_num;
+        amm.rate_den = rate_den;
+        Ok(())
+    }
+
+    /// Opts the signer in to swapping unshields from `pool` through `amm`. The user must
+    /// also approve the route PDA as delegate on their origin token account for at least
+    /// the amounts they intend to unshield.
+    pub fn open_route(ctx: Context<OpenRoute>, pool: Pubkey) -> Result<()> {
+        let route = &mut ctx.accounts.route;
+        route.pool = pool;
+        route.user = ctx.accounts.user.key();
+        route.amm = ctx.accounts.amm.key();
+        route.bump = ctx.bumps.route;
+        Ok(())
+    }
+
+    pub fn close_route(_ctx: Context<CloseRoute>) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn fallback<'info>(
+        program_id: &Pubkey,
+        accounts: &'info [AccountInfo<'info>],
+        data: &[u8],
+    ) -> Result<()> {
+        swap_after_unshield(program_id, accounts, data)
+    }
+}
+
+/// Handles one hook call. Only origin-mode `PostUnshield` payloads to a destination with
+/// an open route are swapped; every other payload is accepted as is.
+pub fn swap_after_unshield<'info>(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo<'info>],
+    data: &[u8],
+) -> Result<()> {
+    let instruction =
+        HookInstruction::try_from_slice(data).map_err(|_| SwapError::UnknownInstruction)?;
+    let HookInstruction::PostUnshield(hook) = instruction else {
+        return Ok(());
+    };
+    if hook.mode != ORIGIN_MODE {
+        return Ok(());
+    }
+    let swap = SwapAccounts::resolve(program_id, accounts, &hook.pool, &hook.destination)?;
+    let Some(route) = swap.route(program_id)? else {
+        return Ok(());
+    };
+    let amount_out = swap.amm.quote(hook.amount)?;
+    if amount_out == 0 {
+        return Ok(());
+    }
+
+    let input_decimals =
+        Mint::try_deserialize(&mut &swap.input_mint.try_borrow_data()?[..])?.decimals;
+    let output_decimals =
+        Mint::try_deserialize(&mut &swap.output_mint.try_borrow_data()?[..])?.decimals;
+    let route_seeds: [&[u8]; 4] = [
+        ROUTE_SEED,
+        hook.pool.as_ref(),
+        hook.destination.as_ref(),
+        &[route.bump],
+    ];
+    token_interface::transfer_checked(
+        CpiContext::new_with_signer(
+            swap.token_program.clone(),
+            TransferChecked {
+                from: swap.user_input.clone(),
+                mint: swap.input_mint.clone(),
+                to: swap.input_vault.clone(),
+                authority: swap.route.clone(),
+            },
+            &[&route_seeds[..]],
+        ),
+        hook.amount,
+        input_decimals,
+    )?;
+    let amm_seeds: [&[u8]; 4] = [
+        AMM_SEED,
+        swap.amm.input_mint.as_ref(),
+        swap.amm.output_mint.as_ref(),
+        &[swap.amm.bump],
+    ];
+    token_interface::transfer_checked(
+        CpiContext::new_with_signer(
+            swap.token_program.clone(),
+            TransferChecked {
+                from: swap.output_vault.clone(),
+                mint: swap.output_mint.clone(),
+                to: swap.user_output.clone(),
+                authority: swap.amm_info.clone(),
+            },
+            &[&amm_seeds[..]],
+        ),
+        amount_out,
+        output_decimals,
+    )?;
+
+    emit!(UnshieldSwapped {
+        pool: hook.pool,
+        user: hook.destination,
+        input_mint: swap.amm.input_mint,
+        output_mint: swap.amm.output_mint,
+        amount_in: hook.amount,
+        amount_out,
+    });
+    Ok(())
+}
+
+/// The accounts one swap needs, picked out of the list the pool forwards: hook config,
+/// pool, the fixed accounts, then the template accounts for every destination.
+pub struct SwapAccounts<'a, 'info> {
+    pub amm: MockAmm,
+    pub amm_info: &'a AccountInfo<'info>,
+    pub input_vault: &'a AccountInfo<'info>,
+    pub output_vault: &'a AccountInfo<'info>,
+    pub input_mint: &'a AccountInfo<'info>,
+    pub output_mint: &'a AccountInfo<'info>,
+    pub token_program: &'a AccountInfo<'info>,
+    pub route: &'a AccountInfo<'info>,
+    pub user_input: &'a AccountInfo<'info>,
+    pub user_output: &'a AccountInfo<'info>,
+}
+
+impl<'a, 'info> SwapAccounts<'a, 'info> {
+    pub fn resolve(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'info>],
+        pool: &Pubkey,
+        user: &Pubkey,
+    ) -> Result<Self> {
+        require!(
+            accounts.len() >= 2 + fixed::COUNT,
+            SwapError::HookAccountMissing
+        );
+        require_keys_eq!(accounts[1].key(), *pool, SwapError::PoolMismatch);
+        let (fixed_accounts, user_accounts) = accounts[2..].split_at(fixed::COUNT);
+
+        let amm_info = &fixed_accounts[fixed::AMM];
+        require_keys_eq!(*amm_info.owner, *program_id, SwapError::AmmMismatch);
+        let amm = MockAmm::try_deserialize(&mut &amm_info.try_borrow_data()?[..])?;
+        let input_vault = &fixed_accounts[fixed::INPUT_VAULT];
+        let output_vault = &fixed_accounts[fixed::OUTPUT_VAULT];
+        let input_mint = &fixed_accounts[fixed::INPUT_MINT];
+        let output_mint = &fixed_accounts[fixed::OUTPUT_MINT];
+        let token_program = &fixed_accounts[fixed::TOKEN_PROGRAM];
+        require_keys_eq!(input_vault.key(), amm.input_vault, SwapError::AmmMismatch);
+        require_keys_eq!(output_vault.key(), amm.output_vault, SwapError::AmmMismatch);
+        require_keys_eq!(input_mint.key(), amm.input_mint, SwapError::AmmMismatch);
+        require_keys_eq!(output_mint.key(), amm.output_mint, SwapError::AmmMismatch);
+        require_keys_eq!(
+            *input_mint.owner,
+            token_program.key(),
+            SwapError::AmmMismatch
+        );
+
+        let find = |key: Pubkey| {
+            user_accounts
+                .iter()
+                .find(|info| info.key() == key)
+                .ok_or(error!(SwapError::HookAccountMissing))
+        };
+        let (route, _) = Route::address(program_id, pool, user);
+        let user_input = get_associated_token_address_with_program_id(
+            user,
+            &amm.input_mint,
+            &token_program.key(),
+        );
+        let user_output = get_associated_token_address_with_program_id(
+            user,
+            &amm.output_mint,
+            &token_program.key(),
+        );
+        Ok(Self {
+            route: find(route)?,
+            user_input: find(user_input)?,
+            user_output: find(user_output)?,
+            amm,
+            amm_info,
+            input_vault,
+            output_vault,
+            input_mint,
+            output_mint,
+            token_program,
+        })
+    }
+
+    /// The destination's route, or `None` when it has not opted in.
+    pub fn route(&self, program_id: &Pubkey) -> Result<Option<Route>> {
+        if self.route.owner != program_id || self.route.data_is_empty() {
+            return Ok(None);
+        }
+        let route = Route::try_deserialize(&mut &self.route.try_borrow_data()?[..])?;
+        require_keys_eq!(route.amm, self.amm_info.key(), SwapError::AmmMismatch);
+        Ok(Some(route))
+    }
+}
+
+#[derive(Accounts)]
+pub struct InitializeAmm<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        init,
+        payer = payer,
+        space = MockAmm::SPACE,
+        seeds = [AMM_SEED, input_mint.key().as_ref(), output_mint.key().as_ref()],
+        bump
+    )]
+    pub amm: Account<'info, MockAmm>,
+    pub input_mint: InterfaceAccount<'info, Mint>,
+    pub output_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        token::mint = input_mint,
+        token::authority = amm,
+        token::token_program = token_program
+    )]
+    pub input_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        token::mint = output_mint,
+        token::authority = amm,
+        token::token_program = token_program
+    )]
+    pub output_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetRate<'info> {
+    pub authority: Signer<'info>,
+    #[account(mut, has_one = authority)]
+    pub amm: Account<'info, MockAmm>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool: Pubkey)]
+pub struct OpenRoute<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub amm: Account<'info, MockAmm>,
+    #[account(
+        init,
+        payer = user,
+        space = Route::SPACE,
+        seeds = [ROUTE_SEED, pool.as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub route: Account<'info, Route>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseRoute<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        close = user,
+        seeds = [ROUTE_SEED, route.pool.as_ref(), user.key().as_ref()],
+        bump = route.bump,
+        has_one = user
+    )]
+    pub route: Account<'info, Route>,
+}
+
+/// Fixed-rate pool at `["amm", input_mint, output_mint]`. A stand-in for a real AMM: it
+/// has no curve, fees or liquidity accounting beyond its vault balances.
+#[account]
+pub struct MockAmm {
+    pub authority: Pubkey,
+    pub input_mint: Pubkey,
+    pub output_mint: Pubkey,
+    pub input_vault: Pubkey,
+    pub output_vault: Pubkey,
+    pub rate_num: u64,
+    pub rate_den: u64,
+    pub bump: u8,
+}
+
+impl MockAmm {
+    pub const SPACE: usize = 8 + 32 * 5 + 8 + 8 + 1;
+
+    pub fn quote(&self, amount_in: u64) -> Result<u64> {
+        let out = (amount_in as u128)
+            .checked_mul(self.rate_num as u128)
+            .ok_or(SwapError::MathOverflow)?
+            / self.rate_den as u128;
+        u64::try_from(out).map_err(|_| error!(SwapError::MathOverflow))
+    }
+}
+
+/// A user's opt-in at `["route", pool, user]`. The route PDA is the delegate that moves
+/// the user's origin tokens into the AMM.
+#[account]
+pub struct Route {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub amm: Pubkey,
+    pub bump: u8,
+}
+
+impl Route {
+    pub const SPACE: usize = 8 + 32 * 3 + 1;
+
+    pub fn address(program_id: &Pubkey, pool: &Pubkey, user: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[ROUTE_SEED, pool.as_ref(), user.as_ref()], program_id)
+    }
+}
+
+#[event]
+pub struct UnshieldSwapped {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub input_mint: Pubkey,
+    pub output_mint: Pubkey,
+    pub amount_in: u64,
+    pub amount_out: u64,
+}
+
+#[error_code]
+pub enum SwapError {
+    #[msg("E_UNKNOWN_HOOK_INSTRUCTION")]
+    UnknownInstruction,
+    #[msg("E_POOL_MISMATCH")]
+    PoolMismatch,
+    #[msg("E_HOOK_ACCOUNT_MISSING")]
+    HookAccountMissing,
+    #[msg("E_AMM_MISMATCH")]
+    AmmMismatch,
+    #[msg("E_INVALID_RATE")]
+    InvalidRate,
+    #[msg("E_MATH_OVERFLOW")]
+    MathOverflow,
+}
@@ -204,7 +204,8 @@ test.describe.serial('private devnet bootstrap', () => {
     expect(poolAccount).not.toBeNull();
     const poolState = poolCoder.accounts.decode('PoolState', poolAccount!.data) as any;
     const verifyingKey = new PublicKey(poolState.verifyingKey);
-    const feeBps: number = poolState.feeBps instanceof BN ? poolState.feeBps.toNumber() : poolState.feeBps;
+    const feeBps: number =
+      poolState.unshieldFeeBps instanceof BN ? poolState.unshieldFeeBps.toNumber() : poolState.unshieldFeeBps;
 
     const vaultTokenAccount = await getAssociatedTokenAddress(
       originMint,
@@ -260,7 +261,8 @@ test.describe.serial('private devnet bootstrap', () => {
       amountCommit: Array.from(toBytes32(amountCommitBytes)),
       amount: new BN(amount.toString()),
       proof: Buffer.alloc(0),
-      publicInputs: Buffer.alloc(0)
+      publicInputs: Buffer.alloc(0),
+      encryptedNote: Buffer.alloc(0)
     };
 
     const shieldKeys = [
@@ -352,8 +354,10 @@ test.describe.serial('private devnet bootstrap', () => {
       outputCommitments: [Array.from(changeCommitmentBytes)],
       outputAmountCommitments: [Array.from(changeAmountCommitmentBytes)],
       amount: new BN(amount.toString()),
+      maxFee: new BN(feeAmount.toString()),
       proof: Buffer.alloc(0),
-      publicInputs
+      publicInputs,
+      createDestinationAta: false
     };
 
     const unshieldKeys = [
@@ -7,11 +7,39 @@ use solana_sdk::{
 };
 
 pub const FACTORY_PROGRAM_ID: Pubkey = pubkey!("4z618BY2dXGqAUiegqDt8omo3e81TSdXRHt64ikX1bTy");
-const FEATURE_HOOKS_ENABLED: u8 = 0x02;
+const FEATURE_HOOKS_ENABLED: u64 = 0x02;
 const SEED_FACTORY: &[u8] = b"factory";
 const SEED_MINT_MAPPING: &[u8] = b"map";
 const SEED_TIMELOCK: &[u8] = b"timelock";
+const SEED_TIMELOCK_INDEX: &[u8] = b"timelock-index";
+const SEED_COUNCIL: &[u8] = b"council";
+const SEED_PROPOSAL: &[u8] = b"proposal";
+const SEED_PTKN_MINT: &[u8] = b"ptkn-mint";
+const SEED_NATIVE_TREASURY: &[u8] = b"native-treasury";
+const SEED_GOVERNANCE: &[u8] = b"governance";
+const SEED_ACCOUNT_GOVERNANCE: &[u8] = b"account-governance";
+const SEED_REALM_CONFIG: &[u8] = b"realm-config";
+const SEED_PROPOSAL_DEPOSIT: &[u8] = b"proposal-deposit";
+pub const GOVERNANCE_PROGRAM_ID: Pubkey = pubkey!("GovER5Lthms3bLBqWub97yVrMmEogzX7xNjdXpPPCVZw");
 const SYSTEM_PROGRAM_ID: Pubkey = pubkey!("11111111111111111111111111111111");
+pub const VAULT_PROGRAM_ID: Pubkey = pubkey!("9g6ZodQwxK8MN6MX3dbvFC3E7vGVqFtKZEHY7PByRAuh");
+pub const POOL_PROGRAM_ID: Pubkey = pubkey!("7kbUWzeTPY6qb1mFJC1ZMRmTZAdaHC27yukc3Czj7fKh");
+pub const VERIFIER_PROGRAM_ID: Pubkey = pubkey!("3aCv39mCRFH9BGJskfXqwQoWzW1ULq2yXEbEwGgKtLgg");
+pub const TOKEN_METADATA_PROGRAM_ID: Pubkey =
+    pubkey!("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
+const TOKEN_PROGRAM_ID: Pubkey = pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+const ASSOCIATED_TOKEN_PROGRAM_ID: Pubkey = pubkey!("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL");
+const SEED_VAULT: &[u8] = b"vault";
+const SEED_POOL: &[u8] = b"pool";
+const SEED_NULLIFIERS: &[u8] = b"nulls";
+const SEED_NOTES: &[u8] = b"notes";
+const SEED_TREE: &[u8] = b"tree";
+const SEED_HOOKS: &[u8] = b"hooks";
+const SEED_VERIFIER: &[u8] = b"vk";
+const SEED_PROTOCOL_CONFIG: &[u8] = b"protocol-config";
+const SEED_TEMPLATE: &[u8] = b"template";
+const SEED_REGISTRATION_BOND: &[u8] = b"bond";
+const SEED_EVENT_AUTHORITY: &[u8] = b"__event_authority";
 
 fn sighash(name: &str) -> [u8; 8] {
     let mut hasher = Sha256::new();
@@ -26,11 +54,16 @@ fn serialize_pubkey(buf: &mut Vec<u8>, key: &Pubkey) {
     buf.extend_from_slice(key.as_ref());
 }
 
-fn serialize_option_u8(buf: &mut Vec<u8>, value: Option<u8>) {
+fn serialize_string(buf: &mut Vec<u8>, value: &str) {
+    buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    buf.extend_from_slice(value.as_bytes());
+}
+
+fn serialize_option_u64(buf: &mut Vec<u8>, value: Option<u64>) {
     match value {
         Some(v) => {
             buf.push(1);
-            buf.push(v);
+            buf.extend_from_slice(&v.to_le_bytes());
         }
         None => buf.push(0),
     }
@@ -60,7 +93,7 @@ fn serialize_timelock_action(buf: &mut Vec<u8>, action: &TimelockAction) {
     match action {
         TimelockAction::SetDefaultFeatures { features } => {
             buf.push(0);
-            buf.push(*features);
+            buf.extend_from_slice(&features.to_le_bytes());
         }
         TimelockAction::UpdateMint {
             origin_mint,
@@ -69,25 +102,179 @@ fn serialize_timelock_action(buf: &mut Vec<u8>, action: &TimelockAction) {
             buf.push(1);
             serialize_pubkey(buf, origin_mint);
             serialize_option_bool(buf, params.enable_ptkn);
-            serialize_option_u8(buf, params.features);
+            serialize_option_u64(buf, params.features);
             serialize_option_u16(buf, params.fee_bps_override);
         }
         TimelockAction::PauseFactory => buf.push(2),
         TimelockAction::UnpauseFactory => buf.push(3),
+        TimelockAction::SetTimelockDelays { delays } => {
+            buf.push(4);
+            for delay in delays {
+                buf.extend_from_slice(&delay.to_le_bytes());
+            }
+        }
+        TimelockAction::SetGuardian { guardian } => {
+            buf.push(5);
+            serialize_pubkey(buf, guardian);
+        }
+        TimelockAction::SetAuthority { new_authority } => {
+            buf.push(6);
+            serialize_pubkey(buf, new_authority);
+        }
+        TimelockAction::SetTimelockSeconds { seconds } => {
+            buf.push(7);
+            buf.extend_from_slice(&seconds.to_le_bytes());
+        }
+        TimelockAction::SetDefaultFee { fee_bps } => {
+            buf.push(8);
+            buf.extend_from_slice(&fee_bps.to_le_bytes());
+        }
+        TimelockAction::SetRestrictedExecution { restricted } => {
+            buf.push(9);
+            buf.push(*restricted as u8);
+        }
+        TimelockAction::SetPoolProgram { program, allowed } => {
+            buf.push(10);
+            serialize_pubkey(buf, program);
+            buf.push(*allowed as u8);
+        }
+        TimelockAction::SetPtknSupplyCap {
+            origin_mint,
+            max_ptkn_supply,
+        } => {
+            buf.push(11);
+            serialize_pubkey(buf, origin_mint);
+            buf.extend_from_slice(&max_ptkn_supply.to_le_bytes());
+        }
+        TimelockAction::SetTreasury {
+            treasury,
+            registration_fee_lamports,
+        } => {
+            buf.push(12);
+            serialize_pubkey(buf, treasury);
+            buf.extend_from_slice(&registration_fee_lamports.to_le_bytes());
+        }
+        TimelockAction::WithdrawTreasury { amount } => {
+            buf.push(13);
+            buf.extend_from_slice(&amount.to_le_bytes());
+        }
+        TimelockAction::SetMaxDecimals { max_decimals } => {
+            buf.push(14);
+            buf.push(*max_decimals);
+        }
+        TimelockAction::PauseScopes { scopes } => {
+            buf.push(15);
+            buf.push(*scopes);
+        }
+        TimelockAction::UnpauseScopes { scopes } => {
+            buf.push(16);
+            buf.push(*scopes);
+        }
+        TimelockAction::SetOperator { operator } => {
+            buf.push(17);
+            serialize_pubkey(buf, operator);
+        }
+        TimelockAction::PinVerifyingKey {
+            origin_mint,
+            verifying_key,
+        } => {
+            buf.push(18);
+            serialize_pubkey(buf, origin_mint);
+            serialize_pubkey(buf, verifying_key);
+        }
+        TimelockAction::SetPoolFee {
+            origin_mint,
+            shield_fee_bps,
+            unshield_fee_bps,
+            shield_flat_fee,
+            unshield_flat_fee,
+        } => {
+            buf.push(19);
+            serialize_pubkey(buf, origin_mint);
+            buf.extend_from_slice(&shield_fee_bps.to_le_bytes());
+            buf.extend_from_slice(&unshield_fee_bps.to_le_bytes());
+            buf.extend_from_slice(&shield_flat_fee.to_le_bytes());
+            buf.extend_from_slice(&unshield_flat_fee.to_le_bytes());
+        }
+        TimelockAction::SetPoolFeatures {
+            origin_mint,
+            features,
+        } => {
+            buf.push(20);
+            serialize_pubkey(buf, origin_mint);
+            buf.push(*features);
+        }
+        TimelockAction::PausePool { origin_mint } => {
+            buf.push(21);
+            serialize_pubkey(buf, origin_mint);
+        }
+        TimelockAction::UnpausePool { origin_mint } => {
+            buf.push(22);
+            serialize_pubkey(buf, origin_mint);
+        }
+        TimelockAction::SetKillSwitch { engaged } => {
+            buf.push(23);
+            buf.push(*engaged as u8);
+        }
+        TimelockAction::SetProtocolMaxFee { max_fee_bps } => {
+            buf.push(24);
+            buf.extend_from_slice(&max_fee_bps.to_le_bytes());
+        }
+        TimelockAction::SetVerifierProgram { program, allowed } => {
+            buf.push(25);
+            serialize_pubkey(buf, program);
+            buf.push(*allowed as u8);
+        }
+        TimelockAction::SetRegistrationBond { bond_lamports } => {
+            buf.push(26);
+            buf.extend_from_slice(&bond_lamports.to_le_bytes());
+        }
+        TimelockAction::SetCouncil { members, threshold } => {
+            buf.push(27);
+            buf.extend_from_slice(&(members.len() as u32).to_le_bytes());
+            for member in members {
+                serialize_pubkey(buf, member);
+            }
+            buf.push(*threshold);
+        }
+        TimelockAction::DissolveCouncil { new_authority } => {
+            buf.push(28);
+            serialize_pubkey(buf, new_authority);
+        }
+        TimelockAction::SetGovernanceAuthority {
+            governance_program,
+            governance,
+        } => {
+            buf.push(29);
+            serialize_pubkey(buf, governance_program);
+            serialize_pubkey(buf, governance);
+        }
     }
 }
 
 #[derive(Clone)]
 struct UpdateMintParams {
     enable_ptkn: Option<bool>,
-    features: Option<u8>,
+    features: Option<u64>,
+    fee_bps_override: Option<u16>,
+}
+
+#[derive(Clone, Default)]
+struct RegistrationTemplateParams {
+    enable_ptkn: bool,
+    feature_flags: Option<u64>,
     fee_bps_override: Option<u16>,
+    ptkn_extensions: u8,
+    denominations: Vec<u64>,
+    pre_hook_program: Pubkey,
+    post_shield_program: Pubkey,
+    post_unshield_program: Pubkey,
 }
 
 #[derive(Clone)]
 enum TimelockAction {
     SetDefaultFeatures {
-        features: u8,
+        features: u64,
     },
     UpdateMint {
         origin_mint: Pubkey,
@@ -95,6 +282,97 @@ enum TimelockAction {
     },
     PauseFactory,
     UnpauseFactory,
+    /// Pause, unpause, features, mint, fee and authority delays, in that order.
+    SetTimelockDelays {
+        delays: [i64; 6],
+    },
+    SetGuardian {
+        guardian: Pubkey,
+    },
+    SetAuthority {
+        new_authority: Pubkey,
+    },
+    SetTimelockSeconds {
+        seconds: i64,
+    },
+    SetDefaultFee {
+        fee_bps: u16,
+    },
+    SetRestrictedExecution {
+        restricted: bool,
+    },
+    SetPoolProgram {
+        program: Pubkey,
+        allowed: bool,
+    },
+    SetPtknSupplyCap {
+        origin_mint: Pubkey,
+        max_ptkn_supply: u64,
+    },
+    SetTreasury {
+        treasury: Pubkey,
+        registration_fee_lamports: u64,
+    },
+    WithdrawTreasury {
+        amount: u64,
+    },
+    SetMaxDecimals {
+        max_decimals: u8,
+    },
+    PauseScopes {
+        scopes: u8,
+    },
+    UnpauseScopes {
+        scopes: u8,
+    },
+    SetOperator {
+        operator: Pubkey,
+    },
+    PinVerifyingKey {
+        origin_mint: Pubkey,
+        verifying_key: Pubkey,
+    },
+    SetPoolFee {
+        origin_mint: Pubkey,
+        shield_fee_bps: u16,
+        unshield_fee_bps: u16,
+        shield_flat_fee: u64,
+        unshield_flat_fee: u64,
+    },
+    SetPoolFeatures {
+        origin_mint: Pubkey,
+        features: u8,
+    },
+    PausePool {
+        origin_mint: Pubkey,
+    },
+    UnpausePool {
+        origin_mint: Pubkey,
+    },
+    SetKillSwitch {
+        engaged: bool,
+    },
+    SetProtocolMaxFee {
+        max_fee_bps: u16,
+    },
+    SetVerifierProgram {
+        program: Pubkey,
+        allowed: bool,
+    },
+    SetRegistrationBond {
+        bond_lamports: u64,
+    },
+    SetCouncil {
+        members: Vec<Pubkey>,
+        threshold: u8,
+    },
+    DissolveCouncil {
+        new_authority: Pubkey,
+    },
+    SetGovernanceAuthority {
+        governance_program: Pubkey,
+        governance: Pubkey,
+    },
 }
 
 fn initialize_factory_ix(
@@ -120,9 +398,9 @@ fn initialize_factory_ix(
     }
 }
 
-fn set_default_features_ix(factory_state: Pubkey, authority: Pubkey, features: u8) -> Instruction {
+fn set_default_features_ix(factory_state: Pubkey, authority: Pubkey, features: u64) -> Instruction {
     let mut data = sighash("set_default_features").to_vec();
-    data.push(features);
+    data.extend_from_slice(&features.to_le_bytes());
     Instruction {
         program_id: FACTORY_PROGRAM_ID,
         accounts: vec![
@@ -144,8 +422,9 @@ fn register_mint_ix(
     let mut data = sighash("register_mint").to_vec();
     data.push(decimals);
     data.push(0); // enable_ptkn = false
-    serialize_option_u8(&mut data, None);
+    serialize_option_u64(&mut data, None);
     serialize_option_u16(&mut data, None);
+    data.push(0); // ptkn_extensions
 
     Instruction {
         program_id: FACTORY_PROGRAM_ID,
@@ -162,139 +441,1443 @@ fn register_mint_ix(
             ),
             AccountMeta::new_readonly(sysvar::rent::id(), false),
             AccountMeta::new_readonly(SYSTEM_PROGRAM_ID, false),
-        ],
+        ]
+        .into_iter()
+        .chain(registration_tail(4))
+        .collect(),
         data,
     }
 }
 
-fn queue_timelock_action_ix(
+/// `register_mint` with `enable_ptkn` set, creating the pTKN mint at its factory PDA
+/// under token-2022.
+fn register_mint_with_ptkn_ix(
     factory_state: Pubkey,
     authority: Pubkey,
-    timelock_entry: Pubkey,
+    origin_mint: Pubkey,
     payer: Pubkey,
-    mint_mapping: Pubkey,
-    salt: [u8; 32],
-    action: TimelockAction,
+    decimals: u8,
+    ptkn_extensions: u8,
 ) -> Instruction {
-    let mut data = sighash("queue_timelock_action").to_vec();
-    data.extend_from_slice(&salt);
-    serialize_timelock_action(&mut data, &action);
+    let (mint_mapping, _) = mint_mapping_pda(origin_mint);
+    let (ptkn_mint, _) = ptkn_mint_pda(origin_mint);
+    let mut data = sighash("register_mint").to_vec();
+    data.push(decimals);
+    data.push(1); // enable_ptkn = true
+    serialize_option_u64(&mut data, None);
+    serialize_option_u16(&mut data, None);
+    data.push(ptkn_extensions);
 
     Instruction {
         program_id: FACTORY_PROGRAM_ID,
         accounts: vec![
             AccountMeta::new(factory_state, false),
             AccountMeta::new_readonly(authority, true),
-            AccountMeta::new(timelock_entry, false),
+            AccountMeta::new(mint_mapping, false),
+            AccountMeta::new_readonly(origin_mint, false),
             AccountMeta::new(payer, true),
+            AccountMeta::new(ptkn_mint, false),
+            AccountMeta::new_readonly(
+                Pubkey::new_from_array(spl_token_2022::id().to_bytes()),
+                false,
+            ),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
             AccountMeta::new_readonly(SYSTEM_PROGRAM_ID, false),
-            AccountMeta::new(mint_mapping, false),
+        ]
+        .into_iter()
+        .chain(registration_tail(4))
+        .collect(),
+        data,
+    }
+}
+
+fn burn_ptkn_ix(
+    factory_state: Pubkey,
+    origin_mint: Pubkey,
+    pool_authority: Pubkey,
+    source_token_account: Pubkey,
+    owner: Pubkey,
+    amount: u64,
+) -> Instruction {
+    let (mint_mapping, _) = mint_mapping_pda(origin_mint);
+    let (ptkn_mint, _) = ptkn_mint_pda(origin_mint);
+    let mut data = sighash("burn_ptkn").to_vec();
+    data.extend_from_slice(&amount.to_le_bytes());
+
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new_readonly(factory_state, false),
+            AccountMeta::new_readonly(mint_mapping, false),
+            AccountMeta::new_readonly(pool_authority, true),
+            AccountMeta::new(ptkn_mint, false),
+            AccountMeta::new(source_token_account, false),
+            AccountMeta::new_readonly(owner, true),
+            AccountMeta::new_readonly(
+                Pubkey::new_from_array(spl_token_2022::id().to_bytes()),
+                false,
+            ),
         ],
         data,
     }
 }
 
-fn execute_timelock_action_ix(
+/// `update_mint` toggling only the twin, with `ptkn_mint` omitted when `None`.
+fn update_mint_ptkn_ix(
     factory_state: Pubkey,
-    timelock_entry: Pubkey,
-    mint_mapping: Pubkey,
-    executor: Pubkey,
+    authority: Pubkey,
+    origin_mint: Pubkey,
+    enable_ptkn: bool,
+    ptkn_mint: Option<Pubkey>,
 ) -> Instruction {
+    let mut data = sighash("update_mint").to_vec();
+    serialize_option_bool(&mut data, Some(enable_ptkn));
+    serialize_option_u64(&mut data, None);
+    serialize_option_u16(&mut data, None);
+    data.push(0); // ptkn_extensions
+
     Instruction {
         program_id: FACTORY_PROGRAM_ID,
         accounts: vec![
             AccountMeta::new(factory_state, false),
-            AccountMeta::new(timelock_entry, false),
-            AccountMeta::new(mint_mapping, false),
-            AccountMeta::new(mint_mapping, false),
+            AccountMeta::new_readonly(authority, true),
+            AccountMeta::new(mint_mapping_pda(origin_mint).0, false),
+            match ptkn_mint {
+                Some(mint) => AccountMeta::new(mint, false),
+                None => AccountMeta::new_readonly(FACTORY_PROGRAM_ID, false),
+            },
             AccountMeta::new_readonly(
                 Pubkey::new_from_array(spl_token_2022::id().to_bytes()),
                 false,
             ),
-            AccountMeta::new(executor, true),
             AccountMeta::new_readonly(sysvar::rent::id(), false),
         ],
-        data: sighash("execute_timelock_action").to_vec(),
+        data,
     }
 }
 
-pub fn factory_state_pda() -> (Pubkey, u8) {
-    Pubkey::find_program_address(
-        &[SEED_FACTORY, FACTORY_PROGRAM_ID.as_ref()],
-        &FACTORY_PROGRAM_ID,
-    )
-}
+fn set_ptkn_supply_cap_ix(
+    factory_state: Pubkey,
+    authority: Pubkey,
+    mint_mapping: Pubkey,
+    max_ptkn_supply: u64,
+) -> Instruction {
+    let mut data = sighash("set_ptkn_supply_cap").to_vec();
+    data.extend_from_slice(&max_ptkn_supply.to_le_bytes());
 
-pub fn mint_mapping_pda(origin_mint: Pubkey) -> (Pubkey, u8) {
-    Pubkey::find_program_address(
-        &[SEED_MINT_MAPPING, origin_mint.as_ref()],
-        &FACTORY_PROGRAM_ID,
-    )
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+            AccountMeta::new(mint_mapping, false),
+        ],
+        data,
+    }
 }
 
-pub fn timelock_entry_pda(factory_state: Pubkey, salt: &[u8; 32]) -> (Pubkey, u8) {
-    Pubkey::find_program_address(
-        &[SEED_TIMELOCK, factory_state.as_ref(), salt.as_ref()],
-        &FACTORY_PROGRAM_ID,
-    )
-}
+fn migrate_mint_mapping_ix(origin_mint: Pubkey, payer: Pubkey) -> Instruction {
+    let mut data = sighash("migrate_mint_mapping").to_vec();
+    serialize_pubkey(&mut data, &origin_mint);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use anchor_lang::{AccountDeserialize, AccountSerialize};
-    use ptf_factory::FactoryError;
-    use solana_program_test::{BanksClientError, ProgramTest};
-    use solana_sdk::{
-        account::AccountSharedData,
-        instruction::{AccountMeta, Instruction},
-        pubkey,
-        pubkey::Pubkey,
-        signature::Signer,
-        signer::keypair::Keypair,
-        sysvar,
-        transaction::{Transaction, TransactionError},
-    };
-    use std::{env, path::PathBuf};
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(mint_mapping_pda(origin_mint).0, false),
+            AccountMeta::new(payer, true),
+            AccountMeta::new_readonly(SYSTEM_PROGRAM_ID, false),
+        ],
+        data,
+    }
+}
 
-    const DEFAULT_FEE_BPS: u16 = 5;
-    const TIMELOCK_SECS: i64 = 5;
+fn migrate_factory_state_ix(payer: Pubkey) -> Instruction {
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(factory_state_pda().0, false),
+            AccountMeta::new(payer, true),
+            AccountMeta::new_readonly(SYSTEM_PROGRAM_ID, false),
+        ],
+        data: sighash("migrate_factory_state").to_vec(),
+    }
+}
 
-    const FACTORY_SO: &str = "ptf_factory.so";
+fn set_treasury_ix(
+    factory_state: Pubkey,
+    authority: Pubkey,
+    treasury: Pubkey,
+    registration_fee_lamports: u64,
+) -> Instruction {
+    let mut data = sighash("set_treasury").to_vec();
+    serialize_pubkey(&mut data, &treasury);
+    data.extend_from_slice(&registration_fee_lamports.to_le_bytes());
 
-    fn artifact_path(filename: &str) -> PathBuf {
-        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-            .join("..")
-            .join("..")
-            .join("target")
-            .join("deploy")
-            .join(filename)
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+        ],
+        data,
     }
+}
 
-    fn program_test() -> ProgramTest {
-        let so_path = artifact_path(FACTORY_SO);
-        assert!(
-            so_path.exists(),
-            "{} missing. Run `anchor build` so the `.so` artifact is present at {}",
-            FACTORY_SO,
-            so_path.display()
-        );
-        if let Some(dir) = so_path.parent() {
-            let canonical = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
-            env::set_var("BPF_OUT_DIR", &canonical);
-            env::set_var("SBF_OUT_DIR", &canonical);
-        }
-        let mut test = ProgramTest::default();
-        test.add_program("ptf_factory", FACTORY_PROGRAM_ID, None);
-        test
+fn set_registration_bond_ix(
+    factory_state: Pubkey,
+    authority: Pubkey,
+    bond_lamports: u64,
+) -> Instruction {
+    let mut data = sighash("set_registration_bond").to_vec();
+    data.extend_from_slice(&bond_lamports.to_le_bytes());
+
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+        ],
+        data,
     }
+}
 
-    async fn process_instruction(
-        context: &mut solana_program_test::ProgramTestContext,
-        instruction: Instruction,
-        additional_signers: &[&Keypair],
-    ) -> Result<(), BanksClientError> {
-        let mut tx = Transaction::new_with_payer(&[instruction], Some(&context.payer.pubkey()));
+fn register_mint_bonded_ix(
+    factory_state: Pubkey,
+    registrant: Pubkey,
+    origin_mint: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(factory_state, false),
+            AccountMeta::new(registrant, true),
+            AccountMeta::new(mint_mapping_pda(origin_mint).0, false),
+            AccountMeta::new_readonly(origin_mint, false),
+            AccountMeta::new(registration_bond_pda(origin_mint).0, false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(SYSTEM_PROGRAM_ID, false),
+        ]
+        .into_iter()
+        .chain(registration_tail(1))
+        .collect(),
+        data: sighash("register_mint_bonded").to_vec(),
+    }
+}
+
+/// `refund_registration_bond`, or `slash_registration_bond` when `slash` is set.
+fn resolve_registration_bond_ix(
+    factory_state: Pubkey,
+    authority: Pubkey,
+    origin_mint: Pubkey,
+    registrant: Pubkey,
+    slash: bool,
+) -> Instruction {
+    let name = if slash {
+        "slash_registration_bond"
+    } else {
+        "refund_registration_bond"
+    };
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+            AccountMeta::new(registration_bond_pda(origin_mint).0, false),
+            AccountMeta::new(registrant, false),
+            AccountMeta::new(mint_mapping_pda(origin_mint).0, false),
+        ],
+        data: sighash(name).to_vec(),
+    }
+}
+
+fn set_max_decimals_ix(factory_state: Pubkey, authority: Pubkey, max_decimals: u8) -> Instruction {
+    let mut data = sighash("set_max_decimals").to_vec();
+    data.push(max_decimals);
+
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+        ],
+        data,
+    }
+}
+
+fn withdraw_treasury_ix(
+    factory_state: Pubkey,
+    authority: Pubkey,
+    treasury: Pubkey,
+    amount: u64,
+) -> Instruction {
+    let mut data = sighash("withdraw_treasury").to_vec();
+    data.extend_from_slice(&amount.to_le_bytes());
+
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+            AccountMeta::new(treasury, false),
+        ],
+        data,
+    }
+}
+
+fn freeze_mapping_ix(
+    factory_state: Pubkey,
+    authority: Pubkey,
+    mint_mapping: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+            AccountMeta::new(mint_mapping, false),
+        ],
+        data: sighash("freeze_mapping").to_vec(),
+    }
+}
+
+fn thaw_mapping_ix(factory_state: Pubkey, authority: Pubkey, mint_mapping: Pubkey) -> Instruction {
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+            AccountMeta::new(mint_mapping, false),
+        ],
+        data: sighash("thaw_mapping").to_vec(),
+    }
+}
+
+fn close_mint_mapping_ix(
+    factory_state: Pubkey,
+    authority: Pubkey,
+    origin_mint: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new_readonly(factory_state, false),
+            AccountMeta::new(authority, true),
+            AccountMeta::new(mint_mapping_pda(origin_mint).0, false),
+            AccountMeta::new_readonly(deploy_accounts(origin_mint).pool_state, false),
+            AccountMeta::new_readonly(registration_bond_pda(origin_mint).0, false),
+        ],
+        data: sighash("close_mint_mapping").to_vec(),
+    }
+}
+
+fn link_pool_ix(
+    factory_state: Pubkey,
+    authority: Pubkey,
+    origin_mint: Pubkey,
+    pool_state: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new_readonly(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+            AccountMeta::new(mint_mapping_pda(origin_mint).0, false),
+            AccountMeta::new_readonly(pool_state, false),
+        ],
+        data: sighash("link_pool").to_vec(),
+    }
+}
+
+pub fn verifying_key_pda(circuit_tag: &[u8; 32], version: u8) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[SEED_VERIFIER, circuit_tag.as_ref(), &[version]],
+        &VERIFIER_PROGRAM_ID,
+    )
+}
+
+fn register_verifying_key_ix(
+    factory_state: Pubkey,
+    authority: Pubkey,
+    payer: Pubkey,
+    circuit_tag: [u8; 32],
+    version: u8,
+    verifying_key_data: &[u8],
+) -> Instruction {
+    let hash: [u8; 32] = sha3::Keccak256::digest(verifying_key_data).into();
+    let mut data = sighash("register_verifying_key").to_vec();
+    data.extend_from_slice(&circuit_tag);
+    data.extend_from_slice(&[version; 32]); // verifying_key_id
+    data.extend_from_slice(&hash);
+    data.push(version);
+    data.extend_from_slice(&(verifying_key_data.len() as u32).to_le_bytes());
+    data.extend_from_slice(verifying_key_data);
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new_readonly(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+            AccountMeta::new(verifying_key_pda(&circuit_tag, version).0, false),
+            AccountMeta::new(payer, true),
+            AccountMeta::new_readonly(VERIFIER_PROGRAM_ID, false),
+            AccountMeta::new_readonly(SYSTEM_PROGRAM_ID, false),
+        ],
+        data,
+    }
+}
+
+fn pin_verifying_key_ix(
+    factory_state: Pubkey,
+    authority: Pubkey,
+    mint_mapping: Pubkey,
+    verifying_key: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new_readonly(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+            AccountMeta::new(mint_mapping, false),
+            AccountMeta::new_readonly(verifying_key, false),
+        ],
+        data: sighash("pin_verifying_key").to_vec(),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_ptkn_metadata_ix(
+    factory_state: Pubkey,
+    authority: Pubkey,
+    origin_mint: Pubkey,
+    ptkn_mint: Pubkey,
+    payer: Pubkey,
+    name: &str,
+    symbol: &str,
+    uri: &str,
+) -> Instruction {
+    let mut data = sighash("update_ptkn_metadata").to_vec();
+    serialize_string(&mut data, name);
+    serialize_string(&mut data, symbol);
+    serialize_string(&mut data, uri);
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new_readonly(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+            AccountMeta::new_readonly(mint_mapping_pda(origin_mint).0, false),
+            AccountMeta::new_readonly(ptkn_mint, false),
+            AccountMeta::new(token_metadata_pda(ptkn_mint), false),
+            AccountMeta::new(payer, true),
+            AccountMeta::new_readonly(TOKEN_METADATA_PROGRAM_ID, false),
+            AccountMeta::new_readonly(SYSTEM_PROGRAM_ID, false),
+        ],
+        data,
+    }
+}
+
+/// Accounts `register_and_deploy` creates for one origin mint.
+pub struct DeployAccounts {
+    pub vault_state: Pubkey,
+    pub vault_token_account: Pubkey,
+    pub pool_state: Pubkey,
+    pub nullifier_set: Pubkey,
+    pub note_ledger: Pubkey,
+    pub commitment_tree: Pubkey,
+    pub hook_config: Pubkey,
+}
+
+pub fn deploy_accounts(origin_mint: Pubkey) -> DeployAccounts {
+    let pool_pda = |seed: &[u8]| {
+        Pubkey::find_program_address(&[seed, origin_mint.as_ref()], &POOL_PROGRAM_ID).0
+    };
+    let (vault_state, _) =
+        Pubkey::find_program_address(&[SEED_VAULT, origin_mint.as_ref()], &VAULT_PROGRAM_ID);
+    let (vault_token_account, _) = Pubkey::find_program_address(
+        &[
+            vault_state.as_ref(),
+            TOKEN_PROGRAM_ID.as_ref(),
+            origin_mint.as_ref(),
+        ],
+        &ASSOCIATED_TOKEN_PROGRAM_ID,
+    );
+    DeployAccounts {
+        vault_state,
+        vault_token_account,
+        pool_state: pool_pda(SEED_POOL),
+        nullifier_set: pool_pda(SEED_NULLIFIERS),
+        note_ledger: pool_pda(SEED_NOTES),
+        commitment_tree: pool_pda(SEED_TREE),
+        hook_config: pool_pda(SEED_HOOKS),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn register_and_deploy_ix(
+    factory_state: Pubkey,
+    authority: Pubkey,
+    mint_mapping: Pubkey,
+    origin_mint: Pubkey,
+    payer: Pubkey,
+    verifying_key: Pubkey,
+    deploy: &DeployAccounts,
+    merkle_depth: u8,
+    factory_governed: bool,
+) -> Instruction {
+    let mut data = sighash("register_and_deploy").to_vec();
+    data.push(0); // enable_ptkn = false
+    serialize_option_u64(&mut data, None);
+    serialize_option_u16(&mut data, None);
+    data.extend_from_slice(&5u16.to_le_bytes()); // shield_fee_bps
+    data.push(0); // pool_features
+    data.push(merkle_depth);
+    data.push(factory_governed as u8);
+
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+            AccountMeta::new(mint_mapping, false),
+            AccountMeta::new_readonly(origin_mint, false),
+            AccountMeta::new_readonly(FACTORY_PROGRAM_ID, false), // no ptkn_mint
+            AccountMeta::new(deploy.vault_state, false),
+            AccountMeta::new(deploy.vault_token_account, false),
+            AccountMeta::new(deploy.pool_state, false),
+            AccountMeta::new(deploy.nullifier_set, false),
+            AccountMeta::new(deploy.note_ledger, false),
+            AccountMeta::new(deploy.commitment_tree, false),
+            AccountMeta::new(deploy.hook_config, false),
+            AccountMeta::new_readonly(VERIFIER_PROGRAM_ID, false),
+            AccountMeta::new_readonly(verifying_key, false),
+            AccountMeta::new(payer, true),
+            AccountMeta::new_readonly(VAULT_PROGRAM_ID, false),
+            AccountMeta::new_readonly(POOL_PROGRAM_ID, false),
+            AccountMeta::new_readonly(TOKEN_PROGRAM_ID, false),
+            AccountMeta::new_readonly(ASSOCIATED_TOKEN_PROGRAM_ID, false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(SYSTEM_PROGRAM_ID, false),
+        ]
+        .into_iter()
+        .chain(registration_tail(1))
+        .collect(),
+        data,
+    }
+}
+
+/// Placeholders for the `optional` trailing accounts a registration leaves out, then
+/// the event authority and the factory program its `emit_cpi!` events need.
+fn registration_tail(optional: usize) -> Vec<AccountMeta> {
+    let mut accounts = vec![AccountMeta::new_readonly(FACTORY_PROGRAM_ID, false); optional];
+    accounts.push(AccountMeta::new_readonly(event_authority_pda().0, false));
+    accounts.push(AccountMeta::new_readonly(FACTORY_PROGRAM_ID, false));
+    accounts
+}
+
+/// Points a registration built with [`registration_tail`] at `template`, the last
+/// optional account.
+fn set_template_account(ix: &mut Instruction, template: Pubkey) {
+    let index = ix.accounts.len() - 3;
+    ix.accounts[index] = AccountMeta::new_readonly(template, false);
+}
+
+fn set_registration_template_ix(
+    factory_state: Pubkey,
+    authority: Pubkey,
+    payer: Pubkey,
+    template_id: u32,
+    name: [u8; 32],
+    params: &RegistrationTemplateParams,
+) -> Instruction {
+    let mut data = sighash("set_registration_template").to_vec();
+    data.extend_from_slice(&template_id.to_le_bytes());
+    data.extend_from_slice(&name);
+    data.push(params.enable_ptkn as u8);
+    serialize_option_u64(&mut data, params.feature_flags);
+    serialize_option_u16(&mut data, params.fee_bps_override);
+    data.push(params.ptkn_extensions);
+    data.extend_from_slice(&(params.denominations.len() as u32).to_le_bytes());
+    for amount in &params.denominations {
+        data.extend_from_slice(&amount.to_le_bytes());
+    }
+    serialize_pubkey(&mut data, &params.pre_hook_program);
+    serialize_pubkey(&mut data, &params.post_shield_program);
+    serialize_pubkey(&mut data, &params.post_unshield_program);
+
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new_readonly(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+            AccountMeta::new(registration_template_pda(template_id).0, false),
+            AccountMeta::new(payer, true),
+            AccountMeta::new_readonly(SYSTEM_PROGRAM_ID, false),
+        ],
+        data,
+    }
+}
+
+fn close_registration_template_ix(
+    factory_state: Pubkey,
+    authority: Pubkey,
+    template_id: u32,
+) -> Instruction {
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new_readonly(factory_state, false),
+            AccountMeta::new(authority, true),
+            AccountMeta::new(registration_template_pda(template_id).0, false),
+        ],
+        data: sighash("close_registration_template").to_vec(),
+    }
+}
+
+fn queue_timelock_action_ix(
+    factory_state: Pubkey,
+    authority: Pubkey,
+    timelock_entry: Pubkey,
+    payer: Pubkey,
+    mint_mapping: Pubkey,
+    salt: [u8; 32],
+    action: TimelockAction,
+) -> Instruction {
+    let mut data = sighash("queue_timelock_action").to_vec();
+    data.extend_from_slice(&salt);
+    serialize_timelock_action(&mut data, &action);
+
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+            AccountMeta::new(timelock_entry, false),
+            AccountMeta::new(payer, true),
+            AccountMeta::new_readonly(SYSTEM_PROGRAM_ID, false),
+            AccountMeta::new(mint_mapping, false),
+            AccountMeta::new(timelock_index_pda(factory_state).0, false),
+        ],
+        data,
+    }
+}
+
+fn execute_timelock_action_ix(
+    factory_state: Pubkey,
+    timelock_entry: Pubkey,
+    mint_mapping: Pubkey,
+    ptkn_mint: Pubkey,
+    executor: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(factory_state, false),
+            AccountMeta::new(timelock_entry, false),
+            AccountMeta::new(mint_mapping, false),
+            AccountMeta::new(ptkn_mint, false),
+            AccountMeta::new_readonly(
+                Pubkey::new_from_array(spl_token_2022::id().to_bytes()),
+                false,
+            ),
+            AccountMeta::new(executor, true),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new(timelock_index_pda(factory_state).0, false),
+        ],
+        data: sighash("execute_timelock_action").to_vec(),
+    }
+}
+
+fn cancel_timelock_action_ix(
+    factory_state: Pubkey,
+    authority: Pubkey,
+    timelock_entry: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(factory_state, false),
+            AccountMeta::new(authority, true),
+            AccountMeta::new(timelock_entry, false),
+            AccountMeta::new(timelock_index_pda(factory_state).0, false),
+        ],
+        data: sighash("cancel_timelock_action").to_vec(),
+    }
+}
+
+fn close_expired_timelock_action_ix(
+    factory_state: Pubkey,
+    timelock_entry: Pubkey,
+    closer: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new_readonly(factory_state, false),
+            AccountMeta::new(timelock_entry, false),
+            AccountMeta::new(timelock_index_pda(factory_state).0, false),
+            AccountMeta::new(closer, true),
+        ],
+        data: sighash("close_expired_timelock_action").to_vec(),
+    }
+}
+
+pub fn index_timelock_entry_ix(
+    factory_state: Pubkey,
+    timelock_entry: Pubkey,
+    payer: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new_readonly(factory_state, false),
+            AccountMeta::new_readonly(timelock_entry, false),
+            AccountMeta::new(timelock_index_pda(factory_state).0, false),
+            AccountMeta::new(payer, true),
+            AccountMeta::new_readonly(SYSTEM_PROGRAM_ID, false),
+        ],
+        data: sighash("index_timelock_entry").to_vec(),
+    }
+}
+
+fn set_guardian_ix(factory_state: Pubkey, authority: Pubkey, guardian: Pubkey) -> Instruction {
+    let mut data = sighash("set_guardian").to_vec();
+    serialize_pubkey(&mut data, &guardian);
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+        ],
+        data,
+    }
+}
+
+fn set_operator_ix(factory_state: Pubkey, authority: Pubkey, operator: Pubkey) -> Instruction {
+    let mut data = sighash("set_operator").to_vec();
+    serialize_pubkey(&mut data, &operator);
+
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+        ],
+        data,
+    }
+}
+
+fn set_restricted_execution_ix(
+    factory_state: Pubkey,
+    authority: Pubkey,
+    restricted: bool,
+) -> Instruction {
+    let mut data = sighash("set_restricted_execution").to_vec();
+    data.push(restricted as u8);
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+        ],
+        data,
+    }
+}
+
+fn set_pool_program_ix(
+    factory_state: Pubkey,
+    authority: Pubkey,
+    program: Pubkey,
+    allowed: bool,
+) -> Instruction {
+    let mut data = sighash("set_pool_program").to_vec();
+    serialize_pubkey(&mut data, &program);
+    data.push(allowed as u8);
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+        ],
+        data,
+    }
+}
+
+fn initialize_protocol_config_ix(
+    factory_state: Pubkey,
+    authority: Pubkey,
+    payer: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new_readonly(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+            AccountMeta::new(protocol_config_pda().0, false),
+            AccountMeta::new(payer, true),
+            AccountMeta::new_readonly(SYSTEM_PROGRAM_ID, false),
+        ],
+        data: sighash("initialize_protocol_config").to_vec(),
+    }
+}
+
+fn set_kill_switch_ix(factory_state: Pubkey, authority: Pubkey, engaged: bool) -> Instruction {
+    let mut data = sighash("set_kill_switch").to_vec();
+    data.push(engaged as u8);
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new_readonly(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+            AccountMeta::new(protocol_config_pda().0, false),
+        ],
+        data,
+    }
+}
+
+fn pause_ix(factory_state: Pubkey, authority: Pubkey, unpause: bool) -> Instruction {
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+        ],
+        data: sighash(if unpause { "unpause" } else { "pause" }).to_vec(),
+    }
+}
+
+fn pause_scopes_ix(
+    factory_state: Pubkey,
+    authority: Pubkey,
+    scopes: u8,
+    unpause: bool,
+) -> Instruction {
+    let mut data = sighash(if unpause {
+        "unpause_scopes"
+    } else {
+        "pause_scopes"
+    })
+    .to_vec();
+    data.push(scopes);
+
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+        ],
+        data,
+    }
+}
+
+fn set_council_ix(
+    factory_state: Pubkey,
+    authority: Pubkey,
+    members: &[Pubkey],
+    threshold: u8,
+) -> Instruction {
+    let mut data = sighash("set_council").to_vec();
+    data.extend_from_slice(&(members.len() as u32).to_le_bytes());
+    for member in members {
+        serialize_pubkey(&mut data, member);
+    }
+    data.push(threshold);
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+        ],
+        data,
+    }
+}
+
+fn set_governance_authority_ix(
+    factory_state: Pubkey,
+    authority: Pubkey,
+    governance_program: Pubkey,
+    governance: Pubkey,
+) -> Instruction {
+    let mut data = sighash("set_governance_authority").to_vec();
+    serialize_pubkey(&mut data, &governance_program);
+    serialize_pubkey(&mut data, &governance);
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(factory_state, false),
+            AccountMeta::new_readonly(authority, true),
+        ],
+        data,
+    }
+}
+
+fn create_council_proposal_ix(
+    factory_state: Pubkey,
+    proposer: Pubkey,
+    proposal: Pubkey,
+    inner: &Instruction,
+) -> Instruction {
+    let mut data = sighash("create_council_proposal").to_vec();
+    data.extend_from_slice(&(inner.accounts.len() as u32).to_le_bytes());
+    for meta in &inner.accounts {
+        serialize_pubkey(&mut data, &meta.pubkey);
+        data.push(meta.is_signer as u8);
+        data.push(meta.is_writable as u8);
+    }
+    data.extend_from_slice(&(inner.data.len() as u32).to_le_bytes());
+    data.extend_from_slice(&inner.data);
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(factory_state, false),
+            AccountMeta::new(proposer, true),
+            AccountMeta::new(proposal, false),
+            AccountMeta::new_readonly(SYSTEM_PROGRAM_ID, false),
+        ],
+        data,
+    }
+}
+
+fn approve_council_proposal_ix(
+    factory_state: Pubkey,
+    member: Pubkey,
+    proposal: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new_readonly(factory_state, false),
+            AccountMeta::new_readonly(member, true),
+            AccountMeta::new(proposal, false),
+        ],
+        data: sighash("approve_council_proposal").to_vec(),
+    }
+}
+
+/// `inner` is the proposed instruction; its accounts other than the council are passed
+/// as remaining accounts.
+fn execute_council_proposal_ix(
+    factory_state: Pubkey,
+    proposal: Pubkey,
+    executor: Pubkey,
+    inner: &Instruction,
+) -> Instruction {
+    let (council, _) = council_pda(factory_state);
+    let mut accounts = vec![
+        AccountMeta::new_readonly(factory_state, false),
+        AccountMeta::new_readonly(council, false),
+        AccountMeta::new(proposal, false),
+        AccountMeta::new_readonly(executor, true),
+        AccountMeta::new_readonly(FACTORY_PROGRAM_ID, false),
+    ];
+    accounts.extend(
+        inner
+            .accounts
+            .iter()
+            .filter(|meta| meta.pubkey != council)
+            .map(|meta| AccountMeta {
+                pubkey: meta.pubkey,
+                is_signer: meta.is_signer,
+                is_writable: meta.is_writable,
+            }),
+    );
+    Instruction {
+        program_id: FACTORY_PROGRAM_ID,
+        accounts,
+        data: sighash("execute_council_proposal").to_vec(),
+    }
+}
+
+pub fn factory_state_pda() -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[SEED_FACTORY, FACTORY_PROGRAM_ID.as_ref()],
+        &FACTORY_PROGRAM_ID,
+    )
+}
+
+pub fn registration_template_pda(template_id: u32) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[SEED_TEMPLATE, template_id.to_le_bytes().as_ref()],
+        &FACTORY_PROGRAM_ID,
+    )
+}
+
+pub fn registration_bond_pda(origin_mint: Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[SEED_REGISTRATION_BOND, origin_mint.as_ref()],
+        &FACTORY_PROGRAM_ID,
+    )
+}
+
+pub fn event_authority_pda() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[SEED_EVENT_AUTHORITY], &FACTORY_PROGRAM_ID)
+}
+
+pub fn protocol_config_pda() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[SEED_PROTOCOL_CONFIG], &FACTORY_PROGRAM_ID)
+}
+
+pub fn mint_mapping_pda(origin_mint: Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[SEED_MINT_MAPPING, origin_mint.as_ref()],
+        &FACTORY_PROGRAM_ID,
+    )
+}
+
+pub fn timelock_entry_pda(factory_state: Pubkey, salt: &[u8; 32]) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[SEED_TIMELOCK, factory_state.as_ref(), salt.as_ref()],
+        &FACTORY_PROGRAM_ID,
+    )
+}
+
+pub fn council_pda(factory_state: Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[SEED_COUNCIL, factory_state.as_ref()], &FACTORY_PROGRAM_ID)
+}
+
+pub fn timelock_index_pda(factory_state: Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[SEED_TIMELOCK_INDEX, factory_state.as_ref()],
+        &FACTORY_PROGRAM_ID,
+    )
+}
+
+pub fn native_treasury_pda(governance: Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[SEED_NATIVE_TREASURY, governance.as_ref()],
+        &GOVERNANCE_PROGRAM_ID,
+    )
+}
+
+/// SPL Governance accounts for a single-voter realm whose one community token holder
+/// decides every proposal.
+pub struct GovernanceAccounts {
+    pub realm: Pubkey,
+    pub community_mint: Pubkey,
+    pub voter: Pubkey,
+    pub token_owner_record: Pubkey,
+    pub governance: Pubkey,
+    pub native_treasury: Pubkey,
+}
+
+pub fn governance_accounts(
+    realm_name: &str,
+    community_mint: Pubkey,
+    voter: Pubkey,
+    governance_seed: Pubkey,
+) -> GovernanceAccounts {
+    let realm = Pubkey::find_program_address(
+        &[SEED_GOVERNANCE, realm_name.as_bytes()],
+        &GOVERNANCE_PROGRAM_ID,
+    )
+    .0;
+    let token_owner_record = Pubkey::find_program_address(
+        &[
+            SEED_GOVERNANCE,
+            realm.as_ref(),
+            community_mint.as_ref(),
+            voter.as_ref(),
+        ],
+        &GOVERNANCE_PROGRAM_ID,
+    )
+    .0;
+    let governance = Pubkey::find_program_address(
+        &[
+            SEED_ACCOUNT_GOVERNANCE,
+            realm.as_ref(),
+            governance_seed.as_ref(),
+        ],
+        &GOVERNANCE_PROGRAM_ID,
+    )
+    .0;
+    GovernanceAccounts {
+        realm,
+        community_mint,
+        voter,
+        token_owner_record,
+        governance,
+        native_treasury: native_treasury_pda(governance).0,
+    }
+}
+
+fn governance_realm_config_pda(realm: Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[SEED_REALM_CONFIG, realm.as_ref()], &GOVERNANCE_PROGRAM_ID).0
+}
+
+fn governance_token_holding_pda(realm: Pubkey, mint: Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[SEED_GOVERNANCE, realm.as_ref(), mint.as_ref()],
+        &GOVERNANCE_PROGRAM_ID,
+    )
+    .0
+}
+
+pub fn governance_proposal_pda(accounts: &GovernanceAccounts, proposal_seed: Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            SEED_GOVERNANCE,
+            accounts.governance.as_ref(),
+            accounts.community_mint.as_ref(),
+            proposal_seed.as_ref(),
+        ],
+        &GOVERNANCE_PROGRAM_ID,
+    )
+    .0
+}
+
+/// The proposal's only transaction, at option 0 and index 0.
+fn governance_proposal_transaction_pda(proposal: Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            SEED_GOVERNANCE,
+            proposal.as_ref(),
+            &0u8.to_le_bytes(),
+            &0u16.to_le_bytes(),
+        ],
+        &GOVERNANCE_PROGRAM_ID,
+    )
+    .0
+}
+
+/// `CreateRealm` for a liquid community token with no council, where holding the whole
+/// supply is enough to create governances.
+pub fn governance_create_realm_ix(
+    accounts: &GovernanceAccounts,
+    realm_name: &str,
+    payer: Pubkey,
+) -> Instruction {
+    let mut data = vec![0u8];
+    serialize_string(&mut data, realm_name);
+    data.push(0); // use_council_mint
+    data.extend_from_slice(&1u64.to_le_bytes()); // min_community_weight_to_create_governance
+    data.push(0); // MintMaxVoterWeightSource::SupplyFraction
+    data.extend_from_slice(&10_000_000_000u64.to_le_bytes());
+    for _ in 0..2 {
+        // GoverningTokenConfigArgs: no voter weight addins, liquid token.
+        data.extend_from_slice(&[0, 0, 0]);
+    }
+    Instruction {
+        program_id: GOVERNANCE_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(accounts.realm, false),
+            AccountMeta::new_readonly(payer, false),
+            AccountMeta::new_readonly(accounts.community_mint, false),
+            AccountMeta::new(
+                governance_token_holding_pda(accounts.realm, accounts.community_mint),
+                false,
+            ),
+            AccountMeta::new(payer, true),
+            AccountMeta::new_readonly(SYSTEM_PROGRAM_ID, false),
+            AccountMeta::new_readonly(TOKEN_PROGRAM_ID, false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new(governance_realm_config_pda(accounts.realm), false),
+        ],
+        data,
+    }
+}
+
+/// `DepositGoverningTokens` from the voter's token account; the voter also pays.
+pub fn governance_deposit_ix(
+    accounts: &GovernanceAccounts,
+    source: Pubkey,
+    amount: u64,
+) -> Instruction {
+    let mut data = vec![1u8];
+    data.extend_from_slice(&amount.to_le_bytes());
+    Instruction {
+        program_id: GOVERNANCE_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new_readonly(accounts.realm, false),
+            AccountMeta::new(
+                governance_token_holding_pda(accounts.realm, accounts.community_mint),
+                false,
+            ),
+            AccountMeta::new(source, false),
+            AccountMeta::new_readonly(accounts.voter, true),
+            AccountMeta::new_readonly(accounts.voter, true),
+            AccountMeta::new(accounts.token_owner_record, false),
+            AccountMeta::new(accounts.voter, true),
+            AccountMeta::new_readonly(SYSTEM_PROGRAM_ID, false),
+            AccountMeta::new_readonly(TOKEN_PROGRAM_ID, false),
+            AccountMeta::new_readonly(governance_realm_config_pda(accounts.realm), false),
+        ],
+        data,
+    }
+}
+
+/// `CreateGovernance` with a 60% community yes threshold, strict tipping and no hold-up,
+/// so a unanimous vote succeeds and executes at once.
+pub fn governance_create_ix(accounts: &GovernanceAccounts, governance_seed: Pubkey) -> Instruction {
+    let mut data = vec![4u8];
+    data.extend_from_slice(&[0, 60]); // community_vote_threshold: YesVotePercentage(60)
+    data.extend_from_slice(&1u64.to_le_bytes()); // min_community_weight_to_create_proposal
+    data.extend_from_slice(&0u32.to_le_bytes()); // transactions_hold_up_time
+    data.extend_from_slice(&3_600u32.to_le_bytes()); // voting_base_time
+    data.push(0); // community_vote_tipping: Strict
+    data.push(2); // council_vote_threshold: Disabled
+    data.push(2); // council_veto_vote_threshold: Disabled
+    data.extend_from_slice(&1u64.to_le_bytes()); // min_council_weight_to_create_proposal
+    data.push(0); // council_vote_tipping: Strict
+    data.push(2); // community_veto_vote_threshold: Disabled
+    data.extend_from_slice(&0u32.to_le_bytes()); // voting_cool_off_time
+    data.push(10); // deposit_exempt_proposal_count
+    Instruction {
+        program_id: GOVERNANCE_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new_readonly(accounts.realm, false),
+            AccountMeta::new(accounts.governance, false),
+            AccountMeta::new_readonly(governance_seed, false),
+            AccountMeta::new_readonly(accounts.token_owner_record, false),
+            AccountMeta::new(accounts.voter, true),
+            AccountMeta::new_readonly(SYSTEM_PROGRAM_ID, false),
+            AccountMeta::new_readonly(accounts.voter, true),
+            AccountMeta::new_readonly(governance_realm_config_pda(accounts.realm), false),
+        ],
+        data,
+    }
+}
+
+/// `CreateProposal` for a single-choice proposal owned and paid for by the voter.
+pub fn governance_create_proposal_ix(
+    accounts: &GovernanceAccounts,
+    proposal_seed: Pubkey,
+    name: &str,
+) -> Instruction {
+    let proposal = governance_proposal_pda(accounts, proposal_seed);
+    let mut data = vec![6u8];
+    serialize_string(&mut data, name);
+    serialize_string(&mut data, ""); // description_link
+    data.push(0); // VoteType::SingleChoice
+    data.extend_from_slice(&1u32.to_le_bytes());
+    serialize_string(&mut data, "Approve");
+    data.push(1); // use_deny_option
+    serialize_pubkey(&mut data, &proposal_seed);
+    Instruction {
+        program_id: GOVERNANCE_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(accounts.realm, false),
+            AccountMeta::new(proposal, false),
+            AccountMeta::new(accounts.governance, false),
+            AccountMeta::new(accounts.token_owner_record, false),
+            AccountMeta::new_readonly(accounts.community_mint, false),
+            AccountMeta::new_readonly(accounts.voter, true),
+            AccountMeta::new(accounts.voter, true),
+            AccountMeta::new_readonly(SYSTEM_PROGRAM_ID, false),
+            AccountMeta::new_readonly(governance_realm_config_pda(accounts.realm), false),
+            AccountMeta::new(
+                Pubkey::find_program_address(
+                    &[
+                        SEED_PROPOSAL_DEPOSIT,
+                        proposal.as_ref(),
+                        accounts.voter.as_ref(),
+                    ],
+                    &GOVERNANCE_PROGRAM_ID,
+                )
+                .0,
+                false,
+            ),
+        ],
+        data,
+    }
+}
+
+/// `InsertTransaction` placing `instruction` as the proposal's only transaction.
+pub fn governance_insert_transaction_ix(
+    accounts: &GovernanceAccounts,
+    proposal: Pubkey,
+    instruction: &Instruction,
+) -> Instruction {
+    let mut data = vec![9u8];
+    data.push(0); // option_index
+    data.extend_from_slice(&0u16.to_le_bytes()); // index
+    data.extend_from_slice(&0u32.to_le_bytes()); // legacy hold_up_time
+    data.extend_from_slice(&1u32.to_le_bytes());
+    serialize_pubkey(&mut data, &instruction.program_id);
+    data.extend_from_slice(&(instruction.accounts.len() as u32).to_le_bytes());
+    for meta in &instruction.accounts {
+        serialize_pubkey(&mut data, &meta.pubkey);
+        data.push(meta.is_signer as u8);
+        data.push(meta.is_writable as u8);
+    }
+    data.extend_from_slice(&(instruction.data.len() as u32).to_le_bytes());
+    data.extend_from_slice(&instruction.data);
+    Instruction {
+        program_id: GOVERNANCE_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new_readonly(accounts.governance, false),
+            AccountMeta::new(proposal, false),
+            AccountMeta::new_readonly(accounts.token_owner_record, false),
+            AccountMeta::new_readonly(accounts.voter, true),
+            AccountMeta::new(governance_proposal_transaction_pda(proposal), false),
+            AccountMeta::new(accounts.voter, true),
+            AccountMeta::new_readonly(SYSTEM_PROGRAM_ID, false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+        ],
+        data,
+    }
+}
+
+/// `SignOffProposal` by the proposal owner, who appointed no signatories.
+pub fn governance_sign_off_ix(accounts: &GovernanceAccounts, proposal: Pubkey) -> Instruction {
+    Instruction {
+        program_id: GOVERNANCE_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(accounts.realm, false),
+            AccountMeta::new(accounts.governance, false),
+            AccountMeta::new(proposal, false),
+            AccountMeta::new_readonly(accounts.voter, true),
+            AccountMeta::new_readonly(accounts.token_owner_record, false),
+        ],
+        data: vec![12],
+    }
+}
+
+/// `CastVote` approving the proposal's only option with the voter's full weight.
+pub fn governance_approve_ix(accounts: &GovernanceAccounts, proposal: Pubkey) -> Instruction {
+    let vote_record = Pubkey::find_program_address(
+        &[
+            SEED_GOVERNANCE,
+            proposal.as_ref(),
+            accounts.token_owner_record.as_ref(),
+        ],
+        &GOVERNANCE_PROGRAM_ID,
+    )
+    .0;
+    let mut data = vec![13u8];
+    data.push(0); // Vote::Approve
+    data.extend_from_slice(&1u32.to_le_bytes());
+    data.extend_from_slice(&[0, 100]); // VoteChoice { rank, weight_percentage }
+    Instruction {
+        program_id: GOVERNANCE_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(accounts.realm, false),
+            AccountMeta::new(accounts.governance, false),
+            AccountMeta::new(proposal, false),
+            AccountMeta::new(accounts.token_owner_record, false),
+            AccountMeta::new(accounts.token_owner_record, false),
+            AccountMeta::new_readonly(accounts.voter, true),
+            AccountMeta::new(vote_record, false),
+            AccountMeta::new_readonly(accounts.community_mint, false),
+            AccountMeta::new(accounts.voter, true),
+            AccountMeta::new_readonly(SYSTEM_PROGRAM_ID, false),
+            AccountMeta::new_readonly(governance_realm_config_pda(accounts.realm), false),
+        ],
+        data,
+    }
+}
+
+/// `ExecuteTransaction` for the proposal's only transaction. Governance signs for the
+/// native treasury itself, so no account is passed as a signer.
+pub fn governance_execute_transaction_ix(
+    accounts: &GovernanceAccounts,
+    proposal: Pubkey,
+    instruction: &Instruction,
+) -> Instruction {
+    let mut metas = vec![
+        AccountMeta::new_readonly(accounts.governance, false),
+        AccountMeta::new(proposal, false),
+        AccountMeta::new(governance_proposal_transaction_pda(proposal), false),
+        AccountMeta::new_readonly(instruction.program_id, false),
+    ];
+    metas.extend(instruction.accounts.iter().map(|meta| AccountMeta {
+        pubkey: meta.pubkey,
+        is_signer: false,
+        is_writable: meta.is_writable,
+    }));
+    Instruction {
+        program_id: GOVERNANCE_PROGRAM_ID,
+        accounts: metas,
+        data: vec![16],
+    }
+}
+
+pub fn ptkn_mint_pda(origin_mint: Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[SEED_PTKN_MINT, origin_mint.as_ref()], &FACTORY_PROGRAM_ID)
+}
+
+pub fn token_metadata_pda(mint: Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            b"metadata",
+            TOKEN_METADATA_PROGRAM_ID.as_ref(),
+            mint.as_ref(),
+        ],
+        &TOKEN_METADATA_PROGRAM_ID,
+    )
+    .0
+}
+
+pub fn council_proposal_pda(factory_state: Pubkey, id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[SEED_PROPOSAL, factory_state.as_ref(), &id.to_le_bytes()],
+        &FACTORY_PROGRAM_ID,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::{solana_program::program_pack::Pack, AccountDeserialize, AccountSerialize};
+    use ptf_factory::FactoryError;
+    use solana_program_test::{BanksClientError, ProgramTest};
+    use solana_sdk::{
+        account::{Account, AccountSharedData},
+        instruction::Instruction,
+        pubkey,
+        pubkey::Pubkey,
+        signature::Signer,
+        signer::keypair::Keypair,
+        sysvar,
+        transaction::{Transaction, TransactionError},
+    };
+    use std::{env, path::PathBuf};
+
+    const DEFAULT_FEE_BPS: u16 = 5;
+    const TIMELOCK_SECS: i64 = 5;
+
+    const FACTORY_SO: &str = "ptf_factory.so";
+    /// SPL Governance is not built here: `solana program dump -u m
+    /// GovER5Lthms3bLBqWub97yVrMmEogzX7xNjdXpPPCVZw target/deploy/spl_governance.so`.
+    const GOVERNANCE_SO: &str = "spl_governance.so";
+
+    fn artifact_path(filename: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("..")
+            .join("..")
+            .join("target")
+            .join("deploy")
+            .join(filename)
+    }
+
+    fn program_test() -> ProgramTest {
+        let governance_path = artifact_path(GOVERNANCE_SO);
+        assert!(
+            governance_path.exists(),
+            "{} missing. Dump the deployed SPL Governance program to {}",
+            GOVERNANCE_SO,
+            governance_path.display()
+        );
+        let so_path = artifact_path(FACTORY_SO);
+        assert!(
+            so_path.exists(),
+            "{} missing. Run `anchor build` so the `.so` artifact is present at {}",
+            FACTORY_SO,
+            so_path.display()
+        );
+        if let Some(dir) = so_path.parent() {
+            let canonical = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+            env::set_var("BPF_OUT_DIR", &canonical);
+            env::set_var("SBF_OUT_DIR", &canonical);
+        }
+        let mut test = ProgramTest::default();
+        test.add_program("ptf_factory", FACTORY_PROGRAM_ID, None);
+        test.add_program("spl_governance", GOVERNANCE_PROGRAM_ID, None);
+        test
+    }
+
+    async fn process_instruction(
+        context: &mut solana_program_test::ProgramTestContext,
+        instruction: Instruction,
+        additional_signers: &[&Keypair],
+    ) -> Result<(), BanksClientError> {
+        let mut tx = Transaction::new_with_payer(&[instruction], Some(&context.payer.pubkey()));
         let mut signers = vec![&context.payer];
         signers.extend_from_slice(additional_signers);
         tx.sign(&signers, context.last_blockhash);
@@ -302,18 +1885,2880 @@ mod tests {
         if result.is_ok() {
             context.last_blockhash = context.banks_client.get_latest_blockhash().await.unwrap();
         }
-        result
-    }
+        result
+    }
+
+    async fn advance_clock(context: &mut solana_program_test::ProgramTestContext, slots: u64) {
+        let current_slot = context.banks_client.get_root_slot().await.unwrap();
+        context.warp_to_slot(current_slot + slots).unwrap();
+        context.last_blockhash = context.banks_client.get_latest_blockhash().await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn timelock_blocks_direct_update() {
+        let authority = Keypair::new();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            TIMELOCK_SECS,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        let set_ix =
+            set_default_features_ix(factory_state, authority.pubkey(), FEATURE_HOOKS_ENABLED);
+        let err = process_instruction(&mut context, set_ix, &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::TimelockOnlyQueue);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn timelock_queue_and_execute_mint_update() {
+        let authority = Keypair::new();
+        let origin_mint = Keypair::new();
+
+        let program_test = program_test();
+
+        let mut context = program_test.start_with_context().await;
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            TIMELOCK_SECS,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        set_origin_mint(&mut context, origin_mint.pubkey(), 6);
+
+        let (mint_mapping, _) = mint_mapping_pda(origin_mint.pubkey());
+        let register_ix = register_mint_ix(
+            factory_state,
+            authority.pubkey(),
+            mint_mapping,
+            origin_mint.pubkey(),
+            context.payer.pubkey(),
+            6,
+        );
+        process_instruction(&mut context, register_ix, &[&authority])
+            .await
+            .unwrap();
+
+        let salt = [7u8; 32];
+        let (timelock_entry, _) = timelock_entry_pda(factory_state, &salt);
+        let queue_ix = queue_timelock_action_ix(
+            factory_state,
+            authority.pubkey(),
+            timelock_entry,
+            context.payer.pubkey(),
+            mint_mapping,
+            salt,
+            TimelockAction::UpdateMint {
+                origin_mint: origin_mint.pubkey(),
+                params: UpdateMintParams {
+                    enable_ptkn: None,
+                    features: Some(FEATURE_HOOKS_ENABLED),
+                    fee_bps_override: None,
+                },
+            },
+        );
+        process_instruction(&mut context, queue_ix, &[&authority])
+            .await
+            .unwrap();
+        let pending = load_timelock_index(&mut context, factory_state).await;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].entry.to_bytes(), timelock_entry.to_bytes());
+
+        let execute_ix = execute_timelock_action_ix(
+            factory_state,
+            timelock_entry,
+            mint_mapping,
+            mint_mapping,
+            authority.pubkey(),
+        );
+        let err = process_instruction(&mut context, execute_ix.clone(), &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::TimelockNotReady);
+
+        advance_clock(&mut context, 10).await;
+        {
+            let mut entry_account = context
+                .banks_client
+                .get_account(timelock_entry)
+                .await
+                .unwrap()
+                .unwrap();
+            let mut entry_state =
+                ptf_factory::TimelockEntry::try_deserialize(&mut entry_account.data.as_slice())
+                    .unwrap();
+            entry_state.execute_after = 0;
+
+            let mut serialized = Vec::with_capacity(entry_account.data.len());
+            entry_state.try_serialize(&mut serialized).unwrap();
+            if serialized.len() < entry_account.data.len() {
+                serialized.resize(entry_account.data.len(), 0);
+            }
+            entry_account.data = serialized;
+            context.set_account(&timelock_entry, &AccountSharedData::from(entry_account));
+        }
+
+        process_instruction(&mut context, execute_ix, &[&authority])
+            .await
+            .unwrap();
+
+        let account = context
+            .banks_client
+            .get_account(mint_mapping)
+            .await
+            .unwrap()
+            .unwrap();
+        let mapping =
+            ptf_factory::MintMapping::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert_eq!(mapping.features.bits(), FEATURE_HOOKS_ENABLED);
+        assert!(load_timelock_index(&mut context, factory_state)
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn register_and_deploy_creates_pool_in_one_instruction() {
+        let authority = Keypair::new();
+        let origin_mint = Pubkey::new_unique();
+        let verifying_key = Pubkey::new_unique();
+
+        let program_test = deploy_program_test(origin_mint, verifying_key, authority.pubkey());
+        let mut context = program_test.start_with_context().await;
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            TIMELOCK_SECS,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        let (mint_mapping, _) = mint_mapping_pda(origin_mint);
+        let deploy = deploy_accounts(origin_mint);
+        let deploy_ix = register_and_deploy_ix(
+            factory_state,
+            authority.pubkey(),
+            mint_mapping,
+            origin_mint,
+            context.payer.pubkey(),
+            verifying_key,
+            &deploy,
+            8,
+            false,
+        );
+        process_instruction(&mut context, deploy_ix.clone(), &[&authority])
+            .await
+            .unwrap();
+
+        for (key, owner) in [
+            (mint_mapping, FACTORY_PROGRAM_ID),
+            (deploy.vault_state, VAULT_PROGRAM_ID),
+            (deploy.vault_token_account, TOKEN_PROGRAM_ID),
+            (deploy.pool_state, POOL_PROGRAM_ID),
+            (deploy.nullifier_set, POOL_PROGRAM_ID),
+            (deploy.note_ledger, POOL_PROGRAM_ID),
+            (deploy.commitment_tree, POOL_PROGRAM_ID),
+            (deploy.hook_config, POOL_PROGRAM_ID),
+        ] {
+            let account = context
+                .banks_client
+                .get_account(key)
+                .await
+                .unwrap()
+                .unwrap_or_else(|| panic!("{} was not created", key));
+            assert_eq!(account.owner, owner);
+        }
+        let account = context
+            .banks_client
+            .get_account(mint_mapping)
+            .await
+            .unwrap()
+            .unwrap();
+        let mapping =
+            ptf_factory::MintMapping::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert_eq!(mapping.pool.to_bytes(), deploy.pool_state.to_bytes());
+        assert_eq!(mapping.vault.to_bytes(), deploy.vault_state.to_bytes());
+        assert_eq!(
+            mapping.commitment_tree.to_bytes(),
+            deploy.commitment_tree.to_bytes()
+        );
+        assert_eq!(
+            mapping.nullifier_set.to_bytes(),
+            deploy.nullifier_set.to_bytes()
+        );
+
+        // The mapping now exists, so the same mint cannot be deployed twice.
+        advance_clock(&mut context, 1).await;
+        assert!(process_instruction(&mut context, deploy_ix, &[&authority])
+            .await
+            .is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn council_executes_proposal_once_threshold_is_met() {
+        let authority = Keypair::new();
+        let members = [Keypair::new(), Keypair::new()];
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            TIMELOCK_SECS,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        // The transaction payer proposes, so it doubles as the first member.
+        let member_keys = [
+            context.payer.pubkey(),
+            members[0].pubkey(),
+            members[1].pubkey(),
+        ];
+        // With the timelock on, the council can only be installed through the queue.
+        let council_ix = set_council_ix(factory_state, authority.pubkey(), &member_keys, 2);
+        let err = process_instruction(&mut context, council_ix, &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::TimelockOnlyQueue);
+
+        // Anchor reads the program id as an omitted optional account.
+        let unused_mapping = FACTORY_PROGRAM_ID;
+        let salt = [3u8; 32];
+        let (timelock_entry, _) = timelock_entry_pda(factory_state, &salt);
+        let queue_ix = queue_timelock_action_ix(
+            factory_state,
+            authority.pubkey(),
+            timelock_entry,
+            context.payer.pubkey(),
+            unused_mapping,
+            salt,
+            TimelockAction::SetCouncil {
+                members: member_keys.to_vec(),
+                threshold: 2,
+            },
+        );
+        process_instruction(&mut context, queue_ix, &[&authority])
+            .await
+            .unwrap();
+        make_timelock_ready(&mut context, timelock_entry).await;
+        let execute_ix = execute_timelock_action_ix(
+            factory_state,
+            timelock_entry,
+            unused_mapping,
+            unused_mapping,
+            context.payer.pubkey(),
+        );
+        process_instruction(&mut context, execute_ix, &[])
+            .await
+            .unwrap();
+
+        // The old authority no longer controls the factory directly.
+        let (council, _) = council_pda(factory_state);
+        assert!(process_instruction(
+            &mut context,
+            pause_ix(factory_state, authority.pubkey(), false),
+            &[&authority]
+        )
+        .await
+        .is_err());
+
+        let inner = pause_ix(factory_state, council, false);
+        let (proposal, _) = council_proposal_pda(factory_state, 0);
+        let create_ix =
+            create_council_proposal_ix(factory_state, context.payer.pubkey(), proposal, &inner);
+        process_instruction(&mut context, create_ix, &[])
+            .await
+            .unwrap();
+
+        let execute_ix =
+            execute_council_proposal_ix(factory_state, proposal, context.payer.pubkey(), &inner);
+        let err = process_instruction(&mut context, execute_ix.clone(), &[])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::CouncilThresholdNotMet);
+
+        let approve_ix = approve_council_proposal_ix(factory_state, members[1].pubkey(), proposal);
+        process_instruction(&mut context, approve_ix, &[&members[1]])
+            .await
+            .unwrap();
+        process_instruction(&mut context, execute_ix.clone(), &[])
+            .await
+            .unwrap();
+
+        let account = context
+            .banks_client
+            .get_account(factory_state)
+            .await
+            .unwrap()
+            .unwrap();
+        let state =
+            ptf_factory::FactoryState::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert!(state.paused);
+
+        advance_clock(&mut context, 1).await;
+        let err = process_instruction(&mut context, execute_ix, &[])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::CouncilProposalExecuted);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn governance_treasury_takes_authority() {
+        let authority = Keypair::new();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            TIMELOCK_SECS,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        let governance = create_single_voter_governance(&mut context).await;
+        // With the timelock on, the handover can only go through the queue.
+        let set_ix = set_governance_authority_ix(
+            factory_state,
+            authority.pubkey(),
+            GOVERNANCE_PROGRAM_ID,
+            governance.governance,
+        );
+        let err = process_instruction(&mut context, set_ix, &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::TimelockOnlyQueue);
+
+        // Anchor reads the program id as an omitted optional account.
+        let unused_mapping = FACTORY_PROGRAM_ID;
+        let payer = context.payer.pubkey();
+        let queue_handover_ix = |salt: [u8; 32], governance_program: Pubkey| {
+            queue_timelock_action_ix(
+                factory_state,
+                authority.pubkey(),
+                timelock_entry_pda(factory_state, &salt).0,
+                payer,
+                unused_mapping,
+                salt,
+                TimelockAction::SetGovernanceAuthority {
+                    governance_program,
+                    governance: governance.governance,
+                },
+            )
+        };
+        // A program other than SPL Governance is refused, so it cannot mint the authority.
+        let err = process_instruction(
+            &mut context,
+            queue_handover_ix([4u8; 32], FACTORY_PROGRAM_ID),
+            &[&authority],
+        )
+        .await
+        .unwrap_err();
+        assert_anchor_error(err, FactoryError::GovernanceInvalid);
+
+        let handover_salt = [6u8; 32];
+        let (handover_entry, _) = timelock_entry_pda(factory_state, &handover_salt);
+        process_instruction(
+            &mut context,
+            queue_handover_ix(handover_salt, GOVERNANCE_PROGRAM_ID),
+            &[&authority],
+        )
+        .await
+        .unwrap();
+        make_timelock_ready(&mut context, handover_entry).await;
+        let execute_ix = execute_timelock_action_ix(
+            factory_state,
+            handover_entry,
+            unused_mapping,
+            unused_mapping,
+            context.payer.pubkey(),
+        );
+        process_instruction(&mut context, execute_ix, &[])
+            .await
+            .unwrap();
+
+        let account = context
+            .banks_client
+            .get_account(factory_state)
+            .await
+            .unwrap()
+            .unwrap();
+        let state =
+            ptf_factory::FactoryState::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert_eq!(
+            state.authority.to_bytes(),
+            governance.native_treasury.to_bytes()
+        );
+        assert_eq!(
+            state.governance_program.to_bytes(),
+            GOVERNANCE_PROGRAM_ID.to_bytes()
+        );
+        assert_eq!(
+            state.governance.to_bytes(),
+            governance.governance.to_bytes()
+        );
+
+        // Only a governance-executed transaction can sign for the treasury now.
+        let salt = [5u8; 32];
+        let (timelock_entry, _) = timelock_entry_pda(factory_state, &salt);
+        let fee_action = TimelockAction::SetDefaultFee { fee_bps: 25 };
+        let stale_queue_ix = queue_timelock_action_ix(
+            factory_state,
+            authority.pubkey(),
+            timelock_entry,
+            context.payer.pubkey(),
+            unused_mapping,
+            salt,
+            fee_action.clone(),
+        );
+        assert!(
+            process_instruction(&mut context, stale_queue_ix, &[&authority])
+                .await
+                .is_err()
+        );
+
+        // The treasury pays for the entry it queues.
+        context.set_account(
+            &governance.native_treasury,
+            &AccountSharedData::from(Account {
+                lamports: 1_000_000_000,
+                data: Vec::new(),
+                owner: SYSTEM_PROGRAM_ID,
+                executable: false,
+                rent_epoch: 0,
+            }),
+        );
+
+        let queue_ix = queue_timelock_action_ix(
+            factory_state,
+            governance.native_treasury,
+            timelock_entry,
+            governance.native_treasury,
+            unused_mapping,
+            salt,
+            fee_action,
+        );
+        execute_governance_proposal(&mut context, &governance, "Queue fee", queue_ix).await;
+        let pending = load_timelock_index(&mut context, factory_state).await;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].entry.to_bytes(), timelock_entry.to_bytes());
+
+        make_timelock_ready(&mut context, timelock_entry).await;
+        let execute_ix = execute_timelock_action_ix(
+            factory_state,
+            timelock_entry,
+            unused_mapping,
+            unused_mapping,
+            governance.native_treasury,
+        );
+        execute_governance_proposal(&mut context, &governance, "Execute fee", execute_ix).await;
+
+        let account = context
+            .banks_client
+            .get_account(factory_state)
+            .await
+            .unwrap()
+            .unwrap();
+        let state =
+            ptf_factory::FactoryState::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert_eq!(state.default_fee_bps, 25);
+        assert!(load_timelock_index(&mut context, factory_state)
+            .await
+            .is_empty());
+        assert!(context
+            .banks_client
+            .get_account(timelock_entry)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    /// Creates a realm whose community token supply is held by the context payer, deposits
+    /// it, and creates a governance the payer alone decides.
+    async fn create_single_voter_governance(
+        context: &mut solana_program_test::ProgramTestContext,
+    ) -> GovernanceAccounts {
+        let realm_name = "ptf";
+        let community_mint = Pubkey::new_unique();
+        let source = Pubkey::new_unique();
+        let voter = context.payer.pubkey();
+        let mut mint = vec![0u8; 82];
+        mint[36..44].copy_from_slice(&1u64.to_le_bytes()); // supply
+        mint[45] = 1; // is_initialized
+        let mut token_account = vec![0u8; 165];
+        token_account[..32].copy_from_slice(community_mint.as_ref());
+        token_account[32..64].copy_from_slice(voter.as_ref());
+        token_account[64..72].copy_from_slice(&1u64.to_le_bytes()); // amount
+        token_account[108] = 1; // AccountState::Initialized
+        for (address, data) in [(community_mint, mint), (source, token_account)] {
+            context.set_account(
+                &address,
+                &AccountSharedData::from(Account {
+                    lamports: 1_000_000_000,
+                    data,
+                    owner: TOKEN_PROGRAM_ID,
+                    executable: false,
+                    rent_epoch: 0,
+                }),
+            );
+        }
+
+        let governance_seed = Pubkey::new_unique();
+        let accounts = governance_accounts(realm_name, community_mint, voter, governance_seed);
+        for ix in [
+            governance_create_realm_ix(&accounts, realm_name, voter),
+            governance_deposit_ix(&accounts, source, 1),
+            governance_create_ix(&accounts, governance_seed),
+        ] {
+            process_instruction(context, ix, &[]).await.unwrap();
+        }
+        accounts
+    }
+
+    /// Passes a single-transaction proposal with the governance's only voter and executes
+    /// it, so `instruction` runs signed by the native treasury.
+    async fn execute_governance_proposal(
+        context: &mut solana_program_test::ProgramTestContext,
+        governance: &GovernanceAccounts,
+        name: &str,
+        mut instruction: Instruction,
+    ) {
+        // Omitted optional accounts stay read-only so the CPI does not escalate them.
+        for meta in &mut instruction.accounts {
+            if meta.pubkey == instruction.program_id {
+                meta.is_writable = false;
+            }
+        }
+        let proposal_seed = Pubkey::new_unique();
+        let proposal = governance_proposal_pda(governance, proposal_seed);
+        for ix in [
+            governance_create_proposal_ix(governance, proposal_seed, name),
+            governance_insert_transaction_ix(governance, proposal, &instruction),
+            governance_sign_off_ix(governance, proposal),
+            governance_approve_ix(governance, proposal),
+            governance_execute_transaction_ix(governance, proposal, &instruction),
+        ] {
+            process_instruction(context, ix, &[]).await.unwrap();
+        }
+    }
+
+    /// Governance-executed transactions cannot supply a fresh mint keypair signature, so
+    /// enabling pTKN through the timelock uses the factory-signed mint PDA instead.
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn timelock_enables_ptkn_at_factory_mint_pda() {
+        let authority = Keypair::new();
+        let origin_mint = Keypair::new();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            TIMELOCK_SECS,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        set_origin_mint(&mut context, origin_mint.pubkey(), 6);
+
+        let (mint_mapping, _) = mint_mapping_pda(origin_mint.pubkey());
+        let register_ix = register_mint_ix(
+            factory_state,
+            authority.pubkey(),
+            mint_mapping,
+            origin_mint.pubkey(),
+            context.payer.pubkey(),
+            6,
+        );
+        process_instruction(&mut context, register_ix, &[&authority])
+            .await
+            .unwrap();
+
+        let salt = [9u8; 32];
+        let (timelock_entry, _) = timelock_entry_pda(factory_state, &salt);
+        let queue_ix = queue_timelock_action_ix(
+            factory_state,
+            authority.pubkey(),
+            timelock_entry,
+            context.payer.pubkey(),
+            mint_mapping,
+            salt,
+            TimelockAction::UpdateMint {
+                origin_mint: origin_mint.pubkey(),
+                params: UpdateMintParams {
+                    enable_ptkn: Some(true),
+                    features: None,
+                    fee_bps_override: None,
+                },
+            },
+        );
+        process_instruction(&mut context, queue_ix, &[&authority])
+            .await
+            .unwrap();
+
+        {
+            let mut entry_account = context
+                .banks_client
+                .get_account(timelock_entry)
+                .await
+                .unwrap()
+                .unwrap();
+            let mut entry_state =
+                ptf_factory::TimelockEntry::try_deserialize(&mut entry_account.data.as_slice())
+                    .unwrap();
+            entry_state.execute_after = 0;
+            let mut serialized = Vec::with_capacity(entry_account.data.len());
+            entry_state.try_serialize(&mut serialized).unwrap();
+            serialized.resize(entry_account.data.len(), 0);
+            entry_account.data = serialized;
+            context.set_account(&timelock_entry, &AccountSharedData::from(entry_account));
+        }
+
+        let (ptkn_mint, _) = ptkn_mint_pda(origin_mint.pubkey());
+        let execute_ix = execute_timelock_action_ix(
+            factory_state,
+            timelock_entry,
+            mint_mapping,
+            ptkn_mint,
+            context.payer.pubkey(),
+        );
+        process_instruction(&mut context, execute_ix, &[])
+            .await
+            .unwrap();
+
+        let account = context
+            .banks_client
+            .get_account(mint_mapping)
+            .await
+            .unwrap()
+            .unwrap();
+        let mapping =
+            ptf_factory::MintMapping::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert!(mapping.has_ptkn);
+        assert_eq!(mapping.ptkn_mint.to_bytes(), ptkn_mint.to_bytes());
+        assert_eq!(
+            mapping.ptkn_token_program,
+            ptf_factory::PtknTokenProgram::Token2022 as u8
+        );
+        assert_eq!(mapping.ptkn_extensions, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn timelock_delays_respect_protocol_minimums() {
+        let authority = Keypair::new();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            TIMELOCK_SECS,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        // Anchor reads the program id as an omitted optional account.
+        let mint_mapping = FACTORY_PROGRAM_ID;
+        let payer = context.payer.pubkey();
+        let queue_delays = |salt: [u8; 32], fee_delay: i64| {
+            queue_timelock_action_ix(
+                factory_state,
+                authority.pubkey(),
+                timelock_entry_pda(factory_state, &salt).0,
+                payer,
+                mint_mapping,
+                salt,
+                TimelockAction::SetTimelockDelays {
+                    delays: [0, 0, 3_600, 3_600, fee_delay, 259_200],
+                },
+            )
+        };
+
+        // Fee changes must wait at least a day.
+        let short_ix = queue_delays([1u8; 32], 3_600);
+        let err = process_instruction(&mut context, short_ix, &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::TimelockDelayOutOfBounds);
+
+        let valid_ix = queue_delays([2u8; 32], 86_400);
+        process_instruction(&mut context, valid_ix, &[&authority])
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn timelock_index_dedupes_frees_and_caps_entries() {
+        let authority = Keypair::new();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+        let payer = context.payer.pubkey();
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            payer,
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            TIMELOCK_SECS,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        // Anchor reads the program id as an omitted optional account.
+        let unused_mapping = FACTORY_PROGRAM_ID;
+        let queue_ix = |salt: [u8; 32]| {
+            queue_timelock_action_ix(
+                factory_state,
+                authority.pubkey(),
+                timelock_entry_pda(factory_state, &salt).0,
+                payer,
+                unused_mapping,
+                salt,
+                TimelockAction::SetDefaultFee { fee_bps: 10 },
+            )
+        };
+        for idx in 0..ptf_factory::TimelockIndex::MAX_PENDING {
+            process_instruction(&mut context, queue_ix([idx as u8 + 1; 32]), &[&authority])
+                .await
+                .unwrap();
+        }
+        let pending = load_timelock_index(&mut context, factory_state).await;
+        assert_eq!(pending.len(), ptf_factory::TimelockIndex::MAX_PENDING);
+
+        // Backfilling an entry that is already indexed does not list it twice.
+        let (first_entry, _) = timelock_entry_pda(factory_state, &[1u8; 32]);
+        let index_ix = index_timelock_entry_ix(factory_state, first_entry, payer);
+        process_instruction(&mut context, index_ix, &[])
+            .await
+            .unwrap();
+        assert_eq!(
+            load_timelock_index(&mut context, factory_state).await,
+            pending
+        );
+
+        let overflow_salt = [0xffu8; 32];
+        let err = process_instruction(&mut context, queue_ix(overflow_salt), &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::TimelockIndexFull);
+
+        // Canceling frees the slot for the next queued action.
+        let cancel_ix = cancel_timelock_action_ix(factory_state, authority.pubkey(), first_entry);
+        process_instruction(&mut context, cancel_ix, &[&authority])
+            .await
+            .unwrap();
+        let pending = load_timelock_index(&mut context, factory_state).await;
+        assert_eq!(pending.len(), ptf_factory::TimelockIndex::MAX_PENDING - 1);
+        assert!(pending
+            .iter()
+            .all(|item| item.entry.to_bytes() != first_entry.to_bytes()));
+
+        process_instruction(&mut context, queue_ix(overflow_salt), &[&authority])
+            .await
+            .unwrap();
+        let pending = load_timelock_index(&mut context, factory_state).await;
+        assert_eq!(pending.len(), ptf_factory::TimelockIndex::MAX_PENDING);
+        assert_eq!(
+            pending.last().unwrap().entry.to_bytes(),
+            timelock_entry_pda(factory_state, &overflow_salt)
+                .0
+                .to_bytes()
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn guardian_cancels_and_pauses_only() {
+        let authority = Keypair::new();
+        let guardian = Keypair::new();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            5,
+            0,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+        let guardian_ix = set_guardian_ix(factory_state, authority.pubkey(), guardian.pubkey());
+        process_instruction(&mut context, guardian_ix, &[&authority])
+            .await
+            .unwrap();
+
+        let salt = [3u8; 32];
+        let (timelock_entry, _) = timelock_entry_pda(factory_state, &salt);
+        let queue_ix = queue_timelock_action_ix(
+            factory_state,
+            authority.pubkey(),
+            timelock_entry,
+            context.payer.pubkey(),
+            FACTORY_PROGRAM_ID,
+            salt,
+            TimelockAction::SetDefaultFeatures {
+                features: FEATURE_HOOKS_ENABLED,
+            },
+        );
+        process_instruction(&mut context, queue_ix, &[&authority])
+            .await
+            .unwrap();
+
+        // The guardian cannot queue.
+        let guardian_queue_ix = queue_timelock_action_ix(
+            factory_state,
+            guardian.pubkey(),
+            timelock_entry_pda(factory_state, &[4u8; 32]).0,
+            context.payer.pubkey(),
+            FACTORY_PROGRAM_ID,
+            [4u8; 32],
+            TimelockAction::UnpauseFactory,
+        );
+        assert!(
+            process_instruction(&mut context, guardian_queue_ix, &[&guardian])
+                .await
+                .is_err()
+        );
+
+        let cancel_ix = cancel_timelock_action_ix(factory_state, guardian.pubkey(), timelock_entry);
+        process_instruction(&mut context, cancel_ix, &[&guardian])
+            .await
+            .unwrap();
+        assert!(load_timelock_index(&mut context, factory_state)
+            .await
+            .is_empty());
+
+        process_instruction(
+            &mut context,
+            pause_ix(factory_state, guardian.pubkey(), false),
+            &[&guardian],
+        )
+        .await
+        .unwrap();
+        let err = process_instruction(
+            &mut context,
+            pause_ix(factory_state, guardian.pubkey(), true),
+            &[&guardian],
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, BanksClientError::TransactionError(_)));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn timelocked_factory_unpauses_through_the_queue() {
+        let authority = Keypair::new();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            TIMELOCK_SECS,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        // Pausing stays immediate; lifting it does not.
+        process_instruction(
+            &mut context,
+            pause_ix(factory_state, authority.pubkey(), false),
+            &[&authority],
+        )
+        .await
+        .unwrap();
+        process_instruction(
+            &mut context,
+            pause_scopes_ix(
+                factory_state,
+                authority.pubkey(),
+                ptf_factory::PAUSE_REGISTRATION,
+                false,
+            ),
+            &[&authority],
+        )
+        .await
+        .unwrap();
+        let err = process_instruction(
+            &mut context,
+            pause_ix(factory_state, authority.pubkey(), true),
+            &[&authority],
+        )
+        .await
+        .unwrap_err();
+        assert_anchor_error(err, FactoryError::TimelockOnlyQueue);
+        let err = process_instruction(
+            &mut context,
+            pause_scopes_ix(
+                factory_state,
+                authority.pubkey(),
+                ptf_factory::PAUSE_REGISTRATION,
+                true,
+            ),
+            &[&authority],
+        )
+        .await
+        .unwrap_err();
+        assert_anchor_error(err, FactoryError::TimelockOnlyQueue);
+
+        // While paused, only `UnpauseFactory` can be queued.
+        let payer = context.payer.pubkey();
+        let queue_ix = |salt: [u8; 32], action| {
+            queue_timelock_action_ix(
+                factory_state,
+                authority.pubkey(),
+                timelock_entry_pda(factory_state, &salt).0,
+                payer,
+                FACTORY_PROGRAM_ID,
+                salt,
+                action,
+            )
+        };
+        let err = process_instruction(
+            &mut context,
+            queue_ix(
+                [1u8; 32],
+                TimelockAction::UnpauseScopes {
+                    scopes: ptf_factory::PAUSE_REGISTRATION,
+                },
+            ),
+            &[&authority],
+        )
+        .await
+        .unwrap_err();
+        assert_anchor_error(err, FactoryError::Paused);
+
+        for (salt, action) in [
+            ([2u8; 32], TimelockAction::UnpauseFactory),
+            (
+                [3u8; 32],
+                TimelockAction::UnpauseScopes {
+                    scopes: ptf_factory::PAUSE_REGISTRATION,
+                },
+            ),
+        ] {
+            process_instruction(&mut context, queue_ix(salt, action), &[&authority])
+                .await
+                .unwrap();
+            let (timelock_entry, _) = timelock_entry_pda(factory_state, &salt);
+            make_timelock_ready(&mut context, timelock_entry).await;
+            let execute_ix = execute_timelock_action_ix(
+                factory_state,
+                timelock_entry,
+                FACTORY_PROGRAM_ID,
+                FACTORY_PROGRAM_ID,
+                payer,
+            );
+            process_instruction(&mut context, execute_ix, &[])
+                .await
+                .unwrap();
+        }
+
+        let account = context
+            .banks_client
+            .get_account(factory_state)
+            .await
+            .unwrap()
+            .unwrap();
+        let state =
+            ptf_factory::FactoryState::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert!(!state.paused);
+        assert_eq!(state.paused_scopes, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn timelock_rotates_fee_and_authority() {
+        let authority = Keypair::new();
+        let new_authority = Pubkey::new_unique();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            TIMELOCK_SECS,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        // Anchor reads the program id as an omitted optional account.
+        let unused_mapping = FACTORY_PROGRAM_ID;
+        let invalid_fee_ix = queue_timelock_action_ix(
+            factory_state,
+            authority.pubkey(),
+            timelock_entry_pda(factory_state, &[1u8; 32]).0,
+            context.payer.pubkey(),
+            unused_mapping,
+            [1u8; 32],
+            TimelockAction::SetDefaultFee { fee_bps: 10_001 },
+        );
+        let err = process_instruction(&mut context, invalid_fee_ix, &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::InvalidFeeBps);
+
+        for (salt, action) in [
+            ([2u8; 32], TimelockAction::SetDefaultFee { fee_bps: 25 }),
+            (
+                [3u8; 32],
+                TimelockAction::SetTimelockSeconds { seconds: 60 },
+            ),
+            ([4u8; 32], TimelockAction::SetAuthority { new_authority }),
+        ] {
+            let (timelock_entry, _) = timelock_entry_pda(factory_state, &salt);
+            let queue_ix = queue_timelock_action_ix(
+                factory_state,
+                authority.pubkey(),
+                timelock_entry,
+                context.payer.pubkey(),
+                unused_mapping,
+                salt,
+                action,
+            );
+            process_instruction(&mut context, queue_ix, &[&authority])
+                .await
+                .unwrap();
+            make_timelock_ready(&mut context, timelock_entry).await;
+            let execute_ix = execute_timelock_action_ix(
+                factory_state,
+                timelock_entry,
+                unused_mapping,
+                unused_mapping,
+                context.payer.pubkey(),
+            );
+            process_instruction(&mut context, execute_ix, &[])
+                .await
+                .unwrap();
+        }
+
+        let account = context
+            .banks_client
+            .get_account(factory_state)
+            .await
+            .unwrap()
+            .unwrap();
+        let state =
+            ptf_factory::FactoryState::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert_eq!(state.default_fee_bps, 25);
+        assert_eq!(state.timelock_seconds, 60);
+        assert_eq!(state.authority.to_bytes(), new_authority.to_bytes());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn expired_timelock_action_can_only_be_closed() {
+        let authority = Keypair::new();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            TIMELOCK_SECS,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        let salt = [5u8; 32];
+        let (timelock_entry, _) = timelock_entry_pda(factory_state, &salt);
+        let queue_ix = queue_timelock_action_ix(
+            factory_state,
+            authority.pubkey(),
+            timelock_entry,
+            context.payer.pubkey(),
+            FACTORY_PROGRAM_ID,
+            salt,
+            TimelockAction::SetDefaultFee { fee_bps: 25 },
+        );
+        process_instruction(&mut context, queue_ix, &[&authority])
+            .await
+            .unwrap();
+
+        // Not expired yet.
+        let early_close_ix =
+            close_expired_timelock_action_ix(factory_state, timelock_entry, context.payer.pubkey());
+        let err = process_instruction(&mut context, early_close_ix, &[])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::TimelockNotExpired);
+
+        edit_timelock_entry(&mut context, timelock_entry, |entry| {
+            entry.execute_after = 0;
+            entry.expires_after = 1;
+        })
+        .await;
+        let execute_ix = execute_timelock_action_ix(
+            factory_state,
+            timelock_entry,
+            FACTORY_PROGRAM_ID,
+            FACTORY_PROGRAM_ID,
+            context.payer.pubkey(),
+        );
+        let err = process_instruction(&mut context, execute_ix, &[])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::TimelockExpired);
+
+        let close_ix =
+            close_expired_timelock_action_ix(factory_state, timelock_entry, context.payer.pubkey());
+        process_instruction(&mut context, close_ix, &[])
+            .await
+            .unwrap();
+        assert!(context
+            .banks_client
+            .get_account(timelock_entry)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(load_timelock_index(&mut context, factory_state)
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn restricted_execution_limits_executors() {
+        let authority = Keypair::new();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            0,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+        let restrict_ix = set_restricted_execution_ix(factory_state, authority.pubkey(), true);
+        process_instruction(&mut context, restrict_ix, &[&authority])
+            .await
+            .unwrap();
+
+        let salt = [6u8; 32];
+        let (timelock_entry, _) = timelock_entry_pda(factory_state, &salt);
+        let queue_ix = queue_timelock_action_ix(
+            factory_state,
+            authority.pubkey(),
+            timelock_entry,
+            context.payer.pubkey(),
+            FACTORY_PROGRAM_ID,
+            salt,
+            TimelockAction::SetDefaultFee { fee_bps: 25 },
+        );
+        process_instruction(&mut context, queue_ix, &[&authority])
+            .await
+            .unwrap();
+
+        let outsider_ix = execute_timelock_action_ix(
+            factory_state,
+            timelock_entry,
+            FACTORY_PROGRAM_ID,
+            FACTORY_PROGRAM_ID,
+            context.payer.pubkey(),
+        );
+        let err = process_instruction(&mut context, outsider_ix, &[])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::Unauthorized);
+
+        let authority_ix = execute_timelock_action_ix(
+            factory_state,
+            timelock_entry,
+            FACTORY_PROGRAM_ID,
+            FACTORY_PROGRAM_ID,
+            authority.pubkey(),
+        );
+        process_instruction(&mut context, authority_ix, &[&authority])
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn frozen_mapping_without_pool_can_be_closed() {
+        let authority = Keypair::new();
+        let origin_mint = Keypair::new();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            0,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        set_origin_mint(&mut context, origin_mint.pubkey(), 6);
+
+        let (mint_mapping, _) = mint_mapping_pda(origin_mint.pubkey());
+        let register_ix = register_mint_ix(
+            factory_state,
+            authority.pubkey(),
+            mint_mapping,
+            origin_mint.pubkey(),
+            context.payer.pubkey(),
+            6,
+        );
+        process_instruction(&mut context, register_ix, &[&authority])
+            .await
+            .unwrap();
+
+        let close_ix =
+            close_mint_mapping_ix(factory_state, authority.pubkey(), origin_mint.pubkey());
+        let err = process_instruction(&mut context, close_ix.clone(), &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::MintNotFrozen);
+
+        let freeze_ix = freeze_mapping_ix(factory_state, authority.pubkey(), mint_mapping);
+        process_instruction(&mut context, freeze_ix, &[&authority])
+            .await
+            .unwrap();
+
+        // A second allowlisted program could host the mint's pool, so its PDA is needed too.
+        let forked_pool = Pubkey::new_unique();
+        let allow_ix = set_pool_program_ix(factory_state, authority.pubkey(), forked_pool, true);
+        process_instruction(&mut context, allow_ix, &[&authority])
+            .await
+            .unwrap();
+        let err = process_instruction(&mut context, close_ix.clone(), &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::PoolAccountsMissing);
+
+        let mut close_ix = close_ix;
+        close_ix.accounts.push(AccountMeta::new_readonly(
+            Pubkey::find_program_address(&[SEED_POOL, origin_mint.pubkey().as_ref()], &forked_pool)
+                .0,
+            false,
+        ));
+        process_instruction(&mut context, close_ix, &[&authority])
+            .await
+            .unwrap();
+        assert!(context
+            .banks_client
+            .get_account(mint_mapping)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn pool_program_allowlist_is_managed_under_timelock() {
+        let authority = Keypair::new();
+        let forked_pool = Pubkey::new_unique();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            TIMELOCK_SECS,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        let direct_ix = set_pool_program_ix(factory_state, authority.pubkey(), forked_pool, true);
+        let err = process_instruction(&mut context, direct_ix, &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::TimelockOnlyQueue);
+
+        for (salt, action) in [
+            (
+                [1u8; 32],
+                TimelockAction::SetPoolProgram {
+                    program: forked_pool,
+                    allowed: true,
+                },
+            ),
+            (
+                [2u8; 32],
+                TimelockAction::SetPoolProgram {
+                    program: POOL_PROGRAM_ID,
+                    allowed: false,
+                },
+            ),
+        ] {
+            let (timelock_entry, _) = timelock_entry_pda(factory_state, &salt);
+            let queue_ix = queue_timelock_action_ix(
+                factory_state,
+                authority.pubkey(),
+                timelock_entry,
+                context.payer.pubkey(),
+                FACTORY_PROGRAM_ID,
+                salt,
+                action,
+            );
+            process_instruction(&mut context, queue_ix, &[&authority])
+                .await
+                .unwrap();
+            make_timelock_ready(&mut context, timelock_entry).await;
+            let execute_ix = execute_timelock_action_ix(
+                factory_state,
+                timelock_entry,
+                FACTORY_PROGRAM_ID,
+                FACTORY_PROGRAM_ID,
+                context.payer.pubkey(),
+            );
+            process_instruction(&mut context, execute_ix, &[])
+                .await
+                .unwrap();
+        }
+
+        let account = context
+            .banks_client
+            .get_account(factory_state)
+            .await
+            .unwrap()
+            .unwrap();
+        let state =
+            ptf_factory::FactoryState::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert_eq!(state.pool_programs_len, 1);
+        assert_eq!(state.pool_programs[0].to_bytes(), forked_pool.to_bytes());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn ptkn_metadata_update_checks_fields_and_twin() {
+        let authority = Keypair::new();
+        let origin_mint = Keypair::new();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            0,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        set_origin_mint(&mut context, origin_mint.pubkey(), 6);
+
+        let (mint_mapping, _) = mint_mapping_pda(origin_mint.pubkey());
+        let register_ix = register_mint_ix(
+            factory_state,
+            authority.pubkey(),
+            mint_mapping,
+            origin_mint.pubkey(),
+            context.payer.pubkey(),
+            6,
+        );
+        process_instruction(&mut context, register_ix, &[&authority])
+            .await
+            .unwrap();
+
+        // The mapping has no twin, so its pTKN mint is the default key.
+        let update = |symbol: &str| {
+            update_ptkn_metadata_ix(
+                factory_state,
+                authority.pubkey(),
+                origin_mint.pubkey(),
+                Pubkey::default(),
+                context.payer.pubkey(),
+                "Private Token",
+                symbol,
+                "",
+            )
+        };
+        let too_long_ix = update("pTOOLONGSYMBOL");
+        let valid_ix = update("pTKN");
+        let err = process_instruction(&mut context, too_long_ix, &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::PtknMetadataInvalid);
+        let err = process_instruction(&mut context, valid_ix, &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::PtknMintDisabled);
+    }
+
+    /// Reads `len` bytes of a `PoolState` field from the account data.
+    macro_rules! pool_field {
+        ($data:expr, $field:ident, $len:literal) => {{
+            let offset = 8 + std::mem::offset_of!(ptf_pool::PoolState, $field);
+            <[u8; $len]>::try_from(&$data[offset..offset + $len]).unwrap()
+        }};
+    }
+
+    /// A program test with the vault, pool and verifier loaded, a 6-decimal
+    /// `origin_mint` and a registered `verifying_key`, ready for `register_and_deploy`.
+    fn deploy_program_test(
+        origin_mint: Pubkey,
+        verifying_key: Pubkey,
+        vk_authority: Pubkey,
+    ) -> ProgramTest {
+        let mut program_test = program_test();
+        program_test.add_program("ptf_vault", VAULT_PROGRAM_ID, None);
+        program_test.add_program("ptf_pool", POOL_PROGRAM_ID, None);
+        program_test.add_program("ptf_verifier_groth16", VERIFIER_PROGRAM_ID, None);
+        program_test.set_compute_max_units(1_400_000);
+        // An initialized 6-decimal mint: no authorities, zero supply.
+        let mut mint_data = vec![0u8; 82];
+        mint_data[44] = 6;
+        mint_data[45] = 1;
+        program_test.add_account(
+            origin_mint,
+            Account {
+                lamports: 1_000_000_000,
+                data: mint_data,
+                owner: TOKEN_PROGRAM_ID,
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+        // A registered verifying key with an empty key body; `initialize_pool` only
+        // records its id and hash.
+        let mut vk_data = account_discriminator("VerifyingKeyAccount").to_vec();
+        vk_data.extend_from_slice(vk_authority.as_ref());
+        vk_data.extend_from_slice(&[1u8; 32]); // circuit_tag
+        vk_data.extend_from_slice(&[2u8; 32]); // verifying_key_id
+        vk_data.extend_from_slice(&[3u8; 32]); // hash
+        vk_data.extend_from_slice(&[0, 1]); // bump, version
+        vk_data.extend_from_slice(&0u32.to_le_bytes());
+        program_test.add_account(
+            verifying_key,
+            Account {
+                lamports: 1_000_000_000,
+                data: vk_data,
+                owner: VERIFIER_PROGRAM_ID,
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+        program_test
+    }
+
+    /// Writes an initialized SPL mint with no authorities at `address`.
+    fn set_origin_mint(
+        context: &mut solana_program_test::ProgramTestContext,
+        address: Pubkey,
+        decimals: u8,
+    ) {
+        let mut data = vec![0u8; 82];
+        data[44] = decimals;
+        data[45] = 1; // is_initialized
+        context.set_account(
+            &address,
+            &AccountSharedData::from(Account {
+                lamports: 1_000_000_000,
+                data,
+                owner: TOKEN_PROGRAM_ID,
+                executable: false,
+                rent_epoch: 0,
+            }),
+        );
+    }
+
+    /// Rewrites a queued entry so it can execute immediately.
+    async fn make_timelock_ready(
+        context: &mut solana_program_test::ProgramTestContext,
+        timelock_entry: Pubkey,
+    ) {
+        edit_timelock_entry(context, timelock_entry, |entry| entry.execute_after = 0).await;
+    }
+
+    async fn edit_timelock_entry(
+        context: &mut solana_program_test::ProgramTestContext,
+        timelock_entry: Pubkey,
+        edit: impl FnOnce(&mut ptf_factory::TimelockEntry),
+    ) {
+        let mut entry_account = context
+            .banks_client
+            .get_account(timelock_entry)
+            .await
+            .unwrap()
+            .unwrap();
+        let mut entry_state =
+            ptf_factory::TimelockEntry::try_deserialize(&mut entry_account.data.as_slice())
+                .unwrap();
+        edit(&mut entry_state);
+        let mut serialized = Vec::with_capacity(entry_account.data.len());
+        entry_state.try_serialize(&mut serialized).unwrap();
+        serialized.resize(entry_account.data.len(), 0);
+        entry_account.data = serialized;
+        context.set_account(&timelock_entry, &AccountSharedData::from(entry_account));
+    }
+
+    async fn load_timelock_index(
+        context: &mut solana_program_test::ProgramTestContext,
+        factory_state: Pubkey,
+    ) -> Vec<ptf_factory::PendingTimelock> {
+        let account = context
+            .banks_client
+            .get_account(timelock_index_pda(factory_state).0)
+            .await
+            .unwrap()
+            .unwrap();
+        ptf_factory::TimelockIndex::try_deserialize(&mut account.data.as_slice())
+            .unwrap()
+            .pending
+    }
+
+    fn account_discriminator(name: &str) -> [u8; 8] {
+        let hash = Sha256::digest(format!("account:{}", name));
+        let mut out = [0u8; 8];
+        out.copy_from_slice(&hash[..8]);
+        out
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn non_transferable_ptkn_is_recorded_in_mapping() {
+        let authority = Keypair::new();
+        let origin_mint = Keypair::new();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            0,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        set_origin_mint(&mut context, origin_mint.pubkey(), 6);
+
+        let unknown_bits = register_mint_with_ptkn_ix(
+            factory_state,
+            authority.pubkey(),
+            origin_mint.pubkey(),
+            context.payer.pubkey(),
+            6,
+            0x80,
+        );
+        let err = process_instruction(&mut context, unknown_bits, &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::PtknExtensionsInvalid);
+
+        let register_ix = register_mint_with_ptkn_ix(
+            factory_state,
+            authority.pubkey(),
+            origin_mint.pubkey(),
+            context.payer.pubkey(),
+            6,
+            ptf_factory::PTKN_EXTENSION_NON_TRANSFERABLE,
+        );
+        process_instruction(&mut context, register_ix, &[&authority])
+            .await
+            .unwrap();
+
+        let (mint_mapping, _) = mint_mapping_pda(origin_mint.pubkey());
+        let account = context
+            .banks_client
+            .get_account(mint_mapping)
+            .await
+            .unwrap()
+            .unwrap();
+        let mapping =
+            ptf_factory::MintMapping::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert!(mapping.has_ptkn);
+        assert!(mapping.ptkn_non_transferable());
+        assert_eq!(
+            mapping.ptkn_token_program,
+            ptf_factory::PtknTokenProgram::Token2022 as u8
+        );
+
+        let (ptkn_mint, _) = ptkn_mint_pda(origin_mint.pubkey());
+        let mint_account = context
+            .banks_client
+            .get_account(ptkn_mint)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            mint_account.owner.to_bytes(),
+            spl_token_2022::id().to_bytes()
+        );
+        // The NonTransferable extension makes the mint longer than a base mint.
+        assert!(mint_account.data.len() > spl_token_2022::state::Mint::LEN);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn burn_ptkn_requires_pool_authority() {
+        let authority = Keypair::new();
+        let origin_mint = Keypair::new();
+        let holder_account = Pubkey::new_unique();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            0,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        set_origin_mint(&mut context, origin_mint.pubkey(), 6);
+
+        let register_ix = register_mint_with_ptkn_ix(
+            factory_state,
+            authority.pubkey(),
+            origin_mint.pubkey(),
+            context.payer.pubkey(),
+            6,
+            0,
+        );
+        process_instruction(&mut context, register_ix, &[&authority])
+            .await
+            .unwrap();
+
+        // A pTKN balance for the payer, so only the authority check can fail.
+        let (ptkn_mint, _) = ptkn_mint_pda(origin_mint.pubkey());
+        let token_account = spl_token_2022::state::Account {
+            mint: anchor_lang::prelude::Pubkey::new_from_array(ptkn_mint.to_bytes()),
+            owner: anchor_lang::prelude::Pubkey::new_from_array(context.payer.pubkey().to_bytes()),
+            amount: 1_000,
+            state: spl_token_2022::state::AccountState::Initialized,
+            ..Default::default()
+        };
+        let mut data = vec![0u8; spl_token_2022::state::Account::LEN];
+        spl_token_2022::state::Account::pack(token_account, &mut data).unwrap();
+        context.set_account(
+            &holder_account,
+            &AccountSharedData::from(Account {
+                lamports: 10_000_000,
+                data,
+                owner: Pubkey::new_from_array(spl_token_2022::id().to_bytes()),
+                executable: false,
+                rent_epoch: 0,
+            }),
+        );
+
+        // The holder alone cannot burn: the pool authority must be a pool PDA.
+        let burn_ix = burn_ptkn_ix(
+            factory_state,
+            origin_mint.pubkey(),
+            context.payer.pubkey(),
+            holder_account,
+            context.payer.pubkey(),
+            500,
+        );
+        let err = process_instruction(&mut context, burn_ix, &[])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::PoolProgramNotAllowed);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn operator_only_freezes_and_thaws() {
+        let authority = Keypair::new();
+        let operator = Keypair::new();
+        let outsider = Keypair::new();
+        let origin_mint = Keypair::new();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            0,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+        let operator_ix = set_operator_ix(factory_state, authority.pubkey(), operator.pubkey());
+        process_instruction(&mut context, operator_ix, &[&authority])
+            .await
+            .unwrap();
+
+        set_origin_mint(&mut context, origin_mint.pubkey(), 6);
+        let (mint_mapping, _) = mint_mapping_pda(origin_mint.pubkey());
+        let register_ix = register_mint_ix(
+            factory_state,
+            authority.pubkey(),
+            mint_mapping,
+            origin_mint.pubkey(),
+            context.payer.pubkey(),
+            6,
+        );
+        process_instruction(&mut context, register_ix, &[&authority])
+            .await
+            .unwrap();
+
+        let outsider_ix = freeze_mapping_ix(factory_state, outsider.pubkey(), mint_mapping);
+        let err = process_instruction(&mut context, outsider_ix, &[&outsider])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::Unauthorized);
+
+        let freeze_ix = freeze_mapping_ix(factory_state, operator.pubkey(), mint_mapping);
+        process_instruction(&mut context, freeze_ix, &[&operator])
+            .await
+            .unwrap();
+        let account = context
+            .banks_client
+            .get_account(mint_mapping)
+            .await
+            .unwrap()
+            .unwrap();
+        let mapping =
+            ptf_factory::MintMapping::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert_eq!(mapping.status, ptf_factory::MintStatus::Frozen as u8);
+
+        let thaw_ix = thaw_mapping_ix(factory_state, operator.pubkey(), mint_mapping);
+        process_instruction(&mut context, thaw_ix, &[&operator])
+            .await
+            .unwrap();
+
+        // Nothing else accepts the operator.
+        let pause = pause_ix(factory_state, operator.pubkey(), false);
+        assert!(process_instruction(&mut context, pause, &[&operator])
+            .await
+            .is_err());
+        let update_ix = update_mint_ptkn_ix(
+            factory_state,
+            operator.pubkey(),
+            origin_mint.pubkey(),
+            false,
+            None,
+        );
+        assert!(process_instruction(&mut context, update_ix, &[&operator])
+            .await
+            .is_err());
+        let takeover_ix = set_operator_ix(factory_state, operator.pubkey(), outsider.pubkey());
+        assert!(process_instruction(&mut context, takeover_ix, &[&operator])
+            .await
+            .is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn pause_scopes_halt_only_their_instructions() {
+        let authority = Keypair::new();
+        let guardian = Keypair::new();
+        let origin_mint = Keypair::new();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            0,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+        let guardian_ix = set_guardian_ix(factory_state, authority.pubkey(), guardian.pubkey());
+        process_instruction(&mut context, guardian_ix, &[&authority])
+            .await
+            .unwrap();
+
+        let invalid = pause_scopes_ix(factory_state, guardian.pubkey(), 0x80, false);
+        let err = process_instruction(&mut context, invalid, &[&guardian])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::PauseScopesInvalid);
+
+        // The guardian halts mint updates; registration carries on.
+        let pause_ix = pause_scopes_ix(
+            factory_state,
+            guardian.pubkey(),
+            ptf_factory::PAUSE_MINT_UPDATES,
+            false,
+        );
+        process_instruction(&mut context, pause_ix, &[&guardian])
+            .await
+            .unwrap();
+
+        set_origin_mint(&mut context, origin_mint.pubkey(), 6);
+        let register_ix = register_mint_ix(
+            factory_state,
+            authority.pubkey(),
+            mint_mapping_pda(origin_mint.pubkey()).0,
+            origin_mint.pubkey(),
+            context.payer.pubkey(),
+            6,
+        );
+        process_instruction(&mut context, register_ix, &[&authority])
+            .await
+            .unwrap();
+
+        let update_ix = update_mint_ptkn_ix(
+            factory_state,
+            authority.pubkey(),
+            origin_mint.pubkey(),
+            false,
+            None,
+        );
+        let err = process_instruction(&mut context, update_ix.clone(), &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::Paused);
+
+        // Only the authority lifts a scope.
+        let unpause_ix = pause_scopes_ix(
+            factory_state,
+            guardian.pubkey(),
+            ptf_factory::PAUSE_MINT_UPDATES,
+            true,
+        );
+        assert!(process_instruction(&mut context, unpause_ix, &[&guardian])
+            .await
+            .is_err());
+        let unpause_ix = pause_scopes_ix(
+            factory_state,
+            authority.pubkey(),
+            ptf_factory::PAUSE_MINT_UPDATES,
+            true,
+        );
+        process_instruction(&mut context, unpause_ix, &[&authority])
+            .await
+            .unwrap();
+        advance_clock(&mut context, 2).await;
+        process_instruction(&mut context, update_ix, &[&authority])
+            .await
+            .unwrap();
+
+        let account = context
+            .banks_client
+            .get_account(factory_state)
+            .await
+            .unwrap()
+            .unwrap();
+        let state =
+            ptf_factory::FactoryState::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert!(!state.paused);
+        assert_eq!(state.paused_scopes, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn disabling_ptkn_requires_zero_supply() {
+        let authority = Keypair::new();
+        let origin_mint = Keypair::new();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            0,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        set_origin_mint(&mut context, origin_mint.pubkey(), 6);
+
+        let register_ix = register_mint_with_ptkn_ix(
+            factory_state,
+            authority.pubkey(),
+            origin_mint.pubkey(),
+            context.payer.pubkey(),
+            6,
+            0,
+        );
+        process_instruction(&mut context, register_ix, &[&authority])
+            .await
+            .unwrap();
+
+        // Give the twin an outstanding supply.
+        let (ptkn_mint, _) = ptkn_mint_pda(origin_mint.pubkey());
+        let set_supply = |context: &mut solana_program_test::ProgramTestContext,
+                          mut account: Account,
+                          supply: u64| {
+            account.data[36..44].copy_from_slice(&supply.to_le_bytes());
+            context.set_account(&ptkn_mint, &AccountSharedData::from(account));
+        };
+        let mint_account = context
+            .banks_client
+            .get_account(ptkn_mint)
+            .await
+            .unwrap()
+            .unwrap();
+        set_supply(&mut context, mint_account.clone(), 1_000);
+
+        let without_mint = update_mint_ptkn_ix(
+            factory_state,
+            authority.pubkey(),
+            origin_mint.pubkey(),
+            false,
+            None,
+        );
+        let err = process_instruction(&mut context, without_mint, &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::PtknMintMissing);
+
+        let disable_ix = update_mint_ptkn_ix(
+            factory_state,
+            authority.pubkey(),
+            origin_mint.pubkey(),
+            false,
+            Some(ptkn_mint),
+        );
+        let err = process_instruction(&mut context, disable_ix.clone(), &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::PtknSupplyOutstanding);
+
+        // Once every holder has burned, the twin can be switched off.
+        set_supply(&mut context, mint_account, 0);
+        advance_clock(&mut context, 2).await;
+        process_instruction(&mut context, disable_ix, &[&authority])
+            .await
+            .unwrap();
+        let account = context
+            .banks_client
+            .get_account(mint_mapping_pda(origin_mint.pubkey()).0)
+            .await
+            .unwrap()
+            .unwrap();
+        let mapping =
+            ptf_factory::MintMapping::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert!(!mapping.has_ptkn);
+        assert_eq!(mapping.ptkn_mint.to_bytes(), [0u8; 32]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn ptkn_supply_cap_updates_through_timelock() {
+        let authority = Keypair::new();
+        let origin_mint = Keypair::new();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            TIMELOCK_SECS,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        set_origin_mint(&mut context, origin_mint.pubkey(), 6);
+
+        let (mint_mapping, _) = mint_mapping_pda(origin_mint.pubkey());
+        let register_ix = register_mint_ix(
+            factory_state,
+            authority.pubkey(),
+            mint_mapping,
+            origin_mint.pubkey(),
+            context.payer.pubkey(),
+            6,
+        );
+        process_instruction(&mut context, register_ix, &[&authority])
+            .await
+            .unwrap();
+
+        let direct_ix =
+            set_ptkn_supply_cap_ix(factory_state, authority.pubkey(), mint_mapping, 1_000);
+        let err = process_instruction(&mut context, direct_ix, &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::TimelockOnlyQueue);
+
+        let salt = [3u8; 32];
+        let (timelock_entry, _) = timelock_entry_pda(factory_state, &salt);
+        let queue_ix = queue_timelock_action_ix(
+            factory_state,
+            authority.pubkey(),
+            timelock_entry,
+            context.payer.pubkey(),
+            FACTORY_PROGRAM_ID,
+            salt,
+            TimelockAction::SetPtknSupplyCap {
+                origin_mint: origin_mint.pubkey(),
+                max_ptkn_supply: 1_000,
+            },
+        );
+        process_instruction(&mut context, queue_ix, &[&authority])
+            .await
+            .unwrap();
+        make_timelock_ready(&mut context, timelock_entry).await;
+        let execute_ix = execute_timelock_action_ix(
+            factory_state,
+            timelock_entry,
+            mint_mapping,
+            FACTORY_PROGRAM_ID,
+            context.payer.pubkey(),
+        );
+        process_instruction(&mut context, execute_ix, &[])
+            .await
+            .unwrap();
+
+        let account = context
+            .banks_client
+            .get_account(mint_mapping)
+            .await
+            .unwrap()
+            .unwrap();
+        let mapping =
+            ptf_factory::MintMapping::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert_eq!(mapping.max_ptkn_supply, 1_000);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn legacy_mint_mapping_migrates_uncapped() {
+        let authority = Keypair::new();
+        let origin_mint = Keypair::new();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            0,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        set_origin_mint(&mut context, origin_mint.pubkey(), 6);
+
+        let (mint_mapping, _) = mint_mapping_pda(origin_mint.pubkey());
+        let register_ix = register_mint_ix(
+            factory_state,
+            authority.pubkey(),
+            mint_mapping,
+            origin_mint.pubkey(),
+            context.payer.pubkey(),
+            6,
+        );
+        process_instruction(&mut context, register_ix, &[&authority])
+            .await
+            .unwrap();
+
+        // Cut the account back to the original layout, with its one-byte features.
+        let mut account = context
+            .banks_client
+            .get_account(mint_mapping)
+            .await
+            .unwrap()
+            .unwrap();
+        let features = ptf_factory::MintMapping::FEATURES_OFFSET;
+        account.data.drain(features + 1..features + 8);
+        account.data[features] = FEATURE_HOOKS_ENABLED as u8;
+        account
+            .data
+            .truncate(ptf_factory::MintMapping::LEGACY_SPACE);
+        context.set_account(&mint_mapping, &AccountSharedData::from(account));
+
+        let cap_ix = set_ptkn_supply_cap_ix(factory_state, authority.pubkey(), mint_mapping, 1);
+        assert!(process_instruction(&mut context, cap_ix, &[&authority])
+            .await
+            .is_err());
+
+        let migrate_ix = migrate_mint_mapping_ix(origin_mint.pubkey(), context.payer.pubkey());
+        process_instruction(&mut context, migrate_ix, &[])
+            .await
+            .unwrap();
+
+        let account = context
+            .banks_client
+            .get_account(mint_mapping)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(account.data.len(), ptf_factory::MintMapping::SPACE);
+        let mapping =
+            ptf_factory::MintMapping::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert_eq!(
+            mapping.origin_mint.to_bytes(),
+            origin_mint.pubkey().to_bytes()
+        );
+        assert_eq!(mapping.decimals, 6);
+        assert_eq!(mapping.features.bits(), FEATURE_HOOKS_ENABLED);
+        assert!(!mapping.has_fee_override);
+        assert_eq!(mapping.max_ptkn_supply, 0);
+        assert_eq!(mapping.total_minted, 0);
+        assert_eq!(mapping.total_burned, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn narrow_factory_features_widen_on_migration() {
+        let authority = Keypair::new();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            0,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+        let integrator_bit = 1u64 << 40;
+        let features_ix = set_default_features_ix(
+            factory_state,
+            authority.pubkey(),
+            FEATURE_HOOKS_ENABLED | integrator_bit,
+        );
+        process_instruction(&mut context, features_ix, &[&authority])
+            .await
+            .unwrap();
+        let account = context
+            .banks_client
+            .get_account(factory_state)
+            .await
+            .unwrap()
+            .unwrap();
+        let state =
+            ptf_factory::FactoryState::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert_eq!(
+            state.default_features.bits(),
+            FEATURE_HOOKS_ENABLED | integrator_bit
+        );
+
+        // Rewrite the account in the layout that stored the flags in one byte.
+        let mut account = account;
+        let features = ptf_factory::FactoryState::FEATURES_OFFSET;
+        account.data.drain(features + 1..features + 8);
+        account.data[features] = FEATURE_HOOKS_ENABLED as u8;
+        assert_eq!(
+            account.data.len(),
+            ptf_factory::FactoryState::NARROW_FEATURES_SPACE
+        );
+        context.set_account(&factory_state, &AccountSharedData::from(account));
+
+        let migrate_ix = migrate_factory_state_ix(context.payer.pubkey());
+        process_instruction(&mut context, migrate_ix, &[])
+            .await
+            .unwrap();
+        let account = context
+            .banks_client
+            .get_account(factory_state)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(account.data.len(), ptf_factory::FactoryState::SPACE);
+        let state =
+            ptf_factory::FactoryState::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert_eq!(state.default_features.bits(), FEATURE_HOOKS_ENABLED);
+        assert_eq!(state.authority.to_bytes(), authority.pubkey().to_bytes());
+        assert_eq!(state.default_fee_bps, DEFAULT_FEE_BPS);
+        assert_eq!(state.timelock_seconds, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn registration_fee_accrues_to_treasury() {
+        const FEE: u64 = 1_000_000_000;
+        let authority = Keypair::new();
+        let origin_mint = Keypair::new();
+        let treasury = Pubkey::new_unique();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            0,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+        let set_ix = set_treasury_ix(factory_state, authority.pubkey(), treasury, FEE);
+        process_instruction(&mut context, set_ix, &[&authority])
+            .await
+            .unwrap();
+
+        set_origin_mint(&mut context, origin_mint.pubkey(), 6);
+
+        let (mint_mapping, _) = mint_mapping_pda(origin_mint.pubkey());
+        let register_ix = register_mint_ix(
+            factory_state,
+            authority.pubkey(),
+            mint_mapping,
+            origin_mint.pubkey(),
+            context.payer.pubkey(),
+            6,
+        );
+        process_instruction(&mut context, register_ix, &[&authority])
+            .await
+            .unwrap();
+        let account = context
+            .banks_client
+            .get_account(factory_state)
+            .await
+            .unwrap()
+            .unwrap();
+        let state =
+            ptf_factory::FactoryState::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert_eq!(state.treasury_balance, FEE);
+
+        let wrong_ix =
+            withdraw_treasury_ix(factory_state, authority.pubkey(), Pubkey::new_unique(), FEE);
+        let err = process_instruction(&mut context, wrong_ix, &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::TreasuryMismatch);
+
+        let too_much_ix =
+            withdraw_treasury_ix(factory_state, authority.pubkey(), treasury, FEE + 1);
+        let err = process_instruction(&mut context, too_much_ix, &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::InsufficientTreasury);
+
+        let withdraw_ix = withdraw_treasury_ix(factory_state, authority.pubkey(), treasury, FEE);
+        process_instruction(&mut context, withdraw_ix, &[&authority])
+            .await
+            .unwrap();
+        let treasury_account = context
+            .banks_client
+            .get_account(treasury)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(treasury_account.lamports, FEE);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn register_mint_checks_origin_mint() {
+        let authority = Keypair::new();
+        let origin_mint = Keypair::new();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            0,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        // An address with no mint behind it cannot be registered.
+        let (mint_mapping, _) = mint_mapping_pda(origin_mint.pubkey());
+        let register_ix = register_mint_ix(
+            factory_state,
+            authority.pubkey(),
+            mint_mapping,
+            origin_mint.pubkey(),
+            context.payer.pubkey(),
+            6,
+        );
+        assert!(
+            process_instruction(&mut context, register_ix, &[&authority])
+                .await
+                .is_err()
+        );
+
+        // Neither can a mint-shaped account the token programs do not own.
+        let fake_mint = Pubkey::new_unique();
+        let mut data = vec![0u8; 82];
+        data[44] = 6;
+        data[45] = 1;
+        context.set_account(
+            &fake_mint,
+            &AccountSharedData::from(Account {
+                lamports: 1_000_000_000,
+                data,
+                owner: SYSTEM_PROGRAM_ID,
+                executable: false,
+                rent_epoch: 0,
+            }),
+        );
+        let register_ix = register_mint_ix(
+            factory_state,
+            authority.pubkey(),
+            mint_mapping_pda(fake_mint).0,
+            fake_mint,
+            context.payer.pubkey(),
+            6,
+        );
+        assert!(
+            process_instruction(&mut context, register_ix, &[&authority])
+                .await
+                .is_err()
+        );
+
+        // Decimals must match the mint.
+        set_origin_mint(&mut context, origin_mint.pubkey(), 9);
+        let wrong_decimals = register_mint_ix(
+            factory_state,
+            authority.pubkey(),
+            mint_mapping,
+            origin_mint.pubkey(),
+            context.payer.pubkey(),
+            6,
+        );
+        let err = process_instruction(&mut context, wrong_decimals, &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::InvalidDecimals);
+
+        let register_ix = register_mint_ix(
+            factory_state,
+            authority.pubkey(),
+            mint_mapping,
+            origin_mint.pubkey(),
+            context.payer.pubkey(),
+            9,
+        );
+        process_instruction(&mut context, register_ix, &[&authority])
+            .await
+            .unwrap();
+        let account = context
+            .banks_client
+            .get_account(mint_mapping)
+            .await
+            .unwrap()
+            .unwrap();
+        let mapping =
+            ptf_factory::MintMapping::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert_eq!(mapping.decimals, 9);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn max_decimals_bounds_registration() {
+        let authority = Keypair::new();
+        let origin_mint = Keypair::new();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            0,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        set_origin_mint(&mut context, origin_mint.pubkey(), 18);
+
+        // The default bound keeps 18-decimal mints out.
+        let (mint_mapping, _) = mint_mapping_pda(origin_mint.pubkey());
+        let register_ix = register_mint_ix(
+            factory_state,
+            authority.pubkey(),
+            mint_mapping,
+            origin_mint.pubkey(),
+            context.payer.pubkey(),
+            18,
+        );
+        let err = process_instruction(&mut context, register_ix, &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::InvalidDecimals);
+
+        let set_ix = set_max_decimals_ix(factory_state, authority.pubkey(), 18);
+        process_instruction(&mut context, set_ix, &[&authority])
+            .await
+            .unwrap();
+        let account = context
+            .banks_client
+            .get_account(factory_state)
+            .await
+            .unwrap()
+            .unwrap();
+        let state =
+            ptf_factory::FactoryState::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert_eq!(state.decimals_limit(), 18);
+
+        // A fresh blockhash, so the retry is not the failed transaction again.
+        advance_clock(&mut context, 2).await;
+        let register_ix = register_mint_ix(
+            factory_state,
+            authority.pubkey(),
+            mint_mapping,
+            origin_mint.pubkey(),
+            context.payer.pubkey(),
+            18,
+        );
+        process_instruction(&mut context, register_ix, &[&authority])
+            .await
+            .unwrap();
+        let account = context
+            .banks_client
+            .get_account(mint_mapping)
+            .await
+            .unwrap()
+            .unwrap();
+        let mapping =
+            ptf_factory::MintMapping::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert_eq!(mapping.decimals, 18);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn link_pool_records_pool_accounts() {
+        let authority = Keypair::new();
+        let outsider = Keypair::new();
+        let origin_mint = Keypair::new();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            0,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        set_origin_mint(&mut context, origin_mint.pubkey(), 6);
+        let (mint_mapping, _) = mint_mapping_pda(origin_mint.pubkey());
+        let register_ix = register_mint_ix(
+            factory_state,
+            authority.pubkey(),
+            mint_mapping,
+            origin_mint.pubkey(),
+            context.payer.pubkey(),
+            6,
+        );
+        process_instruction(&mut context, register_ix, &[&authority])
+            .await
+            .unwrap();
+
+        // Nothing lives at the pool PDA yet.
+        let deploy = deploy_accounts(origin_mint.pubkey());
+        let link_ix = link_pool_ix(
+            factory_state,
+            authority.pubkey(),
+            origin_mint.pubkey(),
+            deploy.pool_state,
+        );
+        let err = process_instruction(&mut context, link_ix.clone(), &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::PoolProgramNotAllowed);
+
+        let pool_account = AccountSharedData::from(Account {
+            lamports: 1_000_000_000,
+            data: vec![1u8; 8],
+            owner: POOL_PROGRAM_ID,
+            executable: false,
+            rent_epoch: 0,
+        });
+        let stray_pool = Pubkey::new_unique();
+        context.set_account(&stray_pool, &pool_account);
+        let stray_ix = link_pool_ix(
+            factory_state,
+            authority.pubkey(),
+            origin_mint.pubkey(),
+            stray_pool,
+        );
+        let err = process_instruction(&mut context, stray_ix, &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::PoolAddressMismatch);
+
+        context.set_account(&deploy.pool_state, &pool_account);
+        let outsider_ix = link_pool_ix(
+            factory_state,
+            outsider.pubkey(),
+            origin_mint.pubkey(),
+            deploy.pool_state,
+        );
+        assert!(process_instruction(&mut context, outsider_ix, &[&outsider])
+            .await
+            .is_err());
+
+        advance_clock(&mut context, 2).await;
+        process_instruction(&mut context, link_ix, &[&authority])
+            .await
+            .unwrap();
+        let account = context
+            .banks_client
+            .get_account(mint_mapping)
+            .await
+            .unwrap()
+            .unwrap();
+        let mapping =
+            ptf_factory::MintMapping::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert_eq!(mapping.pool.to_bytes(), deploy.pool_state.to_bytes());
+        assert_eq!(mapping.vault.to_bytes(), deploy.vault_state.to_bytes());
+        assert_eq!(
+            mapping.commitment_tree.to_bytes(),
+            deploy.commitment_tree.to_bytes()
+        );
+        assert_eq!(
+            mapping.nullifier_set.to_bytes(),
+            deploy.nullifier_set.to_bytes()
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn verifying_key_registers_and_pins_through_timelock() {
+        let authority = Keypair::new();
+        let origin_mint = Keypair::new();
+        let mut program_test = program_test();
+        program_test.add_program("ptf_verifier_groth16", VERIFIER_PROGRAM_ID, None);
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            TIMELOCK_SECS,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        set_origin_mint(&mut context, origin_mint.pubkey(), 6);
+        let (mint_mapping, _) = mint_mapping_pda(origin_mint.pubkey());
+        let register_ix = register_mint_ix(
+            factory_state,
+            authority.pubkey(),
+            mint_mapping,
+            origin_mint.pubkey(),
+            context.payer.pubkey(),
+            6,
+        );
+        process_instruction(&mut context, register_ix, &[&authority])
+            .await
+            .unwrap();
+
+        // Registering a key changes no mint, so it needs no timelock.
+        let circuit_tag = [5u8; 32];
+        let (verifying_key, _) = verifying_key_pda(&circuit_tag, 1);
+        let vk_ix = register_verifying_key_ix(
+            factory_state,
+            authority.pubkey(),
+            context.payer.pubkey(),
+            circuit_tag,
+            1,
+            &[7u8; 64],
+        );
+        process_instruction(&mut context, vk_ix, &[&authority])
+            .await
+            .unwrap();
+        let account = context
+            .banks_client
+            .get_account(verifying_key)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(account.owner, VERIFIER_PROGRAM_ID);
+        assert_eq!(&account.data[8..40], factory_state.as_ref());
+
+        let direct_ix = pin_verifying_key_ix(
+            factory_state,
+            authority.pubkey(),
+            mint_mapping,
+            verifying_key,
+        );
+        let err = process_instruction(&mut context, direct_ix, &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::TimelockOnlyQueue);
+
+        let salt = [9u8; 32];
+        let (timelock_entry, _) = timelock_entry_pda(factory_state, &salt);
+        let queue_ix = queue_timelock_action_ix(
+            factory_state,
+            authority.pubkey(),
+            timelock_entry,
+            context.payer.pubkey(),
+            FACTORY_PROGRAM_ID,
+            salt,
+            TimelockAction::PinVerifyingKey {
+                origin_mint: origin_mint.pubkey(),
+                verifying_key,
+            },
+        );
+        process_instruction(&mut context, queue_ix, &[&authority])
+            .await
+            .unwrap();
+        make_timelock_ready(&mut context, timelock_entry).await;
+
+        let mut execute_ix = execute_timelock_action_ix(
+            factory_state,
+            timelock_entry,
+            mint_mapping,
+            FACTORY_PROGRAM_ID,
+            context.payer.pubkey(),
+        );
+        let err = process_instruction(&mut context, execute_ix.clone(), &[])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::VerifyingKeyMissing);
+
+        execute_ix.accounts.extend([
+            AccountMeta::new_readonly(FACTORY_PROGRAM_ID, false), // no treasury
+            AccountMeta::new_readonly(verifying_key, false),
+        ]);
+        process_instruction(&mut context, execute_ix, &[])
+            .await
+            .unwrap();
+        let account = context
+            .banks_client
+            .get_account(mint_mapping)
+            .await
+            .unwrap()
+            .unwrap();
+        let mapping =
+            ptf_factory::MintMapping::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert_eq!(mapping.circuit_tag, circuit_tag);
+        assert_eq!(mapping.verifying_key.to_bytes(), verifying_key.to_bytes());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn timelock_administers_factory_governed_pool() {
+        let authority = Keypair::new();
+        let origin_mint = Pubkey::new_unique();
+        let verifying_key = Pubkey::new_unique();
+        let program_test = deploy_program_test(origin_mint, verifying_key, authority.pubkey());
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            TIMELOCK_SECS,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+
+        let (mint_mapping, _) = mint_mapping_pda(origin_mint);
+        let deploy = deploy_accounts(origin_mint);
+        let deploy_ix = register_and_deploy_ix(
+            factory_state,
+            authority.pubkey(),
+            mint_mapping,
+            origin_mint,
+            context.payer.pubkey(),
+            verifying_key,
+            &deploy,
+            8,
+            true,
+        );
+        process_instruction(&mut context, deploy_ix, &[&authority])
+            .await
+            .unwrap();
+        let pool = pool_state_data(&mut context, deploy.pool_state).await;
+        assert_eq!(pool_field!(pool, authority, 32), factory_state.to_bytes());
+        assert_eq!(
+            pool_field!(pool, fee_recipient, 32),
+            authority.pubkey().to_bytes()
+        );
+        assert_eq!(
+            u16::from_le_bytes(pool_field!(pool, unshield_fee_bps, 2)),
+            DEFAULT_FEE_BPS
+        );
+
+        // The pool's own timelock must not block changes the factory timelock released.
+        let mut pool_account = context
+            .banks_client
+            .get_account(deploy.pool_state)
+            .await
+            .unwrap()
+            .unwrap();
+        let offset = 8 + std::mem::offset_of!(ptf_pool::PoolState, timelock_seconds);
+        pool_account.data[offset..offset + 8].copy_from_slice(&86_400i64.to_le_bytes());
+        context.set_account(&deploy.pool_state, &AccountSharedData::from(pool_account));
+
+        let actions = [
+            TimelockAction::SetPoolFee {
+                origin_mint,
+                shield_fee_bps: 7,
+                unshield_fee_bps: 11,
+                shield_flat_fee: 0,
+                unshield_flat_fee: 1_000,
+            },
+            TimelockAction::SetPoolFeatures {
+                origin_mint,
+                features: FEATURE_HOOKS_ENABLED as u8,
+            },
+            TimelockAction::PausePool { origin_mint },
+        ];
+        for (index, action) in actions.into_iter().enumerate() {
+            let salt = [20 + index as u8; 32];
+            let (timelock_entry, _) = timelock_entry_pda(factory_state, &salt);
+            let queue_ix = queue_timelock_action_ix(
+                factory_state,
+                authority.pubkey(),
+                timelock_entry,
+                context.payer.pubkey(),
+                FACTORY_PROGRAM_ID,
+                salt,
+                action,
+            );
+            process_instruction(&mut context, queue_ix, &[&authority])
+                .await
+                .unwrap();
+            make_timelock_ready(&mut context, timelock_entry).await;
+
+            let mut execute_ix = execute_timelock_action_ix(
+                factory_state,
+                timelock_entry,
+                FACTORY_PROGRAM_ID,
+                FACTORY_PROGRAM_ID,
+                context.payer.pubkey(),
+            );
+            if index == 0 {
+                let err = process_instruction(&mut context, execute_ix.clone(), &[])
+                    .await
+                    .unwrap_err();
+                assert_anchor_error(err, FactoryError::PoolAccountsMissing);
+            }
+            execute_ix.accounts.extend([
+                AccountMeta::new_readonly(FACTORY_PROGRAM_ID, false), // no treasury
+                AccountMeta::new_readonly(FACTORY_PROGRAM_ID, false), // no verifying key
+                AccountMeta::new(deploy.pool_state, false),
+                AccountMeta::new_readonly(POOL_PROGRAM_ID, false),
+                AccountMeta::new(deploy.nullifier_set, false),
+            ]);
+            process_instruction(&mut context, execute_ix, &[])
+                .await
+                .unwrap();
+        }
 
-    async fn advance_clock(context: &mut solana_program_test::ProgramTestContext, slots: u64) {
-        let current_slot = context.banks_client.get_root_slot().await.unwrap();
-        context.warp_to_slot(current_slot + slots).unwrap();
-        context.last_blockhash = context.banks_client.get_latest_blockhash().await.unwrap();
+        let pool = pool_state_data(&mut context, deploy.pool_state).await;
+        assert_eq!(u16::from_le_bytes(pool_field!(pool, shield_fee_bps, 2)), 7);
+        assert_eq!(
+            u16::from_le_bytes(pool_field!(pool, unshield_fee_bps, 2)),
+            11
+        );
+        assert_eq!(
+            u64::from_le_bytes(pool_field!(pool, unshield_flat_fee, 8)),
+            1_000
+        );
+        assert_eq!(
+            pool_field!(pool, features, 1)[0],
+            FEATURE_HOOKS_ENABLED as u8
+        );
+        assert_eq!(pool_field!(pool, paused, 1), [1]);
     }
 
     #[tokio::test(flavor = "multi_thread")]
     #[ignore = "requires `anchor build` artifacts under target/deploy"]
-    async fn timelock_blocks_direct_update() {
+    async fn protocol_config_updates_through_timelock() {
         let authority = Keypair::new();
         let program_test = program_test();
         let mut context = program_test.start_with_context().await;
@@ -329,116 +4774,462 @@ mod tests {
         process_instruction(&mut context, init_ix, &[])
             .await
             .unwrap();
+        let config_ix = initialize_protocol_config_ix(
+            factory_state,
+            authority.pubkey(),
+            context.payer.pubkey(),
+        );
+        process_instruction(&mut context, config_ix, &[&authority])
+            .await
+            .unwrap();
 
-        let set_ix =
-            set_default_features_ix(factory_state, authority.pubkey(), FEATURE_HOOKS_ENABLED);
-        let err = process_instruction(&mut context, set_ix, &[&authority])
+        let direct_ix = set_kill_switch_ix(factory_state, authority.pubkey(), true);
+        let err = process_instruction(&mut context, direct_ix, &[&authority])
             .await
             .unwrap_err();
         assert_anchor_error(err, FactoryError::TimelockOnlyQueue);
+
+        let salt = [30u8; 32];
+        let (timelock_entry, _) = timelock_entry_pda(factory_state, &salt);
+        let queue_ix = queue_timelock_action_ix(
+            factory_state,
+            authority.pubkey(),
+            timelock_entry,
+            context.payer.pubkey(),
+            FACTORY_PROGRAM_ID,
+            salt,
+            TimelockAction::SetProtocolMaxFee {
+                max_fee_bps: 10_001,
+            },
+        );
+        let err = process_instruction(&mut context, queue_ix, &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::InvalidFeeBps);
+
+        let forked_verifier = Pubkey::new_unique();
+        let actions = [
+            TimelockAction::SetKillSwitch { engaged: true },
+            TimelockAction::SetProtocolMaxFee { max_fee_bps: 50 },
+            TimelockAction::SetVerifierProgram {
+                program: forked_verifier,
+                allowed: true,
+            },
+        ];
+        for (index, action) in actions.into_iter().enumerate() {
+            let salt = [31 + index as u8; 32];
+            let (timelock_entry, _) = timelock_entry_pda(factory_state, &salt);
+            let queue_ix = queue_timelock_action_ix(
+                factory_state,
+                authority.pubkey(),
+                timelock_entry,
+                context.payer.pubkey(),
+                FACTORY_PROGRAM_ID,
+                salt,
+                action,
+            );
+            process_instruction(&mut context, queue_ix, &[&authority])
+                .await
+                .unwrap();
+            make_timelock_ready(&mut context, timelock_entry).await;
+
+            let mut execute_ix = execute_timelock_action_ix(
+                factory_state,
+                timelock_entry,
+                FACTORY_PROGRAM_ID,
+                FACTORY_PROGRAM_ID,
+                context.payer.pubkey(),
+            );
+            if index == 0 {
+                let err = process_instruction(&mut context, execute_ix.clone(), &[])
+                    .await
+                    .unwrap_err();
+                assert_anchor_error(err, FactoryError::ProtocolConfigMissing);
+            }
+            execute_ix.accounts.extend([
+                AccountMeta::new_readonly(FACTORY_PROGRAM_ID, false), // no treasury
+                AccountMeta::new_readonly(FACTORY_PROGRAM_ID, false), // no verifying key
+                AccountMeta::new_readonly(FACTORY_PROGRAM_ID, false), // no pool state
+                AccountMeta::new_readonly(FACTORY_PROGRAM_ID, false), // no pool program
+                AccountMeta::new_readonly(FACTORY_PROGRAM_ID, false), // no nullifier set
+                AccountMeta::new(protocol_config_pda().0, false),
+            ]);
+            process_instruction(&mut context, execute_ix, &[])
+                .await
+                .unwrap();
+        }
+
+        let account = context
+            .banks_client
+            .get_account(protocol_config_pda().0)
+            .await
+            .unwrap()
+            .unwrap();
+        let config =
+            ptf_factory::ProtocolConfig::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert_eq!(config.factory.to_bytes(), factory_state.to_bytes());
+        assert!(config.kill_switch);
+        assert_eq!(config.max_fee_bps, 50);
+        assert_eq!(config.verifier_programs_len, 2);
+        assert_eq!(
+            config.verifier_programs[0].to_bytes(),
+            VERIFIER_PROGRAM_ID.to_bytes()
+        );
+        assert_eq!(
+            config.verifier_programs[1].to_bytes(),
+            forked_verifier.to_bytes()
+        );
     }
 
     #[tokio::test(flavor = "multi_thread")]
     #[ignore = "requires `anchor build` artifacts under target/deploy"]
-    async fn timelock_queue_and_execute_mint_update() {
+    async fn register_mint_applies_registration_template() {
         let authority = Keypair::new();
-        let origin_mint = Keypair::new();
-
+        let origin_mint = Pubkey::new_unique();
         let program_test = program_test();
-
         let mut context = program_test.start_with_context().await;
+        set_origin_mint(&mut context, origin_mint, 6);
+
         let (factory_state, _) = factory_state_pda();
         let init_ix = initialize_factory_ix(
             factory_state,
             context.payer.pubkey(),
             authority.pubkey(),
             DEFAULT_FEE_BPS,
-            TIMELOCK_SECS,
+            0,
         );
         process_instruction(&mut context, init_ix, &[])
             .await
             .unwrap();
 
-        let (mint_mapping, _) = mint_mapping_pda(origin_mint.pubkey());
-        let register_ix = register_mint_ix(
+        let mut params = RegistrationTemplateParams {
+            feature_flags: Some(FEATURE_HOOKS_ENABLED),
+            fee_bps_override: Some(10_001),
+            ..Default::default()
+        };
+        let template_ix = set_registration_template_ix(
+            factory_state,
+            authority.pubkey(),
+            context.payer.pubkey(),
+            7,
+            *b"stablecoins\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0",
+            &params,
+        );
+        let err = process_instruction(&mut context, template_ix, &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::InvalidFeeBps);
+
+        params.fee_bps_override = Some(42);
+        let template_ix = set_registration_template_ix(
+            factory_state,
+            authority.pubkey(),
+            context.payer.pubkey(),
+            7,
+            *b"stablecoins\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0",
+            &params,
+        );
+        process_instruction(&mut context, template_ix, &[&authority])
+            .await
+            .unwrap();
+
+        let (mint_mapping, _) = mint_mapping_pda(origin_mint);
+        let mut register_ix = register_mint_ix(
             factory_state,
             authority.pubkey(),
             mint_mapping,
-            origin_mint.pubkey(),
+            origin_mint,
             context.payer.pubkey(),
             6,
         );
+        set_template_account(&mut register_ix, registration_template_pda(7).0);
         process_instruction(&mut context, register_ix, &[&authority])
             .await
             .unwrap();
 
-        let salt = [7u8; 32];
-        let (timelock_entry, _) = timelock_entry_pda(factory_state, &salt);
-        let queue_ix = queue_timelock_action_ix(
+        let account = context
+            .banks_client
+            .get_account(mint_mapping)
+            .await
+            .unwrap()
+            .unwrap();
+        let mapping =
+            ptf_factory::MintMapping::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert!(mapping.has_fee_override);
+        assert_eq!(mapping.fee_bps_override, 42);
+        assert_eq!(mapping.features.bits(), FEATURE_HOOKS_ENABLED);
+        assert_eq!(
+            mapping.template.to_bytes(),
+            registration_template_pda(7).0.to_bytes()
+        );
+
+        let close_ix = close_registration_template_ix(factory_state, authority.pubkey(), 7);
+        process_instruction(&mut context, close_ix, &[&authority])
+            .await
+            .unwrap();
+        assert!(context
+            .banks_client
+            .get_account(registration_template_pda(7).0)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn register_and_deploy_applies_template_to_pool() {
+        let authority = Keypair::new();
+        let origin_mint = Pubkey::new_unique();
+        let verifying_key = Pubkey::new_unique();
+        let hook_program = Pubkey::new_unique();
+        let program_test = deploy_program_test(origin_mint, verifying_key, authority.pubkey());
+        let mut context = program_test.start_with_context().await;
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            0,
+        );
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+        let template_ix = set_registration_template_ix(
             factory_state,
             authority.pubkey(),
-            timelock_entry,
             context.payer.pubkey(),
-            mint_mapping,
-            salt,
-            TimelockAction::UpdateMint {
-                origin_mint: origin_mint.pubkey(),
-                params: UpdateMintParams {
-                    enable_ptkn: None,
-                    features: Some(FEATURE_HOOKS_ENABLED),
-                    fee_bps_override: None,
-                },
+            1,
+            [0u8; 32],
+            &RegistrationTemplateParams {
+                fee_bps_override: Some(9),
+                denominations: vec![1_000, 10_000],
+                post_shield_program: hook_program,
+                ..Default::default()
             },
         );
-        process_instruction(&mut context, queue_ix, &[&authority])
+        process_instruction(&mut context, template_ix, &[&authority])
             .await
             .unwrap();
 
-        let execute_ix = execute_timelock_action_ix(
+        let (mint_mapping, _) = mint_mapping_pda(origin_mint);
+        let deploy = deploy_accounts(origin_mint);
+        let mut deploy_ix = register_and_deploy_ix(
             factory_state,
-            timelock_entry,
+            authority.pubkey(),
             mint_mapping,
+            origin_mint,
+            context.payer.pubkey(),
+            verifying_key,
+            &deploy,
+            8,
+            false,
+        );
+        set_template_account(&mut deploy_ix, registration_template_pda(1).0);
+        process_instruction(&mut context, deploy_ix, &[&authority])
+            .await
+            .unwrap();
+
+        let pool = pool_state_data(&mut context, deploy.pool_state).await;
+        assert_eq!(
+            u16::from_le_bytes(pool_field!(pool, unshield_fee_bps, 2)),
+            9
+        );
+        assert_eq!(pool_field!(pool, denominations_len, 1), [2]);
+        assert_eq!(
+            u64::from_le_bytes(pool_field!(pool, denominations, 8)),
+            1_000
+        );
+        assert_eq!(
+            pool_field!(pool, features, 1)[0] & FEATURE_HOOKS_ENABLED as u8,
+            FEATURE_HOOKS_ENABLED as u8
+        );
+        assert_eq!(pool_field!(pool, hook_config_present, 1), [1]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn bonded_registration_refunds_and_slashes() {
+        const BOND: u64 = 500_000_000;
+        let authority = Keypair::new();
+        let kept_mint = Pubkey::new_unique();
+        let rejected_mint = Pubkey::new_unique();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+        set_origin_mint(&mut context, kept_mint, 6);
+        set_origin_mint(&mut context, rejected_mint, 9);
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
             authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            0,
         );
-        let err = process_instruction(&mut context, execute_ix.clone(), &[&authority])
+        process_instruction(&mut context, init_ix, &[])
+            .await
+            .unwrap();
+        let registrant = context.payer.pubkey();
+
+        let register_ix = register_mint_bonded_ix(factory_state, registrant, kept_mint);
+        let err = process_instruction(&mut context, register_ix.clone(), &[])
             .await
             .unwrap_err();
-        assert_anchor_error(err, FactoryError::TimelockNotReady);
+        assert_anchor_error(err, FactoryError::PermissionlessRegistrationDisabled);
 
-        advance_clock(&mut context, 10).await;
-        {
-            let mut entry_account = context
+        let bond_ix = set_registration_bond_ix(factory_state, authority.pubkey(), BOND);
+        process_instruction(&mut context, bond_ix, &[&authority])
+            .await
+            .unwrap();
+        process_instruction(&mut context, register_ix, &[])
+            .await
+            .unwrap();
+        let register_ix = register_mint_bonded_ix(factory_state, registrant, rejected_mint);
+        process_instruction(&mut context, register_ix, &[])
+            .await
+            .unwrap();
+
+        let account = context
+            .banks_client
+            .get_account(mint_mapping_pda(rejected_mint).0)
+            .await
+            .unwrap()
+            .unwrap();
+        let mapping =
+            ptf_factory::MintMapping::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert_eq!(mapping.decimals, 9);
+        assert!(!mapping.has_ptkn);
+        assert_eq!(mapping.status, ptf_factory::MintStatus::Frozen as u8);
+        let account = context
+            .banks_client
+            .get_account(registration_bond_pda(rejected_mint).0)
+            .await
+            .unwrap()
+            .unwrap();
+        let bond =
+            ptf_factory::RegistrationBond::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert_eq!(bond.registrant.to_bytes(), registrant.to_bytes());
+        assert_eq!(bond.lamports, BOND);
+        let close_ix = close_mint_mapping_ix(factory_state, authority.pubkey(), rejected_mint);
+        let err = process_instruction(&mut context, close_ix.clone(), &[&authority])
+            .await
+            .unwrap_err();
+        assert_anchor_error(err, FactoryError::RegistrationBondOutstanding);
+
+        let refund_ix = resolve_registration_bond_ix(
+            factory_state,
+            authority.pubkey(),
+            kept_mint,
+            registrant,
+            false,
+        );
+        process_instruction(&mut context, refund_ix, &[&authority])
+            .await
+            .unwrap();
+        let slash_ix = resolve_registration_bond_ix(
+            factory_state,
+            authority.pubkey(),
+            rejected_mint,
+            registrant,
+            true,
+        );
+        process_instruction(&mut context, slash_ix, &[&authority])
+            .await
+            .unwrap();
+
+        for origin_mint in [kept_mint, rejected_mint] {
+            assert!(context
                 .banks_client
-                .get_account(timelock_entry)
+                .get_account(registration_bond_pda(origin_mint).0)
+                .await
+                .unwrap()
+                .is_none());
+        }
+        let account = context
+            .banks_client
+            .get_account(factory_state)
+            .await
+            .unwrap()
+            .unwrap();
+        let state =
+            ptf_factory::FactoryState::try_deserialize(&mut account.data.as_slice()).unwrap();
+        assert_eq!(state.treasury_balance, BOND);
+        for (origin_mint, status) in [
+            (kept_mint, ptf_factory::MintStatus::Active),
+            (rejected_mint, ptf_factory::MintStatus::Frozen),
+        ] {
+            let account = context
+                .banks_client
+                .get_account(mint_mapping_pda(origin_mint).0)
                 .await
                 .unwrap()
                 .unwrap();
-            let mut entry_state =
-                ptf_factory::TimelockEntry::try_deserialize(&mut entry_account.data.as_slice())
-                    .unwrap();
-            entry_state.execute_after = 0;
-
-            let mut serialized = Vec::with_capacity(entry_account.data.len());
-            entry_state.try_serialize(&mut serialized).unwrap();
-            if serialized.len() < entry_account.data.len() {
-                serialized.resize(entry_account.data.len(), 0);
-            }
-            entry_account.data = serialized;
-            context.set_account(&timelock_entry, &AccountSharedData::from(entry_account));
+            let mapping =
+                ptf_factory::MintMapping::try_deserialize(&mut account.data.as_slice()).unwrap();
+            assert_eq!(mapping.status, status as u8);
         }
+        process_instruction(&mut context, close_ix, &[&authority])
+            .await
+            .unwrap();
+    }
 
-        process_instruction(&mut context, execute_ix, &[&authority])
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires `anchor build` artifacts under target/deploy"]
+    async fn register_mint_emits_events_through_self_cpi() {
+        let authority = Keypair::new();
+        let origin_mint = Pubkey::new_unique();
+        let program_test = program_test();
+        let mut context = program_test.start_with_context().await;
+        set_origin_mint(&mut context, origin_mint, 6);
+
+        let (factory_state, _) = factory_state_pda();
+        let init_ix = initialize_factory_ix(
+            factory_state,
+            context.payer.pubkey(),
+            authority.pubkey(),
+            DEFAULT_FEE_BPS,
+            0,
+        );
+        process_instruction(&mut context, init_ix, &[])
             .await
             .unwrap();
 
-        let account = context
+        let (mint_mapping, _) = mint_mapping_pda(origin_mint);
+        let register_ix = register_mint_ix(
+            factory_state,
+            authority.pubkey(),
+            mint_mapping,
+            origin_mint,
+            context.payer.pubkey(),
+            6,
+        );
+        let mut tx = Transaction::new_with_payer(&[register_ix], Some(&context.payer.pubkey()));
+        tx.sign(&[&context.payer, &authority], context.last_blockhash);
+        let result = context
             .banks_client
-            .get_account(mint_mapping)
+            .process_transaction_with_metadata(tx)
             .await
-            .unwrap()
             .unwrap();
-        let features_byte = account.data[8 + 32 + 32 + 1 + 1 + 1];
-        assert_eq!(features_byte, FEATURE_HOOKS_ENABLED);
+        assert!(result.result.is_ok());
+        let logs = result.metadata.unwrap().log_messages;
+        let self_invoke = format!("Program {FACTORY_PROGRAM_ID} invoke [2]");
+        assert!(logs.contains(&self_invoke));
+        assert!(!logs.iter().any(|line| line.starts_with("Program data:")));
+    }
+
+    async fn pool_state_data(
+        context: &mut solana_program_test::ProgramTestContext,
+        pool_state: Pubkey,
+    ) -> Vec<u8> {
+        context
+            .banks_client
+            .get_account(pool_state)
+            .await
+            .unwrap()
+            .unwrap()
+            .data
     }
 
     fn assert_anchor_error(err: BanksClientError, expected: FactoryError) {
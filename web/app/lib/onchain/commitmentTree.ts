@@ -5,14 +5,17 @@ import { poseidonHash2 } from './poseidon';
 const DISCRIMINATOR_SIZE = 8;
 const PUBKEY_SIZE = 32;
 const U64_SIZE = 8;
-const DEPTH = 32;
+const MAX_DEPTH = 32;
 const MAX_CANOPY = 16;
+// Header size after the discriminator; the frontier and zero hashes follow it.
+const HEADER_SIZE = 1752;
 
 export interface CommitmentTreeState {
   pool: PublicKey;
   canopyDepth: number;
   nextIndex: bigint;
   currentRoot: Uint8Array;
+  depth: number;
   frontier: Uint8Array[];
   zeroes: Uint8Array[];
 }
@@ -37,31 +40,35 @@ export function decodeCommitmentTree(accountData: Uint8Array): CommitmentTreeSta
   const currentRoot = accountData.slice(offset, offset + PUBKEY_SIZE);
   offset += PUBKEY_SIZE;
 
+  offset += MAX_CANOPY * PUBKEY_SIZE; // canopy
+  offset += MAX_CANOPY * PUBKEY_SIZE; // recent commitments
+  offset += MAX_CANOPY * PUBKEY_SIZE; // recent amount commitments
+  offset += MAX_CANOPY * U64_SIZE; // recent indices
+  offset += 1; // recent_len
+  offset += 1; // bump
+  // Trees created before depth became configurable store 0 and run at full depth.
+  const depth = accountData[offset] || MAX_DEPTH;
+
+  // Version 2 keeps `depth` frontier entries, then `depth` zero hashes, after the header.
+  offset = DISCRIMINATOR_SIZE + HEADER_SIZE;
   const frontier: Uint8Array[] = [];
-  for (let i = 0; i < DEPTH; i += 1) {
+  for (let i = 0; i < depth; i += 1) {
     frontier.push(accountData.slice(offset, offset + PUBKEY_SIZE));
     offset += PUBKEY_SIZE;
   }
 
   const zeroes: Uint8Array[] = [];
-  for (let i = 0; i < DEPTH; i += 1) {
+  for (let i = 0; i < depth; i += 1) {
     zeroes.push(accountData.slice(offset, offset + PUBKEY_SIZE));
     offset += PUBKEY_SIZE;
   }
 
-  offset += MAX_CANOPY * PUBKEY_SIZE; // canopy
-  offset += MAX_CANOPY * PUBKEY_SIZE; // recent commitments
-  offset += MAX_CANOPY * PUBKEY_SIZE; // recent amount commitments
-  offset += MAX_CANOPY * U64_SIZE; // recent indices
-  offset += 1; // recent_len
-  offset += 1; // bump
-  // trailing padding ignored
-
   return {
     pool,
     canopyDepth,
     nextIndex,
     currentRoot,
+    depth,
     frontier,
     zeroes
   };
@@ -76,7 +83,7 @@ export async function computeNextCommitmentTreeState(
   let node = commitment;
   let index = Number(state.nextIndex);
 
-  for (let level = 0; level < DEPTH; level += 1) {
+  for (let level = 0; level < state.depth; level += 1) {
     if (index % 2 === 0) {
       frontier[level] = node;
       const left = bytesToBigIntLE(node);
@@ -49,6 +49,10 @@ export function deriveFactoryState(): PublicKey {
   )[0];
 }
 
+export function deriveProtocolConfig(): PublicKey {
+  return PublicKey.findProgramAddressSync([textEncoder.encode('protocol-config')], FACTORY_PROGRAM_ID)[0];
+}
+
 export function deriveVerifyingKey(): PublicKey {
   return PublicKey.findProgramAddressSync(
     [textEncoder.encode('vk'), CIRCUIT_TAGS.shield, new Uint8Array([VERIFIER_VERSION])],
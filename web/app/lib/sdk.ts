@@ -37,6 +37,7 @@ import {
   deriveVerifyingKey,
   deriveMintMapping,
   deriveFactoryState,
+  deriveProtocolConfig,
   deriveShieldClaim
 } from './onchain/pdas';
 import { decodeCommitmentTree } from './onchain/commitmentTree';
@@ -127,6 +128,9 @@ interface WrapParams extends BaseParams {
   recipient?: string;
   twinMint?: string | null;
   lookupTable?: string;
+  encryptedNote?: Uint8Array;
+  /** Wallet credited for the referral; emitted in `PTFShieldReferred`. */
+  referrer?: string | null;
 }
 
 interface UnwrapParams extends BaseParams {
@@ -147,6 +151,9 @@ interface TransferParams {
   outputCommitments: readonly string[];
   outputAmountCommitments: readonly string[];
   lookupTable?: string;
+  encryptedNote?: Uint8Array;
+  /** One ciphertext per output commitment, in the same order; omit to send none. */
+  outputCiphertexts?: readonly Uint8Array[];
 }
 
 interface TransferFromParams extends TransferParams {
@@ -388,7 +395,9 @@ export async function wrap(params: WrapParams): Promise<string> {
     amount_commit: Array.from(amountCommitmentBytes),
     amount: new BN(amount.toString()),
     proof: Buffer.from(decodedProof.proof),
-    public_inputs: Buffer.from(decodedProof.publicInputs)
+    public_inputs: Buffer.from(decodedProof.publicInputs),
+    encrypted_note: Buffer.from(params.encryptedNote ?? []),
+    referrer: params.referrer ? new PublicKey(params.referrer) : null
   };
   const canonicalCommitmentBytes = extractCommitmentByteOutputs(shieldArgs.public_inputs);
   const shaLeafDigest = canonicalCommitmentBytes
@@ -682,6 +691,7 @@ export async function unwrap(params: UnwrapParams): Promise<string> {
   const vaultStateKey = deriveVaultState(originMintKey);
   const mintMappingKey = deriveMintMapping(originMintKey);
   const factoryStateKey = deriveFactoryState();
+  const protocolConfigKey = deriveProtocolConfig();
   const verifyingKey = deriveVerifyingKey();
 
   const commitmentTreeAccount = await connection.getAccountInfo(commitmentTreeKey);
@@ -790,10 +800,12 @@ export async function unwrap(params: UnwrapParams): Promise<string> {
 
   const destinationMint = redeemToTwin ? twinMintKey! : originMintKey;
   const destinationTokenProgram = redeemToTwin ? TOKEN_2022_PROGRAM_ID : TOKEN_PROGRAM_ID;
+  // DAO treasuries and program wallets are PDAs; the pool only pays them when asked to.
+  const allowOwnerOffCurve = !PublicKey.isOnCurve(destinationKey.toBytes());
   const destinationTokenAccount = await getAssociatedTokenAddress(
     destinationMint,
     destinationKey,
-    false,
+    allowOwnerOffCurve,
     destinationTokenProgram,
     ASSOCIATED_TOKEN_PROGRAM_ID
   );
@@ -854,8 +866,12 @@ export async function unwrap(params: UnwrapParams): Promise<string> {
     output_commitments: [Array.from(changeCommitmentBytes)],
     output_amount_commitments: [Array.from(changeAmountCommitmentBytes)],
     amount: new BN(params.amount.toString()),
+    max_fee: new BN(bytesToBigIntLE(feeFieldBytes).toString()),
     proof: decodedProof.proof,
-    public_inputs: decodedProof.publicInputs
+    public_inputs: decodedProof.publicInputs,
+    create_destination_ata: false,
+    allow_owner_off_curve: allowOwnerOffCurve,
+    unlock_timestamp: null
   };
 
   if (process.env.NEXT_PUBLIC_DEBUG_WRAP === 'true') {
@@ -923,7 +939,7 @@ export async function unwrap(params: UnwrapParams): Promise<string> {
     { pubkey: nullifierSetKey, isSigner: false, isWritable: true },
     { pubkey: commitmentTreeKey, isSigner: false, isWritable: true },
     { pubkey: noteLedgerKey, isSigner: false, isWritable: true },
-    { pubkey: mintMappingKey, isSigner: false, isWritable: false },
+    { pubkey: mintMappingKey, isSigner: false, isWritable: true },
     { pubkey: VERIFIER_PROGRAM_ID, isSigner: false, isWritable: false },
     { pubkey: verifyingKey, isSigner: false, isWritable: false },
     { pubkey: vaultStateKey, isSigner: false, isWritable: true },
@@ -948,6 +964,7 @@ export async function unwrap(params: UnwrapParams): Promise<string> {
   keys.push(
     { pubkey: VAULT_PROGRAM_ID, isSigner: false, isWritable: false },
     { pubkey: factoryStateKey, isSigner: false, isWritable: false },
+    { pubkey: protocolConfigKey, isSigner: false, isWritable: false },
     { pubkey: FACTORY_PROGRAM_ID, isSigner: false, isWritable: false },
     { pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false }
   );
@@ -1044,7 +1061,9 @@ export async function transfer(params: TransferParams): Promise<string> {
       'output_amount_commitments'
     ),
     proof: Buffer.from(decodedProof.proof),
-    public_inputs: Buffer.from(decodedProof.publicInputs)
+    public_inputs: Buffer.from(decodedProof.publicInputs),
+    encrypted_note: Buffer.from(params.encryptedNote ?? []),
+    output_ciphertexts: (params.outputCiphertexts ?? []).map((ciphertext) => Buffer.from(ciphertext))
   };
 
   const instructions: TransactionInstruction[] = [];
@@ -1183,7 +1202,9 @@ export async function transferFrom(params: TransferFromParams): Promise<string>
       'output_amount_commitments'
     ),
     proof: Buffer.from(decodedProof.proof),
-    public_inputs: Buffer.from(decodedProof.publicInputs)
+    public_inputs: Buffer.from(decodedProof.publicInputs),
+    encrypted_note: Buffer.from(params.encryptedNote ?? []),
+    output_ciphertexts: (params.outputCiphertexts ?? []).map((ciphertext) => Buffer.from(ciphertext))
   };
 
   const transferFromArgs = {
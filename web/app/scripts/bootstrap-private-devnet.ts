@@ -183,13 +183,17 @@ function buildAccountMetas(
       optional?: boolean;
     }>;
   },
-  mapping: Record<string, PublicKey>
+  mapping: Record<string, PublicKey>,
+  programId: PublicKey
 ): AccountMeta[] {
   const metas: AccountMeta[] = [];
   instruction.accounts.forEach((account) => {
     const pubkey = mapping[account.name];
     if (!pubkey) {
       if (account.optional) {
+        // Anchor reads the program ID as an absent optional account, which keeps the
+        // accounts after it (such as the event authority) in place.
+        metas.push({ pubkey: programId, isWritable: false, isSigner: false });
         return;
       }
       throw new Error(`Missing account mapping for ${account.name}`);
@@ -250,7 +254,7 @@ async function sendInstruction(
       throw error;
     }
   }
-  const keys = buildAccountMetas(ixDef, accounts);
+  const keys = buildAccountMetas(ixDef, accounts, programId);
   const instructions = [
     ...preInstructions,
     new TransactionInstruction({ programId, keys, data })
@@ -463,6 +467,38 @@ async function ensureFactory(ctx: BootstrapContext): Promise<void> {
   console.log(`Initialised factory state ${factoryState.toBase58()}`);
 }
 
+async function ensureProtocolConfig(ctx: BootstrapContext): Promise<void> {
+  const factoryState = PublicKey.findProgramAddressSync(
+    [Buffer.from('factory'), PROGRAM_IDS.factory.toBuffer()],
+    PROGRAM_IDS.factory
+  )[0];
+  const protocolConfig = PublicKey.findProgramAddressSync(
+    [Buffer.from('protocol-config')],
+    PROGRAM_IDS.factory
+  )[0];
+  if (await ctx.provider.connection.getAccountInfo(protocolConfig)) {
+    console.log(`Protocol config already initialised at ${protocolConfig.toBase58()}`);
+    return;
+  }
+
+  await sendInstruction(
+    ctx,
+    ctx.idls.factory,
+    ctx.coders.factory,
+    PROGRAM_IDS.factory,
+    'initialize_protocol_config',
+    {
+      factory_state: factoryState,
+      authority: ctx.payer.publicKey,
+      protocol_config: protocolConfig,
+      payer: ctx.payer.publicKey,
+      system_program: SystemProgram.programId
+    },
+    {}
+  );
+  console.log(`Initialised protocol config ${protocolConfig.toBase58()}`);
+}
+
 async function ensureVerifyingKey(
   ctx: BootstrapContext,
   circuit: string,
@@ -588,7 +624,12 @@ async function ensureMint(
       payer: ctx.payer.publicKey,
       rent: SYSVAR_RENT_PUBKEY,
       system_program: SystemProgram.programId,
-      token_program: TOKEN_2022_PROGRAM_ID
+      token_program: TOKEN_2022_PROGRAM_ID,
+      event_authority: PublicKey.findProgramAddressSync(
+        [Buffer.from('__event_authority')],
+        PROGRAM_IDS.factory
+      )[0],
+      program: PROGRAM_IDS.factory
     };
 
     if (enablePtkn && ptknMintKeypair) {
@@ -606,7 +647,8 @@ async function ensureMint(
         decimals: mintConfig.decimals,
         enable_ptkn: enablePtkn,
         feature_flags: null,
-        fee_bps_override: null
+        fee_bps_override: null,
+        ptkn_extensions: 0
       },
       ptknMintKeypair ? [ptknMintKeypair] : []
     );
@@ -685,8 +727,9 @@ async function ensureMint(
       'initialize_pool',
       poolAccounts,
       {
-        fee_bps: new BN(5),
-        features: FEATURE_PRIVATE_TRANSFER_ENABLED | FEATURE_ALLOWANCES_ENABLED
+        shield_fee_bps: new BN(0),
+        features: FEATURE_PRIVATE_TRANSFER_ENABLED | FEATURE_ALLOWANCES_ENABLED,
+        merkle_depth: 32
       },
       [],
       computeBudgetIxs
@@ -807,6 +850,7 @@ export async function bootstrapPrivateDevnet() {
   };
 
   await ensureFactory(ctx);
+  await ensureProtocolConfig(ctx);
 
   const verifyingKeyMap = new Map<string, Awaited<ReturnType<typeof ensureVerifyingKey>>>();
   for (const [circuit, filename] of Object.entries(VERIFYING_KEY_CONFIG)) {
@@ -282,7 +282,7 @@ async function fetchPoolStateRoot(connection: Connection, poolId: string): Promi
   // roots_len (u8)
   offset += 1;
 
-  // Align to 2-byte boundary for fee_bps (u16)
+  // Align to 2-byte boundary for unshield_fee_bps (u16)
   if (offset % 2 !== 0) {
     offset += 1;
   }